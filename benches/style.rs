@@ -0,0 +1,87 @@
+//! Benchmarks parsing, selector matching and style resolution over synthetic
+//! documents with [criterion](https://github.com/bheisler/criterion.rs), which
+//! reports how each one changed since the last run.
+
+#[macro_use]
+extern crate criterion;
+extern crate magician;
+
+use criterion::Criterion;
+use magician::generator::{generate_css, generate_dom, generate_html, generate_stylesheet,
+                          GeneratorConfig};
+use magician::magicparser::{parse_html, parse_stylesheet};
+use magician::style::resolver::StyleResolver;
+#[cfg(feature = "parallel")]
+use magician::style::selectormatcher::query_selector_all_parallel;
+use magician::style::selectormatcher::{query_selector_all, MatcherConfig};
+
+fn bench_style(c: &mut Criterion) {
+    let small = GeneratorConfig {
+        elements: 1000,
+        rules: 100,
+        ..GeneratorConfig::default()
+    };
+    let large = GeneratorConfig::default();
+    let deep = GeneratorConfig {
+        max_depth: 200,
+        max_children: 2,
+        ..GeneratorConfig::default()
+    };
+
+    for (size, config) in &[("small", small), ("large", large), ("deep", deep)] {
+        let html = generate_html(config);
+        let css = generate_css(config);
+        c.bench_function(&format!("parse_html/{}", size), |b| {
+            b.iter(|| parse_html(&html).unwrap())
+        });
+        c.bench_function(&format!("parse_stylesheet/{}", size), |b| {
+            b.iter(|| parse_stylesheet(&css).unwrap())
+        });
+
+        let root = generate_dom(config);
+        let stylesheet = generate_stylesheet(config);
+        let matcher_config = MatcherConfig::default();
+        c.bench_function(&format!("query_selector_all/{}", size), |b| {
+            b.iter(|| {
+                stylesheet
+                    .rules
+                    .iter()
+                    .map(|rule| query_selector_all(&root, &rule.selector, &matcher_config).len())
+                    .sum::<usize>()
+            })
+        });
+        #[cfg(feature = "parallel")]
+        c.bench_function(&format!("query_selector_all_parallel/{}", size), |b| {
+            b.iter(|| {
+                stylesheet
+                    .rules
+                    .iter()
+                    .map(|rule| {
+                        query_selector_all_parallel(&root, &rule.selector, &matcher_config).len()
+                    })
+                    .sum::<usize>()
+            })
+        });
+
+        let resolver = StyleResolver::new(generate_stylesheet(config));
+        c.bench_function(&format!("resolve/{}", size), |b| b.iter(|| resolver.resolve(&root)));
+        #[cfg(feature = "parallel")]
+        c.bench_function(&format!("resolve_parallel/{}", size), |b| {
+            b.iter(|| resolver.resolve_parallel(&root))
+        });
+
+        let mut styles = resolver.resolve(&root);
+        let body = root.borrow().children[0].clone();
+        c.bench_function(&format!("restyle_after_class_change/{}", size), |b| {
+            b.iter(|| {
+                body.set_attribute("class", Some("c1".to_string()));
+                resolver.restyle(&root, &mut styles);
+                body.remove_attribute("class");
+                resolver.restyle(&root, &mut styles);
+            })
+        });
+    }
+}
+
+criterion_group!(benches, bench_style);
+criterion_main!(benches);
@@ -12,10 +12,10 @@ fn main() {
         std::process::exit(1);
     }
     let filename = &args[2];
-    let mut f = File::open(filename).expect(&format!("file {:?} not found", filename));
+    let mut f = File::open(filename).unwrap_or_else(|_| panic!("file {:?} not found", filename));
     let mut input = String::new();
     f.read_to_string(&mut input)
-        .expect(&format!("error reading file {:?}", filename));
+        .unwrap_or_else(|_| panic!("error reading file {:?}", filename));
     match flag.as_ref() {
         "--html" => {
             let html = parse_html(&input);
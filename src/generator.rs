@@ -0,0 +1,207 @@
+//! Builds large synthetic documents and stylesheets, for benchmarking
+//! selector matching and the cascade. The same config always builds the same
+//! document and stylesheet.
+
+use magicparser::{parse_html, parse_stylesheet, DomNodeRef, Stylesheet};
+
+const TAGS: &[&str] = &["div", "section", "ul", "li", "p", "a", "span", "h1"];
+const PROPERTIES: &[(&str, &[&str])] = &[
+    ("color", &["red", "#333", "rgb(0, 128, 255)", "inherit"]),
+    ("background-color", &["white", "transparent", "#eee"]),
+    ("margin", &["0", "4px", "1em", "2%"]),
+    ("padding", &["0", "8px", "0.5em"]),
+    ("font-size", &["12px", "1.2em", "larger", "80%"]),
+    ("font-weight", &["bold", "normal", "bolder"]),
+    ("display", &["block", "inline", "none", "inline-block"]),
+    ("width", &["auto", "50%", "100px"]),
+    ("text-align", &["left", "center"]),
+];
+
+/// What to generate. Every count is an upper bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    // Elements in the document, not counting text nodes
+    pub elements: usize,
+    pub max_depth: usize,
+    pub max_children: usize,
+    // Size of the pool of class names that elements and selectors draw from
+    pub class_count: usize,
+    pub max_classes_per_element: usize,
+    pub rules: usize,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> GeneratorConfig {
+        GeneratorConfig {
+            seed: 1,
+            elements: 5000,
+            max_depth: 24,
+            max_children: 8,
+            class_count: 200,
+            max_classes_per_element: 4,
+            rules: 500,
+        }
+    }
+}
+
+// xorshift64*, since the crate doesn't depend on a random number generator
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // In 0..n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// Returns the markup of a document with nested elements, classes, ids,
+/// attributes and text.
+pub fn generate_html(config: &GeneratorConfig) -> String {
+    let mut rng = Rng::new(config.seed);
+    let mut html = String::from("<html><body>");
+    let mut remaining = config.elements;
+    while remaining > 0 {
+        generate_element(config, &mut rng, 1, &mut remaining, &mut html);
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+fn generate_element(
+    config: &GeneratorConfig,
+    rng: &mut Rng,
+    depth: usize,
+    remaining: &mut usize,
+    html: &mut String,
+) {
+    *remaining -= 1;
+    let tag = *rng.pick(TAGS);
+    html.push('<');
+    html.push_str(tag);
+    let class_count = rng.below(config.max_classes_per_element + 1);
+    if class_count > 0 {
+        let classes = (0..class_count)
+            .map(|_| format!("c{}", rng.below(config.class_count)))
+            .collect::<Vec<_>>();
+        html.push_str(&format!(" class=\"{}\"", classes.join(" ")));
+    }
+    if rng.chance(5) {
+        html.push_str(&format!(" id=\"i{}\"", *remaining));
+    }
+    if rng.chance(10) {
+        html.push_str(" title=\"t\"");
+    }
+    if tag == "a" {
+        html.push_str(" href=\"#\"");
+    }
+    html.push('>');
+    if rng.chance(30) {
+        html.push_str("Text");
+    }
+    if depth < config.max_depth {
+        let children = rng.below(config.max_children + 1);
+        for _ in 0..children {
+            if *remaining == 0 {
+                break;
+            }
+            generate_element(config, rng, depth + 1, remaining, html);
+        }
+    }
+    html.push_str("</");
+    html.push_str(tag);
+    html.push('>');
+}
+
+/// Returns a stylesheet of rules with a mix of simple, compound and complex
+/// selectors over the classes, tags and ids generate_html() uses.
+pub fn generate_css(config: &GeneratorConfig) -> String {
+    // Not the document's generator, so that changing one doesn't change the
+    // other
+    let mut rng = Rng::new(config.seed.wrapping_add(1));
+    let mut css = String::new();
+    for _ in 0..config.rules {
+        css.push_str(&generate_selector(config, &mut rng));
+        css.push_str(" {");
+        for _ in 0..1 + rng.below(4) {
+            let (property, values) = *rng.pick(PROPERTIES);
+            css.push_str(&format!(" {}: {};", property, rng.pick(values)));
+        }
+        css.push_str(" }\n");
+    }
+    css
+}
+
+fn generate_selector(config: &GeneratorConfig, rng: &mut Rng) -> String {
+    let first = format!(".c{}", rng.below(config.class_count));
+    let second = format!(".c{}", rng.below(config.class_count));
+    let tag = *rng.pick(TAGS);
+    match rng.below(10) {
+        0 => first,
+        1 => format!("{}{}", tag, first),
+        2 => format!("{} {}", first, second),
+        3 => format!("{} > {}", first, tag),
+        4 => format!("#i{}", rng.below(config.elements)),
+        5 => format!("{}:nth-child(2n+1)", tag),
+        6 => format!("{}[title]", first),
+        7 => format!("{} + {}", first, second),
+        8 => format!("{} {} {}", tag, first, *rng.pick(TAGS)),
+        _ => format!("{}, {}:first-child", first, tag),
+    }
+}
+
+/// Parses generate_html()'s document.
+pub fn generate_dom(config: &GeneratorConfig) -> DomNodeRef {
+    parse_html(&generate_html(config)).unwrap()
+}
+
+/// Parses generate_css()'s stylesheet.
+pub fn generate_stylesheet(config: &GeneratorConfig) -> Stylesheet {
+    parse_stylesheet(&generate_css(config)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_elements(dom_node: &DomNodeRef) -> usize {
+        let children = dom_node.borrow().children.to_vec();
+        let element = dom_node.borrow().elem_type.is_element();
+        usize::from(element) + children.iter().map(count_elements).sum::<usize>()
+    }
+
+    #[test]
+    fn test_generate() {
+        let config = GeneratorConfig {
+            elements: 300,
+            rules: 50,
+            ..GeneratorConfig::default()
+        };
+        assert_eq!(generate_html(&config), generate_html(&config));
+        // Plus html and body
+        assert_eq!(count_elements(&generate_dom(&config)), 302);
+        assert_eq!(generate_stylesheet(&config).rules.len(), 50);
+
+        let other = GeneratorConfig { seed: 2, ..config };
+        assert_ne!(generate_css(&config), generate_css(&other));
+    }
+}
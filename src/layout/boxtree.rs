@@ -0,0 +1,780 @@
+use layout::replaced::{self, IntrinsicSize};
+use layout::writing::WritingMode;
+use magicparser::{DomNodeRef, ElemType, WhiteSpace};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::mem;
+use style::resolver::{ComputedStyle, StyleMap};
+use style::typed::TypedStyle;
+
+/// The box an element's `display` makes it generate. Values that aren't
+/// supported yet are treated like the closest one that is, e.g. flex, grid
+/// and table boxes are laid out as blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Display {
+    // No boxes for the element or its descendants
+    None,
+    // No box for the element, with its children's boxes in its place
+    Contents,
+    Block,
+    ListItem,
+    Inline,
+    InlineBlock,
+}
+
+impl<'a> From<&'a str> for Display {
+    fn from(value: &'a str) -> Self {
+        match value.to_ascii_lowercase().as_ref() {
+            "none" => Display::None,
+            "contents" => Display::Contents,
+            "list-item" => Display::ListItem,
+            "inline-block" | "inline-flex" | "inline-grid" | "inline-table" => {
+                Display::InlineBlock
+            }
+            "block" | "flow-root" | "flex" | "grid" | "table" => Display::Block,
+            // The parts of tables, like table-row
+            value if value.starts_with("table-") => Display::Block,
+            _ => Display::Inline,
+        }
+    }
+}
+
+/// What kind of box a LayoutBox is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoxKind {
+    // The initial containing block, the root of the tree, which holds the
+    // root element's box
+    Viewport,
+    // A block-level block container, e.g. of display: block or list-item
+    Block,
+    // An atomic inline-level block container, e.g. of display: inline-block
+    InlineBlock,
+    // A block container taken out of flow by `float`, which is neither
+    // block- nor inline-level, and which the lines after it wrap around
+    Float,
+    // A block container taken out of flow by `position: absolute` or
+    // `fixed`, which takes up no room where it is
+    Absolute,
+    Inline,
+    // The text of a text node
+    Text(String),
+    // Holds a run of inline-level boxes in a block container that also has
+    // block-level children, so that its children are all block-level
+    AnonymousBlock,
+    // Holds a run of text directly in a block container
+    AnonymousInline,
+}
+
+impl BoxKind {
+    pub fn is_block_level(&self) -> bool {
+        matches!(*self, BoxKind::Viewport | BoxKind::Block | BoxKind::AnonymousBlock)
+    }
+
+    pub fn is_inline_level(&self) -> bool {
+        matches!(
+            *self,
+            BoxKind::InlineBlock | BoxKind::Inline | BoxKind::Text(_) | BoxKind::AnonymousInline
+        )
+    }
+
+    /// Returns true for the boxes whose children are either all block-level
+    /// or all inline-level, and laid out in blocks or lines respectively.
+    /// Out-of-flow boxes can be among either.
+    pub fn is_block_container(&self) -> bool {
+        self.is_block_level() || matches!(*self, BoxKind::InlineBlock) || self.is_out_of_flow()
+    }
+
+    /// Returns true for floats and absolutely positioned boxes, which are
+    /// neither block- nor inline-level.
+    pub fn is_out_of_flow(&self) -> bool {
+        matches!(*self, BoxKind::Float | BoxKind::Absolute)
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        matches!(*self, BoxKind::AnonymousBlock | BoxKind::AnonymousInline)
+    }
+}
+
+/// How a box of a BoxTree that was built again differs from the box it
+/// stands for in the old tree; see BoxTree::diff().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxChange {
+    // There's no such box in the old tree
+    New,
+    // The old box, which it or its descendants differ from in a way that can
+    // change how they're laid out
+    Changed(BoxId),
+    // The old box, which it and its descendants are the same as, but for
+    // properties that don't affect layout, like color
+    Unchanged(BoxId),
+}
+
+impl BoxChange {
+    pub fn old_id(self) -> Option<BoxId> {
+        match self {
+            BoxChange::New => None,
+            BoxChange::Changed(id) | BoxChange::Unchanged(id) => Some(id),
+        }
+    }
+}
+
+// Properties that boxes are painted or paginated with, which layout doesn't
+// look at
+const PAINT_PROPERTIES: &[&str] = &[
+    "background-color",
+    "break-after",
+    "break-before",
+    "break-inside",
+    "color",
+    "cursor",
+    "text-decoration",
+    "visibility",
+    "z-index",
+];
+
+fn affects_layout(property: &str) -> bool {
+    !PAINT_PROPERTIES.contains(&property)
+        && !property.starts_with("--")
+        && !property.starts_with("animation-")
+        && !property.starts_with("transition-")
+}
+
+// True if styles a and b lay boxes out the same way
+fn same_layout_style(a: &ComputedStyle, b: &ComputedStyle) -> bool {
+    let layout = |&(property, _): &(&str, &str)| affects_layout(property);
+    a == b || a.iter().filter(layout).eq(b.iter().filter(layout))
+}
+
+/// Identifies a box of a BoxTree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoxId(usize);
+
+impl BoxId {
+    /// Returns where the box is in its tree's boxes, which are in tree
+    /// order, e.g. for keeping data about each box in a Vec.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// A box of a BoxTree.
+#[derive(Debug, Clone)]
+pub struct LayoutBox {
+    pub kind: BoxKind,
+    // The element or text node that generated the box, or None for the
+    // viewport and anonymous boxes. An inline element that has block-level
+    // descendants is split around them, with a box for each part.
+    pub dom_node: Option<DomNodeRef>,
+    // That of dom_node. Anonymous boxes inherit from their parent, and take
+    // the initial values of the other properties.
+    pub style: ComputedStyle,
+    pub parent: Option<BoxId>,
+    pub children: Vec<BoxId>,
+    // The intrinsic size of a replaced element's box, like an img's, which
+    // has no children, or None for other boxes
+    pub replaced: Option<IntrinsicSize>,
+}
+
+impl LayoutBox {
+    /// Returns false for boxes with `visibility: hidden` or `collapse`, which
+    /// are laid out like any other, but aren't to be painted. Their children
+    /// can still be visible.
+    pub fn is_visible(&self) -> bool {
+        self.style.get("visibility").is_none_or(|visibility| visibility == "visible")
+    }
+}
+
+/// The boxes a styled DOM tree generates, which layout positions and sizes,
+/// after their `display`.
+///
+/// Elements with `display: none` generate no boxes, nor do their
+/// descendants, whereas those with `visibility: hidden` generate boxes that
+/// take up room but aren't painted; see LayoutBox::is_visible().
+///
+/// Boxes are stored in tree order, with the viewport at the root. Like the
+/// rest of the style system, it follows shadow trees rather than their
+/// hosts' children, so a host's shadow tree generates its boxes.
+#[derive(Debug, Clone)]
+pub struct BoxTree {
+    boxes: Vec<LayoutBox>,
+    // In tree order
+    without_boxes: Vec<DomNodeRef>,
+    // The root element's
+    writing_mode: WritingMode,
+}
+
+impl BoxTree {
+    /// Builds the boxes of root's tree from their computed styles in styles.
+    /// Comments generate no boxes, nor do nodes that weren't styled, like
+    /// those resolve_lazy() deferred; see nodes_without_boxes(). The root element's box is always a
+    /// block, even if it's floated or absolutely positioned.
+    ///
+    /// root can also be a fragment, whose children are then put in the
+    /// viewport.
+    ///
+    /// Replaced elements, like img, get their intrinsic sizes from their
+    /// `width` and `height` attributes; see new_with_loader().
+    pub fn new(root: &DomNodeRef, styles: &StyleMap) -> BoxTree {
+        BoxTree::new_with_loader(root, styles, |_| None)
+    }
+
+    /// Like new(), but loader is called with the url of each replaced
+    /// element's resource that its attributes don't give the size of, and
+    /// returns its intrinsic size, or None if it can't be loaded; see
+    /// replaced::intrinsic_size().
+    pub fn new_with_loader<F>(root: &DomNodeRef, styles: &StyleMap, mut loader: F) -> BoxTree
+    where
+        F: FnMut(&str) -> Option<IntrinsicSize>,
+    {
+        let mut children = vec![];
+        let is_fragment = root.borrow().elem_type == ElemType::Fragment;
+        if is_fragment {
+            for child in &rendered_children(root) {
+                generate_boxes(child, styles, &mut loader, false, &mut children);
+            }
+        } else {
+            generate_boxes(root, styles, &mut loader, true, &mut children);
+        }
+        let style = ComputedStyle::default();
+        let viewport = BoxNode {
+            kind: BoxKind::Viewport,
+            dom_node: None,
+            children: block_container_children(children, &style),
+            style,
+            replaced: None,
+        };
+        let writing_mode = match styles.get(root) {
+            Some(style) if !is_fragment => WritingMode::from(style.typed().writing_mode.as_str()),
+            _ => WritingMode::default(),
+        };
+        let mut tree = BoxTree {
+            boxes: vec![],
+            without_boxes: vec![],
+            writing_mode,
+        };
+        tree.add(viewport, None);
+        let boxed = tree
+            .boxes
+            .iter()
+            .filter_map(|layout_box| layout_box.dom_node.as_ref())
+            .map(|dom_node| dom_node.borrow().id_num)
+            .collect::<HashSet<_>>();
+        let mut stack = if is_fragment { rendered_children(root) } else { vec![root.clone()] };
+        stack.reverse();
+        while let Some(dom_node) = stack.pop() {
+            let is_rendered = match dom_node.borrow().elem_type {
+                ElemType::Text(_) => true,
+                ref elem_type => elem_type.is_element(),
+            };
+            if !is_rendered {
+                continue;
+            }
+            if !boxed.contains(&dom_node.borrow().id_num) {
+                tree.without_boxes.push(dom_node.clone());
+            }
+            stack.extend(rendered_children(&dom_node).into_iter().rev());
+        }
+        tree
+    }
+
+    /// Returns the viewport's box.
+    pub fn root(&self) -> BoxId {
+        BoxId(0)
+    }
+
+    pub fn get(&self, id: BoxId) -> &LayoutBox {
+        &self.boxes[id.0]
+    }
+
+    /// Returns the ids of all the boxes, in tree order.
+    pub fn ids(&self) -> impl Iterator<Item = BoxId> {
+        (0..self.boxes.len()).map(BoxId)
+    }
+
+    /// Returns the boxes dom_node generated, in tree order.
+    pub fn boxes_of(&self, dom_node: &DomNodeRef) -> Vec<BoxId> {
+        self.ids()
+            .filter(|&id| self.get(id).dom_node.as_ref().is_some_and(|node| node.ptr_eq(dom_node)))
+            .collect()
+    }
+
+    /// Returns the elements and text nodes in root's tree that generated no
+    /// boxes, in tree order: those with `display: none` or `contents` and
+    /// their descendants, whitespace that was dropped, and nodes that
+    /// weren't styled.
+    pub fn nodes_without_boxes(&self) -> &[DomNodeRef] {
+        &self.without_boxes
+    }
+
+    /// Returns the root element's writing mode, which the whole tree is laid
+    /// out in, or horizontal-tb for a fragment.
+    pub fn writing_mode(&self) -> WritingMode {
+        self.writing_mode
+    }
+
+    /// Returns the typed style of box id as layout sees it in the tree's
+    /// writing mode; see WritingMode::logical_style().
+    pub fn logical_style(&self, id: BoxId) -> TypedStyle {
+        self.writing_mode.logical_style(&self.get(id).style.typed())
+    }
+
+    /// Returns, by BoxId::index(), how each box differs from old, the tree
+    /// the same DOM generated before it or its styles changed. A box stands
+    /// for the box in old that the same node generated, of the same kind,
+    /// among the children of the box its parent stands for. Anonymous boxes
+    /// stand for those of their kind in the same order, and text boxes for
+    /// those of the same text node, whatever their text.
+    pub fn diff(&self, old: &BoxTree) -> Vec<BoxChange> {
+        let mut changes = vec![BoxChange::New; self.len()];
+        self.diff_box(old, self.root(), old.root(), &mut changes);
+        changes
+    }
+
+    // Sets the changes of id, which stands for old_id in old, and of its
+    // descendants. Returns true if it's unchanged.
+    fn diff_box(&self, old: &BoxTree, id: BoxId, old_id: BoxId, changes: &mut [BoxChange]) -> bool {
+        let (layout_box, old_box) = (self.get(id), old.get(old_id));
+        let mut unchanged = layout_box.kind == old_box.kind
+            && layout_box.replaced == old_box.replaced
+            && layout_box.children.len() == old_box.children.len()
+            && same_layout_style(&layout_box.style, &old_box.style);
+        let mut matched = vec![false; old_box.children.len()];
+        for (i, &child) in layout_box.children.iter().enumerate() {
+            let old_child = old_box.children.iter().enumerate().position(|(j, &old_child)| {
+                !matched[j] && stands_for(self.get(child), old.get(old_child))
+            });
+            match old_child {
+                Some(j) => {
+                    matched[j] = true;
+                    let child_unchanged = self.diff_box(old, child, old_box.children[j], changes);
+                    unchanged &= child_unchanged && i == j;
+                }
+                None => unchanged = false,
+            }
+        }
+        changes[id.0] =
+            if unchanged { BoxChange::Unchanged(old_id) } else { BoxChange::Changed(old_id) };
+        unchanged
+    }
+
+    pub fn len(&self) -> usize {
+        self.boxes.len()
+    }
+
+    /// Always false, since a tree has at least the viewport.
+    pub fn is_empty(&self) -> bool {
+        self.boxes.is_empty()
+    }
+
+    /// Returns a readable outline of the tree, one box per line, indented by
+    /// depth, e.g. `block div` or `anonymous inline`, for comparing trees in
+    /// tests. Boxes that aren't visible are marked `(hidden)`.
+    pub fn dump(&self) -> String {
+        let mut dump = String::new();
+        let mut stack = vec![(self.root(), 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let layout_box = self.get(id);
+            let tag_name = || match layout_box.dom_node {
+                Some(ref dom_node) => dom_node.borrow().elem_type.tag_name().to_string(),
+                None => String::new(),
+            };
+            let summary = match layout_box.kind {
+                BoxKind::Viewport => "viewport".to_string(),
+                BoxKind::Block => format!("block {}", tag_name()),
+                BoxKind::InlineBlock => format!("inline-block {}", tag_name()),
+                BoxKind::Float => format!("float {}", tag_name()),
+                BoxKind::Absolute => format!("absolute {}", tag_name()),
+                BoxKind::Inline => format!("inline {}", tag_name()),
+                BoxKind::Text(ref text) => format!("text {:?}", text),
+                BoxKind::AnonymousBlock => "anonymous block".to_string(),
+                BoxKind::AnonymousInline => "anonymous inline".to_string(),
+            };
+            let hidden = if layout_box.is_visible() { "" } else { " (hidden)" };
+            writeln!(dump, "{}{}{}", "  ".repeat(depth), summary, hidden).unwrap();
+            for &child in layout_box.children.iter().rev() {
+                stack.push((child, depth + 1));
+            }
+        }
+        dump
+    }
+
+    fn add(&mut self, node: BoxNode, parent: Option<BoxId>) -> BoxId {
+        let id = BoxId(self.boxes.len());
+        self.boxes.push(LayoutBox {
+            kind: node.kind,
+            dom_node: node.dom_node,
+            style: node.style,
+            parent,
+            children: vec![],
+            replaced: node.replaced,
+        });
+        for child in node.children {
+            let child = self.add(child, Some(id));
+            self.boxes[id.0].children.push(child);
+        }
+        id
+    }
+}
+
+// True if layout_box, of a tree that was built again, stands for old_box of
+// the old tree; see BoxTree::diff()
+fn stands_for(layout_box: &LayoutBox, old_box: &LayoutBox) -> bool {
+    let same_node = match (&layout_box.dom_node, &old_box.dom_node) {
+        (Some(dom_node), Some(old_node)) => dom_node.ptr_eq(old_node),
+        (None, None) => true,
+        _ => false,
+    };
+    same_node && mem::discriminant(&layout_box.kind) == mem::discriminant(&old_box.kind)
+}
+
+// A box while the tree is being built, before its siblings are known and
+// it's put in the tree
+struct BoxNode {
+    kind: BoxKind,
+    dom_node: Option<DomNodeRef>,
+    style: ComputedStyle,
+    children: Vec<BoxNode>,
+    replaced: Option<IntrinsicSize>,
+}
+
+impl BoxNode {
+    fn new_anonymous(kind: BoxKind, style: ComputedStyle, children: Vec<BoxNode>) -> BoxNode {
+        BoxNode {
+            kind,
+            dom_node: None,
+            style,
+            children,
+            replaced: None,
+        }
+    }
+
+    // True for text that white-space processing would remove, were it on a
+    // line of its own
+    fn is_collapsible_whitespace(&self) -> bool {
+        let text = match self.kind {
+            BoxKind::Text(ref text) => text,
+            _ => return false,
+        };
+        let mode = WhiteSpace::from(self.style.get("white-space").unwrap_or("normal"));
+        text.chars().all(|ch| ch.is_ascii_whitespace())
+            && (mode == WhiteSpace::Collapse
+                || (mode == WhiteSpace::PreserveBreaks && !text.contains('\n')))
+    }
+}
+
+// The nodes whose boxes are a node's children's: those of its shadow tree
+// if it's a host
+fn rendered_children(dom_node: &DomNodeRef) -> Vec<DomNodeRef> {
+    match dom_node.shadow_root() {
+        Some(shadow_root) => shadow_root.borrow().children.to_vec(),
+        None => dom_node.borrow().children.to_vec(),
+    }
+}
+
+// Adds the boxes dom_node generates to boxes. Those of an inline element
+// with block-level descendants are the parts of its box around them, with
+// the block-level boxes in between. A replaced element's box has no
+// children, and is atomic if it's inline.
+fn generate_boxes<F>(
+    dom_node: &DomNodeRef,
+    styles: &StyleMap,
+    loader: &mut F,
+    is_root: bool,
+    boxes: &mut Vec<BoxNode>,
+) where
+    F: FnMut(&str) -> Option<IntrinsicSize>,
+{
+    let style = match styles.get(dom_node) {
+        Some(style) => style,
+        None => return,
+    };
+    match dom_node.borrow().elem_type {
+        ElemType::Text(ref text) => {
+            boxes.push(BoxNode {
+                kind: BoxKind::Text(text.clone()),
+                dom_node: Some(dom_node.clone()),
+                style: style.clone(),
+                children: vec![],
+                replaced: None,
+            });
+            return;
+        }
+        ref elem_type if !elem_type.is_element() => return,
+        _ => {}
+    }
+    let mut children = vec![];
+    let mut display = Display::from(style.get("display").unwrap_or("inline"));
+    if is_root && (display == Display::Inline || display == Display::InlineBlock) {
+        display = Display::Block;
+    }
+    let is_absolute = !is_root && matches!(style.get("position"), Some("absolute") | Some("fixed"));
+    let is_floated = !is_root && style.get("float").is_some_and(|float| float != "none");
+    let replaced = match display {
+        Display::None => None,
+        _ if replaced::is_replaced(dom_node) => Some(replaced::intrinsic_size(dom_node, loader)),
+        _ => {
+            for child in &rendered_children(dom_node) {
+                generate_boxes(child, styles, loader, false, &mut children);
+            }
+            None
+        }
+    };
+    let element_box = |kind: BoxKind, children: Vec<BoxNode>| BoxNode {
+        kind,
+        dom_node: Some(dom_node.clone()),
+        style: style.clone(),
+        children,
+        replaced,
+    };
+    match display {
+        Display::None => {}
+        // A replaced element has no children to put in its place, so it's
+        // like none
+        Display::Contents => boxes.extend(children),
+        // Whatever their display, out-of-flow boxes are block containers, and
+        // absolute positioning wins over float
+        _ if is_absolute => {
+            boxes.push(element_box(BoxKind::Absolute, block_container_children(children, style)));
+        }
+        _ if is_floated => {
+            boxes.push(element_box(BoxKind::Float, block_container_children(children, style)));
+        }
+        Display::Block | Display::ListItem => {
+            boxes.push(element_box(BoxKind::Block, block_container_children(children, style)));
+        }
+        Display::InlineBlock => {
+            let children = block_container_children(children, style);
+            boxes.push(element_box(BoxKind::InlineBlock, children));
+        }
+        Display::Inline if replaced.is_some() => {
+            boxes.push(element_box(BoxKind::InlineBlock, vec![]));
+        }
+        Display::Inline => {
+            if !children.iter().any(|child| child.kind.is_block_level()) {
+                boxes.push(element_box(BoxKind::Inline, children));
+                return;
+            }
+            let mut part = vec![];
+            for child in children {
+                if !child.kind.is_block_level() {
+                    part.push(child);
+                    continue;
+                }
+                if !part.is_empty() {
+                    boxes.push(element_box(BoxKind::Inline, mem::take(&mut part)));
+                }
+                boxes.push(child);
+            }
+            if !part.is_empty() {
+                boxes.push(element_box(BoxKind::Inline, part));
+            }
+        }
+    }
+}
+
+// Makes the children of a block container, whose style is style, either all
+// block-level or all inline-level, by wrapping each run of inline-level
+// boxes between block-level ones in an anonymous block. Text directly in a
+// block container is wrapped in an anonymous inline. Out-of-flow boxes go in
+// the run they're in, and otherwise stay among the blocks.
+fn block_container_children(children: Vec<BoxNode>, style: &ComputedStyle) -> Vec<BoxNode> {
+    if !children.iter().any(|child| child.kind.is_block_level()) {
+        return wrap_text(children, style);
+    }
+    let mut wrapped = vec![];
+    let mut run = vec![];
+    for child in children {
+        if child.kind.is_inline_level() || (child.kind.is_out_of_flow() && !run.is_empty()) {
+            run.push(child);
+            continue;
+        }
+        wrap_inline_run(mem::take(&mut run), style, &mut wrapped);
+        wrapped.push(child);
+    }
+    wrap_inline_run(run, style, &mut wrapped);
+    wrapped
+}
+
+// Runs of whitespace, like that between blocks, would only make empty lines,
+// so they're left out
+fn wrap_inline_run(run: Vec<BoxNode>, style: &ComputedStyle, boxes: &mut Vec<BoxNode>) {
+    if run.iter().all(BoxNode::is_collapsible_whitespace) {
+        return;
+    }
+    let mut anonymous_style = ComputedStyle::new_inheriting(style);
+    anonymous_style.set("display", "block");
+    let children = wrap_text(run, &anonymous_style);
+    boxes.push(BoxNode::new_anonymous(BoxKind::AnonymousBlock, anonymous_style, children));
+}
+
+fn wrap_text(children: Vec<BoxNode>, style: &ComputedStyle) -> Vec<BoxNode> {
+    let mut wrapped: Vec<BoxNode> = vec![];
+    let mut in_text = false;
+    for child in children {
+        let is_text = matches!(child.kind, BoxKind::Text(_));
+        if !is_text {
+            wrapped.push(child);
+        } else if in_text {
+            wrapped.last_mut().unwrap().children.push(child);
+        } else {
+            let anonymous_style = ComputedStyle::new_inheriting(style);
+            let kind = BoxKind::AnonymousInline;
+            wrapped.push(BoxNode::new_anonymous(kind, anonymous_style, vec![child]));
+        }
+        in_text = is_text;
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, parse_stylesheet};
+    use style::resolver::StyleResolver;
+
+    fn box_tree(root: &DomNodeRef, css: &str) -> BoxTree {
+        let styles = StyleResolver::new(parse_stylesheet(css).unwrap()).resolve(root);
+        BoxTree::new(root, &styles)
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Display::from("NONE"), Display::None);
+        assert_eq!(Display::from("inline-flex"), Display::InlineBlock);
+        assert_eq!(Display::from("table-row"), Display::Block);
+        assert_eq!(Display::from("list-item"), Display::ListItem);
+        assert_eq!(Display::from("ruby"), Display::Inline);
+    }
+
+    #[test]
+    fn test_box_tree() {
+        let root = parse_html(
+            "<div>One<p>Two</p><span>Three<p>Four</p>Five</span><!-- c --><em hidden>Six</em>\
+             <i class=\"c\"><b>Seven</b></i></div>",
+        ).unwrap();
+        let tree = box_tree(&root, "div { color: red } .c { display: contents }");
+        assert_eq!(
+            tree.dump(),
+            "viewport\n\
+             \x20 block div\n\
+             \x20   anonymous block\n\
+             \x20     anonymous inline\n\
+             \x20       text \"One\"\n\
+             \x20   block p\n\
+             \x20     anonymous inline\n\
+             \x20       text \"Two\"\n\
+             \x20   anonymous block\n\
+             \x20     inline span\n\
+             \x20       text \"Three\"\n\
+             \x20   block p\n\
+             \x20     anonymous inline\n\
+             \x20       text \"Four\"\n\
+             \x20   anonymous block\n\
+             \x20     inline span\n\
+             \x20       text \"Five\"\n\
+             \x20     inline b\n\
+             \x20       text \"Seven\"\n"
+        );
+        // The span is split around the p
+        let span = root.borrow().children[2].clone();
+        let parts = tree.boxes_of(&span);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(tree.get(parts[0]).kind, BoxKind::Inline);
+        let anonymous = tree.get(tree.get(parts[0]).parent.unwrap());
+        assert!(anonymous.kind.is_anonymous() && anonymous.dom_node.is_none());
+        assert_eq!(anonymous.style.get("color"), Some("rgb(255, 0, 0)"));
+        assert_eq!(anonymous.style.get("display"), Some("block"));
+        let div = tree.get(tree.get(tree.root()).children[0]);
+        assert!(div.dom_node.as_ref().unwrap().ptr_eq(&root));
+        assert!(tree.boxes_of(&root.borrow().children[4]).is_empty());
+    }
+
+    #[test]
+    fn test_box_tree_root() {
+        // The root is blockified, and whitespace between blocks is dropped
+        let root = dom!(span { img div " " });
+        let div = root.borrow().children[1].clone();
+        div.attach_shadow().unwrap().append_child(dom!(p "Shadow"));
+        assert_eq!(
+            box_tree(&root, "").dump(),
+            "viewport\n\
+             \x20 block span\n\
+             \x20   anonymous block\n\
+             \x20     inline-block img\n\
+             \x20   block div\n\
+             \x20     block p\n\
+             \x20     anonymous block\n\
+             \x20       anonymous inline\n\
+             \x20         text \"Shadow\"\n"
+        );
+
+        let fragment = dom!(p "Text" b);
+        let tree = box_tree(&fragment, "");
+        assert_eq!(
+            tree.dump(),
+            "viewport\n\
+             \x20 block p\n\
+             \x20 anonymous block\n\
+             \x20   anonymous inline\n\
+             \x20     text \"Text\"\n\
+             \x20   inline b\n"
+        );
+        assert_eq!(tree.len(), 6);
+    }
+
+    #[test]
+    fn test_box_tree_floats() {
+        // A float goes among the blocks, or in the run of inline-level boxes
+        // it's in, whatever its display. So do absolutely positioned boxes.
+        let root = dom!(div {
+            p { "One" }
+            span[style = "float: left"] { "Two" }
+            "Three"
+            em[style = "float: right; display: inline-block"]
+            b { i[style = "float: inline-start; position: absolute"] }
+        });
+        assert_eq!(
+            box_tree(&root, "").dump(),
+            "viewport\n\
+             \x20 block div\n\
+             \x20   block p\n\
+             \x20     anonymous inline\n\
+             \x20       text \"One\"\n\
+             \x20   float span\n\
+             \x20     anonymous inline\n\
+             \x20       text \"Two\"\n\
+             \x20   anonymous block\n\
+             \x20     anonymous inline\n\
+             \x20       text \"Three\"\n\
+             \x20     float em\n\
+             \x20     inline b\n\
+             \x20       absolute i\n"
+        );
+    }
+
+    #[test]
+    fn test_box_tree_hidden() {
+        let root = dom!(div {
+            p[style = "display: none"] { "One" b { "Two" } }
+            span[style = "visibility: hidden"] { "Three" em[style = "visibility: visible"] }
+            i[style = "display: contents"] { "Four" }
+        });
+        let tree = box_tree(&root, "");
+        assert_eq!(
+            tree.dump(),
+            "viewport\n\
+             \x20 block div\n\
+             \x20   inline span (hidden)\n\
+             \x20     text \"Three\" (hidden)\n\
+             \x20     inline em\n\
+             \x20   anonymous inline\n\
+             \x20     text \"Four\"\n"
+        );
+        let p = root.borrow().children[0].clone();
+        let without_boxes = tree.nodes_without_boxes();
+        assert_eq!(without_boxes.len(), 5);
+        assert!(without_boxes[0].ptr_eq(&p));
+        assert!(without_boxes[3].ptr_eq(&p.borrow().children[1].borrow().children[0]));
+        assert!(without_boxes[4].ptr_eq(&root.borrow().children[2]));
+    }
+}
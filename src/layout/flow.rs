@@ -0,0 +1,1461 @@
+use layout::boxtree::{BoxChange, BoxId, BoxKind, BoxTree};
+use layout::geometry::{Rect, Sides};
+use layout::hittest;
+use layout::inline::{self, LineBox, LineContext};
+use layout::metrics::{EstimatedMetrics, TextMetrics};
+use layout::paged::{self, Page};
+use layout::replaced::IntrinsicSize;
+use layout::scroll::{self, is_scroll_container, ScrollArea};
+use layout::writing::WritingMode;
+use magicparser::DomNodeRef;
+use std::collections::HashSet;
+use std::mem;
+use std::rc::Rc;
+use style::length::ComputedLength;
+use style::media::Viewport;
+use style::resolver::ComputedStyle;
+use style::typed::TypedStyle;
+
+/// Where a box was laid out, in px from the viewport's top left corner.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BoxGeometry {
+    // Of an inline box or text, the union of its fragments on the lines
+    // it's on, with no padding or margin here
+    pub content: Rect,
+    pub padding: Sides,
+    pub margin: Sides,
+    // Of a block container with inline-level content
+    pub lines: Vec<LineBox>,
+}
+
+impl BoxGeometry {
+    pub fn padding_box(&self) -> Rect {
+        self.content.outset(&self.padding)
+    }
+
+    pub fn margin_box(&self) -> Rect {
+        self.padding_box().outset(&self.margin)
+    }
+
+    fn translate(&mut self, dx: f32, dy: f32) {
+        self.content = self.content.translate(dx, dy);
+        for line in &mut self.lines {
+            line.rect = line.rect.translate(dx, dy);
+            line.baseline += dy;
+            for fragment in &mut line.fragments {
+                fragment.rect = fragment.rect.translate(dx, dy);
+            }
+        }
+    }
+
+    // Maps geometry laid out in writing_mode's coordinates to the viewport's,
+    // which is width wide
+    fn map_to_physical(&mut self, writing_mode: WritingMode, width: f32) {
+        self.content = writing_mode.physical_rect(self.content, width);
+        self.padding = writing_mode.physical_sides(self.padding);
+        self.margin = writing_mode.physical_sides(self.margin);
+        for line in &mut self.lines {
+            line.rect = writing_mode.physical_rect(line.rect, width);
+            line.baseline = writing_mode.physical_baseline(line.baseline, width);
+            for fragment in &mut line.fragments {
+                fragment.rect = writing_mode.physical_rect(fragment.rect, width);
+            }
+        }
+    }
+}
+
+/// A BoxTree laid out in a viewport: block-level boxes stacked in normal
+/// flow, each as wide as its containing block unless it has a `width`, and
+/// inline-level content in lines, with inline-blocks as wide as their
+/// contents. Adjoining margins don't collapse.
+///
+/// Percentages are of the containing block's width, or for heights and
+/// vertical insets, of its height, unless that depends on its contents, in
+/// which case they're auto. Viewport units were already resolved by the
+/// style system, against the viewport the styles were computed for; see
+/// used_value() for the values that layout resolves.
+///
+/// Floats are as wide as their contents too, and are placed as high as they
+/// can be on their side of their containing block, below the floats before
+/// them and beside the content before them on the line they're in. Lines
+/// are shortened to go around them, and boxes with `clear` are moved below
+/// them. Floats stay inside the inline-block or float they're in, which are
+/// tall enough to hold them, but other blocks' floats can overflow them.
+///
+/// Relatively positioned boxes are moved by their insets after they're laid
+/// out, without moving anything else. Absolutely positioned boxes are laid
+/// out once their containing block is, in the padding box of the nearest
+/// positioned ancestor, or else in the viewport, as are fixed ones. They're
+/// stretched between their insets if they have both and no size, and are
+/// otherwise as big as their contents, where they would have been in flow
+/// if they have neither. `sticky` is treated as `static`.
+///
+/// The viewport and blocks with `overflow` hidden, scroll or auto are scroll
+/// containers, which hold their floats like inline-blocks do, and have a
+/// ScrollArea that can be scrolled with scroll_to(). Geometry is where boxes
+/// were laid out, before scrolling, so a box is drawn moved up and left by
+/// the offsets of the scroll containers it's in.
+///
+/// Sizes are kept between `min-width` and `max-width`, and `min-height` and
+/// `max-height`. Auto margins center blocks that are narrower than their
+/// containing block, and absolutely positioned boxes between their insets,
+/// and are otherwise 0. A block that's too wide for its margins gets the
+/// rest of its containing block as its end margin. Inline-blocks, floats
+/// and absolutely positioned boxes without a `width` shrink to fit: as wide
+/// as their contents without breaking lines, if they fit, else as wide as
+/// there's room for, but not narrower than their widest word.
+///
+/// Replaced boxes, like images, are sized from their intrinsic size; see
+/// BoxTree::new_with_loader(). Inline ones are laid out like inline-blocks,
+/// with the bottom of their margin box on the baseline, and absolutely
+/// positioned ones aren't stretched between their insets.
+///
+/// Lines go the way of their block container's `direction`, and blocks are
+/// stacked in the root element's `writing-mode`, which the whole tree is laid
+/// out in: a box with a writing mode of its own is laid out in the root's.
+/// In vertical-rl and vertical-lr, lines go down, and blocks are stacked from
+/// the right or left, with floats to the `left` at the top, while sizes,
+/// margins, padding and insets stay on the physical sides they're for; see
+/// WritingMode. Geometry is always physical.
+///
+/// Laying out again, after the viewport or the tree changed, only lays out
+/// the boxes that changed, and those whose containing block or the floats
+/// beside them changed: block-level boxes that are laid out the same way as
+/// before are moved to where they go, with their contents, as they were.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    tree: BoxTree,
+    metrics: Rc<dyn TextMetrics>,
+    viewport: Viewport,
+    // By BoxId::index()
+    geometry: Vec<BoxGeometry>,
+    // By BoxId::index(), None for boxes that aren't scroll containers
+    scroll_areas: Vec<Option<ScrollArea>>,
+    // See hittest::paint_order()
+    paint_order: Vec<(BoxId, Rect)>,
+    // By BoxId::index(), of the block-level boxes that were laid out, in
+    // layout's coordinates
+    cache: Vec<Option<CachedLayout>>,
+    // By BoxId::index(), true for the boxes the last layout laid out
+    laid_out: Vec<bool>,
+}
+
+impl Layout {
+    /// Lays out tree with text measured by EstimatedMetrics.
+    pub fn new(tree: BoxTree, viewport: Viewport) -> Layout {
+        Layout::new_with_metrics(tree, viewport, Rc::new(EstimatedMetrics))
+    }
+
+    pub fn new_with_metrics(
+        tree: BoxTree,
+        viewport: Viewport,
+        metrics: Rc<dyn TextMetrics>,
+    ) -> Layout {
+        let mut layout = Layout {
+            cache: vec![None; tree.len()],
+            tree,
+            metrics,
+            viewport,
+            geometry: vec![],
+            scroll_areas: vec![],
+            paint_order: vec![],
+            laid_out: vec![],
+        };
+        layout.relayout(viewport);
+        layout
+    }
+
+    /// Lays the tree out again in viewport, e.g. when the window is resized,
+    /// keeping the scroll containers scrolled as near to where they were as
+    /// they can be. The boxes' styles stay the same, so if the viewport
+    /// changes which @media rules apply, or what viewport units are, restyle
+    /// with StyleResolver::set_viewport() and update() with a new BoxTree.
+    pub fn relayout(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+        let writing_mode = self.tree.writing_mode();
+        let (geometry, cache, laid_out) = {
+            let tree = &self.tree;
+            let mut flow = FlowLayout {
+                tree,
+                metrics: &*self.metrics,
+                geometry: vec![BoxGeometry::default(); tree.len()],
+                floats: vec![],
+                float_container: Rect::default(),
+                cache: mem::take(&mut self.cache),
+                laid_out: vec![false; tree.len()],
+            };
+            let root = tree.root();
+            let viewport = match writing_mode {
+                WritingMode::HorizontalTb => Rect::new(0.0, 0.0, viewport.width, viewport.height),
+                _ => Rect::new(0.0, 0.0, viewport.height, viewport.width),
+            };
+            let (lines, _) = flow.layout_contents(root, viewport, Some(viewport.height));
+            flow.geometry[root.index()] = BoxGeometry {
+                content: viewport,
+                lines,
+                ..BoxGeometry::default()
+            };
+            flow.layout_absolutes(root, viewport);
+            flow.layout_fixed(root, viewport);
+            flow.laid_out[root.index()] = true;
+            (flow.geometry, flow.cache, flow.laid_out)
+        };
+        self.geometry = geometry;
+        self.cache = cache;
+        self.laid_out = laid_out;
+        if writing_mode.is_vertical() {
+            for geometry in &mut self.geometry {
+                geometry.map_to_physical(writing_mode, viewport.width);
+            }
+        }
+        let scroll_areas = scroll::scroll_areas(&self.tree, &self.geometry);
+        let old_scroll_areas = mem::replace(&mut self.scroll_areas, scroll_areas);
+        for (scroll_area, old) in self.scroll_areas.iter_mut().zip(old_scroll_areas) {
+            if let (Some(scroll_area), Some(old)) = (scroll_area.as_mut(), old) {
+                scroll_area.scroll_to(old.offset.0, old.offset.1);
+            }
+        }
+        self.paint_order = hittest::paint_order(&self.tree, &self.geometry);
+    }
+
+    /// Replaces the tree with tree, which the same DOM generated after it or
+    /// its styles changed, e.g. once StyleResolver::restyle() restyled it,
+    /// and lays it out again in the same viewport. Only the boxes that
+    /// changed, and those they affect, are laid out again; see
+    /// BoxTree::diff(). Scroll containers that are still there stay
+    /// scrolled.
+    pub fn update(&mut self, tree: BoxTree) {
+        let changes = tree.diff(&self.tree);
+        let mut new_ids = vec![None; self.tree.len()];
+        for (id, change) in tree.ids().zip(&changes) {
+            if let Some(old_id) = change.old_id() {
+                new_ids[old_id.index()] = Some(id);
+            }
+        }
+        let same_writing_mode = tree.writing_mode() == self.tree.writing_mode();
+        let mut old_cache = mem::take(&mut self.cache);
+        self.cache = changes
+            .iter()
+            .map(|change| match *change {
+                BoxChange::Unchanged(old_id) if same_writing_mode => {
+                    old_cache[old_id.index()].take()?.remap(&new_ids)
+                }
+                _ => None,
+            })
+            .collect();
+        let old_scroll_areas = mem::take(&mut self.scroll_areas);
+        self.scroll_areas =
+            changes.iter().map(|change| old_scroll_areas[change.old_id()?.index()]).collect();
+        self.tree = tree;
+        let viewport = self.viewport;
+        self.relayout(viewport);
+    }
+
+    pub fn tree(&self) -> &BoxTree {
+        &self.tree
+    }
+
+    /// Returns false if box id kept the layout it had before the last time
+    /// the tree was laid out, moved to where it goes.
+    pub fn was_laid_out(&self, id: BoxId) -> bool {
+        self.laid_out[id.index()]
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        self.viewport
+    }
+
+    pub fn metrics(&self) -> &dyn TextMetrics {
+        &*self.metrics
+    }
+
+    pub fn geometry(&self, id: BoxId) -> &BoxGeometry {
+        &self.geometry[id.index()]
+    }
+
+    /// Returns the value of property that box id was laid out with, like
+    /// getComputedStyle() does: for the width and height of block
+    /// containers, and margins, padding and line-height, the used value in
+    /// px, with percentages, auto and normal resolved. Other properties have
+    /// their computed values.
+    pub fn used_value(&self, id: BoxId, property: &str) -> Option<String> {
+        let layout_box = self.tree.get(id);
+        let geometry = &self.geometry[id.index()];
+        let is_inline = matches!(
+            layout_box.kind,
+            BoxKind::Inline | BoxKind::Text(_) | BoxKind::AnonymousInline
+        );
+        let px = match property {
+            "width" if !is_inline => geometry.content.width,
+            "height" if !is_inline => geometry.content.height,
+            "margin-top" => geometry.margin.top,
+            "margin-right" => geometry.margin.right,
+            "margin-bottom" => geometry.margin.bottom,
+            "margin-left" => geometry.margin.left,
+            "padding-top" => geometry.padding.top,
+            "padding-right" => geometry.padding.right,
+            "padding-bottom" => geometry.padding.bottom,
+            "padding-left" => geometry.padding.left,
+            "line-height" => inline::line_height(&layout_box.style, &*self.metrics),
+            _ => return layout_box.style.get(property).map(str::to_string),
+        };
+        Some(format!("{}px", px))
+    }
+
+    /// Returns None if id isn't a scroll container.
+    pub fn scroll_area(&self, id: BoxId) -> Option<&ScrollArea> {
+        self.scroll_areas[id.index()].as_ref()
+    }
+
+    /// Scrolls scroll container id as near to offset (x, y) as it can be,
+    /// and returns the new offset, or None if id isn't a scroll container.
+    pub fn scroll_to(&mut self, id: BoxId, x: f32, y: f32) -> Option<(f32, f32)> {
+        let scroll_area = self.scroll_areas[id.index()].as_mut()?;
+        Some(scroll_area.scroll_to(x, y))
+    }
+
+    /// Breaks the layout into pages the size of the viewport, which is the
+    /// page area in paged media; see paged::paginate().
+    pub fn pages(&self) -> Vec<Page> {
+        let root = self.tree.root();
+        let overflow = self.scroll_areas[root.index()].map(|scroll_area| scroll_area.overflow);
+        let bottom = overflow.map_or(self.viewport.height, |overflow| overflow.bottom());
+        paged::paginate(&self.tree, &self.geometry, bottom)
+    }
+
+    /// Returns the element at (x, y) in the viewport, like
+    /// elementFromPoint(): that of the topmost visible box there, as it's
+    /// drawn once scrolled, in paint order, or None if there's only the
+    /// viewport. Text is hit as the element it's in. See
+    /// hittest::paint_order().
+    pub fn hit_test(&self, x: f32, y: f32) -> Option<DomNodeRef> {
+        let paint_order = &self.paint_order;
+        let mut id = hittest::hit_test(&self.tree, &self.scroll_areas, paint_order, x, y)?;
+        loop {
+            let layout_box = self.tree.get(id);
+            if let Some(ref dom_node) = layout_box.dom_node {
+                if dom_node.borrow().elem_type.is_element() {
+                    return Some(dom_node.clone());
+                }
+            }
+            id = layout_box.parent?;
+        }
+    }
+}
+
+/// Returns true if a box with style is relatively, absolutely or fixed
+/// positioned, and so the containing block of its absolutely positioned
+/// descendants.
+pub fn is_positioned(style: &ComputedStyle) -> bool {
+    matches!(style.get("position"), Some("relative") | Some("absolute") | Some("fixed"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+}
+
+// The side that a float or clear value means in direction, or None for
+// none, or both
+fn side(value: &str, direction: &str) -> Option<Side> {
+    let rtl = direction == "rtl";
+    match value {
+        "left" => Some(Side::Left),
+        "right" => Some(Side::Right),
+        "inline-start" if rtl => Some(Side::Right),
+        "inline-start" => Some(Side::Left),
+        "inline-end" if rtl => Some(Side::Left),
+        "inline-end" => Some(Side::Right),
+        _ => None,
+    }
+}
+
+// Limits size to min and max, which are None if they're auto or none.
+// Percentages are of basis, or ignored if it's None.
+fn clamp_size(
+    size: f32,
+    min: Option<ComputedLength>,
+    max: Option<ComputedLength>,
+    basis: Option<f32>,
+) -> f32 {
+    let to_px = |length: Option<ComputedLength>| length?.resolve(basis);
+    let size = to_px(max).map_or(size, |max| size.min(max));
+    to_px(min).map_or(size, |min| size.max(min)).max(0.0)
+}
+
+// The width and height of a replaced box's content box, from its intrinsic
+// size and typed, its style, in a containing block containing_width wide and
+// containing_height tall, which are None where they're auto. A size that's
+// auto is the intrinsic one, or else the other size scaled by the aspect
+// ratio, or else 300px wide or 150px tall. With both auto, a width that's
+// limited by min-width or max-width scales the height with it.
+fn replaced_size(
+    intrinsic: &IntrinsicSize,
+    typed: &TypedStyle,
+    containing_width: Option<f32>,
+    containing_height: Option<f32>,
+) -> (f32, f32) {
+    let ratio = intrinsic.aspect_ratio;
+    let height = typed.height.and_then(|height| height.resolve(containing_height));
+    let width = match (typed.width.and_then(|width| width.resolve(containing_width)), height) {
+        (Some(width), _) => Some(width),
+        (None, Some(height)) => ratio.map(|ratio| height * ratio).or(intrinsic.width),
+        (None, None) => intrinsic.width.or_else(|| Some(intrinsic.height? * ratio?)),
+    }
+    .unwrap_or(300.0);
+    let width = clamp_size(width, typed.min_width, typed.max_width, containing_width);
+    let height = height
+        .or_else(|| ratio.map(|ratio| width / ratio))
+        .or(intrinsic.height)
+        .unwrap_or(150.0);
+    (width, clamp_size(height, typed.min_height, typed.max_height, containing_height))
+}
+
+// The used margins at the start and end of a box, from their values, which
+// are None if they're auto, where room is the sum of them both. Auto margins
+// take what's left of it, split evenly if both are, unless that's negative.
+// If neither is auto, the box is over-constrained, and the end margin is
+// what's left.
+fn resolve_margins(start: Option<f32>, end: Option<f32>, room: f32) -> (f32, f32) {
+    match (start, end) {
+        (None, None) if room < 0.0 => (0.0, room),
+        (None, None) => (room / 2.0, room / 2.0),
+        (None, Some(end)) => (room - end, end),
+        (Some(start), _) => (start, room - start),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentSize {
+    // With a line break at every opportunity
+    MinContent,
+    // With no line breaks but preserved newlines
+    MaxContent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PlacedFloat {
+    side: Side,
+    // Its margin box
+    rect: Rect,
+}
+
+impl PlacedFloat {
+    fn translate(&self, dx: f32, dy: f32) -> PlacedFloat {
+        PlacedFloat {
+            side: self.side,
+            rect: self.rect.translate(dx, dy),
+        }
+    }
+}
+
+// How a block-level box was laid out, to be reused when it's laid out the
+// same way again. Positions are relative to the top left corner of its
+// margin box.
+#[derive(Debug, Clone)]
+struct CachedLayout {
+    // Those of layout_block()
+    containing_width: f32,
+    containing_height: Option<f32>,
+    size: (Option<f32>, Option<f32>),
+    // The floats around it that reached below its top, unless it has floats
+    // of its own
+    floats: Vec<PlacedFloat>,
+    // The floats in it that it placed among those
+    placed_floats: Vec<PlacedFloat>,
+    // Of it and its descendants
+    geometry: Vec<(BoxId, BoxGeometry)>,
+    // Of its margin box
+    height: f32,
+}
+
+impl CachedLayout {
+    // Moves the layout to a tree that was built again, given the new id of
+    // each old box, or None if a box it has the geometry of is gone
+    fn remap(mut self, new_ids: &[Option<BoxId>]) -> Option<CachedLayout> {
+        for &mut (ref mut id, ref mut geometry) in &mut self.geometry {
+            *id = new_ids[id.index()]?;
+            for fragment in geometry.lines.iter_mut().flat_map(|line| &mut line.fragments) {
+                fragment.box_id = new_ids[fragment.box_id.index()]?;
+            }
+        }
+        Some(self)
+    }
+}
+
+struct FlowLayout<'a> {
+    tree: &'a BoxTree,
+    metrics: &'a dyn TextMetrics,
+    geometry: Vec<BoxGeometry>,
+    // Those placed so far in the block formatting context being laid out,
+    // which is the viewport's or an inline-block's or float's
+    floats: Vec<PlacedFloat>,
+    // The content box of the block container whose lines are being laid
+    // out, which the floats in them are placed in
+    float_container: Rect,
+    // By BoxId::index()
+    cache: Vec<Option<CachedLayout>>,
+    laid_out: Vec<bool>,
+}
+
+impl<'a> FlowLayout<'a> {
+    // Lays out a block-level box with the top left corner of its margin box
+    // at origin, in a containing block containing_width wide and
+    // containing_height tall, or None if that's auto. size is the box's used
+    // width and height, where they were already worked out. Returns the
+    // height of the margin box.
+    fn layout_block(
+        &mut self,
+        id: BoxId,
+        origin: (f32, f32),
+        containing_width: f32,
+        containing_height: Option<f32>,
+        size: (Option<f32>, Option<f32>),
+    ) -> f32 {
+        // Inline-blocks, out-of-flow boxes and scroll containers have floats
+        // of their own, which they're tall enough to hold
+        let layout_box = self.tree.get(id);
+        let has_own_floats = match layout_box.kind {
+            BoxKind::InlineBlock | BoxKind::Float | BoxKind::Absolute => true,
+            BoxKind::Block => is_scroll_container(layout_box),
+            _ => false,
+        };
+        let floats = if has_own_floats { vec![] } else { self.floats_below(origin) };
+        let cached = CachedLayout {
+            containing_width,
+            containing_height,
+            size,
+            floats,
+            placed_floats: vec![],
+            geometry: vec![],
+            height: 0.0,
+        };
+        if let Some(height) = self.reuse_layout(id, origin, &cached) {
+            return height;
+        }
+        self.laid_out[id.index()] = true;
+        let floats_before = self.floats.len();
+        let style = &layout_box.style;
+        let typed = self.tree.logical_style(id);
+        let mut margin = Sides::margin(&typed, containing_width);
+        let padding = Sides::padding(&typed, containing_width);
+        let replaced = self.replaced_size(id, Some(containing_width), containing_height);
+        let width = match size.0 {
+            Some(width) => width,
+            None => {
+                let width = match replaced {
+                    Some((width, _)) => width,
+                    None => clamp_size(
+                        typed.width.map_or(
+                            containing_width - margin.horizontal() - padding.horizontal(),
+                            |width| width.to_px(containing_width),
+                        ),
+                        typed.min_width,
+                        typed.max_width,
+                        Some(containing_width),
+                    ),
+                };
+                let room = containing_width - width - padding.horizontal();
+                let to_px = |margin: Option<ComputedLength>| {
+                    margin.map(|margin| margin.to_px(containing_width))
+                };
+                let (left, right) = (to_px(typed.margin_left), to_px(typed.margin_right));
+                if typed.direction == "rtl" {
+                    let (right, left) = resolve_margins(right, left, room);
+                    margin.left = left;
+                    margin.right = right;
+                } else {
+                    let (left, right) = resolve_margins(left, right, room);
+                    margin.left = left;
+                    margin.right = right;
+                }
+                width
+            }
+        }
+        .max(0.0);
+        // A percentage of an auto height is auto
+        let height = size.1.or(replaced.map(|(_, height)| height));
+        let height = height.or_else(|| typed.height?.resolve(containing_height));
+        let clamp_height = |height: f32| {
+            clamp_size(height, typed.min_height, typed.max_height, containing_height)
+        };
+        let height = height.map(clamp_height);
+        let mut content = Rect::new(
+            origin.0 + margin.left + padding.left,
+            origin.1 + margin.top + padding.top,
+            width,
+            0.0,
+        );
+        let outer_floats = Some(()).filter(|_| has_own_floats).map(|_| mem::take(&mut self.floats));
+        let (lines, mut auto_height) = self.layout_contents(id, content, height);
+        if let Some(outer_floats) = outer_floats {
+            let floats = mem::replace(&mut self.floats, outer_floats);
+            for float in floats {
+                auto_height = auto_height.max(float.rect.bottom() - content.y);
+            }
+        }
+        content.height = height.unwrap_or_else(|| clamp_height(auto_height));
+        self.geometry[id.index()] = BoxGeometry {
+            content,
+            padding,
+            margin,
+            lines,
+        };
+        if is_positioned(style) {
+            self.layout_absolutes(id, content.outset(&padding));
+        }
+        let height = content.height + padding.vertical() + margin.vertical();
+        let mut ids = HashSet::new();
+        self.collect_subtree(id, &mut ids);
+        let geometry = ids.into_iter().map(|id| {
+            let mut geometry = self.geometry[id.index()].clone();
+            geometry.translate(-origin.0, -origin.1);
+            (id, geometry)
+        });
+        let placed_floats = self.floats[floats_before..].iter();
+        let placed_floats = placed_floats.map(|float| float.translate(-origin.0, -origin.1));
+        self.cache[id.index()] = Some(CachedLayout {
+            placed_floats: placed_floats.collect(),
+            geometry: geometry.collect(),
+            height,
+            ..cached
+        });
+        height
+    }
+
+    // The floats placed so far that reach below origin, relative to it,
+    // which a block laid out there can go around
+    fn floats_below(&self, origin: (f32, f32)) -> Vec<PlacedFloat> {
+        self.floats
+            .iter()
+            .filter(|float| float.rect.bottom() > origin.1)
+            .map(|float| float.translate(-origin.0, -origin.1))
+            .collect()
+    }
+
+    // Puts back the layout of box id from the last time it was laid out the
+    // same way as cached says it's to be laid out now, with its margin box at
+    // origin. Returns the height of its margin box, or None if it has to be
+    // laid out again.
+    fn reuse_layout(
+        &mut self,
+        id: BoxId,
+        origin: (f32, f32),
+        cached: &CachedLayout,
+    ) -> Option<f32> {
+        let old = self.cache[id.index()].as_ref()?;
+        let same = old.containing_width == cached.containing_width
+            && old.containing_height == cached.containing_height
+            && old.size == cached.size
+            && old.floats == cached.floats;
+        if !same {
+            return None;
+        }
+        for &(id, ref geometry) in &old.geometry {
+            let mut geometry = geometry.clone();
+            geometry.translate(origin.0, origin.1);
+            self.geometry[id.index()] = geometry;
+        }
+        for float in &old.placed_floats {
+            self.floats.push(float.translate(origin.0, origin.1));
+        }
+        Some(old.height)
+    }
+
+    // Lays out the children of a block container in content, its content
+    // box, whose height is None if it's auto. Returns its lines, if its
+    // children are inline-level, and the height of its contents.
+    fn layout_contents(
+        &mut self,
+        id: BoxId,
+        content: Rect,
+        height: Option<f32>,
+    ) -> (Vec<LineBox>, f32) {
+        let tree = self.tree;
+        let children = &tree.get(id).children;
+        if children.iter().any(|&child| tree.get(child).kind.is_block_level()) {
+            let mut y = content.y;
+            for &child in children {
+                match tree.get(child).kind {
+                    BoxKind::Float => {
+                        self.layout_atomic(child, Some(content.width));
+                        self.place_float_in(child, y, content);
+                        continue;
+                    }
+                    BoxKind::Absolute => {
+                        self.set_static_position(child, content.x, y);
+                        continue;
+                    }
+                    _ => {}
+                }
+                // The top of its margin box goes below the floats it clears
+                let typed = tree.logical_style(child);
+                if let Some(clearance) = self.clearance(&typed.clear, &typed.direction) {
+                    y = y.max(clearance);
+                }
+                let size = (None, None);
+                y += self.layout_block(child, (content.x, y), content.width, height, size);
+                self.offset_relative(child, content.width, height);
+            }
+            return (vec![], y - content.y);
+        }
+        if children.is_empty() {
+            return (vec![], 0.0);
+        }
+        let lines = self.layout_inline(id, content);
+        let height = lines.last().map_or(0.0, |line| line.rect.bottom() - content.y);
+        (lines, height)
+    }
+
+    // Lays out the inline-level content of a block container in lines, and
+    // the inline boxes, text, inline-blocks and floats in them
+    fn layout_inline(&mut self, id: BoxId, content: Rect) -> Vec<LineBox> {
+        let tree = self.tree;
+        let metrics = self.metrics;
+        let mut atomics = vec![];
+        self.reset_inline_descendants(id, content, &mut atomics);
+        for &atomic in &atomics {
+            self.layout_atomic(atomic, Some(content.width));
+        }
+        self.float_container = content;
+        let mut lines = inline::layout_lines(tree, id, content, metrics, self);
+        // Inline-blocks were laid out at the origin, and are moved to their
+        // fragments
+        let mut placed = HashSet::new();
+        for fragment in lines.iter().flat_map(|line| &line.fragments) {
+            let id = fragment.box_id;
+            if tree.get(id).kind == BoxKind::InlineBlock {
+                self.translate_subtree(id, fragment.rect.x, fragment.rect.y);
+            } else if placed.insert(id) {
+                self.geometry[id.index()].content = fragment.rect;
+            } else {
+                let content = &mut self.geometry[id.index()].content;
+                *content = content.union(&fragment.rect);
+            }
+        }
+        self.position_inline_descendants(id, content, &mut lines);
+        lines
+    }
+
+    // Moves the relatively positioned inline-level boxes in id, a block
+    // container with lines, and their fragments, then lays out the
+    // absolutely positioned boxes the inline boxes among them contain
+    fn position_inline_descendants(&mut self, id: BoxId, content: Rect, lines: &mut [LineBox]) {
+        let tree = self.tree;
+        for &child in &tree.get(id).children {
+            let kind = &tree.get(child).kind;
+            if !kind.is_inline_level() {
+                continue;
+            }
+            if let Some((dx, dy)) = self.offset_relative(child, content.width, None) {
+                let mut moved = HashSet::new();
+                self.collect_subtree(child, &mut moved);
+                for fragment in lines.iter_mut().flat_map(|line| &mut line.fragments) {
+                    if moved.contains(&fragment.box_id) {
+                        fragment.rect = fragment.rect.translate(dx, dy);
+                    }
+                }
+            }
+            if *kind != BoxKind::InlineBlock {
+                self.position_inline_descendants(child, content, lines);
+                if is_positioned(&tree.get(child).style) {
+                    let containing = self.geometry[child.index()].content;
+                    self.layout_absolutes(child, containing);
+                }
+            }
+        }
+    }
+
+    fn collect_subtree(&self, id: BoxId, ids: &mut HashSet<BoxId>) {
+        ids.insert(id);
+        for &child in &self.tree.get(id).children {
+            self.collect_subtree(child, ids);
+        }
+    }
+
+    // Moves a relatively positioned box by its insets, with percentages of
+    // its containing block, whose height is None if it's auto. Returns how
+    // far it was moved.
+    fn offset_relative(
+        &mut self,
+        id: BoxId,
+        containing_width: f32,
+        containing_height: Option<f32>,
+    ) -> Option<(f32, f32)> {
+        let typed = self.tree.logical_style(id);
+        if typed.position != "relative" {
+            return None;
+        }
+        let horizontal =
+            |length: Option<ComputedLength>| length.map(|length| length.to_px(containing_width));
+        // A percentage of an auto height is auto
+        let vertical = |length: Option<ComputedLength>| length?.resolve(containing_height);
+        let (left, right) = (horizontal(typed.left), horizontal(typed.right));
+        let dx = match (left, right) {
+            (Some(_), Some(right)) if typed.direction == "rtl" => -right,
+            (Some(left), _) => left,
+            (None, Some(right)) => -right,
+            (None, None) => 0.0,
+        };
+        let dy = vertical(typed.top).or_else(|| vertical(typed.bottom).map(|bottom| -bottom));
+        let dy = dy.unwrap_or(0.0);
+        self.translate_subtree(id, dx, dy);
+        Some((dx, dy))
+    }
+
+    // Lays out the absolutely positioned boxes whose containing block is id,
+    // given its padding box
+    fn layout_absolutes(&mut self, id: BoxId, containing: Rect) {
+        let tree = self.tree;
+        for &child in &tree.get(id).children {
+            let style = &tree.get(child).style;
+            if tree.get(child).kind == BoxKind::Absolute {
+                if style.get("position") == Some("absolute") {
+                    self.layout_absolute(child, containing);
+                }
+            } else if !is_positioned(style) {
+                self.layout_absolutes(child, containing);
+            }
+        }
+    }
+
+    // Lays out the fixed positioned boxes in id in the viewport, after
+    // everything else
+    fn layout_fixed(&mut self, id: BoxId, viewport: Rect) {
+        let tree = self.tree;
+        for &child in &tree.get(id).children {
+            let layout_box = tree.get(child);
+            let is_fixed = layout_box.style.get("position") == Some("fixed");
+            if layout_box.kind == BoxKind::Absolute && is_fixed {
+                self.layout_absolute(child, viewport);
+            }
+            self.layout_fixed(child, viewport);
+        }
+    }
+
+    // Lays out an absolutely positioned box in containing, the padding box
+    // of its containing block, from its static position in its geometry
+    fn layout_absolute(&mut self, id: BoxId, containing: Rect) {
+        let typed = self.tree.logical_style(id);
+        let (width, height) = (containing.width, containing.height);
+        let left = typed.left.map(|left| left.to_px(width));
+        let right = typed.right.map(|right| right.to_px(width));
+        let top = typed.top.map(|top| top.to_px(height));
+        let bottom = typed.bottom.map(|bottom| bottom.to_px(height));
+        let mut margin = Sides::margin(&typed, width);
+        let padding = Sides::padding(&typed, width);
+        let edges = margin.horizontal() + padding.horizontal();
+        // Replaced boxes aren't stretched between their insets
+        let replaced = self.replaced_size(id, Some(width), Some(height));
+        let used_width = match (replaced, typed.width, left, right) {
+            (Some((used_width, _)), _, _, _) => used_width,
+            (None, Some(used_width), _, _) => used_width.to_px(width),
+            (None, None, Some(left), Some(right)) => width - left - right - edges,
+            (None, None, _, _) => {
+                let available = width - left.unwrap_or(0.0) - right.unwrap_or(0.0) - edges;
+                self.shrink_to_fit(id, available)
+            }
+        };
+        let used_width = clamp_size(used_width, typed.min_width, typed.max_width, Some(width));
+        let used_height = match (replaced, typed.height, top, bottom) {
+            (Some((_, used_height)), _, _, _) => Some(used_height),
+            (None, Some(used_height), _, _) => Some(used_height.to_px(height)),
+            (None, None, Some(top), Some(bottom)) => {
+                Some(height - top - bottom - margin.vertical() - padding.vertical())
+            }
+            (None, None, _, _) => None,
+        };
+        let used_height = used_height.map(|used_height| {
+            clamp_size(used_height, typed.min_height, typed.max_height, Some(height))
+        });
+        let static_position = self.geometry[id.index()].content;
+        let size = (Some(used_width), used_height);
+        self.layout_block(id, (0.0, 0.0), width, Some(height), size);
+        // Auto margins center the box between insets on both sides
+        let used_height = self.geometry[id.index()].content.height;
+        let to_px = |margin: Option<ComputedLength>| margin.map(|margin| margin.to_px(width));
+        if let (Some(left), Some(right)) = (left, right) {
+            if typed.margin_left.is_none() || typed.margin_right.is_none() {
+                let room = width - left - right - used_width - padding.horizontal();
+                let margins = (to_px(typed.margin_left), to_px(typed.margin_right));
+                if typed.direction == "rtl" {
+                    let (right, left) = resolve_margins(margins.1, margins.0, room);
+                    margin.left = left;
+                    margin.right = right;
+                } else {
+                    let (left, right) = resolve_margins(margins.0, margins.1, room);
+                    margin.left = left;
+                    margin.right = right;
+                }
+            }
+        }
+        if let (Some(top), Some(bottom)) = (top, bottom) {
+            if typed.margin_top.is_none() || typed.margin_bottom.is_none() {
+                let room = height - top - bottom - used_height - padding.vertical();
+                let margins = (to_px(typed.margin_top), to_px(typed.margin_bottom));
+                let (top, bottom) = resolve_margins(margins.0, margins.1, room);
+                margin.top = top;
+                margin.bottom = bottom;
+            }
+        }
+        self.set_margins(id, margin);
+        let margin_box = self.geometry[id.index()].margin_box();
+        // Over-constrained insets give way at the end
+        let x = match (left, right) {
+            (Some(_), Some(right)) if typed.direction == "rtl" => {
+                containing.right() - right - margin_box.width
+            }
+            (Some(left), _) => containing.x + left,
+            (None, Some(right)) => containing.right() - right - margin_box.width,
+            (None, None) => static_position.x,
+        };
+        let y = match (top, bottom) {
+            (Some(top), _) => containing.y + top,
+            (None, Some(bottom)) => containing.bottom() - bottom - margin_box.height,
+            (None, None) => static_position.y,
+        };
+        self.translate_subtree(id, x, y);
+    }
+
+    // Changes the margins of a box laid out with the top left corner of its
+    // margin box at (0, 0), keeping it there
+    fn set_margins(&mut self, id: BoxId, margin: Sides) {
+        let old = self.geometry[id.index()].margin;
+        self.translate_subtree(id, margin.left - old.left, margin.top - old.top);
+        self.geometry[id.index()].margin = margin;
+    }
+
+    // Clears the geometry of the inline boxes and text in id, a block
+    // container with content box content, collecting the inline-blocks and
+    // floats among them. They're left empty at the start of content, where
+    // those that aren't on a line, like empty inline boxes, stay, so that
+    // they move with it.
+    fn reset_inline_descendants(&mut self, id: BoxId, content: Rect, atomics: &mut Vec<BoxId>) {
+        let tree = self.tree;
+        for &child in &tree.get(id).children {
+            match tree.get(child).kind {
+                BoxKind::InlineBlock | BoxKind::Float => atomics.push(child),
+                // Laid out with their containing block
+                BoxKind::Absolute => {}
+                _ => {
+                    self.geometry[child.index()] = BoxGeometry {
+                        content: Rect::new(content.x, content.y, 0.0, 0.0),
+                        ..BoxGeometry::default()
+                    };
+                    self.laid_out[child.index()] = true;
+                    self.reset_inline_descendants(child, content, atomics);
+                }
+            }
+        }
+    }
+
+    // Lays out an inline-block or a float at the origin, in a containing block
+    // containing_width wide, or None when working out the max-content width
+    // of the box containing it. A percentage width of None is auto.
+    fn layout_atomic(&mut self, id: BoxId, containing_width: Option<f32>) {
+        let typed = self.tree.logical_style(id);
+        let basis = containing_width.unwrap_or(0.0);
+        if let Some((width, height)) = self.replaced_size(id, containing_width, None) {
+            self.layout_block(id, (0.0, 0.0), basis, None, (Some(width), Some(height)));
+            return;
+        }
+        let width = match typed.width.and_then(|width| width.resolve(containing_width)) {
+            Some(width) => width,
+            None => {
+                let edges = Sides::margin(&typed, basis).horizontal()
+                    + Sides::padding(&typed, basis).horizontal();
+                let available = containing_width.map_or(f32::INFINITY, |width| width - edges);
+                self.shrink_to_fit(id, available)
+            }
+        };
+        let width = clamp_size(width, typed.min_width, typed.max_width, containing_width);
+        self.layout_block(id, (0.0, 0.0), basis, None, (Some(width), None));
+    }
+
+    // Moves a float, laid out at the origin, to its place in container, the
+    // content box of its containing block, no higher than y
+    fn place_float_in(&mut self, id: BoxId, y: f32, container: Rect) {
+        let typed = self.tree.logical_style(id);
+        let side = side(&typed.float, &typed.direction).unwrap_or(Side::Left);
+        let (width, height) = self.size(id);
+        // Nor higher than the floats before it, or those it clears
+        let mut y = self.floats.iter().fold(y, |y, float| y.max(float.rect.y));
+        if let Some(clearance) = self.clearance(&typed.clear, &typed.direction) {
+            y = y.max(clearance);
+        }
+        let (mut left, mut right) = self.space(container, y, height);
+        while right - left < width && (left > container.x || right < container.right()) {
+            match self.next_float_end(y) {
+                Some(float_end) => y = float_end,
+                None => break,
+            }
+            let space = self.space(container, y, height);
+            left = space.0;
+            right = space.1;
+        }
+        let x = match side {
+            Side::Left => left,
+            Side::Right => right - width,
+        };
+        self.translate_subtree(id, x, y);
+        self.floats.push(PlacedFloat {
+            side,
+            rect: Rect::new(x, y, width, height),
+        });
+        self.offset_relative(id, container.width, None);
+    }
+
+    // The left and right edges of the room that floats leave in container
+    // at y, height tall
+    fn space(&self, container: Rect, y: f32, height: f32) -> (f32, f32) {
+        let mut left = container.x;
+        let mut right = container.right();
+        for float in &self.floats {
+            let rect = float.rect;
+            if rect.bottom() <= y || (rect.y >= y + height && rect.y > y) {
+                continue;
+            }
+            match float.side {
+                Side::Left => left = left.max(rect.right()),
+                Side::Right => right = right.min(rect.x),
+            }
+        }
+        (left, right)
+    }
+
+    // The y below the floats that a box with clear goes, or None if it
+    // doesn't clear any
+    fn clearance(&self, clear: &str, direction: &str) -> Option<f32> {
+        let side = side(clear, direction);
+        self.floats
+            .iter()
+            .filter(|float| clear == "both" || Some(float.side) == side)
+            .map(|float| float.rect.bottom())
+            .fold(None, |max, bottom| Some(max.map_or(bottom, |max: f32| max.max(bottom))))
+    }
+
+    fn translate_subtree(&mut self, id: BoxId, dx: f32, dy: f32) {
+        self.geometry[id.index()].translate(dx, dy);
+        for &child in &self.tree.get(id).children {
+            self.translate_subtree(child, dx, dy);
+        }
+    }
+
+    // The size of a replaced box's content box, or None for other boxes;
+    // see replaced_size()
+    fn replaced_size(
+        &self,
+        id: BoxId,
+        containing_width: Option<f32>,
+        containing_height: Option<f32>,
+    ) -> Option<(f32, f32)> {
+        let mut intrinsic = self.tree.get(id).replaced?;
+        if self.tree.writing_mode().is_vertical() {
+            intrinsic = IntrinsicSize {
+                width: intrinsic.height,
+                height: intrinsic.width,
+                aspect_ratio: intrinsic.aspect_ratio.map(|ratio| 1.0 / ratio),
+            };
+        }
+        let typed = self.tree.logical_style(id);
+        Some(replaced_size(&intrinsic, &typed, containing_width, containing_height))
+    }
+
+    // The width of a block container's content box that's as wide as its
+    // contents, but no wider than available unless they can't be that narrow
+    fn shrink_to_fit(&mut self, id: BoxId, available: f32) -> f32 {
+        let max_content = self.content_width(id, ContentSize::MaxContent);
+        if max_content <= available {
+            return max_content;
+        }
+        self.content_width(id, ContentSize::MinContent).max(available)
+    }
+
+    // The width of a block container's content box that fits its contents
+    // at size, or of a replaced box's
+    fn content_width(&mut self, id: BoxId, size: ContentSize) -> f32 {
+        if let Some((width, _)) = self.replaced_size(id, None, None) {
+            return width;
+        }
+        let tree = self.tree;
+        let children = &tree.get(id).children;
+        if children.iter().any(|&child| tree.get(child).kind.is_block_level()) {
+            let mut max = 0.0f32;
+            for &child in children {
+                if tree.get(child).kind == BoxKind::Absolute {
+                    continue;
+                }
+                let typed = tree.logical_style(child);
+                let width = match typed.width.and_then(|width| width.resolve(None)) {
+                    Some(width) => width,
+                    None => self.content_width(child, size),
+                };
+                let width = clamp_size(width, typed.min_width, typed.max_width, None);
+                let edges = Sides::margin(&typed, 0.0).horizontal()
+                    + Sides::padding(&typed, 0.0).horizontal();
+                max = max.max(width + edges);
+            }
+            return max;
+        }
+        let mut atomics = vec![];
+        self.reset_inline_descendants(id, Rect::default(), &mut atomics);
+        // As narrow as they can be, or as wide as they'd like
+        let containing_width = match size {
+            ContentSize::MinContent => Some(0.0),
+            ContentSize::MaxContent => None,
+        };
+        for &atomic in &atomics {
+            self.layout_atomic(atomic, containing_width);
+        }
+        let geometry = &self.geometry;
+        let atomic_size = |atomic: BoxId| {
+            let margin_box = geometry[atomic.index()].margin_box();
+            (margin_box.width, margin_box.height)
+        };
+        match size {
+            ContentSize::MinContent => {
+                inline::min_content_width(tree, id, self.metrics, atomic_size)
+            }
+            ContentSize::MaxContent => {
+                inline::max_content_width(tree, id, self.metrics, atomic_size)
+            }
+        }
+    }
+}
+
+impl<'a> LineContext for FlowLayout<'a> {
+    fn size(&self, id: BoxId) -> (f32, f32) {
+        let margin_box = self.geometry[id.index()].margin_box();
+        (margin_box.width, margin_box.height)
+    }
+
+    fn line_space(&self, y: f32, height: f32) -> (f32, f32) {
+        self.space(self.float_container, y, height)
+    }
+
+    fn next_float_end(&self, y: f32) -> Option<f32> {
+        self.floats
+            .iter()
+            .map(|float| float.rect.bottom())
+            .filter(|&bottom| bottom > y)
+            .fold(None, |min, bottom| Some(min.map_or(bottom, |min: f32| min.min(bottom))))
+    }
+
+    fn place_float(&mut self, id: BoxId, y: f32) {
+        let container = self.float_container;
+        self.place_float_in(id, y, container);
+    }
+
+    fn set_static_position(&mut self, id: BoxId, x: f32, y: f32) {
+        self.geometry[id.index()] = BoxGeometry {
+            content: Rect::new(x, y, 0.0, 0.0),
+            ..BoxGeometry::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_stylesheet, DomNodeRef};
+    use style::resolver::StyleResolver;
+
+    fn lay_out(root: &DomNodeRef, css: &str) -> Layout {
+        let styles = StyleResolver::new(parse_stylesheet(css).unwrap()).resolve(root);
+        Layout::new(BoxTree::new(root, &styles), Viewport::new(800.0, 600.0))
+    }
+
+    #[test]
+    fn test_layout_blocks() {
+        let root = dom!(body {
+            p { "One" }
+            div[style = "width: 50%; height: 30px; padding: 5px"]
+            section[style = "display: inline-block; margin-left: 10px"] {
+                div[style = "width: 40px"]
+                div[style = "padding: 0 10px"] { "Three four" }
+            }
+        });
+        let layout = lay_out(&root, "body { font-size: 10px; line-height: 20px }");
+        let geometry = |node: &DomNodeRef| layout.geometry(layout.tree().boxes_of(node)[0]);
+        let children = root.borrow().children.to_vec();
+
+        // The body's margin and the p's are 8px and 1em
+        assert_eq!(geometry(&root).content, Rect::new(8.0, 8.0, 784.0, 107.0));
+        assert_eq!(geometry(&children[0]).content, Rect::new(8.0, 18.0, 784.0, 20.0));
+        assert_eq!(geometry(&children[0]).margin_box().bottom(), 48.0);
+        let div = geometry(&children[1]);
+        assert_eq!(div.content, Rect::new(13.0, 53.0, 392.0, 30.0));
+        assert_eq!(div.padding_box(), Rect::new(8.0, 48.0, 402.0, 40.0));
+
+        // The inline-block is as wide as its widest child, and sits on the
+        // baseline of an anonymous block's line, 27px tall with the strut
+        let section = geometry(&children[2]);
+        assert_eq!(section.content, Rect::new(18.0, 88.0, 70.0, 20.0));
+        let three = children[2].borrow().children[1].clone();
+        assert_eq!(geometry(&three).content, Rect::new(28.0, 88.0, 50.0, 20.0));
+        let text = &geometry(&three).lines[0].fragments[1];
+        assert_eq!(text.text.as_ref().unwrap(), "Three four");
+        assert_eq!(text.rect, Rect::new(28.0, 88.0, 50.0, 20.0));
+    }
+
+    #[test]
+    fn test_layout_floats() {
+        let css = "div { width: 100px; font-size: 10px; line-height: 10px } p { margin: 0 }";
+        let root = dom!(div {
+            b[style = "float: left; width: 30px; height: 35px"]
+            i[style = "float: right; width: 20px; height: 10px"]
+            "aaaa bbbb cccc dddd eeee ffff"
+            p[style = "clear: left"] { "gg" }
+        });
+        let layout = lay_out(&root, css);
+        let tree = layout.tree();
+        let children = &tree.get(tree.boxes_of(&root)[0]).children;
+        let margin_box = |id: BoxId| layout.geometry(id).margin_box();
+        assert_eq!(margin_box(children[0]), Rect::new(0.0, 0.0, 30.0, 35.0));
+        assert_eq!(margin_box(children[1]), Rect::new(80.0, 0.0, 20.0, 10.0));
+        // The lines beside the floats are shortened, and the p clears the
+        // left one
+        let lines = layout.geometry(children[2]).lines.iter().map(|line| {
+            let fragment = &line.fragments[1];
+            (fragment.text.clone().unwrap(), fragment.rect.x, fragment.rect.y)
+        });
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            vec![
+                ("aaaa bbbb".to_string(), 30.0, 0.0),
+                ("cccc dddd eeee".to_string(), 30.0, 10.0),
+                ("ffff".to_string(), 30.0, 20.0),
+            ]
+        );
+        assert_eq!(margin_box(children[3]), Rect::new(0.0, 35.0, 100.0, 10.0));
+        assert_eq!(layout.geometry(tree.boxes_of(&root)[0]).content.height, 45.0);
+
+        // A float that doesn't fit beside the content before it on its line
+        // goes below the line, which a float in an inline-block makes taller
+        let root = dom!(div {
+            "aaaa bbbb "
+            em[style = "float: right; width: 70px; height: 10px"]
+            "cc "
+            span[style = "display: inline-block"] { b[style = "float: left; height: 30px"] }
+        });
+        let layout = lay_out(&root, css);
+        let em = layout.tree().boxes_of(&root.borrow().children[1])[0];
+        assert_eq!(layout.geometry(em).margin_box(), Rect::new(30.0, 32.0, 70.0, 10.0));
+        let span = layout.tree().boxes_of(&root.borrow().children[3])[0];
+        assert_eq!(layout.geometry(span).content.height, 30.0);
+    }
+
+    #[test]
+    fn test_layout_positioned() {
+        let style = "position: relative; width: 200px; height: 100px; padding: 10px";
+        let root = dom!(div[style = style] {
+            p[style = "position: relative; top: 5px; left: -5px"] { "aa" }
+            b[style = "position: absolute; right: 10px; bottom: 10px; width: 20px; height: 20px"]
+            p { "bb " em[style = "position: absolute"] { "cc" } }
+            i[style = "position: absolute; left: 0; right: 0; top: 10%"] { "dd" }
+            s[style = "position: fixed; top: 1px; left: 2px"] { "ee" }
+            span[style = "position: relative; left: 10px"] { "ff" }
+        });
+        let layout = lay_out(&root, "div { font-size: 10px; line-height: 10px } p { margin: 0 }");
+        let children = root.borrow().children.to_vec();
+        let id = |node: &DomNodeRef| layout.tree().boxes_of(node)[0];
+        let geometry = |node: &DomNodeRef| layout.geometry(id(node));
+
+        // Moving a box relatively doesn't move the boxes after it, and
+        // absolutely positioned boxes take up no room
+        assert_eq!(geometry(&children[0]).content, Rect::new(5.0, 15.0, 200.0, 10.0));
+        assert_eq!(geometry(&children[2]).content, Rect::new(10.0, 20.0, 200.0, 10.0));
+        // In the div's padding box
+        assert_eq!(geometry(&children[1]).margin_box(), Rect::new(190.0, 90.0, 20.0, 20.0));
+        // Where it would have been on the line, after the p's text
+        let em = children[2].borrow().children[1].clone();
+        assert_eq!(geometry(&em).content, Rect::new(20.0, 20.0, 10.0, 10.0));
+        assert_eq!(geometry(&children[3]).margin_box(), Rect::new(0.0, 12.0, 220.0, 10.0));
+        assert_eq!(geometry(&children[4]).content, Rect::new(2.0, 1.0, 10.0, 10.0));
+
+        // A relatively positioned inline box is moved with its fragments
+        assert_eq!(geometry(&children[5]).content.x, 20.0);
+        let anonymous = layout.tree().get(id(&children[5])).parent.unwrap();
+        let line = &layout.geometry(anonymous).lines[0];
+        assert_eq!(line.rect, Rect::new(10.0, 30.0, 200.0, 10.0));
+        assert_eq!(line.fragments[0].rect.x, 20.0);
+        assert_eq!(line.fragments[1].rect.x, 20.0);
+        assert_eq!(geometry(&root).content.height, 100.0);
+    }
+
+    #[test]
+    fn test_layout_sizes() {
+        let centered = "position: absolute; left: 0; right: 0; top: 0; bottom: 0; \
+                        width: 40px; height: 20px; margin: auto";
+        let root = dom!(div[style = "width: 200px"] {
+            p[style = "width: 100px; margin: 0 auto"]
+            p[style = "max-width: 50px; min-height: 30px"] { "aa" }
+            p[style = "width: 100px; margin: 0 10px"]
+            div[style = "width: 10px"] { span[style = "display: inline-block"] { "aaaa bbbb" } }
+            b[style = centered]
+            span[style = "display: inline-block; max-width: 50%"] { "aaaa bbbb cccc dddd eeee" }
+        });
+        let layout = lay_out(&root, "div { font-size: 10px; line-height: 10px } p { margin: 0 }");
+        let children = root.borrow().children.to_vec();
+        let geometry = |node: &DomNodeRef| layout.geometry(layout.tree().boxes_of(node)[0]);
+
+        // Auto margins center a block, and the end margin of one that's
+        // over-constrained takes the rest of the width
+        assert_eq!(geometry(&children[0]).content, Rect::new(50.0, 0.0, 100.0, 0.0));
+        assert_eq!(geometry(&children[0]).margin.right, 50.0);
+        assert_eq!(geometry(&children[1]).content, Rect::new(0.0, 0.0, 50.0, 30.0));
+        assert_eq!(geometry(&children[2]).content, Rect::new(10.0, 30.0, 100.0, 0.0));
+        assert_eq!(geometry(&children[2]).margin.right, 90.0);
+
+        // Shrinking to fit stops at the widest word
+        let span = children[3].borrow().children[0].clone();
+        assert_eq!(geometry(&span).content.width, 20.0);
+        assert_eq!(geometry(&span).content.height, 20.0);
+        assert_eq!(geometry(&children[4]).content, Rect::new(380.0, 290.0, 40.0, 20.0));
+        assert_eq!(geometry(&children[5]).content.width, 100.0);
+        assert_eq!(geometry(&children[5]).content.height, 20.0);
+    }
+
+    #[test]
+    fn test_relayout() {
+        let root = dom!(div[style = "height: 1000px"] { p[style = "width: 50%"] });
+        let mut layout = lay_out(&root, "");
+        let viewport = layout.tree().root();
+        let p = layout.tree().boxes_of(&root.borrow().children[0])[0];
+        assert_eq!(layout.geometry(p).content.width, 400.0);
+        assert_eq!(layout.scroll_to(viewport, 0.0, 300.0), Some((0.0, 300.0)));
+
+        // Scrolled as far as it can be now that the viewport is taller
+        layout.relayout(Viewport::new(500.0, 800.0));
+        assert_eq!(layout.viewport(), Viewport::new(500.0, 800.0));
+        assert_eq!(layout.geometry(p).content.width, 250.0);
+        assert_eq!(layout.scroll_area(viewport).unwrap().offset, (0.0, 200.0));
+    }
+
+    #[test]
+    fn test_update() {
+        let root = dom!(div {
+            p { span }
+            p[style = "height: 20px"] { b { "Two" } }
+            p { div[style = "width: 50%; height: 5px"] }
+        });
+        let css = "p { height: 10px }";
+        let mut layout = lay_out(&root, css);
+        let (first, second, third) = {
+            let children = &root.borrow().children;
+            (children[0].clone(), children[1].clone(), children[2].clone())
+        };
+        let update = |layout: &mut Layout| {
+            let styles = StyleResolver::new(parse_stylesheet(css).unwrap()).resolve(&root);
+            layout.update(BoxTree::new(&root, &styles));
+            let fresh = Layout::new(layout.tree().clone(), layout.viewport());
+            assert!(layout.tree().ids().all(|id| layout.geometry(id) == fresh.geometry(id)));
+        };
+
+        // Only the p that changed, and the boxes it's in, are laid out again:
+        // the p after it is moved down to where it goes now
+        second.set_attribute("style", Some("height: 30px".to_string()));
+        update(&mut layout);
+        let tree = layout.tree();
+        let laid_out = |node: &DomNodeRef| layout.was_laid_out(tree.boxes_of(node)[0]);
+        assert!(laid_out(&second) && laid_out(&root));
+        assert!(!laid_out(&first) && !laid_out(&third));
+        assert!(laid_out(&second.borrow().children[0]));
+        assert!(!laid_out(&third.borrow().children[0]));
+
+        // A change of color doesn't need anything laid out again
+        second.set_attribute("style", Some("height: 30px; color: red".to_string()));
+        update(&mut layout);
+        let tree = layout.tree();
+        let laid_out = |node: &DomNodeRef| layout.was_laid_out(tree.boxes_of(node)[0]);
+        assert!(!laid_out(&second) && !laid_out(&root));
+    }
+
+    #[test]
+    fn test_update_empty_inline() {
+        // An empty span is on no line, and stays at the start of its
+        // anonymous block, which is moved down as it was
+        let root = dom!(div { div[class = "a"] span });
+        let css = ".a { height: 10px } .b { height: 30px }";
+        let mut layout = lay_out(&root, css);
+        let div = root.borrow().children[0].clone();
+        div.set_attribute("class", Some("b".to_string()));
+        let styles = StyleResolver::new(parse_stylesheet(css).unwrap()).resolve(&root);
+        layout.update(BoxTree::new(&root, &styles));
+        let fresh = Layout::new(layout.tree().clone(), layout.viewport());
+        let span = layout.tree().boxes_of(&root.borrow().children[1])[0];
+        assert!(!layout.was_laid_out(layout.tree().get(span).parent.unwrap()));
+        assert_eq!(layout.geometry(span).content, Rect::new(0.0, 30.0, 0.0, 0.0));
+        assert!(layout.tree().ids().all(|id| layout.geometry(id) == fresh.geometry(id)));
+    }
+
+    #[test]
+    fn test_used_values() {
+        let style = "width: 50%; height: 10vh; margin: 0 auto; padding: 0 10%";
+        let root = dom!(div[style = "width: 200px"] { p[style = style] { b { "One" } } });
+        let layout = lay_out(&root, "div { font-size: 10px }");
+        let p = layout.tree().boxes_of(&root.borrow().children[0])[0];
+        let b = layout.tree().boxes_of(&root.borrow().children[0].borrow().children[0])[0];
+        let used_value = |id: BoxId, property: &str| layout.used_value(id, property).unwrap();
+        assert_eq!(used_value(p, "width"), "100px");
+        assert_eq!(used_value(p, "height"), "60px");
+        assert_eq!(used_value(p, "margin-left"), "30px");
+        assert_eq!(used_value(p, "padding-right"), "20px");
+        assert_eq!(used_value(p, "line-height"), "12px");
+        assert_eq!(used_value(p, "display"), "block");
+        assert_eq!(used_value(b, "width"), "auto");
+    }
+
+    #[test]
+    fn test_layout_replaced() {
+        // A width scales the height with the image's aspect ratio, and
+        // replaced boxes with nothing to go on are 300px by 150px
+        let root = dom!(div {
+            img[src = "a.png"]
+            img[src = "a.png"][style = "width: 50px"]
+            canvas[style = "display: block; max-width: 100px"] { "Fallback" }
+            img[src = "a.png"][style = "position: absolute; left: 10px; right: 10px"]
+        });
+        let styles = StyleResolver::new(parse_stylesheet("").unwrap()).resolve(&root);
+        let loader = |url: &str| Some(IntrinsicSize::new(200.0, 100.0)).filter(|_| url == "a.png");
+        let tree = BoxTree::new_with_loader(&root, &styles, loader);
+        let layout = Layout::new(tree, Viewport::new(800.0, 600.0));
+        let children = root.borrow().children.to_vec();
+        let box_of = |node: &DomNodeRef| layout.tree().boxes_of(node)[0];
+        let content = |node: &DomNodeRef| layout.geometry(box_of(node)).content;
+        // On the baseline of a line that's 100px above it and 4.8px below
+        assert_eq!(content(&children[0]), Rect::new(0.0, 0.0, 200.0, 100.0));
+        assert_eq!(content(&children[1]), Rect::new(200.0, 75.0, 50.0, 25.0));
+        assert_eq!(content(&children[2]), Rect::new(0.0, 104.8, 100.0, 150.0));
+        assert!(layout.tree().get(box_of(&children[2])).children.is_empty());
+        // Not stretched between its insets, and below the canvas, where it
+        // would have been
+        assert_eq!(content(&children[3]), Rect::new(10.0, 254.8, 200.0, 100.0));
+    }
+
+    #[test]
+    fn test_writing_modes() {
+        // Lines go down, and blocks are stacked from the block-start side,
+        // the padding and margins are on
+        let css = "p { margin: 0; font-size: 10px; line-height: 20px }";
+        let lr = dom!(div[style = "writing-mode: vertical-lr; padding-left: 20px"] {
+            p[style = "width: 30px; height: 100px; margin: 10px 0 0 5px"] { "aa" }
+        });
+        let rl = dom!(div[style = "writing-mode: vertical-rl; padding-right: 20px"] {
+            p[style = "width: 30px; height: 100px; margin: 10px 5px 0 0"] { "aa" }
+        });
+        // The x of the div, p, text and baseline
+        let cases = [(lr, [20.0, 25.0, 25.0, 38.0]), (rl, [745.0, 745.0, 755.0, 762.0])];
+        for &(ref root, xs) in &cases {
+            let layout = lay_out(root, css);
+            let div = layout.geometry(layout.tree().boxes_of(root)[0]);
+            assert_eq!(div.content, Rect::new(xs[0], 0.0, 35.0, 600.0));
+            let p = layout.geometry(layout.tree().boxes_of(&root.borrow().children[0])[0]);
+            assert_eq!(p.content, Rect::new(xs[1], 10.0, 30.0, 100.0));
+            assert_eq!(p.margin_box().width, 35.0);
+            assert_eq!(p.lines[0].fragments[1].rect, Rect::new(xs[2], 10.0, 20.0, 10.0));
+            assert_eq!(p.lines[0].baseline, xs[3]);
+        }
+    }
+}
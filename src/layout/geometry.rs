@@ -0,0 +1,117 @@
+use style::length::ComputedLength;
+use style::typed::TypedStyle;
+
+/// A rectangle, in px, with y growing downwards.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    pub fn right(&self) -> f32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+
+    /// Returns true if (x, y) is in the rectangle, counting its left and top
+    /// edges, but not its right and bottom ones.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// Returns the smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+
+    /// Returns the rectangle moved by dx and dy.
+    pub fn translate(&self, dx: f32, dy: f32) -> Rect {
+        Rect::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+
+    /// Returns the rectangle grown by sides, e.g. a content box grown by its
+    /// padding.
+    pub fn outset(&self, sides: &Sides) -> Rect {
+        Rect::new(
+            self.x - sides.left,
+            self.y - sides.top,
+            self.width + sides.horizontal(),
+            self.height + sides.vertical(),
+        )
+    }
+}
+
+/// Widths of the four sides of a box's margin or padding, in px.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Sides {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl Sides {
+    /// Returns style's margins, with percentages of containing_width, the
+    /// width of the containing block. Auto margins are 0.
+    pub fn margin(style: &TypedStyle, containing_width: f32) -> Sides {
+        let to_px = |margin: Option<ComputedLength>| {
+            margin.map_or(0.0, |margin| margin.to_px(containing_width))
+        };
+        Sides {
+            top: to_px(style.margin_top),
+            right: to_px(style.margin_right),
+            bottom: to_px(style.margin_bottom),
+            left: to_px(style.margin_left),
+        }
+    }
+
+    /// Like margin(), but for padding.
+    pub fn padding(style: &TypedStyle, containing_width: f32) -> Sides {
+        Sides {
+            top: style.padding_top.to_px(containing_width),
+            right: style.padding_right.to_px(containing_width),
+            bottom: style.padding_bottom.to_px(containing_width),
+            left: style.padding_left.to_px(containing_width),
+        }
+    }
+
+    pub fn horizontal(&self) -> f32 {
+        self.left + self.right
+    }
+
+    pub fn vertical(&self) -> f32 {
+        self.top + self.bottom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rect() {
+        let rect = Rect::new(10.0, 20.0, 30.0, 40.0);
+        assert_eq!(rect.union(&Rect::new(0.0, 30.0, 5.0, 50.0)), Rect::new(0.0, 20.0, 40.0, 60.0));
+        assert_eq!(rect.translate(-10.0, 5.0), Rect::new(0.0, 25.0, 30.0, 40.0));
+        assert!(rect.contains(10.0, 20.0) && !rect.contains(40.0, 30.0));
+        let sides = Sides {
+            top: 1.0,
+            right: 2.0,
+            bottom: 3.0,
+            left: 4.0,
+        };
+        assert_eq!(rect.outset(&sides), Rect::new(6.0, 19.0, 36.0, 44.0));
+    }
+}
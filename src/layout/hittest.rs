@@ -0,0 +1,250 @@
+use layout::boxtree::{BoxId, BoxKind, BoxTree};
+use layout::flow::{is_positioned, BoxGeometry};
+use layout::geometry::Rect;
+use layout::scroll::{scroll_container_of, ScrollArea};
+
+/// Returns the parts of tree's boxes that are painted, and so can be hit,
+/// in the order they're painted in: the padding boxes of boxes other than
+/// inline boxes and text, and the fragments of those on their lines, with
+/// geometry by BoxId::index(). Boxes that aren't visible are in it too.
+///
+/// It's the order of CSS 2's appendix E, with no borders or outlines, and
+/// stacking contexts made only by the viewport and positioned boxes with a
+/// `z-index` other than auto. Each one paints its own box, the stacking
+/// contexts in it with a negative z-index, its in-flow blocks, its floats,
+/// its lines, then its other positioned boxes, and those with a z-index of
+/// 0, in tree order, and last the ones with a positive z-index, from the
+/// lowest up. Floats, inline-blocks and positioned boxes without a z-index
+/// are painted as if they were stacking contexts, but the positioned boxes
+/// in them are painted with those of the stacking context they're in.
+/// Relatively positioned inline boxes are painted like any other.
+pub fn paint_order(tree: &BoxTree, geometry: &[BoxGeometry]) -> Vec<(BoxId, Rect)> {
+    let painter = Painter { tree, geometry };
+    let mut order = vec![];
+    painter.paint_stacking_context(tree.root(), &mut order);
+    order
+}
+
+/// Returns the topmost box at (x, y) in the viewport: the last one in
+/// paint_order, once it's moved by the offsets of the scroll containers
+/// it's in, from scroll_areas, that isn't clipped out of view by them, and
+/// is visible.
+pub fn hit_test(
+    tree: &BoxTree,
+    scroll_areas: &[Option<ScrollArea>],
+    paint_order: &[(BoxId, Rect)],
+    x: f32,
+    y: f32,
+) -> Option<BoxId> {
+    let is_container = scroll_areas.iter().map(Option::is_some).collect::<Vec<_>>();
+    paint_order
+        .iter()
+        .rev()
+        .find(|&&(id, rect)| {
+            let offset = scroll_offset_at(tree, scroll_areas, &is_container, id, x, y);
+            tree.get(id).is_visible()
+                && offset.is_some_and(|(dx, dy)| rect.translate(-dx, -dy).contains(x, y))
+        })
+        .map(|&(id, _)| id)
+}
+
+// How far box id is scrolled by the scroll containers it's in, or None if
+// (x, y) is outside the scrollport of one of them, where it's clipped
+fn scroll_offset_at(
+    tree: &BoxTree,
+    scroll_areas: &[Option<ScrollArea>],
+    is_container: &[bool],
+    id: BoxId,
+    x: f32,
+    y: f32,
+) -> Option<(f32, f32)> {
+    let container = match scroll_container_of(tree, is_container, id) {
+        Some(container) => container,
+        None => return Some((0.0, 0.0)),
+    };
+    let (dx, dy) = scroll_offset_at(tree, scroll_areas, is_container, container, x, y)?;
+    let scroll_area = scroll_areas[container.index()]?;
+    if !scroll_area.scrollport.translate(-dx, -dy).contains(x, y) {
+        return None;
+    }
+    Some((dx + scroll_area.offset.0, dy + scroll_area.offset.1))
+}
+
+// The positioned boxes a stacking context paints after its in-flow content,
+// in tree order
+#[derive(Default)]
+struct Layers {
+    negative: Vec<(i32, BoxId)>,
+    // Without a z-index, or with 0
+    positioned: Vec<BoxId>,
+    positive: Vec<(i32, BoxId)>,
+}
+
+struct Painter<'a> {
+    tree: &'a BoxTree,
+    geometry: &'a [BoxGeometry],
+}
+
+impl<'a> Painter<'a> {
+    fn paint_stacking_context(&self, id: BoxId, order: &mut Vec<(BoxId, Rect)>) {
+        let mut layers = Layers::default();
+        let mut contents = vec![];
+        self.paint_contents(id, &mut layers, &mut contents);
+        // Painting those adds the positioned boxes in them
+        let mut positioned = vec![];
+        let mut i = 0;
+        while i < layers.positioned.len() {
+            let id = layers.positioned[i];
+            if self.tree.get(id).style.typed().z_index.is_some() {
+                self.paint_stacking_context(id, &mut positioned);
+            } else {
+                self.paint_atomically(id, &mut layers, &mut positioned);
+            }
+            i += 1;
+        }
+        layers.negative.sort_by_key(|&(z_index, _)| z_index);
+        layers.positive.sort_by_key(|&(z_index, _)| z_index);
+        self.paint_box(id, order);
+        for &(_, id) in &layers.negative {
+            self.paint_stacking_context(id, order);
+        }
+        order.extend(contents);
+        order.extend(positioned);
+        for &(_, id) in &layers.positive {
+            self.paint_stacking_context(id, order);
+        }
+    }
+
+    fn paint_atomically(&self, id: BoxId, layers: &mut Layers, order: &mut Vec<(BoxId, Rect)>) {
+        self.paint_box(id, order);
+        self.paint_contents(id, layers, order);
+    }
+
+    fn paint_box(&self, id: BoxId, order: &mut Vec<(BoxId, Rect)>) {
+        order.push((id, self.geometry[id.index()].padding_box()));
+    }
+
+    // Paints what's in block container id, other than the positioned boxes,
+    // which are added to layers
+    fn paint_contents(&self, id: BoxId, layers: &mut Layers, order: &mut Vec<(BoxId, Rect)>) {
+        let (mut blocks, mut floats) = (vec![], vec![]);
+        self.collect_descendants(id, layers, &mut blocks, &mut floats);
+        for &block in &blocks {
+            self.paint_box(block, order);
+        }
+        for &float in &floats {
+            self.paint_atomically(float, layers, order);
+        }
+        for container in Some(id).into_iter().chain(blocks) {
+            let lines = &self.geometry[container.index()].lines;
+            for fragment in lines.iter().flat_map(|line| &line.fragments) {
+                match self.tree.get(fragment.box_id).kind {
+                    BoxKind::InlineBlock if !self.is_stacked(fragment.box_id) => {
+                        self.paint_atomically(fragment.box_id, layers, order)
+                    }
+                    BoxKind::Inline | BoxKind::Text(_) | BoxKind::AnonymousInline => {
+                        order.push((fragment.box_id, fragment.rect))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Adds the in-flow block-level descendants of id to blocks, and its
+    // floats to floats, in tree order, and its positioned descendants to
+    // layers, other than those in floats, inline-blocks and positioned
+    // boxes, which are painted with their own
+    fn collect_descendants(
+        &self,
+        id: BoxId,
+        layers: &mut Layers,
+        blocks: &mut Vec<BoxId>,
+        floats: &mut Vec<BoxId>,
+    ) {
+        for &child in &self.tree.get(id).children {
+            if self.is_stacked(child) {
+                match self.tree.get(child).style.typed().z_index {
+                    Some(z_index) if z_index < 0 => layers.negative.push((z_index, child)),
+                    Some(z_index) if z_index > 0 => layers.positive.push((z_index, child)),
+                    _ => layers.positioned.push(child),
+                }
+                continue;
+            }
+            match self.tree.get(child).kind {
+                BoxKind::Float => floats.push(child),
+                BoxKind::InlineBlock => {}
+                BoxKind::Block | BoxKind::AnonymousBlock => {
+                    blocks.push(child);
+                    self.collect_descendants(child, layers, blocks, floats);
+                }
+                _ => self.collect_descendants(child, layers, blocks, floats),
+            }
+        }
+    }
+
+    // True for the positioned boxes that are painted after the in-flow
+    // content of their stacking context
+    fn is_stacked(&self, id: BoxId) -> bool {
+        let layout_box = self.tree.get(id);
+        match layout_box.kind {
+            BoxKind::Absolute => true,
+            BoxKind::Block | BoxKind::InlineBlock | BoxKind::Float => {
+                is_positioned(&layout_box.style)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use layout::boxtree::BoxTree;
+    use layout::flow::Layout;
+    use magicparser::{parse_stylesheet, DomNodeRef};
+    use style::media::Viewport;
+    use style::resolver::StyleResolver;
+
+    #[test]
+    fn test_hit_test() {
+        let root = dom!(div {
+            p[style = "height: 20px"] { "Hi" }
+            div[style = "width: 100px; height: 30px; overflow: auto"] {
+                p[style = "height: 100px"]
+                p[style = "height: 10px"]
+            }
+            i[style = "top: 0; left: 200px; width: 50px; height: 100px; z-index: -1"]
+            b[style = "top: 0; left: 0; width: 10px; height: 10px"]
+            u[style = "top: 0; left: 300px; width: 10px; height: 10px; visibility: hidden"]
+        });
+        let css = "p { margin: 0 } i, b, u { position: absolute }";
+        let styles = StyleResolver::new(parse_stylesheet(css).unwrap()).resolve(&root);
+        let mut layout = Layout::new(BoxTree::new(&root, &styles), Viewport::new(800.0, 600.0));
+        let child = |i: usize| root.borrow().children[i].clone();
+        let (p, scroller, i, b) = (child(0), child(1), child(2), child(3));
+        let first = scroller.borrow().children[0].clone();
+        let last = scroller.borrow().children[1].clone();
+        let hit = |layout: &Layout, x: f32, y: f32, node: &DomNodeRef| {
+            layout.hit_test(x, y).is_some_and(|hit| hit.ptr_eq(node))
+        };
+
+        // Text is hit as the element it's in, positioned boxes are on top of
+        // the blocks, and those with a negative z-index are below them. Hidden
+        // boxes are hit through.
+        assert!(hit(&layout, 4.0, 15.0, &p));
+        assert!(hit(&layout, 4.0, 5.0, &b));
+        assert!(hit(&layout, 220.0, 10.0, &p));
+        assert!(hit(&layout, 220.0, 40.0, &root));
+        assert!(hit(&layout, 220.0, 80.0, &i));
+        assert!(hit(&layout, 305.0, 5.0, &p));
+        assert_eq!(layout.hit_test(220.0, 200.0), None);
+
+        // What's scrolled out of a scroll container's padding box is clipped
+        assert!(hit(&layout, 50.0, 40.0, &first));
+        assert_eq!(layout.hit_test(50.0, 60.0), None);
+        let scroller = layout.tree().boxes_of(&scroller)[0];
+        layout.scroll_to(scroller, 0.0, 80.0);
+        assert!(hit(&layout, 50.0, 45.0, &last));
+        assert!(hit(&layout, 50.0, 35.0, &first));
+    }
+}
@@ -0,0 +1,681 @@
+use layout::boxtree::{BoxId, BoxKind, BoxTree};
+use layout::geometry::{Rect, Sides};
+use layout::metrics::TextMetrics;
+use magicparser::WhiteSpace;
+use std::mem;
+use style::length::{LengthContext, LengthOrPercentage};
+use style::resolver::ComputedStyle;
+
+/// A line of a block container's inline-level content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineBox {
+    // As wide as the container's content box, and as tall as the content
+    // on the line
+    pub rect: Rect,
+    // The y of the baseline everything on the line is aligned to, or in the
+    // vertical writing modes, where lines go down, its x
+    pub baseline: f32,
+    // In the order of the content, with each inline box's fragment before
+    // those of the boxes in it
+    pub fragments: Vec<Fragment>,
+}
+
+/// The part of an inline-level box that's on a line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    pub box_id: BoxId,
+    // The margin box of an inline-block. Inline boxes and text are as tall
+    // as their line-height, and an inline box is as wide as the part of its
+    // padding box on the line.
+    pub rect: Rect,
+    // Of text: the part of its text on the line, with its whitespace
+    // collapsed
+    pub text: Option<String>,
+}
+
+/// Returns the used line-height of text with style, that of its font from
+/// metrics for `normal`.
+pub fn line_height(style: &ComputedStyle, metrics: &dyn TextMetrics) -> f32 {
+    let typed = style.typed();
+    let font_size = typed.font_size;
+    let value = typed.line_height.trim();
+    if let Ok(number) = value.parse::<f32>() {
+        return number * font_size;
+    }
+    let context = LengthContext {
+        font_size,
+        ..LengthContext::default()
+    };
+    LengthOrPercentage::parse(value)
+        .and_then(|length| length.compute(&context))
+        .map_or_else(
+            || metrics.font_metrics(style).normal_line_height(),
+            |length| length.to_px(font_size),
+        )
+}
+
+// How far text with style reaches above and below the baseline, with its
+// line-height's leading split between the two
+fn text_extent(style: &ComputedStyle, metrics: &dyn TextMetrics) -> (f32, f32) {
+    let font = metrics.font_metrics(style);
+    let line_height = line_height(style, metrics);
+    let half_leading = (line_height - font.ascent - font.descent) / 2.0;
+    let above = font.ascent + half_leading;
+    (above, line_height - above)
+}
+
+// The widths of sides at the start and end of an inline box's fragments,
+// when lines go right to left if rtl
+fn start_and_end(sides: Sides, rtl: bool) -> (f32, f32) {
+    if rtl {
+        (sides.right, sides.left)
+    } else {
+        (sides.left, sides.right)
+    }
+}
+
+// True if white-space lets lines break at spaces
+fn wraps(style: &ComputedStyle) -> bool {
+    !matches!(style.get("white-space"), Some("nowrap") | Some("pre"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ItemKind {
+    Text(String),
+    // A space that's removed at the start and end of a line
+    CollapsibleSpace,
+    // The start and end edges of an inline box, its margin and padding
+    Start,
+    End,
+    // An inline-block, with the height of its margin box
+    Atomic(f32),
+    // A float, which is placed beside the lines rather than on one
+    Float,
+    // Where an absolutely positioned box would have been
+    Placeholder,
+    // A preserved newline
+    Break,
+}
+
+// A piece of inline content that isn't broken across lines
+#[derive(Debug, Clone)]
+struct Item {
+    id: BoxId,
+    kind: ItemKind,
+    width: f32,
+    // True if a line can break after the item
+    break_after: bool,
+}
+
+impl Item {
+    fn is_content(&self) -> bool {
+        matches!(self.kind, ItemKind::Text(_) | ItemKind::Atomic(_))
+    }
+}
+
+// Turns a container's inline-level boxes into items, collapsing whitespace
+struct ItemBuilder<'a, F> {
+    tree: &'a BoxTree,
+    // Of the container, for percentages
+    width: f32,
+    // True if the container's lines go right to left
+    rtl: bool,
+    metrics: &'a dyn TextMetrics,
+    atomic_size: F,
+    items: Vec<Item>,
+    // True after a collapsible space, and at the start, where following
+    // spaces collapse away
+    after_space: bool,
+}
+
+impl<'a, F: Fn(BoxId) -> (f32, f32)> ItemBuilder<'a, F> {
+    fn add_children(&mut self, id: BoxId) {
+        for &child in &self.tree.get(id).children {
+            self.add(child);
+        }
+    }
+
+    fn add(&mut self, id: BoxId) {
+        let layout_box = self.tree.get(id);
+        match layout_box.kind {
+            BoxKind::Text(ref text) => self.add_text(id, text, &layout_box.style),
+            BoxKind::InlineBlock => {
+                let (width, height) = (self.atomic_size)(id);
+                // Lines can break on both sides of an inline-block
+                let wraps = wraps(&layout_box.style);
+                if let Some(last) = self.items.last_mut() {
+                    last.break_after |= wraps;
+                }
+                self.push(id, ItemKind::Atomic(height), width, wraps);
+                self.after_space = false;
+            }
+            BoxKind::Float => {
+                let (width, _) = (self.atomic_size)(id);
+                self.push(id, ItemKind::Float, width, false);
+            }
+            BoxKind::Absolute => self.push(id, ItemKind::Placeholder, 0.0, false),
+            _ => {
+                let typed = self.tree.logical_style(id);
+                let margin = start_and_end(Sides::margin(&typed, self.width), self.rtl);
+                let padding = start_and_end(Sides::padding(&typed, self.width), self.rtl);
+                self.push(id, ItemKind::Start, margin.0 + padding.0, false);
+                self.add_children(id);
+                self.push(id, ItemKind::End, padding.1 + margin.1, false);
+            }
+        }
+    }
+
+    fn add_text(&mut self, id: BoxId, text: &str, style: &ComputedStyle) {
+        let typed = style.typed();
+        let mode = WhiteSpace::from(typed.white_space.as_str());
+        let wraps = wraps(style);
+        let mut word = String::new();
+        for ch in text.chars() {
+            if !ch.is_ascii_whitespace() {
+                word.push(ch);
+                continue;
+            }
+            self.push_word(id, &mut word, style);
+            if ch == '\n' && mode != WhiteSpace::Collapse {
+                self.push(id, ItemKind::Break, 0.0, false);
+                self.after_space = true;
+            } else if mode == WhiteSpace::Preserve {
+                let width = self.metrics.width(" ", style);
+                self.push(id, ItemKind::Text(ch.to_string()), width, wraps);
+                self.after_space = false;
+            } else if !self.after_space {
+                let width = self.metrics.width(" ", style);
+                self.push(id, ItemKind::CollapsibleSpace, width, wraps);
+                self.after_space = true;
+            }
+        }
+        self.push_word(id, &mut word, style);
+    }
+
+    fn push_word(&mut self, id: BoxId, word: &mut String, style: &ComputedStyle) {
+        if word.is_empty() {
+            return;
+        }
+        let width = self.metrics.width(word, style);
+        self.push(id, ItemKind::Text(mem::take(word)), width, false);
+        self.after_space = false;
+    }
+
+    fn push(&mut self, id: BoxId, kind: ItemKind, width: f32, break_after: bool) {
+        self.items.push(Item {
+            id,
+            kind,
+            width,
+            break_after,
+        });
+    }
+}
+
+fn inline_items<F>(
+    tree: &BoxTree,
+    container: BoxId,
+    width: f32,
+    metrics: &dyn TextMetrics,
+    atomic_size: F,
+) -> Vec<Item>
+where
+    F: Fn(BoxId) -> (f32, f32),
+{
+    let mut builder = ItemBuilder {
+        tree,
+        width,
+        rtl: tree.get(container).style.get("direction") == Some("rtl"),
+        metrics,
+        atomic_size,
+        items: vec![],
+        after_space: true,
+    };
+    builder.add_children(container);
+    builder.items
+}
+
+// Collapsible spaces at the start and end of a line take up no room
+fn is_trimmed(line: &[Item], index: usize) -> bool {
+    line[index].kind == ItemKind::CollapsibleSpace
+        && (!line[..index].iter().any(Item::is_content)
+            || !line[index + 1..].iter().any(Item::is_content))
+}
+
+fn used_width(line: &[Item]) -> f32 {
+    (0..line.len())
+        .filter(|&i| !is_trimmed(line, i))
+        .map(|i| line[i].width)
+        .sum()
+}
+
+// Returns where the line starting at items[start] ends, filled with as many
+// items as fit in width: after the last item that allows a break once the
+// next doesn't fit, or after a preserved newline. Items that don't fit on a
+// line of their own overflow it. Also returns true if the line stopped at a
+// float, items[end], which has to be placed before the line can go on.
+fn line_end(items: &[Item], start: usize, width: f32) -> (usize, bool) {
+    for end in start..items.len() {
+        let item = &items[end];
+        match item.kind {
+            ItemKind::Break => return (end + 1, false),
+            ItemKind::Float => return (end, true),
+            _ => {}
+        }
+        let line = &items[start..end];
+        if item.is_content() && used_width(line) + item.width > width {
+            let last_break = line.iter().rposition(|item| item.break_after);
+            if let Some(i) = last_break.filter(|&i| line[..i].iter().any(Item::is_content)) {
+                // With the ends of the inline boxes that end there
+                let mut split = start + i + 1;
+                while items[split].kind == ItemKind::End {
+                    split += 1;
+                }
+                return (split, false);
+            }
+        }
+    }
+    (items.len(), false)
+}
+
+/// What lines are laid out among: the inline-blocks and floats in them, and
+/// the floats they go around, which block layout takes care of.
+pub trait LineContext {
+    /// Returns the width and height of an inline-block's or a float's margin
+    /// box.
+    fn size(&self, id: BoxId) -> (f32, f32);
+
+    /// Returns the left and right edges of the room that floats leave a line
+    /// at y, height tall.
+    fn line_space(&self, y: f32, height: f32) -> (f32, f32);
+
+    /// Returns where the next float below y ends, where lines may have more
+    /// room, or None if none do.
+    fn next_float_end(&self, y: f32) -> Option<f32>;
+
+    /// Places a float no higher than y, which changes the room lines have.
+    fn place_float(&mut self, id: BoxId, y: f32);
+
+    /// Records where an absolutely positioned box would have been on the
+    /// lines, at x on the line whose top is at y.
+    fn set_static_position(&mut self, id: BoxId, x: f32, y: f32);
+}
+
+/// Lays out the inline-level children of container, a block container, in
+/// lines stacked from the top of content, its content box. Lines are broken
+/// where their content would overflow the room floats leave them, at spaces
+/// and around inline-blocks, and at preserved newlines. Text is measured
+/// with metrics.
+///
+/// A float is placed beside the line it's in if it fits there, and otherwise
+/// below it. A line that doesn't fit beside floats is moved down past them.
+///
+/// Lines go the way of the container's `direction`, with the start of each
+/// inline box on its right in rtl, but the text in them isn't reordered, as
+/// there's no bidi algorithm. They're aligned after the container's
+/// `text-align`, with `justify` treated as `start`.
+pub fn layout_lines(
+    tree: &BoxTree,
+    container: BoxId,
+    content: Rect,
+    metrics: &dyn TextMetrics,
+    context: &mut dyn LineContext,
+) -> Vec<LineBox> {
+    let mut items = inline_items(tree, container, content.width, metrics, |id| context.size(id));
+    // Of the container's own text, which lines are at least as tall as
+    let strut = line_height(&tree.get(container).style, metrics);
+    let mut lines: Vec<LineBox> = vec![];
+    // The inline boxes that a line starts in, outermost first
+    let mut open = vec![];
+    let mut y = content.y;
+    let mut start = 0;
+    // Floats that didn't fit beside the line they're in
+    let mut below_line = vec![];
+    let mut placeholders = vec![];
+    while start < items.len() {
+        let (left, right) = context.line_space(y, strut);
+        let width = right - left;
+        let (end, at_float) = line_end(&items, start, width);
+        let has_content = items[start..end].iter().any(Item::is_content);
+        let used = used_width(&items[start..end]);
+        if at_float {
+            let float = items.remove(end);
+            if !has_content || used + float.width <= width {
+                context.place_float(float.id, y);
+            } else {
+                below_line.push(float.id);
+            }
+            continue;
+        }
+        let narrowed = left > content.x || right < content.right();
+        if narrowed && has_content && used > width {
+            if let Some(float_end) = context.next_float_end(y) {
+                y = float_end;
+                continue;
+            }
+        }
+        if has_content || items[end - 1].kind == ItemKind::Break {
+            let line = items[start..end].to_vec();
+            let space = Rect::new(left, y, width, 0.0);
+            let line =
+                place_line(tree, container, line, space, metrics, &mut open, &mut placeholders);
+            for (id, x) in placeholders.drain(..) {
+                context.set_static_position(id, x, y);
+            }
+            y = line.rect.bottom();
+            lines.push(line);
+        } else {
+            for item in &items[start..end] {
+                if item.kind == ItemKind::Placeholder {
+                    context.set_static_position(item.id, left, y);
+                }
+            }
+        }
+        for id in below_line.drain(..) {
+            context.place_float(id, y);
+        }
+        start = end;
+    }
+    lines
+}
+
+/// Returns how wide container's inline-level content is, with lines only
+/// broken at preserved newlines, and floats taken to be on them.
+/// Percentages are taken to be 0.
+pub fn max_content_width<F>(
+    tree: &BoxTree,
+    container: BoxId,
+    metrics: &dyn TextMetrics,
+    atomic_size: F,
+) -> f32
+where
+    F: Fn(BoxId) -> (f32, f32),
+{
+    let items = inline_items(tree, container, 0.0, metrics, atomic_size);
+    items
+        .split(|item| item.kind == ItemKind::Break)
+        .map(used_width)
+        .fold(0.0, f32::max)
+}
+
+// Returns the width of container's content with a line break at every
+// opportunity: its widest piece that can't be broken, or float
+pub fn min_content_width<F>(
+    tree: &BoxTree,
+    container: BoxId,
+    metrics: &dyn TextMetrics,
+    atomic_size: F,
+) -> f32
+where
+    F: Fn(BoxId) -> (f32, f32),
+{
+    let items = inline_items(tree, container, 0.0, metrics, atomic_size);
+    let (floats, items): (Vec<Item>, Vec<Item>) =
+        items.into_iter().partition(|item| item.kind == ItemKind::Float);
+    let mut max = floats.iter().map(|float| float.width).fold(0.0, f32::max);
+    let mut start = 0;
+    for (i, item) in items.iter().enumerate() {
+        if item.break_after || item.kind == ItemKind::Break || i + 1 == items.len() {
+            max = max.max(used_width(&items[start..=i]));
+            start = i + 1;
+        }
+    }
+    max
+}
+
+// Places a line's items from left to right, or in rtl from right to left,
+// then aligns them on their baseline, with the line's top at content.y. Adds
+// the x of each placeholder to placeholders.
+fn place_line(
+    tree: &BoxTree,
+    container: BoxId,
+    items: Vec<Item>,
+    content: Rect,
+    metrics: &dyn TextMetrics,
+    open: &mut Vec<BoxId>,
+    placeholders: &mut Vec<(BoxId, f32)>,
+) -> LineBox {
+    let style = &tree.get(container).style;
+    let rtl = style.get("direction") == Some("rtl");
+    let trimmed = (0..items.len()).map(|i| is_trimmed(&items, i)).collect::<Vec<_>>();
+    let mut fragments: Vec<Fragment> = vec![];
+    // Indices of the fragments of the inline boxes that are open
+    let mut open_fragments = vec![];
+    let fragment = |box_id: BoxId, x: f32, width: f32, text: Option<String>| Fragment {
+        box_id,
+        rect: Rect::new(x, 0.0, width, 0.0),
+        text,
+    };
+    for &id in open.iter() {
+        open_fragments.push(fragments.len());
+        fragments.push(fragment(id, 0.0, 0.0, None));
+    }
+    let mut x = 0.0;
+    for (item, trimmed) in items.into_iter().zip(trimmed) {
+        let text = match item.kind {
+            _ if trimmed => continue,
+            ItemKind::Text(text) => text,
+            ItemKind::CollapsibleSpace => " ".to_string(),
+            ItemKind::Start => {
+                let typed = tree.logical_style(item.id);
+                x += start_and_end(Sides::margin(&typed, content.width), rtl).0;
+                open.push(item.id);
+                open_fragments.push(fragments.len());
+                fragments.push(fragment(item.id, x, 0.0, None));
+                x += start_and_end(Sides::padding(&typed, content.width), rtl).0;
+                continue;
+            }
+            ItemKind::End => {
+                let typed = tree.logical_style(item.id);
+                x += start_and_end(Sides::padding(&typed, content.width), rtl).1;
+                if let Some(index) = open_fragments.pop() {
+                    fragments[index].rect.width = x - fragments[index].rect.x;
+                }
+                open.pop();
+                x += start_and_end(Sides::margin(&typed, content.width), rtl).1;
+                continue;
+            }
+            ItemKind::Atomic(height) => {
+                let mut atomic = fragment(item.id, x, item.width, None);
+                atomic.rect.height = height;
+                fragments.push(atomic);
+                x += item.width;
+                continue;
+            }
+            ItemKind::Placeholder => {
+                placeholders.push((item.id, x));
+                continue;
+            }
+            ItemKind::Float | ItemKind::Break => continue,
+        };
+        match fragments.last_mut() {
+            Some(last) if last.box_id == item.id && last.text.is_some() => {
+                last.text.as_mut().unwrap().push_str(&text);
+                last.rect.width += item.width;
+            }
+            _ => fragments.push(fragment(item.id, x, item.width, Some(text))),
+        }
+        x += item.width;
+    }
+    for index in open_fragments {
+        fragments[index].rect.width = x - fragments[index].rect.x;
+    }
+    // Flipped to go from the right
+    if rtl {
+        for fragment in &mut fragments {
+            fragment.rect.x = x - fragment.rect.right();
+        }
+        for placeholder in placeholders.iter_mut() {
+            placeholder.1 = x - placeholder.1;
+        }
+    }
+
+    // Everything sits on the baseline, as does the container's own text
+    // would, which the line is at least as tall as
+    let extents = fragments
+        .iter()
+        .map(|fragment| match tree.get(fragment.box_id).kind {
+            BoxKind::InlineBlock => (fragment.rect.height, 0.0),
+            _ => text_extent(&tree.get(fragment.box_id).style, metrics),
+        })
+        .collect::<Vec<_>>();
+    let (above, below) = extents
+        .iter()
+        .fold(text_extent(style, metrics), |(above, below), &(a, b)| (above.max(a), below.max(b)));
+    let baseline = content.y + above;
+    let free = (content.width - x).max(0.0);
+    let offset = match style.get("text-align").unwrap_or("start") {
+        "left" => 0.0,
+        "right" => free,
+        "center" => free / 2.0,
+        "end" if !rtl => free,
+        "end" => 0.0,
+        _ if rtl => free,
+        _ => 0.0,
+    };
+    for placeholder in placeholders.iter_mut() {
+        placeholder.1 += content.x + offset;
+    }
+    for (fragment, (above, below)) in fragments.iter_mut().zip(extents) {
+        fragment.rect.x += content.x + offset;
+        fragment.rect.y = baseline - above;
+        fragment.rect.height = above + below;
+    }
+    LineBox {
+        rect: Rect::new(content.x, content.y, content.width, above + below),
+        baseline,
+        fragments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use layout::flow::Layout;
+    use layout::metrics::EstimatedMetrics;
+    use magicparser::{parse_stylesheet, DomNodeRef};
+    use style::media::Viewport;
+    use style::resolver::StyleResolver;
+
+    fn lay_out(root: &DomNodeRef, css: &str) -> Layout {
+        let styles = StyleResolver::new(parse_stylesheet(css).unwrap()).resolve(root);
+        Layout::new(BoxTree::new(root, &styles), Viewport::new(800.0, 600.0))
+    }
+
+    // The lines of the first box root generated, as the text on each
+    fn line_texts(layout: &Layout, root: &DomNodeRef) -> Vec<String> {
+        let id = layout.tree().boxes_of(root)[0];
+        layout
+            .geometry(id)
+            .lines
+            .iter()
+            .map(|line| {
+                let texts = line.fragments.iter().filter_map(|fragment| fragment.text.clone());
+                texts.collect::<Vec<_>>().join("|")
+            })
+            .collect()
+    }
+
+    const CSS: &str = "p { margin: 0; width: 50px; font-size: 10px; line-height: 20px }";
+
+    #[test]
+    fn test_line_height() {
+        let line_height_of = |css: &str| {
+            let root = dom!(p[style = css]);
+            let styles = StyleResolver::new(parse_stylesheet("").unwrap()).resolve(&root);
+            line_height(styles.get(&root).unwrap(), &EstimatedMetrics)
+        };
+        assert_eq!(line_height_of("font-size: 10px"), 12.0);
+        assert_eq!(line_height_of("font-size: 10px; line-height: 1.5"), 15.0);
+        assert_eq!(line_height_of("font-size: 10px; line-height: 2em"), 20.0);
+        assert_eq!(line_height_of("font-size: 10px; line-height: 150%"), 15.0);
+        assert_eq!(line_height_of("line-height: 7px"), 7.0);
+    }
+
+    #[test]
+    fn test_layout_lines() {
+        let root = dom!(p { "aaaa  bbbb " b { "cc dd" } " ee" });
+        let layout = lay_out(&root, CSS);
+        assert_eq!(line_texts(&layout, &root), vec!["aaaa bbbb", "cc dd| ee"]);
+        let p = layout.geometry(layout.tree().boxes_of(&root)[0]);
+        assert_eq!(p.content.height, 40.0);
+        let line = &p.lines[1];
+        assert_eq!(line.rect, Rect::new(0.0, 20.0, 50.0, 20.0));
+        assert_eq!(line.baseline, 33.0);
+        // The b's fragment and its text's, then those of the anonymous inline
+        // around the p's text
+        let rects = line.fragments.iter().map(|fragment| fragment.rect).collect::<Vec<_>>();
+        assert_eq!(
+            rects,
+            vec![
+                Rect::new(0.0, 20.0, 25.0, 20.0),
+                Rect::new(0.0, 20.0, 25.0, 20.0),
+                Rect::new(25.0, 20.0, 15.0, 20.0),
+                Rect::new(25.0, 20.0, 15.0, 20.0),
+            ]
+        );
+        // A word that doesn't fit on a line of its own overflows it
+        let root = dom!(p { "aaaaaaaaaaaa b" });
+        assert_eq!(line_texts(&lay_out(&root, CSS), &root), vec!["aaaaaaaaaaaa", "b"]);
+    }
+
+    #[test]
+    fn test_layout_lines_white_space() {
+        let css = format!("{} .pre {{ white-space: pre }} .nowrap {{ white-space: nowrap }}", CSS);
+        let root = dom!(p.pre { "a  b c d e f g h\n\nx " });
+        let layout = lay_out(&root, &css);
+        assert_eq!(line_texts(&layout, &root), vec!["a  b c d e f g h", "", "x "]);
+        let root = dom!(p.nowrap { "aaaa bbbb cccc" });
+        assert_eq!(line_texts(&lay_out(&root, &css), &root), vec!["aaaa bbbb cccc"]);
+        let root = dom!(p[style = "white-space: pre-line"] { "a  \n  b" });
+        assert_eq!(line_texts(&lay_out(&root, CSS), &root), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_layout_lines_inline_boxes() {
+        // Inline boxes' padding and margins push their contents along, and
+        // inline-blocks are as wide as their contents
+        let css = format!(
+            "{} span {{ padding: 0 5px; margin-left: 5px }} \
+             i {{ display: inline-block; line-height: 30px }} \
+             .c {{ text-align: center }}",
+            CSS
+        );
+        let root = dom!(p.c { "aa" span { "bb" } i { "cc" } });
+        let layout = lay_out(&root, &css);
+        let line = &layout.geometry(layout.tree().boxes_of(&root)[0]).lines[0];
+        let fragments = line
+            .fragments
+            .iter()
+            .map(|fragment| (fragment.rect.x, fragment.rect.width))
+            .collect::<Vec<_>>();
+        // 45px of content, centered, with the anonymous inline around the
+        // p's text first
+        assert_eq!(
+            fragments,
+            vec![(2.5, 10.0), (2.5, 10.0), (17.5, 20.0), (22.5, 10.0), (37.5, 10.0)]
+        );
+        assert_eq!(line.rect.height, 37.0);
+        assert_eq!(line.baseline, 30.0);
+        let i = layout.tree().boxes_of(&root.borrow().children[2])[0];
+        assert_eq!(layout.geometry(i).content, Rect::new(37.5, 0.0, 10.0, 30.0));
+        assert_eq!(layout.geometry(i).lines[0].fragments[0].rect.x, 37.5);
+        let span = layout.tree().boxes_of(&root.borrow().children[1])[0];
+        assert_eq!(layout.geometry(span).content, Rect::new(17.5, 17.0, 20.0, 20.0));
+    }
+
+    #[test]
+    fn test_layout_lines_rtl() {
+        // The span's start, with its padding, is on its right, and the line
+        // is aligned to the right of the p, which is on the viewport's right
+        let css = format!("{} span {{ padding-right: 4px; margin-left: 2px }}", CSS);
+        let root = dom!(p[style = "direction: rtl"] { "aa" span { "bb" } });
+        let layout = lay_out(&root, &css);
+        let line = &layout.geometry(layout.tree().boxes_of(&root)[0]).lines[0];
+        let fragments = line
+            .fragments
+            .iter()
+            .map(|fragment| (fragment.rect.x, fragment.rect.width))
+            .collect::<Vec<_>>();
+        assert_eq!(fragments, vec![(790.0, 10.0), (790.0, 10.0), (776.0, 14.0), (776.0, 10.0)]);
+    }
+}
@@ -0,0 +1,209 @@
+#[cfg(feature = "fonts")]
+use ab_glyph::{Font, FontArc, InvalidFont};
+use std::fmt;
+#[cfg(feature = "fonts")]
+use style::font::{FontRegistry, FontSource};
+use style::resolver::ComputedStyle;
+
+/// How far a font reaches above and below the baseline, and the gap it puts
+/// between lines, in px at a font size.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FontMetrics {
+    pub ascent: f32,
+    // Below the baseline, so positive
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+impl FontMetrics {
+    /// Returns the height of a line with a `normal` line-height.
+    pub fn normal_line_height(&self) -> f32 {
+        self.ascent + self.descent + self.line_gap
+    }
+}
+
+/// Measures text for inline layout, in the font that a style's `font-family`
+/// and `font-size` select.
+pub trait TextMetrics: fmt::Debug {
+    /// Returns how far text advances, in px.
+    fn width(&self, text: &str, style: &ComputedStyle) -> f32;
+
+    fn font_metrics(&self, style: &ComputedStyle) -> FontMetrics;
+}
+
+/// Measures text without fonts: glyphs are taken to be half an em wide, like
+/// `ch` units are, and to reach 0.8em above the baseline and 0.2em below it,
+/// with lines 1.2em apart.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EstimatedMetrics;
+
+impl TextMetrics for EstimatedMetrics {
+    fn width(&self, text: &str, style: &ComputedStyle) -> f32 {
+        text.chars().count() as f32 * style.typed().font_size / 2.0
+    }
+
+    fn font_metrics(&self, style: &ComputedStyle) -> FontMetrics {
+        let font_size = style.typed().font_size;
+        FontMetrics {
+            ascent: font_size * 0.8,
+            descent: font_size * 0.2,
+            line_gap: font_size * 0.2,
+        }
+    }
+}
+
+/// Measures text with the glyph advances, kerning and vertical metrics of
+/// font files, in the face that a FontRegistry matches a style to.
+///
+/// Faces are measured with the first of their sources that was added.
+/// Generic families without a face, and faces with no source added, are
+/// measured with the first font added, or else like EstimatedMetrics does.
+/// Synthesized bold and oblique text is measured like the face it's made
+/// from, and characters that a font doesn't have aren't looked for in
+/// others.
+#[cfg(feature = "fonts")]
+#[derive(Debug, Clone, Default)]
+pub struct Fonts {
+    registry: FontRegistry,
+    fonts: Vec<(FontSource, FontArc)>,
+}
+
+#[cfg(feature = "fonts")]
+impl Fonts {
+    pub fn new(registry: FontRegistry) -> Fonts {
+        Fonts {
+            registry,
+            fonts: vec![],
+        }
+    }
+
+    pub fn registry(&self) -> &FontRegistry {
+        &self.registry
+    }
+
+    /// Adds the font in data, the contents of a TrueType or OpenType file,
+    /// as the one that source, a source of faces in the registry, loads.
+    pub fn add(&mut self, source: FontSource, data: Vec<u8>) -> Result<(), InvalidFont> {
+        let font = FontArc::try_from_vec(data)?;
+        self.fonts.push((source, font));
+        Ok(())
+    }
+
+    // The font for style, with the px per font unit at its font size
+    fn font(&self, style: &ComputedStyle) -> Option<(&FontArc, f32)> {
+        let handle = self.registry.match_style(style);
+        let sources = handle.face.map_or(vec![], |face| face.sources);
+        let font = sources
+            .iter()
+            .find_map(|source| self.fonts.iter().find(|(added, _)| added == source))
+            .or_else(|| self.fonts.first())
+            .map(|(_, font)| font)?;
+        let units_per_em = font.units_per_em().unwrap_or(1000.0);
+        Some((font, style.typed().font_size / units_per_em))
+    }
+}
+
+#[cfg(feature = "fonts")]
+impl TextMetrics for Fonts {
+    fn width(&self, text: &str, style: &ComputedStyle) -> f32 {
+        let (font, scale) = match self.font(style) {
+            Some(font) => font,
+            None => return EstimatedMetrics.width(text, style),
+        };
+        let mut width = 0.0;
+        let mut previous = None;
+        for ch in text.chars() {
+            let glyph = font.glyph_id(ch);
+            if let Some(previous) = previous {
+                width += font.kern_unscaled(previous, glyph);
+            }
+            width += font.h_advance_unscaled(glyph);
+            previous = Some(glyph);
+        }
+        width * scale
+    }
+
+    fn font_metrics(&self, style: &ComputedStyle) -> FontMetrics {
+        match self.font(style) {
+            Some((font, scale)) => FontMetrics {
+                ascent: font.ascent_unscaled() * scale,
+                descent: -font.descent_unscaled() * scale,
+                line_gap: font.line_gap_unscaled() * scale,
+            },
+            None => EstimatedMetrics.font_metrics(style),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(font_size: &str) -> ComputedStyle {
+        let mut style = ComputedStyle::default();
+        style.set("font-size", font_size);
+        style
+    }
+
+    #[test]
+    fn test_estimated_metrics() {
+        let metrics = EstimatedMetrics.font_metrics(&style("10px"));
+        assert_eq!(metrics.ascent, 8.0);
+        assert_eq!(metrics.descent, 2.0);
+        assert_eq!(metrics.normal_line_height(), 12.0);
+        assert_eq!(EstimatedMetrics.width("abcé", &style("10px")), 20.0);
+    }
+
+    #[cfg(feature = "fonts")]
+    #[test]
+    fn test_fonts_without_fonts() {
+        let mut fonts = Fonts::new(FontRegistry::default());
+        let source = FontSource::Local("Broken".to_string());
+        assert!(fonts.add(source, b"not a font".to_vec()).is_err());
+        assert_eq!(fonts.width("abcd", &style("10px")), 20.0);
+        assert_eq!(fonts.font_metrics(&style("10px")).normal_line_height(), 12.0);
+    }
+
+    #[cfg(feature = "fonts")]
+    #[test]
+    fn test_fonts() {
+        use std::fs;
+        use style::font::{FontFace, FontStyle};
+
+        // Both have 1000 units per em, an ascent of 800, a descent of 200 and
+        // a line gap of 100. In the regular one, A is 600 units wide, V 500
+        // and a space 250, and AV is kerned by -100. In the bold one, they're
+        // 700, 600 and 300, without kerning.
+        let mut registry = FontRegistry::default();
+        for (name, weight) in &[("Test Regular", 400), ("Test Bold", 700)] {
+            registry.add_system_font(FontFace {
+                family: "Test".to_string(),
+                sources: vec![FontSource::Local(name.to_string())],
+                weight: (*weight, *weight),
+                style: FontStyle::Normal,
+                stretch: (100.0, 100.0),
+            });
+        }
+        let mut fonts = Fonts::new(registry);
+        let files = [("Test Regular", "test-regular.ttf"), ("Test Bold", "test-bold.ttf")];
+        for (name, file) in &files {
+            let data = fs::read(format!("src/layout/metrics_tests/{}", file)).unwrap();
+            fonts.add(FontSource::Local(name.to_string()), data).unwrap();
+        }
+        let mut regular = style("10px");
+        regular.set("font-family", "Missing, Test");
+        assert_eq!(fonts.width("AV", &regular), 10.0);
+        assert_eq!(fonts.width("VA A", &regular), 19.5);
+        let metrics = fonts.font_metrics(&regular);
+        assert_eq!((metrics.ascent, metrics.descent, metrics.line_gap), (8.0, 2.0, 1.0));
+
+        let mut bold = regular.clone();
+        bold.set("font-weight", "bold");
+        assert_eq!(fonts.width("AV", &bold), 13.0);
+
+        // Generic families are measured with the first font added
+        let mut generic = style("20px");
+        generic.set("font-family", "sans-serif");
+        assert_eq!(fonts.width("AV", &generic), 20.0);
+    }
+}
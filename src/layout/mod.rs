@@ -0,0 +1,10 @@
+pub mod boxtree;
+pub mod flow;
+pub mod geometry;
+pub mod hittest;
+pub mod inline;
+pub mod metrics;
+pub mod paged;
+pub mod replaced;
+pub mod scroll;
+pub mod writing;
@@ -0,0 +1,209 @@
+use layout::boxtree::{BoxId, BoxKind, BoxTree};
+use layout::flow::BoxGeometry;
+use layout::geometry::Rect;
+use layout::scroll::is_scroll_container;
+
+/// A page of a paginated layout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Page {
+    // The part of the layout that's on the page, as wide as the viewport and
+    // at most as tall, which is drawn moved up to the top of the page, and
+    // clipped to it
+    pub area: Rect,
+}
+
+// A place the layout can be broken at, between two blocks or two lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BreakPoint {
+    y: f32,
+    // By `break-before` or `break-after`
+    forced: bool,
+    // By `break-before`, `break-after` or the `break-inside` of a box it's in
+    avoided: bool,
+}
+
+/// Breaks a layout into pages the size of its viewport, the root of tree,
+/// going down from the top of it to bottom, with geometry by
+/// BoxId::index(). Pages are only broken between in-flow blocks, and
+/// between lines, of the blocks in the viewport's flow, other than scroll
+/// containers and replaced boxes, which are kept whole, and never through a
+/// float. A page is broken as far down as it can be without breaking where
+/// `break-before`, `break-after` or `break-inside` is avoid or avoid-page,
+/// unless it can only be broken there, or is sliced at its bottom if it
+/// can't be broken at all, and is always broken where `break-before` or
+/// `break-after` is always, all, page, left, right, recto or verso. A box's
+/// break-before and break-after also apply to the edges of its first and
+/// last children. Column and region breaks are ignored.
+///
+/// Pages go down in every writing mode, so in the vertical ones they break
+/// through lines rather than between them.
+pub fn paginate(tree: &BoxTree, geometry: &[BoxGeometry], bottom: f32) -> Vec<Page> {
+    let viewport = geometry[tree.root().index()].padding_box();
+    let page = |start: f32, end: f32| Page {
+        area: Rect::new(viewport.x, start, viewport.width, end - start),
+    };
+    if viewport.height <= 0.0 {
+        return vec![page(viewport.y, bottom)];
+    }
+    let mut breaks = vec![];
+    collect_breaks(tree, geometry, tree.root(), false, &mut breaks);
+    breaks.sort_by(|a, b| a.y.total_cmp(&b.y));
+    let floats = tree
+        .ids()
+        .filter(|&id| tree.get(id).kind == BoxKind::Float)
+        .map(|id| geometry[id.index()].margin_box())
+        .collect::<Vec<_>>();
+    let through_float = |y: f32| floats.iter().any(|float| float.y < y && y < float.bottom());
+    let mut pages = vec![];
+    let mut start = viewport.y;
+    loop {
+        let end = start + viewport.height;
+        let on_page = breaks
+            .iter()
+            .filter(|point| point.y > start && point.y <= end)
+            .cloned()
+            .collect::<Vec<_>>();
+        let next = match on_page.iter().find(|point| point.forced) {
+            Some(point) => point.y,
+            None if bottom <= end => break,
+            None => {
+                let allowed = |point: &&BreakPoint| !point.avoided && !through_float(point.y);
+                let whole = |point: &&BreakPoint| !through_float(point.y);
+                let mut points = on_page.iter().rev();
+                let point = points.clone().find(allowed).or_else(|| points.find(whole));
+                point.map_or(end, |point| point.y)
+            }
+        };
+        pages.push(page(start, next));
+        start = next;
+    }
+    pages.push(page(start, bottom));
+    pages
+}
+
+// Adds the places the contents of block container id can be broken at to
+// breaks, with avoid_inside true if a box it's in avoids breaks inside it
+fn collect_breaks(
+    tree: &BoxTree,
+    geometry: &[BoxGeometry],
+    id: BoxId,
+    avoid_inside: bool,
+    breaks: &mut Vec<BreakPoint>,
+) {
+    let avoid = avoid_inside || avoids(&tree.get(id).style.typed().break_inside);
+    let children = in_flow_blocks(tree, id);
+    for pair in children.windows(2) {
+        let mut values = edge_values(tree, pair[0], false);
+        values.extend(edge_values(tree, pair[1], true));
+        breaks.push(BreakPoint {
+            y: geometry[pair[1].index()].margin_box().y,
+            forced: values.iter().any(|value| forces(value)),
+            avoided: avoid || values.iter().any(|value| avoids(value)),
+        });
+    }
+    for pair in geometry[id.index()].lines.windows(2) {
+        breaks.push(BreakPoint {
+            y: pair[1].rect.y,
+            forced: false,
+            avoided: avoid,
+        });
+    }
+    for child in children {
+        if !is_monolithic(tree, child) {
+            collect_breaks(tree, geometry, child, avoid, breaks);
+        }
+    }
+}
+
+// The break-before values, if first, or else the break-after ones, of box
+// id and of its first or last child, and so on down, which all apply at
+// its edge
+fn edge_values(tree: &BoxTree, id: BoxId, first: bool) -> Vec<String> {
+    let typed = tree.get(id).style.typed();
+    let mut values = vec![if first { typed.break_before } else { typed.break_after }];
+    if is_monolithic(tree, id) {
+        return values;
+    }
+    let children = in_flow_blocks(tree, id);
+    let child = if first { children.first() } else { children.last() };
+    if let Some(&child) = child {
+        values.extend(edge_values(tree, child, first));
+    }
+    values
+}
+
+fn in_flow_blocks(tree: &BoxTree, id: BoxId) -> Vec<BoxId> {
+    let children = tree.get(id).children.iter().cloned();
+    children
+        .filter(|&child| matches!(tree.get(child).kind, BoxKind::Block | BoxKind::AnonymousBlock))
+        .collect()
+}
+
+// True for the boxes that are kept whole on a page
+fn is_monolithic(tree: &BoxTree, id: BoxId) -> bool {
+    let layout_box = tree.get(id);
+    is_scroll_container(layout_box) || layout_box.replaced.is_some()
+}
+
+fn forces(value: &str) -> bool {
+    matches!(value, "always" | "all" | "page" | "left" | "right" | "recto" | "verso")
+}
+
+fn avoids(value: &str) -> bool {
+    matches!(value, "avoid" | "avoid-page")
+}
+
+#[cfg(test)]
+mod tests {
+    use layout::boxtree::BoxTree;
+    use layout::flow::Layout;
+    use layout::geometry::Rect;
+    use magicparser::{parse_stylesheet, DomNodeRef};
+    use style::media::Viewport;
+    use style::resolver::StyleResolver;
+
+    fn page_areas(root: &DomNodeRef, viewport: Viewport) -> Vec<Rect> {
+        let styles = StyleResolver::new(parse_stylesheet("p { margin: 0 }").unwrap()).resolve(root);
+        let layout = Layout::new(BoxTree::new(root, &styles), viewport);
+        layout.pages().iter().map(|page| page.area).collect()
+    }
+
+    #[test]
+    fn test_paginate() {
+        // Broken as far down as it can be, but not after the second p, nor
+        // in the div, and always before the section
+        let root = dom!(div {
+            p[style = "height: 100px"]
+            p[style = "height: 100px; break-after: avoid"]
+            p[style = "height: 100px"]
+            section[style = "break-before: page"] {
+                p[style = "height: 40px"]
+                p[style = "height: 40px"]
+            }
+            div[style = "break-inside: avoid"] {
+                p[style = "height: 100px"]
+                p[style = "height: 100px"]
+            }
+        });
+        assert_eq!(
+            page_areas(&root, Viewport::new(800.0, 250.0)),
+            vec![
+                Rect::new(0.0, 0.0, 800.0, 100.0),
+                Rect::new(0.0, 100.0, 800.0, 200.0),
+                Rect::new(0.0, 300.0, 800.0, 80.0),
+                Rect::new(0.0, 380.0, 800.0, 200.0),
+            ]
+        );
+
+        // Between lines, of a word each
+        let root = dom!(p[style = "width: 10px; line-height: 20px"] { "a b c d e" });
+        assert_eq!(
+            page_areas(&root, Viewport::new(800.0, 50.0)),
+            vec![
+                Rect::new(0.0, 0.0, 800.0, 40.0),
+                Rect::new(0.0, 40.0, 800.0, 40.0),
+                Rect::new(0.0, 80.0, 800.0, 20.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use magicparser::{DomNodeRef, ElemType};
+
+// The elements whose content is an external resource rather than their
+// children, besides img
+const REPLACED_TAGS: &[&str] = &["video", "canvas", "iframe", "embed", "object"];
+
+/// The size a replaced element's content has of its own, like the pixel size
+/// of an image, in px. Any part of it can be missing, e.g. a vector image
+/// can have only an aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct IntrinsicSize {
+    pub width: Option<f32>,
+    pub height: Option<f32>,
+    // Width divided by height
+    pub aspect_ratio: Option<f32>,
+}
+
+impl IntrinsicSize {
+    /// Returns a size of width by height, with their ratio, unless either is
+    /// 0.
+    pub fn new(width: f32, height: f32) -> IntrinsicSize {
+        IntrinsicSize {
+            width: Some(width),
+            height: Some(height),
+            aspect_ratio: Some(width / height).filter(|_| width > 0.0 && height > 0.0),
+        }
+    }
+}
+
+/// Returns true for the elements that generate replaced boxes: img, video,
+/// canvas, iframe, embed and object.
+pub fn is_replaced(dom_node: &DomNodeRef) -> bool {
+    match dom_node.borrow().elem_type {
+        ElemType::Img => true,
+        ElemType::Custom(ref name) => REPLACED_TAGS.contains(&&**name),
+        _ => false,
+    }
+}
+
+/// Returns the intrinsic size of a replaced element: its `width` and
+/// `height` attributes if it has both, else the size loader returns for its
+/// `src`, or `data` for an object, with a width or height attribute in place
+/// of that part of it, and the other part scaled to match. loader returns
+/// None for resources it can't load.
+pub fn intrinsic_size<F>(dom_node: &DomNodeRef, loader: &mut F) -> IntrinsicSize
+where
+    F: FnMut(&str) -> Option<IntrinsicSize>,
+{
+    let attr = |name: &str| dom_node.borrow().attr(name).cloned().and_then(|value| value);
+    let dimension = |name: &str| {
+        attr(name)?.trim().trim_end_matches("px").parse::<f32>().ok().filter(|&px| px >= 0.0)
+    };
+    match (dimension("width"), dimension("height")) {
+        (Some(width), Some(height)) => IntrinsicSize::new(width, height),
+        (width, height) => {
+            let loaded = attr("src").or_else(|| attr("data")).and_then(|url| loader(&url));
+            let loaded = loaded.unwrap_or_default();
+            // An attribute for one scales the other by the ratio
+            let scaled = (width.is_some() || height.is_some()) && loaded.aspect_ratio.is_some();
+            IntrinsicSize {
+                width: width.or(loaded.width.filter(|_| !scaled)),
+                height: height.or(loaded.height.filter(|_| !scaled)),
+                aspect_ratio: loaded.aspect_ratio,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intrinsic_size() {
+        let mut loader =
+            |url: &str| Some(IntrinsicSize::new(200.0, 100.0)).filter(|_| url == "a.png");
+        let img = dom!(img[src = "a.png"]);
+        assert!(is_replaced(&img));
+        assert_eq!(intrinsic_size(&img, &mut loader), IntrinsicSize::new(200.0, 100.0));
+        let img = dom!(img[src = "a.png"][width = "50"]);
+        assert_eq!(
+            intrinsic_size(&img, &mut loader),
+            IntrinsicSize {
+                width: Some(50.0),
+                height: None,
+                aspect_ratio: Some(2.0),
+            }
+        );
+        let img = dom!(img[src = "a.png"][width = "40"][height = "30px"]);
+        assert_eq!(intrinsic_size(&img, &mut loader), IntrinsicSize::new(40.0, 30.0));
+        let object = dom!(object[data = "b.svg"]);
+        assert!(is_replaced(&object));
+        assert_eq!(intrinsic_size(&object, &mut loader), IntrinsicSize::default());
+        assert!(!is_replaced(&dom!(div)));
+    }
+}
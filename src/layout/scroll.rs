@@ -0,0 +1,184 @@
+use layout::boxtree::{BoxId, BoxKind, BoxTree, LayoutBox};
+use layout::flow::{is_positioned, BoxGeometry};
+use layout::geometry::Rect;
+use style::logical::physical_property;
+
+/// The part of a scroll container's contents that can be scrolled into view
+/// in its padding box, and how far they're scrolled.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ScrollArea {
+    // The padding box, which the contents are clipped to and scrolled in
+    pub scrollport: Rect,
+    // The scrollport grown to take in the contents that overflow it, where
+    // they were laid out
+    pub overflow: Rect,
+    // Like scrollLeft and scrollTop: a box at (x, y) in the layout is drawn
+    // at (x - offset.0, y - offset.1)
+    pub offset: (f32, f32),
+}
+
+impl ScrollArea {
+    /// Returns the smallest offsets, which are negative where the contents
+    /// overflow to the left, in rtl or vertical-rl, or up, in vertical rtl.
+    pub fn min_offset(&self) -> (f32, f32) {
+        (self.overflow.x - self.scrollport.x, self.overflow.y - self.scrollport.y)
+    }
+
+    pub fn max_offset(&self) -> (f32, f32) {
+        (
+            self.overflow.right() - self.scrollport.right(),
+            self.overflow.bottom() - self.scrollport.bottom(),
+        )
+    }
+
+    /// Scrolls as near to (x, y) as the overflow allows, and returns the new
+    /// offset.
+    pub fn scroll_to(&mut self, x: f32, y: f32) -> (f32, f32) {
+        let (min, max) = (self.min_offset(), self.max_offset());
+        self.offset = (x.min(max.0).max(min.0), y.min(max.1).max(min.1));
+        self.offset
+    }
+}
+
+/// Returns true for the viewport, and for block containers with `overflow`
+/// hidden, scroll or auto, which clip their contents to their padding box.
+/// Only those with scroll or auto are meant to be scrollable by the user, but
+/// any can be scrolled by script.
+pub fn is_scroll_container(layout_box: &LayoutBox) -> bool {
+    let scrolls = |overflow: &str| matches!(overflow, "hidden" | "scroll" | "auto");
+    match layout_box.kind {
+        BoxKind::Viewport => true,
+        BoxKind::Block | BoxKind::InlineBlock | BoxKind::Float | BoxKind::Absolute => {
+            let typed = layout_box.style.typed();
+            scrolls(&typed.overflow_x) || scrolls(&typed.overflow_y)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the scroll areas of the scroll containers in tree, by
+/// BoxId::index(), scrolled to the start, and None for other boxes. A scroll
+/// container's contents are the boxes and text whose containing blocks lead
+/// up to it without going through another scroll container, other than
+/// fixed positioned boxes. What overflows its block-start or inline-start
+/// side, in the tree's writing mode and its `direction`, such as its top or
+/// left in horizontal left-to-right text, can't be scrolled to.
+pub fn scroll_areas(tree: &BoxTree, geometry: &[BoxGeometry]) -> Vec<Option<ScrollArea>> {
+    let is_container = tree.ids().map(|id| is_scroll_container(tree.get(id))).collect::<Vec<_>>();
+    let mut overflow = tree
+        .ids()
+        .map(|id| Some(geometry[id.index()].padding_box()).filter(|_| is_container[id.index()]))
+        .collect::<Vec<_>>();
+    let mut add = |id: BoxId, rect: Rect| {
+        let container = match scroll_container_of(tree, &is_container, id) {
+            Some(container) => container,
+            None => return,
+        };
+        if let Some(ref mut overflow) = overflow[container.index()] {
+            *overflow = overflow.union(&rect);
+        }
+    };
+    for id in tree.ids() {
+        let geometry = &geometry[id.index()];
+        for line in &geometry.lines {
+            for fragment in &line.fragments {
+                add(fragment.box_id, fragment.rect);
+            }
+        }
+        match tree.get(id).kind {
+            // Their fragments are on their container's lines
+            BoxKind::Inline | BoxKind::Text(_) | BoxKind::AnonymousInline => {}
+            _ => add(id, geometry.padding_box()),
+        }
+    }
+    tree.ids()
+        .zip(overflow)
+        .map(|(id, overflow)| {
+            let overflow = overflow?;
+            let scrollport = geometry[id.index()].padding_box();
+            let writing_mode = tree.writing_mode().name();
+            let direction = tree.get(id).style.get("direction").unwrap_or("ltr");
+            let clipped = ["inset-block-start", "inset-inline-start"]
+                .iter()
+                .filter_map(|side| physical_property(side, writing_mode, direction))
+                .collect::<Vec<_>>();
+            let clip = |side: &str, overflow: f32, scrollport: f32, inner: fn(f32, f32) -> f32| {
+                if clipped.contains(&side) {
+                    inner(overflow, scrollport)
+                } else {
+                    overflow
+                }
+            };
+            let top = clip("top", overflow.y, scrollport.y, f32::max);
+            let right = clip("right", overflow.right(), scrollport.right(), f32::min);
+            let bottom = clip("bottom", overflow.bottom(), scrollport.bottom(), f32::min);
+            let left = clip("left", overflow.x, scrollport.x, f32::max);
+            Some(ScrollArea {
+                scrollport,
+                overflow: Rect::new(left, top, right - left, bottom - top),
+                offset: (0.0, 0.0),
+            })
+        })
+        .collect()
+}
+
+/// Returns the nearest scroll container that box id is scrolled in, going up
+/// containing blocks, with is_container true for the scroll containers by
+/// BoxId::index(), or None for the viewport and fixed boxes.
+pub fn scroll_container_of(tree: &BoxTree, is_container: &[bool], id: BoxId) -> Option<BoxId> {
+    let mut id = id;
+    loop {
+        let layout_box = tree.get(id);
+        let mut parent = layout_box.parent?;
+        if layout_box.kind == BoxKind::Absolute {
+            if layout_box.style.get("position") == Some("fixed") {
+                return None;
+            }
+            while parent != tree.root() && !is_positioned(&tree.get(parent).style) {
+                parent = tree.get(parent).parent?;
+            }
+        }
+        if is_container[parent.index()] {
+            return Some(parent);
+        }
+        id = parent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use layout::boxtree::BoxTree;
+    use layout::flow::Layout;
+    use layout::geometry::Rect;
+    use magicparser::parse_stylesheet;
+    use style::media::Viewport;
+    use style::resolver::StyleResolver;
+
+    #[test]
+    fn test_scroll_area() {
+        let style = "width: 100px; height: 50px; padding: 5px; overflow: auto";
+        let root = dom!(div[style = style] {
+            p[style = "width: 200px; height: 100px"]
+            b[style = "position: absolute; top: 700px"]
+            div[style = "height: 10px; overflow: hidden"] { p[style = "height: 300px"] }
+        });
+        let css = parse_stylesheet("p { margin: 0 }").unwrap();
+        let styles = StyleResolver::new(css).resolve(&root);
+        let mut layout = Layout::new(BoxTree::new(&root, &styles), Viewport::new(800.0, 600.0));
+        let div = layout.tree().boxes_of(&root)[0];
+
+        // The absolutely positioned b is in the viewport's scroll area, and
+        // the inner div's p is only in the inner div's
+        let scroll_area = *layout.scroll_area(div).unwrap();
+        assert_eq!(scroll_area.scrollport, Rect::new(0.0, 0.0, 110.0, 60.0));
+        assert_eq!(scroll_area.overflow, Rect::new(0.0, 0.0, 205.0, 115.0));
+        assert_eq!(scroll_area.max_offset(), (95.0, 55.0));
+        assert_eq!(layout.scroll_to(div, 1000.0, -5.0), Some((95.0, 0.0)));
+        assert_eq!(layout.scroll_area(div).unwrap().offset, (95.0, 0.0));
+        let viewport = layout.tree().root();
+        assert_eq!(layout.scroll_area(viewport).unwrap().max_offset(), (0.0, 100.0));
+        let p = layout.tree().get(div).children[0];
+        assert!(layout.scroll_area(p).is_none());
+        assert_eq!(layout.scroll_to(p, 0.0, 10.0), None);
+    }
+}
@@ -0,0 +1,139 @@
+use layout::geometry::{Rect, Sides};
+use style::logical::physical_property;
+use style::typed::TypedStyle;
+
+/// The way lines and blocks go, from `writing-mode`. Layout works in the
+/// coordinates of horizontal-tb, with lines going across from the left and
+/// blocks stacked down from the top, and the vertical modes are mapped to
+/// them: their lines go down from the top, which is their line-left, and
+/// their blocks are stacked from the right in vertical-rl, and from the left
+/// in vertical-lr. The sideways modes are laid out as the vertical ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WritingMode {
+    #[default]
+    HorizontalTb,
+    VerticalRl,
+    VerticalLr,
+}
+
+impl<'a> From<&'a str> for WritingMode {
+    fn from(value: &'a str) -> Self {
+        match value {
+            "vertical-rl" | "sideways-rl" => WritingMode::VerticalRl,
+            "vertical-lr" | "sideways-lr" => WritingMode::VerticalLr,
+            _ => WritingMode::HorizontalTb,
+        }
+    }
+}
+
+impl WritingMode {
+    pub fn is_vertical(self) -> bool {
+        self != WritingMode::HorizontalTb
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WritingMode::HorizontalTb => "horizontal-tb",
+            WritingMode::VerticalRl => "vertical-rl",
+            WritingMode::VerticalLr => "vertical-lr",
+        }
+    }
+
+    // The physical sides that layout's top, right, bottom and left are on,
+    // which are where `inset-block-start`, `inset-inline-end`,
+    // `inset-block-end` and `inset-inline-start` go in left-to-right text
+    fn sides(self) -> [&'static str; 4] {
+        let side = |logical: &str| physical_property(logical, self.name(), "ltr").unwrap();
+        [
+            side("inset-block-start"),
+            side("inset-inline-end"),
+            side("inset-block-end"),
+            side("inset-inline-start"),
+        ]
+    }
+
+    /// Returns style as layout sees it: with its insets, margins and padding
+    /// moved from the physical sides they're on to layout's, and its widths
+    /// and heights, and overflows, swapped in the vertical modes.
+    pub fn logical_style(self, style: &TypedStyle) -> TypedStyle {
+        let mut logical = style.clone();
+        if !self.is_vertical() {
+            return logical;
+        }
+        let [top, right, bottom, left] = self.sides();
+        let inset = |side: &str| pick(side, [style.top, style.right, style.bottom, style.left]);
+        logical.top = inset(top);
+        logical.right = inset(right);
+        logical.bottom = inset(bottom);
+        logical.left = inset(left);
+        let margins =
+            [style.margin_top, style.margin_right, style.margin_bottom, style.margin_left];
+        let margin = |side: &str| pick(side, margins);
+        logical.margin_top = margin(top);
+        logical.margin_right = margin(right);
+        logical.margin_bottom = margin(bottom);
+        logical.margin_left = margin(left);
+        let paddings =
+            [style.padding_top, style.padding_right, style.padding_bottom, style.padding_left];
+        let padding = |side: &str| pick(side, paddings);
+        logical.padding_top = padding(top);
+        logical.padding_right = padding(right);
+        logical.padding_bottom = padding(bottom);
+        logical.padding_left = padding(left);
+        logical.width = style.height;
+        logical.height = style.width;
+        logical.min_width = style.min_height;
+        logical.min_height = style.min_width;
+        logical.max_width = style.max_height;
+        logical.max_height = style.max_width;
+        logical.overflow_x = style.overflow_y.clone();
+        logical.overflow_y = style.overflow_x.clone();
+        logical
+    }
+
+    /// Maps rect from layout's coordinates to the viewport's, which is width
+    /// wide.
+    pub fn physical_rect(self, rect: Rect, width: f32) -> Rect {
+        match self {
+            WritingMode::HorizontalTb => rect,
+            WritingMode::VerticalRl => {
+                Rect::new(width - rect.bottom(), rect.x, rect.height, rect.width)
+            }
+            WritingMode::VerticalLr => Rect::new(rect.y, rect.x, rect.height, rect.width),
+        }
+    }
+
+    /// Maps sides from layout's to the physical ones.
+    pub fn physical_sides(self, sides: Sides) -> Sides {
+        let mut physical = Sides::default();
+        let values = [sides.top, sides.right, sides.bottom, sides.left];
+        for (&side, &value) in self.sides().iter().zip(&values) {
+            match side {
+                "top" => physical.top = value,
+                "right" => physical.right = value,
+                "bottom" => physical.bottom = value,
+                _ => physical.left = value,
+            }
+        }
+        physical
+    }
+
+    /// Maps the y of a baseline in layout's coordinates to the x it's at in
+    /// the vertical modes, in a viewport width wide.
+    pub fn physical_baseline(self, y: f32, width: f32) -> f32 {
+        match self {
+            WritingMode::HorizontalTb | WritingMode::VerticalLr => y,
+            WritingMode::VerticalRl => width - y,
+        }
+    }
+}
+
+// The one of values, for the top, right, bottom and left, that's for side
+fn pick<T: Copy>(side: &str, values: [T; 4]) -> T {
+    match side {
+        "top" => values[0],
+        "right" => values[1],
+        "bottom" => values[2],
+        _ => values[3],
+    }
+}
@@ -1,9 +1,24 @@
 #![allow(unknown_lints)]
 #![warn(clippy)]
 
+// Only public for the benchmarks
+#[doc(hidden)]
+pub mod generator;
+#[macro_use]
 pub mod magicparser;
+pub mod layout;
 pub mod style;
 
+#[cfg(feature = "fonts")]
+extern crate ab_glyph;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+extern crate smallvec;
+
 #[cfg(test)]
 #[macro_use]
 extern crate maplit;
@@ -0,0 +1,279 @@
+use magicparser::{event, DomNode};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::fmt;
+use std::mem;
+use std::ptr;
+
+// Slots are allocated in chunks that are never freed or moved, so a slot can
+// be borrowed from for as long as the program runs
+const CHUNK_SIZE: usize = 256;
+
+thread_local! {
+    // Leaked, like its chunks, so that nodes can refer to it without going
+    // through the thread-local, which may already be gone when they're dropped
+    static ARENA: &'static Arena = Box::leak(Box::new(Arena::default()));
+}
+
+/// Identifies a DOM node without keeping it alive, e.g. for parent links.
+/// Once the node is freed, its NodeId no longer resolves, even if its slot is
+/// reused for another node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+#[derive(Default)]
+struct Slot {
+    // Bumped each time the slot is freed, to tell its nodes' NodeIds apart
+    generation: Cell<u32>,
+    // How many DomNodeRefs there are to the node, e.g. in its parent's
+    // children. It's freed when this drops to 0.
+    refs: Cell<usize>,
+    // None while the slot is free
+    node: RefCell<Option<DomNode>>,
+}
+
+// Stores the DOM nodes created by a thread, in order of creation, in chunks
+// of slots. Freed slots are reused, but chunks are never freed, so an arena
+// takes as much memory as the most nodes its thread has had alive at once.
+#[derive(Default)]
+struct Arena {
+    chunks: RefCell<Vec<&'static [Slot]>>,
+    // Slots in use or freed so far
+    len: Cell<usize>,
+    free: RefCell<Vec<usize>>,
+    // Children of freed nodes, waiting to be released by the outermost
+    // release() call
+    orphans: RefCell<Vec<DomNodeRef>>,
+    releasing: Cell<bool>,
+}
+
+impl Arena {
+    fn current() -> &'static Arena {
+        ARENA.with(|arena| *arena)
+    }
+
+    fn slot(&self, index: usize) -> &'static Slot {
+        let chunks: &[&'static [Slot]] = &self.chunks.borrow();
+        &chunks[index / CHUNK_SIZE][index % CHUNK_SIZE]
+    }
+
+    fn insert(&'static self, node: DomNode) -> DomNodeRef {
+        let free = self.free.borrow_mut().pop();
+        let index = free.unwrap_or_else(|| {
+            let index = self.len.get();
+            if index.is_multiple_of(CHUNK_SIZE) {
+                let chunk = (0..CHUNK_SIZE).map(|_| Slot::default()).collect::<Vec<_>>();
+                self.chunks.borrow_mut().push(Box::leak(chunk.into_boxed_slice()));
+            }
+            self.len.set(index + 1);
+            index
+        });
+        let slot = self.slot(index);
+        *slot.node.borrow_mut() = Some(node);
+        slot.refs.set(1);
+        DomNodeRef { arena: self, index }
+    }
+
+    fn get(&'static self, id: NodeId) -> Option<DomNodeRef> {
+        if id.index >= self.len.get() {
+            return None;
+        }
+        let slot = self.slot(id.index);
+        if slot.generation.get() != id.generation || slot.refs.get() == 0 {
+            return None;
+        }
+        slot.refs.set(slot.refs.get() + 1);
+        Some(DomNodeRef { arena: self, index: id.index })
+    }
+
+    fn release(&self, index: usize) {
+        let slot = self.slot(index);
+        slot.refs.set(slot.refs.get() - 1);
+        if slot.refs.get() > 0 {
+            return;
+        }
+        let id = NodeId {
+            index,
+            generation: slot.generation.get(),
+        };
+        let node = slot.node.borrow_mut().take();
+        event::remove_listeners(id);
+        slot.generation.set(slot.generation.get().wrapping_add(1));
+        self.free.borrow_mut().push(index);
+        // The children are released in a loop rather than by recursing, so
+        // that freeing a deep tree doesn't overflow the stack
+        if let Some(mut node) = node {
+            self.orphans.borrow_mut().extend(mem::take(&mut node.children));
+        }
+        if self.releasing.replace(true) {
+            return;
+        }
+        loop {
+            // Not popped in the while condition, which would keep orphans
+            // borrowed while the orphan is dropped
+            let orphan = self.orphans.borrow_mut().pop();
+            match orphan {
+                Some(orphan) => drop(orphan),
+                None => break,
+            }
+        }
+        self.releasing.set(false);
+    }
+}
+
+/// A reference-counted handle to a DOM node, which lives in its thread's
+/// arena until the last handle to it is dropped. Parents hold handles to their
+/// children, while children only hold their parent's NodeId.
+pub struct DomNodeRef {
+    arena: &'static Arena,
+    index: usize,
+}
+
+impl DomNodeRef {
+    /// Returns the node for id, or None if it's been freed.
+    pub fn from_node_id(id: NodeId) -> Option<DomNodeRef> {
+        Arena::current().get(id)
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        NodeId {
+            index: self.index,
+            generation: self.slot().generation.get(),
+        }
+    }
+
+    pub fn borrow(&self) -> Ref<'_, DomNode> {
+        Ref::map(self.slot().node.borrow(), |node| node.as_ref().unwrap())
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, DomNode> {
+        RefMut::map(self.slot().node.borrow_mut(), |node| node.as_mut().unwrap())
+    }
+
+    /// Returns true if both refer to the same node.
+    pub fn ptr_eq(&self, other: &DomNodeRef) -> bool {
+        ptr::eq(self.arena, other.arena) && self.index == other.index
+    }
+
+    fn slot(&self) -> &'static Slot {
+        self.arena.slot(self.index)
+    }
+}
+
+impl DomNode {
+    /// Moves the node into the current thread's arena.
+    pub fn to_dnref(self) -> DomNodeRef {
+        Arena::current().insert(self)
+    }
+}
+
+impl Clone for DomNodeRef {
+    fn clone(&self) -> DomNodeRef {
+        let slot = self.slot();
+        slot.refs.set(slot.refs.get() + 1);
+        DomNodeRef { arena: self.arena, index: self.index }
+    }
+}
+
+impl Drop for DomNodeRef {
+    fn drop(&mut self) {
+        self.arena.release(self.index);
+    }
+}
+
+// Compares the nodes, not their identities; see ptr_eq()
+impl PartialEq for DomNodeRef {
+    fn eq(&self, other: &DomNodeRef) -> bool {
+        *self.borrow() == *other.borrow()
+    }
+}
+
+impl Eq for DomNodeRef {}
+
+impl fmt::Debug for DomNodeRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("DomNodeRef");
+        match self.slot().node.try_borrow() {
+            Ok(node) => debug.field("node", node.as_ref().unwrap()),
+            Err(_) => debug.field("node", &format_args!("<borrowed>")),
+        };
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generator::{generate_dom, GeneratorConfig};
+    use magicparser::{parse_html, ElemType};
+
+    fn div() -> DomNodeRef {
+        DomNode::new(ElemType::Div, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref()
+    }
+
+    #[test]
+    fn test_arena() {
+        let root = parse_html("<html><body><p>One</p></body></html>").unwrap();
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        assert!(p.parent().unwrap().ptr_eq(&body));
+        assert!(DomNodeRef::from_node_id(root.node_id()).unwrap().ptr_eq(&root));
+
+        // Dropping the last handle to a node frees it, but not children that
+        // are still referred to
+        let id = root.node_id();
+        drop(root);
+        assert_eq!(DomNodeRef::from_node_id(id), None);
+        assert!(body.parent().is_none());
+        assert_eq!(p.borrow().children.len(), 1);
+
+        // A reused slot doesn't resolve to the freed node's NodeId
+        let body_id = body.node_id();
+        drop(body);
+        let reused = (0..CHUNK_SIZE).map(|_| div()).collect::<Vec<_>>();
+        assert!(reused.iter().any(|node| node.node_id().index == body_id.index));
+        assert_eq!(DomNodeRef::from_node_id(body_id), None);
+        assert!(p.parent().is_none());
+
+        let parent = div();
+        parent.add_child(p.clone());
+        let clone = p.clone();
+        assert!(clone.ptr_eq(&p) && !clone.ptr_eq(&parent));
+        assert!(p.parent().unwrap().ptr_eq(&parent));
+    }
+
+    #[test]
+    fn test_dropped_trees_are_freed() {
+        let config = GeneratorConfig {
+            elements: 2000,
+            ..GeneratorConfig::default()
+        };
+        let root = generate_dom(&config);
+        let ids = root.descendants().map(|node| node.node_id()).collect::<Vec<_>>();
+        drop(root);
+        assert!(ids.iter().all(|&id| DomNodeRef::from_node_id(id).is_none()));
+
+        // Later trees reuse the freed slots, so the arena doesn't grow
+        let len = Arena::current().len.get();
+        for _ in 0..3 {
+            drop(generate_dom(&config));
+        }
+        assert_eq!(Arena::current().len.get(), len);
+
+        // Deep enough that freeing it recursively would overflow the stack
+        let root = div();
+        let mut node = root.clone();
+        for _ in 0..200_000 {
+            let child = div();
+            node.add_child(child.clone());
+            node = child;
+        }
+        let leaf = node.node_id();
+        drop(node);
+        drop(root);
+        assert_eq!(DomNodeRef::from_node_id(leaf), None);
+        assert!(Arena::current().orphans.borrow().is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::ptr;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+// The strings of the atoms that exist, by the hash of the string. The table
+// only holds weak references, so a string is freed along with its last atom,
+// which removes it. Entries whose atoms were dropped on several threads at
+// once can be left behind, and are removed when their bucket is next used.
+static ATOMS: OnceLock<Mutex<HashMap<u64, Vec<Weak<str>>>>> = OnceLock::new();
+
+fn atoms() -> &'static Mutex<HashMap<u64, Vec<Weak<str>>>> {
+    ATOMS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn string_hash(string: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    string.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Finds the atom for string in its bucket, removing the dead entries
+fn find(bucket: &mut Vec<Weak<str>>, string: &str) -> Option<Atom> {
+    bucket.retain(|atom| atom.strong_count() > 0);
+    bucket.iter().filter_map(Weak::upgrade).find(|atom| &**atom == string).map(Atom)
+}
+
+/// An interned string, for names like tag names, ids, classes and attribute
+/// names that are compared and hashed much more often than they're created.
+///
+/// Atoms with the same string share it, so comparing and hashing them only
+/// looks at its address. They're ordered by their strings, though.
+#[derive(Clone)]
+pub struct Atom(Arc<str>);
+
+impl Atom {
+    pub fn new(string: &str) -> Atom {
+        let mut atoms = atoms().lock().unwrap();
+        let bucket = atoms.entry(string_hash(string)).or_default();
+        if let Some(atom) = find(bucket, string) {
+            return atom;
+        }
+        let atom: Arc<str> = Arc::from(string);
+        bucket.push(Arc::downgrade(&atom));
+        Atom(atom)
+    }
+
+    /// Returns the atom for string if there is one, without interning it.
+    /// Useful for lookups, since no atom means nothing can be named string.
+    pub fn lookup(string: &str) -> Option<Atom> {
+        let mut atoms = atoms().lock().unwrap();
+        find(atoms.get_mut(&string_hash(string))?, string)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn to_ascii_lowercase(&self) -> Atom {
+        if self.0.bytes().any(|byte| byte.is_ascii_uppercase()) {
+            Atom::new(&self.0.to_ascii_lowercase())
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Like to_ascii_lowercase(), but returns None rather than interning the
+    /// lowercased string, like lookup().
+    pub fn lookup_ascii_lowercase(&self) -> Option<Atom> {
+        if self.0.bytes().any(|byte| byte.is_ascii_uppercase()) {
+            Atom::lookup(&self.0.to_ascii_lowercase())
+        } else {
+            Some(self.clone())
+        }
+    }
+}
+
+impl Drop for Atom {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.0) > 1 {
+            return;
+        }
+        // Atoms are only made from the table's entries while it's locked, so
+        // if this is still the last one then, it stays the last
+        let mut atoms = atoms().lock().unwrap();
+        if Arc::strong_count(&self.0) > 1 {
+            return;
+        }
+        let hash = string_hash(&self.0);
+        if let Some(bucket) = atoms.get_mut(&hash) {
+            bucket.retain(|atom| !ptr::eq(atom.as_ptr(), Arc::as_ptr(&self.0)));
+            if bucket.is_empty() {
+                atoms.remove(&hash);
+            }
+        }
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Atom {}
+
+impl Hash for Atom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const u8 as usize).hash(state)
+    }
+}
+
+impl PartialOrd for Atom {
+    fn partial_cmp(&self, other: &Atom) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Atom {
+    fn cmp(&self, other: &Atom) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Deref for Atom {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Atom {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl From<&str> for Atom {
+    fn from(string: &str) -> Atom {
+        Atom::new(string)
+    }
+}
+
+impl From<&String> for Atom {
+    fn from(string: &String) -> Atom {
+        Atom::new(string)
+    }
+}
+
+impl From<String> for Atom {
+    fn from(string: String) -> Atom {
+        Atom::new(&string)
+    }
+}
+
+impl<'a> From<Cow<'a, str>> for Atom {
+    fn from(string: Cow<'a, str>) -> Atom {
+        Atom::new(&string)
+    }
+}
+
+impl PartialEq<str> for Atom {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Atom {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Atom {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_atom() {
+        let a = Atom::from("menu");
+        let b = Atom::from("menu".to_string());
+        assert_eq!(a, b);
+        assert!(ptr::eq(a.as_str(), b.as_str()));
+        assert_ne!(a, Atom::from("Menu"));
+        assert_eq!(Atom::from("Menu").to_ascii_lowercase(), a);
+        assert_eq!(a, "menu");
+        assert_eq!(format!("{} {:?}", a, a), "menu \"menu\"");
+        let mut sorted = vec![Atom::from("b"), Atom::from("a")];
+        sorted.sort();
+        assert_eq!(sorted, vec![Atom::from("a"), Atom::from("b")]);
+
+        let mut atoms = HashSet::new();
+        atoms.insert(a);
+        assert!(atoms.contains(&b));
+        assert_eq!(Atom::lookup("menu"), Some(b));
+        assert_eq!(Atom::lookup("never-interned-atom"), None);
+        assert_eq!(Atom::from("MENU").lookup_ascii_lowercase(), Atom::lookup("menu"));
+        assert_eq!(Atom::from("Never-Lowercased-Atom").lookup_ascii_lowercase(), None);
+
+        // Strings are freed along with their atoms
+        let dropped = Atom::from("dropped-atom");
+        let clone = dropped.clone();
+        drop(dropped);
+        assert_eq!(Atom::lookup("dropped-atom"), Some(clone.clone()));
+        drop(clone);
+        assert_eq!(Atom::lookup("dropped-atom"), None);
+    }
+}
@@ -1,16 +1,42 @@
 use magicparser::error::{Error, Result};
 use magicparser::lexer::Lexer;
+use magicparser::mediaquery::ContainerQuery;
 use magicparser::parser::Parser;
-use magicparser::selectorparser::{Selector, SelectorParser};
-use magicparser::Pos;
+use magicparser::selectorparser::{Namespaces, Selector, SelectorParser};
+use magicparser::{Pos, Token as ParserToken};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 type DeclBlock = Vec<(Token, Token)>;
-type IntermediateBlock = (Token, DeclBlock);
-type Block = (Selector, DeclBlock);
+// Prelude of each enclosing @media rule, outermost first
+type Media = Vec<String>;
+// Full name of the enclosing cascade layer, e.g. "a.b" in `@layer a { @layer b
+// { ... } }`, or None outside of @layer
+type Layer = Option<String>;
+// Condition of each enclosing @container rule, outermost first
+type Containers = Vec<ContainerQuery>;
+type IntermediateBlock = (Token, DeclBlock, Media, Layer, Containers);
+type Block = (Selector, DeclBlock, Media, Layer, Containers);
+// How many anonymous layers have been named
+static ANONYMOUS_LAYERS: AtomicUsize = AtomicUsize::new(0);
+// Name, and the keyframe selector and declarations of each block
+type Keyframes = (String, Vec<(Token, DeclBlock)>);
 
 #[derive(Debug, Eq, PartialEq)]
 pub(super) struct CssBlocks(pub Vec<Block>);
 
+// Style rules along with the at-rules that aren't
+#[derive(Debug, Eq, PartialEq)]
+pub(super) struct CssStylesheet {
+    pub blocks: CssBlocks,
+    // (url, media query list) of each @import rule
+    pub imports: Vec<(String, String)>,
+    // Descriptors of each @font-face rule
+    pub font_faces: Vec<DeclBlock>,
+    pub keyframes: Vec<Keyframes>,
+    // Full name of each cascade layer, in the order they're first declared
+    pub layers: Vec<String>,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub(super) enum Token {
     Selector(Pos, String),
@@ -20,12 +46,349 @@ pub(super) enum Token {
 
 pub struct CssParser {
     lexer: Lexer,
+    namespaces: Namespaces,
+    imports: Vec<(String, String)>,
+    font_faces: Vec<DeclBlock>,
+    keyframes: Vec<Keyframes>,
+    layers: Vec<String>,
 }
 
 impl CssParser {
     fn new(input: &str) -> CssParser {
         CssParser {
             lexer: Lexer::new(input, "/*", "*/"),
+            namespaces: Namespaces::default(),
+            imports: vec![],
+            font_faces: vec![],
+            keyframes: vec![],
+            layers: vec![],
+        }
+    }
+
+    // "..." or url(...), where the contents of url() may also be quoted
+    fn parse_url(&mut self) -> Result<String> {
+        if let Ok(ParserToken::Str(_, url)) = self.try(Self::parse_string) {
+            return Ok(url);
+        }
+        self.lexer.parse_chars("url(")?;
+        let url = match self.try(Self::parse_string) {
+            Ok(ParserToken::Str(_, url)) => url,
+            _ => {
+                let mut url: Vec<char> = vec![];
+                while let Ok((_, ch)) = self.lexer.peek_char() {
+                    if ch == ')' {
+                        break;
+                    }
+                    url.push(ch);
+                    self.lexer.consume_char()?;
+                }
+                url.into_iter().collect::<String>().trim().to_string()
+            }
+        };
+        self.lexer.parse_chars(")")?;
+        Ok(url)
+    }
+
+    // @namespace prefix? url;
+    fn parse_namespace_rule(&mut self) -> Result<()> {
+        self.lexer.parse_chars("@namespace")?;
+        let (prefix, url) = match self.try(Self::parse_url) {
+            Ok(url) => (None, url),
+            Err(_) => {
+                let prefix = self.parse_elem_identifier()?.to_string();
+                (Some(prefix), self.parse_url()?)
+            }
+        };
+        self.lexer.parse_chars(";")?;
+        match prefix {
+            Some(prefix) => {
+                self.namespaces.prefixes.insert(prefix, url);
+            }
+            None => self.namespaces.default = Some(url),
+        }
+        Ok(())
+    }
+
+    // @import url query-list?;
+    fn parse_import_rule(&mut self) -> Result<()> {
+        self.lexer.parse_chars("@import")?;
+        let _ = self.lexer.consume_whitespace();
+        let url = self.parse_url()?;
+        let mut query_list: Vec<char> = vec![];
+        loop {
+            match self.lexer.consume_char()? {
+                (_, ';') => break,
+                (_, ch) => query_list.push(ch),
+            }
+        }
+        let query_list = query_list.into_iter().collect::<String>().trim().to_string();
+        self.imports.push((url, query_list));
+        Ok(())
+    }
+
+    // @font-face { descriptors }
+    fn parse_font_face_rule(&mut self) -> Result<()> {
+        self.lexer.parse_chars("@font-face")?;
+        let descriptors = self.parse_decl_block()?;
+        self.font_faces.push(descriptors);
+        Ok(())
+    }
+
+    // @keyframes name { [keyframe-selector { declarations }]* }
+    fn parse_keyframes_rule(&mut self) -> Result<()> {
+        let start_pos = self.lexer.parse_chars("@keyframes")?;
+        let mut name: Vec<char> = vec![];
+        loop {
+            match self.lexer.peek_char()? {
+                (_, '{') => break,
+                (pos, ';') => {
+                    return Err(Error::Unexpected(
+                        pos,
+                        "expected '{' after @keyframes".to_string(),
+                    ));
+                }
+                (_, ch) => {
+                    name.push(ch);
+                    self.lexer.consume_char()?;
+                }
+            }
+        }
+        let name = name.into_iter().collect::<String>();
+        let name = name.trim().trim_matches(|ch| ch == '"' || ch == '\'').to_string();
+        if name.is_empty() {
+            return Err(Error::Unexpected(start_pos, "expected keyframes name".to_string()));
+        }
+        self.lexer.parse_chars("{")?;
+        let mut blocks = vec![];
+        loop {
+            let _ = self.lexer.consume_whitespace();
+            if self.lexer.try_parse_chars("}").is_ok() {
+                break;
+            }
+            blocks.push(self.parse_block()?);
+        }
+        self.keyframes.push((name, blocks));
+        Ok(())
+    }
+
+    // Adds layer, e.g. "a.b", and the layers it's nested in to the layer order
+    // if they're not in it yet
+    fn declare_layer(&mut self, layer: &str) {
+        for (i, _) in layer.match_indices('.').chain(Some((layer.len(), ""))) {
+            if !self.layers.iter().any(|declared| *declared == layer[..i]) {
+                self.layers.push(layer[..i].to_string());
+            }
+        }
+    }
+
+    // @layer name-list; or @layer name? { rule-list }
+    fn parse_layer_rule(
+        &mut self,
+        media: &[String],
+        layer: Option<&str>,
+        containers: &[ContainerQuery],
+        blocks: &mut Vec<IntermediateBlock>,
+        errs: &mut Vec<Error>,
+    ) -> Result<()> {
+        let start_pos = self.lexer.parse_chars("@layer")?;
+        let mut prelude: Vec<char> = vec![];
+        let is_block = loop {
+            match self.lexer.peek_char()? {
+                (_, '{') => break true,
+                (_, ';') => break false,
+                (_, ch) => {
+                    prelude.push(ch);
+                    self.lexer.consume_char()?;
+                }
+            }
+        };
+        let prelude = prelude.into_iter().collect::<String>();
+        let is_name_char = |ch: char| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-';
+        let is_name = |name: &str| {
+            name.split('.').all(|segment| {
+                !segment.starts_with(|ch: char| ch.is_ascii_digit())
+                    && !segment.is_empty()
+                    && segment.chars().all(is_name_char)
+            })
+        };
+        let mut names = prelude
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect::<Vec<_>>();
+        let anonymous = is_block && prelude.trim().is_empty();
+        if anonymous {
+            names.clear();
+        } else if !names.iter().all(|name| is_name(name)) || (is_block && names.len() != 1) {
+            return Err(Error::Unexpected(start_pos, "invalid @layer name".to_string()));
+        }
+        if anonymous {
+            // Anonymous layers can't be named again, so their names aren't
+            // valid identifiers, and are unique across stylesheets
+            let id = ANONYMOUS_LAYERS.fetch_add(1, Ordering::Relaxed);
+            names.push(format!("<anonymous {}>", id));
+        }
+        let names = names
+            .into_iter()
+            .map(|name| match layer {
+                Some(layer) => format!("{}.{}", layer, name),
+                None => name,
+            })
+            .collect::<Vec<_>>();
+        for name in &names {
+            self.declare_layer(name);
+        }
+        if !is_block {
+            self.lexer.parse_chars(";")?;
+            return Ok(());
+        }
+        self.lexer.parse_chars("{")?;
+        self.parse_rule_list(media, Some(&names[0]), containers, blocks, errs);
+        if self.lexer.parse_chars("}").is_err() {
+            errs.push(Error::Unexpected(start_pos, "unclosed block".to_string()));
+        }
+        Ok(())
+    }
+
+    // @media query-list { rule-list }
+    fn parse_media_rule(
+        &mut self,
+        media: &[String],
+        layer: Option<&str>,
+        containers: &[ContainerQuery],
+        blocks: &mut Vec<IntermediateBlock>,
+        errs: &mut Vec<Error>,
+    ) -> Result<()> {
+        let start_pos = self.lexer.parse_chars("@media")?;
+        let mut query_list: Vec<char> = vec![];
+        loop {
+            match self.lexer.peek_char()? {
+                (_, '{') => break,
+                (pos, ';') => {
+                    return Err(Error::Unexpected(pos, "expected '{' after @media".to_string()));
+                }
+                (_, ch) => {
+                    query_list.push(ch);
+                    self.lexer.consume_char()?;
+                }
+            }
+        }
+        self.lexer.parse_chars("{")?;
+        let mut media = media.to_vec();
+        media.push(query_list.into_iter().collect::<String>().trim().to_string());
+        self.parse_rule_list(&media, layer, containers, blocks, errs);
+        if self.lexer.parse_chars("}").is_err() {
+            errs.push(Error::Unexpected(start_pos, "unclosed block".to_string()));
+        }
+        Ok(())
+    }
+
+    // @container name? condition { rule-list }
+    fn parse_container_rule(
+        &mut self,
+        media: &[String],
+        layer: Option<&str>,
+        containers: &[ContainerQuery],
+        blocks: &mut Vec<IntermediateBlock>,
+        errs: &mut Vec<Error>,
+    ) -> Result<()> {
+        let start_pos = self.lexer.parse_chars("@container")?;
+        let mut condition: Vec<char> = vec![];
+        loop {
+            match self.lexer.peek_char()? {
+                (_, '{') => break,
+                (pos, ';') => {
+                    return Err(Error::Unexpected(
+                        pos,
+                        "expected '{' after @container".to_string(),
+                    ));
+                }
+                (_, ch) => {
+                    condition.push(ch);
+                    self.lexer.consume_char()?;
+                }
+            }
+        }
+        let condition = condition.into_iter().collect::<String>();
+        let query = match ContainerQuery::parse(&condition) {
+            Some(query) => query,
+            None => {
+                return Err(Error::Unexpected(
+                    start_pos,
+                    "invalid @container condition".to_string(),
+                ));
+            }
+        };
+        self.lexer.parse_chars("{")?;
+        let mut containers = containers.to_vec();
+        containers.push(query);
+        self.parse_rule_list(media, layer, &containers, blocks, errs);
+        if self.lexer.parse_chars("}").is_err() {
+            errs.push(Error::Unexpected(start_pos, "unclosed block".to_string()));
+        }
+        Ok(())
+    }
+
+    fn parse_at_rule(
+        &mut self,
+        media: &[String],
+        layer: Option<&str>,
+        containers: &[ContainerQuery],
+        blocks: &mut Vec<IntermediateBlock>,
+        errs: &mut Vec<Error>,
+    ) -> Result<()> {
+        let start_pos = self.lexer.parse_chars("@")?;
+        let name = self.parse_elem_identifier_strict()?.to_string();
+        self.set_pos(start_pos);
+        let nested = !media.is_empty() || layer.is_some() || !containers.is_empty();
+        match name.to_ascii_lowercase().as_ref() {
+            "namespace" => if !blocks.is_empty() || nested {
+                Err(Error::Unexpected(
+                    start_pos,
+                    "@namespace must come before all style rules".to_string(),
+                ))
+            } else {
+                self.parse_namespace_rule()
+            },
+            "import" => {
+                let has_namespaces =
+                    self.namespaces.default.is_some() || !self.namespaces.prefixes.is_empty();
+                if !blocks.is_empty() || nested || has_namespaces {
+                    Err(Error::Unexpected(
+                        start_pos,
+                        "@import must come before all other rules".to_string(),
+                    ))
+                } else {
+                    self.parse_import_rule()
+                }
+            }
+            "font-face" => self.parse_font_face_rule(),
+            "keyframes" => self.parse_keyframes_rule(),
+            "container" => self.parse_container_rule(media, layer, containers, blocks, errs),
+            "layer" => self.parse_layer_rule(media, layer, containers, blocks, errs),
+            "media" => self.parse_media_rule(media, layer, containers, blocks, errs),
+            _ => Err(Error::Unexpected(
+                start_pos,
+                format!("unsupported at-rule: @{}", name),
+            )),
+        }
+    }
+
+    // Skips to the end of the current at-rule, which is either a ';' or the '}'
+    // closing its block
+    fn skip_at_rule(&mut self) {
+        let mut depth = 0;
+        while let Ok((_, ch)) = self.lexer.consume_char() {
+            match ch {
+                ';' if depth == 0 => return,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth <= 0 {
+                        return;
+                    }
+                }
+                _ => (),
+            }
         }
     }
 
@@ -100,12 +463,17 @@ impl CssParser {
         }
     }
 
+    // The ": value" after a property
+    fn parse_declaration_value(&mut self) -> Result<Token> {
+        self.lexer.try_parse_chars(":")?;
+        self.parse_value()
+    }
+
     fn parse_decl_block(&mut self) -> Result<DeclBlock> {
         let mut declarations: Vec<(Token, Token)> = vec![];
         let block_start = self.lexer.parse_chars("{")?;
         while let Ok(property) = self.parse_property() {
-            self.lexer.try_parse_chars(":")?;
-            let value = self.parse_value()?;
+            let value = self.parse_declaration_value()?;
             declarations.push((property, value));
             match self.lexer.try_parse_chars(";") {
                 Ok(_) => (),
@@ -121,7 +489,7 @@ impl CssParser {
         Ok(declarations)
     }
 
-    fn parse_block(&mut self) -> Result<IntermediateBlock> {
+    fn parse_block(&mut self) -> Result<(Token, DeclBlock)> {
         let selector = self.parse_selector()?;
         let decl_block = self.parse_decl_block()?;
         Ok((selector, decl_block))
@@ -130,14 +498,45 @@ impl CssParser {
     fn parse_blocks(&mut self) -> (Vec<IntermediateBlock>, Vec<Error>) {
         let mut blocks = vec![];
         let mut errs = vec![];
+        self.parse_rule_list(&[], None, &[], &mut blocks, &mut errs);
+        (blocks, errs)
+    }
+
+    // Parses rules until EOF, or inside an @media, @layer or @container rule,
+    // until the '}' closing it
+    fn parse_rule_list(
+        &mut self,
+        media: &[String],
+        layer: Option<&str>,
+        containers: &[ContainerQuery],
+        blocks: &mut Vec<IntermediateBlock>,
+        errs: &mut Vec<Error>,
+    ) {
+        let nested = !media.is_empty() || layer.is_some() || !containers.is_empty();
         loop {
+            let _ = self.lexer.consume_whitespace();
+            match self.lexer.peek_char() {
+                Ok((_, '}')) if nested => return,
+                Ok((_, '@')) => {
+                    let start_pos = self.pos();
+                    let result = self.parse_at_rule(media, layer, containers, blocks, errs);
+                    if let Err(err) = result {
+                        errs.push(err);
+                        self.set_pos(start_pos);
+                        self.skip_at_rule();
+                    }
+                    continue;
+                }
+                _ => (),
+            }
             match self.parse_block() {
-                Ok(bl) => {
-                    blocks.push(bl);
+                Ok((selector, decl_block)) => {
+                    let layer = layer.map(|layer| layer.to_string());
+                    blocks.push((selector, decl_block, media.to_vec(), layer, containers.to_vec()));
                     let _ = self.lexer.consume_whitespace();
                 }
                 Err(Error::Eof(..)) => {
-                    return (blocks, errs);
+                    return;
                 }
                 Err(err) => {
                     errs.push(err);
@@ -148,21 +547,65 @@ impl CssParser {
         }
     }
 
+    // Declarations without the braces around them, e.g. of a style
+    // attribute. Invalid declarations are skipped up to the next ';'.
+    pub(super) fn parse_declaration_list(input: &str) -> (DeclBlock, Vec<Error>) {
+        let mut parser = CssParser::new(input);
+        let mut declarations = vec![];
+        let mut errs = vec![];
+        loop {
+            let _ = parser.lexer.consume_whitespace();
+            match parser.lexer.peek_char() {
+                Err(_) => break,
+                Ok((_, ';')) => (),
+                Ok(_) => {
+                    let declaration = parser.parse_property().and_then(|property| {
+                        Ok((property, parser.parse_declaration_value()?))
+                    });
+                    match declaration {
+                        Ok(declaration) => declarations.push(declaration),
+                        Err(err) => errs.push(err),
+                    }
+                }
+            }
+            if parser.lexer.consume_until(';').is_err() {
+                break;
+            }
+        }
+        (declarations, errs)
+    }
+
     pub(super) fn parse(input: &str) -> (CssBlocks, Vec<Error>) {
+        let (stylesheet, errs) = Self::parse_stylesheet(input);
+        (stylesheet.blocks, errs)
+    }
+
+    pub(super) fn parse_stylesheet(input: &str) -> (CssStylesheet, Vec<Error>) {
         let mut parser = CssParser::new(input);
         let (int_blocks, mut errs) = parser.parse_blocks();
         let mut blocks = vec![];
-        for (token, decl_block) in int_blocks {
+        for (token, decl_block, media, layer, containers) in int_blocks {
             match token {
-                Token::Selector(pos, sel_str) => match SelectorParser::parse(&sel_str, pos) {
-                    Ok(sel) => blocks.push((sel, decl_block)),
+                Token::Selector(pos, sel_str) => match SelectorParser::parse(
+                    &sel_str,
+                    pos,
+                    &parser.namespaces,
+                ) {
+                    Ok(sel) => blocks.push((sel, decl_block, media, layer, containers)),
                     Err(err) => errs.push(err),
                 },
                 _ => unreachable!(),
             }
         }
 
-        (CssBlocks(blocks), errs)
+        let stylesheet = CssStylesheet {
+            blocks: CssBlocks(blocks),
+            imports: parser.imports,
+            font_faces: parser.font_faces,
+            keyframes: parser.keyframes,
+            layers: parser.layers,
+        };
+        (stylesheet, errs)
     }
 }
 
@@ -391,8 +834,8 @@ mod tests {
             res,
             (
                 vec![
-                    (Token::Selector((0, 1, 1), "a".to_string()), vec![]),
-                    (Token::Selector((5, 1, 6), "b".to_string()), vec![]),
+                    (Token::Selector((0, 1, 1), "a".to_string()), vec![], vec![], None, vec![]),
+                    (Token::Selector((5, 1, 6), "b".to_string()), vec![], vec![], None, vec![]),
                 ],
                 vec![]
             )
@@ -402,29 +845,327 @@ mod tests {
 
     #[test]
     fn test_parse_skips_errors() {
-        let res = CssParser::parse("a { 1 } a:f {} div {}");
+        // :f is a custom pseudo-class, but :root is a standard one that isn't
+        // supported
+        let res = CssParser::parse("a { 1 } a:f {} a:root {} div {}");
+        assert_eq!(
+            res,
+            (
+                CssBlocks(vec![
+                    (
+                        Selector::Seq(vec![
+                            Selector::Simple(SimpleSelector::new(
+                                (8, 1, 9),
+                                Some(ElemType::A),
+                                None,
+                                vec![],
+                                false,
+                            )),
+                            Selector::PseudoClass(PseudoClassSelector::Custom(
+                                (9, 1, 10),
+                                "f".to_string(),
+                            )),
+                        ]),
+                        vec![],
+                        vec![],
+                        None,
+                        vec![],
+                    ),
+                    (
+                        Selector::Simple(SimpleSelector::new(
+                            (25, 1, 26),
+                            Some(ElemType::Div),
+                            None,
+                            vec![],
+                            false,
+                        )),
+                        vec![],
+                        vec![],
+                        None,
+                        vec![],
+                    ),
+                ]),
+                vec![
+                    Error::Unexpected((6, 1, 7), "expected ':', got '}'".to_string()),
+                    Error::Unexpected(
+                        (16, 1, 17),
+                        "unexpected char ':' at the end of selector".to_string(),
+                    ),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_namespace_rules() {
+        let res = CssParser::parse(
+            "@namespace url(http://www.w3.org/1999/xhtml);\n\
+             @namespace svg \"http://www.w3.org/2000/svg\";\n\
+             svg|rect {}",
+        );
+        assert_eq!(
+            res,
+            (
+                CssBlocks(vec![(
+                    Selector::Simple(SimpleSelector::new_with_namespace(
+                        (91, 3, 1),
+                        Some(ElemType::from("rect")),
+                        None,
+                        vec![],
+                        false,
+                        Some(NamespacePrefix::Url(
+                            (91, 3, 1),
+                            "http://www.w3.org/2000/svg".to_string()
+                        )),
+                    )),
+                    vec![],
+                    vec![],
+                    None,
+                    vec![],
+                )]),
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_namespace_rule_after_style_rule_fail() {
+        let res = CssParser::parse("a {} @namespace svg url(http://www.w3.org/2000/svg); div {}");
+        assert_eq!(res.0 .0.len(), 2);
+        assert_eq!(
+            res.1,
+            vec![Error::Unexpected(
+                (5, 1, 6),
+                "@namespace must come before all style rules".to_string(),
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_skips_unsupported_at_rule() {
+        let res = CssParser::parse("@unknown { a { color: red; } } div {}");
         assert_eq!(
             res,
             (
                 CssBlocks(vec![(
                     Selector::Simple(SimpleSelector::new(
-                        (15, 1, 16),
+                        (31, 1, 32),
                         Some(ElemType::Div),
                         None,
                         vec![],
                         false,
                     )),
                     vec![],
+                    vec![],
+                    None,
+                    vec![],
                 )]),
+                vec![Error::Unexpected(
+                    (0, 1, 1),
+                    "unsupported at-rule: @unknown".to_string(),
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_media_rules() {
+        let mut parser = CssParser::new(
+            "a {} @media screen { b {} @media (width > 1px) { c {} } d {} } e {} @media print;",
+        );
+        let (blocks, errs) = parser.parse_blocks();
+        let screen = "screen".to_string();
+        let width = "(width > 1px)".to_string();
+        assert_eq!(
+            blocks
+                .into_iter()
+                .map(|(selector, _, media, _, _)| (selector, media))
+                .collect::<Vec<_>>(),
+            vec![
+                (Token::Selector((0, 1, 1), "a".to_string()), vec![]),
+                (Token::Selector((21, 1, 22), "b".to_string()), vec![screen.clone()]),
+                (Token::Selector((49, 1, 50), "c".to_string()), vec![screen.clone(), width]),
+                (Token::Selector((56, 1, 57), "d".to_string()), vec![screen]),
+                (Token::Selector((63, 1, 64), "e".to_string()), vec![]),
+            ]
+        );
+        assert_eq!(
+            errs,
+            vec![Error::Unexpected((80, 1, 81), "expected '{' after @media".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parse_layer_rules() {
+        let (stylesheet, errs) = CssParser::parse_stylesheet(
+            "@layer reset, base; a {} @layer base.x { b {} } @layer theme { @layer x { c {} } \
+             @media print { d {} } } @layer { e {} } @layer 1a;",
+        );
+        let layers = stylesheet
+            .blocks
+            .0
+            .into_iter()
+            .map(|(_, _, _, layer, _)| layer)
+            .collect::<Vec<_>>();
+        assert_eq!(layers.len(), 5);
+        assert_eq!(layers[0], None);
+        assert_eq!(layers[1].as_deref(), Some("base.x"));
+        assert_eq!(layers[2].as_deref(), Some("theme.x"));
+        assert_eq!(layers[3].as_deref(), Some("theme"));
+        assert!(layers[4].as_ref().unwrap().starts_with("<anonymous "));
+        assert_eq!(
+            &stylesheet.layers[..5],
+            &["reset", "base", "base.x", "theme", "theme.x"]
+        );
+        assert_eq!(stylesheet.layers.len(), 6);
+        assert_eq!(
+            errs,
+            vec![Error::Unexpected((121, 1, 122), "invalid @layer name".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parse_container_rules() {
+        let (CssBlocks(blocks), errs) = CssParser::parse(
+            "a {} @container card (width > 400px) { b {} @container (height < 10em) { c {} } } \
+             @container card { d {} } e {}",
+        );
+        let card = ContainerQuery::parse("card (width > 400px)").unwrap();
+        let height = ContainerQuery::parse("(height < 10em)").unwrap();
+        assert_eq!(
+            blocks
+                .into_iter()
+                .map(|(_, _, _, _, containers)| containers)
+                .collect::<Vec<_>>(),
+            vec![vec![], vec![card.clone()], vec![card, height], vec![]]
+        );
+        assert_eq!(
+            errs,
+            vec![Error::Unexpected((82, 1, 83), "invalid @container condition".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parse_namespace_rule_in_media_rule_fail() {
+        let res = CssParser::parse("@media screen { @namespace url(a); a {} }");
+        assert_eq!(res.0 .0.len(), 1);
+        assert_eq!(
+            res.1,
+            vec![Error::Unexpected(
+                (16, 1, 17),
+                "@namespace must come before all style rules".to_string(),
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_import_rules() {
+        let (stylesheet, errs) = CssParser::parse_stylesheet(
+            "@import url(a.css);\n\
+             @import \"b.css\" screen and (min-width: 1px);\n\
+             @namespace url(http://www.w3.org/1999/xhtml);\n\
+             @import url(c.css);\n\
+             a {}",
+        );
+        assert_eq!(
+            stylesheet.imports,
+            vec![
+                ("a.css".to_string(), "".to_string()),
+                ("b.css".to_string(), "screen and (min-width: 1px)".to_string()),
+            ]
+        );
+        assert_eq!(stylesheet.blocks.0.len(), 1);
+        assert_eq!(
+            errs,
+            vec![Error::Unexpected(
+                (111, 4, 1),
+                "@import must come before all other rules".to_string(),
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_font_face_rules() {
+        let (stylesheet, errs) = CssParser::parse_stylesheet(
+            "@font-face { font-family: Foo; src: url(foo.woff) } a {} @font-face {}",
+        );
+        assert_eq!(
+            stylesheet.font_faces,
+            vec![
                 vec![
-                    Error::Unexpected((6, 1, 7), "expected ':', got '}'".to_string()),
-                    Error::Unexpected(
-                        (9, 1, 10),
-                        "unexpected char ':' at the end of selector".to_string(),
+                    (
+                        Token::Property((13, 1, 14), "font-family".to_string()),
+                        Token::Value((26, 1, 27), "Foo".to_string()),
                     ),
-                ]
-            )
+                    (
+                        Token::Property((31, 1, 32), "src".to_string()),
+                        Token::Value((36, 1, 37), "url(foo.woff)".to_string()),
+                    ),
+                ],
+                vec![],
+            ]
+        );
+        assert_eq!(stylesheet.blocks.0.len(), 1);
+        assert_eq!(errs, vec![]);
+    }
+
+    #[test]
+    fn test_parse_declaration_list() {
+        let (declarations, errs) =
+            CssParser::parse_declaration_list(" color: red;; $: x; margin : 0 ");
+        assert_eq!(
+            declarations,
+            vec![
+                (
+                    Token::Property((1, 1, 2), "color".to_string()),
+                    Token::Value((8, 1, 9), "red".to_string()),
+                ),
+                (
+                    Token::Property((20, 1, 21), "margin".to_string()),
+                    Token::Value((29, 1, 30), "0".to_string()),
+                ),
+            ]
+        );
+        assert_eq!(errs.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_keyframes_rules() {
+        let (stylesheet, errs) = CssParser::parse_stylesheet(
+            "@keyframes fade { from { opacity: 0 } 50%, TO { opacity: 1 } } a {}",
+        );
+        assert_eq!(
+            stylesheet.keyframes,
+            vec![(
+                "fade".to_string(),
+                vec![
+                    (
+                        Token::Selector((18, 1, 19), "from".to_string()),
+                        vec![(
+                            Token::Property((25, 1, 26), "opacity".to_string()),
+                            Token::Value((34, 1, 35), "0".to_string()),
+                        )],
+                    ),
+                    (
+                        Token::Selector((38, 1, 39), "50%, TO".to_string()),
+                        vec![(
+                            Token::Property((48, 1, 49), "opacity".to_string()),
+                            Token::Value((57, 1, 58), "1".to_string()),
+                        )],
+                    ),
+                ],
+            )]
         );
+        assert_eq!(stylesheet.blocks.0.len(), 1);
+        assert_eq!(errs, vec![]);
+    }
+
+    #[test]
+    fn test_parse_keyframes_rule_fail() {
+        let (stylesheet, errs) = CssParser::parse_stylesheet("@keyframes { from {} } a {}");
+        assert_eq!(stylesheet.keyframes, vec![]);
+        assert_eq!(stylesheet.blocks.0.len(), 1);
+        assert_eq!(errs.len(), 1);
     }
 
     #[test]
@@ -459,6 +1200,9 @@ mod tests {
                                 Token::Value((76, 4, 12), "14px 25px".to_string()),
                             ),
                         ],
+                        vec![],
+                        None,
+                        vec![],
                     ),
                     (
                         Token::Selector((90, 7, 1), "a:hover, a:active".to_string()),
@@ -466,6 +1210,9 @@ mod tests {
                             Token::Property((112, 8, 3), "background-color".to_string()),
                             Token::Value((130, 8, 21), "red".to_string()),
                         )],
+                        vec![],
+                        None,
+                        vec![],
                     ),
                 ],
                 vec![]
@@ -525,6 +1272,9 @@ mod tests {
                                 Token::Value((76, 4, 12), "14px 25px".to_string()),
                             ),
                         ],
+                        vec![],
+                        None,
+                        vec![],
                     ),
                     (
                         Selector::Group(vec![
@@ -553,6 +1303,9 @@ mod tests {
                             Token::Property((112, 8, 3), "background-color".to_string()),
                             Token::Value((130, 8, 21), "red".to_string()),
                         )],
+                        vec![],
+                        None,
+                        vec![],
                     ),
                 ]),
                 vec![]
@@ -613,6 +1366,9 @@ mod tests {
                                 Token::Value((81, 10, 12), "14px 25px".to_string()),
                             ),
                         ],
+                        vec![],
+                        None,
+                        vec![],
                     ),
                     (
                         Selector::Group(vec![
@@ -641,6 +1397,9 @@ mod tests {
                             Token::Property((118, 18, 12), "background-color".to_string()),
                             Token::Value((136, 18, 30), "red".to_string()),
                         )],
+                        vec![],
+                        None,
+                        vec![],
                     ),
                 ]),
                 vec![]
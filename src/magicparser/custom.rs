@@ -0,0 +1,207 @@
+use magicparser::document;
+use magicparser::postparse::bump_dom_revision;
+use magicparser::{Atom, Declaration, DomNodeRef, ElemType};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A function called with each element of a custom element's name when it's
+/// upgraded, e.g. to add its shadow tree or event listeners.
+pub type UpgradeCallback = Rc<dyn Fn(&DomNodeRef)>;
+
+thread_local! {
+    // The custom elements defined on the current thread, by name
+    static DEFINITIONS: RefCell<HashMap<Atom, Rc<CustomElementDefinition>>> =
+        RefCell::new(HashMap::new());
+}
+
+// Names that are valid otherwise, but taken by SVG and MathML elements
+const RESERVED_NAMES: &[&str] = &[
+    "annotation-xml", "color-profile", "font-face", "font-face-format", "font-face-name",
+    "font-face-src", "font-face-uri", "missing-glyph",
+];
+
+/// What an embedder gives the elements of a custom element name like
+/// `my-widget`, which are only defined, and so matched by `:defined`, once
+/// they're upgraded with it.
+#[derive(Clone)]
+pub struct CustomElementDefinition {
+    pub name: Atom,
+    // Applied to the upgraded elements like a user agent rule, e.g. from
+    // parse_style_attribute("display: block")
+    pub default_style: Vec<Declaration>,
+    pub upgrade: Option<UpgradeCallback>,
+}
+
+impl CustomElementDefinition {
+    pub fn new(name: &str) -> CustomElementDefinition {
+        CustomElementDefinition {
+            name: Atom::from(name),
+            default_style: vec![],
+            upgrade: None,
+        }
+    }
+}
+
+/// Returns true if name can be defined as a custom element: it starts with a
+/// lowercase ASCII letter, has a hyphen and no uppercase ASCII letters, and
+/// isn't one of the few hyphenated names SVG and MathML already use.
+pub fn is_valid_custom_element_name(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_lowercase())
+        && name.contains('-')
+        && !name.chars().any(|ch| ch.is_ascii_uppercase() || ch.is_whitespace() || ch == '/')
+        && !RESERVED_NAMES.contains(&name)
+}
+
+/// Defines a custom element for the current thread, upgrading the elements of
+/// its name in every Document's tree. Returns false, changing nothing, if the
+/// name isn't valid or is already defined.
+///
+/// Elements that aren't in a Document's tree are upgraded when they're
+/// inserted into one, or with DomNodeRef::upgrade_custom_elements().
+pub fn define_custom_element(definition: CustomElementDefinition) -> bool {
+    if !is_valid_custom_element_name(&definition.name) {
+        return false;
+    }
+    let defined = DEFINITIONS.with(|definitions| {
+        let mut definitions = definitions.borrow_mut();
+        if definitions.contains_key(&definition.name) {
+            return false;
+        }
+        definitions.insert(definition.name.clone(), Rc::new(definition));
+        true
+    });
+    if defined {
+        for root in document::document_roots() {
+            root.upgrade_custom_elements();
+        }
+    }
+    defined
+}
+
+/// Returns the definition of the custom element name, if it's been defined
+/// on the current thread.
+pub fn custom_element_definition(name: &str) -> Option<Rc<CustomElementDefinition>> {
+    let name = Atom::lookup(name)?;
+    DEFINITIONS.with(|definitions| definitions.borrow().get(&name).cloned())
+}
+
+fn has_definitions() -> bool {
+    DEFINITIONS.with(|definitions| !definitions.borrow().is_empty())
+}
+
+impl DomNodeRef {
+    /// Returns true if this is an element that `:defined` matches: one that
+    /// isn't a custom element, or one that's been upgraded.
+    pub fn is_defined(&self) -> bool {
+        let node = self.borrow();
+        match node.elem_type {
+            ElemType::Custom(ref name) if is_valid_custom_element_name(name) => node.upgraded,
+            ref elem_type => elem_type.is_element(),
+        }
+    }
+
+    /// Returns the definition this element was upgraded with, if any.
+    pub fn custom_element_definition(&self) -> Option<Rc<CustomElementDefinition>> {
+        let node = self.borrow();
+        match node.elem_type {
+            ElemType::Custom(ref name) if node.upgraded => custom_element_definition(name),
+            _ => None,
+        }
+    }
+
+    /// Upgrades the custom elements in this subtree, including this node,
+    /// that have been defined but not upgraded yet, in tree order.
+    pub fn upgrade_custom_elements(&self) {
+        if !has_definitions() {
+            return;
+        }
+        let nodes = Some(self.clone()).into_iter().chain(self.descendants());
+        for node in nodes.collect::<Vec<_>>() {
+            node.upgrade();
+        }
+    }
+
+    fn upgrade(&self) {
+        let definition = {
+            let node = self.borrow();
+            match node.elem_type {
+                ElemType::Custom(ref name) if !node.upgraded => custom_element_definition(name),
+                _ => None,
+            }
+        };
+        let definition = match definition {
+            Some(definition) => definition,
+            None => return,
+        };
+        bump_dom_revision(self);
+        self.borrow_mut().upgraded = true;
+        // For :defined and the default style
+        self.mark_style_dirty();
+        if let Some(ref upgrade) = definition.upgrade {
+            upgrade(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, parse_style_attribute, Document};
+    use std::cell::Cell;
+
+    #[test]
+    fn test_is_valid_custom_element_name() {
+        assert!(is_valid_custom_element_name("my-widget"));
+        assert!(is_valid_custom_element_name("x-1"));
+        assert!(!is_valid_custom_element_name("widget"));
+        assert!(!is_valid_custom_element_name("My-widget"));
+        assert!(!is_valid_custom_element_name("1-widget"));
+        assert!(!is_valid_custom_element_name("font-face"));
+    }
+
+    #[test]
+    fn test_upgrade_custom_elements() {
+        let upgraded = Rc::new(Cell::new(0));
+        let upgraded2 = upgraded.clone();
+        let document = Document::new(
+            parse_html("<body><my-widget><p></p></my-widget><other-widget></other-widget></body>")
+                .unwrap(),
+        );
+        let widget = document.root().borrow().children[0].clone();
+        let other = document.root().borrow().children[1].clone();
+        let p = widget.borrow().children[0].clone();
+        assert!(!widget.is_defined() && p.is_defined() && document.root().is_defined());
+
+        let mut definition = CustomElementDefinition::new("my-widget");
+        definition.default_style = parse_style_attribute("display: block");
+        definition.upgrade = Some(Rc::new(move |node: &DomNodeRef| {
+            assert_eq!(node.borrow().elem_type.tag_name(), "my-widget");
+            upgraded2.set(upgraded2.get() + 1);
+        }));
+        assert!(define_custom_element(definition));
+        assert!(!define_custom_element(CustomElementDefinition::new("my-widget")));
+        assert!(!define_custom_element(CustomElementDefinition::new("widget")));
+        // Those already in a document are upgraded right away
+        assert_eq!(upgraded.get(), 1);
+        assert!(widget.is_defined() && !other.is_defined());
+        assert_eq!(widget.custom_element_definition().unwrap().default_style.len(), 1);
+
+        // Others once they're inserted into one
+        let detached = parse_html("<div><my-widget></my-widget></div>").unwrap();
+        let new_widget = detached.borrow().children[0].clone();
+        assert!(!new_widget.is_defined());
+        document.root().append_child(detached.clone());
+        assert!(new_widget.is_defined());
+        assert_eq!(upgraded.get(), 2);
+        // Or on request, and only once
+        let orphan = parse_html("<my-widget></my-widget>").unwrap();
+        orphan.upgrade_custom_elements();
+        orphan.upgrade_custom_elements();
+        assert!(orphan.is_defined());
+        assert_eq!(upgraded.get(), 3);
+        // Clones are upgraded anew
+        let clone = widget.clone_node(false);
+        assert!(!clone.is_defined());
+    }
+}
@@ -0,0 +1,763 @@
+use magicparser::error;
+use magicparser::htmlparser::HtmlParser;
+use magicparser::{dom_revision, resolve_url, Atom, DomNodeRef, ElemType, ElementState, NodeId,
+                  Stylesheet};
+use smallvec::SmallVec;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::iter;
+use std::rc::{Rc, Weak};
+
+thread_local! {
+    // The current thread's documents, by the NodeId of their root, so that
+    // mutations can keep their id maps up to date
+    static DOCUMENTS: RefCell<HashMap<NodeId, Weak<RefCell<DocumentData>>>> =
+        RefCell::new(HashMap::new());
+}
+
+// Elements that a label can be for
+const LABELABLE: &[&str] = &[
+    "button", "input", "meter", "output", "progress", "select", "textarea",
+];
+
+// Public ids of DOCTYPEs that put documents in quirks mode, by prefix
+static QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+// Public ids of DOCTYPEs that put documents in quirks mode, in full
+static QUIRKS_PUBLIC_IDS: &[&str] = &[
+    "-//w3o//dtd w3 html strict 3.0//en//",
+    "-/w3c/dtd html 4.0 transitional/en",
+    "html",
+];
+
+static QUIRKS_SYSTEM_ID: &str = "http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd";
+
+// Public ids of DOCTYPEs that put documents in quirks mode without a system
+// id, and limited quirks mode with one, by prefix
+static HTML4_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "-//w3c//dtd html 4.01 frameset//",
+    "-//w3c//dtd html 4.01 transitional//",
+];
+
+// Public ids of DOCTYPEs that put documents in limited quirks mode, by prefix
+static LIMITED_QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "-//w3c//dtd xhtml 1.0 frameset//",
+    "-//w3c//dtd xhtml 1.0 transitional//",
+];
+
+/// Attribute names compare ASCII-case-insensitively in HTML documents and
+/// case-sensitively in XML documents.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum DocumentKind {
+    #[default]
+    Html,
+    Xml,
+}
+
+/// Only full quirks mode changes how selectors match; limited quirks mode
+/// affects layout alone.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+/// A document's `<!DOCTYPE>`. The name is lowercased.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Doctype {
+    pub name: String,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+}
+
+impl Doctype {
+    pub fn new(name: String, public_id: Option<String>, system_id: Option<String>) -> Doctype {
+        Doctype {
+            name,
+            public_id,
+            system_id,
+        }
+    }
+
+    /// Returns the mode the DOCTYPE puts a document in, as HTML decides it
+    /// for legacy DOCTYPEs, e.g. quirks mode for HTML 3.2. `<!DOCTYPE html>`
+    /// is the no-quirks one.
+    pub fn quirks_mode(&self) -> QuirksMode {
+        let public_id = self.public_id.as_deref().unwrap_or("").to_ascii_lowercase();
+        let system_id = self.system_id.as_deref().map(str::to_ascii_lowercase);
+        let has_prefix = |prefixes: &[&str]| {
+            prefixes.iter().any(|prefix| public_id.starts_with(prefix))
+        };
+        let quirks = self.name != "html"
+            || has_prefix(QUIRKS_PUBLIC_ID_PREFIXES)
+            || QUIRKS_PUBLIC_IDS.contains(&public_id.as_str())
+            || system_id.as_deref() == Some(QUIRKS_SYSTEM_ID)
+            || (system_id.is_none() && has_prefix(HTML4_PUBLIC_ID_PREFIXES));
+        if quirks {
+            QuirksMode::Quirks
+        } else if has_prefix(LIMITED_QUIRKS_PUBLIC_ID_PREFIXES)
+            || has_prefix(HTML4_PUBLIC_ID_PREFIXES)
+        {
+            QuirksMode::LimitedQuirks
+        } else {
+            QuirksMode::NoQuirks
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct DocumentData {
+    // The DOM revision of the last change to the document's tree
+    revision: usize,
+    // The elements with each id, in no particular order. Usually just one.
+    ids: HashMap<Atom, SmallVec<[NodeId; 1]>>,
+    // The element :target matches, see Document::set_target()
+    target: Option<NodeId>,
+    // Built by the first class or tag lookup after a change
+    elements: Option<ElementIndex>,
+    // Here rather than in Document, since the nodes' attribute methods need
+    // it
+    kind: DocumentKind,
+}
+
+// The elements of a document by class and by type, in document order, as of
+// a document revision
+#[derive(Debug)]
+struct ElementIndex {
+    revision: usize,
+    all: Vec<NodeId>,
+    by_class: HashMap<Atom, Vec<NodeId>>,
+    by_type: HashMap<ElemType, Vec<NodeId>>,
+}
+
+impl ElementIndex {
+    fn new(root: &DomNodeRef, revision: usize) -> ElementIndex {
+        let mut index = ElementIndex {
+            revision,
+            all: vec![],
+            by_class: HashMap::new(),
+            by_type: HashMap::new(),
+        };
+        for dom_node in iter::once(root.clone()).chain(root.descendants()) {
+            let node = dom_node.borrow();
+            if !node.elem_type.is_element() {
+                continue;
+            }
+            let node_id = dom_node.node_id();
+            index.all.push(node_id);
+            for class in &node.classes {
+                index.by_class.entry(class.clone()).or_default().push(node_id);
+            }
+            index.by_type.entry(node.elem_type.clone()).or_default().push(node_id);
+        }
+        index
+    }
+}
+
+impl DocumentData {
+    fn add_subtree(&mut self, dom_node: &DomNodeRef) {
+        for node in iter::once(dom_node.clone()).chain(dom_node.descendants()) {
+            let id = node.borrow().id.clone();
+            if let Some(id) = id {
+                self.ids.entry(id).or_default().push(node.node_id());
+            }
+        }
+    }
+
+    fn remove_subtree(&mut self, dom_node: &DomNodeRef) {
+        for node in iter::once(dom_node.clone()).chain(dom_node.descendants()) {
+            let id = node.borrow().id.clone();
+            if let Some(id) = id {
+                self.remove_id(&id, node.node_id());
+            }
+        }
+    }
+
+    fn remove_id(&mut self, id: &Atom, node_id: NodeId) {
+        if let Some(nodes) = self.ids.get_mut(id) {
+            nodes.retain(|&mut node| node != node_id);
+            if nodes.is_empty() {
+                self.ids.remove(id);
+            }
+        }
+    }
+}
+
+/// A DOM tree along with a map of the ids of its elements, for looking
+/// elements up by id without searching the tree, e.g. for `#id` queries,
+/// `:target` and labels' `for` attributes. Lookups by class and tag name use
+/// an index too.
+///
+/// The map is kept up to date by the DomNodeRef mutation methods, like
+/// append_child() and set_attribute(), for as long as a Document for the root
+/// is alive. Changes made to DomNode fields directly aren't seen.
+///
+/// Also holds what's known about the document besides its tree: its URL,
+/// DOCTYPE and quirks mode, and the stylesheets that apply to it.
+#[derive(Debug)]
+pub struct Document {
+    root: DomNodeRef,
+    data: Rc<RefCell<DocumentData>>,
+    url: String,
+    doctype: Option<Doctype>,
+    quirks_mode: QuirksMode,
+    // In the order they apply, like the DOM's `document.styleSheets`
+    stylesheets: Vec<Stylesheet>,
+}
+
+impl Document {
+    /// Returns the HTML document rooted at root, at about:blank and in
+    /// no-quirks mode. Documents for the same root share their id map.
+    pub fn new(root: DomNodeRef) -> Document {
+        let data = document_data(&root).unwrap_or_else(|| {
+            let mut data = DocumentData {
+                revision: dom_revision(),
+                ..DocumentData::default()
+            };
+            data.add_subtree(&root);
+            let data = Rc::new(RefCell::new(data));
+            DOCUMENTS.with(|documents| {
+                documents.borrow_mut().insert(root.node_id(), Rc::downgrade(&data));
+            });
+            root.upgrade_custom_elements();
+            data
+        });
+        Document {
+            root,
+            data,
+            url: "about:blank".to_string(),
+            doctype: None,
+            quirks_mode: QuirksMode::NoQuirks,
+            stylesheets: vec![],
+        }
+    }
+
+    /// Parses an HTML document loaded from url. Its quirks mode is decided by
+    /// its DOCTYPE, and without one, it's in quirks mode.
+    pub fn parse(input: &str, url: &str) -> error::Result<Document> {
+        let (doctype, root) = HtmlParser::parse_document(input)?;
+        let mut document = Document::new(DomNodeRef::from(root));
+        document.url = url.to_string();
+        document.quirks_mode = doctype.as_ref().map_or(QuirksMode::Quirks, Doctype::quirks_mode);
+        document.doctype = doctype;
+        Ok(document)
+    }
+
+    pub fn root(&self) -> &DomNodeRef {
+        &self.root
+    }
+
+    /// Returns the revision of the document's tree, which changes whenever
+    /// the tree, or a shadow tree in it, is changed through the DomNodeRef
+    /// mutation methods, and only then, so that results computed from the
+    /// tree, like layout, can be kept until it changes. It's the
+    /// dom_revision() of the last change, so it only ever increases.
+    pub fn revision(&self) -> usize {
+        self.data.borrow().revision
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn set_url(&mut self, url: &str) {
+        self.url = url.to_string();
+    }
+
+    /// Returns the URL that relative URLs in the document are resolved
+    /// against: the href of the first `base` element that has one, resolved
+    /// against the document's URL, or else the document's URL.
+    pub fn base_url(&self) -> String {
+        let base = iter::once(self.root.clone()).chain(self.root.descendants()).find_map(|node| {
+            let node = node.borrow();
+            if node.elem_type != ElemType::from("base") {
+                return None;
+            }
+            node.attr("href").cloned().flatten()
+        });
+        match base {
+            Some(href) => resolve_url(&self.url, &href),
+            None => self.url.clone(),
+        }
+    }
+
+    /// Resolves url against base_url(), e.g. for a link's href.
+    pub fn resolve_url(&self, url: &str) -> String {
+        resolve_url(&self.base_url(), url)
+    }
+
+    pub fn doctype(&self) -> Option<&Doctype> {
+        self.doctype.as_ref()
+    }
+
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    pub fn set_quirks_mode(&mut self, quirks_mode: QuirksMode) {
+        self.quirks_mode = quirks_mode;
+    }
+
+    pub fn document_kind(&self) -> DocumentKind {
+        self.data.borrow().kind
+    }
+
+    /// Makes it an HTML or XML document. DomNodeRef::set_attribute() and the
+    /// like only lowercase attribute names of HTML elements in HTML
+    /// documents.
+    pub fn set_document_kind(&mut self, kind: DocumentKind) {
+        self.data.borrow_mut().kind = kind;
+    }
+
+    pub fn stylesheets(&self) -> &[Stylesheet] {
+        &self.stylesheets
+    }
+
+    /// Adds a stylesheet after the others, so that it takes precedence over
+    /// them.
+    pub fn add_stylesheet(&mut self, stylesheet: Stylesheet) {
+        self.stylesheets.push(stylesheet);
+    }
+
+    pub fn stylesheets_mut(&mut self) -> &mut Vec<Stylesheet> {
+        &mut self.stylesheets
+    }
+
+    /// Returns the first element with id in document order, like the DOM's
+    /// `getElementById()`.
+    pub fn get_element_by_id(&self, id: &str) -> Option<DomNodeRef> {
+        let data = self.data.borrow();
+        match &data.ids.get(&Atom::lookup(id)?)?[..] {
+            [node_id] => DomNodeRef::from_node_id(*node_id),
+            _ => {
+                drop(data);
+                self.get_elements_by_id(id).into_iter().next()
+            }
+        }
+    }
+
+    /// Returns every element with id in document order, since ids aren't
+    /// always unique.
+    pub fn get_elements_by_id(&self, id: &str) -> Vec<DomNodeRef> {
+        let data = self.data.borrow();
+        let node_ids = match Atom::lookup(id).and_then(|id| data.ids.get(&id)) {
+            Some(node_ids) => node_ids,
+            None => return vec![],
+        };
+        if let [node_id] = node_ids[..] {
+            return DomNodeRef::from_node_id(node_id).into_iter().collect();
+        }
+        // Rare enough that searching the tree for their order is fine
+        iter::once(self.root.clone())
+            .chain(self.root.descendants())
+            .filter(|node| node_ids.contains(&node.node_id()))
+            .collect()
+    }
+
+    /// Returns the elements that have all of the space-separated classes in
+    /// class_names, in document order, like the DOM's
+    /// `getElementsByClassName()`. Unlike the DOM's, the result isn't live,
+    /// but lookups are answered from an index of the document that's only
+    /// rebuilt after the DOM changes.
+    pub fn get_elements_by_class_name(&self, class_names: &str) -> Vec<DomNodeRef> {
+        // No node has a class that was never interned
+        let classes = class_names.split_whitespace().map(Atom::lookup);
+        let classes = match classes.collect::<Option<Vec<_>>>() {
+            Some(classes) => classes,
+            None => return vec![],
+        };
+        if classes.is_empty() {
+            return vec![];
+        }
+        self.with_index(|index| {
+            let found = index.by_class.get(&classes[0]).map_or(&[][..], |found| &found[..]);
+            found
+                .iter()
+                .filter_map(|&node_id| DomNodeRef::from_node_id(node_id))
+                .filter(|node| {
+                    let node = node.borrow();
+                    classes[1..].iter().all(|class| node.classes.contains(class))
+                })
+                .collect()
+        })
+    }
+
+    /// Returns the elements with the tag name, or every element for `*`, in
+    /// document order, like the DOM's `getElementsByTagName()`. Looked up
+    /// like get_elements_by_class_name().
+    pub fn get_elements_by_tag_name(&self, name: &str) -> Vec<DomNodeRef> {
+        self.with_index(|index| {
+            let found = if name == "*" {
+                &index.all[..]
+            } else {
+                index.by_type.get(&ElemType::from(name)).map_or(&[][..], |found| &found[..])
+            };
+            found.iter().filter_map(|&node_id| DomNodeRef::from_node_id(node_id)).collect()
+        })
+    }
+
+    fn with_index<T, F: FnOnce(&ElementIndex) -> T>(&self, f: F) -> T {
+        let mut data = self.data.borrow_mut();
+        let revision = data.revision;
+        let stale = data.elements.as_ref().is_none_or(|index| index.revision != revision);
+        if stale {
+            data.elements = Some(ElementIndex::new(&self.root, revision));
+        }
+        f(data.elements.as_ref().unwrap())
+    }
+
+    /// Returns the element a URL fragment like `#intro` points to: the one
+    /// with fragment as its id, or else the first `a` with it as its name.
+    /// fragment doesn't include the `#`.
+    pub fn find_fragment(&self, fragment: &str) -> Option<DomNodeRef> {
+        self.get_element_by_id(fragment).or_else(|| {
+            self.root.descendants().find(|node| {
+                let node = node.borrow();
+                node.elem_type == ElemType::A
+                    && node.attr("name").is_some_and(|name| name.as_deref() == Some(fragment))
+            })
+        })
+    }
+
+    /// Makes the element fragment points to (see find_fragment()) the one
+    /// that `:target` matches, as when navigating to a URL ending in it.
+    /// None, or a fragment that doesn't point to anything, clears it. Returns
+    /// the new target.
+    pub fn set_target(&self, fragment: Option<&str>) -> Option<DomNodeRef> {
+        let target = fragment.and_then(|fragment| self.find_fragment(fragment));
+        let old_target = self.data.borrow().target.and_then(DomNodeRef::from_node_id);
+        if let Some(old_target) = old_target {
+            old_target.set_state(ElementState::TARGET, false);
+        }
+        if let Some(ref target) = target {
+            target.set_state(ElementState::TARGET, true);
+        }
+        self.data.borrow_mut().target = target.as_ref().map(DomNodeRef::node_id);
+        target
+    }
+
+    /// Returns the form control label is for: the element its `for`
+    /// attribute names, or without one, its first labelable descendant, like
+    /// the DOM's `HTMLLabelElement.control`.
+    pub fn labeled_control(&self, label: &DomNodeRef) -> Option<DomNodeRef> {
+        let for_id = label.borrow().attr("for").cloned();
+        match for_id {
+            Some(for_id) => self
+                .get_element_by_id(for_id.as_deref().unwrap_or(""))
+                .filter(is_labelable),
+            None => label.descendants().find(is_labelable),
+        }
+    }
+}
+
+fn is_labelable(dom_node: &DomNodeRef) -> bool {
+    LABELABLE.contains(&dom_node.borrow().elem_type.tag_name())
+}
+
+fn has_documents() -> bool {
+    DOCUMENTS.with(|documents| !documents.borrow().is_empty())
+}
+
+// The data of the document dom_node is in, if there's one
+fn document_data(dom_node: &DomNodeRef) -> Option<Rc<RefCell<DocumentData>>> {
+    if !has_documents() {
+        return None;
+    }
+    let root = dom_node.ancestors().last().unwrap_or_else(|| dom_node.clone());
+    DOCUMENTS.with(|documents| {
+        let mut documents = documents.borrow_mut();
+        let data = documents.get(&root.node_id())?.upgrade();
+        if data.is_none() {
+            documents.remove(&root.node_id());
+        }
+        data
+    })
+}
+
+/// Adds the ids in the subtree of dom_node, which was just inserted, to the
+/// map of its document, and upgrades the custom elements in it.
+pub fn subtree_inserted(dom_node: &DomNodeRef) {
+    if let Some(data) = document_data(dom_node) {
+        data.borrow_mut().add_subtree(dom_node);
+        drop(data);
+        dom_node.upgrade_custom_elements();
+    }
+}
+
+/// Updates the revision of the document dom_node is in, or whose shadow
+/// trees it's in, after it changed.
+pub fn tree_changed(dom_node: &DomNodeRef) {
+    if !has_documents() {
+        return;
+    }
+    let root = dom_node.shadow_including_ancestors().last().unwrap_or_else(|| dom_node.clone());
+    if let Some(data) = document_data(&root) {
+        data.borrow_mut().revision = dom_revision();
+    }
+}
+
+/// Returns the roots of the current thread's documents that are alive.
+pub fn document_roots() -> Vec<DomNodeRef> {
+    DOCUMENTS.with(|documents| {
+        let documents = documents.borrow();
+        documents
+            .iter()
+            .filter(|(_, data)| data.strong_count() > 0)
+            .filter_map(|(&id, _)| DomNodeRef::from_node_id(id))
+            .collect()
+    })
+}
+
+/// Returns the kind of the document dom_node is in, or whose shadow trees
+/// it's in. Nodes in no document are taken to be in an HTML one.
+pub fn document_kind(dom_node: &DomNodeRef) -> DocumentKind {
+    if !has_documents() {
+        return DocumentKind::Html;
+    }
+    let root = dom_node.shadow_including_ancestors().last().unwrap_or_else(|| dom_node.clone());
+    document_data(&root).map_or(DocumentKind::Html, |data| data.borrow().kind)
+}
+
+/// Removes the ids in the subtree of dom_node, which is about to be removed,
+/// from the map of its document.
+pub fn subtree_removing(dom_node: &DomNodeRef) {
+    if let Some(data) = document_data(dom_node) {
+        data.borrow_mut().remove_subtree(dom_node);
+    }
+}
+
+/// Updates the map of dom_node's document after its id changed.
+pub fn id_changed(dom_node: &DomNodeRef, old_id: Option<Atom>, new_id: Option<Atom>) {
+    if old_id == new_id {
+        return;
+    }
+    if let Some(data) = document_data(dom_node) {
+        let mut data = data.borrow_mut();
+        if let Some(old_id) = old_id {
+            data.remove_id(&old_id, dom_node.node_id());
+        }
+        if let Some(new_id) = new_id {
+            data.ids.entry(new_id).or_default().push(dom_node.node_id());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_html;
+
+    #[test]
+    fn test_document() {
+        let root = parse_html(
+            "<html><body><p id=\"a\">One<a name=\"b\"></a></p><div id=\"c\"></div>\
+             <label for=\"c\"></label><label>Name <input id=\"name\"></input></label>\
+             </body></html>",
+        ).unwrap();
+        let document = Document::new(root.clone());
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        let div = body.borrow().children[1].clone();
+        assert!(document.get_element_by_id("a").unwrap().ptr_eq(&p));
+        assert!(document.get_element_by_id("A").is_none());
+
+        // Mutations keep the map up to date
+        p.set_attribute("id", Some("x".to_string()));
+        assert!(document.get_element_by_id("a").is_none());
+        assert!(document.get_element_by_id("x").unwrap().ptr_eq(&p));
+        div.set_attribute("id", Some("x".to_string()));
+        div.append_child(p.clone());
+        assert_eq!(document.get_elements_by_id("x"), vec![div.clone(), p.clone()]);
+        assert!(document.get_element_by_id("x").unwrap().ptr_eq(&div));
+        div.remove();
+        assert_eq!(document.get_elements_by_id("x"), vec![]);
+        body.insert_before(p.clone(), None);
+        assert!(document.get_element_by_id("x").unwrap().ptr_eq(&p));
+        p.remove_attribute("id");
+        assert!(document.get_element_by_id("x").is_none());
+        assert!(Document::new(root.clone()).get_element_by_id("name").is_some());
+
+        // Fragments and :target
+        let a = p.borrow().children[1].clone();
+        assert!(document.find_fragment("b").unwrap().ptr_eq(&a));
+        assert!(document.find_fragment("c").is_none());
+        document.set_target(Some("b"));
+        assert!(a.borrow().state.contains(ElementState::TARGET));
+        document.set_target(Some("name"));
+        assert!(!a.borrow().state.contains(ElementState::TARGET));
+        document.set_target(None);
+        assert!(body.descendants().all(|node| node.borrow().state.is_empty()));
+
+        // Labels, whose control must be labelable
+        let labels = body.borrow().children.to_vec();
+        let input = document.get_element_by_id("name").unwrap();
+        assert!(document.labeled_control(&labels[0]).is_none());
+        assert!(document.labeled_control(&labels[1]).unwrap().ptr_eq(&input));
+        labels[0].set_attribute("for", Some("name".to_string()));
+        assert!(document.labeled_control(&labels[0]).unwrap().ptr_eq(&input));
+    }
+
+    #[test]
+    fn test_document_parse() {
+        let document = Document::parse(
+            "<!DOCTYPE html><html><head><base href=\"/static/\"></base></head></html>",
+            "https://example.com/a/index.html",
+        ).unwrap();
+        assert_eq!(document.doctype(), Some(&Doctype::new("html".to_string(), None, None)));
+        assert_eq!(document.quirks_mode(), QuirksMode::NoQuirks);
+        assert_eq!(document.url(), "https://example.com/a/index.html");
+        assert_eq!(document.base_url(), "https://example.com/static/");
+        assert_eq!(document.resolve_url("b.css"), "https://example.com/static/b.css");
+
+        let document = Document::parse("<html></html>", "about:blank").unwrap();
+        assert_eq!(document.doctype(), None);
+        assert_eq!(document.quirks_mode(), QuirksMode::Quirks);
+        assert_eq!(document.base_url(), "about:blank");
+
+        let cases = [
+            ("<!doctype HTML>", QuirksMode::NoQuirks),
+            ("<!DOCTYPE svg>", QuirksMode::Quirks),
+            (
+                "<!DOCTYPE HTML PUBLIC \"-//W3C//DTD HTML 3.2 Final//EN\">",
+                QuirksMode::Quirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">",
+                QuirksMode::Quirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \
+                 \"http://www.w3.org/TR/html4/loose.dtd\">",
+                QuirksMode::LimitedQuirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \
+                 \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">",
+                QuirksMode::NoQuirks,
+            ),
+            ("<!DOCTYPE html SYSTEM \"about:legacy-compat\">", QuirksMode::NoQuirks),
+        ];
+        for &(doctype, quirks_mode) in &cases {
+            let document = Document::parse(&format!("{}<html></html>", doctype), "").unwrap();
+            assert_eq!(document.quirks_mode(), quirks_mode, "{}", doctype);
+        }
+        assert!(Document::parse("<!DOCTYPE html FOO \"x\"><html></html>", "").is_err());
+    }
+
+    #[test]
+    fn test_get_elements_by_class_and_tag_name() {
+        let root = parse_html(
+            "<div class=\"a\"><p class=\"a b\">One</p><p class=\"b\"></p><span class=\"b a\">\
+             </span></div>",
+        ).unwrap();
+        let document = Document::new(root.clone());
+        let children = root.borrow().children.to_vec();
+        assert_eq!(document.get_elements_by_class_name("a"), vec![
+            root.clone(),
+            children[0].clone(),
+            children[2].clone(),
+        ]);
+        assert_eq!(
+            document.get_elements_by_class_name(" b  a "),
+            vec![children[0].clone(), children[2].clone()]
+        );
+        assert_eq!(document.get_elements_by_class_name("a never-seen"), vec![]);
+        assert_eq!(document.get_elements_by_class_name(""), vec![]);
+        assert_eq!(document.get_elements_by_tag_name("P"), children[..2].to_vec());
+        assert_eq!(document.get_elements_by_tag_name("*").len(), 4);
+
+        // Lookups after a change see it
+        children[1].add_class("a");
+        assert_eq!(document.get_elements_by_class_name("a").len(), 4);
+        children[0].remove();
+        assert_eq!(document.get_elements_by_tag_name("p"), vec![children[1].clone()]);
+    }
+
+    #[test]
+    fn test_document_revision() {
+        let root = parse_html("<div><p id=\"a\"></p></div>").unwrap();
+        let p = root.borrow().children[0].clone();
+        let document = Document::new(root.clone());
+        let other = Document::new(parse_html("<div></div>").unwrap());
+        let revision = document.revision();
+        let other_revision = other.revision();
+
+        p.set_attribute("title", Some("t".to_string()));
+        assert!(document.revision() > revision);
+        assert_eq!(other.revision(), other_revision);
+        let revision = document.revision();
+        document.get_element_by_id("a");
+        assert_eq!(document.revision(), revision);
+
+        // Changes to shadow trees count too, but not those to detached nodes
+        let shadow_root = p.attach_shadow().unwrap();
+        let revision = document.revision();
+        shadow_root.append_child(parse_html("<span></span>").unwrap());
+        assert!(document.revision() > revision);
+        p.remove();
+        let revision = document.revision();
+        p.set_state(ElementState::HOVER, true);
+        p.remove_attribute("title");
+        assert_eq!(document.revision(), revision);
+        other.root().append_child(p);
+        assert!(other.revision() > other_revision);
+    }
+}
@@ -0,0 +1,145 @@
+use magicparser::{DomNode, DomNodeRef, DomVisitor, ElemType};
+use std::fmt::Write;
+
+impl DomNodeRef {
+    /// Returns a readable outline of the tree rooted at this node, one node
+    /// per line, indented by depth, e.g. for comparing trees in tests.
+    ///
+    /// Elements are written like selectors, with their id, classes (sorted),
+    /// attributes (sorted by name) and states, e.g.
+    /// `p#intro.a.b[title="t"]:hover`. Text is quoted and comments are written
+    /// as in markup.
+    pub fn dump_tree(&self) -> String {
+        self.dump_tree_with(|_| vec![])
+    }
+
+    /// Like dump_tree(), but writes the lines annotate returns for each node,
+    /// e.g. the rules it matched, below it, prefixed with `- `.
+    pub fn dump_tree_with<F>(&self, mut annotate: F) -> String
+    where
+        F: FnMut(&DomNodeRef) -> Vec<String>,
+    {
+        let mut dumper = TreeDumper {
+            annotate: &mut annotate,
+            depth: 0,
+            dump: String::new(),
+        };
+        // With the shadow trees first, like browsers' devtools
+        self.walk(&mut dumper);
+        dumper.dump
+    }
+}
+
+struct TreeDumper<'a, F: 'a> {
+    annotate: &'a mut F,
+    depth: usize,
+    dump: String,
+}
+
+impl<'a, F> TreeDumper<'a, F>
+where
+    F: FnMut(&DomNodeRef) -> Vec<String>,
+{
+    fn dump_node(&mut self, dom_node: &DomNodeRef) {
+        let indent = "  ".repeat(self.depth);
+        self.dump.push_str(&indent);
+        self.dump.push_str(&dom_node.borrow().summary());
+        self.dump.push('\n');
+        for annotation in (self.annotate)(dom_node) {
+            writeln!(self.dump, "{}  - {}", indent, annotation).unwrap();
+        }
+    }
+}
+
+impl<'a, F> DomVisitor for TreeDumper<'a, F>
+where
+    F: FnMut(&DomNodeRef) -> Vec<String>,
+{
+    fn enter_element(&mut self, element: &DomNodeRef) -> bool {
+        self.dump_node(element);
+        self.depth += 1;
+        true
+    }
+
+    fn leave_element(&mut self, _element: &DomNodeRef) {
+        self.depth -= 1;
+    }
+
+    fn visit_text(&mut self, text: &DomNodeRef) {
+        self.dump_node(text);
+    }
+
+    fn visit_comment(&mut self, comment: &DomNodeRef) {
+        self.dump_node(comment);
+    }
+}
+
+impl DomNode {
+    // The node's line in dump_tree()
+    fn summary(&self) -> String {
+        let mut summary = String::new();
+        match self.elem_type {
+            ElemType::Text(ref text) => write!(summary, "{:?}", text).unwrap(),
+            ElemType::Comment(ref comment) => write!(summary, "<!--{}-->", comment).unwrap(),
+            ref elem_type => {
+                summary.push_str(elem_type.tag_name());
+                if let Some(ref id) = self.id {
+                    write!(summary, "#{}", id).unwrap();
+                }
+                let mut classes = self.classes.iter().collect::<Vec<_>>();
+                classes.sort();
+                for class in classes {
+                    write!(summary, ".{}", class).unwrap();
+                }
+                let mut attrs = self
+                    .attrs
+                    .iter()
+                    .filter(|(name, _)| !["id", "class"].contains(&name.as_str()))
+                    .collect::<Vec<_>>();
+                attrs.sort_by_key(|&(name, _)| name);
+                for (name, value) in attrs {
+                    match value {
+                        Some(value) => write!(summary, "[{}={:?}]", name, value).unwrap(),
+                        None => write!(summary, "[{}]", name).unwrap(),
+                    }
+                }
+                for state in self.state.pseudo_classes() {
+                    write!(summary, ":{}", state).unwrap();
+                }
+            }
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use magicparser::{parse_html, ElementState};
+
+    #[test]
+    fn test_dump_tree() {
+        let root = parse_html(
+            "<div id=\"main\" class=\"b a\" title=\"t\" hidden>One<!-- c -->\
+             <p lang=\"en\">\"Two\"<img src=\"x.png\"></p></div>",
+        ).unwrap();
+        let p = root.borrow().children[2].clone();
+        p.set_state(ElementState::HOVER, true);
+        assert_eq!(
+            root.dump_tree(),
+            "div#main.a.b[hidden][title=\"t\"]\n\
+             \x20 \"One\"\n\
+             \x20 <!-- c -->\n\
+             \x20 p[lang=\"en\"]:hover\n\
+             \x20   \"\\\"Two\\\"\"\n\
+             \x20   img[src=\"x.png\"]\n"
+        );
+        let dump = p.dump_tree_with(|node| {
+            let children = node.borrow().children.len();
+            if children > 0 { vec![format!("{} children", children)] } else { vec![] }
+        });
+        assert_eq!(
+            dump,
+            "p[lang=\"en\"]:hover\n  - 2 children\n  \"\\\"Two\\\"\"\n  img[src=\"x.png\"]\n"
+        );
+    }
+}
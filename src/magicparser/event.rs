@@ -0,0 +1,339 @@
+use magicparser::{DomNodeRef, NodeId};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A function called with each event dispatched to the node it's added to.
+pub type EventListener = Rc<dyn Fn(&mut Event)>;
+
+thread_local! {
+    // The listeners of the current thread's nodes, in the order they were
+    // added. A node's listeners are removed when it's freed.
+    static LISTENERS: RefCell<HashMap<NodeId, Vec<Listener>>> = RefCell::new(HashMap::new());
+    static NEXT_LISTENER_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+struct Listener {
+    id: ListenerId,
+    event_type: String,
+    capture: bool,
+    callback: EventListener,
+}
+
+/// Identifies a listener added with DomNodeRef::add_event_listener(), for
+/// removing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(usize);
+
+/// Which part of its dispatch an event is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPhase {
+    // Not being dispatched
+    None,
+    // Going from the root down to the target's parent
+    Capturing,
+    AtTarget,
+    // Going from the target's parent back up to the root
+    Bubbling,
+}
+
+/// An event, like a click, dispatched to a node with
+/// DomNodeRef::dispatch_event().
+#[derive(Debug)]
+pub struct Event {
+    event_type: String,
+    bubbles: bool,
+    cancelable: bool,
+    phase: EventPhase,
+    target: Option<DomNodeRef>,
+    current_target: Option<DomNodeRef>,
+    propagation_stopped: bool,
+    immediate_propagation_stopped: bool,
+    default_prevented: bool,
+}
+
+impl Event {
+    pub fn new(event_type: &str, bubbles: bool, cancelable: bool) -> Event {
+        Event {
+            event_type: event_type.to_string(),
+            bubbles,
+            cancelable,
+            phase: EventPhase::None,
+            target: None,
+            current_target: None,
+            propagation_stopped: false,
+            immediate_propagation_stopped: false,
+            default_prevented: false,
+        }
+    }
+
+    pub fn event_type(&self) -> &str {
+        &self.event_type
+    }
+
+    pub fn bubbles(&self) -> bool {
+        self.bubbles
+    }
+
+    pub fn cancelable(&self) -> bool {
+        self.cancelable
+    }
+
+    pub fn phase(&self) -> EventPhase {
+        self.phase
+    }
+
+    /// The node the event was dispatched to.
+    pub fn target(&self) -> Option<&DomNodeRef> {
+        self.target.as_ref()
+    }
+
+    /// The node whose listeners are being called.
+    pub fn current_target(&self) -> Option<&DomNodeRef> {
+        self.current_target.as_ref()
+    }
+
+    /// Stops the event from reaching any other node, once the current node's
+    /// listeners have been called.
+    pub fn stop_propagation(&mut self) {
+        self.propagation_stopped = true;
+    }
+
+    /// Like stop_propagation(), but also skips the current node's remaining
+    /// listeners.
+    pub fn stop_immediate_propagation(&mut self) {
+        self.propagation_stopped = true;
+        self.immediate_propagation_stopped = true;
+    }
+
+    /// Marks the event's default action as canceled, if it's cancelable.
+    pub fn prevent_default(&mut self) {
+        if self.cancelable {
+            self.default_prevented = true;
+        }
+    }
+
+    pub fn default_prevented(&self) -> bool {
+        self.default_prevented
+    }
+}
+
+impl DomNodeRef {
+    /// Adds a listener for events of event_type dispatched to this node or,
+    /// as they propagate, its descendants. Capturing listeners are called on
+    /// the way down to the target, and others on the way back up, if the
+    /// event bubbles; both are called at the target.
+    ///
+    /// Listeners that hold a DomNodeRef to the node they're added to keep it
+    /// alive until they're removed.
+    pub fn add_event_listener<F>(&self, event_type: &str, capture: bool, callback: F) -> ListenerId
+    where
+        F: Fn(&mut Event) + 'static,
+    {
+        let id = NEXT_LISTENER_ID.with(|next| ListenerId(next.replace(next.get() + 1)));
+        let listener = Listener {
+            id,
+            event_type: event_type.to_string(),
+            capture,
+            callback: Rc::new(callback),
+        };
+        LISTENERS.with(|listeners| {
+            listeners.borrow_mut().entry(self.node_id()).or_default().push(listener);
+        });
+        id
+    }
+
+    /// Removes a listener added to this node. Returns false if it wasn't
+    /// found.
+    pub fn remove_event_listener(&self, id: ListenerId) -> bool {
+        // Dropped once listeners is no longer borrowed, since dropping the
+        // listener may free nodes
+        let removed = LISTENERS.with(|listeners| {
+            let mut listeners = listeners.borrow_mut();
+            let node_listeners = listeners.get_mut(&self.node_id())?;
+            let index = node_listeners.iter().position(|listener| listener.id == id)?;
+            let removed = node_listeners.remove(index);
+            if node_listeners.is_empty() {
+                listeners.remove(&self.node_id());
+            }
+            Some(removed)
+        });
+        removed.is_some()
+    }
+
+    /// Dispatches event to this node: calls the capturing listeners of its
+    /// ancestors, from the root down, then its own listeners, then, if the
+    /// event bubbles, its ancestors' other listeners, from its parent up.
+    /// The path is fixed before any listener is called.
+    ///
+    /// Returns false if a listener canceled the event with prevent_default().
+    pub fn dispatch_event(&self, event: &mut Event) -> bool {
+        let mut path = self.ancestors().collect::<Vec<_>>();
+        path.reverse();
+        event.target = Some(self.clone());
+        event.propagation_stopped = false;
+        event.immediate_propagation_stopped = false;
+        event.default_prevented = false;
+
+        event.phase = EventPhase::Capturing;
+        for node in &path {
+            if event.propagation_stopped {
+                break;
+            }
+            node.call_listeners(event, |capture| capture);
+        }
+        if !event.propagation_stopped {
+            event.phase = EventPhase::AtTarget;
+            self.call_listeners(event, |capture| capture);
+            self.call_listeners(event, |capture| !capture);
+        }
+        if event.bubbles {
+            event.phase = EventPhase::Bubbling;
+            for node in path.iter().rev() {
+                if event.propagation_stopped {
+                    break;
+                }
+                node.call_listeners(event, |capture| !capture);
+            }
+        }
+
+        event.phase = EventPhase::None;
+        event.current_target = None;
+        !event.default_prevented
+    }
+
+    // Calls the node's listeners for event, whose capture flag passes
+    // filter. Listeners added meanwhile aren't called, and ones removed
+    // meanwhile are skipped.
+    fn call_listeners<F>(&self, event: &mut Event, filter: F)
+    where
+        F: Fn(bool) -> bool,
+    {
+        let node_id = self.node_id();
+        let matching = LISTENERS.with(|listeners| {
+            let listeners = listeners.borrow();
+            let node_listeners = listeners.get(&node_id).map_or(&[][..], |found| &found[..]);
+            node_listeners
+                .iter()
+                .filter(|listener| {
+                    listener.event_type == event.event_type && filter(listener.capture)
+                })
+                .map(|listener| (listener.id, listener.callback.clone()))
+                .collect::<Vec<_>>()
+        });
+        event.current_target = Some(self.clone());
+        for (id, callback) in matching {
+            if event.immediate_propagation_stopped {
+                break;
+            }
+            let registered = LISTENERS.with(|listeners| {
+                listeners.borrow().get(&node_id).is_some_and(|node_listeners| {
+                    node_listeners.iter().any(|listener| listener.id == id)
+                })
+            });
+            if registered {
+                callback(event);
+            }
+        }
+    }
+}
+
+// Called when the node identified by id is freed
+pub fn remove_listeners(id: NodeId) {
+    // The thread-local may already be gone if the thread is exiting
+    let removed = LISTENERS.try_with(|listeners| listeners.borrow_mut().remove(&id));
+    drop(removed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_html;
+
+    #[test]
+    fn test_dispatch_event() {
+        let root = parse_html("<div><p><a></a></p></div>").unwrap();
+        let p = root.borrow().children[0].clone();
+        let a = p.borrow().children[0].clone();
+        let log = Rc::new(RefCell::new(vec![]));
+        let listen = |node: &DomNodeRef, name: &'static str, capture: bool| {
+            let log = log.clone();
+            node.add_event_listener("click", capture, move |event| {
+                let target = event.target().unwrap().borrow().elem_type.tag_name().to_string();
+                log.borrow_mut().push(format!("{} {:?} {}", name, event.phase(), target));
+            })
+        };
+        listen(&root, "div", false);
+        listen(&root, "div", true);
+        listen(&a, "a", false);
+        listen(&a, "a", true);
+        let p_listener = listen(&p, "p", false);
+        // Not for clicks
+        p.add_event_listener("focus", false, |_| panic!());
+
+        let mut event = Event::new("click", true, true);
+        assert!(a.dispatch_event(&mut event));
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "div Capturing a",
+                "a AtTarget a",
+                "a AtTarget a",
+                "p Bubbling a",
+                "div Bubbling a",
+            ]
+        );
+        assert_eq!(event.phase(), EventPhase::None);
+        assert!(event.current_target().is_none());
+
+        // Events that don't bubble still reach the target
+        log.borrow_mut().clear();
+        p.dispatch_event(&mut Event::new("click", false, true));
+        assert_eq!(*log.borrow(), vec!["div Capturing p", "p AtTarget p"]);
+
+        log.borrow_mut().clear();
+        assert!(p.remove_event_listener(p_listener));
+        assert!(!p.remove_event_listener(p_listener));
+        p.add_event_listener("click", false, |event| {
+            event.prevent_default();
+            event.stop_propagation();
+        });
+        let mut event = Event::new("click", true, true);
+        assert!(!a.dispatch_event(&mut event));
+        assert!(event.default_prevented());
+        assert_eq!(*log.borrow(), vec!["div Capturing a", "a AtTarget a", "a AtTarget a"]);
+
+        // Only cancelable events can be canceled
+        let mut event = Event::new("click", true, false);
+        assert!(a.dispatch_event(&mut event));
+
+        // Listeners removed after the event reached their node aren't called
+        log.borrow_mut().clear();
+        let b = parse_html("<b></b>").unwrap();
+        let later = Rc::new(Cell::new(None));
+        let later2 = later.clone();
+        let log2 = log.clone();
+        b.add_event_listener("click", false, move |event| {
+            log2.borrow_mut().push("first".to_string());
+            event.current_target().unwrap().remove_event_listener(later2.get().unwrap());
+        });
+        later.set(Some(listen(&b, "b", false)));
+        b.dispatch_event(&mut Event::new("click", true, true));
+        assert_eq!(*log.borrow(), vec!["first"]);
+
+        // Stopping immediate propagation skips the node's other listeners
+        log.borrow_mut().clear();
+        b.add_event_listener("click", true, |event| event.stop_immediate_propagation());
+        b.dispatch_event(&mut Event::new("click", true, true));
+        assert_eq!(*log.borrow(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_listeners_are_removed_with_node() {
+        let node = parse_html("<div></div>").unwrap();
+        let node_id = node.node_id();
+        node.add_event_listener("click", false, |_| {});
+        drop(node);
+        assert!(LISTENERS.with(|listeners| !listeners.borrow().contains_key(&node_id)));
+    }
+}
@@ -0,0 +1,409 @@
+use magicparser::postparse::bump_dom_revision;
+use magicparser::{DomNodeRef, ElemType};
+
+/// The state of a form control that's kept apart from its content
+/// attributes, e.g. the text typed into an input, as opposed to its `value`
+/// attribute, which only gives the default.
+///
+/// Each part is None until it's changed with one of DomNodeRef's setters, i.e.
+/// until its dirty flag is set. Until then, it follows the content attribute.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormState {
+    // Of an input or textarea
+    pub value: Option<String>,
+    // Of a checkbox or radio button
+    pub checked: Option<bool>,
+    // Of an option
+    pub selected: Option<bool>,
+    // Of a checkbox, which has no attribute for it
+    pub indeterminate: bool,
+}
+
+// Elements that can be disabled
+const DISABLEABLE: &[&str] = &[
+    "button", "fieldset", "input", "optgroup", "option", "select", "textarea",
+];
+
+impl DomNodeRef {
+    /// Returns the current value of an input, textarea, select or option,
+    /// which is the one last set with set_value() or, if none was, the
+    /// default from the `value` attribute (or the content, for textareas and
+    /// options without one). A select's value is that of its first selected
+    /// option. Other nodes have no value.
+    pub fn value(&self) -> Option<String> {
+        match self.form_tag()? {
+            "input" => {
+                let value = self.borrow().form_state.value.clone();
+                Some(value.unwrap_or_else(|| attr_value(self, "value").unwrap_or_default()))
+            }
+            "textarea" => {
+                let value = self.borrow().form_state.value.clone();
+                Some(value.unwrap_or_else(|| self.text_content()))
+            }
+            "option" => Some(attr_value(self, "value").unwrap_or_else(|| self.text_content())),
+            "select" => {
+                let selected = self.options().into_iter().find(|option| option.selected());
+                Some(selected.and_then(|option| option.value()).unwrap_or_default())
+            }
+            _ => None,
+        }
+    }
+
+    /// Sets the value of an input or textarea, leaving its `value` attribute
+    /// alone, or selects the first option of a select with value. Returns
+    /// false, changing nothing, for other nodes.
+    pub fn set_value(&self, value: &str) -> bool {
+        match self.form_tag() {
+            Some("input") | Some("textarea") => {
+                bump_dom_revision(self);
+                self.borrow_mut().form_state.value = Some(value.to_string());
+                true
+            }
+            Some("select") => {
+                let options = self.options();
+                let found = options.iter().find(|option| option.value().as_deref() == Some(value));
+                match found {
+                    Some(option) => option.set_selected(true),
+                    None => {
+                        for option in &options {
+                            option.set_selected(false);
+                        }
+                        true
+                    }
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if this is a checked checkbox or radio button. Until
+    /// set_checked() is called, that's if it has a `checked` attribute.
+    pub fn checked(&self) -> bool {
+        if !self.is_checkable() {
+            return false;
+        }
+        let checked = self.borrow().form_state.checked;
+        checked.unwrap_or_else(|| self.borrow().attr("checked").is_some())
+    }
+
+    /// Checks or unchecks a checkbox or radio button, leaving its `checked`
+    /// attribute alone. Checking a radio button unchecks the others in its
+    /// group, i.e. with the same name in the same form. Returns false,
+    /// changing nothing, for other nodes.
+    pub fn set_checked(&self, checked: bool) -> bool {
+        if !self.is_checkable() {
+            return false;
+        }
+        if self.input_type() == "radio" {
+            for other in self.radio_group() {
+                if checked {
+                    other.update_form_state(|state| state.checked = Some(false));
+                } else {
+                    // Which can become indeterminate
+                    other.mark_style_dirty();
+                }
+            }
+        }
+        self.update_form_state(|state| state.checked = Some(checked));
+        true
+    }
+
+    /// Returns true if this is a checkbox whose indeterminate flag is set, a
+    /// radio button in a group with none checked, or a progress bar without
+    /// a `value` attribute.
+    pub fn indeterminate(&self) -> bool {
+        match self.form_tag() {
+            Some("input") if self.input_type() == "checkbox" => {
+                self.borrow().form_state.indeterminate
+            }
+            Some("input") if self.input_type() == "radio" => {
+                !self.checked() && self.radio_group().iter().all(|other| !other.checked())
+            }
+            Some("progress") => self.borrow().attr("value").is_none(),
+            _ => false,
+        }
+    }
+
+    /// Sets the indeterminate flag of a checkbox, which only changes how it's
+    /// shown, not whether it's checked. Returns false, changing nothing, for
+    /// other nodes.
+    pub fn set_indeterminate(&self, indeterminate: bool) -> bool {
+        if !self.is_checkable() || self.input_type() != "checkbox" {
+            return false;
+        }
+        self.update_form_state(|state| state.indeterminate = indeterminate);
+        true
+    }
+
+    /// Returns true if this is the default of a set of form controls: a
+    /// checkbox or radio button with a `checked` attribute, an option with a
+    /// `selected` attribute, or the first submit button of a form.
+    pub fn is_default(&self) -> bool {
+        match self.form_tag() {
+            Some("input") if self.is_checkable() => self.borrow().attr("checked").is_some(),
+            Some("option") => self.borrow().attr("selected").is_some(),
+            Some("button") | Some("input") if self.is_submit_button() => {
+                let form = self.ancestors().find(|ancestor| ancestor.form_tag() == Some("form"));
+                form.and_then(|form| form.descendants().find(|node| node.is_submit_button()))
+                    .is_some_and(|button| button.ptr_eq(self))
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if this is a selected option. Until set_selected() is
+    /// called, that's if it has a `selected` attribute.
+    pub fn selected(&self) -> bool {
+        if self.form_tag() != Some("option") {
+            return false;
+        }
+        let selected = self.borrow().form_state.selected;
+        selected.unwrap_or_else(|| self.borrow().attr("selected").is_some())
+    }
+
+    /// Selects or deselects an option, leaving its `selected` attribute
+    /// alone. Selecting an option of a select that doesn't allow multiple
+    /// selections deselects the others. Returns false, changing nothing, for
+    /// other nodes.
+    pub fn set_selected(&self, selected: bool) -> bool {
+        if self.form_tag() != Some("option") {
+            return false;
+        }
+        let select = self.ancestors().find(|ancestor| ancestor.form_tag() == Some("select"));
+        if let Some(select) = select.filter(|select| select.borrow().attr("multiple").is_none()) {
+            if selected {
+                for option in select.options() {
+                    if !option.ptr_eq(self) {
+                        option.update_form_state(|state| state.selected = Some(false));
+                    }
+                }
+            }
+        }
+        self.update_form_state(|state| state.selected = Some(selected));
+        true
+    }
+
+    /// Returns true if this is a form control that's disabled, by its own
+    /// `disabled` attribute or by being in a disabled fieldset (but not in
+    /// its first legend) or, for an option, in a disabled optgroup.
+    pub fn disabled(&self) -> bool {
+        let tag = match self.form_tag() {
+            Some(tag) if DISABLEABLE.contains(&tag) => tag,
+            _ => return false,
+        };
+        if self.borrow().attr("disabled").is_some() {
+            return true;
+        }
+        if tag == "option" {
+            let optgroup = self.parent().filter(|parent| parent.form_tag() == Some("optgroup"));
+            return optgroup.is_some_and(|optgroup| optgroup.disabled());
+        }
+        if tag == "optgroup" {
+            return false;
+        }
+        let mut child = self.clone();
+        for ancestor in self.ancestors() {
+            let is_disabled_fieldset = ancestor.form_tag() == Some("fieldset")
+                && ancestor.borrow().attr("disabled").is_some();
+            if is_disabled_fieldset {
+                let first_legend = ancestor
+                    .borrow()
+                    .children
+                    .iter()
+                    .find(|node| node.form_tag() == Some("legend"))
+                    .cloned();
+                if !first_legend.is_some_and(|legend| legend.ptr_eq(&child)) {
+                    return true;
+                }
+            }
+            child = ancestor;
+        }
+        false
+    }
+
+    /// Returns true if this is a form control that can be disabled but isn't.
+    pub fn enabled(&self) -> bool {
+        self.form_tag().is_some_and(|tag| DISABLEABLE.contains(&tag)) && !self.disabled()
+    }
+
+    /// Same as setting or removing the `disabled` attribute, which, unlike
+    /// the other parts of a control's state, is all there is to it.
+    pub fn set_disabled(&self, disabled: bool) {
+        if disabled {
+            self.set_attribute("disabled", Some(String::new()));
+        } else {
+            self.remove_attribute("disabled");
+        }
+    }
+
+    /// Clears the dirty flags of the form controls in this subtree, e.g. a
+    /// form, so that their states follow their content attributes again, like
+    /// resetting a form. Checkboxes stay indeterminate.
+    pub fn reset_form_state(&self) {
+        let controls = Some(self.clone()).into_iter().chain(self.descendants());
+        for control in controls.collect::<Vec<_>>() {
+            let reset = FormState {
+                indeterminate: control.borrow().form_state.indeterminate,
+                ..FormState::default()
+            };
+            if control.borrow().form_state != reset {
+                control.update_form_state(|state| *state = reset);
+            }
+        }
+    }
+
+    // Changes the node's form state, marking it for restyling, since
+    // :checked or :indeterminate can match it differently
+    fn update_form_state<F>(&self, update: F)
+    where
+        F: FnOnce(&mut FormState),
+    {
+        bump_dom_revision(self);
+        update(&mut self.borrow_mut().form_state);
+        self.mark_style_dirty();
+    }
+
+    // The tag of an HTML element, for telling form controls apart
+    fn form_tag(&self) -> Option<&'static str> {
+        let node = self.borrow();
+        let tag = match node.elem_type {
+            ElemType::Custom(ref name) => name.as_str(),
+            _ => return None,
+        };
+        ["button", "fieldset", "form", "input", "legend", "optgroup", "option", "progress",
+         "select", "textarea"]
+            .iter()
+            .find(|&&form_tag| form_tag == tag)
+            .cloned()
+    }
+
+    // The type of an input or button, lowercased; text if it isn't given
+    fn input_type(&self) -> String {
+        attr_value(self, "type").map_or("text".to_string(), |kind| kind.to_ascii_lowercase())
+    }
+
+    fn is_checkable(&self) -> bool {
+        self.form_tag() == Some("input") && ["checkbox", "radio"].contains(&&*self.input_type())
+    }
+
+    // The other radio buttons with the same name in the same form, or in the
+    // same tree if it isn't in one
+    fn radio_group(&self) -> Vec<DomNodeRef> {
+        let name = match attr_value(self, "name").filter(|name| !name.is_empty()) {
+            Some(name) => name,
+            None => return vec![],
+        };
+        let owner = self.ancestors().find(|ancestor| ancestor.form_tag() == Some("form"));
+        let scope = owner.or_else(|| self.ancestors().last()).unwrap_or_else(|| self.clone());
+        scope
+            .descendants()
+            .filter(|other| {
+                !other.ptr_eq(self) && other.is_checkable() && other.input_type() == "radio"
+                    && attr_value(other, "name").as_ref() == Some(&name)
+            })
+            .collect()
+    }
+
+    // Buttons and inputs that submit their form. Buttons do unless their
+    // type is button or reset.
+    fn is_submit_button(&self) -> bool {
+        match self.form_tag() {
+            Some("button") => !["button", "reset"].contains(&&*self.input_type()),
+            Some("input") => ["submit", "image"].contains(&&*self.input_type()),
+            _ => false,
+        }
+    }
+
+    // The options of a select, including those in optgroups
+    fn options(&self) -> Vec<DomNodeRef> {
+        self.descendants().filter(|node| node.form_tag() == Some("option")).collect()
+    }
+}
+
+fn attr_value(dom_node: &DomNodeRef, name: &str) -> Option<String> {
+    dom_node.borrow().attr(name).cloned().map(Option::unwrap_or_default)
+}
+
+#[cfg(test)]
+mod tests {
+    use magicparser::parse_html;
+
+    #[test]
+    fn test_form_state() {
+        let root = parse_html(
+            "<form><input value=\"a\"></input><textarea>Some text</textarea>\
+             <input type=\"checkbox\" checked></input>\
+             <input type=\"radio\" name=\"r\" checked></input>\
+             <input type=\"RADIO\" name=\"r\"></input>\
+             <select><option>One</option><optgroup disabled><option value=\"2\" selected>\
+             Two</option></optgroup></select>\
+             <fieldset disabled><legend><button></button></legend><button></button></fieldset>\
+             </form>",
+        ).unwrap();
+        let children = root.borrow().children.to_vec();
+        let (input, textarea, checkbox) = (&children[0], &children[1], &children[2]);
+        let (radio1, radio2) = (&children[3], &children[4]);
+        let (select, fieldset) = (&children[5], &children[6]);
+        let one = select.borrow().children[0].clone();
+        let two = select.borrow().children[1].borrow().children[0].clone();
+
+        assert_eq!(input.value().as_deref(), Some("a"));
+        assert!(input.set_value("b"));
+        assert_eq!(input.value().as_deref(), Some("b"));
+        assert_eq!(input.borrow().attr("value"), Some(&Some("a".to_string())));
+        // Once dirty, the value no longer follows the attribute
+        input.set_attribute("value", Some("c".to_string()));
+        assert_eq!(input.value().as_deref(), Some("b"));
+        assert_eq!(textarea.value().as_deref(), Some("Some text"));
+        assert_eq!(root.value(), None);
+        assert!(!root.set_value("a"));
+
+        assert!(checkbox.checked());
+        checkbox.set_checked(false);
+        assert!(!checkbox.checked());
+        assert!(checkbox.borrow().attr("checked").is_some());
+        assert!(radio1.checked() && !radio2.checked());
+        radio2.set_checked(true);
+        assert!(!radio1.checked() && radio2.checked());
+        assert!(!input.set_checked(true));
+        assert!(!input.checked());
+        // Defaults follow the attributes
+        assert!(checkbox.is_default() && radio1.is_default() && !radio2.is_default());
+
+        assert!(!radio1.indeterminate());
+        radio2.set_checked(false);
+        assert!(radio1.indeterminate() && radio2.indeterminate());
+        assert!(!checkbox.indeterminate());
+        assert!(checkbox.set_indeterminate(true));
+        assert!(checkbox.indeterminate() && !checkbox.checked());
+        assert!(!radio1.set_indeterminate(true));
+
+        assert!(!one.selected() && two.selected());
+        assert_eq!(select.value().as_deref(), Some("2"));
+        select.set_value("One");
+        assert!(one.selected() && !two.selected());
+        assert_eq!(select.value().as_deref(), Some("One"));
+
+        assert!(two.disabled() && !one.disabled());
+        let disabled = fieldset
+            .descendants()
+            .filter(|node| node.borrow().elem_type.tag_name() == "button")
+            .map(|button| button.disabled())
+            .collect::<Vec<_>>();
+        // Not the one in the legend
+        assert_eq!(disabled, vec![false, true]);
+        let buttons = fieldset.descendants().filter(|node| node.form_tag() == Some("button"));
+        let defaults = buttons.map(|button| button.is_default()).collect::<Vec<_>>();
+        assert_eq!(defaults, vec![true, false]);
+        assert!(one.enabled() && !two.enabled() && !root.enabled());
+        fieldset.set_disabled(false);
+        assert!(!fieldset.disabled());
+        assert!(!fieldset.borrow().children[1].disabled());
+
+        root.reset_form_state();
+        assert_eq!(input.value().as_deref(), Some("c"));
+        assert!(checkbox.checked() && radio1.checked() && !radio2.checked());
+        assert!(two.selected());
+        assert!(checkbox.indeterminate());
+    }
+}
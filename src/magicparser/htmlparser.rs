@@ -1,7 +1,7 @@
 use magicparser::error::{Error, Result};
 use magicparser::lexer::Lexer;
 use magicparser::parser::Parser;
-use magicparser::{ElemType, Pos, Token};
+use magicparser::{Doctype, ElemType, Pos, Token};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct DomNode {
@@ -27,10 +27,28 @@ impl DomNode {
     }
 }
 
+// Receives each node as soon as its opening tag has been parsed, for building
+// the tree while parsing instead of after. See HtmlParser::parse_with_sink().
+pub trait TreeSink {
+    // node has no children yet
+    fn open(&mut self, node: &DomNode);
+    // Called after the children of the node last opened and not yet closed
+    fn close(&mut self);
+}
+
 pub struct HtmlParser {
     lexer: Lexer,
 }
 
+// Option::as_deref_mut() would keep sink borrowed for as long as the original
+// trait object's lifetime
+fn reborrow<'a>(sink: &'a mut Option<&mut dyn TreeSink>) -> Option<&'a mut dyn TreeSink> {
+    match sink {
+        Some(sink) => Some(&mut **sink),
+        None => None,
+    }
+}
+
 type ParserFn<T> = fn(&mut HtmlParser) -> Result<T>;
 
 impl HtmlParser {
@@ -107,11 +125,37 @@ impl HtmlParser {
         }
     }
 
-    fn parse_doctype(&mut self) -> Result<()> {
-        match self.lexer.parse_chars("<!DOCTYPE html>") {
-            Ok(_) => Ok(()),
-            Err(err) => Err(err),
+    // <!DOCTYPE name>, optionally followed by PUBLIC "public id" "system id"
+    // (the system id being optional) or SYSTEM "system id". Keywords are
+    // case-insensitive.
+    fn parse_doctype(&mut self) -> Result<Doctype> {
+        let start_pos = self.lexer.parse_chars("<!")?;
+        let keyword = self.parse_elem_identifier_strict()?.to_string();
+        if !keyword.eq_ignore_ascii_case("doctype") {
+            return Err(Error::Unexpected(start_pos, "expected DOCTYPE".to_string()));
+        }
+        let name = self.parse_elem_identifier()?.to_string().to_ascii_lowercase();
+        let mut doctype = Doctype::new(name, None, None);
+        if let Ok(keyword) = self.try(HtmlParser::parse_elem_identifier) {
+            let keyword = keyword.to_string().to_ascii_lowercase();
+            match keyword.as_ref() {
+                "public" => {
+                    doctype.public_id = Some(self.parse_string()?.to_string());
+                    doctype.system_id = self.try(HtmlParser::parse_string).ok().map(|id| {
+                        id.to_string()
+                    });
+                }
+                "system" => doctype.system_id = Some(self.parse_string()?.to_string()),
+                _ => {
+                    return Err(Error::Unexpected(
+                        start_pos,
+                        format!("unexpected {} in DOCTYPE", keyword),
+                    ))
+                }
+            }
         }
+        self.lexer.parse_chars(">")?;
+        Ok(doctype)
     }
 
     fn parse_opening_tag(&mut self) -> Result<DomNode> {
@@ -180,7 +224,8 @@ impl HtmlParser {
     fn parse_text_node(&mut self) -> Result<DomNode> {
         let start_pos = self.pos();
         let mut text: Vec<char> = vec![];
-        while let Ok((_, ch)) = self.lexer.peek_char() {
+        // Comments end text, since they're nodes of their own
+        while let (false, Ok((_, ch))) = (self.lexer.at_block_comment(), self.lexer.peek_char()) {
             if ch != '<' {
                 let (_, ch) = self.lexer.consume_char()?;
                 text.push(ch);
@@ -196,7 +241,18 @@ impl HtmlParser {
         }
     }
 
+    fn parse_comment_node(&mut self) -> Result<DomNode> {
+        let (start_pos, text) = self.lexer.consume_block_comment()?;
+        Ok(DomNode::new(start_pos, ElemType::Comment(text), vec![], vec![]))
+    }
+
     fn parse_node(&mut self) -> Result<DomNode> {
+        self.parse_node_with_sink(None)
+    }
+
+    // With a sink, nodes are passed to it instead of being added to their
+    // parents' children
+    fn parse_node_with_sink(&mut self, mut sink: Option<&mut dyn TreeSink>) -> Result<DomNode> {
         let mut node = match self.try(HtmlParser::parse_text_node) {
             Ok(node) => node,
             Err(_) => match self.try(HtmlParser::parse_opening_tag) {
@@ -206,11 +262,17 @@ impl HtmlParser {
                 }
             },
         };
+        if let Some(sink) = reborrow(&mut sink) {
+            sink.open(&node);
+        }
         if node.elem_type.is_void_elem() {
+            if let Some(sink) = reborrow(&mut sink) {
+                sink.close();
+            }
             return Ok(node);
         }
         loop {
-            match self.lexer.consume_whitespace() {
+            match self.lexer.consume_whitespace_before_comment() {
                 Ok(_) => (),
                 Err(Error::Eof(_)) => {
                     return Err(Error::Unexpected(
@@ -226,11 +288,26 @@ impl HtmlParser {
                     format!("unclosed element: {:?}", node),
                 ));
             }
+            if let Ok(comment_node) = self.parse_comment_node() {
+                match reborrow(&mut sink) {
+                    Some(sink) => {
+                        sink.open(&comment_node);
+                        sink.close();
+                    }
+                    None => node.children.push(comment_node),
+                }
+                continue;
+            }
             match self.lexer.peek_chars(2) {
                 Ok((_, chars)) => {
                     if chars == "</" {
                         match self.parse_closing_tag(node.clone()) {
-                            ok @ Ok(_) => return ok,
+                            ok @ Ok(_) => {
+                                if let Some(sink) = reborrow(&mut sink) {
+                                    sink.close();
+                                }
+                                return ok;
+                            }
                             Err(Error::Eof(_)) => {
                                 return Err(Error::Unexpected(
                                     node.pos,
@@ -240,13 +317,15 @@ impl HtmlParser {
                             Err(err) => return Err(err),
                         }
                     } else if chars.starts_with('<') {
-                        let child_node = match self.parse_node() {
+                        let child_node = match self.parse_node_with_sink(reborrow(&mut sink)) {
                             Ok(child_node) => child_node,
                             Err(err) => {
                                 return Err(err);
                             }
                         };
-                        node.children.push(child_node);
+                        if sink.is_none() {
+                            node.children.push(child_node);
+                        }
                     } else {
                         let text_node = match self.parse_text_node() {
                             Ok(text_node) => text_node,
@@ -254,7 +333,13 @@ impl HtmlParser {
                                 return Err(err);
                             }
                         };
-                        node.children.push(text_node);
+                        match reborrow(&mut sink) {
+                            Some(sink) => {
+                                sink.open(&text_node);
+                                sink.close();
+                            }
+                            None => node.children.push(text_node),
+                        }
                     }
                 }
                 Err(Error::Eof(_)) => {
@@ -269,10 +354,48 @@ impl HtmlParser {
     }
 
     pub fn parse(input: &str) -> Result<DomNode> {
+        HtmlParser::parse_document(input).map(|(_, node)| node)
+    }
+
+    // Like parse(), but also returns the DOCTYPE, if there's one
+    pub fn parse_document(input: &str) -> Result<(Option<Doctype>, DomNode)> {
         let mut parser = HtmlParser::new(input);
-        let _ = parser.try(HtmlParser::parse_doctype);
+        let doctype = parser.try(HtmlParser::parse_doctype).ok();
         let node = parser.parse_node()?;
-        Ok(node)
+        Ok((doctype, node))
+    }
+
+    // Parses a sequence of nodes, like an element's contents, which may be
+    // empty
+    pub fn parse_fragment(input: &str) -> Result<Vec<DomNode>> {
+        let mut parser = HtmlParser::new(input);
+        let mut nodes = vec![];
+        loop {
+            match parser.lexer.consume_whitespace_before_comment() {
+                Ok(_) => (),
+                Err(Error::Eof(_)) => return Ok(nodes),
+                Err(err) => return Err(err),
+            }
+            if parser.lexer.eof() {
+                return Ok(nodes);
+            }
+            if let Ok(comment_node) = parser.parse_comment_node() {
+                nodes.push(comment_node);
+            } else if parser.lexer.peek_chars(1)?.1 == "<" {
+                nodes.push(parser.parse_node()?);
+            } else {
+                nodes.push(parser.parse_text_node()?);
+            }
+        }
+    }
+
+    // Like parse(), but passes the nodes to sink as they're parsed instead of
+    // returning them
+    pub fn parse_with_sink(input: &str, sink: &mut dyn TreeSink) -> Result<()> {
+        let mut parser = HtmlParser::new(input);
+        let _ = parser.try(HtmlParser::parse_doctype);
+        parser.parse_node_with_sink(Some(sink))?;
+        Ok(())
     }
 }
 
@@ -284,6 +407,11 @@ impl Parser<Error> for HtmlParser {
     fn lexer_immut(&self) -> &Lexer {
         &self.lexer
     }
+
+    // Allow namespaced attributes like xlink:href
+    fn is_attr_identifier_char(&self, ch: char) -> bool {
+        ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == ':'
+    }
 }
 
 #[cfg(test)]
@@ -458,6 +586,26 @@ mod tests {
         assert_eq!(parser.pos(), (1, 1, 2));
     }
 
+    #[test]
+    fn test_parse_tag_attributes_namespaced() {
+        let mut parser = HtmlParser::new("xlink:href=\"#a\" xmlns:xlink");
+        let res = parser.parse_tag_attributes();
+        assert_eq!(
+            res,
+            Ok(vec![
+                (
+                    Token::AttrIdentifier((0, 1, 1), "xlink:href".to_string()),
+                    Some(Token::Str((11, 1, 12), "#a".to_string())),
+                ),
+                (
+                    Token::AttrIdentifier((16, 1, 17), "xmlns:xlink".to_string()),
+                    None,
+                ),
+            ],)
+        );
+        assert_eq!(parser.pos(), (27, 1, 28));
+    }
+
     #[test]
     fn test_try_parse1() {
         let mut parser = HtmlParser::new("abc");
@@ -842,6 +990,24 @@ mod tests {
         assert_eq!(parser.pos(), (21, 1, 22));
     }
 
+    #[test]
+    fn test_parse_node_with_comment_children() {
+        let mut parser = HtmlParser::new("<p>a<!-- b -->c <!--\nd-->\n<img></p>");
+        let res = parser.parse_node().unwrap();
+        assert_eq!(
+            res.children.iter().map(|child| &child.elem_type).collect::<Vec<_>>(),
+            vec![
+                &ElemType::Text("a".to_string()),
+                &ElemType::Comment(" b ".to_string()),
+                &ElemType::Text("c".to_string()),
+                &ElemType::Comment("\nd".to_string()),
+                &ElemType::Img,
+            ]
+        );
+        assert_eq!(res.children[4].pos, (26, 3, 1));
+        assert_eq!(parser.pos(), (35, 3, 10));
+    }
+
     #[test]
     fn test_parse_opening_tag_is_void_elem1() {
         let mut parser = HtmlParser::new("<img src=\"abc\">");
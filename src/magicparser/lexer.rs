@@ -236,6 +236,59 @@ impl Lexer {
         }
     }
 
+    // Like consume_whitespace(), but stops at a block comment instead of
+    // skipping it, for parsers that keep comments
+    pub fn consume_whitespace_before_comment(&mut self) -> Result<()> {
+        let mut found_whitespace = false;
+        while !self.at_block_comment() {
+            match self.get_char(self.index) {
+                Ok(' ') | Ok('\t') | Ok('\n') => {
+                    found_whitespace = true;
+                    self.consume_char()?;
+                }
+                Ok(_) => break,
+                Err(err) => if !found_whitespace {
+                    return Err(err);
+                } else {
+                    break;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub fn at_block_comment(&self) -> bool {
+        !self.block_comment_start.is_empty()
+            && self.get_chars(self.index, &self.block_comment_start).is_ok()
+    }
+
+    // Consumes the block comment at the current position, returning its
+    // contents. Doesn't advance on failure, e.g. for an unclosed comment.
+    pub fn consume_block_comment(&mut self) -> Result<(Pos, String)> {
+        let start_pos = self.pos();
+        if !self.at_block_comment() {
+            return Err(Error::Unexpected(start_pos, "expected comment".to_string()));
+        }
+        let start = self.index + self.block_comment_start.len();
+        let mut end = start;
+        while self.get_chars(end, &self.block_comment_end).is_err() {
+            if end >= self.input.len() {
+                return Err(Error::Unexpected(start_pos, "unclosed comment".to_string()));
+            }
+            end += 1;
+        }
+        for ch in &self.input[self.index..end + self.block_comment_end.len()] {
+            if *ch == '\n' {
+                self.row += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        self.index = end + self.block_comment_end.len();
+        Ok((start_pos, self.input[start..end].iter().collect()))
+    }
+
     // Does not advance parser on failure since it peeks first (maybe it should?)
     pub fn try_parse_one_char(&mut self, ch: char) -> Result<Pos> {
         self.consume_whitespace()?;
@@ -0,0 +1,110 @@
+/// Builds a DOM tree from selector-like markup, e.g. for tests,
+/// `dom!(div#main.a[data-x="1"][hidden] { p { "Text" } my-widget span.b })`.
+///
+/// Each element is a tag name followed by any number of `#id`, `.class`,
+/// `[name="value"]` and `[name]` parts, and then optionally its children in
+/// braces. Text is given as a string literal. Names can have hyphens, and
+/// values can be any `&str` expression.
+///
+/// Returns the node if there's only one at the top level, or else a fragment
+/// holding them. The elements are in the HTML namespace; see
+/// DomNode::builder() for building others.
+#[macro_export]
+macro_rules! dom {
+    // The nodes so far are kept in the brackets, as expressions building
+    // them, while the rest of the tokens are munched
+    (@nodes [$($done:expr,)*];) => {
+        vec![$($done),*]
+    };
+    (@nodes [$($done:expr,)*]; $text:literal $($rest:tt)*) => {
+        dom!(@nodes [$($done,)* $crate::magicparser::DomNode::builder(
+            $crate::magicparser::ElemType::Text($text.to_string()),
+        ).build(),]; $($rest)*)
+    };
+    (@nodes [$($done:expr,)*]; $tag:ident $($rest:tt)*) => {
+        dom!(@tag [$($done,)*] stringify!($tag); $($rest)*)
+    };
+    (@tag [$($done:expr,)*] $name:expr; - $next:tt $($rest:tt)*) => {
+        dom!(@tag [$($done,)*] concat!($name, "-", stringify!($next)); $($rest)*)
+    };
+    (@tag [$($done:expr,)*] $name:expr; $($rest:tt)*) => {
+        dom!(@element [$($done,)*] $crate::magicparser::DomNode::builder(
+            $crate::magicparser::ElemType::from($name),
+        ); $($rest)*)
+    };
+    (@element [$($done:expr,)*] $builder:expr; # $id:tt $($rest:tt)*) => {
+        dom!(@id [$($done,)*] $builder, stringify!($id); $($rest)*)
+    };
+    (@element [$($done:expr,)*] $builder:expr; . $class:tt $($rest:tt)*) => {
+        dom!(@class [$($done,)*] $builder, stringify!($class); $($rest)*)
+    };
+    (@element [$($done:expr,)*] $builder:expr; [$name:tt $($attr:tt)*] $($rest:tt)*) => {
+        dom!(@element [$($done,)*] dom!(@attr $builder, stringify!($name); $($attr)*);
+             $($rest)*)
+    };
+    (@element [$($done:expr,)*] $builder:expr; {$($children:tt)*} $($rest:tt)*) => {
+        dom!(@nodes [$($done,)* {
+            let mut builder = $builder;
+            for child in dom!(@nodes []; $($children)*) {
+                builder = builder.child(child);
+            }
+            builder.build()
+        },]; $($rest)*)
+    };
+    (@element [$($done:expr,)*] $builder:expr; $($rest:tt)*) => {
+        dom!(@nodes [$($done,)* $builder.build(),]; $($rest)*)
+    };
+    (@id [$($done:expr,)*] $builder:expr, $name:expr; - $next:tt $($rest:tt)*) => {
+        dom!(@id [$($done,)*] $builder, concat!($name, "-", stringify!($next)); $($rest)*)
+    };
+    (@id [$($done:expr,)*] $builder:expr, $name:expr; $($rest:tt)*) => {
+        dom!(@element [$($done,)*] $builder.id($name); $($rest)*)
+    };
+    (@class [$($done:expr,)*] $builder:expr, $name:expr; - $next:tt $($rest:tt)*) => {
+        dom!(@class [$($done,)*] $builder, concat!($name, "-", stringify!($next)); $($rest)*)
+    };
+    (@class [$($done:expr,)*] $builder:expr, $name:expr; $($rest:tt)*) => {
+        dom!(@element [$($done,)*] $builder.class($name); $($rest)*)
+    };
+    (@attr $builder:expr, $name:expr; - $next:tt $($rest:tt)*) => {
+        dom!(@attr $builder, concat!($name, "-", stringify!($next)); $($rest)*)
+    };
+    (@attr $builder:expr, $name:expr; = $value:expr) => {
+        $builder.attr($name, $value)
+    };
+    (@attr $builder:expr, $name:expr;) => {
+        $builder.bool_attr($name)
+    };
+    ($($tokens:tt)+) => {{
+        let mut nodes: Vec<$crate::magicparser::DomNodeRef> = dom!(@nodes []; $($tokens)+);
+        if nodes.len() == 1 {
+            nodes.pop().unwrap()
+        } else {
+            $crate::magicparser::DomNodeRef::new_fragment(nodes)
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use magicparser::{parse_html, parse_html_fragment};
+
+    #[test]
+    fn test_dom_macro() {
+        let title = "A title";
+        let root = dom!(div#main-1.a.b-c[data-x="1"][hidden][title=title] {
+            p { "One" a.link { "Two" } }
+            my-widget
+            "Three"
+        });
+        let html = "<div id=\"main-1\" class=\"a b-c\" data-x=\"1\" hidden title=\"A title\">\
+                    <p>One<a class=\"link\">Two</a></p><my-widget></my-widget>Three</div>";
+        assert!(root.is_equal_node(&parse_html(html).unwrap()));
+        assert_eq!(root.borrow().id.as_deref(), Some("main-1"));
+        let p = root.borrow().children[0].clone();
+        assert!(p.parent().unwrap().ptr_eq(&root));
+
+        let fragment = dom!(p "Text" img);
+        assert!(fragment.is_equal_node(&parse_html_fragment("<p></p>Text<img>").unwrap()));
+    }
+}
@@ -0,0 +1,380 @@
+/// Comparison in a media feature, e.g. the `>=` of `(width >= 600px)`.
+/// `min-` and `max-` prefixes are parsed as Ge and Le.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum MediaFeatureOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl MediaFeatureOp {
+    // For when the value comes before the name, e.g. (600px <= width)
+    fn reverse(self) -> MediaFeatureOp {
+        match self {
+            MediaFeatureOp::Eq => MediaFeatureOp::Eq,
+            MediaFeatureOp::Lt => MediaFeatureOp::Gt,
+            MediaFeatureOp::Le => MediaFeatureOp::Ge,
+            MediaFeatureOp::Gt => MediaFeatureOp::Lt,
+            MediaFeatureOp::Ge => MediaFeatureOp::Le,
+        }
+    }
+}
+
+/// A parenthesized test of the device, e.g. `(min-width: 600px)`.
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+pub struct MediaFeature {
+    // Lowercase, without a min-/max- prefix
+    pub name: String,
+    // None in a boolean context, e.g. (color)
+    pub op_value: Option<(MediaFeatureOp, String)>,
+}
+
+impl MediaFeature {
+    pub fn new(name: String, op_value: Option<(MediaFeatureOp, String)>) -> MediaFeature {
+        MediaFeature { name, op_value }
+    }
+}
+
+/// e.g. `not screen and (orientation: landscape)`
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+pub struct MediaQuery {
+    pub negated: bool,
+    // Lowercase. None if there's no media type, which is the same as "all"
+    pub media_type: Option<String>,
+    // All must match
+    pub features: Vec<MediaFeature>,
+}
+
+impl MediaQuery {
+    pub fn new(
+        negated: bool,
+        media_type: Option<String>,
+        features: Vec<MediaFeature>,
+    ) -> MediaQuery {
+        MediaQuery {
+            negated,
+            media_type,
+            features,
+        }
+    }
+
+    // What invalid queries are replaced with
+    fn not_all() -> MediaQuery {
+        MediaQuery::new(true, Some("all".to_string()), vec![])
+    }
+}
+
+/// The comma-separated queries of an @media rule, which applies if any of them
+/// match. An empty list always matches.
+#[derive(Debug, Eq, PartialEq, Clone, Hash, Default)]
+pub struct MediaQueryList(pub Vec<MediaQuery>);
+
+impl MediaQueryList {
+    /// Queries that fail to parse become `not all`, so they never match.
+    pub fn parse(input: &str) -> MediaQueryList {
+        if input.trim().is_empty() {
+            return MediaQueryList::default();
+        }
+        MediaQueryList(
+            input
+                .split(',')
+                .map(|query| parse_media_query(query).unwrap_or_else(MediaQuery::not_all))
+                .collect(),
+        )
+    }
+}
+
+/// The condition of an @container rule, e.g. `sidebar (min-width: 400px)`,
+/// which is tested against the size of the nearest ancestor container with the
+/// name.
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+pub struct ContainerQuery {
+    // As written, since container names are case-sensitive. None if any
+    // container can be queried.
+    pub name: Option<String>,
+    pub negated: bool,
+    // All must match
+    pub features: Vec<MediaFeature>,
+}
+
+impl ContainerQuery {
+    pub fn new(
+        name: Option<String>,
+        negated: bool,
+        features: Vec<MediaFeature>,
+    ) -> ContainerQuery {
+        ContainerQuery {
+            name,
+            negated,
+            features,
+        }
+    }
+
+    /// Returns None if input isn't a valid container condition, which is
+    /// `name? [not] (feature) [and (feature)]*`.
+    pub fn parse(input: &str) -> Option<ContainerQuery> {
+        let mut rest = input.trim_start();
+        let mut name = None;
+        if !rest.starts_with('(') {
+            let end = rest
+                .find(|ch: char| ch.is_whitespace() || ch == '(')
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            let is_keyword = ["none", "and", "or", "not"]
+                .iter()
+                .any(|keyword| word.eq_ignore_ascii_case(keyword));
+            if !is_keyword {
+                if !is_identifier(word) {
+                    return None;
+                }
+                name = Some(word.to_string());
+                rest = &rest[end..];
+            }
+        }
+        let tokens = tokenize_media_query(rest)?;
+        let mut tokens = tokens.into_iter().peekable();
+        let negated = match tokens.peek() {
+            Some(QueryToken::Word(word)) if word == "not" => {
+                tokens.next();
+                true
+            }
+            _ => false,
+        };
+        let mut features = vec![];
+        match tokens.next()? {
+            QueryToken::Parens(feature) => features.push(parse_media_feature(feature)?),
+            QueryToken::Word(_) => return None,
+        }
+        while let Some(token) = tokens.next() {
+            match (token, tokens.next()) {
+                (QueryToken::Word(ref word), Some(QueryToken::Parens(feature)))
+                    if word == "and" && !negated =>
+                {
+                    features.push(parse_media_feature(feature)?);
+                }
+                _ => return None,
+            }
+        }
+        Some(ContainerQuery::new(name, negated, features))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum QueryToken<'a> {
+    Word(String),
+    // Contents of (...)
+    Parens(&'a str),
+}
+
+fn tokenize_media_query(input: &str) -> Option<Vec<QueryToken<'_>>> {
+    let mut tokens = vec![];
+    let mut rest = input.trim_start();
+    while !rest.is_empty() {
+        if let Some(after_paren) = rest.strip_prefix('(') {
+            let end = after_paren.find(')')?;
+            tokens.push(QueryToken::Parens(&after_paren[..end]));
+            rest = &after_paren[end + 1..];
+        } else {
+            let end = rest
+                .find(|ch: char| ch.is_whitespace() || ch == '(')
+                .unwrap_or(rest.len());
+            tokens.push(QueryToken::Word(rest[..end].to_ascii_lowercase()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Some(tokens)
+}
+
+fn is_identifier(st: &str) -> bool {
+    !st.is_empty() && st.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+        && !st.starts_with(|ch: char| ch.is_ascii_digit())
+}
+
+fn parse_media_feature(input: &str) -> Option<MediaFeature> {
+    let input = input.trim();
+    if let Some(colon) = input.find(':') {
+        let name = input[..colon].trim().to_ascii_lowercase();
+        let value = input[colon + 1..].trim().to_string();
+        if !is_identifier(&name) || value.is_empty() {
+            return None;
+        }
+        let (op, name) = if let Some(name) = name.strip_prefix("min-") {
+            (MediaFeatureOp::Ge, name.to_string())
+        } else if let Some(name) = name.strip_prefix("max-") {
+            (MediaFeatureOp::Le, name.to_string())
+        } else {
+            (MediaFeatureOp::Eq, name)
+        };
+        return Some(MediaFeature::new(name, Some((op, value))));
+    }
+    let ops = [
+        ("<=", MediaFeatureOp::Le),
+        (">=", MediaFeatureOp::Ge),
+        ("<", MediaFeatureOp::Lt),
+        (">", MediaFeatureOp::Gt),
+        ("=", MediaFeatureOp::Eq),
+    ];
+    for &(op_str, op) in &ops {
+        if let Some(i) = input.find(op_str) {
+            let (lhs, rhs) = (input[..i].trim(), input[i + op_str.len()..].trim());
+            if lhs.is_empty() || rhs.is_empty() {
+                return None;
+            }
+            return if is_identifier(lhs) {
+                Some(MediaFeature::new(lhs.to_ascii_lowercase(), Some((op, rhs.to_string()))))
+            } else if is_identifier(rhs) {
+                Some(MediaFeature::new(
+                    rhs.to_ascii_lowercase(),
+                    Some((op.reverse(), lhs.to_string())),
+                ))
+            } else {
+                None
+            };
+        }
+    }
+    if is_identifier(input) {
+        Some(MediaFeature::new(input.to_ascii_lowercase(), None))
+    } else {
+        None
+    }
+}
+
+// [not | only] media_type [and (feature)]* | (feature) [and (feature)]*
+fn parse_media_query(input: &str) -> Option<MediaQuery> {
+    let tokens = tokenize_media_query(input)?;
+    let mut tokens = tokens.into_iter().peekable();
+    let mut negated = false;
+    let mut media_type = None;
+    let mut features = vec![];
+
+    let has_modifier = match tokens.peek() {
+        Some(QueryToken::Word(word)) if word == "not" || word == "only" => {
+            negated = word == "not";
+            true
+        }
+        _ => false,
+    };
+    if has_modifier {
+        tokens.next();
+    }
+    match tokens.next()? {
+        QueryToken::Word(ref word) if word == "and" || !is_identifier(word) => return None,
+        QueryToken::Word(word) => media_type = Some(word),
+        QueryToken::Parens(_) if has_modifier => return None,
+        QueryToken::Parens(feature) => features.push(parse_media_feature(feature)?),
+    }
+    while let Some(token) = tokens.next() {
+        match (token, tokens.next()) {
+            (QueryToken::Word(ref word), Some(QueryToken::Parens(feature))) if word == "and" => {
+                features.push(parse_media_feature(feature)?);
+            }
+            _ => return None,
+        }
+    }
+    Some(MediaQuery::new(negated, media_type, features))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feature(name: &str, op: MediaFeatureOp, value: &str) -> MediaFeature {
+        MediaFeature::new(name.to_string(), Some((op, value.to_string())))
+    }
+
+    #[test]
+    fn test_parse_media_query_list() {
+        assert_eq!(MediaQueryList::parse(" "), MediaQueryList(vec![]));
+        assert_eq!(
+            MediaQueryList::parse("screen, NOT Print and (Color)"),
+            MediaQueryList(vec![
+                MediaQuery::new(false, Some("screen".to_string()), vec![]),
+                MediaQuery::new(
+                    true,
+                    Some("print".to_string()),
+                    vec![MediaFeature::new("color".to_string(), None)],
+                ),
+            ])
+        );
+        assert_eq!(
+            MediaQueryList::parse("only screen and (min-width: 600px) and (orientation:landscape)"),
+            MediaQueryList(vec![MediaQuery::new(
+                false,
+                Some("screen".to_string()),
+                vec![
+                    feature("width", MediaFeatureOp::Ge, "600px"),
+                    feature("orientation", MediaFeatureOp::Eq, "landscape"),
+                ],
+            )])
+        );
+    }
+
+    #[test]
+    fn test_parse_media_feature_range() {
+        assert_eq!(
+            MediaQueryList::parse("(width >= 600px) and (400px > height)"),
+            MediaQueryList(vec![MediaQuery::new(
+                false,
+                None,
+                vec![
+                    feature("width", MediaFeatureOp::Ge, "600px"),
+                    feature("height", MediaFeatureOp::Lt, "400px"),
+                ],
+            )])
+        );
+        assert_eq!(
+            MediaQueryList::parse("(max-aspect-ratio: 16/9)"),
+            MediaQueryList(vec![MediaQuery::new(
+                false,
+                None,
+                vec![feature("aspect-ratio", MediaFeatureOp::Le, "16/9")],
+            )])
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_media_query() {
+        let not_all = MediaQuery::not_all();
+        assert_eq!(
+            MediaQueryList::parse("screen and, print"),
+            MediaQueryList(vec![
+                not_all.clone(),
+                MediaQuery::new(false, Some("print".to_string()), vec![]),
+            ])
+        );
+        assert_eq!(MediaQueryList::parse("(width"), MediaQueryList(vec![not_all.clone()]));
+        assert_eq!(MediaQueryList::parse("not (color)"), MediaQueryList(vec![not_all.clone()]));
+        assert_eq!(MediaQueryList::parse("screen print"), MediaQueryList(vec![not_all.clone()]));
+        assert_eq!(MediaQueryList::parse("(1px < 2px)"), MediaQueryList(vec![not_all]));
+    }
+
+    #[test]
+    fn test_parse_container_query() {
+        assert_eq!(
+            ContainerQuery::parse("Sidebar (min-width: 400px) and (height < 30em)"),
+            Some(ContainerQuery::new(
+                Some("Sidebar".to_string()),
+                false,
+                vec![
+                    feature("width", MediaFeatureOp::Ge, "400px"),
+                    feature("height", MediaFeatureOp::Lt, "30em"),
+                ],
+            ))
+        );
+        assert_eq!(
+            ContainerQuery::parse("not (orientation: portrait)"),
+            Some(ContainerQuery::new(
+                None,
+                true,
+                vec![feature("orientation", MediaFeatureOp::Eq, "portrait")],
+            ))
+        );
+        assert_eq!(ContainerQuery::parse("sidebar"), None);
+        assert_eq!(ContainerQuery::parse("none (width > 1px)"), None);
+        assert_eq!(ContainerQuery::parse("not (width > 1px) and (height > 1px)"), None);
+        assert_eq!(ContainerQuery::parse("(width > 1px) or (height > 1px)"), None);
+    }
+}
@@ -1,20 +1,70 @@
+#[macro_use]
+mod macros;
+
+mod arena;
+mod atom;
 mod cssparser;
+mod custom;
+mod document;
+mod dump;
 pub mod error;
+mod event;
+mod form;
 mod htmlparser;
 mod lexer;
+mod mediaquery;
 mod parser;
 mod postparse;
 mod selectorparser;
+mod serialize;
+mod shadow;
+mod smallmap;
+mod snapshot;
+mod state;
+mod synctree;
+mod text;
+mod traversal;
+mod treewalker;
+mod url;
 
-pub use self::postparse::{AttrSelector, AttrSelectorOp, Combinator, CssBlocks, DomNode,
-                          DomNodeRef, NthExpr, NthExprOp, PseudoClassSelector,
-                          PseudoElementSelector, Selector, SimpleSelector};
+pub use self::arena::{DomNodeRef, NodeId};
+pub use self::atom::Atom;
+pub use self::custom::{custom_element_definition, define_custom_element,
+                       is_valid_custom_element_name, CustomElementDefinition, UpgradeCallback};
+pub use self::document::{Doctype, Document, DocumentKind, QuirksMode};
+pub use self::event::{Event, EventListener, EventPhase, ListenerId};
+pub use self::form::FormState;
+pub use self::postparse::{AttrSelector, AttrSelectorOp, Children, ClassList, Combinator,
+                          CssBlocks, Declaration, DomNode, DomNodeBuilder, FontFaceRule,
+                          HtmlSink, ImportRule, InvalidationHook, Keyframe, KeyframesRule,
+                          NamespaceConstraint, NthExpr, NthExprOp, PseudoClassSelector,
+                          PseudoElementSelector, Selector, SimpleSelector, Specificity,
+                          StyleRule, Stylesheet,
+                          dom_revision, set_invalidation_hook,
+                          HTML_NAMESPACE, MATHML_NAMESPACE, SVG_NAMESPACE, XLINK_NAMESPACE,
+                          XMLNS_NAMESPACE, XML_NAMESPACE};
+pub use self::mediaquery::{
+    ContainerQuery, MediaFeature, MediaFeatureOp, MediaQuery, MediaQueryList,
+};
+pub use self::serialize::ToCss;
+pub use self::shadow::shadow_roots_attached;
+pub use self::smallmap::{SmallMap, SmallSet};
+pub use self::snapshot::NodeSnapshot;
+#[cfg(feature = "serde")]
+pub use self::snapshot::parse_json;
+pub use self::state::ElementState;
+pub use self::synctree::{SyncNode, SyncTree};
+pub use self::text::WhiteSpace;
+pub use self::url::resolve_url;
+pub use self::traversal::{Ancestors, Descendants, DomVisitor, FollowingSiblings,
+                          PrecedingSiblings, ShadowIncludingAncestors};
+pub use self::treewalker::{FilterResult, NodeFilter, TreeWalker};
 
 use std::convert::From;
 use std::fmt;
 
 // For running outside of cargo (e.g. lldb)
-pub static DEFAULT_CARGO_MANIFEST_DIR: &'static str = "/Users/chrisf/projects/magician";
+pub static DEFAULT_CARGO_MANIFEST_DIR: &str = "/Users/chrisf/projects/magician";
 
 pub type Pos = (usize, usize, usize); // index, row, col
 
@@ -55,10 +105,26 @@ impl fmt::Display for Token {
     }
 }
 
+/// Case-sensitivity modifier at the end of an attribute selector, e.g. `[type="a" i]`.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum AttrCaseFlag {
+    // No modifier; the value is compared case-sensitively
+    Unspecified,
+    // i or I
+    Insensitive,
+    // s or S
+    Sensitive,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub enum ElemType {
     Html,
     Text(String),
+    Comment(String),
+    // Holds nodes to be inserted together; see DomNodeRef::new_fragment()
+    Fragment,
+    // Root of a shadow tree; see DomNodeRef::attach_shadow()
+    ShadowRoot,
     Head,
     Body,
     Img,
@@ -67,10 +133,10 @@ pub enum ElemType {
     P,
     A,
     Div,
-    Custom(String),
+    Custom(Atom),
 }
 
-impl<'a> From<&'a str> for ElemType {
+impl From<&str> for ElemType {
     fn from(tag_id_str: &str) -> Self {
         match tag_id_str.to_ascii_lowercase().as_ref() {
             "html" => ElemType::Html,
@@ -82,12 +148,12 @@ impl<'a> From<&'a str> for ElemType {
             "p" => ElemType::P,
             "a" => ElemType::A,
             "div" => ElemType::Div,
-            custom => ElemType::Custom(custom.to_string()),
+            custom => ElemType::Custom(Atom::from(custom)),
         }
     }
 }
 
-impl<'a> From<&'a String> for ElemType {
+impl From<&String> for ElemType {
     fn from(tag_id_str: &String) -> Self {
         match tag_id_str.to_ascii_lowercase().as_ref() {
             "html" => ElemType::Html,
@@ -99,24 +165,65 @@ impl<'a> From<&'a String> for ElemType {
             "p" => ElemType::P,
             "a" => ElemType::A,
             "div" => ElemType::Div,
-            custom => ElemType::Custom(custom.to_string()),
+            custom => ElemType::Custom(Atom::from(custom)),
         }
     }
 }
 
 impl ElemType {
-    pub fn is_void_elem(&self) -> bool {
+    pub fn tag_name(&self) -> &str {
         match self {
-            ElemType::Img | ElemType::Link => true,
-            _ => false,
+            ElemType::Html => "html",
+            ElemType::Text(_) => "#text",
+            ElemType::Comment(_) => "#comment",
+            ElemType::Fragment => "#document-fragment",
+            ElemType::ShadowRoot => "#shadow-root",
+            ElemType::Head => "head",
+            ElemType::Body => "body",
+            ElemType::Img => "img",
+            ElemType::Link => "link",
+            ElemType::H1 => "h1",
+            ElemType::P => "p",
+            ElemType::A => "a",
+            ElemType::Div => "div",
+            ElemType::Custom(ref name) => name,
         }
     }
+
+    pub fn is_void_elem(&self) -> bool {
+        matches!(self, ElemType::Img | ElemType::Link)
+    }
+
+    /// Returns false for text, comment, fragment and shadow root nodes, which
+    /// selectors don't match and structural pseudo-classes don't count.
+    pub fn is_element(&self) -> bool {
+        !matches!(
+            self,
+            ElemType::Text(_) | ElemType::Comment(_) | ElemType::Fragment | ElemType::ShadowRoot
+        )
+    }
 }
 
 pub fn parse_html(input: &str) -> error::Result<DomNodeRef> {
     Ok(DomNodeRef::from(htmlparser::HtmlParser::parse(input)?))
 }
 
+/// Parses a sequence of nodes, like the contents of an element, into a
+/// fragment holding them, which inserting moves them out of.
+pub fn parse_html_fragment(input: &str) -> error::Result<DomNodeRef> {
+    let children = postparse::parse_fragment(input, HTML_NAMESPACE)?;
+    Ok(DomNodeRef::new_fragment(children))
+}
+
+/// Like parse_html(), but builds the tree as the document is parsed, passing
+/// each node to sink as it's added. Nodes are passed to sink even if parsing
+/// fails later on.
+pub fn parse_html_with_sink(input: &str, sink: &mut dyn HtmlSink) -> error::Result<DomNodeRef> {
+    let mut builder = postparse::TreeBuilder::new(sink);
+    htmlparser::HtmlParser::parse_with_sink(input, &mut builder)?;
+    Ok(builder.root().unwrap())
+}
+
 pub fn parse_css(input: &str) -> error::Result<CssBlocks> {
     let (blocks, errs) = cssparser::CssParser::parse(input);
     for err in errs {
@@ -124,3 +231,130 @@ pub fn parse_css(input: &str) -> error::Result<CssBlocks> {
     }
     Ok(CssBlocks::from(blocks))
 }
+
+pub fn parse_stylesheet(input: &str) -> error::Result<Stylesheet> {
+    let (stylesheet, errs) = cssparser::CssParser::parse_stylesheet(input);
+    for err in errs {
+        eprintln!("warning: {:?}", err);
+    }
+    Ok(Stylesheet::from(stylesheet))
+}
+
+/// Parses a selector list, like those `querySelectorAll()` takes, e.g.
+/// `div > p, .a`.
+pub fn parse_selector(input: &str) -> error::Result<Selector> {
+    let selector = selectorparser::SelectorParser::parse(input, (0, 1, 1), &Default::default())?;
+    Ok(Selector::from(selector))
+}
+
+/// Parses the declarations of a style attribute, e.g. `color: red; margin: 0`.
+/// Invalid declarations are skipped.
+pub fn parse_style_attribute(input: &str) -> Vec<Declaration> {
+    let (declarations, errs) = cssparser::CssParser::parse_declaration_list(input);
+    for err in errs {
+        eprintln!("warning: {:?}", err);
+    }
+    postparse::convert_decl_block(declarations)
+}
+
+/// Parses a stylesheet along with the stylesheets it @imports, recursively.
+/// loader is called with the url of each @import rule, and returns the
+/// stylesheet's text, or None if it can't be loaded.
+///
+/// Imported rules come before the importing stylesheet's rules, in the order
+/// they were imported, and only apply if the @import rule's media queries
+/// match.
+pub fn parse_stylesheet_with_loader<F>(input: &str, mut loader: F) -> error::Result<Stylesheet>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut stylesheet = parse_stylesheet(input)?;
+    load_imports(&mut stylesheet, &mut loader, &mut vec![]);
+    Ok(stylesheet)
+}
+
+// urls are of the stylesheets currently being imported, so that an @import
+// cycle is only followed once
+fn load_imports<F>(stylesheet: &mut Stylesheet, loader: &mut F, urls: &mut Vec<String>)
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let (mut rules, mut font_faces, mut keyframes) = (vec![], vec![], vec![]);
+    let mut layers: Vec<String> = vec![];
+    for import in &stylesheet.imports {
+        if urls.contains(&import.url) {
+            eprintln!("warning: @import cycle at {}", import.url);
+            continue;
+        }
+        let mut imported = match loader(&import.url).map(|input| parse_stylesheet(&input)) {
+            Some(Ok(imported)) => imported,
+            _ => {
+                eprintln!("warning: failed to load {}", import.url);
+                continue;
+            }
+        };
+        urls.push(import.url.clone());
+        load_imports(&mut imported, loader, urls);
+        urls.pop();
+        for mut rule in imported.rules {
+            if !import.media.0.is_empty() {
+                rule.media.insert(0, import.media.clone());
+            }
+            rules.push(rule);
+        }
+        font_faces.append(&mut imported.font_faces);
+        keyframes.append(&mut imported.keyframes);
+        for layer in imported.layers {
+            if !layers.contains(&layer) {
+                layers.push(layer);
+            }
+        }
+    }
+    rules.append(&mut stylesheet.rules);
+    stylesheet.rules = rules;
+    font_faces.append(&mut stylesheet.font_faces);
+    stylesheet.font_faces = font_faces;
+    keyframes.append(&mut stylesheet.keyframes);
+    stylesheet.keyframes = keyframes;
+    // Layers first declared by imported stylesheets come first
+    for layer in stylesheet.layers.drain(..) {
+        if !layers.contains(&layer) {
+            layers.push(layer);
+        }
+    }
+    stylesheet.layers = layers;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_stylesheet_with_loader() {
+        let mut files = HashMap::new();
+        files.insert("a.css", "@import url(b.css) print; a { color: red }");
+        files.insert("b.css", "@import 'a.css'; b { color: red }");
+        let mut loaded = vec![];
+        let stylesheet = parse_stylesheet_with_loader(
+            "@import 'a.css'; @import 'missing.css'; c { color: blue }",
+            |url| {
+                loaded.push(url.to_string());
+                files.get(url).map(|input| input.to_string())
+            },
+        ).unwrap();
+        // a.css imported from b.css is a cycle, so it isn't loaded again
+        assert_eq!(loaded, vec!["a.css", "b.css", "missing.css"]);
+        let selectors = stylesheet
+            .rules
+            .iter()
+            .map(|rule| (rule.selector.to_css_string(), rule.media.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            selectors,
+            vec![("b".to_string(), 1), ("a".to_string(), 0), ("c".to_string(), 0)]
+        );
+        assert_eq!(stylesheet.rules[0].media[0], MediaQueryList::parse("print"));
+        assert_eq!(stylesheet.imports.len(), 2);
+    }
+}
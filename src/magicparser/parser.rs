@@ -3,6 +3,8 @@ use magicparser::lexer::Lexer;
 use magicparser::{Pos, Token};
 use std::convert::From;
 
+type TryParserFn<P, T, E> = fn(&mut P) -> Result<T, E>;
+
 pub trait Parser<E: From<Error> + MultipleErrors<Error>> {
     fn lexer(&mut self) -> &mut Lexer;
     fn lexer_immut(&self) -> &Lexer;
@@ -15,6 +17,11 @@ pub trait Parser<E: From<Error> + MultipleErrors<Error>> {
         self.lexer_immut().pos()
     }
 
+    // Chars allowed in an attribute identifier after the first (alphabetic) char
+    fn is_attr_identifier_char(&self, ch: char) -> bool {
+        ch.is_ascii_alphanumeric() || ch == '-' || ch == '_'
+    }
+
     fn parse_attr_identifier(&mut self) -> Result<Token, E> {
         self.lexer().consume_whitespace()?;
         self.parse_attr_identifier_strict()
@@ -36,7 +43,7 @@ pub trait Parser<E: From<Error> + MultipleErrors<Error>> {
             Err(err) => return Err(E::from(err)),
         }
         while let Ok((_, ch)) = self.lexer().peek_char() {
-            if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' {
+            if self.is_attr_identifier_char(ch) {
                 id.push(ch);
                 self.lexer().consume_char()?;
             } else {
@@ -192,7 +199,7 @@ pub trait Parser<E: From<Error> + MultipleErrors<Error>> {
 
     fn try_parsers_rec<T>(
         &mut self,
-        parsers: &[fn(&mut Self) -> Result<T, E>],
+        parsers: &[TryParserFn<Self, T, E>],
         mut errors: Vec<E>,
     ) -> Result<T, Vec<E>> {
         if parsers.is_empty() {
@@ -213,7 +220,7 @@ pub trait Parser<E: From<Error> + MultipleErrors<Error>> {
         }
     }
 
-    fn try_parsers<T>(&mut self, parsers: &[fn(&mut Self) -> Result<T, E>]) -> Result<T, E> {
+    fn try_parsers<T>(&mut self, parsers: &[TryParserFn<Self, T, E>]) -> Result<T, E> {
         if parsers.is_empty() {
             panic!("cannot call try_parsers() with no parsers!");
         }
@@ -1,41 +1,146 @@
-use magicparser::cssparser::{CssBlocks as CPCssBlocks, Token as CPToken};
-use magicparser::htmlparser::DomNode as HPDomNode;
+use magicparser::cssparser::{CssBlocks as CPCssBlocks, CssParser,
+                             CssStylesheet as CPCssStylesheet, Token as CPToken};
+use magicparser::document::{self, DocumentKind};
+use magicparser::error::{Error, Result};
+use magicparser::htmlparser::{DomNode as HPDomNode, HtmlParser, TreeSink};
 use magicparser::selectorparser::{AttrSelector as SPAttrSelector,
                                   AttrSelectorOp as SPAttrSelectorOp, Combinator as SPCombinator,
-                                  NthExpr as SPNthExpr, NthExprOp as SPNthExprOp,
+                                  NamespacePrefix as SPNamespacePrefix, NthExpr as SPNthExpr,
+                                  NthExprOp as SPNthExprOp,
                                   PseudoClassSelector as SPPseudoClassSelector,
                                   PseudoElementSelector as SPPseudoElementSelector,
                                   Selector as SPSelector, SimpleSelector as SPSimpleSelector};
-use magicparser::{ElemType, Token};
-use std::cell::{Ref, RefCell, RefMut};
+use magicparser::mediaquery::{ContainerQuery, MediaQueryList};
+use magicparser::{AttrCaseFlag, Atom, DomNodeRef, ElemType, ElementState, FormState, NodeId,
+                  SmallMap, SmallSet, Token};
+use smallvec::SmallVec;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::convert::From;
-use std::rc::{Rc, Weak};
-use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::mem;
+use std::ops::Add;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static DOM_NODE_NEXT_ID_NUM: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Per thread, like the arena the thread's nodes live in
+    static DOM_REVISION: Cell<usize> = const { Cell::new(0) };
+    static INVALIDATION_HOOK: RefCell<Option<Rc<dyn InvalidationHook>>> =
+        const { RefCell::new(None) };
+}
+
+/// Returns the revision of the current thread's DOM nodes, which is bumped by
+/// each change made through the DomNodeRef mutation methods, like add_child()
+/// and set_attribute(). Results computed from the DOM at one revision still hold
+/// while it's unchanged. Changes made to DomNode fields directly don't bump
+/// it. See also Document::revision(), for the changes to one document.
+pub fn dom_revision() -> usize {
+    DOM_REVISION.with(|revision| revision.get())
+}
+
+/// Bumps the revision for a change to dom_node, along with that of its
+/// document, if it's in one.
+pub fn bump_dom_revision(dom_node: &DomNodeRef) {
+    DOM_REVISION.with(|revision| revision.set(revision.get() + 1));
+    document::tree_changed(dom_node);
+}
 
-static DOM_NODE_NEXT_ID_NUM: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Decides which nodes a change to an attribute of dom_node can restyle, and
+/// marks them dirty, e.g. style::invalidation::InvalidationMap. Values are
+/// None for attributes without a value, or that aren't set.
+pub trait InvalidationHook {
+    fn attr_changed(
+        &self,
+        dom_node: &DomNodeRef,
+        name: &str,
+        old_value: Option<String>,
+        new_value: Option<String>,
+    );
+}
+
+/// Sets the hook that DomNodeRef::set_attribute() and the like tell about
+/// changes to the current thread's nodes, returning the previous one. Without
+/// a hook, they mark the node, its descendants and its later siblings dirty.
+pub fn set_invalidation_hook(
+    hook: Option<Rc<dyn InvalidationHook>>,
+) -> Option<Rc<dyn InvalidationHook>> {
+    INVALIDATION_HOOK.with(|current| current.replace(hook))
+}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct DomNodeRef {
-    ptr: Rc<RefCell<DomNode>>,
+pub static HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+pub static SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+pub static MATHML_NAMESPACE: &str = "http://www.w3.org/1998/Math/MathML";
+pub static XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+pub static XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+pub static XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+// Splits a qualified attribute name like xlink:href into its namespace and
+// local name. As in HTML, only the xlink, xml, and xmlns prefixes are
+// recognized; any other name is in no namespace.
+fn split_attr_name(name: &str) -> (Option<&'static str>, &str) {
+    if name == "xmlns" {
+        return (Some(XMLNS_NAMESPACE), name);
+    }
+    match name.find(':') {
+        Some(i) => match &name[..i] {
+            "xlink" => (Some(XLINK_NAMESPACE), &name[i + 1..]),
+            "xml" => (Some(XML_NAMESPACE), &name[i + 1..]),
+            "xmlns" => (Some(XMLNS_NAMESPACE), &name[i + 1..]),
+            _ => (None, name),
+        },
+        None => (None, name),
+    }
 }
 
+/// A node's children. Most nodes have few enough to be stored inline.
+pub type Children = SmallVec<[DomNodeRef; 4]>;
+
 #[derive(Debug, Clone)]
 pub struct DomNode {
     pub id_num: usize,
     pub elem_type: ElemType,
-    pub id: Option<String>,
-    pub classes: HashSet<String>,
-    pub attrs: HashMap<String, Option<String>>,
-    pub parent: Option<Weak<RefCell<DomNode>>>,
-    pub children: Vec<DomNodeRef>,
+    pub id: Option<Atom>,
+    pub classes: SmallSet<Atom>,
+    // Keyed by name, lowercased for HTML elements
+    pub attrs: SmallMap<Atom, Option<String>>,
+    pub parent: Option<NodeId>,
+    // Position among the parent's children, starting at 0. Kept up to date by
+    // add_child() and remove_child(), so that child_index() needn't search.
+    pub index_in_parent: Option<usize>,
+    pub children: Children,
+    // Positions of the element children of each type, in order, for
+    // of-type pseudo-classes. Kept up to date like index_in_parent.
+    pub children_by_type: HashMap<ElemType, Vec<usize>>,
+    pub namespace: Option<String>, // namespace url
+    // Dynamic states like hover, which state pseudo-classes match
+    pub state: ElementState,
+    // The value and checkedness of form controls, where they've been changed
+    // from what the content attributes say
+    pub form_state: FormState,
+    // For custom elements, true once they've been upgraded with their
+    // definition
+    pub upgraded: bool,
+    // Root of the shadow tree attached to the element, if any
+    pub shadow_root: Option<DomNodeRef>,
+    // For shadow roots, the element they're attached to
+    pub host: Option<NodeId>,
+    // True if the node's style, and so its descendants' styles, need to be
+    // recomputed. Set for new nodes and by mutations.
+    pub style_dirty: bool,
+    // True if the node's style needs to be recomputed, but its descendants'
+    // styles only do if it changes
+    pub self_style_dirty: bool,
+    // True if some descendant has style_dirty or self_style_dirty set
+    pub descendants_dirty: bool,
 }
 
 impl PartialEq for DomNode {
     fn eq(&self, other: &DomNode) -> bool {
         self.id_num == other.id_num && self.elem_type == other.elem_type && self.id == other.id
             && self.classes == other.classes && self.attrs == other.attrs
-            && self.children == other.children
+            && self.children == other.children && self.namespace == other.namespace
     }
 }
 
@@ -47,54 +152,654 @@ impl DomNode {
         id: Option<String>,
         classes: HashSet<String>,
         attrs: HashMap<String, Option<String>>,
-        parent: Option<Weak<RefCell<DomNode>>>,
+        parent: Option<NodeId>,
         children: Vec<DomNodeRef>,
     ) -> DomNode {
-        let id_num = DOM_NODE_NEXT_ID_NUM.fetch_add(1, Ordering::SeqCst);
-        DomNode {
-            id_num,
+        Self::new_with_namespace(
             elem_type,
             id,
             classes,
             attrs,
             parent,
             children,
+            Some(HTML_NAMESPACE.to_string()),
+        )
+    }
+
+    pub fn new_with_namespace(
+        elem_type: ElemType,
+        id: Option<String>,
+        classes: HashSet<String>,
+        attrs: HashMap<String, Option<String>>,
+        parent: Option<NodeId>,
+        children: Vec<DomNodeRef>,
+        namespace: Option<String>,
+    ) -> DomNode {
+        let id_num = DOM_NODE_NEXT_ID_NUM.fetch_add(1, Ordering::SeqCst);
+        DomNode {
+            id_num,
+            elem_type,
+            id: id.map(Atom::from),
+            classes: classes.into_iter().map(Atom::from).collect(),
+            attrs: attrs.into_iter().map(|(name, value)| (Atom::from(name), value)).collect(),
+            parent,
+            index_in_parent: None,
+            children: Children::from_vec(children),
+            children_by_type: HashMap::new(),
+            namespace,
+            state: ElementState::empty(),
+            form_state: FormState::default(),
+            upgraded: false,
+            shadow_root: None,
+            host: None,
+            style_dirty: true,
+            self_style_dirty: false,
+            descendants_dirty: false,
+        }
+    }
+
+    /// Starts building a node of elem_type, e.g.
+    /// `DomNode::builder(ElemType::A).class("nav").attr("href", "/").text("Home")`,
+    /// which is in the HTML namespace unless it's given another one.
+    pub fn builder(elem_type: ElemType) -> DomNodeBuilder {
+        DomNodeBuilder {
+            elem_type,
+            attrs: vec![],
+            namespace: HTML_NAMESPACE.to_string(),
+            children: vec![],
         }
     }
 
-    pub fn to_dnref(self) -> DomNodeRef {
-        DomNodeRef {
-            ptr: Rc::new(RefCell::new(self)),
+    /// Looks up an attribute by its full name, e.g. `xlink:href`.
+    pub fn attr(&self, name: &str) -> Option<&Option<String>> {
+        self.attrs.get(&Atom::lookup(name)?)
+    }
+
+    pub fn has_class(&self, class: &str) -> bool {
+        Atom::lookup(class).is_some_and(|class| self.classes.contains(&class))
+    }
+
+    // Rebuilds children_by_type from children
+    fn index_children_by_type(&mut self) {
+        let mut children_by_type: HashMap<ElemType, Vec<usize>> = HashMap::new();
+        for (i, child) in self.children.iter().enumerate() {
+            let child = child.borrow();
+            if child.elem_type.is_element() {
+                children_by_type.entry(child.elem_type.clone()).or_default().push(i);
+            }
         }
+        self.children_by_type = children_by_type;
     }
+
+    /// Returns true if the node has no children but comments, as :empty
+    /// matches.
+    pub fn is_empty(&self) -> bool {
+        self.children
+            .iter()
+            .all(|child| matches!(child.borrow().elem_type, ElemType::Comment(_)))
+    }
+
+    /// Looks up an attribute by local name, restricted to the attributes in
+    /// namespace (e.g. xlink|href finds the xlink:href attribute).
+    pub fn attr_ns(
+        &self,
+        local_name: &str,
+        namespace: &NamespaceConstraint,
+    ) -> Option<&Option<String>> {
+        self.find_attr_ns(local_name, namespace, false)
+    }
+
+    /// Like attr_ns(), but compares local names ASCII-case-insensitively, as
+    /// HTML documents do.
+    pub fn attr_ns_ignore_ascii_case(
+        &self,
+        local_name: &str,
+        namespace: &NamespaceConstraint,
+    ) -> Option<&Option<String>> {
+        self.find_attr_ns(local_name, namespace, true)
+    }
+
+    fn find_attr_ns(
+        &self,
+        local_name: &str,
+        namespace: &NamespaceConstraint,
+        ignore_ascii_case: bool,
+    ) -> Option<&Option<String>> {
+        self.attrs
+            .iter()
+            .find(|(name, _)| {
+                let (attr_namespace, attr_local_name) = split_attr_name(name);
+                let name_matches = if ignore_ascii_case {
+                    attr_local_name.eq_ignore_ascii_case(local_name)
+                } else {
+                    attr_local_name == local_name
+                };
+                name_matches && namespace.matches(attr_namespace)
+            })
+            .map(|(_, value)| value)
+    }
+
 }
 
-impl DomNodeRef {
-    pub fn borrow_mut(&self) -> RefMut<DomNode> {
-        self.ptr.borrow_mut()
+/// Builds a DomNodeRef piece by piece; see DomNode::builder(). The id and
+/// classes are kept in the `id` and `class` attributes, as if the node were
+/// parsed.
+#[derive(Debug, Clone)]
+pub struct DomNodeBuilder {
+    elem_type: ElemType,
+    // In the order they're set
+    attrs: Vec<(String, Option<String>)>,
+    namespace: String,
+    children: Vec<DomNodeRef>,
+}
+
+impl DomNodeBuilder {
+    pub fn id(self, id: &str) -> Self {
+        self.attr("id", id)
+    }
+
+    /// Adds a class, after those already added.
+    pub fn class(mut self, class: &str) -> Self {
+        let classes = self.attrs.iter_mut().find(|(name, _)| name == "class");
+        match classes {
+            Some((_, Some(classes))) => {
+                classes.push(' ');
+                classes.push_str(class);
+            }
+            _ => return self.attr("class", class),
+        }
+        self
+    }
+
+    /// Sets an attribute, replacing its value if it's already set.
+    pub fn attr(self, name: &str, value: &str) -> Self {
+        self.set_attr(name, Some(value.to_string()))
+    }
+
+    /// Sets an attribute without a value, like `hidden`.
+    pub fn bool_attr(self, name: &str) -> Self {
+        self.set_attr(name, None)
+    }
+
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.namespace = namespace.to_string();
+        self
+    }
+
+    /// Appends a child, e.g. another builder.
+    pub fn child<C: Into<DomNodeRef>>(mut self, child: C) -> Self {
+        self.children.push(child.into());
+        self
     }
 
-    pub fn borrow(&self) -> Ref<DomNode> {
-        self.ptr.borrow()
+    /// Appends a text node.
+    pub fn text(self, text: &str) -> Self {
+        let elem_type = ElemType::Text(text.to_string());
+        self.child(DomNode::builder(elem_type))
     }
 
+    pub fn build(self) -> DomNodeRef {
+        let attr = |name: &str| {
+            self.attrs
+                .iter()
+                .find(|(attr_name, _)| attr_name == name)
+                .and_then(|(_, value)| value.clone())
+        };
+        let id = attr("id");
+        let classes = attr("class").map_or(HashSet::new(), |classes| {
+            classes.split_whitespace().map(str::to_string).collect()
+        });
+        let is_element = self.elem_type.is_element();
+        let mut dom_node = DomNode::new_with_namespace(
+            self.elem_type.clone(),
+            id,
+            classes,
+            HashMap::new(),
+            None,
+            vec![],
+            Some(self.namespace.clone()),
+        );
+        // Kept in order, unlike through new()
+        dom_node.attrs = self
+            .attrs
+            .iter()
+            .map(|(name, value)| (Atom::from(name.as_str()), value.clone()))
+            .collect();
+        if !is_element {
+            dom_node.namespace = None;
+        }
+        let dom_node = dom_node.to_dnref();
+        dom_node.add_children(self.children);
+        dom_node
+    }
+
+    fn set_attr(mut self, name: &str, value: Option<String>) -> Self {
+        match self.attrs.iter_mut().find(|(attr_name, _)| attr_name == name) {
+            Some(attr) => attr.1 = value,
+            None => self.attrs.push((name.to_string(), value)),
+        }
+        self
+    }
+}
+
+impl From<DomNodeBuilder> for DomNodeRef {
+    fn from(builder: DomNodeBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl DomNodeRef {
     pub fn parent(&self) -> Option<DomNodeRef> {
-        if let Some(ref parent) = self.borrow().parent {
-            parent.upgrade().map(|ptr| DomNodeRef { ptr })
+        let parent = self.borrow().parent;
+        parent.and_then(DomNodeRef::from_node_id)
+    }
+
+    pub fn add_child(&self, child: DomNodeRef) -> &Self {
+        bump_dom_revision(self);
+        let was_empty = {
+            let mut node = self.borrow_mut();
+            let was_empty = node.is_empty();
+            let mut child_node = child.borrow_mut();
+            child_node.parent = Some(self.node_id());
+            let index = node.children.len();
+            child_node.index_in_parent = Some(index);
+            if child_node.elem_type.is_element() {
+                let elem_type = child_node.elem_type.clone();
+                node.children_by_type.entry(elem_type).or_default().push(index);
+            }
+            node.children.push(child.clone());
+            was_empty
+        };
+        document::subtree_inserted(&child);
+        child.mark_style_dirty();
+        // For :empty
+        if was_empty && !self.borrow().is_empty() {
+            self.mark_self_style_dirty();
+        }
+        self
+    }
+
+    /// Returns false if child isn't a child of this node.
+    pub fn remove_child(&self, child: &DomNodeRef) -> bool {
+        let position = self.borrow().children.iter().position(|node| node.ptr_eq(child));
+        let index = match position {
+            Some(index) => index,
+            None => return false,
+        };
+        bump_dom_revision(self);
+        document::subtree_removing(child);
+        self.borrow_mut().children.remove(index);
+        for (i, sibling) in self.borrow().children.iter().enumerate().skip(index) {
+            sibling.borrow_mut().index_in_parent = Some(i);
+        }
+        self.borrow_mut().index_children_by_type();
+        {
+            let mut child = child.borrow_mut();
+            child.parent = None;
+            child.index_in_parent = None;
+        }
+        // For :empty
+        if self.borrow().is_empty() && !matches!(child.borrow().elem_type, ElemType::Comment(_)) {
+            self.mark_self_style_dirty();
+        }
+        self.mark_children_style_dirty();
+        true
+    }
+
+    /// Removes the node from its parent, if it has one.
+    pub fn remove(&self) -> bool {
+        match self.parent() {
+            Some(parent) => parent.remove_child(self),
+            None => false,
+        }
+    }
+
+    /// Moves child to the end of this node's children, removing it from its
+    /// current parent first. Unlike add_child(), which is for building trees,
+    /// this keeps the styles of the other children up to date. Returns false,
+    /// changing nothing, if child is this node or one of its ancestors.
+    pub fn append_child(&self, child: DomNodeRef) -> bool {
+        self.insert_before(child, None)
+    }
+
+    /// Moves child to just before reference, or to the end of this node's
+    /// children if reference is None, removing it from its current parent
+    /// first. Returns false, changing nothing, if reference isn't a child of
+    /// this node, or child is this node or one of its ancestors.
+    ///
+    /// If child is a fragment, its children are moved instead, leaving it
+    /// empty. Shadow roots can't be inserted.
+    pub fn insert_before(&self, child: DomNodeRef, reference: Option<&DomNodeRef>) -> bool {
+        if child.is_inclusive_ancestor_of(self)
+            || child.borrow().elem_type == ElemType::ShadowRoot
+        {
+            return false;
+        }
+        let mut reference = reference.cloned();
+        if let Some(ref node) = reference {
+            if !node.parent().is_some_and(|parent| parent.ptr_eq(self)) {
+                return false;
+            }
+            // Inserting child before itself leaves it where it is
+            if node.ptr_eq(&child) {
+                reference = child.later_siblings().into_iter().next();
+            }
+        }
+        let is_fragment = child.borrow().elem_type == ElemType::Fragment;
+        if !is_fragment {
+            child.remove();
+        }
+        let index = match reference {
+            Some(node) => node.child_index().unwrap() - 1,
+            None => self.borrow().children.len(),
+        };
+        if is_fragment {
+            self.insert_children_at(index, child.take_children());
         } else {
-            None
+            self.insert_child_at(index, child);
         }
+        true
     }
 
-    pub fn add_child<'a>(&'a self, child: DomNodeRef) -> &'a Self {
-        let node = &self.ptr;
-        child.borrow_mut().parent = Some(Rc::downgrade(node));
-        let mut node = node.borrow_mut();
-        node.children.push(child);
-        self
+    /// Returns a fragment holding children, which moves them all at once
+    /// when it's inserted with append_child() or the like, e.g. to insert
+    /// a copy of a template's contents.
+    pub fn new_fragment(children: Vec<DomNodeRef>) -> DomNodeRef {
+        let fragment = DomNode::new_with_namespace(
+            ElemType::Fragment,
+            None,
+            HashSet::new(),
+            HashMap::new(),
+            None,
+            vec![],
+            None,
+        ).to_dnref();
+        for child in children {
+            fragment.append_child(child);
+        }
+        fragment
+    }
+
+    /// Replaces the node's children with the nodes parsed from html, which
+    /// are in the node's namespace unless they declare their own. Returns an
+    /// error, changing nothing, if html can't be parsed or this is a text or
+    /// comment node.
+    pub fn set_inner_html(&self, html: &str) -> Result<()> {
+        let namespace = {
+            let node = self.borrow();
+            if matches!(node.elem_type, ElemType::Text(_) | ElemType::Comment(_)) {
+                let message = format!("{:?} can't have children", node.elem_type);
+                return Err(Error::Unexpected((0, 0, 0), message));
+            }
+            node.namespace.clone().unwrap_or_else(|| HTML_NAMESPACE.to_string())
+        };
+        let children = parse_fragment(html, &namespace)?;
+        let old_children = self.take_children();
+        // For :empty, if there are no new children to mark it
+        if !old_children.is_empty() {
+            self.mark_self_style_dirty();
+        }
+        self.insert_children_at(0, children);
+        Ok(())
+    }
+
+    // Removes every child, for moving them elsewhere
+    fn take_children(&self) -> Vec<DomNodeRef> {
+        bump_dom_revision(self);
+        let mut node = self.borrow_mut();
+        node.children_by_type.clear();
+        let children = mem::take(&mut node.children).into_vec();
+        for child in &children {
+            document::subtree_removing(child);
+            let mut child = child.borrow_mut();
+            child.parent = None;
+            child.index_in_parent = None;
+        }
+        children
+    }
+
+    /// Puts new_child in old_child's place, removing new_child from its
+    /// current parent first. Returns false, changing nothing, if old_child
+    /// isn't a child of this node, or new_child is this node or one of its
+    /// ancestors.
+    pub fn replace_child(&self, new_child: DomNodeRef, old_child: &DomNodeRef) -> bool {
+        if new_child.ptr_eq(old_child) {
+            return old_child.parent().is_some_and(|parent| parent.ptr_eq(self));
+        }
+        self.insert_before(new_child, Some(old_child)) && self.remove_child(old_child)
+    }
+
+    // Like insert_child_at(), for children that aren't in a tree
+    fn insert_children_at(&self, index: usize, children: Vec<DomNodeRef>) {
+        if children.is_empty() {
+            return;
+        }
+        bump_dom_revision(self);
+        let was_empty = {
+            let mut node = self.borrow_mut();
+            let was_empty = node.is_empty();
+            for child in &children {
+                child.borrow_mut().parent = Some(self.node_id());
+            }
+            node.children.insert_many(index, children.iter().cloned());
+            for (i, sibling) in node.children.iter().enumerate().skip(index) {
+                sibling.borrow_mut().index_in_parent = Some(i);
+            }
+            node.index_children_by_type();
+            was_empty
+        };
+        for child in &children {
+            document::subtree_inserted(child);
+        }
+        self.mark_children_style_dirty();
+        if was_empty && !self.borrow().is_empty() {
+            self.mark_self_style_dirty();
+        }
+    }
+
+    fn insert_child_at(&self, index: usize, child: DomNodeRef) {
+        if index == self.borrow().children.len() {
+            self.add_child(child);
+            self.mark_children_style_dirty();
+            return;
+        }
+        bump_dom_revision(self);
+        let was_empty = {
+            let mut node = self.borrow_mut();
+            let was_empty = node.is_empty();
+            child.borrow_mut().parent = Some(self.node_id());
+            node.children.insert(index, child.clone());
+            for (i, sibling) in node.children.iter().enumerate().skip(index) {
+                sibling.borrow_mut().index_in_parent = Some(i);
+            }
+            node.index_children_by_type();
+            was_empty
+        };
+        document::subtree_inserted(&child);
+        self.mark_children_style_dirty();
+        if was_empty && !self.borrow().is_empty() {
+            self.mark_self_style_dirty();
+        }
+    }
+
+    // Marks every child as needing to be restyled after children were added
+    // or removed, which selectors like + and :nth-child() can match the later
+    // ones differently for, and :last-child and :nth-last-of-type() the
+    // earlier ones
+    fn mark_children_style_dirty(&self) {
+        let children = self.borrow().children.to_vec();
+        for child in children {
+            child.mark_subtree_style_dirty();
+        }
+    }
+
+    // True if this node is other or one of its ancestors, including the hosts
+    // of shadow trees other is in
+    fn is_inclusive_ancestor_of(&self, other: &DomNodeRef) -> bool {
+        other.ptr_eq(self)
+            || other.shadow_including_ancestors().any(|ancestor| ancestor.ptr_eq(self))
+    }
+
+    /// Marks the node as needing to be restyled, along with its descendants
+    /// and later siblings, which sibling combinators and structural
+    /// pseudo-classes can match based on this node.
+    pub fn mark_style_dirty(&self) {
+        self.mark_subtree_style_dirty();
+        for sibling in self.later_siblings() {
+            sibling.mark_subtree_style_dirty();
+        }
     }
 
-    pub fn add_children<'a>(&'a self, children: Vec<DomNodeRef>) -> &'a Self {
+    /// Marks the node and its descendants as needing to be restyled.
+    pub fn mark_subtree_style_dirty(&self) {
+        self.borrow_mut().style_dirty = true;
+        self.mark_ancestors_descendants_dirty();
+    }
+
+    /// Marks the node as needing to be restyled. Its descendants are only
+    /// restyled if its style changes.
+    pub fn mark_self_style_dirty(&self) {
+        self.borrow_mut().self_style_dirty = true;
+        self.mark_ancestors_descendants_dirty();
+    }
+
+    fn mark_ancestors_descendants_dirty(&self) {
+        // Shadow trees are styled along with their hosts
+        for ancestor in self.shadow_including_ancestors() {
+            if ancestor.borrow().descendants_dirty {
+                break;
+            }
+            ancestor.borrow_mut().descendants_dirty = true;
+        }
+    }
+
+    /// Returns the siblings after this node.
+    pub fn later_siblings(&self) -> Vec<DomNodeRef> {
+        self.following_siblings().collect()
+    }
+
+    /// Sets or clears states of the node, e.g. ElementState::HOVER when the
+    /// pointer moves over it.
+    pub fn set_state(&self, state: ElementState, on: bool) {
+        if !self.replace_state(state, on).is_empty() {
+            self.mark_style_dirty();
+        }
+    }
+
+    /// Like set_state(), but doesn't mark anything for restyling. Returns the
+    /// states that changed.
+    pub fn replace_state(&self, state: ElementState, on: bool) -> ElementState {
+        let (old_state, new_state) = {
+            let mut node = self.borrow_mut();
+            let old_state = node.state;
+            node.state.set(state, on);
+            (old_state, node.state)
+        };
+        if new_state != old_state {
+            bump_dom_revision(self);
+        }
+        old_state ^ new_state
+    }
+
+    /// Sets an attribute, keeping id and classes in sync with the id and
+    /// class attributes, and marks what the change can restyle as dirty (see
+    /// set_invalidation_hook()). name is lowercased on HTML elements in HTML
+    /// documents, and kept as it is otherwise.
+    pub fn set_attribute(&self, name: &str, value: Option<String>) {
+        let old_value = self.replace_attr(name, value.clone());
+        self.attr_changed(name, old_value.unwrap_or(None), value);
+    }
+
+    pub fn remove_attribute(&self, name: &str) {
+        if let Some(old_value) = self.take_attr(name) {
+            self.attr_changed(name, old_value, None);
+        }
+    }
+
+    fn attr_changed(&self, name: &str, old_value: Option<String>, new_value: Option<String>) {
+        let hook = INVALIDATION_HOOK.with(|hook| hook.borrow().clone());
+        match hook {
+            Some(hook) => hook.attr_changed(self, name, old_value, new_value),
+            None => self.mark_style_dirty(),
+        }
+    }
+
+    /// Like set_attribute(), but doesn't mark anything for restyling. Returns
+    /// the old value if the attribute was set.
+    pub fn replace_attr(&self, name: &str, value: Option<String>) -> Option<Option<String>> {
+        let name = Atom::from(self.attr_name(name));
+        bump_dom_revision(self);
+        let mut node = self.borrow_mut();
+        match name.as_str() {
+            "id" => {
+                let new_id = value.as_deref().map(Atom::from);
+                let old_id = std::mem::replace(&mut node.id, new_id.clone());
+                drop(node);
+                document::id_changed(self, old_id, new_id);
+                node = self.borrow_mut();
+            }
+            "class" => {
+                node.classes = value
+                    .iter()
+                    .flat_map(|value| value.split_whitespace())
+                    .map(Atom::from)
+                    .collect()
+            }
+            _ => (),
+        }
+        node.attrs.insert(name, value)
+    }
+
+    /// Like remove_attribute(), but doesn't mark anything for restyling. Returns
+    /// the removed value if the attribute was set.
+    pub fn take_attr(&self, name: &str) -> Option<Option<String>> {
+        let name = Atom::lookup(&self.attr_name(name))?;
+        let value = self.borrow_mut().attrs.remove(&name)?;
+        bump_dom_revision(self);
+        let mut node = self.borrow_mut();
+        match name.as_str() {
+            "id" => {
+                let old_id = node.id.take();
+                drop(node);
+                document::id_changed(self, old_id, None);
+            }
+            "class" => node.classes.clear(),
+            _ => (),
+        }
+        Some(value)
+    }
+
+    // Attribute names are case-insensitive on HTML elements in HTML documents,
+    // and stored lowercased
+    fn attr_name(&self, name: &str) -> String {
+        let is_html = self
+            .borrow()
+            .namespace
+            .as_deref()
+            .is_none_or(|namespace| namespace == HTML_NAMESPACE);
+        if is_html && document::document_kind(self) == DocumentKind::Html {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Same as class_list().add(class).
+    pub fn add_class(&self, class: &str) {
+        self.class_list().add(class);
+    }
+
+    /// Same as class_list().remove(class).
+    pub fn remove_class(&self, class: &str) {
+        self.class_list().remove(class);
+    }
+
+    /// Returns the node's classes, like the DOM's `classList`.
+    pub fn class_list(&self) -> ClassList {
+        ClassList(self.clone())
+    }
+
+    pub fn add_children(&self, children: Vec<DomNodeRef>) -> &Self {
         for child in children {
             self.add_child(child);
         }
@@ -103,31 +808,143 @@ impl DomNodeRef {
 
     // Starts at 1
     pub fn child_index(&self) -> Option<usize> {
-        if let Some(parent) = self.parent() {
-            parent
-                .borrow()
-                .children
-                .iter()
-                .position(|child| child == self)
-                .map(|x| x + 1)
-        } else {
-            None
+        let parent = self.parent()?;
+        let parent = parent.borrow();
+        let cached = self.borrow().index_in_parent;
+        // The cache is stale if children was changed without add_child() or
+        // remove_child()
+        match cached {
+            Some(index) if parent.children.get(index).is_some_and(|child| child.ptr_eq(self)) => {
+                Some(index + 1)
+            }
+            _ => parent.children.iter().position(|child| child.ptr_eq(self)).map(|x| x + 1),
+        }
+    }
+
+    /// Returns the node's position among its element siblings of the same
+    /// type, starting at 1, as :nth-of-type() counts.
+    pub fn type_index(&self) -> Option<usize> {
+        self.type_position().map(|(position, _)| position + 1)
+    }
+
+    /// Like type_index(), but counts from the last sibling of the type.
+    pub fn rev_type_index(&self) -> Option<usize> {
+        self.type_position().map(|(position, count)| count - position)
+    }
+
+    // Returns the node's position among its siblings of the same type, and
+    // how many of them there are
+    fn type_position(&self) -> Option<(usize, usize)> {
+        let index = self.child_index()? - 1;
+        let parent = self.parent()?;
+        let parent = parent.borrow();
+        let node = self.borrow();
+        if let Some(positions) = parent.children_by_type.get(&node.elem_type) {
+            if let Ok(position) = positions.binary_search(&index) {
+                return Some((position, positions.len()));
+            }
         }
+        // Not indexed, e.g. a text node or one added without add_child()
+        let same_type = parent
+            .children
+            .iter()
+            .filter(|child| child.ptr_eq(self) || child.borrow().elem_type == node.elem_type)
+            .collect::<Vec<_>>();
+        let position = same_type.iter().position(|child| child.ptr_eq(self))?;
+        Some((position, same_type.len()))
+    }
+
+    /// Returns the node's position among its element siblings, starting at 1,
+    /// as :nth-child() counts. Unlike child_index(), text and comment nodes
+    /// aren't counted.
+    pub fn element_index(&self) -> Option<usize> {
+        self.element_position().map(|(position, _)| position + 1)
+    }
+
+    /// Like element_index(), but counts from the last element sibling.
+    pub fn rev_element_index(&self) -> Option<usize> {
+        self.element_position().map(|(position, count)| count - position)
+    }
+
+    // Like type_position(), but among the element siblings of any type
+    fn element_position(&self) -> Option<(usize, usize)> {
+        let index = self.child_index()? - 1;
+        let parent = self.parent()?;
+        let parent = parent.borrow();
+        let node = self.borrow();
+        let indexed = parent
+            .children_by_type
+            .get(&node.elem_type)
+            .is_some_and(|positions| positions.binary_search(&index).is_ok());
+        if indexed {
+            let count = parent.children_by_type.values().map(Vec::len).sum();
+            // Usually every child is an element
+            if count == parent.children.len() {
+                return Some((index, count));
+            }
+            let position = parent
+                .children_by_type
+                .values()
+                .map(|positions| positions.partition_point(|&i| i < index))
+                .sum();
+            return Some((position, count));
+        }
+        // Not indexed, like in type_position()
+        let elements = parent
+            .children
+            .iter()
+            .filter(|child| child.ptr_eq(self) || child.borrow().elem_type.is_element())
+            .collect::<Vec<_>>();
+        let position = elements.iter().position(|child| child.ptr_eq(self))?;
+        Some((position, elements.len()))
     }
 
     // Also starts at 1
     pub fn rev_child_index(&self) -> Option<usize> {
-        if let Some(parent) = self.parent() {
-            parent
-                .borrow()
-                .children
-                .iter()
-                .rev()
-                .position(|child| child == self)
-                .map(|x| x + 1)
-        } else {
-            None
+        let index = self.child_index()?;
+        let parent = self.parent()?;
+        let len = parent.borrow().children.len();
+        Some(len + 1 - index)
+    }
+
+    /// Returns a copy of the node, with its descendants if deep is true, like
+    /// the DOM's `cloneNode()`. The copy has no parent and shares nothing with
+    /// the original: its nodes are new, with new id_nums, and its dynamic
+    /// states are cleared.
+    pub fn clone_node(&self, deep: bool) -> DomNodeRef {
+        let clone = {
+            let node = self.borrow();
+            DomNode {
+                id_num: DOM_NODE_NEXT_ID_NUM.fetch_add(1, Ordering::SeqCst),
+                elem_type: node.elem_type.clone(),
+                id: node.id.clone(),
+                classes: node.classes.clone(),
+                attrs: node.attrs.clone(),
+                parent: None,
+                index_in_parent: None,
+                children: Children::new(),
+                children_by_type: HashMap::new(),
+                namespace: node.namespace.clone(),
+                state: ElementState::empty(),
+                // Like a browser, which copies what was typed into an input
+                form_state: node.form_state.clone(),
+                // Upgraded anew once inserted into a document
+                upgraded: false,
+                // Shadow trees aren't cloned
+                shadow_root: None,
+                host: None,
+                style_dirty: true,
+                self_style_dirty: false,
+                descendants_dirty: false,
+            }.to_dnref()
+        };
+        if deep {
+            let children = self.borrow().children.to_vec();
+            for child in &children {
+                clone.add_child(child.clone_node(true));
+            }
         }
+        clone
     }
 
     pub fn eq_ignore_id_num(&self, other: &DomNodeRef) -> bool {
@@ -135,35 +952,149 @@ impl DomNodeRef {
         let other = other.borrow();
 
         this.elem_type == other.elem_type && this.id == other.id && this.classes == other.classes
-            && this.attrs == other.attrs && this.children.len() == other.children.len()
+            && this.attrs == other.attrs && this.namespace == other.namespace
+            && this.children.len() == other.children.len()
             && this.children
                 .iter()
                 .zip(other.children.iter())
                 .all(|(ch1, ch2)| ch1.eq_ignore_id_num(ch2))
     }
 
+    /// Returns true if the two subtrees are alike: their nodes have the same
+    /// types, namespaces, attributes and, for text and comments, content,
+    /// and the same children in the same order. Unlike ptr_eq(), other can
+    /// be a separate tree, e.g. the one a test expects this one to be.
+    ///
+    /// What isn't part of the markup, like element states, form states and
+    /// shadow trees, isn't compared.
+    pub fn is_equal_node(&self, other: &DomNodeRef) -> bool {
+        // Walked without recursion, since trees can be deep
+        let mut pairs = vec![(self.clone(), other.clone())];
+        while let Some((a, b)) = pairs.pop() {
+            if a.ptr_eq(&b) {
+                continue;
+            }
+            let (a, b) = (a.borrow(), b.borrow());
+            // Only elements are in a namespace, whatever other nodes were
+            // built with
+            let alike = a.elem_type == b.elem_type
+                && (!a.elem_type.is_element() || a.namespace == b.namespace)
+                && a.attrs == b.attrs && a.children.len() == b.children.len();
+            if !alike {
+                return false;
+            }
+            pairs.extend(a.children.iter().cloned().zip(b.children.iter().cloned()));
+        }
+        true
+    }
+
     pub fn siblings(&self) -> Vec<DomNodeRef> {
         if let Some(parent) = self.parent() {
-            parent.borrow().children.iter().map(|x| x.clone()).collect()
+            parent.borrow().children.to_vec()
         } else {
             vec![]
         }
     }
 }
 
+/// The classes of a node, which changes them through its class attribute, so
+/// that they stay in sync and changes are marked for restyling like any other
+/// attribute change.
+#[derive(Debug, Clone)]
+pub struct ClassList(DomNodeRef);
+
+impl ClassList {
+    /// Returns the classes in the order of the class attribute.
+    pub fn to_vec(&self) -> Vec<String> {
+        match self.0.borrow().attr("class") {
+            Some(Some(value)) => value.split_whitespace().map(|class| class.to_string()).collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().classes.is_empty()
+    }
+
+    pub fn contains(&self, class: &str) -> bool {
+        self.0.borrow().has_class(class)
+    }
+
+    pub fn add(&self, class: &str) {
+        if self.contains(class) {
+            return;
+        }
+        let mut classes = self.to_vec();
+        classes.push(class.to_string());
+        self.0.set_attribute("class", Some(classes.join(" ")));
+    }
+
+    pub fn remove(&self, class: &str) {
+        if !self.contains(class) {
+            return;
+        }
+        let classes = self.to_vec().into_iter().filter(|other| other != class).collect::<Vec<_>>();
+        self.0.set_attribute("class", Some(classes.join(" ")));
+    }
+
+    /// Removes class if it's there, and adds it otherwise. Returns whether
+    /// it's there now.
+    pub fn toggle(&self, class: &str) -> bool {
+        let add = !self.contains(class);
+        if add {
+            self.add(class);
+        } else {
+            self.remove(class);
+        }
+        add
+    }
+}
+
+/// Parses the nodes of html, which are in namespace unless they declare their
+/// own, into trees of their own.
+pub fn parse_fragment(html: &str, namespace: &str) -> Result<Vec<DomNodeRef>> {
+    let nodes = HtmlParser::parse_fragment(html)?;
+    Ok(nodes.into_iter().map(|node| DomNodeRef::from_parser_node(node, namespace)).collect())
+}
+
 impl From<HPDomNode> for DomNodeRef {
-    fn from(
+    fn from(node: HPDomNode) -> DomNodeRef {
+        DomNodeRef::from_parser_node(node, HTML_NAMESPACE)
+    }
+}
+
+impl DomNodeRef {
+    fn from_parser_node(node: HPDomNode, parent_namespace: &str) -> DomNodeRef {
+        let dom_node = DomNodeRef::from_parser_element(&node, parent_namespace);
+        let namespace = dom_node.borrow().namespace.clone().unwrap();
+        let children: Vec<DomNodeRef> = node
+            .children
+            .into_iter()
+            .map(|child| DomNodeRef::from_parser_node(child, &namespace))
+            .collect();
+        dom_node.add_children(children);
+        dom_node
+    }
+
+    // Converts node without its children. Elements inherit their parent's
+    // namespace unless they declare one with xmlns or are the root of an svg
+    // or math subtree.
+    fn from_parser_element(
         HPDomNode {
             elem_type,
             attrs,
-            children,
             ..
-        }: HPDomNode,
+        }: &HPDomNode,
+        parent_namespace: &str,
     ) -> DomNodeRef {
         let mut id: Option<String> = None;
         let mut classes: HashSet<String> = HashSet::new();
         let mut deduped_attrs: HashMap<String, Option<String>> = HashMap::new();
-        for &(ref attr, ref val) in &attrs {
+        for (attr, val) in attrs {
             let value = match val {
                 Some(Token::Value(_, ref value_str)) | Some(Token::Str(_, ref value_str)) => {
                     Some(value_str.to_string())
@@ -188,14 +1119,72 @@ impl From<HPDomNode> for DomNodeRef {
             }
         }
 
-        let node = DomNode::new(elem_type, id, classes, deduped_attrs, None, vec![]).to_dnref();
+        let namespace = match (deduped_attrs.get("xmlns"), elem_type) {
+            (Some(Some(namespace)), _) => namespace.to_string(),
+            (_, ElemType::Custom(name)) if name == "svg" => SVG_NAMESPACE.to_string(),
+            (_, ElemType::Custom(name)) if name == "math" => MATHML_NAMESPACE.to_string(),
+            _ => parent_namespace.to_string(),
+        };
+        DomNode::new_with_namespace(
+            elem_type.clone(),
+            id,
+            classes,
+            deduped_attrs,
+            None,
+            vec![],
+            Some(namespace),
+        ).to_dnref()
+    }
+}
 
-        let children: Vec<DomNodeRef> = children
-            .iter()
-            .map(|child| DomNodeRef::from(child.clone()))
-            .collect();
-        node.add_children(children);
-        node
+/// Receives the nodes of a document as parse_html_with_sink() parses it.
+pub trait HtmlSink {
+    /// Called once node has been added to the tree, with its attributes and
+    /// after its earlier siblings, but before its children are parsed.
+    fn node_opened(&mut self, node: &DomNodeRef);
+
+    /// Called once node's children have been parsed.
+    fn node_closed(&mut self, _node: &DomNodeRef) {}
+}
+
+// Builds the DOM tree from the parser's nodes as they're parsed, passing each
+// node to an HtmlSink
+pub struct TreeBuilder<'a> {
+    sink: &'a mut dyn HtmlSink,
+    // Nodes whose children are being parsed, outermost first
+    open: Vec<DomNodeRef>,
+    root: Option<DomNodeRef>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    pub fn new(sink: &'a mut dyn HtmlSink) -> TreeBuilder<'a> {
+        TreeBuilder { sink, open: vec![], root: None }
+    }
+
+    pub fn root(self) -> Option<DomNodeRef> {
+        self.root
+    }
+}
+
+impl<'a> TreeSink for TreeBuilder<'a> {
+    fn open(&mut self, node: &HPDomNode) {
+        let parent_namespace = self.open.last().map_or(HTML_NAMESPACE.to_string(), |parent| {
+            parent.borrow().namespace.clone().unwrap()
+        });
+        let dom_node = DomNodeRef::from_parser_element(node, &parent_namespace);
+        match self.open.last() {
+            Some(parent) => {
+                parent.add_child(dom_node.clone());
+            }
+            None => self.root = Some(dom_node.clone()),
+        }
+        self.sink.node_opened(&dom_node);
+        self.open.push(dom_node);
+    }
+
+    fn close(&mut self) {
+        let dom_node = self.open.pop().unwrap();
+        self.sink.node_closed(&dom_node);
     }
 }
 
@@ -223,12 +1212,40 @@ impl From<SPAttrSelectorOp> for AttrSelectorOp {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum NamespaceConstraint {
+    Any,         // *|
+    NoNamespace, // |
+    Url(String), // ns|
+}
+
+impl NamespaceConstraint {
+    pub fn matches(&self, namespace: Option<&str>) -> bool {
+        match self {
+            NamespaceConstraint::Any => true,
+            NamespaceConstraint::NoNamespace => namespace.is_none(),
+            NamespaceConstraint::Url(url) => namespace == Some(url.as_str()),
+        }
+    }
+}
+
+impl From<SPNamespacePrefix> for NamespaceConstraint {
+    fn from(namespace: SPNamespacePrefix) -> Self {
+        match namespace {
+            SPNamespacePrefix::Any(_) => NamespaceConstraint::Any,
+            SPNamespacePrefix::NoNamespace(_) => NamespaceConstraint::NoNamespace,
+            SPNamespacePrefix::Url(_, url) => NamespaceConstraint::Url(url),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct SimpleSelector {
     pub elem_type: Option<ElemType>,
-    pub id: Option<String>,
-    pub classes: HashSet<String>,
+    pub id: Option<Atom>,
+    pub classes: HashSet<Atom>,
     pub universal: bool,
+    pub namespace: NamespaceConstraint,
 }
 
 impl SimpleSelector {
@@ -237,12 +1254,23 @@ impl SimpleSelector {
         id: Option<String>,
         classes: HashSet<String>,
         universal: bool,
+    ) -> SimpleSelector {
+        Self::new_with_namespace(elem_type, id, classes, universal, NamespaceConstraint::Any)
+    }
+
+    pub fn new_with_namespace(
+        elem_type: Option<ElemType>,
+        id: Option<String>,
+        classes: HashSet<String>,
+        universal: bool,
+        namespace: NamespaceConstraint,
     ) -> SimpleSelector {
         SimpleSelector {
             elem_type,
-            id,
-            classes,
+            id: id.map(Atom::from),
+            classes: classes.into_iter().map(Atom::from).collect(),
             universal,
+            namespace,
         }
     }
 }
@@ -251,19 +1279,35 @@ impl SimpleSelector {
 pub struct AttrSelector {
     pub attr: String,
     pub op_val: Option<(AttrSelectorOp, String)>,
-    pub case_insensitive: bool,
+    pub case_flag: AttrCaseFlag,
+    pub namespace: NamespaceConstraint,
+    // The value in op_val, ASCII-lowercased once here for case-insensitive
+    // matching
+    pub lowercase_val: Option<String>,
 }
 
 impl AttrSelector {
     pub fn new(
         attr: String,
         op_val: Option<(AttrSelectorOp, String)>,
-        case_insensitive: bool,
+        case_flag: AttrCaseFlag,
+    ) -> AttrSelector {
+        Self::new_with_namespace(attr, op_val, case_flag, NamespaceConstraint::NoNamespace)
+    }
+
+    pub fn new_with_namespace(
+        attr: String,
+        op_val: Option<(AttrSelectorOp, String)>,
+        case_flag: AttrCaseFlag,
+        namespace: NamespaceConstraint,
     ) -> AttrSelector {
+        let lowercase_val = op_val.as_ref().map(|(_, val)| val.to_ascii_lowercase());
         AttrSelector {
             attr,
             op_val,
-            case_insensitive,
+            case_flag,
+            namespace,
+            lowercase_val,
         }
     }
 }
@@ -294,12 +1338,12 @@ impl NthExpr {
     pub fn matches(&self, i: usize) -> bool {
         use self::NthExpr::*;
         let i = i as isize;
-        match self {
-            &A(a) => a == i,
-            &AnOpB(a, Some(NthExprOp::Add), b) => (i - b) / a >= 0 && (i - b) % a == 0,
+        match *self {
+            A(a) => a == i,
+            AnOpB(a, Some(NthExprOp::Add), b) => (i - b) / a >= 0 && (i - b) % a == 0,
 
-            &AnOpB(a, Some(NthExprOp::Sub), b) => (i + b) / a >= 0 && (i + b) % a == 0,
-            &AnOpB(a, None, _) => i / a >= 0 && i % a == 0,
+            AnOpB(a, Some(NthExprOp::Sub), b) => (i + b) / a >= 0 && (i + b) % a == 0,
+            AnOpB(a, None, _) => i / a >= 0 && i % a == 0,
         }
     }
 }
@@ -329,12 +1373,24 @@ impl From<SPNthExpr> for NthExpr {
 #[derive(Debug, Eq, PartialEq)]
 pub enum PseudoClassSelector {
     Active,
+    Checked,
+    // Form controls that are checked or selected by default, and forms'
+    // default buttons
+    Default,
+    // Elements other than custom elements that haven't been upgraded
+    Defined,
+    Disabled,
+    // No children but comments
+    Empty,
+    Enabled,
+    Focus,
     Hover,
     // experimental: Dir,
-    // experimental: Host,
+    Host(Option<Box<Selector>>),
     // experimental: HostContext,
     FirstChild,
     FirstOfType,
+    Indeterminate,
     Lang(String),
     LastChild,
     LastOfType,
@@ -346,6 +1402,8 @@ pub enum PseudoClassSelector {
     NthLastChild(NthExpr),
     NthLastOfType(NthExpr),
     NthOfType(NthExpr),
+    Target,
+    Custom(String),
 }
 
 impl From<SPPseudoClassSelector> for PseudoClassSelector {
@@ -353,9 +1411,20 @@ impl From<SPPseudoClassSelector> for PseudoClassSelector {
         use self::SPPseudoClassSelector::*;
         match sel {
             Active(_) => PseudoClassSelector::Active,
+            Checked(_) => PseudoClassSelector::Checked,
+            Default(_) => PseudoClassSelector::Default,
+            Defined(_) => PseudoClassSelector::Defined,
+            Disabled(_) => PseudoClassSelector::Disabled,
+            Empty(_) => PseudoClassSelector::Empty,
+            Enabled(_) => PseudoClassSelector::Enabled,
             FirstChild(_) => PseudoClassSelector::FirstChild,
             FirstOfType(_) => PseudoClassSelector::FirstOfType,
+            Focus(_) => PseudoClassSelector::Focus,
+            Host(_, sel) => {
+                PseudoClassSelector::Host(sel.map(|sel| Box::new(Selector::from(*sel))))
+            }
             Hover(_) => PseudoClassSelector::Hover,
+            Indeterminate(_) => PseudoClassSelector::Indeterminate,
             Lang(_, tok) => PseudoClassSelector::Lang(tok.to_string()),
             LastChild(_) => PseudoClassSelector::LastChild,
             LastOfType(_) => PseudoClassSelector::LastOfType,
@@ -369,6 +1438,8 @@ impl From<SPPseudoClassSelector> for PseudoClassSelector {
                 PseudoClassSelector::NthLastOfType(NthExpr::from(nth_expr))
             }
             NthOfType(_, nth_expr) => PseudoClassSelector::NthOfType(NthExpr::from(nth_expr)),
+            Target(_) => PseudoClassSelector::Target,
+            Custom(_, name) => PseudoClassSelector::Custom(name),
         }
     }
 }
@@ -380,8 +1451,29 @@ pub enum PseudoElementSelector {
     Cue,
     FirstLetter,
     FirstLine,
+    Placeholder,
     Selection,
-    Slotted,
+    Slotted(Box<Selector>),
+    Part(Vec<String>),
+}
+
+impl PseudoElementSelector {
+    /// ::selection only honors a handful of properties (colors, outlines, text
+    /// decorations and shadows). Every other pseudo-element accepts anything.
+    pub fn allows_property(&self, property: &str) -> bool {
+        match self {
+            PseudoElementSelector::Selection => matches!(
+                property,
+                "color" | "background-color" | "cursor" | "caret-color" | "outline"
+                    | "outline-color" | "outline-style" | "outline-width"
+                    | "text-decoration" | "text-decoration-color"
+                    | "text-decoration-line" | "text-decoration-style"
+                    | "text-decoration-thickness" | "text-emphasis-color"
+                    | "text-shadow"
+            ),
+            _ => true,
+        }
+    }
 }
 
 impl From<SPPseudoElementSelector> for PseudoElementSelector {
@@ -393,8 +1485,12 @@ impl From<SPPseudoElementSelector> for PseudoElementSelector {
             Cue(..) => PseudoElementSelector::Cue,
             FirstLetter(..) => PseudoElementSelector::FirstLetter,
             FirstLine(..) => PseudoElementSelector::FirstLine,
+            Placeholder(..) => PseudoElementSelector::Placeholder,
             Selection(..) => PseudoElementSelector::Selection,
-            Slotted(..) => PseudoElementSelector::Slotted,
+            Slotted(_, sel) => PseudoElementSelector::Slotted(Box::new(Selector::from(*sel))),
+            Part(_, names) => {
+                PseudoElementSelector::Part(names.iter().map(|name| name.to_string()).collect())
+            }
         }
     }
 }
@@ -430,6 +1526,69 @@ pub enum Selector {
     Group(Vec<Selector>), // comma-separated group
 }
 
+impl Selector {
+    /// Returns the pseudo-element targeted by the selector, which is the one in
+    /// its rightmost compound selector. Groups can target several different
+    /// pseudo-elements, so this returns None for them.
+    pub fn pseudo_element(&self) -> Option<&PseudoElementSelector> {
+        match self {
+            Selector::PseudoElement(pe) => Some(pe),
+            Selector::Seq(sels) => sels.iter().filter_map(Selector::pseudo_element).next(),
+            Selector::Combinator(_, _, sel) => sel.pseudo_element(),
+            _ => None,
+        }
+    }
+
+    /// For groups, this is the specificity of the most specific selector in the
+    /// group. The cascade should instead use the most specific selector that
+    /// matched.
+    pub fn specificity(&self) -> Specificity {
+        match self {
+            Selector::Simple(SimpleSelector {
+                elem_type,
+                id,
+                classes,
+                ..
+            }) => Specificity(
+                id.is_some() as u32,
+                classes.len() as u32,
+                elem_type.is_some() as u32,
+            ),
+            Selector::Attr(_) => Specificity(0, 1, 0),
+            Selector::PseudoClass(PseudoClassSelector::Matches(sel))
+            | Selector::PseudoClass(PseudoClassSelector::Not(sel)) => sel.specificity(),
+            Selector::PseudoClass(PseudoClassSelector::Host(Some(sel))) => {
+                Specificity(0, 1, 0) + sel.specificity()
+            }
+            Selector::PseudoClass(_) => Specificity(0, 1, 0),
+            Selector::PseudoElement(PseudoElementSelector::Slotted(sel)) => {
+                Specificity(0, 0, 1) + sel.specificity()
+            }
+            Selector::PseudoElement(_) => Specificity(0, 0, 1),
+            Selector::Seq(sels) => sels.iter()
+                .fold(Specificity::default(), |acc, sel| acc + sel.specificity()),
+            Selector::Combinator(first, _, second) => first.specificity() + second.specificity(),
+            Selector::Group(sels) => sels.iter()
+                .map(Selector::specificity)
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Selector specificity as (ids, classes/attributes/pseudo-classes,
+/// types/pseudo-elements). Compares lexicographically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Specificity(pub u32, pub u32, pub u32);
+
+impl Add for Specificity {
+    type Output = Specificity;
+
+    fn add(self, other: Specificity) -> Specificity {
+        Specificity(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    }
+}
+
 impl From<SPSelector> for Selector {
     fn from(selector: SPSelector) -> Selector {
         use self::SPSelector::*;
@@ -439,21 +1598,34 @@ impl From<SPSelector> for Selector {
                 id,
                 classes,
                 universal,
+                namespace,
                 ..
             }) => {
                 let id = id.map(|tok| tok.to_string());
                 let classes = classes.iter().map(|cl| cl.to_string()).collect();
-                Selector::Simple(SimpleSelector::new(elem_type, id, classes, universal))
+                let namespace =
+                    namespace.map_or(NamespaceConstraint::Any, NamespaceConstraint::from);
+                Selector::Simple(SimpleSelector::new_with_namespace(
+                    elem_type, id, classes, universal, namespace,
+                ))
             }
             Attr(SPAttrSelector {
                 attr,
                 op_val,
-                case_insensitive,
+                case_flag,
+                namespace,
                 ..
             }) => {
-                let attr = attr.into_lowercase().to_string();
+                let attr = attr.to_string();
                 let op_val = op_val.map(|(op, tok)| (AttrSelectorOp::from(op), tok.to_string()));
-                Selector::Attr(AttrSelector::new(attr, op_val, case_insensitive))
+                let namespace =
+                    namespace.map_or(NamespaceConstraint::NoNamespace, NamespaceConstraint::from);
+                Selector::Attr(AttrSelector::new_with_namespace(
+                    attr,
+                    op_val,
+                    case_flag,
+                    namespace,
+                ))
             }
             PseudoClass(sel) => Selector::PseudoClass(PseudoClassSelector::from(sel)),
             PseudoElement(sel) => Selector::PseudoElement(PseudoElementSelector::from(sel)),
@@ -475,11 +1647,16 @@ pub struct CssBlocks(pub Vec<(Selector, HashMap<String, String>)>);
 impl From<CPCssBlocks> for CssBlocks {
     fn from(CPCssBlocks(blocks): CPCssBlocks) -> Self {
         let mut blks = vec![];
-        for (selector, decl_block) in blocks {
+        // Rules inside @media and @container rules are left out, since there's
+        // nothing to evaluate their queries against
+        let blocks = blocks
+            .into_iter()
+            .filter(|(_, _, media, _, containers)| media.is_empty() && containers.is_empty());
+        for (selector, decl_block, _, _, _) in blocks {
             // Check if selector is already in blks, and if so, consolidate them into one
             let sel = Selector::from(selector);
             match blks.iter().position(
-                |&(ref blks_sel, _): &(Selector, HashMap<String, String>)| *blks_sel == sel,
+                |(blks_sel, _): &(Selector, HashMap<String, String>)| *blks_sel == sel,
             ) {
                 Some(index) => {
                     let (_, ref mut hmap) = &mut blks[index];
@@ -508,9 +1685,343 @@ impl From<CPCssBlocks> for CssBlocks {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Declaration {
+    pub property: String, // lowercased
+    pub value: String,    // without !important
+    pub important: bool,
+}
+
+impl Declaration {
+    pub fn new(property: String, value: String) -> Declaration {
+        Self::new_with_importance(property, value, false)
+    }
+
+    pub fn new_with_importance(property: String, value: String, important: bool) -> Declaration {
+        Declaration {
+            property,
+            value,
+            important,
+        }
+    }
+
+    /// Splits a trailing `!important` (in any case, and with any whitespace
+    /// after the `!`) off of value.
+    pub fn parse(property: String, value: &str) -> Declaration {
+        if let Some(bang) = value.rfind('!') {
+            if value[bang + 1..].trim().eq_ignore_ascii_case("important") {
+                let value = value[..bang].trim_end().to_string();
+                return Self::new_with_importance(property, value, true);
+            }
+        }
+        Self::new(property, value.to_string())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct StyleRule {
+    pub selector: Selector,
+    pub declarations: Vec<Declaration>,
+    // Query list of each enclosing @media rule. The rule only applies if all
+    // of them match.
+    pub media: Vec<MediaQueryList>,
+    // Full name of the cascade layer the rule is in, e.g. "a.b", or None if
+    // it's unlayered. See Stylesheet::layers.
+    pub layer: Option<String>,
+    // Condition of each enclosing @container rule. The rule only applies if
+    // all of them match.
+    pub containers: Vec<ContainerQuery>,
+}
+
+impl StyleRule {
+    pub fn new(selector: Selector, declarations: Vec<Declaration>) -> StyleRule {
+        Self::new_with_media(selector, declarations, vec![])
+    }
+
+    pub fn new_with_media(
+        selector: Selector,
+        declarations: Vec<Declaration>,
+        media: Vec<MediaQueryList>,
+    ) -> StyleRule {
+        StyleRule {
+            selector,
+            declarations,
+            media,
+            layer: None,
+            containers: vec![],
+        }
+    }
+
+    /// Returns the value of the last declaration of property.
+    pub fn property_value(&self, property: &str) -> Option<&str> {
+        let property = property.to_lowercase();
+        self.declarations
+            .iter()
+            .rev()
+            .find(|decl| decl.property == property)
+            .map(|decl| decl.value.as_str())
+    }
+
+    /// Sets a declaration of the rule, replacing the existing declarations of
+    /// property in place. Returns false and leaves the rule as it is if the
+    /// declaration is invalid.
+    pub fn set_property(&mut self, property: &str, value: &str, important: bool) -> bool {
+        let (decl_block, errs) =
+            CssParser::parse_declaration_list(&format!("{}: {}", property, value));
+        let mut declarations = convert_decl_block(decl_block);
+        if !errs.is_empty() || declarations.len() != 1 {
+            return false;
+        }
+        let mut declaration = declarations.remove(0);
+        declaration.important = declaration.important || important;
+        match self
+            .declarations
+            .iter()
+            .position(|decl| decl.property == declaration.property)
+        {
+            Some(index) => {
+                let property = declaration.property.clone();
+                self.declarations[index] = declaration;
+                let mut i = 0;
+                self.declarations.retain(|decl| {
+                    let keep = i == index || decl.property != property;
+                    i += 1;
+                    keep
+                });
+            }
+            None => self.declarations.push(declaration),
+        }
+        true
+    }
+
+    /// Removes the declarations of property. Returns the value of the last
+    /// one, if any.
+    pub fn remove_property(&mut self, property: &str) -> Option<String> {
+        let value = self.property_value(property).map(|value| value.to_string());
+        let property = property.to_lowercase();
+        self.declarations.retain(|decl| decl.property != property);
+        value
+    }
+}
+
+/// `@import url media;`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ImportRule {
+    pub url: String,
+    pub media: MediaQueryList,
+}
+
+impl ImportRule {
+    pub fn new(url: String, media: MediaQueryList) -> ImportRule {
+        ImportRule { url, media }
+    }
+}
+
+/// `@font-face { descriptors }`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FontFaceRule {
+    // e.g. font-family, src, font-weight
+    pub descriptors: Vec<Declaration>,
+}
+
+impl FontFaceRule {
+    pub fn new(descriptors: Vec<Declaration>) -> FontFaceRule {
+        FontFaceRule { descriptors }
+    }
+
+    /// Returns the value of the last descriptor with the given (lowercase)
+    /// name.
+    pub fn descriptor(&self, name: &str) -> Option<&str> {
+        self.descriptors
+            .iter()
+            .rev()
+            .find(|descriptor| descriptor.property == name)
+            .map(|descriptor| descriptor.value.as_str())
+    }
+}
+
+/// One block of an @keyframes rule, e.g. `from, 50% { opacity: 0 }`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Keyframe {
+    // Lowercase, e.g. ["from", "50%"]
+    pub selectors: Vec<String>,
+    pub declarations: Vec<Declaration>,
+}
+
+impl Keyframe {
+    pub fn new(selectors: Vec<String>, declarations: Vec<Declaration>) -> Keyframe {
+        Keyframe {
+            selectors,
+            declarations,
+        }
+    }
+}
+
+/// `@keyframes name { keyframes }`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct KeyframesRule {
+    pub name: String,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl KeyframesRule {
+    pub fn new(name: String, keyframes: Vec<Keyframe>) -> KeyframesRule {
+        KeyframesRule { name, keyframes }
+    }
+}
+
+/// Unlike CssBlocks, keeps every rule and declaration in source order, which the
+/// cascade needs to break ties.
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct Stylesheet {
+    pub rules: Vec<StyleRule>,
+    // Not loaded by the parser; see parse_stylesheet_with_loader()
+    pub imports: Vec<ImportRule>,
+    pub font_faces: Vec<FontFaceRule>,
+    pub keyframes: Vec<KeyframesRule>,
+    // Full name of each cascade layer declared with @layer, in the order
+    // they're first declared, which is the order they cascade in. Anonymous
+    // layers get names that aren't valid identifiers.
+    pub layers: Vec<String>,
+}
+
+impl Stylesheet {
+    pub fn new(rules: Vec<StyleRule>) -> Stylesheet {
+        Stylesheet {
+            rules,
+            ..Stylesheet::default()
+        }
+    }
+
+    /// Parses a style rule, which may be inside @media, @layer and @container
+    /// rules, and inserts it before the rule at index. Returns index. Layers
+    /// the stylesheet doesn't have yet are added after the others.
+    ///
+    /// Styles computed from the stylesheet aren't updated; see
+    /// StyleResolver::invalidate_rule().
+    pub fn insert_rule(&mut self, rule: &str, index: usize) -> Result<usize> {
+        if index > self.rules.len() {
+            return Err(Error::Unexpected(
+                (0, 0, 0),
+                format!("rule index {} out of range", index),
+            ));
+        }
+        let (stylesheet, mut errs) = CssParser::parse_stylesheet(rule);
+        if !errs.is_empty() {
+            return Err(if errs.len() == 1 {
+                errs.remove(0)
+            } else {
+                Error::Multiple(errs)
+            });
+        }
+        let mut stylesheet = Stylesheet::from(stylesheet);
+        let has_other_rules = !stylesheet.imports.is_empty()
+            || !stylesheet.font_faces.is_empty()
+            || !stylesheet.keyframes.is_empty();
+        if stylesheet.rules.len() != 1 || has_other_rules {
+            return Err(Error::Unexpected((0, 0, 0), "expected a single style rule".to_string()));
+        }
+        for layer in stylesheet.layers {
+            if !self.layers.contains(&layer) {
+                self.layers.push(layer);
+            }
+        }
+        self.rules.insert(index, stylesheet.rules.remove(0));
+        Ok(index)
+    }
+
+    /// Removes and returns the rule at index.
+    pub fn delete_rule(&mut self, index: usize) -> Result<StyleRule> {
+        if index >= self.rules.len() {
+            return Err(Error::Unexpected(
+                (0, 0, 0),
+                format!("rule index {} out of range", index),
+            ));
+        }
+        Ok(self.rules.remove(index))
+    }
+}
+
+pub(super) fn convert_decl_block(decl_block: Vec<(CPToken, CPToken)>) -> Vec<Declaration> {
+    decl_block
+        .into_iter()
+        .filter_map(|decl| match decl {
+            (CPToken::Property(_, property), CPToken::Value(_, value)) => {
+                Some(Declaration::parse(property.to_lowercase(), &value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+impl From<CPCssBlocks> for Stylesheet {
+    fn from(CPCssBlocks(blocks): CPCssBlocks) -> Self {
+        let rules = blocks
+            .into_iter()
+            .map(|(selector, decl_block, media, layer, containers)| {
+                let declarations = convert_decl_block(decl_block);
+                let media = media.iter().map(|query_list| MediaQueryList::parse(query_list));
+                StyleRule {
+                    layer,
+                    containers,
+                    ..StyleRule::new_with_media(
+                        Selector::from(selector),
+                        declarations,
+                        media.collect(),
+                    )
+                }
+            })
+            .collect();
+        Stylesheet::new(rules)
+    }
+}
+
+impl From<CPCssStylesheet> for Stylesheet {
+    fn from(stylesheet: CPCssStylesheet) -> Self {
+        let imports = stylesheet
+            .imports
+            .into_iter()
+            .map(|(url, query_list)| ImportRule::new(url, MediaQueryList::parse(&query_list)))
+            .collect();
+        let font_faces = stylesheet
+            .font_faces
+            .into_iter()
+            .map(|descriptors| FontFaceRule::new(convert_decl_block(descriptors)))
+            .collect();
+        let keyframes = stylesheet
+            .keyframes
+            .into_iter()
+            .map(|(name, blocks)| {
+                let keyframes = blocks
+                    .into_iter()
+                    .filter_map(|(selector, decl_block)| match selector {
+                        CPToken::Selector(_, selector) => {
+                            let selectors = selector
+                                .split(',')
+                                .map(|selector| selector.trim().to_lowercase())
+                                .collect();
+                            Some(Keyframe::new(selectors, convert_decl_block(decl_block)))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                KeyframesRule::new(name, keyframes)
+            })
+            .collect();
+        Stylesheet {
+            imports,
+            font_faces,
+            keyframes,
+            layers: stylesheet.layers,
+            ..Stylesheet::from(stylesheet.blocks)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use magicparser::{parse_html, parse_html_fragment};
 
     #[test]
     fn test_convert_to_domnode1() {
@@ -553,7 +2064,7 @@ mod tests {
                 ),
                 HPDomNode::new(
                     (0, 1, 1),
-                    ElemType::Custom("custom".to_string()),
+                    ElemType::Custom(Atom::from("custom")),
                     vec![],
                     vec![],
                 ),
@@ -581,7 +2092,7 @@ mod tests {
             vec![],
         ).to_dnref();
         let child2 = DomNode::new(
-            ElemType::Custom("custom".to_string()),
+            ElemType::Custom(Atom::from("custom")),
             None,
             HashSet::new(),
             HashMap::new(),
@@ -593,6 +2104,48 @@ mod tests {
         // assert_eq!(DomNodeRef::from(parser_dom_node), node)
     }
 
+    #[test]
+    fn test_convert_to_domnode_namespaces() {
+        let parser_dom_node = HPDomNode::new(
+            (0, 1, 1),
+            ElemType::Div,
+            vec![],
+            vec![HPDomNode::new(
+                (0, 1, 1),
+                ElemType::from("svg"),
+                vec![],
+                vec![HPDomNode::new(
+                    (0, 1, 1),
+                    ElemType::from("use"),
+                    vec![(
+                        Token::AttrIdentifier((0, 1, 1), "xlink:href".to_string()),
+                        Some(Token::Str((0, 1, 1), "#a".to_string())),
+                    )],
+                    vec![],
+                )],
+            )],
+        );
+        let node = DomNodeRef::from(parser_dom_node);
+        assert_eq!(node.borrow().namespace, Some(HTML_NAMESPACE.to_string()));
+        let svg = node.borrow().children[0].clone();
+        assert_eq!(svg.borrow().namespace, Some(SVG_NAMESPACE.to_string()));
+        let use_node = svg.borrow().children[0].clone();
+        assert_eq!(use_node.borrow().namespace, Some(SVG_NAMESPACE.to_string()));
+        assert_eq!(
+            use_node.borrow().attr_ns(
+                "href",
+                &NamespaceConstraint::Url(XLINK_NAMESPACE.to_string())
+            ),
+            Some(&Some("#a".to_string()))
+        );
+        assert_eq!(
+            use_node
+                .borrow()
+                .attr_ns("href", &NamespaceConstraint::NoNamespace),
+            None
+        );
+    }
+
     #[test]
     fn test_convert_to_nth_expr1() {
         assert_eq!(
@@ -683,7 +2236,7 @@ mod tests {
                         SPAttrSelectorOp::ContainsAtLeastOne((0, 1, 1)),
                         Token::Str((0, 1, 1), "href-str".to_string()),
                     )),
-                    true,
+                    AttrCaseFlag::Insensitive,
                 )),
             ])),
             Selector::Seq(vec![
@@ -694,9 +2247,9 @@ mod tests {
                     false,
                 )),
                 Selector::Attr(AttrSelector::new(
-                    "href".to_string(),
+                    "HrEf".to_string(),
                     Some((AttrSelectorOp::ContainsAtLeastOne, "href-str".to_string())),
-                    true,
+                    AttrCaseFlag::Insensitive,
                 )),
             ])
         );
@@ -724,6 +2277,9 @@ mod tests {
                             CPToken::Value((0, 1, 1), "val2".to_string()),
                         ),
                     ],
+                    vec![],
+                    None,
+                    vec![],
                 ),
                 (
                     SPSelector::Simple(SPSimpleSelector::new(
@@ -737,6 +2293,9 @@ mod tests {
                         CPToken::Property((0, 1, 1), "AtTr2".to_string()),
                         CPToken::Value((0, 1, 1), "val3".to_string()),
                     )],
+                    vec![],
+                    None,
+                    vec![],
                 ),
             ])),
             CssBlocks(vec![(
@@ -766,6 +2325,260 @@ mod tests {
         assert_eq!(parent.borrow().children[0].child_index(), Some(1));
         assert_eq!(parent.borrow().children[1].child_index(), Some(2));
         assert_eq!(parent.borrow().children[2].child_index(), Some(3));
+
+        // Removing a child shifts the later ones
+        let first = parent.borrow().children[0].clone();
+        let last = parent.borrow().children[2].clone();
+        assert!(parent.remove_child(&first));
+        assert_eq!(first.child_index(), None);
+        assert_eq!(last.borrow().index_in_parent, Some(1));
+        assert_eq!(last.child_index(), Some(2));
+        assert_eq!(last.rev_child_index(), Some(1));
+        parent.add_child(first.clone());
+        assert_eq!(first.child_index(), Some(3));
+    }
+
+    #[test]
+    fn test_mutation() {
+        let root = parse_html("<div><p>1</p><a></a><p>2</p></div>").unwrap();
+        let children = root.borrow().children.to_vec();
+        let (p1, a, p2) = (&children[0], &children[1], &children[2]);
+        let child_indices = |node: &DomNodeRef| {
+            node.borrow().children.iter().map(|child| child.child_index()).collect::<Vec<_>>()
+        };
+        let clean = |node: &DomNodeRef| {
+            let mut node = node.borrow_mut();
+            node.style_dirty = false;
+            node.descendants_dirty = false;
+        };
+        for node in children.iter().chain(Some(&root)) {
+            clean(node);
+        }
+
+        assert!(root.insert_before(p2.clone(), Some(p1)));
+        assert!(p2.ptr_eq(&root.borrow().children[0]));
+        assert_eq!(child_indices(&root), vec![Some(1), Some(2), Some(3)]);
+        assert_eq!((p1.type_index(), p2.type_index()), (Some(2), Some(1)));
+        assert!(p1.borrow().style_dirty && a.borrow().style_dirty);
+        assert!(root.borrow().descendants_dirty);
+
+        // Appending moves a node from its old parent
+        for node in children.iter().chain(Some(&root)) {
+            clean(node);
+        }
+        assert!(a.append_child(p2.clone()));
+        assert!(p2.parent().unwrap().ptr_eq(a));
+        assert_eq!(child_indices(&root), vec![Some(1), Some(2)]);
+        assert_eq!(p2.child_index(), Some(1));
+        // p1 is the first child now
+        assert!(p1.borrow().style_dirty && a.borrow().style_dirty);
+        clean(a);
+        assert!(root.append_child(p2.clone()));
+        // a isn't the last child anymore
+        assert!(a.borrow().style_dirty);
+
+        // Nodes can't be moved into themselves
+        assert!(!p2.append_child(root.clone()));
+        assert!(!p2.append_child(p2.clone()));
+        assert!(!root.insert_before(p1.clone(), Some(&p2.borrow().children[0])));
+        assert!(root.insert_before(p1.clone(), Some(p1)));
+        assert_eq!(child_indices(&root), vec![Some(1), Some(2), Some(3)]);
+
+        let b = parse_html("<b></b>").unwrap();
+        assert!(root.replace_child(b.clone(), a));
+        assert!(!root.replace_child(b.clone(), a));
+        assert_eq!(a.parent(), None);
+        assert!(b.parent().unwrap().ptr_eq(&root));
+        assert_eq!(b.child_index(), Some(2));
+        assert_eq!(root.borrow().children_by_type.get(&ElemType::A), None);
+
+        assert!(b.remove());
+        assert!(!b.remove());
+        assert_eq!(child_indices(&root), vec![Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn test_fragment() {
+        let root = parse_html("<div><p>1</p><p>2</p></div>").unwrap();
+        let children = root.borrow().children.to_vec();
+        let template = parse_html("<div><a></a>Text<h1></h1></div>").unwrap();
+        let template_children = template.borrow().children.to_vec();
+        let fragment = DomNodeRef::new_fragment(template_children);
+        assert!(template.borrow().children.is_empty());
+        assert_eq!(fragment.borrow().children.len(), 3);
+        for node in children.iter().chain(Some(&root)) {
+            node.borrow_mut().style_dirty = false;
+        }
+
+        // The fragment's children are moved, not the fragment
+        let copy = fragment.clone_node(true);
+        assert!(root.insert_before(copy.clone(), Some(&children[1])));
+        assert!(copy.borrow().children.is_empty());
+        let types = root
+            .borrow()
+            .children
+            .iter()
+            .map(|child| child.borrow().elem_type.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                ElemType::P,
+                ElemType::A,
+                ElemType::Text("Text".to_string()),
+                ElemType::H1,
+                ElemType::P,
+            ]
+        );
+        let inserted = root.borrow().children[1].clone();
+        assert!(inserted.parent().unwrap().ptr_eq(&root));
+        assert_eq!(inserted.child_index(), Some(2));
+        assert_eq!(children[1].child_index(), Some(5));
+        assert_eq!(children[1].element_index(), Some(4));
+        // Along with the earlier ones, for :last-child and the like
+        assert!(children[1].borrow().style_dirty && children[0].borrow().style_dirty);
+
+        root.append_child(fragment.clone());
+        assert_eq!(root.borrow().children.len(), 8);
+        assert!(fragment.borrow().children.is_empty());
+        assert!(!fragment.borrow().elem_type.is_element());
+        assert!(children[1].borrow().style_dirty);
+    }
+
+    #[test]
+    fn test_set_inner_html() {
+        let root = parse_html("<div><p>Old</p><svg></svg></div>").unwrap();
+        let p = root.borrow().children[0].clone();
+        let svg = root.borrow().children[1].clone();
+        root.borrow_mut().style_dirty = false;
+
+        root.set_inner_html("Text <a href=\"#\">link</a><!-- c --><h1></h1>").unwrap();
+        assert!(p.parent().is_none() && svg.parent().is_none());
+        let types = root
+            .borrow()
+            .children
+            .iter()
+            .map(|child| child.borrow().elem_type.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                ElemType::Text("Text".to_string()),
+                ElemType::A,
+                ElemType::Comment(" c ".to_string()),
+                ElemType::H1,
+            ]
+        );
+        let h1 = root.borrow().children[3].clone();
+        assert_eq!(h1.child_index(), Some(4));
+        assert_eq!(h1.element_index(), Some(2));
+        assert_eq!(root.borrow().children_by_type.get(&ElemType::H1), Some(&vec![3]));
+        assert!(h1.borrow().style_dirty && root.borrow().descendants_dirty);
+
+        // Nodes are in the context element's namespace
+        svg.set_inner_html("<circle></circle>").unwrap();
+        let circle = svg.borrow().children[0].clone();
+        assert_eq!(circle.borrow().namespace.as_deref(), Some(SVG_NAMESPACE));
+
+        // Errors leave the children alone
+        assert!(root.set_inner_html("<p>Unclosed").is_err());
+        assert_eq!(root.borrow().children.len(), 4);
+        assert!(root.borrow().children[0].set_inner_html("a").is_err());
+        root.borrow_mut().self_style_dirty = false;
+        root.set_inner_html("  ").unwrap();
+        assert!(root.borrow().children.is_empty());
+        // For :empty
+        assert!(root.borrow().self_style_dirty);
+
+        let fragment = parse_html_fragment("<p>1</p><p>2</p>").unwrap();
+        assert_eq!(fragment.borrow().children.len(), 2);
+        root.append_child(fragment);
+        assert_eq!(root.borrow().children.len(), 2);
+    }
+
+    #[test]
+    fn test_dom_node_builder() {
+        let root = DomNode::builder(ElemType::Div)
+            .id("main")
+            .class("a")
+            .class("b")
+            .attr("title", "t")
+            .bool_attr("hidden")
+            .attr("title", "u")
+            .child(DomNode::builder(ElemType::P).text("One"))
+            .child(DomNode::builder(ElemType::from("svg")).namespace(SVG_NAMESPACE))
+            .build();
+        let html = "<div id=\"main\" class=\"a b\" title=\"u\" hidden><p>One</p><svg></svg></div>";
+        assert!(root.is_equal_node(&parse_html(html).unwrap()));
+        assert_eq!(root.borrow().id.as_deref(), Some("main"));
+        assert!(root.borrow().has_class("a") && root.borrow().has_class("b"));
+        let names = root.borrow().attrs.keys().map(|name| name.to_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["id", "class", "title", "hidden"]);
+        let svg = root.borrow().children[1].clone();
+        assert_eq!(svg.borrow().namespace.as_deref(), Some(SVG_NAMESPACE));
+        assert_eq!(svg.child_index(), Some(2));
+        let text = root.borrow().children[0].borrow().children[0].clone();
+        assert_eq!(text.borrow().namespace, None);
+    }
+
+    #[test]
+    fn test_is_equal_node() {
+        let html = "<div id=\"a\" class=\"b c\"><p title=\"t\">One<!-- c --></p><img></div>";
+        let root = parse_html(html).unwrap();
+        assert!(root.is_equal_node(&root));
+        assert!(root.is_equal_node(&parse_html(html).unwrap()));
+        // Attribute order doesn't matter
+        let reordered = "<div class=\"b c\" id=\"a\"><p title=\"t\">One<!-- c --></p><img></div>";
+        assert!(root.is_equal_node(&parse_html(reordered).unwrap()));
+        assert!(root.is_equal_node(&root.clone_node(true)));
+        assert!(!root.is_equal_node(&root.clone_node(false)));
+
+        let different = [
+            "<div id=\"a\" class=\"b\"><p title=\"t\">One<!-- c --></p><img></div>",
+            "<div id=\"a\" class=\"b c\"><p title=\"t\">Two<!-- c --></p><img></div>",
+            "<div id=\"a\" class=\"b c\"><p title=\"t\">One<!-- d --></p><img></div>",
+            "<div id=\"a\" class=\"b c\"><p>One<!-- c --></p><img></div>",
+            "<div id=\"a\" class=\"b c\"><p title=\"t\">One<!-- c --></p></div>",
+            "<div id=\"a\" class=\"b c\"><h1 title=\"t\">One<!-- c --></h1><img></div>",
+        ];
+        for other in &different {
+            assert!(!root.is_equal_node(&parse_html(other).unwrap()), "{}", other);
+        }
+
+        // States aren't markup
+        let copy = root.clone_node(true);
+        copy.set_state(ElementState::HOVER, true);
+        assert!(root.is_equal_node(&copy));
+        copy.set_attribute("title", None);
+        assert!(!root.is_equal_node(&copy));
+    }
+
+    #[test]
+    fn test_clone_node() {
+        let root = parse_html("<div id=\"a\" class=\"b\"><p>One<!-- c --></p><img></div>").unwrap();
+        let p = root.borrow().children[0].clone();
+        p.set_state(ElementState::HOVER, true);
+
+        let deep = root.clone_node(true);
+        assert!(deep.eq_ignore_id_num(&root));
+        assert_ne!(deep.borrow().id_num, root.borrow().id_num);
+        assert_eq!(deep.parent(), None);
+        let deep_p = deep.borrow().children[0].clone();
+        assert!(deep_p.parent().unwrap().ptr_eq(&deep));
+        assert!(!deep_p.ptr_eq(&p));
+        assert!(deep_p.borrow().state.is_empty());
+        assert_eq!(deep.borrow().children_by_type.get(&ElemType::Img), Some(&vec![1]));
+
+        // Changing the copy leaves the original alone
+        deep.add_class("c");
+        deep_p.remove();
+        assert!(!root.borrow().has_class("c"));
+        assert_eq!(root.borrow().children.len(), 2);
+
+        let shallow = p.clone_node(false);
+        assert_eq!(shallow.borrow().elem_type, ElemType::P);
+        assert!(shallow.borrow().children.is_empty());
+        assert!(shallow.borrow().style_dirty);
     }
 
     #[test]
@@ -782,6 +2595,31 @@ mod tests {
         assert_eq!(parent.borrow().children[2].rev_child_index(), Some(1));
     }
 
+    #[test]
+    fn test_type_index() {
+        let parent = parse_html("<div><p>1</p><a></a><p>2</p><p>3</p></div>").unwrap();
+        let children = parent.borrow().children.to_vec();
+        let type_indices = |children: &[DomNodeRef]| {
+            children
+                .iter()
+                .map(|child| (child.type_index(), child.rev_type_index()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            type_indices(&children),
+            vec![(Some(1), Some(3)), (Some(1), Some(1)), (Some(2), Some(2)), (Some(3), Some(1))]
+        );
+        let text = children[0].borrow().children[0].clone();
+        assert_eq!(text.type_index(), Some(1));
+
+        assert!(parent.remove_child(&children[0]));
+        assert_eq!(children[0].type_index(), None);
+        assert_eq!(
+            type_indices(&children[1..]),
+            vec![(Some(1), Some(1)), (Some(1), Some(2)), (Some(2), Some(1))]
+        );
+    }
+
     #[test]
     fn test_child_index3() {
         let parent =
@@ -905,4 +2743,201 @@ mod tests {
         assert!(!expr.matches(4));
         assert!(!expr.matches(5));
     }
+
+    #[test]
+    fn test_convert_to_stylesheet() {
+        use magicparser::cssparser::CssParser;
+
+        let (blocks, _) =
+            CssParser::parse("a { COLOR: red; color: blue !important } b {} a { margin: 0 }");
+        let a = || {
+            Selector::Simple(SimpleSelector::new(Some(ElemType::A), None, hashset!{}, false))
+        };
+        assert_eq!(
+            Stylesheet::from(blocks),
+            Stylesheet::new(vec![
+                StyleRule::new(
+                    a(),
+                    vec![
+                        Declaration::new("color".to_string(), "red".to_string()),
+                        Declaration::new_with_importance(
+                            "color".to_string(),
+                            "blue".to_string(),
+                            true,
+                        ),
+                    ],
+                ),
+                StyleRule::new(
+                    Selector::Simple(SimpleSelector::new(
+                        Some(ElemType::Custom(Atom::from("b"))),
+                        None,
+                        hashset!{},
+                        false,
+                    )),
+                    vec![],
+                ),
+                StyleRule::new(
+                    a(),
+                    vec![Declaration::new("margin".to_string(), "0".to_string())],
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_selector_specificity() {
+        use magicparser::parse_css;
+
+        let specificity = |css: &str| {
+            let CssBlocks(blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+            blocks[0].0.specificity()
+        };
+        assert_eq!(specificity("*"), Specificity(0, 0, 0));
+        assert_eq!(specificity("li"), Specificity(0, 0, 1));
+        assert_eq!(specificity("ul li"), Specificity(0, 0, 2));
+        assert_eq!(specificity("ul ol + li"), Specificity(0, 0, 3));
+        assert_eq!(specificity("h1 + *[rel=up]"), Specificity(0, 1, 1));
+        assert_eq!(specificity("ul ol li.red"), Specificity(0, 1, 3));
+        assert_eq!(specificity("li.red.level"), Specificity(0, 2, 1));
+        assert_eq!(specificity("#x34y"), Specificity(1, 0, 0));
+        assert_eq!(specificity("#s12:not(foo)"), Specificity(1, 0, 1));
+        assert_eq!(specificity("p:hover::first-line"), Specificity(0, 1, 2));
+        assert_eq!(specificity("a, #b, .c"), Specificity(1, 0, 0));
+        assert!(Specificity(1, 0, 0) > Specificity(0, 9, 9));
+    }
+
+    #[test]
+    fn test_parse_declaration_important() {
+        let decl = |value: &str| Declaration::parse("color".to_string(), value);
+        assert_eq!(
+            decl("red !important"),
+            Declaration::new_with_importance("color".to_string(), "red".to_string(), true)
+        );
+        assert_eq!(
+            decl("red! IMPORTANT "),
+            Declaration::new_with_importance("color".to_string(), "red".to_string(), true)
+        );
+        assert_eq!(
+            decl("red"),
+            Declaration::new("color".to_string(), "red".to_string())
+        );
+        assert_eq!(
+            decl("\"!important\""),
+            Declaration::new("color".to_string(), "\"!important\"".to_string())
+        );
+    }
+
+    // Clears the dirty bits of node and its descendants, as restyling does
+    fn clear_dirty(node: &DomNodeRef) {
+        node.borrow_mut().style_dirty = false;
+        node.borrow_mut().descendants_dirty = false;
+        for child in &node.borrow().children {
+            clear_dirty(child);
+        }
+    }
+
+    #[test]
+    fn test_edit_stylesheet() {
+        let mut stylesheet = ::magicparser::parse_stylesheet("a { color: red } b {}").unwrap();
+        assert_eq!(stylesheet.insert_rule("@media print { i { color: blue } }", 2), Ok(2));
+        assert_eq!(stylesheet.rules[2].property_value("COLOR"), Some("blue"));
+        assert_eq!(stylesheet.rules[2].media.len(), 1);
+        assert!(stylesheet.insert_rule("u {}", 4).is_err());
+        assert!(stylesheet.insert_rule("u {} s {}", 0).is_err());
+        assert!(stylesheet.insert_rule("@keyframes k {}", 0).is_err());
+        assert!(stylesheet.insert_rule("u[ {}", 0).is_err());
+        assert_eq!(stylesheet.rules.len(), 3);
+        assert!(stylesheet.delete_rule(3).is_err());
+        let deleted = stylesheet.delete_rule(1).unwrap();
+        assert!(deleted.declarations.is_empty());
+        assert_eq!(stylesheet.rules.len(), 2);
+
+        let rule = &mut stylesheet.rules[0];
+        rule.declarations.push(Declaration::new("margin".to_string(), "0".to_string()));
+        rule.declarations.push(Declaration::new("color".to_string(), "green".to_string()));
+        assert!(rule.set_property("Color", "blue !important", false));
+        assert!(rule.set_property("padding", "1px", true));
+        assert!(!rule.set_property("$", "1px", false));
+        assert_eq!(
+            rule.declarations,
+            vec![
+                Declaration::new_with_importance("color".to_string(), "blue".to_string(), true),
+                Declaration::new("margin".to_string(), "0".to_string()),
+                Declaration::new_with_importance("padding".to_string(), "1px".to_string(), true),
+            ]
+        );
+        assert_eq!(rule.remove_property("margin"), Some("0".to_string()));
+        assert_eq!(rule.remove_property("margin"), None);
+        assert_eq!(rule.declarations.len(), 2);
+    }
+
+    #[test]
+    fn test_mutations_mark_style_dirty() {
+        use magicparser::parse_html;
+
+        let root = parse_html("<div><p></p><p id=\"a\"></p><p></p></div>").unwrap();
+        assert!(root.borrow().style_dirty);
+        let children = root.borrow().children.clone();
+        let is_dirty = |node: &DomNodeRef| node.borrow().style_dirty;
+
+        clear_dirty(&root);
+        children[1].add_class("b");
+        assert_eq!(children[1].borrow().attr("class"), Some(&Some("b".to_string())));
+        assert!(children[1].borrow().has_class("b"));
+        assert!(!is_dirty(&root) && root.borrow().descendants_dirty);
+        assert!(!is_dirty(&children[0]) && is_dirty(&children[1]) && is_dirty(&children[2]));
+
+        clear_dirty(&root);
+        children[1].remove_class("c");
+        children[0].remove_attribute("title");
+        assert!(!root.borrow().descendants_dirty);
+        children[1].set_attribute("ID", Some("c".to_string()));
+        assert_eq!(children[1].borrow().id, Some(Atom::from("c")));
+        assert!(is_dirty(&children[1]));
+
+        let class_list = children[0].class_list();
+        assert!(class_list.is_empty());
+        class_list.add("x");
+        class_list.add("y");
+        assert!(class_list.toggle("z"));
+        assert!(!class_list.toggle("x"));
+        assert_eq!(class_list.to_vec(), vec!["y", "z"]);
+        assert!(class_list.contains("z") && !class_list.contains("x"));
+        assert_eq!(class_list.len(), 2);
+        assert_eq!(children[0].borrow().attr("class"), Some(&Some("y z".to_string())));
+
+        clear_dirty(&root);
+        assert!(root.remove_child(&children[1]));
+        assert!(!root.remove_child(&children[1]));
+        assert_eq!(children[1].parent(), None);
+        // The earlier ones too, for :nth-last-child() and the like
+        assert!(is_dirty(&children[0]) && is_dirty(&children[2]));
+    }
+
+    #[test]
+    fn test_set_attribute_name_case() {
+        use magicparser::{parse_html, Document};
+
+        let root = parse_html("<div><svg></svg></div>").unwrap();
+        let svg = root.borrow().children[0].clone();
+        let value = Some("0 0 10 10".to_string());
+        root.set_attribute("Title", Some("t".to_string()));
+        assert_eq!(root.borrow().attr("title"), Some(&Some("t".to_string())));
+        // Only HTML elements' attribute names are lowercased
+        svg.set_attribute("viewBox", value.clone());
+        assert_eq!(svg.borrow().attr("viewBox"), Some(&value));
+        assert_eq!(svg.borrow().attr("viewbox"), None);
+        assert_eq!(svg.take_attr("viewbox"), None);
+        svg.remove_attribute("viewBox");
+        assert_eq!(svg.borrow().attr("viewBox"), None);
+
+        // Nor any in XML documents
+        let mut document = Document::new(root.clone());
+        document.set_document_kind(DocumentKind::Xml);
+        root.set_attribute("dataValue", Some("1".to_string()));
+        assert_eq!(root.borrow().attr("dataValue"), Some(&Some("1".to_string())));
+        root.remove_attribute("datavalue");
+        assert_eq!(root.replace_attr("dataValue", None), Some(Some("1".to_string())));
+        assert_eq!(root.borrow().attr("datavalue"), None);
+    }
 }
@@ -1,8 +1,9 @@
 use magicparser::error::{Error, MultipleErrors};
 use magicparser::lexer::Lexer;
 use magicparser::parser::Parser;
-use magicparser::{ElemType, Pos, Token};
+use magicparser::{AttrCaseFlag, ElemType, Pos, Token};
 use std::boxed::Box;
+use std::collections::HashMap;
 use std::convert::From;
 use std::result;
 
@@ -11,6 +12,7 @@ enum SelectorParserError {
     Eof(Pos),
     Unexpected(Pos, String),
     MultipleIds(Pos, String),
+    UndeclaredNamespacePrefix(Pos, String),
     Multiple(Vec<SelectorParserError>),
 }
 
@@ -28,6 +30,9 @@ impl From<SelectorParserError> for Error {
             SelectorParserError::MultipleIds(pos, ids) => {
                 Error::Unexpected(pos, format!("{:?}", ids))
             }
+            SelectorParserError::UndeclaredNamespacePrefix(pos, prefix) => {
+                Error::Unexpected(pos, format!("undeclared namespace prefix: {}", prefix))
+            }
             SelectorParserError::Multiple(errs) => {
                 Error::Multiple(errs.into_iter().map(Error::from).collect())
             }
@@ -60,6 +65,34 @@ pub(super) enum AttrSelectorOp {
     ContainsAtLeastOne(Pos), // *=
 }
 
+// Standard pseudo-classes the parser doesn't support. Like browsers that
+// don't support one, it rejects selectors with them, rather than parsing
+// them as custom pseudo-classes that never match.
+static UNSUPPORTED_PSEUDO_CLASSES: &[&str] = &[
+    "any-link", "autofill", "blank", "buffering", "current", "dir", "first", "focus-visible",
+    "focus-within", "fullscreen", "future", "has", "host-context", "in-range", "invalid", "is",
+    "left", "local-link", "modal", "muted", "nth-col", "nth-last-col", "only-child",
+    "only-of-type", "open", "optional", "out-of-range", "past", "paused", "picture-in-picture",
+    "placeholder-shown", "playing", "popover-open", "read-only", "read-write", "required",
+    "right", "root", "scope", "seeking", "stalled", "state", "target-within", "user-invalid",
+    "user-valid", "valid", "volume-locked", "where",
+];
+
+// Prefixes declared with @namespace. The default namespace (declared without a
+// prefix) applies to type and universal selectors that don't have a prefix.
+#[derive(Debug, Default, Eq, PartialEq, Clone)]
+pub(super) struct Namespaces {
+    pub default: Option<String>,
+    pub prefixes: HashMap<String, String>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(super) enum NamespacePrefix {
+    Any(Pos),         // *|
+    NoNamespace(Pos), // |
+    Url(Pos, String), // ns|, resolved to the namespace url
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub(super) struct SimpleSelector {
     pub pos: Pos,
@@ -67,15 +100,28 @@ pub(super) struct SimpleSelector {
     pub id: Option<Token>,   // AttrIdentifier or Str
     pub classes: Vec<Token>, // AttrIdentifier or Str
     pub universal: bool,
+    pub namespace: Option<NamespacePrefix>, // None if there is no prefix or default namespace
 }
 
 impl SimpleSelector {
+    #[cfg(test)]
     pub fn new(
         pos: Pos,
         elem_type: Option<ElemType>,
         id: Option<Token>,
         classes: Vec<Token>,
         universal: bool,
+    ) -> SimpleSelector {
+        Self::new_with_namespace(pos, elem_type, id, classes, universal, None)
+    }
+
+    pub fn new_with_namespace(
+        pos: Pos,
+        elem_type: Option<ElemType>,
+        id: Option<Token>,
+        classes: Vec<Token>,
+        universal: bool,
+        namespace: Option<NamespacePrefix>,
     ) -> SimpleSelector {
         SimpleSelector {
             pos,
@@ -83,6 +129,7 @@ impl SimpleSelector {
             id,
             classes,
             universal,
+            namespace,
         }
     }
 }
@@ -92,21 +139,34 @@ pub(super) struct AttrSelector {
     pub pos: Pos,
     pub attr: Token,
     pub op_val: Option<(AttrSelectorOp, Token)>,
-    pub case_insensitive: bool,
+    pub case_flag: AttrCaseFlag,
+    pub namespace: Option<NamespacePrefix>, // None if there is no prefix
 }
 
 impl AttrSelector {
+    #[cfg(test)]
     pub(super) fn new(
         pos: Pos,
         attr: Token,
         op_val: Option<(AttrSelectorOp, Token)>,
-        case_insensitive: bool,
+        case_flag: AttrCaseFlag,
+    ) -> AttrSelector {
+        Self::new_with_namespace(pos, attr, op_val, case_flag, None)
+    }
+
+    pub(super) fn new_with_namespace(
+        pos: Pos,
+        attr: Token,
+        op_val: Option<(AttrSelectorOp, Token)>,
+        case_flag: AttrCaseFlag,
+        namespace: Option<NamespacePrefix>,
     ) -> AttrSelector {
         AttrSelector {
             pos,
             attr,
             op_val,
-            case_insensitive,
+            case_flag,
+            namespace,
         }
     }
 }
@@ -128,12 +188,20 @@ pub(super) enum NthExpr {
 #[derive(Debug, Eq, PartialEq)]
 pub(super) enum PseudoClassSelector {
     Active(Pos),
+    Checked(Pos),
+    Default(Pos),
+    Defined(Pos),
+    Disabled(Pos),
+    Empty(Pos),
+    Enabled(Pos),
     FirstChild(Pos),
     FirstOfType(Pos),
+    Focus(Pos),
     Hover(Pos),
     // experimental: Dir,
-    // experimental: Host,
+    Host(Pos, Option<Box<Selector>>), // :host or :host(<compound-selector>)
     // experimental: HostContext,
+    Indeterminate(Pos),
     Lang(Pos, Token),
     LastChild(Pos),
     LastOfType(Pos),
@@ -145,6 +213,8 @@ pub(super) enum PseudoClassSelector {
     NthLastChild(Pos, NthExpr),
     NthLastOfType(Pos, NthExpr),
     NthOfType(Pos, NthExpr),
+    Target(Pos),
+    Custom(Pos, String), // lowercased name of a pseudo-class not known to the parser
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -154,8 +224,10 @@ pub(super) enum PseudoElementSelector {
     Cue(Pos),
     FirstLetter(Pos),
     FirstLine(Pos),
+    Placeholder(Pos),
     Selection(Pos),
-    Slotted(Pos),
+    Slotted(Pos, Box<Selector>), // ::slotted(<compound-selector>)
+    Part(Pos, Vec<Token>),       // ::part(<ident>+)
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -179,17 +251,61 @@ pub(super) enum Selector {
 
 pub(super) struct SelectorParser {
     lexer: Lexer,
+    namespaces: Namespaces,
 }
 
 impl SelectorParser {
     #[cfg(test)]
     fn new(input: &str) -> SelectorParser {
-        Self::new_with_pos(input, (0, 1, 1))
+        Self::new_with_pos(input, (0, 1, 1), Namespaces::default())
     }
 
-    fn new_with_pos(input: &str, pos: Pos) -> SelectorParser {
+    fn new_with_pos(input: &str, pos: Pos, namespaces: Namespaces) -> SelectorParser {
         SelectorParser {
             lexer: Lexer::new_with_pos(input, pos, "", ""),
+            namespaces,
+        }
+    }
+
+    // strict. Parses "*|", "|", or "prefix|". Fails on "|=" so that attribute
+    // selectors like [lang|=en] aren't mistaken for a prefix.
+    fn parse_namespace_prefix(&mut self) -> Result<NamespacePrefix> {
+        let start_pos = self.pos();
+        let prefix = match self.lexer.peek_char()? {
+            (_, '*') => {
+                self.lexer.consume_char()?;
+                Some("*".to_string())
+            }
+            (_, '|') => None,
+            _ => match self.parse_elem_identifier_strict()? {
+                Token::ElemIdentifier(_, prefix) => Some(prefix),
+                _ => unreachable!(),
+            },
+        };
+        self.lexer.parse_chars_strict("|")?;
+        if let Ok((pos, '=')) = self.lexer.peek_char() {
+            return Err(SelectorParserError::Unexpected(
+                pos,
+                "expected namespace prefix".to_string(),
+            ));
+        }
+        match prefix {
+            None => Ok(NamespacePrefix::NoNamespace(start_pos)),
+            Some(ref prefix) if prefix == "*" => Ok(NamespacePrefix::Any(start_pos)),
+            Some(prefix) => match self.namespaces.prefixes.get(&prefix) {
+                Some(url) => Ok(NamespacePrefix::Url(start_pos, url.to_string())),
+                None => Err(SelectorParserError::UndeclaredNamespacePrefix(
+                    start_pos, prefix,
+                )),
+            },
+        }
+    }
+
+    fn try_parse_namespace_prefix(&mut self) -> Result<Option<NamespacePrefix>> {
+        match self.try(Self::parse_namespace_prefix) {
+            Ok(namespace) => Ok(Some(namespace)),
+            Err(err @ SelectorParserError::UndeclaredNamespacePrefix(..)) => Err(err),
+            Err(_) => Ok(None),
         }
     }
 
@@ -203,6 +319,7 @@ impl SelectorParser {
         let mut id: Option<Token> = None;
         let mut universal = false;
         let mut found = false;
+        let prefix = self.try_parse_namespace_prefix()?;
         loop {
             match self.lexer.peek_char() {
                 Ok((_, '.')) => {
@@ -246,9 +363,20 @@ impl SelectorParser {
                 start_pos,
                 "empty simple selector".to_string(),
             ))
+        } else if prefix.is_some() && elem_type.is_none() && !universal {
+            Err(SelectorParserError::Unexpected(
+                start_pos,
+                "expected type or universal selector after namespace prefix".to_string(),
+            ))
         } else {
-            Ok(Selector::Simple(SimpleSelector::new(
-                start_pos, elem_type, id, classes, universal,
+            let namespace = prefix.or_else(|| {
+                self.namespaces
+                    .default
+                    .as_ref()
+                    .map(|url| NamespacePrefix::Url(start_pos, url.to_string()))
+            });
+            Ok(Selector::Simple(SimpleSelector::new_with_namespace(
+                start_pos, elem_type, id, classes, universal, namespace,
             )))
         }
     }
@@ -275,6 +403,8 @@ impl SelectorParser {
     fn parse_attr_selector(&mut self) -> Result<Selector> {
         let start_pos = self.pos();
         self.lexer.parse_chars_strict("[")?;
+        self.lexer.consume_whitespace()?;
+        let namespace = self.try_parse_namespace_prefix()?;
         let attr = self.parse_attr_identifier()?;
         let op = match self.parse_attr_selector_op() {
             Ok(op) => Some(op),
@@ -289,25 +419,26 @@ impl SelectorParser {
             }
             None => None,
         };
-        let case_insensitive = self.lexer.try_parse_chars_list(vec!["i", "I"]).is_ok();
-        if op.is_none() {
-            if case_insensitive {
-                return Err(SelectorParserError::Unexpected(
-                    start_pos,
-                    "cannot specify i/I for attribute selector without op and val".to_string(),
-                ));
-            }
+        let case_flag = match self.lexer.try_parse_chars_list(vec!["i", "I", "s", "S"]) {
+            Ok((_, ref flag)) if flag.eq_ignore_ascii_case("i") => AttrCaseFlag::Insensitive,
+            Ok(_) => AttrCaseFlag::Sensitive,
+            Err(_) => AttrCaseFlag::Unspecified,
+        };
+        if op.is_none() && case_flag != AttrCaseFlag::Unspecified {
+            let flag = if case_flag == AttrCaseFlag::Insensitive { "i/I" } else { "s/S" };
+            return Err(SelectorParserError::Unexpected(
+                start_pos,
+                format!("cannot specify {} for attribute selector without op and val", flag),
+            ));
         }
         self.lexer.parse_chars("]")?;
-        let op_val = match op {
-            Some(op) => Some((op, val.unwrap())),
-            None => None,
-        };
-        Ok(Selector::Attr(AttrSelector::new(
+        let op_val = op.map(|op| (op, val.unwrap()));
+        Ok(Selector::Attr(AttrSelector::new_with_namespace(
             start_pos,
             attr,
             op_val,
-            case_insensitive,
+            case_flag,
+            namespace,
         )))
     }
 
@@ -321,10 +452,12 @@ impl SelectorParser {
         }
         let parsers: Vec<ParserFn<Selector>> = vec![
             Self::parse_attr_selector,
-            Self::parse_pseudo_class_selector,
+            // Before pseudo-classes, since any unknown :name parses as a custom
+            // pseudo-class and would shadow the legacy single-colon pseudo-elements
             Self::parse_pseudo_element_selector,
+            Self::parse_pseudo_class_selector,
         ];
-        let mut parser_err: Option<SelectorParserError> = None;
+        let parser_err: Option<SelectorParserError>;
         loop {
             let selector = match self.try_parsers(&parsers) {
                 Ok(sel) => sel,
@@ -366,6 +499,34 @@ impl SelectorParser {
         Ok(Box::new(selectors))
     }
 
+    // Parses (<compound-selector>), as taken by :host() and ::slotted()
+    fn parse_compound_selector_args(&mut self) -> Result<Box<Selector>> {
+        self.lexer.parse_chars_strict("(")?;
+        // parse_selector_seq() is strict, so consume whitespace first
+        self.lexer.consume_whitespace()?;
+        let selector = self.parse_selector_seq()?;
+        self.lexer.parse_chars(")")?;
+        Ok(Box::new(selector))
+    }
+
+    // The argument of :host is optional
+    fn parse_pcs_host_args(&mut self) -> Result<Option<Box<Selector>>> {
+        match self.lexer.peek_char() {
+            Ok((_, '(')) => Ok(Some(self.parse_compound_selector_args()?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_pes_part_args(&mut self) -> Result<Vec<Token>> {
+        self.lexer.parse_chars_strict("(")?;
+        let mut names = vec![self.parse_attr_identifier()?];
+        while let Ok(name) = self.try(Self::parse_attr_identifier) {
+            names.push(name);
+        }
+        self.lexer.parse_chars(")")?;
+        Ok(names)
+    }
+
     fn parse_nth_expr(&mut self) -> Result<NthExpr> {
         self.lexer.consume_whitespace()?;
         let start_pos = self.pos();
@@ -383,15 +544,9 @@ impl SelectorParser {
                     Err(err) => return Err(err),
                 };
                 let n = if a.is_some() {
-                    match self.lexer.try_parse_chars_strict("n") {
-                        Ok(_) => true,
-                        Err(_) => false,
-                    }
+                    self.lexer.try_parse_chars_strict("n").is_ok()
                 } else {
-                    match self.lexer.try_parse_chars("n") {
-                        Ok(_) => true,
-                        Err(_) => false,
-                    }
+                    self.lexer.try_parse_chars("n").is_ok()
                 };
                 let op = match self.lexer.parse_chars_list(vec!["+", "-"]) {
                     Ok((pos, st)) => match st.as_ref() {
@@ -461,10 +616,21 @@ impl SelectorParser {
                     Token::ElemIdentifier(_, sel_name) => {
                         match sel_name.to_ascii_lowercase().as_ref() {
                             "active" => Active(pos),
+                            "checked" => Checked(pos),
+                            "default" => Default(pos),
+                            "defined" => Defined(pos),
+                            "disabled" => Disabled(pos),
+                            "empty" => Empty(pos),
+                            "enabled" => Enabled(pos),
                             "first-child" => FirstChild(pos),
+                            "first-of-type" => FirstOfType(pos),
+                            "focus" => Focus(pos),
+                            "host" => Host(pos, self.parse_pcs_host_args()?),
                             "hover" => Hover(pos),
+                            "indeterminate" => Indeterminate(pos),
                             "lang" => Lang(pos, self.parse_pcs_lang_args()?),
                             "last-child" => LastChild(pos),
+                            "last-of-type" => LastOfType(pos),
                             "link" => Link(pos),
                             "matches" => Matches(pos, self.parse_pcs_selector_list_args()?),
                             "not" => Not(pos, self.parse_pcs_selector_list_args()?),
@@ -472,13 +638,16 @@ impl SelectorParser {
                             "nth-last-child" => NthLastChild(pos, self.parse_nth_pcs_args()?),
                             "nth-last-of-type" => NthLastOfType(pos, self.parse_nth_pcs_args()?),
                             "nth-of-type" => NthOfType(pos, self.parse_nth_pcs_args()?),
+                            "target" => Target(pos),
                             "visited" => Visited(pos),
-                            _ => {
+                            name if UNSUPPORTED_PSEUDO_CLASSES.contains(&name) => {
                                 return Err(SelectorParserError::Unexpected(
                                     pos,
-                                    format!("unsupported pseudo-class selector: ::{}", sel_name),
+                                    format!("unsupported pseudo-class selector: :{}", name),
                                 ))
                             }
+                            // May be registered with the matcher by the embedder
+                            name => Custom(pos, name.to_string()),
                         }
                     }
                     _ => unreachable!(),
@@ -500,8 +669,13 @@ impl SelectorParser {
                 "cue" => Ok(PseudoElementSelector::Cue(start_pos)),
                 "first-letter" => Ok(PseudoElementSelector::FirstLetter(start_pos)),
                 "first-line" => Ok(PseudoElementSelector::FirstLine(start_pos)),
+                "placeholder" => Ok(PseudoElementSelector::Placeholder(start_pos)),
                 "selection" => Ok(PseudoElementSelector::Selection(start_pos)),
-                "slotted" => Ok(PseudoElementSelector::Slotted(start_pos)),
+                "slotted" => Ok(PseudoElementSelector::Slotted(
+                    start_pos,
+                    self.parse_compound_selector_args()?,
+                )),
+                "part" => Ok(PseudoElementSelector::Part(start_pos, self.parse_pes_part_args()?)),
                 _ => Err(SelectorParserError::Unexpected(
                     start_pos,
                     format!("unsupported pseudo-element selector: ::{}", sel_name),
@@ -590,11 +764,7 @@ impl SelectorParser {
         // parse_selector_seq() is strict, so consume whitespace first
         self.lexer.consume_whitespace()?;
         selectors.push(self.try(Self::parse_selector)?);
-        loop {
-            match self.lexer.try_parse_chars(",") {
-                Ok(_) => (),
-                Err(_) => break,
-            }
+        while self.lexer.try_parse_chars(",").is_ok() {
             self.lexer.consume_whitespace()?;
             match self.try(Self::parse_selector) {
                 Ok(sel) => selectors.push(sel),
@@ -608,8 +778,12 @@ impl SelectorParser {
         }
     }
 
-    pub fn parse(input: &str, pos: Pos) -> result::Result<Selector, Error> {
-        let mut parser = SelectorParser::new_with_pos(input, pos);
+    pub fn parse(
+        input: &str,
+        pos: Pos,
+        namespaces: &Namespaces,
+    ) -> result::Result<Selector, Error> {
+        let mut parser = SelectorParser::new_with_pos(input, pos, namespaces.clone());
         let group = parser.parse_selector_list()?;
         match parser.lexer.peek_char() {
             Ok((pos, ch)) => Err(Error::Unexpected(
@@ -724,6 +898,114 @@ mod tests {
         assert_eq!(parser.pos(), (13, 1, 14))
     }
 
+    #[test]
+    fn test_parse_simple_selector_namespace() {
+        let mut namespaces = Namespaces::default();
+        namespaces
+            .prefixes
+            .insert("svg".to_string(), "http://www.w3.org/2000/svg".to_string());
+        let mut parser = SelectorParser::new_with_pos("svg|rect.cl", (0, 1, 1), namespaces);
+        let res = parser.parse_simple_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::Simple(SimpleSelector::new_with_namespace(
+                (0, 1, 1),
+                Some(ElemType::from("rect")),
+                None,
+                vec![Token::AttrIdentifier((9, 1, 10), "cl".to_string())],
+                false,
+                Some(NamespacePrefix::Url(
+                    (0, 1, 1),
+                    "http://www.w3.org/2000/svg".to_string()
+                )),
+            )))
+        );
+        assert_eq!(parser.pos(), (11, 1, 12))
+    }
+
+    #[test]
+    fn test_parse_simple_selector_any_and_no_namespace() {
+        let mut parser = SelectorParser::new("*|*");
+        let res = parser.parse_simple_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::Simple(SimpleSelector::new_with_namespace(
+                (0, 1, 1),
+                None,
+                None,
+                vec![],
+                true,
+                Some(NamespacePrefix::Any((0, 1, 1))),
+            )))
+        );
+        assert_eq!(parser.pos(), (3, 1, 4));
+
+        let mut parser = SelectorParser::new("|a");
+        let res = parser.parse_simple_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::Simple(SimpleSelector::new_with_namespace(
+                (0, 1, 1),
+                Some(ElemType::A),
+                None,
+                vec![],
+                false,
+                Some(NamespacePrefix::NoNamespace((0, 1, 1))),
+            )))
+        );
+        assert_eq!(parser.pos(), (2, 1, 3));
+    }
+
+    #[test]
+    fn test_parse_simple_selector_default_namespace() {
+        let namespaces = Namespaces {
+            default: Some("http://www.w3.org/1999/xhtml".to_string()),
+            prefixes: HashMap::new(),
+        };
+        let mut parser = SelectorParser::new_with_pos(".cl", (0, 1, 1), namespaces);
+        let res = parser.parse_simple_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::Simple(SimpleSelector::new_with_namespace(
+                (0, 1, 1),
+                None,
+                None,
+                vec![Token::AttrIdentifier((1, 1, 2), "cl".to_string())],
+                false,
+                Some(NamespacePrefix::Url(
+                    (0, 1, 1),
+                    "http://www.w3.org/1999/xhtml".to_string()
+                )),
+            )))
+        );
+    }
+
+    #[test]
+    fn test_parse_simple_selector_undeclared_namespace_fail() {
+        let mut parser = SelectorParser::new("svg|rect");
+        let res = parser.parse_simple_selector();
+        assert_eq!(
+            res,
+            Err(SelectorParserError::UndeclaredNamespacePrefix(
+                (0, 1, 1),
+                "svg".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_simple_selector_namespace_without_type_fail() {
+        let mut parser = SelectorParser::new("*|.cl");
+        let res = parser.parse_simple_selector();
+        assert_eq!(
+            res,
+            Err(SelectorParserError::Unexpected(
+                (0, 1, 1),
+                "expected type or universal selector after namespace prefix".to_string()
+            ))
+        );
+    }
+
     #[test]
     fn test_parse_simple_selector_fail() {
         let mut parser = SelectorParser::new("-ab");
@@ -786,7 +1068,7 @@ mod tests {
                 (0, 1, 1),
                 Token::AttrIdentifier((2, 1, 3), "abc".to_string()),
                 None,
-                false,
+                AttrCaseFlag::Unspecified,
             )))
         );
         assert_eq!(parser.pos(), (7, 1, 8));
@@ -805,12 +1087,91 @@ mod tests {
                     AttrSelectorOp::Exactly((4, 1, 5)),
                     Token::AttrIdentifier((6, 1, 7), "b".to_string())
                 )),
-                true,
+                AttrCaseFlag::Insensitive,
             )))
         );
         assert_eq!(parser.pos(), (11, 1, 12));
     }
 
+    #[test]
+    fn test_parse_attr_selector_case_sensitive() {
+        let mut parser = SelectorParser::new("[a=\"b\" S]");
+        let res = parser.parse_attr_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::Attr(AttrSelector::new(
+                (0, 1, 1),
+                Token::AttrIdentifier((1, 1, 2), "a".to_string()),
+                Some((
+                    AttrSelectorOp::Exactly((2, 1, 3)),
+                    Token::Str((3, 1, 4), "b".to_string())
+                )),
+                AttrCaseFlag::Sensitive,
+            )))
+        );
+        assert_eq!(parser.pos(), (9, 1, 10));
+    }
+
+    #[test]
+    fn test_parse_attr_selector_exactly_or_hyphen_not_namespace() {
+        let mut parser = SelectorParser::new("[lang|=en]");
+        let res = parser.parse_attr_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::Attr(AttrSelector::new(
+                (0, 1, 1),
+                Token::AttrIdentifier((1, 1, 2), "lang".to_string()),
+                Some((
+                    AttrSelectorOp::ExactlyOrHyphen((5, 1, 6)),
+                    Token::AttrIdentifier((7, 1, 8), "en".to_string())
+                )),
+                AttrCaseFlag::Unspecified,
+            )))
+        );
+        assert_eq!(parser.pos(), (10, 1, 11));
+    }
+
+    #[test]
+    fn test_parse_attr_selector_namespace() {
+        let mut namespaces = Namespaces::default();
+        namespaces
+            .prefixes
+            .insert("xl".to_string(), "http://www.w3.org/1999/xlink".to_string());
+        let mut parser = SelectorParser::new_with_pos("[xl|href]", (0, 1, 1), namespaces);
+        let res = parser.parse_attr_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::Attr(AttrSelector::new_with_namespace(
+                (0, 1, 1),
+                Token::AttrIdentifier((4, 1, 5), "href".to_string()),
+                None,
+                AttrCaseFlag::Unspecified,
+                Some(NamespacePrefix::Url(
+                    (1, 1, 2),
+                    "http://www.w3.org/1999/xlink".to_string()
+                )),
+            )))
+        );
+        assert_eq!(parser.pos(), (9, 1, 10));
+    }
+
+    #[test]
+    fn test_parse_attr_selector_any_namespace() {
+        let mut parser = SelectorParser::new("[*|href]");
+        let res = parser.parse_attr_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::Attr(AttrSelector::new_with_namespace(
+                (0, 1, 1),
+                Token::AttrIdentifier((3, 1, 4), "href".to_string()),
+                None,
+                AttrCaseFlag::Unspecified,
+                Some(NamespacePrefix::Any((1, 1, 2))),
+            )))
+        );
+        assert_eq!(parser.pos(), (8, 1, 9));
+    }
+
     #[test]
     fn test_parse_attr_selector_fail1() {
         let mut parser = SelectorParser::new("[ a = a/ ]");
@@ -839,6 +1200,19 @@ mod tests {
         assert_eq!(parser.pos(), (5, 1, 6));
     }
 
+    #[test]
+    fn test_parse_attr_selector_fail_case_sensitive() {
+        let mut parser = SelectorParser::new("[a s]");
+        let res = parser.parse_attr_selector();
+        assert_eq!(
+            res,
+            Err(SelectorParserError::Unexpected(
+                (0, 1, 1),
+                "cannot specify s/S for attribute selector without op and val".to_string()
+            ))
+        );
+    }
+
     #[test]
     fn test_parse_attr_selector_fail_empty1() {
         let mut parser = SelectorParser::new("[]");
@@ -871,7 +1245,7 @@ mod tests {
                     (4, 1, 5),
                     Token::AttrIdentifier((5, 1, 6), "href".to_string()),
                     None,
-                    false,
+                    AttrCaseFlag::Unspecified,
                 )),
                 Selector::Attr(AttrSelector::new(
                     (10, 1, 11),
@@ -880,7 +1254,7 @@ mod tests {
                         AttrSelectorOp::ExactlyOne((16, 1, 17)),
                         Token::Str((18, 1, 19), "cl".to_string()),
                     )),
-                    false,
+                    AttrCaseFlag::Unspecified,
                 )),
             ]))
         );
@@ -898,7 +1272,7 @@ mod tests {
                     (0, 1, 1),
                     Token::AttrIdentifier((1, 1, 2), "href".to_string()),
                     None,
-                    false,
+                    AttrCaseFlag::Unspecified,
                 )),
                 Selector::Attr(AttrSelector::new(
                     (6, 1, 7),
@@ -907,7 +1281,7 @@ mod tests {
                         AttrSelectorOp::ExactlyOne((12, 1, 13)),
                         Token::Str((14, 1, 15), "cl".to_string()),
                     )),
-                    false,
+                    AttrCaseFlag::Unspecified,
                 )),
             ]))
         );
@@ -925,7 +1299,7 @@ mod tests {
                     (0, 1, 1),
                     Token::AttrIdentifier((1, 1, 2), "href".to_string()),
                     None,
-                    false,
+                    AttrCaseFlag::Unspecified,
                 )),
                 Selector::PseudoClass(PseudoClassSelector::NthChild(
                     (6, 1, 7),
@@ -951,7 +1325,7 @@ mod tests {
                     (16, 1, 17),
                     Token::AttrIdentifier((17, 1, 18), "href".to_string()),
                     None,
-                    false,
+                    AttrCaseFlag::Unspecified,
                 )),
             ]))
         );
@@ -969,7 +1343,7 @@ mod tests {
                     (0, 1, 1),
                     Token::AttrIdentifier((1, 1, 2), "href".to_string()),
                     None,
-                    false,
+                    AttrCaseFlag::Unspecified,
                 )),
                 Selector::PseudoElement(PseudoElementSelector::After((6, 1, 7))),
             ]))
@@ -1005,7 +1379,7 @@ mod tests {
                         AttrSelectorOp::Exactly((35, 1, 36)),
                         Token::Str((36, 1, 37), "link".to_string()),
                     )),
-                    true,
+                    AttrCaseFlag::Insensitive,
                 )),
                 Selector::PseudoElement(PseudoElementSelector::After((44, 1, 45))),
             ]))
@@ -1041,7 +1415,7 @@ mod tests {
                         AttrSelectorOp::Exactly((35, 1, 36)),
                         Token::Str((36, 1, 37), "link".to_string()),
                     )),
-                    true,
+                    AttrCaseFlag::Insensitive,
                 )),
                 Selector::PseudoElement(PseudoElementSelector::After((44, 1, 45))),
             ]))
@@ -1059,7 +1433,7 @@ mod tests {
                 (0, 1, 1),
                 Token::AttrIdentifier((1, 1, 2), "id".to_string()),
                 None,
-                false
+                AttrCaseFlag::Unspecified
             )))
         );
         assert_eq!(parser.pos(), (4, 1, 5));
@@ -1075,7 +1449,7 @@ mod tests {
                 (0, 1, 1),
                 Token::AttrIdentifier((1, 1, 2), "href".to_string()),
                 None,
-                false
+                AttrCaseFlag::Unspecified
             )))
         );
         assert_eq!(parser.pos(), (6, 1, 7));
@@ -1134,6 +1508,58 @@ mod tests {
         assert_eq!(parser.pos(), (6, 1, 7));
     }
 
+    #[test]
+    fn test_parse_pcs_host() {
+        let mut parser = SelectorParser::new(":host");
+        let res = parser.parse_pseudo_class_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::PseudoClass(PseudoClassSelector::Host((0, 1, 1), None)))
+        );
+        assert_eq!(parser.pos(), (5, 1, 6));
+
+        let mut parser = SelectorParser::new(":host(.a)");
+        let res = parser.parse_pseudo_class_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::PseudoClass(PseudoClassSelector::Host(
+                (0, 1, 1),
+                Some(Box::new(Selector::Simple(SimpleSelector::new(
+                    (6, 1, 7),
+                    None,
+                    None,
+                    vec![Token::AttrIdentifier((7, 1, 8), "a".to_string())],
+                    false,
+                )))),
+            )))
+        );
+        assert_eq!(parser.pos(), (9, 1, 10));
+    }
+
+    #[test]
+    fn test_parse_pcs_custom() {
+        let mut parser = SelectorParser::new(":My-State");
+        let res = parser.parse_pseudo_class_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::PseudoClass(PseudoClassSelector::Custom(
+                (0, 1, 1),
+                "my-state".to_string()
+            )))
+        );
+        assert_eq!(parser.pos(), (9, 1, 10));
+
+        // Standard ones aren't custom
+        let mut parser = SelectorParser::new(":Only-Child");
+        assert_eq!(
+            parser.parse_pseudo_class_selector(),
+            Err(SelectorParserError::Unexpected(
+                (0, 1, 1),
+                "unsupported pseudo-class selector: :only-child".to_string()
+            ))
+        );
+    }
+
     #[test]
     fn test_parse_pcs_not1() {
         let mut parser = SelectorParser::new(":not( a )");
@@ -1174,7 +1600,7 @@ mod tests {
                         (10, 1, 11),
                         Token::AttrIdentifier((11, 1, 12), "href".to_string()),
                         None,
-                        false,
+                        AttrCaseFlag::Unspecified,
                     )),
                 ]))
             )))
@@ -1203,7 +1629,7 @@ mod tests {
                         (8, 1, 9),
                         Token::AttrIdentifier((9, 1, 10), "href".to_string()),
                         None,
-                        false,
+                        AttrCaseFlag::Unspecified,
                     )))
                 ))
             )))
@@ -1471,6 +1897,20 @@ mod tests {
         assert_eq!(parser.pos(), (4, 1, 5));
     }
 
+    #[test]
+    fn test_parse_pcs_of_type() {
+        let mut parser = SelectorParser::new(":first-of-type:Last-Of-Type");
+        assert_eq!(
+            parser.parse_pseudo_class_selector(),
+            Ok(Selector::PseudoClass(PseudoClassSelector::FirstOfType((0, 1, 1))))
+        );
+        assert_eq!(
+            parser.parse_pseudo_class_selector(),
+            Ok(Selector::PseudoClass(PseudoClassSelector::LastOfType((14, 1, 15))))
+        );
+        assert_eq!(parser.pos(), (27, 1, 28));
+    }
+
     #[test]
     fn test_parse_pcs_nth_child1() {
         let mut parser = SelectorParser::new(":nth-child(even)");
@@ -1584,6 +2024,56 @@ mod tests {
         assert_eq!(parser.pos(), (13, 1, 14))
     }
 
+    #[test]
+    fn test_parse_pes_placeholder() {
+        let mut parser = SelectorParser::new("::placeholder");
+        let res = parser.parse_pseudo_element_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::PseudoElement(PseudoElementSelector::Placeholder(
+                (0, 1, 1)
+            )))
+        );
+        assert_eq!(parser.pos(), (13, 1, 14))
+    }
+
+    #[test]
+    fn test_parse_pes_slotted() {
+        let mut parser = SelectorParser::new("::slotted(p)");
+        let res = parser.parse_pseudo_element_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::PseudoElement(PseudoElementSelector::Slotted(
+                (0, 1, 1),
+                Box::new(Selector::Simple(SimpleSelector::new(
+                    (10, 1, 11),
+                    Some(ElemType::P),
+                    None,
+                    vec![],
+                    false,
+                ))),
+            )))
+        );
+        assert_eq!(parser.pos(), (12, 1, 13))
+    }
+
+    #[test]
+    fn test_parse_pes_part() {
+        let mut parser = SelectorParser::new("::part(label icon)");
+        let res = parser.parse_pseudo_element_selector();
+        assert_eq!(
+            res,
+            Ok(Selector::PseudoElement(PseudoElementSelector::Part(
+                (0, 1, 1),
+                vec![
+                    Token::AttrIdentifier((7, 1, 8), "label".to_string()),
+                    Token::AttrIdentifier((13, 1, 14), "icon".to_string()),
+                ],
+            )))
+        );
+        assert_eq!(parser.pos(), (18, 1, 19))
+    }
+
     #[test]
     fn test_parse_pes_fail1() {
         let mut parser = SelectorParser::new("::first-lette");
@@ -1642,7 +2132,7 @@ mod tests {
                         AttrSelectorOp::Exactly((35, 1, 36)),
                         Token::Str((36, 1, 37), "link".to_string()),
                     )),
-                    true,
+                    AttrCaseFlag::Insensitive,
                 )),
                 Selector::PseudoElement(PseudoElementSelector::After((44, 1, 45))),
             ]))
@@ -1877,7 +2367,7 @@ mod tests {
 
     #[test]
     fn test_parse1() {
-        let res = SelectorParser::parse("div , div", (0, 1, 1));
+        let res = SelectorParser::parse("div , div", (0, 1, 1), &Namespaces::default());
         assert_eq!(
             res,
             Ok(Selector::Group(vec![
@@ -1901,7 +2391,7 @@ mod tests {
 
     #[test]
     fn test_parse2() {
-        let res = SelectorParser::parse(":hover", (0, 1, 1));
+        let res = SelectorParser::parse(":hover", (0, 1, 1), &Namespaces::default());
         assert_eq!(
             res,
             Ok(Selector::PseudoClass(PseudoClassSelector::Hover((0, 1, 1))))
@@ -1910,13 +2400,13 @@ mod tests {
 
     #[test]
     fn test_parse_fail1() {
-        let res = SelectorParser::parse("div , div,", (0, 1, 1));
+        let res = SelectorParser::parse("div , div,", (0, 1, 1), &Namespaces::default());
         assert_eq!(res, Err(Error::Eof((10, 1, 11))));
     }
 
     #[test]
     fn test_parse_fail2() {
-        let res = SelectorParser::parse("div , div ;", (0, 1, 1));
+        let res = SelectorParser::parse("div , div ;", (0, 1, 1), &Namespaces::default());
         assert_eq!(
             res,
             Err(Error::Unexpected(
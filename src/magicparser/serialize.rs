@@ -0,0 +1,266 @@
+use magicparser::{AttrCaseFlag, AttrSelector, AttrSelectorOp, Combinator, NamespaceConstraint,
+                  NthExpr, NthExprOp, PseudoClassSelector, PseudoElementSelector, Selector,
+                  SimpleSelector};
+use std::fmt::{self, Write};
+
+/// Serializes selectors back to CSS text, e.g. for reporting which rule matched.
+///
+/// The output parses back to an equal selector, with two exceptions: classes
+/// are written in sorted order, and namespace prefixes declared with
+/// @namespace are dropped, since selectors only keep the namespace URL.
+pub trait ToCss {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result;
+
+    fn to_css_string(&self) -> String {
+        let mut css = String::new();
+        self.to_css(&mut css).unwrap();
+        css
+    }
+}
+
+fn write_string<W: Write>(dest: &mut W, st: &str) -> fmt::Result {
+    dest.write_char('"')?;
+    for ch in st.chars() {
+        if ch == '"' || ch == '\\' {
+            dest.write_char('\\')?;
+        }
+        dest.write_char(ch)?;
+    }
+    dest.write_char('"')
+}
+
+impl ToCss for SimpleSelector {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        let has_prefix = self.namespace == NamespaceConstraint::NoNamespace;
+        if has_prefix {
+            dest.write_char('|')?;
+        }
+        match self.elem_type {
+            Some(ref elem_type) => dest.write_str(elem_type.tag_name())?,
+            None => {
+                let is_empty = self.id.is_none() && self.classes.is_empty();
+                if self.universal || has_prefix || is_empty {
+                    dest.write_char('*')?;
+                }
+            }
+        }
+        if let Some(ref id) = self.id {
+            write!(dest, "#{}", id)?;
+        }
+        let mut classes = self.classes.iter().collect::<Vec<_>>();
+        classes.sort();
+        for class in classes {
+            write!(dest, ".{}", class)?;
+        }
+        Ok(())
+    }
+}
+
+impl ToCss for AttrSelectorOp {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        dest.write_str(match self {
+            AttrSelectorOp::Exactly => "=",
+            AttrSelectorOp::ExactlyOne => "~=",
+            AttrSelectorOp::ExactlyOrHyphen => "|=",
+            AttrSelectorOp::Prefixed => "^=",
+            AttrSelectorOp::Suffixed => "$=",
+            AttrSelectorOp::ContainsAtLeastOne => "*=",
+        })
+    }
+}
+
+impl ToCss for AttrSelector {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        dest.write_char('[')?;
+        if self.namespace == NamespaceConstraint::Any {
+            dest.write_str("*|")?;
+        }
+        dest.write_str(&self.attr)?;
+        if let Some((ref op, ref val)) = self.op_val {
+            op.to_css(dest)?;
+            write_string(dest, val)?;
+            match self.case_flag {
+                AttrCaseFlag::Unspecified => (),
+                AttrCaseFlag::Insensitive => dest.write_str(" i")?,
+                AttrCaseFlag::Sensitive => dest.write_str(" s")?,
+            }
+        }
+        dest.write_char(']')
+    }
+}
+
+impl ToCss for NthExpr {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        match *self {
+            NthExpr::A(a) => write!(dest, "{}", a),
+            NthExpr::AnOpB(a, op, b) => {
+                match a {
+                    1 => dest.write_char('n')?,
+                    -1 => dest.write_str("-n")?,
+                    a => write!(dest, "{}n", a)?,
+                }
+                match op {
+                    Some(NthExprOp::Add) => write!(dest, "+{}", b),
+                    Some(NthExprOp::Sub) => write!(dest, "-{}", b),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl ToCss for PseudoClassSelector {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        use self::PseudoClassSelector::*;
+        let (name, nth_expr) = match self {
+            Active => (":active", None),
+            Checked => (":checked", None),
+            Default => (":default", None),
+            Defined => (":defined", None),
+            Disabled => (":disabled", None),
+            Empty => (":empty", None),
+            Enabled => (":enabled", None),
+            Focus => (":focus", None),
+            Hover => (":hover", None),
+            Indeterminate => (":indeterminate", None),
+            FirstChild => (":first-child", None),
+            FirstOfType => (":first-of-type", None),
+            LastChild => (":last-child", None),
+            LastOfType => (":last-of-type", None),
+            Link => (":link", None),
+            Target => (":target", None),
+            Visited => (":visited", None),
+            NthChild(ref expr) => (":nth-child", Some(expr)),
+            NthLastChild(ref expr) => (":nth-last-child", Some(expr)),
+            NthLastOfType(ref expr) => (":nth-last-of-type", Some(expr)),
+            NthOfType(ref expr) => (":nth-of-type", Some(expr)),
+            Host(None) => (":host", None),
+            Host(Some(ref sel)) => return write!(dest, ":host({})", sel.to_css_string()),
+            Lang(ref lang) => return write!(dest, ":lang({})", lang),
+            Matches(ref sel) => return write!(dest, ":matches({})", sel.to_css_string()),
+            Not(ref sel) => return write!(dest, ":not({})", sel.to_css_string()),
+            Custom(ref name) => return write!(dest, ":{}", name),
+        };
+        dest.write_str(name)?;
+        if let Some(expr) = nth_expr {
+            dest.write_char('(')?;
+            expr.to_css(dest)?;
+            dest.write_char(')')?;
+        }
+        Ok(())
+    }
+}
+
+impl ToCss for PseudoElementSelector {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        match self {
+            PseudoElementSelector::After => dest.write_str("::after"),
+            PseudoElementSelector::Before => dest.write_str("::before"),
+            PseudoElementSelector::Cue => dest.write_str("::cue"),
+            PseudoElementSelector::FirstLetter => dest.write_str("::first-letter"),
+            PseudoElementSelector::FirstLine => dest.write_str("::first-line"),
+            PseudoElementSelector::Placeholder => dest.write_str("::placeholder"),
+            PseudoElementSelector::Selection => dest.write_str("::selection"),
+            PseudoElementSelector::Slotted(ref sel) => {
+                write!(dest, "::slotted({})", sel.to_css_string())
+            }
+            PseudoElementSelector::Part(ref names) => write!(dest, "::part({})", names.join(" ")),
+        }
+    }
+}
+
+impl ToCss for Combinator {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        dest.write_str(match self {
+            Combinator::AdjacentSibling => " + ",
+            Combinator::GeneralSibling => " ~ ",
+            Combinator::Child => " > ",
+            Combinator::Descendant => " ",
+        })
+    }
+}
+
+impl ToCss for Selector {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        match self {
+            Selector::Simple(ref sel) => sel.to_css(dest),
+            Selector::Attr(ref sel) => sel.to_css(dest),
+            Selector::PseudoClass(ref sel) => sel.to_css(dest),
+            Selector::PseudoElement(ref sel) => sel.to_css(dest),
+            Selector::Seq(ref sels) => {
+                for sel in sels {
+                    sel.to_css(dest)?;
+                }
+                Ok(())
+            }
+            Selector::Combinator(ref first, ref combinator, ref second) => {
+                first.to_css(dest)?;
+                combinator.to_css(dest)?;
+                second.to_css(dest)
+            }
+            Selector::Group(ref sels) => {
+                for (i, sel) in sels.iter().enumerate() {
+                    if i > 0 {
+                        dest.write_str(", ")?;
+                    }
+                    sel.to_css(dest)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_css;
+
+    fn first_selector_css(css: &str) -> String {
+        let blocks = parse_css(css).unwrap();
+        blocks.0[0].0.to_css_string()
+    }
+
+    #[test]
+    fn test_simple_selector_to_css() {
+        assert_eq!(first_selector_css("div#main.b.a {}"), "div#main.a.b");
+        assert_eq!(first_selector_css("* {}"), "*");
+        assert_eq!(first_selector_css(".a {}"), ".a");
+        assert_eq!(first_selector_css("|p {}"), "|p");
+    }
+
+    #[test]
+    fn test_attr_selector_to_css() {
+        assert_eq!(first_selector_css("[href] {}"), "[href]");
+        assert_eq!(
+            first_selector_css("a[ title ~= 'say \"hi\"' i ] {}"),
+            "a[title~=\"say \\\"hi\\\"\" i]"
+        );
+        assert_eq!(first_selector_css("[*|lang|=en s] {}"), "[*|lang|=\"en\" s]");
+    }
+
+    #[test]
+    fn test_pseudo_class_selector_to_css() {
+        assert_eq!(
+            first_selector_css("li:nth-child(2n+1):not(.a):hover {}"),
+            "li:nth-child(2n+1):not(.a):hover"
+        );
+        assert_eq!(first_selector_css("p:nth-of-type(odd) {}"), "p:nth-of-type(2n+1)");
+        assert_eq!(first_selector_css("p:nth-last-child(n-2) {}"), "p:nth-last-child(n-2)");
+        assert_eq!(first_selector_css(":host(.a) {}"), ":host(.a)");
+        assert_eq!(first_selector_css("p:lang(en) {}"), "p:lang(en)");
+    }
+
+    #[test]
+    fn test_pseudo_element_selector_to_css() {
+        assert_eq!(first_selector_css("p:after {}"), "p::after");
+        assert_eq!(first_selector_css("::part(label icon) {}"), "::part(label icon)");
+        assert_eq!(first_selector_css("::slotted(p) {}"), "::slotted(p)");
+    }
+
+    #[test]
+    fn test_combinator_and_group_to_css() {
+        assert_eq!(first_selector_css("div>p+a~b i {}"), "div > p + a ~ b i");
+        assert_eq!(first_selector_css("a, b.c {}"), "a, b.c");
+    }
+}
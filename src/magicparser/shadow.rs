@@ -0,0 +1,124 @@
+use magicparser::postparse::bump_dom_revision;
+use magicparser::{DomNode, DomNodeRef, ElemType};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+
+thread_local! {
+    static SHADOW_ROOTS_ATTACHED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns true once a shadow root has been attached on the current thread,
+/// so that code that has to tell shadow trees apart, like the style engine,
+/// can skip looking for them until then.
+pub fn shadow_roots_attached() -> bool {
+    SHADOW_ROOTS_ATTACHED.with(|attached| attached.get())
+}
+
+impl DomNodeRef {
+    /// Attaches an empty shadow tree to this element, whose root is returned
+    /// for adding its contents to. Returns None, changing nothing, if this
+    /// isn't an element or already has a shadow root.
+    ///
+    /// The shadow tree is a separate tree: its root has no parent, so its
+    /// nodes aren't descendants of the host, and selectors from outside it
+    /// don't match them. Its nodes inherit styles from the host, though, and
+    /// are styled with the stylesheets scoped to the shadow root (see
+    /// StyleResolver::add_shadow_stylesheet()).
+    pub fn attach_shadow(&self) -> Option<DomNodeRef> {
+        {
+            let node = self.borrow();
+            if !node.elem_type.is_element() || node.shadow_root.is_some() {
+                return None;
+            }
+        }
+        let mut shadow_root = DomNode::new_with_namespace(
+            ElemType::ShadowRoot,
+            None,
+            HashSet::new(),
+            HashMap::new(),
+            None,
+            vec![],
+            None,
+        );
+        shadow_root.host = Some(self.node_id());
+        let shadow_root = shadow_root.to_dnref();
+        SHADOW_ROOTS_ATTACHED.with(|attached| attached.set(true));
+        bump_dom_revision(self);
+        self.borrow_mut().shadow_root = Some(shadow_root.clone());
+        // :host rules can now match it
+        self.mark_style_dirty();
+        Some(shadow_root)
+    }
+
+    /// Returns the root of the shadow tree attached to this element, if any.
+    pub fn shadow_root(&self) -> Option<DomNodeRef> {
+        self.borrow().shadow_root.clone()
+    }
+
+    /// Returns the element this shadow root is attached to, or None if this
+    /// isn't a shadow root.
+    pub fn host(&self) -> Option<DomNodeRef> {
+        let host = self.borrow().host;
+        host.and_then(DomNodeRef::from_node_id)
+    }
+
+    /// Returns the root of the shadow tree this node is in, which may be the
+    /// node itself, or None if it isn't in a shadow tree.
+    pub fn containing_shadow_root(&self) -> Option<DomNodeRef> {
+        let root = self.ancestors().last().unwrap_or_else(|| self.clone());
+        let is_shadow_root = root.borrow().elem_type == ElemType::ShadowRoot;
+        if is_shadow_root {
+            Some(root)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the node's parent or, for a shadow root, its host.
+    pub fn parent_or_host(&self) -> Option<DomNodeRef> {
+        self.parent().or_else(|| self.host())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use magicparser::{parse_html, DomNodeRef};
+
+    #[test]
+    fn test_attach_shadow() {
+        let root = parse_html("<div><p>Text</p></div>").unwrap();
+        let p = root.borrow().children[0].clone();
+        let text = p.borrow().children[0].clone();
+        let shadow_root = p.attach_shadow().unwrap();
+        assert!(p.attach_shadow().is_none());
+        assert!(text.attach_shadow().is_none());
+        assert!(p.shadow_root().unwrap().ptr_eq(&shadow_root));
+        assert!(shadow_root.host().unwrap().ptr_eq(&p));
+        assert_eq!(shadow_root.borrow().elem_type.tag_name(), "#shadow-root");
+
+        let span = parse_html("<span><a></a></span>").unwrap();
+        shadow_root.append_child(span.clone());
+        let a = span.borrow().children[0].clone();
+        // Separate from the host's tree
+        assert_eq!(a.ancestors().count(), 2);
+        assert!(!p.descendants().any(|node| node.ptr_eq(&a)));
+        assert!(a.containing_shadow_root().unwrap().ptr_eq(&shadow_root));
+        assert!(shadow_root.containing_shadow_root().is_some());
+        assert!(p.containing_shadow_root().is_none());
+        let ancestors = a.shadow_including_ancestors().collect::<Vec<_>>();
+        assert_eq!(ancestors.len(), 4);
+        assert!(ancestors[2].ptr_eq(&p));
+
+        // The host can't be moved into its own shadow tree
+        assert!(!span.append_child(root.clone()));
+        // Nor can the shadow root be moved anywhere
+        assert!(!root.append_child(shadow_root.clone()));
+
+        // The shadow tree lives as long as its host
+        let id = a.node_id();
+        drop((a, span, shadow_root, text, ancestors));
+        assert!(DomNodeRef::from_node_id(id).is_some());
+        drop((root, p));
+        assert!(DomNodeRef::from_node_id(id).is_none());
+    }
+}
@@ -0,0 +1,269 @@
+use smallvec::SmallVec;
+use std::fmt;
+use std::iter::FromIterator;
+use std::slice;
+
+// Most elements have no more than this many classes or attributes, which are
+// then stored inline in the node instead of in a separate allocation
+const INLINE_CLASSES: usize = 2;
+const INLINE_ATTRS: usize = 3;
+
+/// A set for the few items of a node, like its classes. Looks them up by
+/// comparing each in turn, which beats hashing them for a handful of items,
+/// and keeps them in the order they're inserted.
+#[derive(Clone)]
+pub struct SmallSet<T>(SmallVec<[T; INLINE_CLASSES]>);
+
+impl<T: PartialEq> SmallSet<T> {
+    pub fn new() -> SmallSet<T> {
+        SmallSet(SmallVec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.0.iter()
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.0.contains(item)
+    }
+
+    /// Returns false if the set already had item, leaving it as it was.
+    pub fn insert(&mut self, item: T) -> bool {
+        if self.contains(&item) {
+            return false;
+        }
+        self.0.push(item);
+        true
+    }
+
+    /// Returns false if the set didn't have item.
+    pub fn remove(&mut self, item: &T) -> bool {
+        match self.0.iter().position(|other| other == item) {
+            Some(i) => {
+                self.0.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl<T: PartialEq> Default for SmallSet<T> {
+    fn default() -> SmallSet<T> {
+        SmallSet::new()
+    }
+}
+
+impl<T: PartialEq> FromIterator<T> for SmallSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> SmallSet<T> {
+        let mut set = SmallSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T: PartialEq> Extend<T> for SmallSet<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.insert(item);
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a SmallSet<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> slice::Iter<'a, T> {
+        self.0.iter()
+    }
+}
+
+impl<T> IntoIterator for SmallSet<T> {
+    type Item = T;
+    type IntoIter = smallvec::IntoIter<[T; INLINE_CLASSES]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+// Sets are equal if they have the same items, in any order
+impl<T: PartialEq> PartialEq for SmallSet<T> {
+    fn eq(&self, other: &SmallSet<T>) -> bool {
+        self.len() == other.len() && self.iter().all(|item| other.contains(item))
+    }
+}
+
+impl<T: Eq> Eq for SmallSet<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for SmallSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.iter()).finish()
+    }
+}
+
+/// A map for the few entries of a node, like its attributes. Like SmallSet,
+/// looks keys up by comparing each in turn and keeps entries in the order
+/// they're inserted.
+#[derive(Clone)]
+pub struct SmallMap<K, V>(SmallVec<[(K, V); INLINE_ATTRS]>);
+
+impl<K: PartialEq, V> SmallMap<K, V> {
+    pub fn new() -> SmallMap<K, V> {
+        SmallMap(SmallVec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.0.iter_mut().find(|(k, _)| k == key).map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns the value key had, if any. Replacing a value keeps the key's
+    /// position.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(std::mem::replace(old, value));
+        }
+        self.0.push((key, value));
+        None
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(i).1)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter(self.0.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.0.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.0.iter().map(|(_, value)| value)
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl<K: PartialEq, V> Default for SmallMap<K, V> {
+    fn default() -> SmallMap<K, V> {
+        SmallMap::new()
+    }
+}
+
+impl<K: PartialEq, V> FromIterator<(K, V)> for SmallMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> SmallMap<K, V> {
+        let mut map = SmallMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: PartialEq, V> Extend<(K, V)> for SmallMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// Iterates over a SmallMap's entries, like a HashMap's.
+pub struct Iter<'a, K, V>(slice::Iter<'a, (K, V)>);
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.0.next().map(|(key, value)| (key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: PartialEq, V> IntoIterator for &'a SmallMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// Maps are equal if they have the same entries, in any order
+impl<K: PartialEq, V: PartialEq> PartialEq for SmallMap<K, V> {
+    fn eq(&self, other: &SmallMap<K, V>) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Eq, V: Eq> Eq for SmallMap<K, V> {}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for SmallMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.0.iter().map(|(key, value)| (key, value))).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_set_and_map() {
+        let mut set = vec!["a", "b", "a", "c"].into_iter().collect::<SmallSet<_>>();
+        assert_eq!(set.iter().cloned().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert!(!set.insert("b"));
+        assert!(set.remove(&"a"));
+        assert!(!set.remove(&"a"));
+        assert_eq!(set, vec!["c", "b"].into_iter().collect());
+        assert_eq!(format!("{:?}", set), "{\"b\", \"c\"}");
+
+        let mut map = SmallMap::new();
+        for (i, key) in ["w", "x", "y", "z"].iter().enumerate() {
+            assert_eq!(map.insert(*key, i), None);
+        }
+        assert_eq!(map.insert("x", 5), Some(1));
+        assert_eq!(map.get(&"x"), Some(&5));
+        assert_eq!(map.remove(&"w"), Some(0));
+        assert_eq!(map.remove(&"w"), None);
+        assert_eq!(map.keys().cloned().collect::<Vec<_>>(), vec!["x", "y", "z"]);
+        assert_eq!(map, vec![("z", 3), ("y", 2), ("x", 5)].into_iter().collect());
+        assert_eq!(format!("{:?}", map), "{\"x\": 5, \"y\": 2, \"z\": 3}");
+    }
+}
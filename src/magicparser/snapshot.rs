@@ -0,0 +1,264 @@
+use magicparser::error::{Error, Result};
+use magicparser::{DomNode, DomNodeRef, ElemType, HTML_NAMESPACE};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A DOM subtree as plain data, without the parent links and shared nodes of
+/// DomNodeRef, for serializing it, e.g. to JSON for snapshot tests and
+/// external tools with the `serde` feature. Maps are sorted by key, so the
+/// same tree always serializes the same way.
+///
+/// Going the other way, to_dom() builds a tree from one, e.g. one
+/// deserialized from a JSON fixture (see parse_json()), in which only the
+/// name is required.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct NodeSnapshot {
+    // Tag name of an element, or #text, #comment and the like for other nodes
+    pub name: String,
+    // Of elements. When building a tree, elements without one are in their
+    // parent's namespace, or the HTML namespace at the root.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub namespace: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeMap::is_empty"))]
+    pub attrs: BTreeMap<String, Option<String>>,
+    // Content of text and comment nodes
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub text: Option<String>,
+    // Computed values, for snapshots of styled trees. Ignored when building a
+    // tree.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub style: Option<BTreeMap<String, String>>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub shadow_root: Option<Box<NodeSnapshot>>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub children: Vec<NodeSnapshot>,
+}
+
+impl NodeSnapshot {
+    /// Like NodeSnapshot::from(), but with the style style returns for each
+    /// node, e.g. its computed values (see StyleMap::snapshot()).
+    pub fn new_with<F>(dom_node: &DomNodeRef, style: F) -> NodeSnapshot
+    where
+        F: Fn(&DomNodeRef) -> Option<BTreeMap<String, String>>,
+    {
+        NodeSnapshot::from_node(dom_node, &style)
+    }
+
+    /// Builds the tree this is a snapshot of. Returns an error for names
+    /// like `#foo` that no node has, and for attributes, children or shadow
+    /// roots on nodes that can't have them, like text.
+    pub fn to_dom(&self) -> Result<DomNodeRef> {
+        self.to_dom_in(HTML_NAMESPACE)
+    }
+
+    fn to_dom_in(&self, parent_namespace: &str) -> Result<DomNodeRef> {
+        let elem_type = match self.name.as_ref() {
+            "#text" => ElemType::Text(self.text.clone().unwrap_or_default()),
+            "#comment" => ElemType::Comment(self.text.clone().unwrap_or_default()),
+            "#document-fragment" => ElemType::Fragment,
+            name if name.is_empty() || name.starts_with('#') => {
+                return Err(snapshot_error(format!("unexpected node name {:?}", name)));
+            }
+            name => ElemType::from(name),
+        };
+        let is_element = elem_type.is_element();
+        let invalid = !is_element
+            && (!self.attrs.is_empty() || self.shadow_root.is_some()
+                || (elem_type != ElemType::Fragment && !self.children.is_empty()));
+        if invalid {
+            let message = format!("{} can't have attributes, children or a shadow root", self.name);
+            return Err(snapshot_error(message));
+        }
+        let namespace = self.namespace.as_deref().unwrap_or(parent_namespace).to_string();
+        let attrs = self.attrs.clone().into_iter().collect::<HashMap<_, _>>();
+        let id = attrs.get("id").cloned().unwrap_or_default();
+        let classes = attrs
+            .get("class")
+            .and_then(Option::as_ref)
+            .map_or(HashSet::new(), |classes| {
+                classes.split_whitespace().map(str::to_string).collect()
+            });
+        let dom_node = DomNode::new_with_namespace(
+            elem_type,
+            id,
+            classes,
+            attrs,
+            None,
+            vec![],
+            Some(namespace.clone()).filter(|_| is_element),
+        ).to_dnref();
+        if let Some(ref shadow_root) = self.shadow_root {
+            if shadow_root.name != "#shadow-root" {
+                let message = format!("unexpected shadow root {:?}", shadow_root.name);
+                return Err(snapshot_error(message));
+            }
+            let root = dom_node.attach_shadow().unwrap();
+            for child in &shadow_root.children {
+                root.add_child(child.to_dom_in(&namespace)?);
+            }
+        }
+        for child in &self.children {
+            dom_node.add_child(child.to_dom_in(&namespace)?);
+        }
+        Ok(dom_node)
+    }
+
+    fn from_node<F>(dom_node: &DomNodeRef, style: &F) -> NodeSnapshot
+    where
+        F: Fn(&DomNodeRef) -> Option<BTreeMap<String, String>>,
+    {
+        let node = dom_node.borrow();
+        let (namespace, text) = match node.elem_type {
+            ElemType::Text(ref text) | ElemType::Comment(ref text) => (None, Some(text.clone())),
+            ref elem_type if elem_type.is_element() => (node.namespace.clone(), None),
+            _ => (None, None),
+        };
+        let attrs = node
+            .attrs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        let shadow_root = node.shadow_root.as_ref();
+        NodeSnapshot {
+            name: node.elem_type.tag_name().to_string(),
+            namespace,
+            attrs,
+            text,
+            style: style(dom_node),
+            shadow_root: shadow_root.map(|root| Box::new(NodeSnapshot::from_node(root, style))),
+            children: node
+                .children
+                .iter()
+                .map(|child| NodeSnapshot::from_node(child, style))
+                .collect(),
+        }
+    }
+}
+
+impl<'a> From<&'a DomNodeRef> for NodeSnapshot {
+    fn from(dom_node: &'a DomNodeRef) -> Self {
+        NodeSnapshot::new_with(dom_node, |_| None)
+    }
+}
+
+/// Builds a DOM tree from its description in JSON, a NodeSnapshot like
+/// `{"name": "p", "attrs": {"class": "a"}, "children": [{"name": "#text",
+/// "text": "Hi"}]}`. Fields other than the name can be left out.
+#[cfg(feature = "serde")]
+pub fn parse_json(input: &str) -> Result<DomNodeRef> {
+    let snapshot: NodeSnapshot = serde_json::from_str(input).map_err(|err| {
+        Error::Unexpected((0, err.line(), err.column()), err.to_string())
+    })?;
+    snapshot.to_dom()
+}
+
+fn snapshot_error(message: String) -> Error {
+    Error::Unexpected((0, 0, 0), message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, SVG_NAMESPACE};
+
+    #[test]
+    fn test_snapshot() {
+        let root = parse_html("<div class=\"a\" id=\"b\">Text<!-- c --><img src=\"x\"></div>")
+            .unwrap();
+        let snapshot = NodeSnapshot::from(&root);
+        assert_eq!(snapshot.name, "div");
+        assert_eq!(snapshot.namespace.as_deref(), Some(HTML_NAMESPACE));
+        let attrs = snapshot.attrs.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(attrs, vec!["class", "id"]);
+        assert_eq!(snapshot.children.len(), 3);
+        assert_eq!(snapshot.children[0].text.as_deref(), Some("Text"));
+        assert_eq!(snapshot.children[1].name, "#comment");
+        assert_eq!(snapshot.children[1].namespace, None);
+        assert_eq!(snapshot.children[2].attrs.get("src"), Some(&Some("x".to_string())));
+        assert!(snapshot.shadow_root.is_none() && snapshot.style.is_none());
+    }
+
+    #[test]
+    fn test_snapshot_to_dom() {
+        let html = "<div class=\"a b\" id=\"c\">Text<!-- d --><svg><g></g></svg><img></div>";
+        let root = parse_html(html).unwrap();
+        let built = NodeSnapshot::from(&root).to_dom().unwrap();
+        assert!(built.is_equal_node(&root));
+        assert!(built.borrow().has_class("b"));
+        assert_eq!(built.borrow().id.as_deref(), Some("c"));
+        let g = built.borrow().children[2].borrow().children[0].clone();
+        assert_eq!(g.borrow().namespace.as_deref(), Some(SVG_NAMESPACE));
+        assert_eq!(g.parent().unwrap().child_index(), Some(3));
+
+        let text = |children| NodeSnapshot {
+            name: "#text".to_string(),
+            children,
+            ..Default::default()
+        };
+        assert!(text(vec![]).to_dom().is_ok());
+        assert!(text(vec![text(vec![])]).to_dom().is_err());
+        let unknown = NodeSnapshot {
+            name: "#foo".to_string(),
+            ..Default::default()
+        };
+        assert!(unknown.to_dom().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_parse_json() {
+        let root = parse_json(
+            r##"{"name": "div", "attrs": {"class": "a", "hidden": null}, "children": [
+                {"name": "#text", "text": "Hi"},
+                {"name": "p", "shadow_root": {"name": "#shadow-root", "children": [
+                    {"name": "span"}
+                ]}}
+            ]}"##,
+        ).unwrap();
+        let html = parse_html("<div class=\"a\" hidden>Hi<p></p></div>").unwrap();
+        assert!(root.is_equal_node(&html));
+        let p = root.borrow().children[1].clone();
+        assert_eq!(p.shadow_root().unwrap().borrow().children.len(), 1);
+        // Round trips
+        let json = serde_json::to_string(&NodeSnapshot::from(&root)).unwrap();
+        assert!(parse_json(&json).unwrap().is_equal_node(&root));
+
+        assert_eq!(
+            parse_json("{\"name\": \"p\",\n\"tag\": 1}").unwrap_err(),
+            Error::Unexpected(
+                (0, 2, 5),
+                "unknown field `tag`, expected one of `name`, `namespace`, `attrs`, `text`, \
+                 `style`, `shadow_root`, `children` at line 2 column 5"
+                    .to_string(),
+            )
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_to_json() {
+        let root = parse_html("<p title=\"t\">Text<img></p>").unwrap();
+        root.attach_shadow().unwrap().append_child(parse_html("<a></a>").unwrap());
+        let snapshot = NodeSnapshot::new_with(&root, |node| {
+            let name = node.borrow().elem_type.tag_name().to_string();
+            Some(vec![("name".to_string(), name)].into_iter().collect())
+        });
+        assert_eq!(
+            serde_json::to_string(&snapshot).unwrap(),
+            format!(
+                "{{\"name\":\"p\",\"namespace\":\"{0}\",\"attrs\":{{\"title\":\"t\"}},\
+                 \"style\":{{\"name\":\"p\"}},\"shadow_root\":{{\"name\":\"#shadow-root\",\
+                 \"style\":{{\"name\":\"#shadow-root\"}},\"children\":[{{\"name\":\"a\",\
+                 \"namespace\":\"{0}\",\"style\":{{\"name\":\"a\"}}}}]}},\
+                 \"children\":[{{\"name\":\"#text\",\"text\":\"Text\",\
+                 \"style\":{{\"name\":\"#text\"}}}},{{\"name\":\"img\",\"namespace\":\"{0}\",\
+                 \"style\":{{\"name\":\"img\"}}}}]}}",
+                HTML_NAMESPACE
+            )
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign, BitXor, Not};
+
+/// A set of the dynamic states of an element, like being hovered or focused,
+/// which state pseudo-classes like `:hover` match. Set by the embedder, e.g.
+/// with DomNodeRef::set_state().
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ElementState(u16);
+
+impl ElementState {
+    pub const HOVER: ElementState = ElementState(1);
+    pub const FOCUS: ElementState = ElementState(1 << 1);
+    pub const ACTIVE: ElementState = ElementState(1 << 2);
+    pub const CHECKED: ElementState = ElementState(1 << 3);
+    pub const DISABLED: ElementState = ElementState(1 << 4);
+    pub const VISITED: ElementState = ElementState(1 << 5);
+    pub const TARGET: ElementState = ElementState(1 << 6);
+
+    // Each state, with the name of the pseudo-class that matches it
+    const NAMES: &'static [(ElementState, &'static str)] = &[
+        (ElementState::HOVER, "hover"),
+        (ElementState::FOCUS, "focus"),
+        (ElementState::ACTIVE, "active"),
+        (ElementState::CHECKED, "checked"),
+        (ElementState::DISABLED, "disabled"),
+        (ElementState::VISITED, "visited"),
+        (ElementState::TARGET, "target"),
+    ];
+
+    pub fn empty() -> ElementState {
+        ElementState(0)
+    }
+
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if every state in other is set.
+    pub fn contains(self, other: ElementState) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if any state in other is set.
+    pub fn intersects(self, other: ElementState) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn insert(&mut self, other: ElementState) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: ElementState) {
+        self.0 &= !other.0;
+    }
+
+    /// Inserts other if on is true, and removes it otherwise.
+    pub fn set(&mut self, other: ElementState, on: bool) {
+        if on {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+
+    /// Returns the state matched by the pseudo-class called name, e.g.
+    /// HOVER for `hover`.
+    pub fn from_pseudo_class(name: &str) -> Option<ElementState> {
+        ElementState::NAMES
+            .iter()
+            .find(|(_, state_name)| name.eq_ignore_ascii_case(state_name))
+            .map(|&(state, _)| state)
+    }
+
+    /// Returns the names of the pseudo-classes matching the states that are
+    /// set, e.g. `hover`.
+    pub fn pseudo_classes(self) -> impl Iterator<Item = &'static str> {
+        ElementState::NAMES
+            .iter()
+            .filter(move |&&(state, _)| self.contains(state))
+            .map(|&(_, name)| name)
+    }
+}
+
+impl BitOr for ElementState {
+    type Output = ElementState;
+
+    fn bitor(self, other: ElementState) -> ElementState {
+        ElementState(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for ElementState {
+    fn bitor_assign(&mut self, other: ElementState) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for ElementState {
+    type Output = ElementState;
+
+    fn bitand(self, other: ElementState) -> ElementState {
+        ElementState(self.0 & other.0)
+    }
+}
+
+impl BitXor for ElementState {
+    type Output = ElementState;
+
+    fn bitxor(self, other: ElementState) -> ElementState {
+        ElementState(self.0 ^ other.0)
+    }
+}
+
+impl Not for ElementState {
+    type Output = ElementState;
+
+    fn not(self) -> ElementState {
+        ElementState(!self.0)
+    }
+}
+
+impl fmt::Debug for ElementState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.pseudo_classes()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_element_state() {
+        let mut state = ElementState::HOVER | ElementState::FOCUS;
+        assert!(state.contains(ElementState::HOVER));
+        assert!(!state.contains(ElementState::HOVER | ElementState::ACTIVE));
+        assert!(state.intersects(ElementState::HOVER | ElementState::ACTIVE));
+        state.set(ElementState::FOCUS, false);
+        state.set(ElementState::CHECKED, true);
+        assert_eq!(state.pseudo_classes().collect::<Vec<_>>(), vec!["hover", "checked"]);
+        assert_eq!(format!("{:?}", state), "{\"hover\", \"checked\"}");
+        assert_eq!(ElementState::from_pseudo_class("Target"), Some(ElementState::TARGET));
+        assert_eq!(ElementState::from_pseudo_class("link"), None);
+        state.remove(ElementState::HOVER | ElementState::CHECKED);
+        assert!(state.is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use magicparser::{Atom, DomNode, DomNodeRef, ElemType, ElementState, FormState, SmallMap,
+                  SmallSet};
+
+/// A read-only copy of a DOM tree that's `Send` and `Sync`, unlike
+/// DomNodeRef, whose nodes live in their thread's arena. It can be moved to
+/// another thread and rebuilt there with to_dom(), or shared between threads,
+/// e.g. behind an Arc, for styling or laying it out in parallel.
+///
+/// Nodes are stored in one Vec, in tree order, and refer to each other by
+/// their indices in it, with the root at 0. A shadow root comes right after
+/// its host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncTree {
+    nodes: Vec<SyncNode>,
+}
+
+/// A node of a SyncTree. Nodes, parents, children, shadow roots and hosts
+/// are given by their indices in the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncNode {
+    // Of the node the copy was made from, for mapping results like styles
+    // computed from the copy back to it
+    pub id_num: usize,
+    pub elem_type: ElemType,
+    pub id: Option<Atom>,
+    pub classes: SmallSet<Atom>,
+    pub attrs: SmallMap<Atom, Option<String>>,
+    pub namespace: Option<String>,
+    pub state: ElementState,
+    pub form_state: FormState,
+    // Of a custom element, whether it was upgraded on the thread the copy
+    // was made on
+    pub upgraded: bool,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub shadow_root: Option<usize>,
+    pub host: Option<usize>,
+}
+
+impl SyncTree {
+    pub fn root(&self) -> &SyncNode {
+        &self.nodes[0]
+    }
+
+    pub fn get(&self, index: usize) -> Option<&SyncNode> {
+        self.nodes.get(index)
+    }
+
+    /// Returns the nodes in tree order, e.g. for iterating over them in
+    /// parallel.
+    pub fn nodes(&self) -> &[SyncNode] {
+        &self.nodes
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Always false, since a tree has at least its root.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Builds the tree in the current thread's arena, returning its root.
+    ///
+    /// Only the tree is copied, not what's kept for it on the thread it came
+    /// from, like its event listeners. Custom elements aren't upgraded until
+    /// they're inserted into a Document on this thread, with its definitions.
+    pub fn to_dom(&self) -> DomNodeRef {
+        self.to_dom_nodes().swap_remove(0)
+    }
+
+    /// Like to_dom(), but returns every node built, by its index in the tree,
+    /// e.g. for finding the copies of some of the tree's nodes.
+    pub fn to_dom_nodes(&self) -> Vec<DomNodeRef> {
+        let mut dom_nodes: Vec<DomNodeRef> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            // A host comes before its shadow root
+            let dom_node = match node.host {
+                Some(host) => dom_nodes[host].attach_shadow().unwrap(),
+                None => DomNode::new_with_namespace(
+                    node.elem_type.clone(),
+                    None,
+                    Default::default(),
+                    Default::default(),
+                    None,
+                    vec![],
+                    node.namespace.clone(),
+                ).to_dnref(),
+            };
+            {
+                let mut dom_node = dom_node.borrow_mut();
+                dom_node.id = node.id.clone();
+                dom_node.classes = node.classes.clone();
+                dom_node.attrs = node.attrs.clone();
+                dom_node.state = node.state;
+                dom_node.form_state = node.form_state.clone();
+            }
+            dom_nodes.push(dom_node);
+        }
+        for (node, dom_node) in self.nodes.iter().zip(&dom_nodes) {
+            for &child in &node.children {
+                dom_node.add_child(dom_nodes[child].clone());
+            }
+        }
+        dom_nodes
+    }
+}
+
+impl<'a> From<&'a DomNodeRef> for SyncTree {
+    /// Copies the subtree rooted at dom_node, along with the shadow trees in
+    /// it.
+    fn from(dom_node: &'a DomNodeRef) -> Self {
+        let mut nodes: Vec<SyncNode> = vec![];
+        // Nodes to copy, with their parent's or host's index and whether
+        // they're a shadow root. Walked without recursion, since trees can be
+        // deep.
+        let mut stack: Vec<(DomNodeRef, Option<usize>, bool)> =
+            vec![(dom_node.clone(), None, false)];
+        while let Some((dom_node, parent, is_shadow_root)) = stack.pop() {
+            let index = nodes.len();
+            let node = dom_node.borrow();
+            match parent {
+                Some(parent) if is_shadow_root => nodes[parent].shadow_root = Some(index),
+                Some(parent) => nodes[parent].children.push(index),
+                None => {}
+            }
+            nodes.push(SyncNode {
+                id_num: node.id_num,
+                elem_type: node.elem_type.clone(),
+                id: node.id.clone(),
+                classes: node.classes.clone(),
+                attrs: node.attrs.clone(),
+                namespace: node.namespace.clone(),
+                state: node.state,
+                form_state: node.form_state.clone(),
+                upgraded: node.upgraded,
+                parent: parent.filter(|_| !is_shadow_root),
+                children: vec![],
+                shadow_root: None,
+                host: parent.filter(|_| is_shadow_root),
+            });
+            // Pushed in reverse, so that they're popped in tree order, right
+            // after the shadow root
+            for child in node.children.iter().rev() {
+                stack.push((child.clone(), Some(index), false));
+            }
+            if let Some(ref shadow_root) = node.shadow_root {
+                stack.push((shadow_root.clone(), Some(index), true));
+            }
+        }
+        SyncTree { nodes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_html;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_sync_tree() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SyncTree>();
+
+        let root = parse_html("<div class=\"a\"><p id=\"b\">Text</p><input></input></div>")
+            .unwrap();
+        let p = root.borrow().children[0].clone();
+        let input = root.borrow().children[1].clone();
+        p.attach_shadow().unwrap().append_child(parse_html("<span></span>").unwrap());
+        input.set_value("typed");
+        input.set_state(ElementState::FOCUS, true);
+        let tree = SyncTree::from(&root);
+        let tags = tree
+            .nodes()
+            .iter()
+            .map(|node| node.elem_type.tag_name())
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec!["div", "p", "#shadow-root", "span", "#text", "input"]);
+        assert_eq!(tree.get(1).unwrap().shadow_root, Some(2));
+        assert_eq!(tree.get(2).unwrap().host, Some(1));
+        assert_eq!(tree.get(2).unwrap().parent, None);
+        assert_eq!(tree.get(4).unwrap().parent, Some(1));
+        assert_eq!(tree.root().children, vec![1, 5]);
+        assert_eq!(tree.root().id_num, root.borrow().id_num);
+
+        // Rebuilt on another thread and sent back
+        let shared = Arc::new(tree);
+        let shared2 = shared.clone();
+        let rebuilt = thread::spawn(move || {
+            let root = shared2.to_dom();
+            assert_eq!(root.borrow().children.len(), 2);
+            SyncTree::from(&root)
+        }).join()
+            .unwrap();
+        let rebuilt_root = rebuilt.to_dom();
+        assert!(rebuilt_root.is_equal_node(&root));
+        let p = rebuilt_root.borrow().children[0].clone();
+        let input = rebuilt_root.borrow().children[1].clone();
+        assert_eq!(p.shadow_root().unwrap().borrow().children.len(), 1);
+        assert_eq!(input.value().as_deref(), Some("typed"));
+        assert!(input.borrow().state.contains(ElementState::FOCUS));
+        assert_eq!(p.child_index(), Some(1));
+    }
+}
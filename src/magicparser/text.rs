@@ -0,0 +1,237 @@
+use magicparser::postparse::bump_dom_revision;
+use magicparser::{DomNodeRef, ElemType};
+
+/// How DomNodeRef::normalize_whitespace() treats the whitespace of the text
+/// in an element, after the element's `white-space` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhiteSpace {
+    // normal and nowrap: each run of whitespace becomes a space
+    Collapse,
+    // pre-line: like Collapse, but newlines are kept, without the spaces
+    // around them
+    PreserveBreaks,
+    // pre, pre-wrap and break-spaces
+    Preserve,
+}
+
+impl<'a> From<&'a str> for WhiteSpace {
+    fn from(value: &'a str) -> Self {
+        match value.to_ascii_lowercase().as_ref() {
+            "pre" | "pre-wrap" | "break-spaces" => WhiteSpace::Preserve,
+            "pre-line" => WhiteSpace::PreserveBreaks,
+            _ => WhiteSpace::Collapse,
+        }
+    }
+}
+
+impl DomNodeRef {
+    /// Returns the node's text, like the DOM's `textContent`: the content of
+    /// a text or comment node, or else that of the text nodes among its
+    /// descendants, in tree order. Shadow trees aren't included.
+    pub fn text_content(&self) -> String {
+        match self.borrow().elem_type {
+            ElemType::Text(ref text) | ElemType::Comment(ref text) => return text.clone(),
+            _ => {}
+        }
+        let mut text = String::new();
+        for node in self.descendants() {
+            if let ElemType::Text(ref content) = node.borrow().elem_type {
+                text.push_str(content);
+            }
+        }
+        text
+    }
+
+    /// Merges the adjacent text nodes in this subtree and removes the empty
+    /// ones, like the tree the parser builds, in which text is never split.
+    /// An element whose only text was empty is then matched by `:empty`.
+    pub fn normalize(&self) {
+        for parent in self.parents_in_subtree() {
+            parent.merge_text_children();
+        }
+    }
+
+    /// Like normalize(), but also collapses the whitespace in the text of
+    /// each element as white_space says the element does, e.g. from the
+    /// element's computed `white-space` with WhiteSpace::from().
+    ///
+    /// Text isn't trimmed where lines would start and end, since that's up to
+    /// layout, but with the parser trimming text, only text added since has
+    /// whitespace there.
+    pub fn normalize_whitespace<F>(&self, white_space: F)
+    where
+        F: Fn(&DomNodeRef) -> WhiteSpace,
+    {
+        self.normalize();
+        for parent in self.parents_in_subtree() {
+            let mode = white_space(&parent);
+            if mode == WhiteSpace::Preserve {
+                continue;
+            }
+            let children = parent.borrow().children.to_vec();
+            for child in &children {
+                let collapsed = match child.borrow().elem_type {
+                    ElemType::Text(ref text) => collapse_whitespace(text, mode),
+                    _ => continue,
+                };
+                child.replace_text(collapsed);
+            }
+        }
+    }
+
+    // Nodes that can have children in the subtree, including this node
+    fn parents_in_subtree(&self) -> Vec<DomNodeRef> {
+        Some(self.clone())
+            .into_iter()
+            .chain(self.descendants())
+            .filter(|node| !node.borrow().children.is_empty())
+            .collect()
+    }
+
+    fn merge_text_children(&self) {
+        let children = self.borrow().children.to_vec();
+        // The text node the following ones are merged into
+        let mut merged: Option<(DomNodeRef, String)> = None;
+        for child in children {
+            let text = match child.borrow().elem_type {
+                ElemType::Text(ref text) => Some(text.clone()),
+                _ => None,
+            };
+            match (text, merged.as_mut()) {
+                (Some(text), Some((_, merged_text))) => {
+                    merged_text.push_str(&text);
+                    self.remove_child(&child);
+                }
+                (Some(text), None) => merged = Some((child, text)),
+                (None, _) => {
+                    if let Some((node, text)) = merged.take() {
+                        self.finish_merge(node, text);
+                    }
+                }
+            }
+        }
+        if let Some((node, text)) = merged {
+            self.finish_merge(node, text);
+        }
+    }
+
+    // Gives node, a child text node, the text merged into it, removing it if
+    // it's empty
+    fn finish_merge(&self, node: DomNodeRef, text: String) {
+        if text.is_empty() {
+            self.remove_child(&node);
+        } else {
+            node.replace_text(text);
+        }
+    }
+
+    fn replace_text(&self, text: String) {
+        if self.borrow().elem_type == ElemType::Text(text.clone()) {
+            return;
+        }
+        bump_dom_revision(self);
+        self.borrow_mut().elem_type = ElemType::Text(text);
+        self.mark_style_dirty();
+    }
+}
+
+fn collapse_whitespace(text: &str, mode: WhiteSpace) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut pending_space = false;
+    for ch in text.chars() {
+        if ch == '\n' && mode == WhiteSpace::PreserveBreaks {
+            // Without the spaces around it
+            while collapsed.ends_with(' ') {
+                collapsed.pop();
+            }
+            collapsed.push('\n');
+            pending_space = false;
+        } else if ch.is_ascii_whitespace() {
+            pending_space = !collapsed.ends_with('\n');
+        } else {
+            if pending_space {
+                collapsed.push(' ');
+                pending_space = false;
+            }
+            collapsed.push(ch);
+        }
+    }
+    if pending_space {
+        collapsed.push(' ');
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, DomNode};
+    use std::collections::{HashMap, HashSet};
+
+    fn text_children(node: &DomNodeRef) -> Vec<String> {
+        node.borrow()
+            .children
+            .iter()
+            .map(|child| match child.borrow().elem_type {
+                ElemType::Text(ref text) => text.clone(),
+                ref elem_type => elem_type.tag_name().to_string(),
+            })
+            .collect()
+    }
+
+    fn text(content: &str) -> DomNodeRef {
+        let elem_type = ElemType::Text(content.to_string());
+        DomNode::new(elem_type, None, HashSet::new(), HashMap::new(), None, vec![]).to_dnref()
+    }
+
+    #[test]
+    fn test_text_content() {
+        let root = parse_html("<div>One<!-- c --><p>Two<a>Three</a></p><img></div>").unwrap();
+        assert_eq!(root.text_content(), "OneTwoThree");
+        let children = root.borrow().children.to_vec();
+        assert_eq!(children[1].text_content(), " c ");
+        assert_eq!(children[2].text_content(), "TwoThree");
+        assert_eq!(children[3].text_content(), "");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let root = parse_html("<div><p>One</p><a></a></div>").unwrap();
+        let p = root.borrow().children[0].clone();
+        let a = root.borrow().children[1].clone();
+        p.append_child(text(" two"));
+        p.append_child(text(""));
+        p.append_child(text(" three"));
+        a.append_child(text(""));
+        root.append_child(text("Four"));
+        root.borrow_mut().style_dirty = false;
+
+        root.normalize();
+        assert_eq!(text_children(&p), vec!["One two three"]);
+        assert_eq!(text_children(&root), vec!["p", "a", "Four"]);
+        assert!(a.borrow().is_empty() && a.borrow().children.is_empty());
+        assert!(p.borrow().children[0].borrow().style_dirty);
+        assert_eq!(root.borrow().children[2].child_index(), Some(3));
+    }
+
+    #[test]
+    fn test_normalize_whitespace() {
+        let root = parse_html("<div><p></p><pre></pre><h1></h1><a></a></div>").unwrap();
+        let children = root.borrow().children.to_vec();
+        children[0].append_child(text("  One \n\t two  "));
+        children[1].append_child(text("  One \n two  "));
+        children[2].append_child(text("One  \n  two \n"));
+        children[3].append_child(text(" \n "));
+        children[3].append_child(text(" "));
+
+        root.normalize_whitespace(|node| match node.borrow().elem_type {
+            ElemType::Custom(ref name) if name == "pre" => WhiteSpace::Preserve,
+            ElemType::H1 => WhiteSpace::from("pre-line"),
+            _ => WhiteSpace::from("normal"),
+        });
+        assert_eq!(text_children(&children[0]), vec![" One two "]);
+        assert_eq!(text_children(&children[1]), vec!["  One \n two  "]);
+        assert_eq!(text_children(&children[2]), vec!["One\ntwo\n"]);
+        assert_eq!(text_children(&children[3]), vec![" "]);
+    }
+}
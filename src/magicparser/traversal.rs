@@ -0,0 +1,295 @@
+use magicparser::{DomNodeRef, ElemType};
+
+/// Receives the nodes of a tree as DomNodeRef::walk() goes through them, so
+/// that code that looks at every node, like dumping or cascading a tree,
+/// needn't walk it by hand. Each method does nothing by default.
+///
+/// The nodes aren't borrowed while they're passed, so they can be changed,
+/// though the children of an element are walked as they were when it was
+/// entered.
+pub trait DomVisitor {
+    /// Called for each element, and for other nodes that can have children,
+    /// like fragments and shadow roots, before their children. Returning
+    /// false skips the children and the shadow tree, but leave_element() is
+    /// still called.
+    fn enter_element(&mut self, _element: &DomNodeRef) -> bool {
+        true
+    }
+
+    /// Called for each node enter_element() was called for, after its
+    /// children.
+    fn leave_element(&mut self, _element: &DomNodeRef) {}
+
+    fn visit_text(&mut self, _text: &DomNodeRef) {}
+
+    fn visit_comment(&mut self, _comment: &DomNodeRef) {}
+}
+
+impl DomNodeRef {
+    /// Returns the sibling before this node, if any.
+    pub fn previous_sibling(&self) -> Option<DomNodeRef> {
+        let index = self.child_index()? - 1;
+        let parent = self.parent()?;
+        let sibling = parent.borrow().children.get(index.checked_sub(1)?).cloned();
+        sibling
+    }
+
+    /// Returns the sibling after this node, if any.
+    pub fn next_sibling(&self) -> Option<DomNodeRef> {
+        let index = self.child_index()? - 1;
+        let parent = self.parent()?;
+        let sibling = parent.borrow().children.get(index + 1).cloned();
+        sibling
+    }
+
+    /// Iterates over the node's ancestors, nearest first.
+    pub fn ancestors(&self) -> Ancestors {
+        Ancestors { next: self.parent() }
+    }
+
+    /// Like ancestors(), but continues from the root of a shadow tree to its
+    /// host, and so on.
+    pub fn shadow_including_ancestors(&self) -> ShadowIncludingAncestors {
+        ShadowIncludingAncestors { next: self.parent_or_host() }
+    }
+
+    /// Iterates over the node's descendants in document order (pre-order),
+    /// not including the node itself.
+    ///
+    /// Each step finds the next node from the last one in the tree as it is
+    /// then, so the subtree can be changed between steps, as long as the last
+    /// node returned stays in it.
+    pub fn descendants(&self) -> Descendants {
+        Descendants { root: self.clone(), last: Some(self.clone()) }
+    }
+
+    /// Passes this node and its descendants to visitor in tree order, along
+    /// with the shadow trees in the subtree, each right after its host is
+    /// entered, like browsers' devtools show them.
+    ///
+    /// Walked without recursion, so trees of any depth can be walked.
+    pub fn walk<V: DomVisitor + ?Sized>(&self, visitor: &mut V) {
+        // Nodes to visit, and elements to leave once their children have
+        // been walked
+        let mut stack = vec![(self.clone(), false)];
+        while let Some((node, leaving)) = stack.pop() {
+            if leaving {
+                visitor.leave_element(&node);
+                continue;
+            }
+            let (is_text, is_comment) = match node.borrow().elem_type {
+                ElemType::Text(_) => (true, false),
+                ElemType::Comment(_) => (false, true),
+                _ => (false, false),
+            };
+            if is_text {
+                visitor.visit_text(&node);
+                continue;
+            }
+            if is_comment {
+                visitor.visit_comment(&node);
+                continue;
+            }
+            if !visitor.enter_element(&node) {
+                visitor.leave_element(&node);
+                continue;
+            }
+            stack.push((node.clone(), true));
+            let node = node.borrow();
+            // Pushed in reverse, so that they're popped in tree order
+            for child in node.children.iter().rev() {
+                stack.push((child.clone(), false));
+            }
+            if let Some(ref shadow_root) = node.shadow_root {
+                stack.push((shadow_root.clone(), false));
+            }
+        }
+    }
+
+    /// Iterates over the siblings before this node, nearest first.
+    pub fn preceding_siblings(&self) -> PrecedingSiblings {
+        PrecedingSiblings { next: self.previous_sibling() }
+    }
+
+    /// Iterates over the siblings after this node, nearest first.
+    pub fn following_siblings(&self) -> FollowingSiblings {
+        FollowingSiblings { next: self.next_sibling() }
+    }
+}
+
+/// See DomNodeRef::ancestors().
+#[derive(Debug, Clone)]
+pub struct Ancestors {
+    next: Option<DomNodeRef>,
+}
+
+impl Iterator for Ancestors {
+    type Item = DomNodeRef;
+
+    fn next(&mut self) -> Option<DomNodeRef> {
+        let node = self.next.take()?;
+        self.next = node.parent();
+        Some(node)
+    }
+}
+
+/// See DomNodeRef::shadow_including_ancestors().
+#[derive(Debug, Clone)]
+pub struct ShadowIncludingAncestors {
+    next: Option<DomNodeRef>,
+}
+
+impl Iterator for ShadowIncludingAncestors {
+    type Item = DomNodeRef;
+
+    fn next(&mut self) -> Option<DomNodeRef> {
+        let node = self.next.take()?;
+        self.next = node.parent_or_host();
+        Some(node)
+    }
+}
+
+/// See DomNodeRef::descendants().
+#[derive(Debug, Clone)]
+pub struct Descendants {
+    root: DomNodeRef,
+    // The root before the first step, and None after the last
+    last: Option<DomNodeRef>,
+}
+
+impl Iterator for Descendants {
+    type Item = DomNodeRef;
+
+    fn next(&mut self) -> Option<DomNodeRef> {
+        let last = self.last.take()?;
+        let first_child = last.borrow().children.first().cloned();
+        self.last = first_child.or_else(|| {
+            // The next sibling of the nearest inclusive ancestor that has one,
+            // without leaving the root's subtree
+            let mut ancestor = last;
+            while !ancestor.ptr_eq(&self.root) {
+                if let Some(sibling) = ancestor.next_sibling() {
+                    return Some(sibling);
+                }
+                ancestor = ancestor.parent()?;
+            }
+            None
+        });
+        self.last.clone()
+    }
+}
+
+/// See DomNodeRef::preceding_siblings().
+#[derive(Debug, Clone)]
+pub struct PrecedingSiblings {
+    next: Option<DomNodeRef>,
+}
+
+impl Iterator for PrecedingSiblings {
+    type Item = DomNodeRef;
+
+    fn next(&mut self) -> Option<DomNodeRef> {
+        let node = self.next.take()?;
+        self.next = node.previous_sibling();
+        Some(node)
+    }
+}
+
+/// See DomNodeRef::following_siblings().
+#[derive(Debug, Clone)]
+pub struct FollowingSiblings {
+    next: Option<DomNodeRef>,
+}
+
+impl Iterator for FollowingSiblings {
+    type Item = DomNodeRef;
+
+    fn next(&mut self) -> Option<DomNodeRef> {
+        let node = self.next.take()?;
+        self.next = node.next_sibling();
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_html;
+
+    fn tags(nodes: impl Iterator<Item = DomNodeRef>) -> Vec<String> {
+        nodes.map(|node| node.borrow().elem_type.tag_name().to_string()).collect()
+    }
+
+    #[test]
+    fn test_traversal() {
+        let root = parse_html(
+            "<html><head></head><body><h1>One</h1><p><a></a><img></p><div></div></body></html>",
+        ).unwrap();
+        let body = root.borrow().children[1].clone();
+        let p = body.borrow().children[1].clone();
+        let a = p.borrow().children[0].clone();
+        assert_eq!(
+            tags(root.descendants()),
+            vec!["head", "body", "h1", "#text", "p", "a", "img", "div"]
+        );
+        assert_eq!(tags(p.descendants()), vec!["a", "img"]);
+        assert_eq!(tags(a.descendants()), Vec::<String>::new());
+        assert_eq!(tags(a.ancestors()), vec!["p", "body", "html"]);
+        assert_eq!(tags(root.ancestors()), Vec::<String>::new());
+        assert_eq!(tags(p.preceding_siblings()), vec!["h1"]);
+        assert_eq!(tags(p.following_siblings()), vec!["div"]);
+        assert_eq!(tags(body.borrow().children[0].following_siblings()), vec!["p", "div"]);
+        assert!(a.previous_sibling().is_none());
+        assert_eq!(tags(a.next_sibling().into_iter()), vec!["img"]);
+
+        // Nodes removed before they're reached aren't visited
+        let mut descendants = body.descendants();
+        assert_eq!(tags(descendants.by_ref().take(3)), vec!["h1", "#text", "p"]);
+        a.remove();
+        assert_eq!(tags(descendants), vec!["img", "div"]);
+    }
+
+    // Records the walk, skipping the children of elements with a title
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl DomVisitor for Recorder {
+        fn enter_element(&mut self, element: &DomNodeRef) -> bool {
+            let node = element.borrow();
+            self.0.push(format!("<{}>", node.elem_type.tag_name()));
+            node.attr("title").is_none()
+        }
+
+        fn leave_element(&mut self, element: &DomNodeRef) {
+            self.0.push(format!("</{}>", element.borrow().elem_type.tag_name()));
+        }
+
+        fn visit_text(&mut self, text: &DomNodeRef) {
+            if let ElemType::Text(ref content) = text.borrow().elem_type {
+                self.0.push(content.clone());
+            }
+            // Nodes can be changed while they're visited
+            text.borrow_mut().style_dirty = false;
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let root = parse_html(
+            "<div><p>One<!-- c --></p><a title=\"t\">Two</a><img></div>",
+        ).unwrap();
+        let p = root.borrow().children[0].clone();
+        p.attach_shadow().unwrap().append_child(parse_html("<span>Three</span>").unwrap());
+        let mut recorder = Recorder::default();
+        root.walk(&mut recorder);
+        assert_eq!(
+            recorder.0,
+            vec![
+                "<div>", "<p>", "<#shadow-root>", "<span>", "Three", "</span>",
+                "</#shadow-root>", "One", "</p>", "<a>", "</a>", "<img>", "</img>", "</div>",
+            ]
+        );
+        let text = p.borrow().children[0].clone();
+        assert!(!text.borrow().style_dirty);
+    }
+}
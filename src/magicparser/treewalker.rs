@@ -0,0 +1,373 @@
+use magicparser::DomNodeRef;
+use std::fmt;
+use std::rc::Rc;
+
+/// What a TreeWalker's filter decides for a node, like the DOM's NodeFilter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterResult {
+    Accept,
+    // Passes over the node, but not its descendants
+    Skip,
+    // Passes over the node and its descendants
+    Reject,
+}
+
+/// Decides which nodes a TreeWalker stops at; see TreeWalker::filter().
+pub type NodeFilter = Rc<dyn Fn(&DomNodeRef) -> FilterResult>;
+
+/// Moves around the subtree of a root node, stopping only at the nodes its
+/// filters accept, like the DOM's `TreeWalker`. Iterating over it goes
+/// through the accepted nodes after the current one in tree order.
+///
+/// Unlike the plain iterators, like DomNodeRef::descendants(), it can be
+/// moved in any direction from where it is, e.g. to the parent it would stop
+/// at. It never leaves the root's subtree, nor goes into shadow trees.
+#[derive(Clone)]
+pub struct TreeWalker {
+    root: DomNodeRef,
+    current: DomNodeRef,
+    elements_only: bool,
+    max_depth: Option<usize>,
+    filter: Option<NodeFilter>,
+}
+
+impl TreeWalker {
+    /// Walks the subtree of root, starting at root, stopping at every node.
+    pub fn new(root: DomNodeRef) -> TreeWalker {
+        TreeWalker {
+            current: root.clone(),
+            root,
+            elements_only: false,
+            max_depth: None,
+            filter: None,
+        }
+    }
+
+    /// Skips nodes that aren't elements, like text and comments.
+    pub fn elements_only(mut self) -> Self {
+        self.elements_only = true;
+        self
+    }
+
+    /// Rejects the nodes more than depth levels below the root, e.g. all but
+    /// the root's children for 1.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Asks filter about the nodes that elements_only() and max_depth()
+    /// don't already pass over, e.g. one made with
+    /// style::selectormatcher::selector_filter().
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&DomNodeRef) -> FilterResult + 'static,
+    {
+        self.filter = Some(Rc::new(filter));
+        self
+    }
+
+    pub fn root(&self) -> &DomNodeRef {
+        &self.root
+    }
+
+    pub fn current_node(&self) -> &DomNodeRef {
+        &self.current
+    }
+
+    /// Moves to node, whether the filters accept it or not.
+    pub fn set_current_node(&mut self, node: DomNodeRef) {
+        self.current = node;
+    }
+
+    /// Moves to the nearest accepted ancestor of the current node, up to the
+    /// root, and returns it. Returns None, staying put, if there's none.
+    pub fn parent_node(&mut self) -> Option<DomNodeRef> {
+        let mut node = self.current.clone();
+        while !node.ptr_eq(&self.root) {
+            node = node.parent()?;
+            if self.accept(&node) == FilterResult::Accept {
+                self.current = node.clone();
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// Moves to the first accepted node among the current node's children,
+    /// or, for those that are skipped, their children, and so on.
+    pub fn first_child(&mut self) -> Option<DomNodeRef> {
+        self.traverse_children(true)
+    }
+
+    /// Like first_child(), but moves to the last one.
+    pub fn last_child(&mut self) -> Option<DomNodeRef> {
+        self.traverse_children(false)
+    }
+
+    /// Moves to the first accepted node after the current one that's a
+    /// sibling of it, or would be if the skipped nodes around it were left
+    /// out of the tree.
+    pub fn next_sibling(&mut self) -> Option<DomNodeRef> {
+        self.traverse_siblings(true)
+    }
+
+    /// Like next_sibling(), but moves to the one before the current node.
+    pub fn previous_sibling(&mut self) -> Option<DomNodeRef> {
+        self.traverse_siblings(false)
+    }
+
+    /// Moves to the next accepted node in tree order.
+    pub fn next_node(&mut self) -> Option<DomNodeRef> {
+        let mut node = self.current.clone();
+        let mut result = FilterResult::Accept;
+        loop {
+            while result != FilterResult::Reject {
+                let first_child = match first_or_last_child(&node, true) {
+                    Some(first_child) => first_child,
+                    None => break,
+                };
+                node = first_child;
+                result = self.accept(&node);
+                if result == FilterResult::Accept {
+                    self.current = node.clone();
+                    return Some(node);
+                }
+            }
+            // The next node that isn't a descendant of node
+            let mut following = node.clone();
+            node = loop {
+                if following.ptr_eq(&self.root) {
+                    return None;
+                }
+                if let Some(sibling) = following.next_sibling() {
+                    break sibling;
+                }
+                following = following.parent()?;
+            };
+            result = self.accept(&node);
+            if result == FilterResult::Accept {
+                self.current = node.clone();
+                return Some(node);
+            }
+        }
+    }
+
+    /// Moves to the previous accepted node in tree order.
+    pub fn previous_node(&mut self) -> Option<DomNodeRef> {
+        let mut node = self.current.clone();
+        while !node.ptr_eq(&self.root) {
+            let mut sibling = node.previous_sibling();
+            while let Some(previous) = sibling {
+                node = previous;
+                let mut result = self.accept(&node);
+                // The last descendant that isn't rejected with an ancestor
+                while result != FilterResult::Reject {
+                    let last_child = match first_or_last_child(&node, false) {
+                        Some(last_child) => last_child,
+                        None => break,
+                    };
+                    node = last_child;
+                    result = self.accept(&node);
+                }
+                if result == FilterResult::Accept {
+                    self.current = node.clone();
+                    return Some(node);
+                }
+                sibling = node.previous_sibling();
+            }
+            node = node.parent()?;
+            if self.accept(&node) == FilterResult::Accept {
+                self.current = node.clone();
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    fn traverse_children(&mut self, first: bool) -> Option<DomNodeRef> {
+        let mut node = first_or_last_child(&self.current, first);
+        while let Some(mut child) = node {
+            match self.accept(&child) {
+                FilterResult::Accept => {
+                    self.current = child.clone();
+                    return Some(child);
+                }
+                FilterResult::Skip => {
+                    if let Some(grandchild) = first_or_last_child(&child, first) {
+                        node = Some(grandchild);
+                        continue;
+                    }
+                }
+                FilterResult::Reject => {}
+            }
+            // The next sibling of child, or of its nearest ancestor that has
+            // one, below the current node
+            node = loop {
+                let sibling = if first { child.next_sibling() } else { child.previous_sibling() };
+                if sibling.is_some() {
+                    break sibling;
+                }
+                let parent = child.parent()?;
+                if parent.ptr_eq(&self.root) || parent.ptr_eq(&self.current) {
+                    return None;
+                }
+                child = parent;
+            };
+        }
+        None
+    }
+
+    fn traverse_siblings(&mut self, next: bool) -> Option<DomNodeRef> {
+        let mut node = self.current.clone();
+        if node.ptr_eq(&self.root) {
+            return None;
+        }
+        loop {
+            let mut sibling = if next { node.next_sibling() } else { node.previous_sibling() };
+            while let Some(current) = sibling {
+                node = current;
+                let result = self.accept(&node);
+                if result == FilterResult::Accept {
+                    self.current = node.clone();
+                    return Some(node);
+                }
+                // The children of a skipped node take its place
+                sibling = first_or_last_child(&node, next);
+                if result == FilterResult::Reject || sibling.is_none() {
+                    sibling = if next { node.next_sibling() } else { node.previous_sibling() };
+                }
+            }
+            node = node.parent()?;
+            if node.ptr_eq(&self.root) || self.accept(&node) == FilterResult::Accept {
+                return None;
+            }
+        }
+    }
+
+    fn accept(&self, node: &DomNodeRef) -> FilterResult {
+        if self.elements_only && !node.borrow().elem_type.is_element() {
+            return FilterResult::Skip;
+        }
+        if let Some(max_depth) = self.max_depth {
+            if self.depth(node) > max_depth {
+                return FilterResult::Reject;
+            }
+        }
+        match self.filter {
+            Some(ref filter) => filter(node),
+            None => FilterResult::Accept,
+        }
+    }
+
+    // How many levels below the root node is
+    fn depth(&self, node: &DomNodeRef) -> usize {
+        if node.ptr_eq(&self.root) {
+            return 0;
+        }
+        node.ancestors()
+            .position(|ancestor| ancestor.ptr_eq(&self.root))
+            .map_or(0, |position| position + 1)
+    }
+}
+
+impl Iterator for TreeWalker {
+    type Item = DomNodeRef;
+
+    fn next(&mut self) -> Option<DomNodeRef> {
+        self.next_node()
+    }
+}
+
+impl fmt::Debug for TreeWalker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TreeWalker")
+            .field("root", &self.root)
+            .field("current", &self.current)
+            .field("elements_only", &self.elements_only)
+            .field("max_depth", &self.max_depth)
+            .finish()
+    }
+}
+
+fn first_or_last_child(dom_node: &DomNodeRef, first: bool) -> Option<DomNodeRef> {
+    let node = dom_node.borrow();
+    if first {
+        node.children.first().cloned()
+    } else {
+        node.children.last().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_html;
+
+    fn tags<I: Iterator<Item = DomNodeRef>>(nodes: I) -> Vec<String> {
+        nodes.map(|node| node.borrow().elem_type.tag_name().to_string()).collect()
+    }
+
+    fn tag(node: Option<DomNodeRef>) -> String {
+        node.map_or("-".to_string(), |node| node.borrow().elem_type.tag_name().to_string())
+    }
+
+    #[test]
+    fn test_tree_walker() {
+        let root = parse_html(
+            "<body><h1>One</h1><div hidden><p>Two</p></div><div><a></a><img></div>\
+             <!-- c --></body>",
+        ).unwrap();
+        assert_eq!(
+            tags(TreeWalker::new(root.clone())),
+            vec!["h1", "#text", "div", "p", "#text", "div", "a", "img", "#comment"]
+        );
+        assert_eq!(
+            tags(TreeWalker::new(root.clone()).elements_only()),
+            vec!["h1", "div", "p", "div", "a", "img"]
+        );
+        assert_eq!(
+            tags(TreeWalker::new(root.clone()).elements_only().max_depth(1)),
+            vec!["h1", "div", "div"]
+        );
+        // Hidden subtrees are rejected, and divs are skipped, leaving their
+        // children in their place
+        let walker = TreeWalker::new(root.clone()).elements_only().filter(|node| {
+            let node = node.borrow();
+            if node.attr("hidden").is_some() {
+                FilterResult::Reject
+            } else if node.elem_type.tag_name() == "div" {
+                FilterResult::Skip
+            } else {
+                FilterResult::Accept
+            }
+        });
+        assert_eq!(tags(walker.clone()), vec!["h1", "a", "img"]);
+
+        let mut walker = walker;
+        assert_eq!(tag(walker.first_child()), "h1");
+        assert_eq!(tag(walker.next_sibling()), "a");
+        assert_eq!(tag(walker.next_sibling()), "img");
+        assert_eq!(tag(walker.next_sibling()), "-");
+        assert_eq!(tag(walker.previous_sibling()), "a");
+        assert_eq!(tag(walker.previous_sibling()), "h1");
+        assert_eq!(tag(walker.previous_sibling()), "-");
+        assert_eq!(tag(walker.parent_node()), "body");
+        assert_eq!(tag(walker.parent_node()), "-");
+        assert_eq!(tag(walker.last_child()), "img");
+        assert_eq!(tag(walker.previous_node()), "a");
+        assert_eq!(tag(walker.previous_node()), "h1");
+        assert_eq!(tag(walker.previous_node()), "body");
+        assert_eq!(tag(walker.previous_node()), "-");
+        assert!(walker.current_node().ptr_eq(&root));
+
+        // Never leaves the root's subtree
+        let div = root.borrow().children[2].clone();
+        let mut walker = TreeWalker::new(div.clone());
+        assert_eq!(tags(walker.by_ref()), vec!["a", "img"]);
+        assert!(walker.current_node().borrow().elem_type.tag_name() == "img");
+        walker.set_current_node(div);
+        assert_eq!(tag(walker.next_sibling()), "-");
+        assert_eq!(tag(walker.parent_node()), "-");
+    }
+}
@@ -0,0 +1,106 @@
+/// Resolves url against the absolute URL base, like a browser following a
+/// link, e.g. `../a.css` against `https://example.com/css/main.css` is
+/// `https://example.com/a.css`. Only the URL syntax documents commonly use is
+/// understood: nothing is percent-encoded or otherwise normalized, besides
+/// removing `.` and `..` segments from the path. url is returned as it is if
+/// it's absolute or base isn't.
+pub fn resolve_url(base: &str, url: &str) -> String {
+    if scheme_len(url).is_some() {
+        return url.to_string();
+    }
+    let (scheme, rest) = match scheme_len(base) {
+        Some(len) => (&base[..len], &base[len + 1..]),
+        None => return url.to_string(),
+    };
+    if url.starts_with("//") {
+        return format!("{}:{}", scheme, url);
+    }
+    let (authority, rest) = match rest.strip_prefix("//") {
+        Some(after) => rest.split_at(2 + after.find(['/', '?', '#']).unwrap_or(after.len())),
+        None => ("", rest),
+    };
+    let path_end = rest.find(['?', '#']).unwrap_or(rest.len());
+    let (path, query) = rest.split_at(path_end);
+    let query = &query[..query.find('#').unwrap_or(query.len())];
+    let (path, suffix) = match url.chars().next() {
+        None => (path.to_string(), query.to_string()),
+        Some('#') => (path.to_string(), format!("{}{}", query, url)),
+        Some('?') => (path.to_string(), url.to_string()),
+        _ => {
+            let (url_path, url_suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+            let joined = if url_path.starts_with('/') {
+                url_path.to_string()
+            } else if path.is_empty() && !authority.is_empty() {
+                format!("/{}", url_path)
+            } else {
+                format!("{}{}", &path[..path.rfind('/').map_or(0, |i| i + 1)], url_path)
+            };
+            (remove_dot_segments(&joined), url_suffix.to_string())
+        }
+    };
+    format!("{}:{}{}{}", scheme, authority, path, suffix)
+}
+
+// Length of url's scheme, e.g. 5 for https://example.com, if it has one
+fn scheme_len(url: &str) -> Option<usize> {
+    let len = url.find(':')?;
+    let mut chars = url[..len].chars();
+    let valid = chars.next()?.is_ascii_alphabetic()
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || "+-.".contains(ch));
+    if valid {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let segments = path.split('/').collect::<Vec<_>>();
+    let mut output: Vec<&str> = vec![];
+    for (i, &segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        match segment {
+            "." | ".." => {
+                // Never above the root
+                if segment == ".." && output.len() > 1 {
+                    output.pop();
+                }
+                // a/b/.. is a/, not a
+                if last {
+                    output.push("");
+                }
+            }
+            segment => output.push(segment),
+        }
+    }
+    output.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_url() {
+        let base = "https://example.com/css/main.css?v=1#top";
+        let cases = [
+            ("a.css", "https://example.com/css/a.css"),
+            ("../a.css?x", "https://example.com/a.css?x"),
+            ("./img/../b.png#c", "https://example.com/css/b.png#c"),
+            ("/a/./b/../", "https://example.com/a/"),
+            ("../../../a", "https://example.com/a"),
+            ("..", "https://example.com/"),
+            ("//cdn.example.com/a", "https://cdn.example.com/a"),
+            ("?v=2", "https://example.com/css/main.css?v=2"),
+            ("#intro", "https://example.com/css/main.css?v=1#intro"),
+            ("", "https://example.com/css/main.css?v=1"),
+            ("mailto:a@example.com", "mailto:a@example.com"),
+        ];
+        for &(url, resolved) in &cases {
+            assert_eq!(resolve_url(base, url), resolved, "{}", url);
+        }
+        assert_eq!(resolve_url("https://example.com", "a"), "https://example.com/a");
+        assert_eq!(resolve_url("file:///home/a/index.html", "b.html"), "file:///home/a/b.html");
+        assert_eq!(resolve_url("a/b.html", "c.html"), "c.html");
+    }
+}
@@ -0,0 +1,644 @@
+use magicparser::{Declaration, KeyframesRule, Stylesheet};
+use std::collections::HashMap;
+use style::declaration::{expand_declaration, is_valid};
+use style::interpolate::interpolate;
+use style::length::LengthContext;
+use style::resolver::ComputedStyle;
+
+/// Parses a `<time>`, e.g. `1.5s` or `200ms`, to seconds.
+pub fn parse_time(value: &str) -> Option<f32> {
+    let value = value.trim().to_ascii_lowercase();
+    if let Some(ms) = value.strip_suffix("ms") {
+        ms.parse::<f32>().ok().map(|ms| ms / 1000.0)
+    } else {
+        value.strip_suffix('s')?.parse::<f32>().ok()
+    }
+}
+
+/// Where the jumps of a `steps()` easing function are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepPosition {
+    JumpStart,
+    JumpEnd,
+    JumpNone,
+    JumpBoth,
+}
+
+/// An easing function, e.g. of `animation-timing-function`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimingFunction {
+    Linear,
+    // x1, y1, x2, y2
+    CubicBezier(f32, f32, f32, f32),
+    Steps(u32, StepPosition),
+}
+
+// Splits e.g. "steps(4, end)" into ("steps", ["4", "end"])
+fn split_function(value: &str) -> Option<(&str, Vec<&str>)> {
+    let open = value.find('(')?;
+    let args = value[open + 1..].strip_suffix(')')?;
+    Some((value[..open].trim(), args.split(',').map(|arg| arg.trim()).collect()))
+}
+
+impl TimingFunction {
+    pub fn parse(value: &str) -> Option<TimingFunction> {
+        let value = value.trim().to_ascii_lowercase();
+        let function = match value.as_str() {
+            "linear" => TimingFunction::Linear,
+            "ease" => TimingFunction::CubicBezier(0.25, 0.1, 0.25, 1.0),
+            "ease-in" => TimingFunction::CubicBezier(0.42, 0.0, 1.0, 1.0),
+            "ease-out" => TimingFunction::CubicBezier(0.0, 0.0, 0.58, 1.0),
+            "ease-in-out" => TimingFunction::CubicBezier(0.42, 0.0, 0.58, 1.0),
+            "step-start" => TimingFunction::Steps(1, StepPosition::JumpStart),
+            "step-end" => TimingFunction::Steps(1, StepPosition::JumpEnd),
+            _ => match split_function(&value)? {
+                ("cubic-bezier", ref args) if args.len() == 4 => {
+                    let args = args
+                        .iter()
+                        .map(|arg| arg.parse::<f32>().ok())
+                        .collect::<Option<Vec<_>>>()?;
+                    // x values must be in [0, 1] so that the curve is a function of x
+                    if !(0.0..=1.0).contains(&args[0]) || !(0.0..=1.0).contains(&args[2]) {
+                        return None;
+                    }
+                    TimingFunction::CubicBezier(args[0], args[1], args[2], args[3])
+                }
+                ("steps", ref args) if args.len() == 1 || args.len() == 2 => {
+                    let count = args[0].parse::<u32>().ok()?;
+                    let position = match args.get(1).cloned() {
+                        None | Some("jump-end") | Some("end") => StepPosition::JumpEnd,
+                        Some("jump-start") | Some("start") => StepPosition::JumpStart,
+                        Some("jump-none") => StepPosition::JumpNone,
+                        Some("jump-both") => StepPosition::JumpBoth,
+                        Some(_) => return None,
+                    };
+                    let min_count = if position == StepPosition::JumpNone { 2 } else { 1 };
+                    if count < min_count {
+                        return None;
+                    }
+                    TimingFunction::Steps(count, position)
+                }
+                _ => return None,
+            },
+        };
+        Some(function)
+    }
+
+    /// Maps progress in [0, 1] to eased progress, which may be outside of
+    /// [0, 1] for cubic Béziers that overshoot.
+    pub fn apply(&self, progress: f32) -> f32 {
+        match *self {
+            TimingFunction::Linear => progress,
+            TimingFunction::CubicBezier(x1, y1, x2, y2) => {
+                let bezier = |t: f32, p1: f32, p2: f32| {
+                    3.0 * (1.0 - t) * (1.0 - t) * t * p1 + 3.0 * (1.0 - t) * t * t * p2 + t * t * t
+                };
+                // x is increasing in t, so find the t for progress by bisection
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..32 {
+                    let mid = (low + high) / 2.0;
+                    if bezier(mid, x1, x2) < progress {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                bezier((low + high) / 2.0, y1, y2)
+            }
+            TimingFunction::Steps(count, position) => {
+                let count = count as f32;
+                let mut step = (progress * count).floor();
+                if position == StepPosition::JumpStart || position == StepPosition::JumpBoth {
+                    step += 1.0;
+                }
+                let jumps = match position {
+                    StepPosition::JumpNone => count - 1.0,
+                    StepPosition::JumpBoth => count + 1.0,
+                    _ => count,
+                };
+                step.clamp(0.0, jumps) / jumps
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IterationCount {
+    Count(f32),
+    Infinite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationDirection {
+    Normal,
+    Reverse,
+    Alternate,
+    AlternateReverse,
+}
+
+/// Whether an animation applies before it starts and after it ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillMode {
+    None,
+    Forwards,
+    Backwards,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayState {
+    Running,
+    Paused,
+}
+
+/// One animation applied to an element, from its `animation-*` properties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    // Of the @keyframes rule
+    pub name: String,
+    // In seconds
+    pub duration: f32,
+    pub timing_function: TimingFunction,
+    pub delay: f32,
+    pub iteration_count: IterationCount,
+    pub direction: AnimationDirection,
+    pub fill_mode: FillMode,
+    pub play_state: PlayState,
+}
+
+// Item i of a comma-separated list, which repeats if it's shorter than the
+// list of animation names
+fn list_item<'a>(style: &'a ComputedStyle, property: &str, i: usize) -> Option<&'a str> {
+    let items = style.get(property)?.split(',').collect::<Vec<_>>();
+    Some(items[i % items.len()].trim())
+}
+
+impl Animation {
+    /// Returns the animations of a computed style, in the order of
+    /// `animation-name`. Invalid values are replaced with the initial value.
+    pub fn from_style(style: &ComputedStyle) -> Vec<Animation> {
+        let names = match style.get("animation-name") {
+            Some(names) => names.split(',').map(|name| name.trim()).collect::<Vec<_>>(),
+            None => return vec![],
+        };
+        names
+            .iter()
+            .enumerate()
+            .filter(|&(_, name)| !name.eq_ignore_ascii_case("none") && !name.is_empty())
+            .map(|(i, name)| {
+                let item = |property: &str| list_item(style, property, i).unwrap_or("");
+                let iteration_count = match item("animation-iteration-count") {
+                    "infinite" => IterationCount::Infinite,
+                    count => IterationCount::Count(
+                        count.parse::<f32>().ok().filter(|&count| count >= 0.0).unwrap_or(1.0),
+                    ),
+                };
+                let direction = match item("animation-direction") {
+                    "reverse" => AnimationDirection::Reverse,
+                    "alternate" => AnimationDirection::Alternate,
+                    "alternate-reverse" => AnimationDirection::AlternateReverse,
+                    _ => AnimationDirection::Normal,
+                };
+                let fill_mode = match item("animation-fill-mode") {
+                    "forwards" => FillMode::Forwards,
+                    "backwards" => FillMode::Backwards,
+                    "both" => FillMode::Both,
+                    _ => FillMode::None,
+                };
+                let play_state = match item("animation-play-state") {
+                    "paused" => PlayState::Paused,
+                    _ => PlayState::Running,
+                };
+                Animation {
+                    name: name.trim_matches(|ch| ch == '"' || ch == '\'').to_string(),
+                    duration: parse_time(item("animation-duration"))
+                        .filter(|&duration| duration >= 0.0)
+                        .unwrap_or(0.0),
+                    timing_function: TimingFunction::parse(item("animation-timing-function"))
+                        .unwrap_or(TimingFunction::CubicBezier(0.25, 0.1, 0.25, 1.0)),
+                    delay: parse_time(item("animation-delay")).unwrap_or(0.0),
+                    iteration_count,
+                    direction,
+                    fill_mode,
+                    play_state,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns how far through its keyframes the animation is, in [0, 1],
+    /// elapsed seconds after it started. Returns None if the animation
+    /// doesn't apply then, i.e. during its delay or after it ends without a
+    /// fill mode that covers that.
+    ///
+    /// The play state isn't taken into account: elapsed shouldn't include
+    /// time spent paused.
+    pub fn progress(&self, elapsed: f32) -> Option<f32> {
+        let iterations = match self.iteration_count {
+            IterationCount::Count(count) => count,
+            IterationCount::Infinite => f32::INFINITY,
+        };
+        let active_duration = if self.duration == 0.0 { 0.0 } else { self.duration * iterations };
+        let local_time = elapsed - self.delay;
+        let (iteration, progress) = if local_time < 0.0 {
+            match self.fill_mode {
+                FillMode::Backwards | FillMode::Both => (0.0, 0.0),
+                _ => return None,
+            }
+        } else if local_time >= active_duration {
+            match self.fill_mode {
+                FillMode::Forwards | FillMode::Both => {
+                    // At the end of the last, possibly partial, iteration
+                    if iterations == 0.0 {
+                        (0.0, 0.0)
+                    } else if iterations.fract() == 0.0 {
+                        (iterations - 1.0, 1.0)
+                    } else {
+                        (iterations.floor(), iterations.fract())
+                    }
+                }
+                _ => return None,
+            }
+        } else {
+            let iterations_done = local_time / self.duration;
+            (iterations_done.floor(), iterations_done.fract())
+        };
+        let odd_iteration = iteration % 2.0 == 1.0;
+        let reversed = match self.direction {
+            AnimationDirection::Normal => false,
+            AnimationDirection::Reverse => true,
+            AnimationDirection::Alternate => odd_iteration,
+            AnimationDirection::AlternateReverse => !odd_iteration,
+        };
+        Some(if reversed { 1.0 - progress } else { progress })
+    }
+
+    /// Returns the declarations the animation overrides the element's style
+    /// with, elapsed seconds after it started. base is the element's style
+    /// without animations, which fills in properties missing from the first or
+    /// last keyframe. context is what lengths in keyframes are relative to.
+    pub fn sample(
+        &self,
+        keyframes: &Keyframes,
+        elapsed: f32,
+        base: &ComputedStyle,
+        context: &LengthContext,
+    ) -> Vec<Declaration> {
+        let progress = match self.progress(elapsed) {
+            Some(progress) => progress,
+            None => return vec![],
+        };
+        keyframes
+            .properties()
+            .into_iter()
+            .filter_map(|property| {
+                let value = keyframes.sample_property(
+                    &property,
+                    progress,
+                    self.timing_function,
+                    base,
+                    context,
+                )?;
+                Some(Declaration::new(property, value))
+            })
+            .collect()
+    }
+}
+
+// A keyframe's offset in [0, 1], e.g. 0.5 for "50%"
+fn parse_offset(selector: &str) -> Option<f32> {
+    match selector {
+        "from" => Some(0.0),
+        "to" => Some(1.0),
+        _ => {
+            let percentage = selector.strip_suffix('%')?.trim().parse::<f32>().ok()?;
+            if (0.0..=100.0).contains(&percentage) {
+                Some(percentage / 100.0)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// An @keyframes rule with its keyframes sorted by offset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframes {
+    pub name: String,
+    // (offset in [0, 1], declarations). Keyframes with the same offset are in
+    // source order.
+    pub keyframes: Vec<(f32, Vec<Declaration>)>,
+}
+
+impl Keyframes {
+    /// Keyframes with invalid selectors, and invalid or !important
+    /// declarations, are ignored. Shorthands are expanded into their
+    /// longhands.
+    pub fn from_rule(rule: &KeyframesRule) -> Keyframes {
+        let mut keyframes = vec![];
+        for keyframe in &rule.keyframes {
+            let offsets = match keyframe
+                .selectors
+                .iter()
+                .map(|selector| parse_offset(selector))
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(offsets) => offsets,
+                None => continue,
+            };
+            let declarations = keyframe
+                .declarations
+                .iter()
+                .filter(|decl| !decl.important && is_valid(decl))
+                .flat_map(|decl| expand_declaration(&decl.property, &decl.value))
+                .map(|(property, value)| Declaration::new(property.to_string(), value))
+                .collect::<Vec<_>>();
+            for offset in offsets {
+                keyframes.push((offset, declarations.clone()));
+            }
+        }
+        // Stable, so source order is kept for equal offsets
+        keyframes.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+        Keyframes {
+            name: rule.name.clone(),
+            keyframes,
+        }
+    }
+
+    // Animated properties, in the order they first appear. Timing functions
+    // in keyframes apply to the keyframe rather than being animated.
+    fn properties(&self) -> Vec<String> {
+        let mut properties: Vec<String> = vec![];
+        for (_, declarations) in &self.keyframes {
+            for decl in declarations {
+                let is_animation_property = decl.property.starts_with("animation");
+                if !is_animation_property && !properties.contains(&decl.property) {
+                    properties.push(decl.property.clone());
+                }
+            }
+        }
+        properties
+    }
+
+    fn sample_property(
+        &self,
+        property: &str,
+        progress: f32,
+        default_timing_function: TimingFunction,
+        base: &ComputedStyle,
+        context: &LengthContext,
+    ) -> Option<String> {
+        // (offset, value, timing function) of the keyframes that set property
+        let mut frames: Vec<(f32, &str, TimingFunction)> = vec![];
+        for (offset, declarations) in &self.keyframes {
+            let value = match declarations.iter().rev().find(|decl| decl.property == property) {
+                Some(decl) => decl.value.as_str(),
+                None => continue,
+            };
+            let timing_function = declarations
+                .iter()
+                .rev()
+                .find(|decl| decl.property == "animation-timing-function")
+                .and_then(|decl| TimingFunction::parse(&decl.value))
+                .unwrap_or(default_timing_function);
+            match frames.last_mut() {
+                // The later of keyframes with the same offset wins
+                Some(last) if last.0 == *offset => *last = (*offset, value, timing_function),
+                _ => frames.push((*offset, value, timing_function)),
+            }
+        }
+        // Missing from and to keyframes take the underlying value
+        let (first, last) = (*frames.first()?, *frames.last()?);
+        if first.0 > 0.0 {
+            let value = base.get(property).unwrap_or(first.1);
+            frames.insert(0, (0.0, value, default_timing_function));
+        }
+        if last.0 < 1.0 {
+            let value = base.get(property).unwrap_or(last.1);
+            frames.push((1.0, value, default_timing_function));
+        }
+
+        let i = frames
+            .iter()
+            .rposition(|&(offset, _, _)| offset <= progress)
+            .unwrap_or(0);
+        let (from_offset, from_value, timing_function) = frames[i];
+        let (to_offset, to_value, _) = match frames.get(i + 1) {
+            Some(&frame) => frame,
+            None => return Some(from_value.to_string()),
+        };
+        let local_progress = (progress - from_offset) / (to_offset - from_offset);
+        Some(interpolate(
+            from_value,
+            to_value,
+            timing_function.apply(local_progress),
+            context,
+        ))
+    }
+}
+
+/// The @keyframes rules of a set of stylesheets, by name.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct KeyframesRegistry {
+    keyframes: HashMap<String, Keyframes>,
+}
+
+impl KeyframesRegistry {
+    /// Adds the @keyframes rules of stylesheet. A rule replaces any earlier
+    /// rule with the same name.
+    pub fn add_stylesheet(&mut self, stylesheet: &Stylesheet) {
+        for rule in &stylesheet.keyframes {
+            self.keyframes
+                .insert(rule.name.clone(), Keyframes::from_rule(rule));
+        }
+    }
+
+    /// Names are case-sensitive.
+    pub fn get(&self, name: &str) -> Option<&Keyframes> {
+        self.keyframes.get(name)
+    }
+
+    /// Returns the declarations all of an element's animations override its
+    /// style with, elapsed seconds after they started. Later animations take
+    /// precedence over earlier ones.
+    pub fn sample(
+        &self,
+        style: &ComputedStyle,
+        elapsed: f32,
+        context: &LengthContext,
+    ) -> Vec<Declaration> {
+        let mut declarations: Vec<Declaration> = vec![];
+        for animation in Animation::from_style(style) {
+            let keyframes = match self.get(&animation.name) {
+                Some(keyframes) => keyframes,
+                None => continue,
+            };
+            for decl in animation.sample(keyframes, elapsed, style, context) {
+                declarations.retain(|earlier| earlier.property != decl.property);
+                declarations.push(decl);
+            }
+        }
+        declarations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_stylesheet;
+
+    fn registry(css: &str) -> KeyframesRegistry {
+        let mut registry = KeyframesRegistry::default();
+        registry.add_stylesheet(&parse_stylesheet(css).unwrap());
+        registry
+    }
+
+    fn style(declarations: &[(&str, &str)]) -> ComputedStyle {
+        let mut style = ComputedStyle::default();
+        for &(property, value) in declarations {
+            style.set(property, value);
+        }
+        style
+    }
+
+    fn sample(registry: &KeyframesRegistry, style: &ComputedStyle, elapsed: f32) -> Vec<String> {
+        registry
+            .sample(style, elapsed, &LengthContext::default())
+            .iter()
+            .map(|decl| format!("{}: {}", decl.property, decl.value))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1.5s"), Some(1.5));
+        assert_eq!(parse_time("250MS"), Some(0.25));
+        assert_eq!(parse_time("-1s"), Some(-1.0));
+        assert_eq!(parse_time("1"), None);
+        assert_eq!(parse_time("s"), None);
+    }
+
+    #[test]
+    fn test_timing_function() {
+        let apply = |value: &str, progress: f32| {
+            (TimingFunction::parse(value).unwrap().apply(progress) * 1000.0).round() / 1000.0
+        };
+        assert_eq!(apply("linear", 0.3), 0.3);
+        assert_eq!(apply("ease-in-out", 0.5), 0.5);
+        assert_eq!(apply("ease", 0.0), 0.0);
+        assert_eq!(apply("ease", 1.0), 1.0);
+        assert_eq!(apply("ease-in", 0.5), 0.315);
+        assert_eq!(apply("steps(4)", 0.3), 0.25);
+        assert_eq!(apply("steps(4, jump-start)", 0.3), 0.5);
+        assert_eq!(apply("steps(3, jump-none)", 0.5), 0.5);
+        assert_eq!(apply("step-end", 0.99), 0.0);
+        assert_eq!(TimingFunction::parse("cubic-bezier(2, 0, 0, 1)"), None);
+        assert_eq!(TimingFunction::parse("steps(1, jump-none)"), None);
+        assert_eq!(TimingFunction::parse("bounce"), None);
+    }
+
+    #[test]
+    fn test_animation_from_style() {
+        let style = style(&[
+            ("animation-name", "a, none, b"),
+            ("animation-duration", "1s, 500ms"),
+            ("animation-timing-function", "linear"),
+            ("animation-delay", "0s"),
+            ("animation-iteration-count", "infinite, 2"),
+            ("animation-direction", "alternate"),
+            ("animation-fill-mode", "both"),
+            ("animation-play-state", "running, paused"),
+        ]);
+        let animations = Animation::from_style(&style);
+        assert_eq!(animations.len(), 2);
+        assert_eq!(animations[0].name, "a");
+        assert_eq!(animations[0].iteration_count, IterationCount::Infinite);
+        assert_eq!(animations[1].name, "b");
+        assert_eq!(animations[1].duration, 1.0);
+        assert_eq!(animations[1].iteration_count, IterationCount::Infinite);
+        assert_eq!(animations[1].play_state, PlayState::Running);
+        assert_eq!(animations[1].fill_mode, FillMode::Both);
+    }
+
+    #[test]
+    fn test_animation_progress() {
+        let animation = Animation {
+            name: "a".to_string(),
+            duration: 2.0,
+            timing_function: TimingFunction::Linear,
+            delay: 1.0,
+            iteration_count: IterationCount::Count(1.5),
+            direction: AnimationDirection::Alternate,
+            fill_mode: FillMode::None,
+            play_state: PlayState::Running,
+        };
+        assert_eq!(animation.progress(0.5), None);
+        assert_eq!(animation.progress(2.0), Some(0.5));
+        assert_eq!(animation.progress(3.5), Some(0.75));
+        assert_eq!(animation.progress(4.5), None);
+        let animation = Animation {
+            fill_mode: FillMode::Both,
+            ..animation
+        };
+        assert_eq!(animation.progress(0.5), Some(0.0));
+        assert_eq!(animation.progress(4.5), Some(0.5));
+    }
+
+    #[test]
+    fn test_keyframes_from_rule() {
+        let registry = registry(
+            "@keyframes a { to { opacity: 1 } 20%, from { opacity: 0 } 150% { opacity: 2 } } \
+             @keyframes a { 50% { color: red !important; opacity: 0.5 } }",
+        );
+        let keyframes = registry.get("a").unwrap();
+        assert_eq!(keyframes.keyframes.len(), 1);
+        let opacity = Declaration::new("opacity".to_string(), "0.5".to_string());
+        assert_eq!(keyframes.keyframes, vec![(0.5, vec![opacity])]);
+        assert_eq!(registry.get("A"), None);
+    }
+
+    #[test]
+    fn test_sample() {
+        let registry = registry(
+            "@keyframes fade { from { opacity: 0 } 50% { opacity: 0.5; margin-left: 10px } \
+                               to { opacity: 1; display: none } }",
+        );
+        let style = style(&[
+            ("animation-name", "fade"),
+            ("animation-duration", "2s"),
+            ("animation-timing-function", "linear"),
+            ("animation-delay", "0s"),
+            ("margin-left", "0px"),
+            ("display", "block"),
+        ]);
+        assert_eq!(
+            sample(&registry, &style, 0.5),
+            vec!["opacity: 0.25", "margin-left: 5px", "display: block"]
+        );
+        assert_eq!(
+            sample(&registry, &style, 1.5),
+            vec!["opacity: 0.75", "margin-left: 5px", "display: none"]
+        );
+        assert!(sample(&registry, &style, 2.0).is_empty());
+    }
+
+    #[test]
+    fn test_sample_keyframe_timing_function() {
+        let registry = registry(
+            "@keyframes a { from { width: 0; animation-timing-function: steps(2) } \
+                            to { width: 100px } } \
+             @keyframes b { to { width: 50px } }",
+        );
+        let style = style(&[
+            ("animation-name", "a, b"),
+            ("animation-duration", "1s, 2s"),
+            ("animation-timing-function", "linear"),
+            ("width", "10px"),
+        ]);
+        // b overrides a
+        assert_eq!(sample(&registry, &style, 0.8), vec!["width: 26px"]);
+        let mut style = style;
+        style.set("animation-name", "a");
+        assert_eq!(sample(&registry, &style, 0.8), vec!["width: 50px"]);
+    }
+}
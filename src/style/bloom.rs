@@ -0,0 +1,216 @@
+use magicparser::{Combinator, DomNodeRef, ElemType, Selector};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use style::selectormatcher::flatten_complex_selector;
+
+// Each hash sets two counters, picked by its low and next-lowest 12 bits
+const KEY_BITS: u32 = 12;
+const KEY_MASK: u32 = (1 << KEY_BITS) - 1;
+const COUNTERS: usize = 1 << KEY_BITS;
+
+/// A counting Bloom filter of the element types, ids and classes of the
+/// ancestors of the node being styled, kept up to date by pushing each node
+/// before styling its children and popping it after.
+///
+/// It can tell that a selector like `.menu a` doesn't match a node without
+/// walking its ancestors, since no ancestor has the class `menu`. It never
+/// rejects a selector that does match, but may let through some that don't.
+#[derive(Debug, Clone)]
+pub struct AncestorFilter {
+    counters: Vec<u8>,
+    // Ids and classes match ASCII-case-insensitively in quirks mode
+    quirks: bool,
+}
+
+impl AncestorFilter {
+    pub fn new(quirks: bool) -> AncestorFilter {
+        AncestorFilter {
+            counters: vec![0; COUNTERS],
+            quirks,
+        }
+    }
+
+    /// Adds dom_node's element type, id and classes, before styling its
+    /// descendants.
+    pub fn push(&mut self, dom_node: &DomNodeRef) {
+        for hash in node_hashes(dom_node, self.quirks) {
+            for counter in counter_indices(hash) {
+                // A saturated counter stays saturated, since it can't tell
+                // how many hashes set it
+                self.counters[counter] = self.counters[counter].saturating_add(1);
+            }
+        }
+    }
+
+    /// Removes what push() added for dom_node, after styling its descendants.
+    pub fn pop(&mut self, dom_node: &DomNodeRef) {
+        for hash in node_hashes(dom_node, self.quirks) {
+            for counter in counter_indices(hash) {
+                debug_assert!(self.counters[counter] > 0, "popped a node that wasn't pushed");
+                if self.counters[counter] != u8::MAX {
+                    self.counters[counter] -= 1;
+                }
+            }
+        }
+    }
+
+    /// Returns false if no ancestor has the element type, id or class hash
+    /// was computed from.
+    pub fn might_contain(&self, hash: u32) -> bool {
+        counter_indices(hash).iter().all(|&counter| self.counters[counter] > 0)
+    }
+
+    /// Returns false if selector can't match a node whose ancestors are the
+    /// ones in the filter.
+    pub fn might_match(&self, hashes: &AncestorHashes) -> bool {
+        // A selector list matches if any of its selectors does
+        hashes
+            .0
+            .iter()
+            .any(|branch| branch.iter().all(|&hash| self.might_contain(hash)))
+    }
+}
+
+/// Hashes of the element types, ids and classes that a selector requires of
+/// the ancestors of the nodes it matches, for each selector in its list.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AncestorHashes(Vec<Vec<u32>>);
+
+impl AncestorHashes {
+    pub fn new(selector: &Selector, quirks: bool) -> AncestorHashes {
+        match *selector {
+            Selector::Group(ref sels) => AncestorHashes(
+                sels.iter()
+                    .map(|sel| complex_selector_hashes(sel, quirks))
+                    .collect(),
+            ),
+            _ => AncestorHashes(vec![complex_selector_hashes(selector, quirks)]),
+        }
+    }
+}
+
+fn complex_selector_hashes(selector: &Selector, quirks: bool) -> Vec<u32> {
+    let (mut compounds, mut combinators) = (vec![], vec![]);
+    flatten_complex_selector(selector, &mut compounds, &mut combinators);
+    let mut hashes = vec![];
+    // Right to left: once past a child or descendant combinator, every
+    // compound is of an ancestor, since siblings have the same ones
+    let mut ancestors = false;
+    for (compound, combinator) in compounds.iter().rev().skip(1).zip(combinators.iter().rev()) {
+        ancestors |= matches!(combinator, Combinator::Child | Combinator::Descendant);
+        if !ancestors {
+            continue;
+        }
+        let sels = match **compound {
+            Selector::Seq(ref sels) => sels.iter().collect::<Vec<_>>(),
+            _ => vec![*compound],
+        };
+        // Pseudo-classes and attribute selectors aren't in the filter
+        for sel in sels {
+            if let Selector::Simple(ref simple) = *sel {
+                if let Some(ref elem_type) = simple.elem_type {
+                    hashes.push(type_hash(elem_type));
+                }
+                if let Some(ref id) = simple.id {
+                    hashes.push(id_hash(id, quirks));
+                }
+                hashes.extend(simple.classes.iter().map(|class| class_hash(class, quirks)));
+            }
+        }
+    }
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
+
+fn node_hashes(dom_node: &DomNodeRef, quirks: bool) -> Vec<u32> {
+    let node = dom_node.borrow();
+    if !node.elem_type.is_element() {
+        return vec![];
+    }
+    let mut hashes = vec![type_hash(&node.elem_type)];
+    if let Some(ref id) = node.id {
+        hashes.push(id_hash(id, quirks));
+    }
+    hashes.extend(node.classes.iter().map(|class| class_hash(class, quirks)));
+    hashes
+}
+
+fn counter_indices(hash: u32) -> [usize; 2] {
+    [(hash & KEY_MASK) as usize, ((hash >> KEY_BITS) & KEY_MASK) as usize]
+}
+
+fn type_hash(elem_type: &ElemType) -> u32 {
+    hash(0, elem_type.tag_name())
+}
+
+fn id_hash(id: &str, quirks: bool) -> u32 {
+    if quirks {
+        hash(1, &id.to_ascii_lowercase())
+    } else {
+        hash(1, id)
+    }
+}
+
+fn class_hash(class: &str, quirks: bool) -> u32 {
+    if quirks {
+        hash(2, &class.to_ascii_lowercase())
+    } else {
+        hash(2, class)
+    }
+}
+
+// kind keeps e.g. the id `p` from colliding with the element type `p`
+fn hash(kind: u8, name: &str) -> u32 {
+    let mut hasher = DefaultHasher::new();
+    (kind, name).hash(&mut hasher);
+    hasher.finish() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, parse_stylesheet};
+
+    #[test]
+    fn test_ancestor_filter() {
+        let root = parse_html(
+            "<html><body id=\"Main\" class=\"menu\"><div><p>One</p></div></body></html>",
+        ).unwrap();
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let selectors = parse_stylesheet(
+            "p { } body p { } .menu > div p { } #main p { } div + p { } \
+             div ~ .x > p { } html > .x ~ p { } .other p, body p { } .other p, #x p { } \
+             :not(.x) p { } body[id] p { }",
+        ).unwrap().rules.into_iter().map(|rule| rule.selector).collect::<Vec<_>>();
+
+        let mut filter = AncestorFilter::new(false);
+        filter.push(&root);
+        filter.push(&body);
+        filter.push(&div);
+        let might_match = |filter: &AncestorFilter, quirks: bool| {
+            selectors
+                .iter()
+                .map(|selector| filter.might_match(&AncestorHashes::new(selector, quirks)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            might_match(&filter, false),
+            vec![true, true, true, false, true, false, true, true, false, true, true]
+        );
+
+        filter.pop(&div);
+        filter.pop(&body);
+        assert!(filter.might_contain(type_hash(&ElemType::Html)));
+        assert!(!filter.might_contain(type_hash(&ElemType::Body)));
+        filter.pop(&root);
+        assert!(filter.counters.iter().all(|&counter| counter == 0));
+
+        let mut filter = AncestorFilter::new(true);
+        filter.push(&root);
+        filter.push(&body);
+        filter.push(&div);
+        assert!(might_match(&filter, true)[3]);
+    }
+}
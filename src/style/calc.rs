@@ -0,0 +1,326 @@
+use style::length::{Length, LengthContext, LengthUnit};
+
+/// A parsed calc() expression, e.g. `calc(100% - 2 * 1em)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcNode {
+    Number(f32),
+    Dimension(Length),
+    Percentage(f32),
+    Sum(Box<CalcNode>, Box<CalcNode>),
+    Difference(Box<CalcNode>, Box<CalcNode>),
+    Product(Box<CalcNode>, Box<CalcNode>),
+    Quotient(Box<CalcNode>, Box<CalcNode>),
+}
+
+/// The result of evaluating a calc() expression. Percentages can't be resolved
+/// until layout knows what they're a percentage of, so they're kept apart from
+/// the rest of the length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalcValue {
+    Number(f32),
+    Length { px: f32, percentage: f32 },
+}
+
+impl CalcValue {
+    /// Returns the length in px, given the length percentages are relative
+    /// to. Returns None for numbers.
+    pub fn to_px(&self, percentage_basis: f32) -> Option<f32> {
+        match *self {
+            CalcValue::Number(_) => None,
+            CalcValue::Length { px, percentage } => {
+                Some(px + percentage_basis * percentage / 100.0)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CalcToken {
+    Number(f32),
+    Dimension(f32, String),
+    Percentage(f32),
+    Op(char),
+    OpenParen,
+    CloseParen,
+}
+
+fn tokenize(input: &str) -> Option<Vec<CalcToken>> {
+    let chars = input.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        // A sign is part of the number unless the previous token was a value
+        let follows_value = match tokens.last() {
+            Some(CalcToken::Op(_)) | Some(CalcToken::OpenParen) | None => false,
+            Some(_) => true,
+        };
+        let starts_number = ch.is_ascii_digit()
+            || ch == '.'
+            || ((ch == '+' || ch == '-') && !follows_value
+                && chars
+                    .get(i + 1)
+                    .is_some_and(|next| next.is_ascii_digit() || *next == '.'));
+        if ch.is_whitespace() {
+            i += 1;
+        } else if starts_number {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number = chars[start..i].iter().collect::<String>().parse::<f32>().ok()?;
+            if chars.get(i) == Some(&'%') {
+                i += 1;
+                tokens.push(CalcToken::Percentage(number));
+            } else {
+                let unit_start = i;
+                while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                    i += 1;
+                }
+                if unit_start == i {
+                    tokens.push(CalcToken::Number(number));
+                } else {
+                    let unit = chars[unit_start..i].iter().collect::<String>();
+                    tokens.push(CalcToken::Dimension(number, unit));
+                }
+            }
+        } else if ch == '+' || ch == '-' || ch == '*' || ch == '/' {
+            i += 1;
+            tokens.push(CalcToken::Op(ch));
+        } else if ch == '(' {
+            i += 1;
+            tokens.push(CalcToken::OpenParen);
+        } else if ch == ')' {
+            i += 1;
+            tokens.push(CalcToken::CloseParen);
+        } else if chars[i..].iter().take(5).collect::<String>().eq_ignore_ascii_case("calc(") {
+            // Nested calc() is the same as parentheses
+            i += 5;
+            tokens.push(CalcToken::OpenParen);
+        } else {
+            return None;
+        }
+    }
+    Some(tokens)
+}
+
+struct CalcParser {
+    tokens: Vec<CalcToken>,
+    pos: usize,
+}
+
+impl CalcParser {
+    fn peek(&self) -> Option<&CalcToken> {
+        self.tokens.get(self.pos)
+    }
+
+    fn consume(&mut self) -> Option<CalcToken> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn parse_sum(&mut self) -> Option<CalcNode> {
+        let mut node = self.parse_product()?;
+        while let Some(&CalcToken::Op(op)) = self.peek() {
+            if op != '+' && op != '-' {
+                break;
+            }
+            self.consume();
+            let rhs = Box::new(self.parse_product()?);
+            node = if op == '+' {
+                CalcNode::Sum(Box::new(node), rhs)
+            } else {
+                CalcNode::Difference(Box::new(node), rhs)
+            };
+        }
+        Some(node)
+    }
+
+    // product := value (('*' | '/') value)*
+    fn parse_product(&mut self) -> Option<CalcNode> {
+        let mut node = self.parse_value()?;
+        while let Some(&CalcToken::Op(op)) = self.peek() {
+            if op != '*' && op != '/' {
+                break;
+            }
+            self.consume();
+            let rhs = Box::new(self.parse_value()?);
+            node = if op == '*' {
+                CalcNode::Product(Box::new(node), rhs)
+            } else {
+                CalcNode::Quotient(Box::new(node), rhs)
+            };
+        }
+        Some(node)
+    }
+
+    // value := number | dimension | percentage | '(' sum ')'
+    fn parse_value(&mut self) -> Option<CalcNode> {
+        match self.consume()? {
+            CalcToken::Number(number) => Some(CalcNode::Number(number)),
+            CalcToken::Dimension(number, unit) => {
+                Some(CalcNode::Dimension(Length::new(number, LengthUnit::parse(&unit)?)))
+            }
+            CalcToken::Percentage(number) => Some(CalcNode::Percentage(number)),
+            CalcToken::OpenParen => {
+                let node = self.parse_sum()?;
+                match self.consume()? {
+                    CalcToken::CloseParen => Some(node),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses `calc(...)`. Returns None if value isn't a calc() expression, has
+/// a syntax error, or uses an unknown unit.
+pub fn parse_calc(value: &str) -> Option<CalcNode> {
+    let value = value.trim();
+    if !value.get(..5).is_some_and(|start| start.eq_ignore_ascii_case("calc(")) {
+        return None;
+    }
+    let mut parser = CalcParser {
+        tokens: tokenize(value)?,
+        pos: 0,
+    };
+    let node = parser.parse_value()?;
+    if parser.peek().is_some() {
+        return None;
+    }
+    Some(node)
+}
+
+impl CalcNode {
+    /// Returns None if the expression mixes numbers and lengths, e.g.
+    /// `1px + 2`, multiplies two lengths, or divides by a length or zero.
+    pub fn evaluate(&self, context: &LengthContext) -> Option<CalcValue> {
+        use self::CalcValue::{Length, Number};
+        Some(match *self {
+            CalcNode::Number(number) => Number(number),
+            CalcNode::Dimension(ref length) => Length {
+                px: length.to_px(context),
+                percentage: 0.0,
+            },
+            CalcNode::Percentage(percentage) => Length {
+                px: 0.0,
+                percentage,
+            },
+            CalcNode::Sum(ref lhs, ref rhs) | CalcNode::Difference(ref lhs, ref rhs) => {
+                let sign = if let CalcNode::Sum(..) = *self { 1.0 } else { -1.0 };
+                match (lhs.evaluate(context)?, rhs.evaluate(context)?) {
+                    (Number(a), Number(b)) => Number(a + sign * b),
+                    (Length { px: a, percentage: a_pct }, Length { px: b, percentage: b_pct }) => {
+                        Length {
+                            px: a + sign * b,
+                            percentage: a_pct + sign * b_pct,
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            CalcNode::Product(ref lhs, ref rhs) => {
+                match (lhs.evaluate(context)?, rhs.evaluate(context)?) {
+                    (Number(a), Number(b)) => Number(a * b),
+                    (Number(a), Length { px, percentage })
+                    | (Length { px, percentage }, Number(a)) => Length {
+                        px: a * px,
+                        percentage: a * percentage,
+                    },
+                    _ => return None,
+                }
+            }
+            CalcNode::Quotient(ref lhs, ref rhs) => {
+                let divisor = match rhs.evaluate(context)? {
+                    Number(divisor) if divisor != 0.0 => divisor,
+                    _ => return None,
+                };
+                match lhs.evaluate(context)? {
+                    Number(a) => Number(a / divisor),
+                    Length { px, percentage } => Length {
+                        px: px / divisor,
+                        percentage: percentage / divisor,
+                    },
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(value: &str) -> Option<CalcValue> {
+        let context = LengthContext {
+            font_size: 20.0,
+            root_font_size: 10.0,
+            ..LengthContext::default()
+        };
+        parse_calc(value).and_then(|node| node.evaluate(&context))
+    }
+
+    #[test]
+    fn test_parse_calc() {
+        assert_eq!(
+            parse_calc("calc(100% - 2 * 1.5EM)"),
+            Some(CalcNode::Difference(
+                Box::new(CalcNode::Percentage(100.0)),
+                Box::new(CalcNode::Product(
+                    Box::new(CalcNode::Number(2.0)),
+                    Box::new(CalcNode::Dimension(Length::new(1.5, LengthUnit::Em))),
+                )),
+            ))
+        );
+        assert_eq!(
+            parse_calc("calc((-1px + calc(2px)))"),
+            Some(CalcNode::Sum(
+                Box::new(CalcNode::Dimension(Length::px(-1.0))),
+                Box::new(CalcNode::Dimension(Length::px(2.0))),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_calc_fail() {
+        assert_eq!(parse_calc("1px"), None);
+        assert_eq!(parse_calc("calc(1px"), None);
+        assert_eq!(parse_calc("calc(1px +)"), None);
+        assert_eq!(parse_calc("calc(1px) 2px"), None);
+        assert_eq!(parse_calc("calc(1px $ 2px)"), None);
+        assert_eq!(parse_calc("calc(1furlong)"), None);
+    }
+
+    #[test]
+    fn test_evaluate_calc() {
+        assert_eq!(eval("calc(1 + 2 * 3)"), Some(CalcValue::Number(7.0)));
+        assert_eq!(
+            eval("calc(1in + 2em - 1rem)"),
+            Some(CalcValue::Length {
+                px: 96.0 + 40.0 - 10.0,
+                percentage: 0.0,
+            })
+        );
+        assert_eq!(
+            eval("calc((50% - 10px) / 2)"),
+            Some(CalcValue::Length {
+                px: -5.0,
+                percentage: 25.0,
+            })
+        );
+        assert_eq!(eval("calc(50% + 1ex)").and_then(|value| value.to_px(200.0)), Some(110.0));
+        assert_eq!(eval("calc(1 + 1)").and_then(|value| value.to_px(200.0)), None);
+    }
+
+    #[test]
+    fn test_evaluate_calc_fail() {
+        assert_eq!(eval("calc(1px + 1)"), None);
+        assert_eq!(eval("calc(1px * 1px)"), None);
+        assert_eq!(eval("calc(1px / 1px)"), None);
+        assert_eq!(eval("calc(1px / 0)"), None);
+    }
+}
@@ -0,0 +1,469 @@
+use magicparser::{Declaration, Specificity, Stylesheet};
+use std::collections::HashMap;
+use style::declaration::expand_declaration;
+use style::properties::{is_reset_by_all, CssWideKeyword, PROPERTIES};
+use style::resolver::ComputedStyle;
+
+/// Where a stylesheet comes from. Later origins take precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
+}
+
+/// The tree a declaration's stylesheet is scoped to, relative to the tree of
+/// the node it applies to. Normal declarations from outer trees win over
+/// those from inner ones, and important ones lose to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum TreeContext {
+    // The node's own shadow tree, applying to the node as its host through
+    // :host
+    ShadowTree,
+    // The node's tree
+    #[default]
+    Tree,
+    // The tree of the host whose shadow tree the node is in, applying to the
+    // node as one of the host's parts through ::part()
+    HostTree,
+}
+
+/// A declaration that applies to a DOM node, along with what the cascade needs
+/// to rank it against other declarations of the same property.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CascadedDeclaration<'a> {
+    pub declaration: &'a Declaration,
+    pub origin: Origin,
+    // True if the declaration is from the node's style attribute
+    pub inline: bool,
+    // Rank of the declaration's cascade layer within its origin, from
+    // LayerOrder::rank(). Unlayered declarations rank above every layer.
+    pub layer: usize,
+    // Of the selector that matched the node
+    pub specificity: Specificity,
+    // Position of the declaration among all declarations being cascaded
+    pub source_order: usize,
+    // Of the stylesheet the declaration is from
+    pub context: TreeContext,
+}
+
+impl<'a> CascadedDeclaration<'a> {
+    pub fn new(
+        declaration: &'a Declaration,
+        origin: Origin,
+        specificity: Specificity,
+        source_order: usize,
+    ) -> CascadedDeclaration<'a> {
+        Self::new_with_layer(declaration, origin, UNLAYERED, specificity, source_order)
+    }
+
+    pub fn new_with_layer(
+        declaration: &'a Declaration,
+        origin: Origin,
+        layer: usize,
+        specificity: Specificity,
+        source_order: usize,
+    ) -> CascadedDeclaration<'a> {
+        CascadedDeclaration {
+            declaration,
+            origin,
+            inline: false,
+            layer,
+            specificity,
+            source_order,
+            context: TreeContext::Tree,
+        }
+    }
+
+    /// Declarations from a style attribute are author declarations, and have
+    /// no selector.
+    pub fn new_inline(
+        declaration: &'a Declaration,
+        source_order: usize,
+    ) -> CascadedDeclaration<'a> {
+        CascadedDeclaration {
+            inline: true,
+            ..Self::new(declaration, Origin::Author, Specificity::default(), source_order)
+        }
+    }
+
+    // Important declarations reverse the order of origins, so that users (and
+    // the user agent) can override authors, of layers, so that earlier layers
+    // can override later ones, and of tree contexts, so that a shadow tree
+    // can override its host's tree.
+    fn precedence(&self) -> (u8, u8, bool, usize, Specificity, usize) {
+        let level = match (self.declaration.important, self.origin) {
+            (false, Origin::UserAgent) => 0,
+            (false, Origin::User) => 1,
+            (false, Origin::Author) => 2,
+            (true, Origin::Author) => 3,
+            (true, Origin::User) => 4,
+            (true, Origin::UserAgent) => 5,
+        };
+        let layer = if self.declaration.important {
+            UNLAYERED - self.layer
+        } else {
+            self.layer
+        };
+        let context = if self.declaration.important {
+            TreeContext::HostTree as u8 - self.context as u8
+        } else {
+            self.context as u8
+        };
+        (level, context, self.inline, layer, self.specificity, self.source_order)
+    }
+}
+
+/// The rank of unlayered declarations.
+pub const UNLAYERED: usize = usize::MAX;
+
+/// The order of the cascade layers of an origin's stylesheets, which rank
+/// the layers' declarations.
+///
+/// Layers with the same name in different stylesheets are the same layer,
+/// ordered where it's first declared. Declarations in later layers take
+/// precedence over those in earlier ones, and declarations directly in a
+/// layer over those in the layers nested in it, as unlayered declarations do
+/// over layered ones.
+#[derive(Debug, Clone, Default)]
+pub struct LayerOrder {
+    // Full names, in the order they're first declared
+    names: Vec<String>,
+    ranks: HashMap<String, usize>,
+}
+
+impl LayerOrder {
+    pub fn add_stylesheet(&mut self, stylesheet: &Stylesheet) {
+        for name in &stylesheet.layers {
+            if !self.ranks.contains_key(name) {
+                self.names.push(name.clone());
+                self.ranks.insert(name.clone(), 0);
+            }
+        }
+        // Sorting each name's path of declaration positions, with nested
+        // layers before the layer they're nested in
+        let position = |name: &str| {
+            self.names.iter().position(|other| other == name).unwrap_or_default()
+        };
+        let mut paths = self
+            .names
+            .iter()
+            .map(|name| {
+                let mut path = name
+                    .match_indices('.')
+                    .map(|(i, _)| position(&name[..i]))
+                    .collect::<Vec<_>>();
+                path.push(position(name));
+                path.push(usize::MAX);
+                (path, name.clone())
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        self.ranks = paths
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (_, name))| (name, rank))
+            .collect();
+    }
+
+    /// Returns the rank of a layer, from 0 for the lowest precedence, or
+    /// UNLAYERED for None. Layers that aren't in the order rank as unlayered.
+    pub fn rank(&self, layer: Option<&str>) -> usize {
+        layer.and_then(|layer| self.ranks.get(layer)).map_or(UNLAYERED, |rank| *rank)
+    }
+}
+
+/// Sorts declarations from lowest to highest precedence: by origin and
+/// importance, then shadow tree context, then whether they're inline, then
+/// cascade layer, then specificity, then source order.
+pub fn sort_declarations(declarations: &mut [CascadedDeclaration]) {
+    declarations.sort_by_key(CascadedDeclaration::precedence);
+}
+
+/// Applies declarations in cascade order, so for each property the
+/// declaration with the highest precedence wins.
+///
+/// A winning `revert` is replaced by the value the property would have if its
+/// origin had no declarations for it. If no origin is left, the property is
+/// left out, same as `unset`. Other CSS-wide keywords are kept, since they
+/// depend on the parent's style.
+///
+/// `all` sets every supported property but direction to its CSS-wide keyword,
+/// and unsets the unsupported ones it beats. Other shorthands, like `margin`,
+/// set their longhands, which cascade like any other declaration of them,
+/// and aren't in the style themselves.
+pub fn cascade(declarations: Vec<CascadedDeclaration>) -> ComputedStyle {
+    cascade_with_winners(declarations).0
+}
+
+/// Same as cascade(), but also returns the declarations that took effect, by
+/// setting or reverting at least one property. The others were overridden.
+pub fn cascade_with_winners<'a>(
+    mut declarations: Vec<CascadedDeclaration<'a>>,
+) -> (ComputedStyle, Vec<&'a Declaration>) {
+    sort_declarations(&mut declarations);
+    let mut winners = vec![];
+    let mut style = ComputedStyle::default();
+    // Property -> declarations from this origin or later are ignored
+    let mut reverted: HashMap<&str, Origin> = HashMap::new();
+    // Same for the properties `all: revert` sets, except those in reverted
+    let mut all_reverted: Option<Origin> = None;
+    // After `all` sets a value, the properties it sets take no more values
+    let mut all_applied = false;
+    for CascadedDeclaration { declaration, origin, .. } in declarations.into_iter().rev() {
+        let property = declaration.property.as_str();
+        let is_revert = CssWideKeyword::parse(&declaration.value) == Some(CssWideKeyword::Revert);
+        let is_reverted =
+            |property: &str| reverted.get(property).is_some_and(|reverted| origin >= *reverted);
+        let reset = all_applied || all_reverted.is_some_and(|reverted| origin >= reverted);
+        if property == "all" {
+            if reset {
+                continue;
+            }
+            winners.push(declaration);
+            if is_revert {
+                all_reverted = Some(origin);
+                continue;
+            }
+            // Properties that aren't supported have no known initial value, so
+            // all leaves them unset
+            for property in PROPERTIES {
+                if is_reset_by_all(property.name)
+                    && style.get(property.name).is_none()
+                    && !is_reverted(property.name)
+                {
+                    style.set(property.name, &declaration.value);
+                }
+            }
+            all_applied = true;
+            continue;
+        }
+        let mut applied = false;
+        for (property, value) in expand_declaration(property, &declaration.value) {
+            let is_reverted =
+                reverted.get(property).is_some_and(|reverted| origin >= *reverted);
+            if style.get(property).is_some()
+                || is_reverted
+                || (reset && is_reset_by_all(property))
+            {
+                continue;
+            }
+            applied = true;
+            if is_revert {
+                reverted.insert(property, origin);
+            } else {
+                style.set(property, &value);
+            }
+        }
+        if applied {
+            winners.push(declaration);
+        }
+    }
+    (style, winners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_stylesheet;
+
+    #[test]
+    fn test_cascade() {
+        let decl = |value: &str| Declaration::new("color".to_string(), value.to_string());
+        let (red, green, blue, black) = (decl("red"), decl("green"), decl("blue"), decl("black"));
+        // Specificity beats source order
+        let style = cascade(vec![
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 1, 0), 0),
+            CascadedDeclaration::new(&green, Origin::Author, Specificity(0, 0, 1), 1),
+        ]);
+        assert_eq!(style.get("color"), Some("red"));
+        // Source order breaks ties
+        let style = cascade(vec![
+            CascadedDeclaration::new(&green, Origin::Author, Specificity(0, 1, 0), 1),
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 1, 0), 0),
+        ]);
+        assert_eq!(style.get("color"), Some("green"));
+        // Origin beats specificity
+        let style = cascade(vec![
+            CascadedDeclaration::new(&blue, Origin::Author, Specificity(0, 0, 1), 2),
+            CascadedDeclaration::new(&black, Origin::User, Specificity(1, 0, 0), 3),
+            CascadedDeclaration::new(&red, Origin::UserAgent, Specificity(1, 0, 0), 4),
+        ]);
+        assert_eq!(style.get("color"), Some("blue"));
+    }
+
+    #[test]
+    fn test_cascade_important() {
+        let decl = |value: &str, important: bool| {
+            Declaration::new_with_importance("color".to_string(), value.to_string(), important)
+        };
+        let (red, green, blue) = (decl("red", false), decl("green", true), decl("blue", false));
+        // Author vs. author: importance beats specificity and source order
+        let style = cascade(vec![
+            CascadedDeclaration::new(&green, Origin::Author, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(1, 0, 0), 1),
+        ]);
+        assert_eq!(style.get("color"), Some("green"));
+        // Inline vs. rule: inline beats any selector, unless the rule is important
+        let style = cascade(vec![
+            CascadedDeclaration::new(&blue, Origin::Author, Specificity(1, 0, 0), 1),
+            CascadedDeclaration::new_inline(&red, 0),
+        ]);
+        assert_eq!(style.get("color"), Some("red"));
+        let style = cascade(vec![
+            CascadedDeclaration::new_inline(&red, 0),
+            CascadedDeclaration::new(&green, Origin::Author, Specificity(0, 0, 1), 1),
+        ]);
+        assert_eq!(style.get("color"), Some("green"));
+        let important_red = decl("red", true);
+        let style = cascade(vec![
+            CascadedDeclaration::new_inline(&important_red, 0),
+            CascadedDeclaration::new(&green, Origin::Author, Specificity(1, 0, 0), 1),
+        ]);
+        assert_eq!(style.get("color"), Some("red"));
+        // Important user declarations beat important author ones
+        let user_blue = decl("blue", true);
+        let style = cascade(vec![
+            CascadedDeclaration::new(&user_blue, Origin::User, Specificity(0, 0, 0), 0),
+            CascadedDeclaration::new_inline(&important_red, 1),
+        ]);
+        assert_eq!(style.get("color"), Some("blue"));
+    }
+
+    #[test]
+    fn test_cascade_revert() {
+        let decl = |value: &str, important: bool| {
+            Declaration::new_with_importance("color".to_string(), value.to_string(), important)
+        };
+        let (red, green, blue) = (decl("red", false), decl("green", false), decl("blue", false));
+        let (revert, important_revert) = (decl("revert", false), decl("REVERT", true));
+        // Author revert falls back to the user's value
+        let style = cascade(vec![
+            CascadedDeclaration::new(&red, Origin::UserAgent, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&green, Origin::User, Specificity(0, 0, 1), 1),
+            CascadedDeclaration::new(&blue, Origin::Author, Specificity(0, 0, 1), 2),
+            CascadedDeclaration::new(&revert, Origin::Author, Specificity(0, 1, 0), 3),
+        ]);
+        assert_eq!(style.get("color"), Some("green"));
+        // Reverting the user origin too falls back to the user agent's value
+        let style = cascade(vec![
+            CascadedDeclaration::new(&red, Origin::UserAgent, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&revert, Origin::User, Specificity(0, 0, 1), 1),
+            CascadedDeclaration::new(&important_revert, Origin::Author, Specificity(0, 0, 1), 2),
+        ]);
+        assert_eq!(style.get("color"), Some("red"));
+        // Nothing to revert to
+        let style = cascade(vec![
+            CascadedDeclaration::new(&revert, Origin::UserAgent, Specificity(0, 0, 1), 0),
+        ]);
+        assert_eq!(style.get("color"), None);
+        // Other keywords are left for the resolver
+        let inherit = decl("inherit", false);
+        let style = cascade(vec![
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&inherit, Origin::Author, Specificity(0, 0, 1), 1),
+        ]);
+        assert_eq!(style.get("color"), Some("inherit"));
+    }
+
+    #[test]
+    fn test_cascade_all() {
+        let decl = |property: &str, value: &str| {
+            Declaration::new(property.to_string(), value.to_string())
+        };
+        let (red, opacity, direction) =
+            (decl("color", "red"), decl("opacity", "0.5"), decl("direction", "rtl"));
+        let (inherit, revert, width) =
+            (decl("all", "inherit"), decl("all", "revert"), decl("width", "1px"));
+        let style = cascade(vec![
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&opacity, Origin::Author, Specificity(0, 0, 1), 1),
+            CascadedDeclaration::new(&direction, Origin::Author, Specificity(0, 0, 1), 2),
+            CascadedDeclaration::new(&inherit, Origin::Author, Specificity(0, 1, 0), 3),
+            CascadedDeclaration::new(&width, Origin::Author, Specificity(1, 0, 0), 4),
+        ]);
+        assert_eq!(style.get("color"), Some("inherit"));
+        assert_eq!(style.get("display"), Some("inherit"));
+        assert_eq!(style.get("width"), Some("1px"));
+        // Not set by all
+        assert_eq!(style.get("direction"), Some("rtl"));
+        // Unsupported, so unset
+        assert_eq!(style.get("opacity"), None);
+
+        let style = cascade(vec![
+            CascadedDeclaration::new(&red, Origin::UserAgent, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&width, Origin::Author, Specificity(0, 0, 1), 1),
+            CascadedDeclaration::new(&revert, Origin::Author, Specificity(0, 0, 1), 2),
+        ]);
+        assert_eq!(style.get("color"), Some("red"));
+        assert_eq!(style.get("width"), None);
+    }
+
+    #[test]
+    fn test_cascade_with_winners() {
+        let decl = |property: &str, value: &str| {
+            Declaration::new(property.to_string(), value.to_string())
+        };
+        let (red, blue) = (decl("color", "red"), decl("color", "blue"));
+        let (width, all) = (decl("width", "1px"), decl("all", "unset"));
+        let (style, winners) = cascade_with_winners(vec![
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&all, Origin::Author, Specificity(0, 0, 1), 1),
+            CascadedDeclaration::new(&blue, Origin::Author, Specificity(0, 1, 0), 2),
+            CascadedDeclaration::new(&width, Origin::UserAgent, Specificity(0, 0, 1), 3),
+        ]);
+        assert_eq!(style.get("color"), Some("blue"));
+        assert!(winners.iter().any(|winner| std::ptr::eq(*winner, &blue)));
+        assert!(winners.iter().any(|winner| std::ptr::eq(*winner, &all)));
+        assert!(!winners.iter().any(|winner| std::ptr::eq(*winner, &red)));
+        assert!(!winners.iter().any(|winner| std::ptr::eq(*winner, &width)));
+    }
+
+    #[test]
+    fn test_layer_order() {
+        let mut order = LayerOrder::default();
+        order.add_stylesheet(&parse_stylesheet("@layer a, b; @layer a.x { } @layer c;").unwrap());
+        order.add_stylesheet(&parse_stylesheet("@layer c, d, a.y;").unwrap());
+        let ranks = ["a.x", "a.y", "a", "b", "c", "d"]
+            .iter()
+            .map(|layer| order.rank(Some(layer)))
+            .collect::<Vec<_>>();
+        assert_eq!(ranks, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(order.rank(None), UNLAYERED);
+        assert_eq!(order.rank(Some("missing")), UNLAYERED);
+    }
+
+    #[test]
+    fn test_cascade_layers() {
+        let decl = |value: &str, important: bool| {
+            Declaration::new_with_importance("color".to_string(), value.to_string(), important)
+        };
+        let (red, green, blue) = (decl("red", false), decl("green", false), decl("blue", false));
+        // Later layers beat earlier ones and unlayered declarations beat both,
+        // whatever their specificity
+        let style = cascade(vec![
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new_with_layer(&green, Origin::Author, 1, Specificity(1, 0, 0), 1),
+            CascadedDeclaration::new_with_layer(&blue, Origin::Author, 0, Specificity(1, 0, 0), 2),
+        ]);
+        assert_eq!(style.get("color"), Some("red"));
+        let style = cascade(vec![
+            CascadedDeclaration::new_with_layer(&green, Origin::Author, 1, Specificity(0, 0, 1), 1),
+            CascadedDeclaration::new_with_layer(&blue, Origin::Author, 0, Specificity(1, 0, 0), 2),
+        ]);
+        assert_eq!(style.get("color"), Some("green"));
+        // Important declarations reverse the order
+        let (important_red, important_green) = (decl("red", true), decl("green", true));
+        let style = cascade(vec![
+            CascadedDeclaration::new(&important_red, Origin::Author, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new_with_layer(
+                &important_green,
+                Origin::Author,
+                0,
+                Specificity(0, 0, 1),
+                1,
+            ),
+        ]);
+        assert_eq!(style.get("color"), Some("green"));
+    }
+}
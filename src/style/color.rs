@@ -0,0 +1,411 @@
+use std::fmt;
+
+/// A color in the sRGB color space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgba {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    // Between 0 (transparent) and 1 (opaque)
+    pub alpha: f32,
+}
+
+impl Rgba {
+    pub fn new(red: u8, green: u8, blue: u8, alpha: f32) -> Rgba {
+        Rgba {
+            red,
+            green,
+            blue,
+            alpha,
+        }
+    }
+
+    pub fn opaque(red: u8, green: u8, blue: u8) -> Rgba {
+        Rgba::new(red, green, blue, 1.0)
+    }
+
+    pub fn transparent() -> Rgba {
+        Rgba::new(0, 0, 0, 0.0)
+    }
+
+    // rgb is 0xRRGGBB
+    fn from_rgb_hex(rgb: u32) -> Rgba {
+        Rgba::opaque((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
+}
+
+/// Serializes like browsers do for computed values, e.g. `rgb(255, 0, 0)` or
+/// `rgba(0, 0, 0, 0.5)`.
+impl fmt::Display for Rgba {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.alpha == 1.0 {
+            write!(f, "rgb({}, {}, {})", self.red, self.green, self.blue)
+        } else {
+            write!(f, "rgba({}, {}, {}, {})", self.red, self.green, self.blue, self.alpha)
+        }
+    }
+}
+
+/// A specified color value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Rgba(Rgba),
+    // The element's color property
+    CurrentColor,
+}
+
+impl Color {
+    /// Parses `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `rgb()`, `rgba()`,
+    /// `hsl()`, `hsla()`, named colors, `transparent` and `currentColor`.
+    /// Returns None for anything else.
+    pub fn parse(value: &str) -> Option<Color> {
+        let value = value.trim().to_ascii_lowercase();
+        if value == "currentcolor" {
+            return Some(Color::CurrentColor);
+        }
+        if value == "transparent" {
+            return Some(Color::Rgba(Rgba::transparent()));
+        }
+        if let Some(hex) = value.strip_prefix('#') {
+            return parse_hex(hex).map(Color::Rgba);
+        }
+        if let Some(paren) = value.find('(') {
+            let args = value[paren + 1..].strip_suffix(')')?;
+            let rgba = match &value[..paren] {
+                "rgb" | "rgba" => parse_rgb_args(args)?,
+                "hsl" | "hsla" => parse_hsl_args(args)?,
+                _ => return None,
+            };
+            return Some(Color::Rgba(rgba));
+        }
+        NAMED_COLORS
+            .binary_search_by_key(&value.as_str(), |&(name, _)| name)
+            .ok()
+            .map(|i| Color::Rgba(Rgba::from_rgb_hex(NAMED_COLORS[i].1)))
+    }
+
+    /// current_color is the element's computed color.
+    pub fn resolve(&self, current_color: Rgba) -> Rgba {
+        match *self {
+            Color::Rgba(rgba) => rgba,
+            Color::CurrentColor => current_color,
+        }
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Rgba> {
+    if !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    match hex.len() {
+        3 | 4 => {
+            let alpha = if hex.len() == 4 { digit(3) * 17 } else { 255 };
+            Some(Rgba::new(digit(0) * 17, digit(1) * 17, digit(2) * 17, alpha as f32 / 255.0))
+        }
+        6 | 8 => {
+            let alpha = if hex.len() == 8 { byte(6) } else { 255 };
+            Some(Rgba::new(byte(0), byte(2), byte(4), alpha as f32 / 255.0))
+        }
+        _ => None,
+    }
+}
+
+// Splits the arguments of rgb() or hsl(), which may be separated by commas
+// (with the legacy syntax) or spaces, with a / before the alpha. Returns None
+// if there aren't 3 or 4 of them.
+fn split_args(args: &str) -> Option<Vec<&str>> {
+    let args = if args.contains(',') {
+        args.split(',').map(|arg| arg.trim()).collect::<Vec<_>>()
+    } else {
+        let mut parts = args.splitn(2, '/');
+        let mut args = parts.next()?.split_whitespace().collect::<Vec<_>>();
+        if args.len() != 3 {
+            return None;
+        }
+        if let Some(alpha) = parts.next() {
+            args.push(alpha.trim());
+        }
+        args
+    };
+    if args.len() == 3 || args.len() == 4 {
+        Some(args)
+    } else {
+        None
+    }
+}
+
+fn parse_number(arg: &str) -> Option<f32> {
+    let number = arg.parse::<f32>().ok()?;
+    if number.is_finite() {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+// Returns a fraction between 0 and 1
+fn parse_percentage(arg: &str) -> Option<f32> {
+    Some(parse_number(arg.strip_suffix('%')?)? / 100.0)
+}
+
+// Number or percentage, defaulting to opaque
+fn parse_alpha(arg: Option<&&str>) -> Option<f32> {
+    let alpha = match arg {
+        Some(arg) => parse_percentage(arg).or_else(|| parse_number(arg))?,
+        None => 1.0,
+    };
+    Some(alpha.clamp(0.0, 1.0))
+}
+
+fn to_channel(fraction: f32) -> u8 {
+    (fraction.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn parse_rgb_args(args: &str) -> Option<Rgba> {
+    let args = split_args(args)?;
+    let mut channels = [0; 3];
+    for (channel, arg) in channels.iter_mut().zip(&args) {
+        *channel = match parse_percentage(arg) {
+            Some(fraction) => to_channel(fraction),
+            None => to_channel(parse_number(arg)? / 255.0),
+        };
+    }
+    Some(Rgba::new(channels[0], channels[1], channels[2], parse_alpha(args.get(3))?))
+}
+
+fn parse_hsl_args(args: &str) -> Option<Rgba> {
+    let args = split_args(args)?;
+    let hue = parse_number(args[0].strip_suffix("deg").unwrap_or(args[0]))?;
+    let saturation = parse_percentage(args[1])?.clamp(0.0, 1.0);
+    let lightness = parse_percentage(args[2])?.clamp(0.0, 1.0);
+    let alpha = parse_alpha(args.get(3))?;
+
+    // From https://www.w3.org/TR/css-color-4/#hsl-to-rgb
+    let hue = hue.rem_euclid(360.0);
+    let a = saturation * lightness.min(1.0 - lightness);
+    let channel = |n: f32| {
+        let k = (n + hue / 30.0) % 12.0;
+        to_channel(lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0))
+    };
+    Some(Rgba::new(channel(0.0), channel(8.0), channel(4.0), alpha))
+}
+
+// Sorted by name
+static NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(value: &str) -> Option<Rgba> {
+        Color::parse(value).map(|color| color.resolve(Rgba::transparent()))
+    }
+
+    #[test]
+    fn test_named_colors_sorted() {
+        for pair in NAMED_COLORS.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{} >= {}", pair[0].0, pair[1].0);
+        }
+    }
+
+    #[test]
+    fn test_parse_hex_color() {
+        assert_eq!(rgba("#f00"), Some(Rgba::opaque(255, 0, 0)));
+        assert_eq!(rgba("#F008"), Some(Rgba::new(255, 0, 0, 136.0 / 255.0)));
+        assert_eq!(rgba("#0080ff"), Some(Rgba::opaque(0, 128, 255)));
+        assert_eq!(rgba("#0080ff00"), Some(Rgba::new(0, 128, 255, 0.0)));
+        assert_eq!(rgba("#0080f"), None);
+        assert_eq!(rgba("#ggg"), None);
+    }
+
+    #[test]
+    fn test_parse_rgb_color() {
+        assert_eq!(rgba("rgb(255, 0, 128)"), Some(Rgba::opaque(255, 0, 128)));
+        assert_eq!(rgba("RGBA(100%, 0%, 50%, 0.5)"), Some(Rgba::new(255, 0, 128, 0.5)));
+        assert_eq!(rgba("rgb(300 -1 0 / 50%)"), Some(Rgba::new(255, 0, 0, 0.5)));
+        assert_eq!(rgba("rgba(0, 0, 0)"), Some(Rgba::opaque(0, 0, 0)));
+        assert_eq!(rgba("rgb(0, 0)"), None);
+        assert_eq!(rgba("rgb(0 0 0 0)"), None);
+        assert_eq!(rgba("rgb(red, 0, 0)"), None);
+        assert_eq!(rgba("rgb(0, 0, 0"), None);
+    }
+
+    #[test]
+    fn test_parse_hsl_color() {
+        assert_eq!(rgba("hsl(0, 100%, 50%)"), Some(Rgba::opaque(255, 0, 0)));
+        assert_eq!(rgba("hsl(120deg 100% 25%)"), Some(Rgba::opaque(0, 128, 0)));
+        assert_eq!(rgba("hsla(-120, 100%, 50%, 0.25)"), Some(Rgba::new(0, 0, 255, 0.25)));
+        assert_eq!(rgba("hsl(0, 0%, 100%)"), Some(Rgba::opaque(255, 255, 255)));
+        assert_eq!(rgba("hsl(0, 100, 50)"), None);
+    }
+
+    #[test]
+    fn test_parse_named_color() {
+        assert_eq!(rgba("rebeccapurple"), Some(Rgba::opaque(0x66, 0x33, 0x99)));
+        assert_eq!(rgba("Black"), Some(Rgba::opaque(0, 0, 0)));
+        assert_eq!(rgba("transparent"), Some(Rgba::transparent()));
+        assert_eq!(rgba("notacolor"), None);
+        assert_eq!(Color::parse("currentColor"), Some(Color::CurrentColor));
+        assert_eq!(
+            Color::parse("currentcolor").map(|color| color.resolve(Rgba::opaque(1, 2, 3))),
+            Some(Rgba::opaque(1, 2, 3))
+        );
+    }
+
+    #[test]
+    fn test_rgba_to_string() {
+        assert_eq!(Rgba::opaque(255, 0, 0).to_string(), "rgb(255, 0, 0)");
+        assert_eq!(Rgba::new(0, 0, 0, 0.5).to_string(), "rgba(0, 0, 0, 0.5)");
+    }
+}
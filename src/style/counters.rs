@@ -0,0 +1,474 @@
+use magicparser::{DomNodeRef, PseudoElementSelector};
+use std::collections::HashMap;
+use style::declaration::is_ident;
+use style::resolver::{ComputedStyle, StyleMap, StyleResolver};
+
+/// Parses a counter-reset, counter-set or counter-increment value, e.g.
+/// `chapter section 2`, into (name, value) pairs. Names without a value get
+/// default. Returns None for invalid values.
+pub fn parse_counter_list(value: &str, default: i32) -> Option<Vec<(String, i32)>> {
+    if value.trim().eq_ignore_ascii_case("none") {
+        return Some(vec![]);
+    }
+    let mut counters: Vec<(String, i32)> = vec![];
+    let mut after_name = false;
+    for word in value.split_whitespace() {
+        if let Ok(number) = word.parse::<i32>() {
+            if !after_name {
+                return None;
+            }
+            counters.last_mut().unwrap().1 = number;
+            after_name = false;
+        } else if is_ident(word) && !word.eq_ignore_ascii_case("none") {
+            counters.push((word.to_string(), default));
+            after_name = true;
+        } else {
+            return None;
+        }
+    }
+    if counters.is_empty() {
+        None
+    } else {
+        Some(counters)
+    }
+}
+
+/// Formats a counter's value in a list-style-type counter style, e.g. `iv`
+/// for 4 in lower-roman. Unsupported styles, and values a style can't
+/// represent, fall back to decimal.
+pub fn format_counter(value: i32, style: &str) -> String {
+    let alphabetic = |first: u8| {
+        let mut letters = vec![];
+        let mut value = value;
+        while value > 0 {
+            value -= 1;
+            letters.push((first + (value % 26) as u8) as char);
+            value /= 26;
+        }
+        letters.iter().rev().collect::<String>()
+    };
+    let roman = || {
+        let numerals = [
+            (1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"),
+            (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i"),
+        ];
+        let mut roman = String::new();
+        let mut value = value;
+        for &(numeral_value, numeral) in &numerals {
+            while value >= numeral_value {
+                roman.push_str(numeral);
+                value -= numeral_value;
+            }
+        }
+        roman
+    };
+    match style.to_ascii_lowercase().as_str() {
+        "none" => String::new(),
+        "disc" => "\u{2022}".to_string(),
+        "circle" => "\u{25e6}".to_string(),
+        "square" => "\u{25aa}".to_string(),
+        "decimal-leading-zero" if (0..10).contains(&value) => format!("0{}", value),
+        "lower-alpha" | "lower-latin" if value > 0 => alphabetic(b'a'),
+        "upper-alpha" | "upper-latin" if value > 0 => alphabetic(b'A'),
+        "lower-roman" if (1..4000).contains(&value) => roman(),
+        "upper-roman" if (1..4000).contains(&value) => roman().to_uppercase(),
+        _ => value.to_string(),
+    }
+}
+
+// One component of a content value
+#[derive(Debug, Clone, PartialEq)]
+enum ContentItem {
+    Text(String),
+    // counter(name, style)
+    Counter(String, String),
+    // counters(name, separator, style)
+    Counters(String, String, String),
+    // attr(name)
+    Attr(String),
+}
+
+// Reads a quoted string at the start of value. Returns its contents and the
+// rest of value.
+fn parse_string(value: &str) -> Option<(String, &str)> {
+    let quote = value.chars().next()?;
+    let mut string = String::new();
+    let mut chars = value.char_indices().skip(1);
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => string.push(chars.next()?.1),
+            _ if ch == quote => return Some((string, &value[i + 1..])),
+            _ => string.push(ch),
+        }
+    }
+    None
+}
+
+// Splits the arguments of a function at commas outside of strings
+fn parse_arguments(arguments: &str) -> Option<Vec<String>> {
+    let mut parsed = vec![];
+    let mut rest = arguments.trim();
+    while !rest.is_empty() {
+        let (argument, after) = if rest.starts_with('"') || rest.starts_with('\'') {
+            parse_string(rest)?
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            (rest[..end].trim().to_string(), &rest[end..])
+        };
+        parsed.push(argument);
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(parsed)
+}
+
+// Returns None for invalid values, and an empty list for `normal` and `none`,
+// which generate no content
+fn parse_content(value: &str) -> Option<Vec<ContentItem>> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("normal") || value.eq_ignore_ascii_case("none") {
+        return Some(vec![]);
+    }
+    let mut items = vec![];
+    let mut rest = value;
+    while !rest.is_empty() {
+        if rest.starts_with('"') || rest.starts_with('\'') {
+            let (string, after) = parse_string(rest)?;
+            items.push(ContentItem::Text(string));
+            rest = after.trim_start();
+            continue;
+        }
+        let end = rest.find(|ch: char| ch.is_whitespace() || ch == '(').unwrap_or(rest.len());
+        let name = rest[..end].to_ascii_lowercase();
+        rest = &rest[end..];
+        if let Some(after) = rest.strip_prefix('(') {
+            let close = after.find(')')?;
+            let arguments = parse_arguments(&after[..close])?;
+            let is_name = |i: usize| arguments.get(i).is_some_and(|name| is_ident(name));
+            let style = |i: usize| arguments.get(i).cloned().unwrap_or("decimal".to_string());
+            let item = match (name.as_str(), arguments.len()) {
+                ("counter", 1..=2) if is_name(0) => {
+                    ContentItem::Counter(arguments[0].clone(), style(1))
+                }
+                ("counters", 2..=3) if is_name(0) => {
+                    ContentItem::Counters(arguments[0].clone(), arguments[1].clone(), style(2))
+                }
+                ("attr", 1) if is_name(0) => ContentItem::Attr(arguments[0].to_lowercase()),
+                _ => return None,
+            };
+            items.push(item);
+            rest = &after[close + 1..];
+        } else {
+            let quote = match name.as_str() {
+                "open-quote" => "\u{201c}",
+                "close-quote" => "\u{201d}",
+                "no-open-quote" | "no-close-quote" => "",
+                _ => return None,
+            };
+            items.push(ContentItem::Text(quote.to_string()));
+        }
+        rest = rest.trim_start();
+    }
+    Some(items)
+}
+
+/// Returns true if value is a valid value of the content property.
+pub fn is_valid_content(value: &str) -> bool {
+    parse_content(value).is_some()
+}
+
+// The counters in scope at some point of a walk through a document. Each name
+// can have nested counters, innermost last, each with the depth of the
+// element that created it.
+#[derive(Debug, Default)]
+struct CounterScopes {
+    counters: HashMap<String, Vec<(i32, usize)>>,
+}
+
+impl CounterScopes {
+    // A counter created by a sibling, which the new one replaces, has the
+    // same depth
+    fn reset(&mut self, name: &str, value: i32, depth: usize) {
+        let instances = self.counters.entry(name.to_string()).or_default();
+        match instances.last_mut() {
+            Some(last) if last.1 == depth => last.0 = value,
+            _ => instances.push((value, depth)),
+        }
+    }
+
+    // Counters that aren't in scope are created with a value of 0 first
+    fn update<F: FnOnce(i32) -> i32>(&mut self, name: &str, depth: usize, update: F) {
+        let instances = self.counters.entry(name.to_string()).or_default();
+        if instances.is_empty() {
+            instances.push((0, depth));
+        }
+        let last = instances.last_mut().unwrap();
+        last.0 = update(last.0);
+    }
+
+    // Counters created by descendants of an element at depth go out of scope
+    // after it
+    fn leave(&mut self, depth: usize) {
+        for instances in self.counters.values_mut() {
+            instances.retain(|&(_, created_at)| created_at <= depth);
+        }
+    }
+
+    // Outermost first
+    fn values(&self, name: &str) -> Vec<i32> {
+        match self.counters.get(name) {
+            Some(instances) if !instances.is_empty() => {
+                instances.iter().map(|&(value, _)| value).collect()
+            }
+            _ => vec![0],
+        }
+    }
+
+    fn value(&self, name: &str) -> i32 {
+        *self.values(name).last().unwrap()
+    }
+
+    // Applies the counter properties of an element or pseudo-element at depth:
+    // counter-reset, then counter-increment, then counter-set. List items
+    // increment the list-item counter unless counter-increment mentions it.
+    // The list-item counter of `<ol start>` starts at start.
+    fn apply(&mut self, style: &ComputedStyle, depth: usize, dom_node: Option<&DomNodeRef>) {
+        let list = |property: &str, default: i32| {
+            style
+                .get(property)
+                .and_then(|value| parse_counter_list(value, default))
+                .unwrap_or_default()
+        };
+        let attr = |name: &str| -> Option<i32> {
+            let node = dom_node?.borrow();
+            match node.attr(name) {
+                Some(Some(value)) => value.trim().parse().ok(),
+                _ => None,
+            }
+        };
+        let tag_name = dom_node.map(|node| node.borrow().elem_type.tag_name().to_string());
+        for (name, value) in list("counter-reset", 0) {
+            let start = match attr("start") {
+                Some(start) if name == "list-item" && tag_name.as_deref() == Some("ol") => {
+                    start - 1
+                }
+                _ => value,
+            };
+            self.reset(&name, start, depth);
+        }
+        let mut increments = list("counter-increment", 1);
+        let is_list_item = style.get("display") == Some("list-item");
+        if is_list_item && !increments.iter().any(|(name, _)| name == "list-item") {
+            increments.push(("list-item".to_string(), 1));
+        }
+        for (name, by) in increments {
+            self.update(&name, depth, |value| value.wrapping_add(by));
+        }
+        let mut sets = list("counter-set", 0);
+        if let (Some(value), Some("li")) = (attr("value"), tag_name.as_deref()) {
+            sets.push(("list-item".to_string(), value));
+        }
+        for (name, value) in sets {
+            self.update(&name, depth, |_| value);
+        }
+    }
+
+    // Returns None if content generates nothing
+    fn evaluate(&self, content: &str, dom_node: &DomNodeRef) -> Option<String> {
+        let items = parse_content(content)?;
+        if items.is_empty() {
+            return None;
+        }
+        let mut text = String::new();
+        for item in items {
+            match item {
+                ContentItem::Text(string) => text.push_str(&string),
+                ContentItem::Counter(name, style) => {
+                    text.push_str(&format_counter(self.value(&name), &style))
+                }
+                ContentItem::Counters(name, separator, style) => {
+                    let values = self
+                        .values(&name)
+                        .into_iter()
+                        .map(|value| format_counter(value, &style))
+                        .collect::<Vec<_>>();
+                    text.push_str(&values.join(&separator));
+                }
+                ContentItem::Attr(name) => {
+                    if let Some(Some(value)) = dom_node.borrow().attr(&name) {
+                        text.push_str(value);
+                    }
+                }
+            }
+        }
+        Some(text)
+    }
+}
+
+/// The text of the ::before and ::after pseudo-elements and list item markers
+/// of a document, with counters evaluated.
+#[derive(Debug, Default)]
+pub struct GeneratedContent {
+    // Keyed by DomNode::id_num
+    before: HashMap<usize, String>,
+    after: HashMap<usize, String>,
+    markers: HashMap<usize, String>,
+}
+
+impl GeneratedContent {
+    /// Walks root's tree in document order, evaluating counters as elements'
+    /// and pseudo-elements' counter properties create and update them.
+    pub fn new(resolver: &StyleResolver, root: &DomNodeRef, styles: &StyleMap) -> GeneratedContent {
+        let mut generated = GeneratedContent::default();
+        generated.walk(resolver, root, styles, &mut CounterScopes::default(), 0);
+        generated
+    }
+
+    pub fn before(&self, dom_node: &DomNodeRef) -> Option<&str> {
+        self.before.get(&dom_node.borrow().id_num).map(|text| text.as_str())
+    }
+
+    pub fn after(&self, dom_node: &DomNodeRef) -> Option<&str> {
+        self.after.get(&dom_node.borrow().id_num).map(|text| text.as_str())
+    }
+
+    /// Returns the marker of a list item, e.g. `2. ` or a bullet.
+    pub fn marker(&self, dom_node: &DomNodeRef) -> Option<&str> {
+        self.markers.get(&dom_node.borrow().id_num).map(|text| text.as_str())
+    }
+
+    fn walk(
+        &mut self,
+        resolver: &StyleResolver,
+        dom_node: &DomNodeRef,
+        styles: &StyleMap,
+        scopes: &mut CounterScopes,
+        depth: usize,
+    ) {
+        if !dom_node.borrow().elem_type.is_element() {
+            return;
+        }
+        let style = match styles.get(dom_node) {
+            Some(style) => style,
+            None => return,
+        };
+        let id_num = dom_node.borrow().id_num;
+        scopes.apply(style, depth, Some(dom_node));
+        if style.get("display") == Some("list-item") {
+            let list_style_type = style.get("list-style-type").unwrap_or("disc");
+            let marker = format_counter(scopes.value("list-item"), list_style_type);
+            let marker = match list_style_type {
+                "none" => marker,
+                "disc" | "circle" | "square" => format!("{} ", marker),
+                _ => format!("{}. ", marker),
+            };
+            self.markers.insert(id_num, marker);
+        }
+        // Pseudo-elements are like the element's first and last children
+        let generate = |pseudo_element: &PseudoElementSelector, scopes: &mut CounterScopes| {
+            let style = resolver.resolve_pseudo_element(dom_node, pseudo_element, styles)?;
+            let content = style.get("content")?;
+            if parse_content(content).is_none_or(|items| items.is_empty()) {
+                return None;
+            }
+            scopes.apply(&style, depth + 1, None);
+            scopes.evaluate(content, dom_node)
+        };
+        if let Some(text) = generate(&PseudoElementSelector::Before, scopes) {
+            self.before.insert(id_num, text);
+        }
+        for child in &dom_node.borrow().children {
+            self.walk(resolver, child, styles, scopes, depth + 1);
+        }
+        if let Some(text) = generate(&PseudoElementSelector::After, scopes) {
+            self.after.insert(id_num, text);
+        }
+        scopes.leave(depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, parse_stylesheet};
+
+    #[test]
+    fn test_parse_counter_list() {
+        let list = |value: &str| parse_counter_list(value, 1);
+        assert_eq!(list("none"), Some(vec![]));
+        assert_eq!(
+            list("a b -2 c"),
+            Some(vec![("a".to_string(), 1), ("b".to_string(), -2), ("c".to_string(), 1)])
+        );
+        assert_eq!(list("2"), None);
+        assert_eq!(list("a 1 2"), None);
+        assert_eq!(list("a none"), None);
+    }
+
+    #[test]
+    fn test_format_counter() {
+        assert_eq!(format_counter(4, "lower-roman"), "iv");
+        assert_eq!(format_counter(1994, "upper-roman"), "MCMXCIV");
+        assert_eq!(format_counter(28, "lower-alpha"), "ab");
+        assert_eq!(format_counter(0, "upper-alpha"), "0");
+        assert_eq!(format_counter(7, "decimal-leading-zero"), "07");
+        assert_eq!(format_counter(7, "square"), "\u{25aa}");
+        assert_eq!(format_counter(-3, "unknown"), "-3");
+    }
+
+    #[test]
+    fn test_parse_content() {
+        assert_eq!(parse_content("none"), Some(vec![]));
+        assert_eq!(
+            parse_content("\"a\\\"\" counter(x) counters(y, '.', lower-alpha) attr(TITLE)"),
+            Some(vec![
+                ContentItem::Text("a\"".to_string()),
+                ContentItem::Counter("x".to_string(), "decimal".to_string()),
+                ContentItem::Counters("y".to_string(), ".".to_string(), "lower-alpha".to_string()),
+                ContentItem::Attr("title".to_string()),
+            ])
+        );
+        assert_eq!(parse_content("counter()"), None);
+        assert_eq!(parse_content("\"unclosed"), None);
+        assert_eq!(parse_content("bogus"), None);
+    }
+
+    #[test]
+    fn test_generated_content() {
+        let root = parse_html(
+            "<body><h2>A</h2><ol start=\"3\"><li>One</li><li>Two<ol><li>Three</li></ol></li>\
+             <li value=\"9\">Four</li></ol><h2 title=\"t\">B</h2><ul><li>Five</li></ul></body>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "body { counter-reset: chapter } \
+             h2::before { counter-increment: chapter; \
+                          content: counter(chapter, upper-roman) \": \" } \
+             h2::after { content: \" (\" attr(title) \")\" } \
+             li li::before { content: counters(list-item, \".\") }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let styles = resolver.resolve(&root);
+        let generated = GeneratedContent::new(&resolver, &root, &styles);
+        let children = root.borrow().children.clone();
+        let (h2_a, ol, h2_b, ul) = (&children[0], &children[1], &children[2], &children[3]);
+        assert_eq!(generated.before(h2_a), Some("I: "));
+        assert_eq!(generated.after(h2_a), Some(" ()"));
+        assert_eq!(generated.before(h2_b), Some("II: "));
+        assert_eq!(generated.after(h2_b), Some(" (t)"));
+
+        let items = ol.borrow().children.clone();
+        assert_eq!(generated.marker(&items[0]), Some("3. "));
+        assert_eq!(generated.marker(&items[1]), Some("4. "));
+        let nested = items[1].borrow().children[1].borrow().children[0].clone();
+        assert_eq!(generated.marker(&nested), Some("1. "));
+        assert_eq!(generated.before(&nested), Some("4.1"));
+        assert_eq!(generated.marker(&items[2]), Some("9. "));
+        assert_eq!(generated.before(&items[2]), None);
+        let bullet = ul.borrow().children[0].clone();
+        assert_eq!(generated.marker(&bullet), Some("\u{2022} "));
+    }
+}
@@ -0,0 +1,471 @@
+use magicparser::Declaration;
+use style::animation::{parse_time, TimingFunction};
+use style::color::Color;
+use style::counters::{is_valid_content, parse_counter_list};
+use style::font::parse_font_stretch;
+use style::length::LengthOrPercentage;
+use style::properties::{longhands, CssWideKeyword};
+
+/// A declaration's value, parsed according to its property's grammar.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    CssWide(CssWideKeyword),
+    // Lowercase
+    Keyword(String),
+    Number(f32),
+    Color(Color),
+    // Space-separated, e.g. of `margin: 0 auto`. None for auto.
+    Lengths(Vec<Option<LengthOrPercentage>>),
+    // Comma-separated, in seconds
+    Times(Vec<f32>),
+    // Comma-separated
+    TimingFunctions(Vec<TimingFunction>),
+    // Comma-separated keywords, names or numbers, e.g. of `animation-name`
+    List(Vec<String>),
+    // Counter names and values, e.g. of `counter-reset: a 2 b`
+    Counters(Vec<(String, i32)>),
+    // Of properties whose grammar isn't checked, e.g. font-family, of
+    // content, which is only checked, and of properties the resolver doesn't
+    // know about
+    Unchecked(String),
+}
+
+/// A declaration whose value is valid for its property.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyDeclaration {
+    // Lowercase
+    pub property: String,
+    pub value: PropertyValue,
+    pub important: bool,
+}
+
+impl PropertyDeclaration {
+    /// Returns None if declaration's value is invalid for its property, in
+    /// which case the declaration is ignored as if it weren't there.
+    pub fn parse(declaration: &Declaration) -> Option<PropertyDeclaration> {
+        let property = declaration.property.to_ascii_lowercase();
+        let value = match CssWideKeyword::parse(&declaration.value) {
+            Some(keyword) => PropertyValue::CssWide(keyword),
+            None => parse_value(&property, declaration.value.trim())?,
+        };
+        Some(PropertyDeclaration {
+            property,
+            value,
+            important: declaration.important,
+        })
+    }
+}
+
+/// Returns true if declaration's value is valid for its property.
+pub fn is_valid(declaration: &Declaration) -> bool {
+    PropertyDeclaration::parse(declaration).is_some()
+}
+
+/// Returns the properties a declaration of property sets, each with its
+/// value. A shorthand sets each of its longhands, e.g. `margin: 1px 2px`
+/// sets margin-top and margin-bottom to 1px and margin-right and margin-left
+/// to 2px, and a CSS-wide keyword sets them all to itself. Other properties
+/// only set themselves. A shorthand with too many or too few values, which is
+/// invalid, sets nothing.
+pub fn expand_declaration<'a>(property: &'a str, value: &str) -> Vec<(&'a str, String)> {
+    let longhands = match longhands(property) {
+        Some(longhands) => longhands,
+        None => return vec![(property, value.to_string())],
+    };
+    if CssWideKeyword::parse(value).is_some() {
+        return longhands.iter().map(|&longhand| (longhand, value.to_string())).collect();
+    }
+    let values = split_components(value);
+    if values.is_empty() || values.len() > longhands.len() {
+        return vec![];
+    }
+    longhands
+        .iter()
+        .enumerate()
+        .map(|(i, &longhand)| {
+            // A missing left side is the same as the right one, and other
+            // missing values as the first
+            let index = match i {
+                i if i < values.len() => i,
+                3 if values.len() > 1 => 1,
+                _ => 0,
+            };
+            (longhand, values[index].to_string())
+        })
+        .collect()
+}
+
+fn keyword(value: &str, keywords: &[&str]) -> Option<PropertyValue> {
+    let value = value.to_ascii_lowercase();
+    if keywords.contains(&value.as_str()) {
+        Some(PropertyValue::Keyword(value))
+    } else {
+        None
+    }
+}
+
+const OVERFLOW_KEYWORDS: &[&str] = &["visible", "hidden", "clip", "scroll", "auto"];
+
+fn comma_list<T, F>(value: &str, parse_item: F) -> Option<Vec<T>>
+where
+    F: Fn(&str) -> Option<T>,
+{
+    value.split(',').map(|item| parse_item(item.trim())).collect()
+}
+
+/// Returns true if value looks like a CSS identifier, e.g. `slide-in` or
+/// `_x`.
+pub fn is_ident(value: &str) -> bool {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(ch) if ch.is_alphabetic() || ch == '_' || ch == '-' => (),
+        _ => return false,
+    }
+    chars.all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-')
+}
+
+fn idents(value: &str, keywords: Option<&[&str]>) -> Option<PropertyValue> {
+    let list = comma_list(value, |item| {
+        let item = item.to_ascii_lowercase();
+        let valid = match keywords {
+            Some(keywords) => keywords.contains(&item.as_str()),
+            None => is_ident(&item),
+        };
+        if valid {
+            Some(item)
+        } else {
+            None
+        }
+    })?;
+    Some(PropertyValue::List(list))
+}
+
+// calc() can't be checked for negative values until it's computed
+fn is_non_negative(length: &LengthOrPercentage) -> bool {
+    match *length {
+        LengthOrPercentage::Length(ref length) => length.value >= 0.0,
+        LengthOrPercentage::Percentage(percentage) => percentage >= 0.0,
+        LengthOrPercentage::Calc(_) => true,
+    }
+}
+
+// Splits value at whitespace outside of parentheses, since calc() can
+// contain spaces
+fn split_components(value: &str) -> Vec<&str> {
+    let mut components = vec![];
+    let (mut depth, mut start) = (0, None);
+    for (i, ch) in value.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if ch.is_whitespace() && depth == 0 => {
+                if let Some(start) = start.take() {
+                    components.push(&value[start..i]);
+                }
+                continue;
+            }
+            _ => (),
+        }
+        start.get_or_insert(i);
+    }
+    components.extend(start.map(|start| &value[start..]));
+    components
+}
+
+// count is the range of how many space-separated lengths there may be
+fn lengths(
+    value: &str,
+    count: (usize, usize),
+    auto: bool,
+    non_negative: bool,
+) -> Option<PropertyValue> {
+    let items = split_components(value);
+    if items.len() < count.0 || items.len() > count.1 {
+        return None;
+    }
+    let lengths = items
+        .into_iter()
+        .map(|item| {
+            if auto && item.eq_ignore_ascii_case("auto") {
+                return Some(None);
+            }
+            let length = LengthOrPercentage::parse(item)?;
+            if non_negative && !is_non_negative(&length) {
+                return None;
+            }
+            Some(Some(length))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(PropertyValue::Lengths(lengths))
+}
+
+// none, or space-separated names, which are case-sensitive
+fn container_names(value: &str) -> Option<PropertyValue> {
+    if value.eq_ignore_ascii_case("none") {
+        return Some(PropertyValue::List(vec![]));
+    }
+    let reserved = ["none", "and", "not", "or"];
+    value
+        .split_whitespace()
+        .map(|name| {
+            if is_ident(name) && !reserved.iter().any(|word| name.eq_ignore_ascii_case(word)) {
+                Some(name.to_string())
+            } else {
+                None
+            }
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(PropertyValue::List)
+}
+
+fn non_negative_number(value: &str) -> Option<f32> {
+    value.parse::<f32>().ok().filter(|number| *number >= 0.0)
+}
+
+fn parse_value(property: &str, value: &str) -> Option<PropertyValue> {
+    if value.is_empty() {
+        return None;
+    }
+    match property {
+        // Only takes CSS-wide keywords
+        "all" => None,
+        "animation-delay" | "transition-delay" => {
+            comma_list(value, parse_time).map(PropertyValue::Times)
+        }
+        "animation-duration" | "transition-duration" => {
+            let duration = |item: &str| parse_time(item).filter(|time| *time >= 0.0);
+            comma_list(value, duration).map(PropertyValue::Times)
+        }
+        "animation-timing-function" | "transition-timing-function" => {
+            comma_list(value, TimingFunction::parse).map(PropertyValue::TimingFunctions)
+        }
+        "animation-direction" => {
+            idents(value, Some(&["normal", "reverse", "alternate", "alternate-reverse"]))
+        }
+        "animation-fill-mode" => idents(value, Some(&["none", "forwards", "backwards", "both"])),
+        "animation-iteration-count" => {
+            let count = |item: &str| {
+                if item.eq_ignore_ascii_case("infinite") || non_negative_number(item).is_some() {
+                    Some(item.to_ascii_lowercase())
+                } else {
+                    None
+                }
+            };
+            comma_list(value, count).map(PropertyValue::List)
+        }
+        "animation-name" | "transition-property" => idents(value, None),
+        "animation-play-state" => idents(value, Some(&["running", "paused"])),
+        "background-color" | "color" => Color::parse(value).map(PropertyValue::Color),
+        "break-after" | "break-before" => keyword(
+            value,
+            &[
+                "auto", "avoid", "always", "all", "avoid-page", "page", "left", "right", "recto",
+                "verso", "avoid-column", "column", "avoid-region", "region",
+            ],
+        ),
+        "break-inside" => keyword(
+            value,
+            &["auto", "avoid", "avoid-page", "avoid-column", "avoid-region"],
+        ),
+        "container-name" => container_names(value),
+        "container-type" => keyword(value, &["normal", "size", "inline-size"]),
+        "content" if is_valid_content(value) => Some(PropertyValue::Unchecked(value.to_string())),
+        "content" => None,
+        "counter-increment" => parse_counter_list(value, 1).map(PropertyValue::Counters),
+        "counter-reset" | "counter-set" => {
+            parse_counter_list(value, 0).map(PropertyValue::Counters)
+        }
+        "clear" => keyword(
+            value,
+            &["none", "left", "right", "both", "inline-start", "inline-end"],
+        ),
+        "direction" => keyword(value, &["ltr", "rtl"]),
+        "display" => keyword(
+            value,
+            &[
+                "block", "inline", "inline-block", "none", "contents", "flow-root", "flex",
+                "inline-flex", "grid", "inline-grid", "list-item", "table", "inline-table",
+                "table-caption", "table-cell", "table-column", "table-column-group",
+                "table-footer-group", "table-header-group", "table-row", "table-row-group",
+            ],
+        ),
+        "float" => keyword(value, &["none", "left", "right", "inline-start", "inline-end"]),
+        "font-size" => keyword(
+            value,
+            &[
+                "xx-small", "x-small", "small", "medium", "large", "x-large", "xx-large",
+                "larger", "smaller",
+            ],
+        ).or_else(|| lengths(value, (1, 1), false, true)),
+        // As a percentage
+        "font-stretch" => parse_font_stretch(value).map(PropertyValue::Number),
+        "font-style" => keyword(value, &["normal", "italic", "oblique"]),
+        "font-weight" => keyword(value, &["normal", "bold", "bolder", "lighter"]).or_else(|| {
+            let weight = value.parse::<f32>().ok()?;
+            if (1.0..=1000.0).contains(&weight) {
+                Some(PropertyValue::Number(weight))
+            } else {
+                None
+            }
+        }),
+        "bottom" | "left" | "right" | "top" => lengths(value, (1, 1), true, false),
+        "height" | "width" => lengths(value, (1, 1), true, true),
+        "inset-block" | "inset-inline" => lengths(value, (1, 2), true, false),
+        "inset-block-end" | "inset-block-start" | "inset-inline-end" | "inset-inline-start" => {
+            lengths(value, (1, 1), true, false)
+        }
+        "line-height" => keyword(value, &["normal"])
+            .or_else(|| non_negative_number(value).map(PropertyValue::Number))
+            .or_else(|| lengths(value, (1, 1), false, true)),
+        "margin" => lengths(value, (1, 4), true, false),
+        "margin-block" | "margin-inline" => lengths(value, (1, 2), true, false),
+        "margin-block-end" | "margin-block-start" | "margin-bottom" | "margin-inline-end"
+        | "margin-inline-start" | "margin-left" | "margin-right" | "margin-top" => {
+            lengths(value, (1, 1), true, false)
+        }
+        "max-height" | "max-width" => {
+            keyword(value, &["none"]).or_else(|| lengths(value, (1, 1), false, true))
+        }
+        "min-height" | "min-width" => lengths(value, (1, 1), true, true),
+        // One keyword for both axes, or one for each
+        "overflow" => {
+            let components = split_components(value);
+            let keywords = components
+                .iter()
+                .map(|component| component.to_ascii_lowercase())
+                .filter(|component| OVERFLOW_KEYWORDS.contains(&component.as_str()))
+                .collect::<Vec<_>>();
+            match (components.len(), keywords.len()) {
+                (1, 1) | (2, 2) => Some(PropertyValue::Keyword(keywords.join(" "))),
+                _ => None,
+            }
+        }
+        "overflow-x" | "overflow-y" => keyword(value, OVERFLOW_KEYWORDS),
+        "padding" => lengths(value, (1, 4), false, true),
+        "padding-block" | "padding-inline" => lengths(value, (1, 2), false, true),
+        "padding-block-end" | "padding-block-start" | "padding-bottom" | "padding-inline-end"
+        | "padding-inline-start" | "padding-left" | "padding-right" | "padding-top" => {
+            lengths(value, (1, 1), false, true)
+        }
+        "position" => keyword(value, &["static", "relative", "absolute", "fixed", "sticky"]),
+        "text-align" => keyword(
+            value,
+            &["start", "end", "left", "right", "center", "justify", "match-parent"],
+        ),
+        "visibility" => keyword(value, &["visible", "hidden", "collapse"]),
+        "white-space" => keyword(
+            value,
+            &["normal", "pre", "nowrap", "pre-wrap", "pre-line", "break-spaces"],
+        ),
+        "writing-mode" => keyword(
+            value,
+            &["horizontal-tb", "vertical-rl", "vertical-lr", "sideways-rl", "sideways-lr"],
+        ),
+        "z-index" => keyword(value, &["auto"])
+            .or_else(|| value.parse::<i32>().ok().map(|z| PropertyValue::Number(z as f32))),
+        _ => Some(PropertyValue::Unchecked(value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use style::color::Rgba;
+    use style::length::Length;
+    use style::useragent::user_agent_stylesheet;
+
+    fn parse(property: &str, value: &str) -> Option<PropertyValue> {
+        let declaration = Declaration::new(property.to_string(), value.to_string());
+        PropertyDeclaration::parse(&declaration).map(|declaration| declaration.value)
+    }
+
+    #[test]
+    fn test_parse_property_declaration() {
+        let red = Color::Rgba(Rgba::opaque(255, 0, 0));
+        assert_eq!(parse("COLOR", "red"), Some(PropertyValue::Color(red)));
+        let inherit = CssWideKeyword::Inherit;
+        assert_eq!(parse("color", "INHERIT"), Some(PropertyValue::CssWide(inherit)));
+        assert_eq!(parse("display", "Block"), Some(PropertyValue::Keyword("block".to_string())));
+        let zero = LengthOrPercentage::Length(Length::px(0.0));
+        assert_eq!(parse("margin", "0 auto"), Some(PropertyValue::Lengths(vec![Some(zero), None])));
+        match parse("padding", "calc(1em + 2px) 3px") {
+            Some(PropertyValue::Lengths(ref lengths)) => assert_eq!(lengths.len(), 2),
+            value => panic!("{:?}", value),
+        }
+        let times = PropertyValue::Times(vec![1.0, 0.5]);
+        assert_eq!(parse("animation-duration", "1s, 500ms"), Some(times));
+        assert_eq!(parse("font-weight", "700"), Some(PropertyValue::Number(700.0)));
+        let overflow = PropertyValue::Keyword("hidden auto".to_string());
+        assert_eq!(parse("overflow", "Hidden  auto"), Some(overflow));
+        assert_eq!(
+            parse("animation-name", "Slide, none"),
+            Some(PropertyValue::List(vec!["slide".to_string(), "none".to_string()]))
+        );
+        assert_eq!(parse("opacity", "0.5"), Some(PropertyValue::Unchecked("0.5".to_string())));
+        assert_eq!(
+            parse("container-name", "Card main"),
+            Some(PropertyValue::List(vec!["Card".to_string(), "main".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_user_agent_stylesheet_is_valid() {
+        for rule in &user_agent_stylesheet().rules {
+            for declaration in &rule.declarations {
+                assert!(is_valid(declaration), "{:?}", declaration);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_property_declaration() {
+        assert_eq!(parse("color", "1px"), None);
+        assert_eq!(parse("display", "blocky"), None);
+        assert_eq!(parse("width", "-1px"), None);
+        assert_eq!(parse("padding", "1px -1px"), None);
+        assert_eq!(parse("margin", "1px 2px 3px 4px 5px"), None);
+        assert_eq!(parse("margin-top", "auto 1px"), None);
+        assert_eq!(parse("animation-duration", "-1s"), None);
+        assert_eq!(parse("animation-delay", "1"), None);
+        assert_eq!(parse("animation-iteration-count", "2, -1"), None);
+        assert_eq!(parse("font-weight", "1001"), None);
+        assert_eq!(parse("font-stretch", "squished"), None);
+        assert_eq!(parse("padding-inline", "1px 2px 3px"), None);
+        assert_eq!(parse("writing-mode", "diagonal"), None);
+        assert_eq!(parse("z-index", "1.5"), None);
+        assert_eq!(parse("break-inside", "page"), None);
+        assert_eq!(parse("overflow", "hidden visible auto"), None);
+        assert_eq!(parse("overflow", "hidden sideways"), None);
+        assert_eq!(parse("font-size", ""), None);
+        assert_eq!(parse("animation-name", "1a"), None);
+        assert_eq!(parse("counter-reset", "a 1 2"), None);
+        assert_eq!(parse("content", "counter(1)"), None);
+        assert_eq!(parse("container-type", "block-size"), None);
+        assert_eq!(parse("container-name", "card not"), None);
+    }
+
+    #[test]
+    fn test_expand_declaration() {
+        let expand = |property: &str, value: &str| {
+            expand_declaration(property, value)
+                .into_iter()
+                .map(|(longhand, value)| format!("{}: {}", longhand, value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            expand("margin", "1px calc(50% + 2px) 3px"),
+            vec![
+                "margin-top: 1px",
+                "margin-right: calc(50% + 2px)",
+                "margin-bottom: 3px",
+                "margin-left: calc(50% + 2px)",
+            ]
+        );
+        assert_eq!(expand("padding", "1px 2px")[2], "padding-bottom: 1px");
+        assert_eq!(expand("padding", "1px")[3], "padding-left: 1px");
+        assert_eq!(expand("overflow", "hidden"), vec!["overflow-x: hidden", "overflow-y: hidden"]);
+        assert_eq!(expand("overflow", "inherit")[1], "overflow-y: inherit");
+        assert_eq!(expand("margin-left", "auto"), vec!["margin-left: auto"]);
+        assert!(expand("overflow", "auto hidden clip").is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use magicparser::{
+    dom_revision, Atom, DomNodeRef, ElemType, NamespaceConstraint, NodeId, Selector,
+};
+use std::collections::HashMap;
+use style::selectormatcher::{MatcherConfig, QuirksMode};
+
+// What a selector that's just an id, a class or an element type requires
+enum Key<'a> {
+    Id(&'a Atom),
+    Class(&'a Atom),
+    Type(&'a ElemType),
+}
+
+/// The elements of a tree by id, class and element type, so that queries for
+/// `#id`, `.class` or `tag` are a lookup instead of a walk over the tree.
+/// Only valid until the DOM changes; see is_stale().
+#[derive(Debug)]
+pub struct DocumentIndex {
+    // DOM revision the index was built at
+    revision: usize,
+    // Each in document order
+    by_id: HashMap<Atom, Vec<NodeId>>,
+    by_class: HashMap<Atom, Vec<NodeId>>,
+    by_type: HashMap<ElemType, Vec<NodeId>>,
+    // Ids and classes are keyed in lowercase in quirks mode, where they match
+    // ASCII-case-insensitively
+    quirks: bool,
+}
+
+impl DocumentIndex {
+    /// Indexes the descendants of root, but not root itself, like the
+    /// queries it answers.
+    pub fn new(root: &DomNodeRef, config: &MatcherConfig) -> DocumentIndex {
+        let mut index = DocumentIndex {
+            revision: dom_revision(),
+            by_id: HashMap::new(),
+            by_class: HashMap::new(),
+            by_type: HashMap::new(),
+            quirks: config.quirks_mode == QuirksMode::Quirks,
+        };
+        index.add_descendants(root);
+        index
+    }
+
+    fn add_descendants(&mut self, dom_node: &DomNodeRef) {
+        for child in &dom_node.borrow().children {
+            let node = child.borrow();
+            // Text and comment nodes aren't matched by selectors
+            if !node.elem_type.is_element() {
+                continue;
+            }
+            let node_id = child.node_id();
+            if let Some(ref id) = node.id {
+                self.by_id.entry(self.key(id)).or_default().push(node_id);
+            }
+            for class in &node.classes {
+                self.by_class.entry(self.key(class)).or_default().push(node_id);
+            }
+            self.by_type.entry(node.elem_type.clone()).or_default().push(node_id);
+            drop(node);
+            self.add_descendants(child);
+        }
+    }
+
+    fn key(&self, name: &Atom) -> Atom {
+        if self.quirks {
+            name.to_ascii_lowercase()
+        } else {
+            name.clone()
+        }
+    }
+
+    // Like key(), but without interning a lowercased atom, since no node is
+    // indexed under it if it doesn't exist
+    fn lookup_key(&self, name: &Atom) -> Option<Atom> {
+        if self.quirks {
+            name.lookup_ascii_lowercase()
+        } else {
+            Some(name.clone())
+        }
+    }
+
+    /// Returns true if the DOM has changed since the index was built.
+    pub fn is_stale(&self) -> bool {
+        self.revision != dom_revision()
+    }
+
+    /// Returns the indexed nodes that match selector, in document order, if
+    /// it's just an id, a class or an element type. Returns None for other
+    /// selectors, which need the general matcher.
+    pub fn lookup(&self, selector: &Selector) -> Option<Vec<DomNodeRef>> {
+        let found = match lookup_key(selector)? {
+            Key::Id(id) => self.lookup_key(id).and_then(|id| self.by_id.get(&id)),
+            Key::Class(class) => self.lookup_key(class).and_then(|class| self.by_class.get(&class)),
+            Key::Type(elem_type) => self.by_type.get(elem_type),
+        };
+        let found = found.map_or(&[][..], |found| &found[..]);
+        Some(found.iter().filter_map(|&id| DomNodeRef::from_node_id(id)).collect())
+    }
+}
+
+fn lookup_key(selector: &Selector) -> Option<Key<'_>> {
+    let simple = match selector {
+        Selector::Simple(simple) => simple,
+        _ => return None,
+    };
+    if simple.namespace != NamespaceConstraint::Any {
+        return None;
+    }
+    match (&simple.id, simple.classes.len(), &simple.elem_type) {
+        (Some(id), 0, None) => Some(Key::Id(id)),
+        (None, 1, None) => simple.classes.iter().next().map(Key::Class),
+        (None, 0, Some(elem_type)) => Some(Key::Type(elem_type)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_css, parse_html, CssBlocks};
+    use style::selectormatcher::query_selector_all;
+
+    fn selector(css: &str) -> Selector {
+        let CssBlocks(mut blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+        blocks.remove(0).0
+    }
+
+    #[test]
+    fn test_document_index() {
+        let root = parse_html(
+            "<div class=\"a\"><p id=\"x\" class=\"a b\">One</p><p>Two<span class=\"A\">\
+             </span></p></div>",
+        ).unwrap();
+        let config = MatcherConfig::default();
+        let index = DocumentIndex::new(&root, &config);
+        for css in &["#x", ".a", ".b", "p", "span", "#y", ".c", "div"] {
+            let selector = selector(css);
+            let found = query_selector_all(&root, &selector, &config);
+            assert_eq!(index.lookup(&selector), Some(found));
+        }
+        // Not the root itself
+        assert_eq!(index.lookup(&selector("div")), Some(vec![]));
+        for css in &["p.a", "div p", "*", "[id]", "|p"] {
+            assert_eq!(index.lookup(&selector(css)), None);
+        }
+
+        let mut quirks = MatcherConfig::default();
+        quirks.quirks_mode = QuirksMode::Quirks;
+        let index = DocumentIndex::new(&root, &quirks);
+        assert_eq!(index.lookup(&selector(".a")).unwrap().len(), 2);
+        assert_eq!(index.lookup(&selector("#X")).unwrap().len(), 1);
+
+        assert!(!index.is_stale());
+        root.add_class("c");
+        assert!(index.is_stale());
+    }
+}
@@ -0,0 +1,525 @@
+use magicparser::{FontFaceRule, Stylesheet};
+use style::resolver::ComputedStyle;
+
+/// Where a font face's data can be found, from the `src` descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FontSource {
+    // e.g. url(foo.woff2) format("woff2")
+    Url { url: String, format: Option<String> },
+    // e.g. local("Foo Bold"), a font installed on the system
+    Local(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+/// A font face declared with @font-face, or installed on the system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontFace {
+    pub family: String,
+    // In order of preference
+    pub sources: Vec<FontSource>,
+    // Inclusive range of weights the face covers, e.g. (400, 400) or (100, 900)
+    pub weight: (u16, u16),
+    pub style: FontStyle,
+    // Inclusive range of widths the face covers, as percentages of normal
+    pub stretch: (f32, f32),
+}
+
+// Splits on commas that aren't in parentheses or quotes
+fn split_commas(value: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, ch) in value.char_indices() {
+        match (quote, ch) {
+            (Some(q), _) if ch == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(value[start..].trim());
+    parts
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    for quote in &['"', '\''] {
+        if let Some(unquoted) = value.strip_prefix(*quote).and_then(|v| v.strip_suffix(*quote)) {
+            return unquoted;
+        }
+    }
+    value
+}
+
+// Returns the argument of e.g. `url(...)`, with the name matched
+// case-insensitively, and what comes after the closing parenthesis
+fn function_arg<'a>(value: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let prefix = value.get(..name.len() + 1)?;
+    if !prefix[..name.len()].eq_ignore_ascii_case(name) || !prefix.ends_with('(') {
+        return None;
+    }
+    let rest = &value[name.len() + 1..];
+    let close = rest.find(')')?;
+    Some((unquote(&rest[..close]), rest[close + 1..].trim()))
+}
+
+fn parse_source(value: &str) -> Option<FontSource> {
+    if let Some((url, rest)) = function_arg(value, "url") {
+        let format = if rest.is_empty() {
+            None
+        } else {
+            match function_arg(rest, "format")? {
+                (format, "") => Some(format.to_string()),
+                _ => return None,
+            }
+        };
+        return Some(FontSource::Url {
+            url: url.to_string(),
+            format,
+        });
+    }
+    match function_arg(value, "local")? {
+        (name, "") => Some(FontSource::Local(name.to_string())),
+        _ => None,
+    }
+}
+
+/// Parses an absolute font weight, e.g. `bold` or `300`.
+pub fn parse_font_weight(value: &str) -> Option<u16> {
+    match value.to_ascii_lowercase().as_str() {
+        "normal" => Some(400),
+        "bold" => Some(700),
+        value => value.parse::<u16>().ok().filter(|weight| (1..=1000).contains(weight)),
+    }
+}
+
+// e.g. "bold" or "100 900"
+fn parse_weight_range(value: &str) -> Option<(u16, u16)> {
+    let weights = value
+        .split_whitespace()
+        .map(parse_font_weight)
+        .collect::<Option<Vec<_>>>()?;
+    match weights[..] {
+        [weight] => Some((weight, weight)),
+        [min, max] if min <= max => Some((min, max)),
+        _ => None,
+    }
+}
+
+/// Returns the weight `bolder` computes to, relative to the parent's weight.
+pub fn bolder(weight: u16) -> u16 {
+    match weight {
+        0..=349 => 400,
+        350..=549 => 700,
+        550..=899 => 900,
+        _ => weight,
+    }
+}
+
+/// Returns the weight `lighter` computes to, relative to the parent's weight.
+pub fn lighter(weight: u16) -> u16 {
+    match weight {
+        0..=99 => weight,
+        100..=549 => 100,
+        550..=749 => 400,
+        _ => 700,
+    }
+}
+
+/// Parses a font width, e.g. `condensed` or `75%`, as a percentage of
+/// normal.
+pub fn parse_font_stretch(value: &str) -> Option<f32> {
+    let value = value.trim().to_ascii_lowercase();
+    let percentage = match value.as_str() {
+        "ultra-condensed" => 50.0,
+        "extra-condensed" => 62.5,
+        "condensed" => 75.0,
+        "semi-condensed" => 87.5,
+        "normal" => 100.0,
+        "semi-expanded" => 112.5,
+        "expanded" => 125.0,
+        "extra-expanded" => 150.0,
+        "ultra-expanded" => 200.0,
+        _ => value.strip_suffix('%')?.parse::<f32>().ok()?,
+    };
+    if percentage >= 0.0 {
+        Some(percentage)
+    } else {
+        None
+    }
+}
+
+// e.g. "condensed" or "75% 125%"
+fn parse_stretch_range(value: &str) -> Option<(f32, f32)> {
+    let widths = value
+        .split_whitespace()
+        .map(parse_font_stretch)
+        .collect::<Option<Vec<_>>>()?;
+    match widths[..] {
+        [width] => Some((width, width)),
+        [min, max] if min <= max => Some((min, max)),
+        _ => None,
+    }
+}
+
+fn parse_style(value: &str) -> Option<FontStyle> {
+    // Oblique may be followed by an angle, which is ignored
+    let keyword = value.split_whitespace().next()?.to_ascii_lowercase();
+    match keyword.as_str() {
+        "normal" => Some(FontStyle::Normal),
+        "italic" => Some(FontStyle::Italic),
+        "oblique" => Some(FontStyle::Oblique),
+        _ => None,
+    }
+}
+
+impl FontFace {
+    /// Returns None if the rule has no font-family or no valid src, or an
+    /// invalid font-weight, font-style or font-stretch. Invalid sources are
+    /// skipped.
+    pub fn from_rule(rule: &FontFaceRule) -> Option<FontFace> {
+        let family = unquote(rule.descriptor("font-family")?).to_string();
+        if family.is_empty() {
+            return None;
+        }
+        let sources = split_commas(rule.descriptor("src")?)
+            .into_iter()
+            .filter_map(parse_source)
+            .collect::<Vec<_>>();
+        if sources.is_empty() {
+            return None;
+        }
+        let weight = match rule.descriptor("font-weight") {
+            Some(weight) => parse_weight_range(weight)?,
+            None => (400, 400),
+        };
+        let style = match rule.descriptor("font-style") {
+            Some(style) => parse_style(style)?,
+            None => FontStyle::Normal,
+        };
+        let stretch = match rule.descriptor("font-stretch") {
+            Some(stretch) => parse_stretch_range(stretch)?,
+            None => (100.0, 100.0),
+        };
+        Some(FontFace {
+            family,
+            sources,
+            weight,
+            style,
+            stretch,
+        })
+    }
+}
+
+// Families the platform always has a font for
+const GENERIC_FAMILIES: &[&str] = &[
+    "serif", "sans-serif", "monospace", "cursive", "fantasy", "system-ui", "math", "emoji",
+];
+
+/// The font chosen for text with some font-family, font-weight, font-style
+/// and font-stretch, for layout and paint to use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontHandle {
+    // The family that matched, e.g. "Foo Sans", or a generic family
+    pub family: String,
+    // None for a generic family with no face in the registry, which the
+    // platform picks a font for
+    pub face: Option<FontFace>,
+    // The values asked for. They can differ from the face's, in which case
+    // bold or oblique has to be synthesized.
+    pub weight: u16,
+    pub style: FontStyle,
+    pub stretch: f32,
+}
+
+// Sorts faces that don't cover desired, which is one of the face's weights or
+// widths, after those that do. higher is true if values above desired are
+// preferred to those below it; values on the preferred side up to limit are
+// preferred to all others.
+fn distance_key(range: (f32, f32), desired: f32, higher: bool, limit: f32) -> (u8, f32) {
+    let (min, max) = range;
+    if min <= desired && desired <= max {
+        (0, 0.0)
+    } else if min > desired {
+        match (higher, min <= limit) {
+            (true, true) => (1, min - desired),
+            (true, false) => (3, min - desired),
+            (false, _) => (2, min - desired),
+        }
+    } else if higher {
+        (2, desired - max)
+    } else {
+        (1, desired - max)
+    }
+}
+
+// Keeps the faces with the smallest key
+fn narrow<K, F>(faces: Vec<&FontFace>, key: F) -> Vec<&FontFace>
+where
+    K: PartialOrd,
+    F: Fn(&FontFace) -> K,
+{
+    let best = faces.iter().map(|face| key(face)).fold(None, |best: Option<K>, key| {
+        match best {
+            Some(best) if best <= key => Some(best),
+            _ => Some(key),
+        }
+    });
+    faces
+        .into_iter()
+        .filter(|face| Some(key(face)) == best)
+        .collect()
+}
+
+// Picks one of a family's faces as in the CSS font matching algorithm: the
+// closest width first, then style, then weight. The first declared face wins
+// ties.
+fn best_face(
+    faces: Vec<&FontFace>,
+    weight: u16,
+    style: FontStyle,
+    stretch: f32,
+) -> Option<&FontFace> {
+    let faces = narrow(faces, |face| {
+        // Narrower widths are preferred for condensed text, wider otherwise
+        distance_key(face.stretch, stretch, stretch > 100.0, f32::INFINITY)
+    });
+    let preference = match style {
+        FontStyle::Normal => [FontStyle::Normal, FontStyle::Oblique, FontStyle::Italic],
+        FontStyle::Italic => [FontStyle::Italic, FontStyle::Oblique, FontStyle::Normal],
+        FontStyle::Oblique => [FontStyle::Oblique, FontStyle::Italic, FontStyle::Normal],
+    };
+    let faces = narrow(faces, |face| preference.iter().position(|style| *style == face.style));
+    let faces = narrow(faces, |face| {
+        let range = (face.weight.0 as f32, face.weight.1 as f32);
+        let weight = weight as f32;
+        // Weights from 400 to 500 look for heavier faces up to 500 first,
+        // then lighter ones
+        if (400.0..=500.0).contains(&weight) {
+            distance_key(range, weight, true, 500.0)
+        } else {
+            distance_key(range, weight, weight > 500.0, f32::INFINITY)
+        }
+    });
+    faces.into_iter().next()
+}
+
+/// The font faces declared by a set of stylesheets, along with the fonts
+/// installed on the system.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FontRegistry {
+    // In the order they were declared
+    faces: Vec<FontFace>,
+    system_faces: Vec<FontFace>,
+}
+
+impl FontRegistry {
+    /// Adds the valid @font-face rules of stylesheet.
+    pub fn add_stylesheet(&mut self, stylesheet: &Stylesheet) {
+        self.faces
+            .extend(stylesheet.font_faces.iter().filter_map(FontFace::from_rule));
+    }
+
+    /// Adds a font installed on the system. Families declared with
+    /// @font-face hide system fonts of the same name.
+    pub fn add_system_font(&mut self, face: FontFace) {
+        self.system_faces.push(face);
+    }
+
+    /// Returns the faces declared with @font-face.
+    pub fn faces(&self) -> &[FontFace] {
+        &self.faces
+    }
+
+    /// Family names are matched case-insensitively.
+    pub fn faces_for_family(&self, family: &str) -> Vec<&FontFace> {
+        self.faces
+            .iter()
+            .filter(|face| face.family.eq_ignore_ascii_case(family))
+            .collect()
+    }
+
+    /// Returns the font for the first family of a font-family list that has
+    /// a face, or is a generic family. Falls back to serif if none does.
+    pub fn match_font(
+        &self,
+        families: &str,
+        weight: u16,
+        style: FontStyle,
+        stretch: f32,
+    ) -> FontHandle {
+        let handle = |family: &str, face: Option<&FontFace>| FontHandle {
+            family: family.to_string(),
+            face: face.cloned(),
+            weight,
+            style,
+            stretch,
+        };
+        for name in split_commas(families) {
+            let family = unquote(name);
+            let mut faces = self.faces_for_family(family);
+            if faces.is_empty() {
+                faces = self
+                    .system_faces
+                    .iter()
+                    .filter(|face| face.family.eq_ignore_ascii_case(family))
+                    .collect();
+            }
+            if let Some(face) = best_face(faces, weight, style, stretch) {
+                return handle(&face.family, Some(face));
+            }
+            // Only unquoted names are generic families
+            let generic = family.to_ascii_lowercase();
+            if family == name && GENERIC_FAMILIES.contains(&generic.as_str()) {
+                return handle(&generic, None);
+            }
+        }
+        handle("serif", None)
+    }
+
+    /// Matches the font properties of a computed style.
+    pub fn match_style(&self, style: &ComputedStyle) -> FontHandle {
+        let weight = style.get("font-weight").and_then(parse_font_weight).unwrap_or(400);
+        let font_style = style.get("font-style").and_then(parse_style).unwrap_or(FontStyle::Normal);
+        let stretch = style.get("font-stretch").and_then(parse_font_stretch).unwrap_or(100.0);
+        self.match_font(style.get("font-family").unwrap_or("serif"), weight, font_style, stretch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_stylesheet;
+
+    #[test]
+    fn test_font_face_from_rule() {
+        let stylesheet = parse_stylesheet(
+            "@font-face { font-family: \"Foo Sans\"; font-weight: 100 bold; font-stretch: 75%; \
+             src: local('Foo'), url(\"a,b.woff2\") format('woff2'), url(c.ttf), bad(d) }",
+        ).unwrap();
+        assert_eq!(
+            FontFace::from_rule(&stylesheet.font_faces[0]),
+            Some(FontFace {
+                family: "Foo Sans".to_string(),
+                sources: vec![
+                    FontSource::Local("Foo".to_string()),
+                    FontSource::Url {
+                        url: "a,b.woff2".to_string(),
+                        format: Some("woff2".to_string()),
+                    },
+                    FontSource::Url {
+                        url: "c.ttf".to_string(),
+                        format: None,
+                    },
+                ],
+                weight: (100, 700),
+                style: FontStyle::Normal,
+                stretch: (75.0, 75.0),
+            })
+        );
+    }
+
+    #[test]
+    fn test_font_face_from_rule_fail() {
+        let from_css = |css: &str| {
+            let stylesheet = parse_stylesheet(css).unwrap();
+            FontFace::from_rule(&stylesheet.font_faces[0])
+        };
+        assert_eq!(from_css("@font-face { src: url(a.woff) }"), None);
+        assert_eq!(from_css("@font-face { font-family: Foo }"), None);
+        assert_eq!(from_css("@font-face { font-family: Foo; src: bad(a.woff) }"), None);
+        assert_eq!(
+            from_css("@font-face { font-family: Foo; src: url(a.woff); font-weight: 900 100 }"),
+            None
+        );
+        assert_eq!(
+            from_css("@font-face { font-family: Foo; src: url(a.woff); font-style: wavy }"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_font_registry() {
+        let stylesheet = parse_stylesheet(
+            "@font-face { font-family: Foo; src: url(foo.woff) } \
+             @font-face { font-family: Bar; src: url(bar.woff) } \
+             @font-face { font-family: foo; src: url(foo-italic.woff); font-style: italic } \
+             @font-face { font-family: Baz }",
+        ).unwrap();
+        let mut registry = FontRegistry::default();
+        registry.add_stylesheet(&stylesheet);
+        assert_eq!(registry.faces().len(), 3);
+        let styles = registry
+            .faces_for_family("FOO")
+            .iter()
+            .map(|face| face.style)
+            .collect::<Vec<_>>();
+        assert_eq!(styles, vec![FontStyle::Normal, FontStyle::Italic]);
+        assert!(registry.faces_for_family("Baz").is_empty());
+    }
+
+    #[test]
+    fn test_match_font() {
+        let stylesheet = parse_stylesheet(
+            "@font-face { font-family: Foo; src: url(regular.woff) } \
+             @font-face { font-family: Foo; src: url(bold.woff); font-weight: 600 900 } \
+             @font-face { font-family: Foo; src: url(oblique.woff); font-style: oblique } \
+             @font-face { font-family: Foo; src: url(light.woff); font-weight: 300 } \
+             @font-face { font-family: Foo; src: url(wide.woff); font-stretch: expanded }",
+        ).unwrap();
+        let mut registry = FontRegistry::default();
+        registry.add_stylesheet(&stylesheet);
+        registry.add_system_font(FontFace {
+            family: "Bar".to_string(),
+            sources: vec![FontSource::Local("Bar".to_string())],
+            weight: (400, 400),
+            style: FontStyle::Normal,
+            stretch: (100.0, 100.0),
+        });
+        let url = |handle: FontHandle| match handle.face.unwrap().sources[0] {
+            FontSource::Url { ref url, .. } => url.clone(),
+            ref source => panic!("{:?}", source),
+        };
+        let normal = FontStyle::Normal;
+        assert_eq!(url(registry.match_font("foo", 400, normal, 100.0)), "regular.woff");
+        assert_eq!(url(registry.match_font("Foo", 700, normal, 100.0)), "bold.woff");
+        // 500 looks for lighter faces before heavier ones above 500
+        assert_eq!(url(registry.match_font("Foo", 500, normal, 100.0)), "regular.woff");
+        assert_eq!(url(registry.match_font("Foo", 200, normal, 100.0)), "light.woff");
+        assert_eq!(url(registry.match_font("Foo", 400, FontStyle::Italic, 100.0)), "oblique.woff");
+        assert_eq!(url(registry.match_font("Foo", 400, normal, 110.0)), "wide.woff");
+        assert_eq!(url(registry.match_font("Foo", 400, normal, 90.0)), "regular.woff");
+
+        let handle = registry.match_font("Missing, 'Bar', serif", 700, normal, 100.0);
+        assert_eq!(handle.family, "Bar");
+        assert_eq!(handle.weight, 700);
+        let handle = registry.match_font("Missing, \"serif\", MONOSPACE", 400, normal, 100.0);
+        assert_eq!((handle.family.as_str(), handle.face), ("monospace", None));
+        assert_eq!(registry.match_font("Missing", 400, normal, 100.0).family, "serif");
+    }
+
+    #[test]
+    fn test_relative_weights() {
+        assert_eq!(bolder(300), 400);
+        assert_eq!(bolder(400), 700);
+        assert_eq!(bolder(700), 900);
+        assert_eq!(lighter(400), 100);
+        assert_eq!(lighter(700), 400);
+        assert_eq!(lighter(900), 700);
+        assert_eq!(parse_font_stretch("Condensed"), Some(75.0));
+        assert_eq!(parse_font_stretch("-5%"), None);
+    }
+}
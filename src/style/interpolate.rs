@@ -0,0 +1,126 @@
+use style::color::{Color, Rgba};
+use style::length::{ComputedLength, LengthContext, LengthOrPercentage};
+
+fn lerp(from: f32, to: f32, progress: f32) -> f32 {
+    from + (to - from) * progress
+}
+
+// Interpolates premultiplied by alpha, so that fading from transparent doesn't
+// pass through black
+fn interpolate_rgba(from: Rgba, to: Rgba, progress: f32) -> Rgba {
+    let alpha = lerp(from.alpha, to.alpha, progress).clamp(0.0, 1.0);
+    if alpha == 0.0 {
+        return Rgba::transparent();
+    }
+    let channel = |from_channel: u8, to_channel: u8| {
+        let premultiplied = lerp(
+            f32::from(from_channel) * from.alpha,
+            f32::from(to_channel) * to.alpha,
+            progress,
+        );
+        (premultiplied / alpha).round().clamp(0.0, 255.0) as u8
+    };
+    Rgba::new(
+        channel(from.red, to.red),
+        channel(from.green, to.green),
+        channel(from.blue, to.blue),
+        alpha,
+    )
+}
+
+fn interpolate_value(
+    from: &str,
+    to: &str,
+    progress: f32,
+    context: &LengthContext,
+) -> Option<String> {
+    if let (Ok(from), Ok(to)) = (from.parse::<f32>(), to.parse::<f32>()) {
+        return Some(lerp(from, to, progress).to_string());
+    }
+    let compute_length = |value: &str| LengthOrPercentage::parse(value)?.compute(context);
+    if let (Some(from), Some(to)) = (compute_length(from), compute_length(to)) {
+        let length = ComputedLength {
+            px: lerp(from.px, to.px, progress),
+            percentage: lerp(from.percentage, to.percentage, progress),
+        };
+        return Some(length.to_string());
+    }
+    match (Color::parse(from), Color::parse(to)) {
+        (Some(Color::Rgba(from)), Some(Color::Rgba(to))) => {
+            Some(interpolate_rgba(from, to, progress).to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Returns the value progress of the way from one value of a property to
+/// another. Numbers, lengths, percentages and colors are interpolated, as are
+/// space-separated lists of them with the same length, e.g. `margin: 0 1em`.
+/// Returns None for other values.
+///
+/// progress may be outside of [0, 1] if an easing function overshoots.
+pub fn interpolate_smooth(
+    from: &str,
+    to: &str,
+    progress: f32,
+    context: &LengthContext,
+) -> Option<String> {
+    if let Some(value) = interpolate_value(from, to, progress, context) {
+        return Some(value);
+    }
+    let (from_list, to_list) = (
+        from.split_whitespace().collect::<Vec<_>>(),
+        to.split_whitespace().collect::<Vec<_>>(),
+    );
+    if from_list.len() > 1 && from_list.len() == to_list.len() {
+        let values = from_list
+            .iter()
+            .zip(&to_list)
+            .map(|(from, to)| interpolate_value(from, to, progress, context))
+            .collect::<Option<Vec<_>>>()?;
+        return Some(values.join(" "));
+    }
+    None
+}
+
+/// Like interpolate_smooth(), but values that can't be interpolated switch
+/// from one to the other halfway.
+pub fn interpolate(from: &str, to: &str, progress: f32, context: &LengthContext) -> String {
+    interpolate_smooth(from, to, progress, context).unwrap_or_else(|| {
+        if progress < 0.5 {
+            from.to_string()
+        } else {
+            to.to_string()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpolate_default(from: &str, to: &str, progress: f32) -> String {
+        interpolate(from, to, progress, &LengthContext::default())
+    }
+
+    #[test]
+    fn test_interpolate() {
+        assert_eq!(interpolate_default("0", "1", 0.25), "0.25");
+        assert_eq!(interpolate_default("0", "2em", 0.5), "16px");
+        assert_eq!(interpolate_default("10%", "20px", 0.5), "calc(5% + 10px)");
+        assert_eq!(interpolate_default("1px 2px", "3px 4em", 0.5), "2px 33px");
+        assert_eq!(interpolate_default("red", "blue", 0.5), "rgb(128, 0, 128)");
+        assert_eq!(interpolate_default("transparent", "blue", 0.5), "rgba(0, 0, 255, 0.5)");
+        assert_eq!(interpolate_default("0", "10px", 1.5), "15px");
+    }
+
+    #[test]
+    fn test_interpolate_discrete() {
+        assert_eq!(interpolate_default("block", "none", 0.49), "block");
+        assert_eq!(interpolate_default("block", "none", 0.5), "none");
+        assert_eq!(interpolate_default("1px auto", "2px 3px", 0.25), "1px auto");
+        assert_eq!(interpolate_default("red", "currentColor", 0.75), "currentColor");
+        let context = LengthContext::default();
+        assert_eq!(interpolate_smooth("block", "none", 0.5, &context), None);
+    }
+}
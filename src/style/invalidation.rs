@@ -0,0 +1,499 @@
+use magicparser::{
+    Combinator, DomNodeRef, ElementState, InvalidationHook, PseudoClassSelector,
+    PseudoElementSelector, Selector, Stylesheet,
+};
+use std::collections::{HashMap, HashSet};
+use style::selectormatcher::{MatcherConfig, QuirksMode};
+
+/// Something about an element that selectors can depend on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Class(String),
+    Id(String),
+    // Lowercase attribute name
+    Attr(String),
+    // Lowercase name of a state pseudo-class, e.g. "hover"
+    State(String),
+}
+
+/// Which elements a change to a feature of an element can restyle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InvalidationScope {
+    // The feature is used by a selector's subject
+    pub element: bool,
+    // ... to the left of a descendant or child combinator
+    pub descendants: bool,
+    // ... to the left of a sibling combinator
+    pub later_siblings: bool,
+}
+
+impl InvalidationScope {
+    fn union(self, other: InvalidationScope) -> InvalidationScope {
+        InvalidationScope {
+            element: self.element || other.element,
+            descendants: self.descendants || other.descendants,
+            later_siblings: self.later_siblings || other.later_siblings,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == InvalidationScope::default()
+    }
+}
+
+const SUBJECT: InvalidationScope = InvalidationScope {
+    element: true,
+    descendants: false,
+    later_siblings: false,
+};
+
+/// The features that selectors in a set of stylesheets depend on, so that a
+/// change to an element only marks the elements it could restyle as dirty.
+/// Set it as the invalidation hook (see set_invalidation_hook()) for
+/// DomNodeRef's attribute and class methods to use it too.
+#[derive(Debug, Clone, Default)]
+pub struct InvalidationMap {
+    features: HashMap<Feature, InvalidationScope>,
+    // Ids and classes are keyed in lowercase in quirks mode, where they match
+    // ASCII-case-insensitively
+    quirks: bool,
+}
+
+impl InvalidationMap {
+    pub fn new(config: &MatcherConfig) -> InvalidationMap {
+        InvalidationMap {
+            quirks: config.quirks_mode == QuirksMode::Quirks,
+            ..InvalidationMap::default()
+        }
+    }
+
+    pub fn add_stylesheet(&mut self, stylesheet: &Stylesheet) {
+        for rule in &stylesheet.rules {
+            self.add_selector(&rule.selector);
+        }
+    }
+
+    pub fn add_selector(&mut self, selector: &Selector) {
+        self.add_selector_at(selector, SUBJECT);
+    }
+
+    // scope is what a change to a feature in selector restyles
+    fn add_selector_at(&mut self, selector: &Selector, scope: InvalidationScope) {
+        match *selector {
+            Selector::Simple(ref simple) => {
+                if let Some(ref id) = simple.id {
+                    self.add_feature(Feature::Id(id.to_string()), scope);
+                }
+                for class in &simple.classes {
+                    self.add_feature(Feature::Class(class.to_string()), scope);
+                }
+            }
+            Selector::Attr(ref attr) => {
+                self.add_feature(Feature::Attr(attr.attr.to_lowercase()), scope);
+            }
+            Selector::PseudoClass(ref pseudo_class) => match *pseudo_class {
+                PseudoClassSelector::Active => self.add_state("active", scope),
+                PseudoClassSelector::Checked => {
+                    // Which decide the checkedness of controls with no dirty
+                    // state
+                    self.add_feature(Feature::Attr("checked".to_string()), scope);
+                    self.add_feature(Feature::Attr("selected".to_string()), scope);
+                    self.add_state("checked", scope)
+                }
+                PseudoClassSelector::Default => {
+                    self.add_feature(Feature::Attr("checked".to_string()), scope);
+                    self.add_feature(Feature::Attr("selected".to_string()), scope);
+                    self.add_feature(Feature::Attr("type".to_string()), scope)
+                }
+                PseudoClassSelector::Disabled | PseudoClassSelector::Enabled => {
+                    // Disabling a fieldset or optgroup disables the controls
+                    // in it
+                    let descendants = InvalidationScope {
+                        descendants: true,
+                        ..InvalidationScope::default()
+                    };
+                    let disabled = Feature::Attr("disabled".to_string());
+                    self.add_feature(disabled, scope.union(descendants));
+                    self.add_state("disabled", scope)
+                }
+                PseudoClassSelector::Focus => self.add_state("focus", scope),
+                PseudoClassSelector::Hover => self.add_state("hover", scope),
+                PseudoClassSelector::Indeterminate => {
+                    // Which decide whether radio buttons are in a group with
+                    // one checked, and whether progress bars have a value
+                    for name in &["checked", "name", "type", "value"] {
+                        self.add_feature(Feature::Attr(name.to_string()), scope);
+                    }
+                }
+                PseudoClassSelector::Lang(_) => {
+                    // Which the descendants inherit the language from
+                    let descendants = InvalidationScope {
+                        descendants: true,
+                        ..InvalidationScope::default()
+                    };
+                    for name in &["lang", "xml:lang"] {
+                        self.add_feature(Feature::Attr(name.to_string()), scope.union(descendants));
+                    }
+                }
+                PseudoClassSelector::Link => self.add_state("link", scope),
+                PseudoClassSelector::Target => self.add_state("target", scope),
+                PseudoClassSelector::Visited => self.add_state("visited", scope),
+                PseudoClassSelector::Custom(ref name) => self.add_state(name, scope),
+                PseudoClassSelector::Matches(ref selector)
+                | PseudoClassSelector::Not(ref selector)
+                | PseudoClassSelector::Host(Some(ref selector)) => {
+                    self.add_selector_at(selector, scope)
+                }
+                _ => (),
+            },
+            Selector::PseudoElement(PseudoElementSelector::Part(_)) => {
+                self.add_feature(Feature::Attr("part".to_string()), scope)
+            }
+            Selector::PseudoElement(_) => (),
+            Selector::Seq(ref selectors) => {
+                // What comes before a ::part() is of its host, and so restyles
+                // the shadow tree the part is in
+                let part = selectors.iter().position(|selector| {
+                    matches!(selector, Selector::PseudoElement(PseudoElementSelector::Part(_)))
+                });
+                let host_scope = InvalidationScope {
+                    descendants: true,
+                    ..InvalidationScope::default()
+                };
+                for (i, selector) in selectors.iter().enumerate() {
+                    let is_host = part.is_some_and(|part| i < part);
+                    self.add_selector_at(selector, if is_host { host_scope } else { scope });
+                }
+            }
+            Selector::Group(ref selectors) => {
+                for selector in selectors {
+                    self.add_selector_at(selector, scope);
+                }
+            }
+            Selector::Combinator(ref left, ref combinator, ref right) => {
+                self.add_selector_at(right, scope);
+                let left_scope = match *combinator {
+                    Combinator::Descendant | Combinator::Child => InvalidationScope {
+                        descendants: true,
+                        ..InvalidationScope::default()
+                    },
+                    Combinator::AdjacentSibling | Combinator::GeneralSibling => {
+                        InvalidationScope {
+                            later_siblings: true,
+                            ..InvalidationScope::default()
+                        }
+                    }
+                };
+                self.add_selector_at(left, left_scope);
+            }
+        }
+    }
+
+    fn add_state(&mut self, name: &str, scope: InvalidationScope) {
+        self.add_feature(Feature::State(name.to_ascii_lowercase()), scope);
+    }
+
+    fn add_feature(&mut self, feature: Feature, scope: InvalidationScope) {
+        let entry = self.features.entry(self.key(feature)).or_default();
+        *entry = entry.union(scope);
+    }
+
+    fn key(&self, feature: Feature) -> Feature {
+        match feature {
+            Feature::Class(ref name) if self.quirks => Feature::Class(name.to_ascii_lowercase()),
+            Feature::Id(ref name) if self.quirks => Feature::Id(name.to_ascii_lowercase()),
+            _ => feature,
+        }
+    }
+
+    /// Returns what a change to feature can restyle. Empty if no selector
+    /// depends on it.
+    pub fn scope(&self, feature: &Feature) -> InvalidationScope {
+        let feature = self.key(feature.clone());
+        self.features.get(&feature).cloned().unwrap_or_default()
+    }
+
+    /// Marks what a change to features of dom_node can restyle as dirty.
+    pub fn invalidate(&self, dom_node: &DomNodeRef, features: &[Feature]) {
+        let scope = features
+            .iter()
+            .fold(InvalidationScope::default(), |scope, feature| {
+                scope.union(self.scope(feature))
+            });
+        if scope.descendants {
+            dom_node.mark_subtree_style_dirty();
+        } else if scope.element {
+            dom_node.mark_self_style_dirty();
+        }
+        if scope.later_siblings {
+            for sibling in dom_node.later_siblings() {
+                sibling.mark_subtree_style_dirty();
+            }
+        }
+    }
+
+    /// Sets an attribute of dom_node, marking only what the change can restyle
+    /// as dirty.
+    pub fn set_attr(&self, dom_node: &DomNodeRef, name: &str, value: Option<String>) {
+        let new_value = value.clone();
+        let old_value = dom_node.replace_attr(name, value);
+        self.attr_changed(dom_node, name, old_value.unwrap_or(None), new_value);
+    }
+
+    /// Removes an attribute of dom_node, marking only what the change can
+    /// restyle as dirty.
+    pub fn remove_attr(&self, dom_node: &DomNodeRef, name: &str) {
+        if let Some(old_value) = dom_node.take_attr(name) {
+            self.attr_changed(dom_node, name, old_value, None);
+        }
+    }
+
+    pub fn add_class(&self, dom_node: &DomNodeRef, class: &str) {
+        if dom_node.borrow().has_class(class) {
+            return;
+        }
+        let mut classes = dom_node.class_list().to_vec();
+        classes.push(class.to_string());
+        self.set_attr(dom_node, "class", Some(classes.join(" ")));
+    }
+
+    pub fn remove_class(&self, dom_node: &DomNodeRef, class: &str) {
+        if !dom_node.borrow().has_class(class) {
+            return;
+        }
+        let classes = dom_node
+            .class_list()
+            .to_vec()
+            .into_iter()
+            .filter(|other| other != class)
+            .collect::<Vec<_>>();
+        self.set_attr(dom_node, "class", Some(classes.join(" ")));
+    }
+
+    /// Marks what a change in whether dom_node is in a state, e.g. hovered,
+    /// can restyle as dirty. The state itself is decided by the pseudo-class
+    /// matchers of a MatcherConfig.
+    pub fn state_changed(&self, dom_node: &DomNodeRef, pseudo_class: &str) {
+        self.invalidate(dom_node, &[Feature::State(pseudo_class.to_ascii_lowercase())]);
+    }
+
+    /// Sets or clears states of dom_node, marking only what the change can
+    /// restyle as dirty.
+    pub fn set_state(&self, dom_node: &DomNodeRef, state: ElementState, on: bool) {
+        let features = dom_node
+            .replace_state(state, on)
+            .pseudo_classes()
+            .map(|name| Feature::State(name.to_string()))
+            .collect::<Vec<_>>();
+        self.invalidate(dom_node, &features);
+    }
+}
+
+impl InvalidationHook for InvalidationMap {
+    fn attr_changed(
+        &self,
+        dom_node: &DomNodeRef,
+        name: &str,
+        old_value: Option<String>,
+        new_value: Option<String>,
+    ) {
+        let name = name.to_lowercase();
+        if old_value == new_value {
+            return;
+        }
+        let mut features = vec![Feature::Attr(name.clone())];
+        let words = |value: &Option<String>| -> HashSet<String> {
+            value
+                .iter()
+                .flat_map(|value| value.split_whitespace())
+                .map(|word| word.to_string())
+                .collect()
+        };
+        match name.as_str() {
+            "class" => {
+                let (old_classes, new_classes) = (words(&old_value), words(&new_value));
+                let changed = old_classes.symmetric_difference(&new_classes);
+                features.extend(changed.map(|class| Feature::Class(class.clone())));
+            }
+            "id" => features.extend(
+                old_value
+                    .into_iter()
+                    .chain(new_value)
+                    .map(Feature::Id),
+            ),
+            _ => (),
+        }
+        self.invalidate(dom_node, &features);
+        // Inline declarations are read from it, whatever selectors depend on
+        if name == "style" {
+            dom_node.mark_self_style_dirty();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, parse_stylesheet};
+
+    fn invalidation_map(css: &str) -> InvalidationMap {
+        let mut map = InvalidationMap::default();
+        map.add_stylesheet(&parse_stylesheet(css).unwrap());
+        map
+    }
+
+    fn clear_dirty(node: &DomNodeRef) {
+        {
+            let mut node = node.borrow_mut();
+            node.style_dirty = false;
+            node.self_style_dirty = false;
+            node.descendants_dirty = false;
+        }
+        for child in &node.borrow().children {
+            clear_dirty(child);
+        }
+    }
+
+    // (style_dirty, self_style_dirty) of each node, in document order
+    fn dirty_bits(node: &DomNodeRef) -> Vec<(bool, bool)> {
+        let mut bits = vec![(node.borrow().style_dirty, node.borrow().self_style_dirty)];
+        for child in &node.borrow().children {
+            bits.extend(dirty_bits(child));
+        }
+        bits
+    }
+
+    #[test]
+    fn test_invalidation_scope() {
+        let map = invalidation_map(
+            ".a { color: red } .b p { color: red } .c + p, :hover > #d { color: red } \
+             p:not([title]) ~ .e { color: red } .a .f { color: red } \
+             :disabled, :checked, :lang(en) { color: red } .h::part(label) { color: red }",
+        );
+        let scope = |feature: Feature| {
+            let scope = map.scope(&feature);
+            (scope.element, scope.descendants, scope.later_siblings)
+        };
+        assert_eq!(scope(Feature::Class("a".to_string())), (true, true, false));
+        assert_eq!(scope(Feature::Class("b".to_string())), (false, true, false));
+        assert_eq!(scope(Feature::Class("c".to_string())), (false, false, true));
+        assert_eq!(scope(Feature::Id("d".to_string())), (true, false, false));
+        assert_eq!(scope(Feature::State("hover".to_string())), (false, true, false));
+        assert_eq!(scope(Feature::Attr("title".to_string())), (false, false, true));
+        assert_eq!(scope(Feature::Class("e".to_string())), (true, false, false));
+        assert_eq!(scope(Feature::Class("g".to_string())), (false, false, false));
+        // Which form controls' states follow
+        assert_eq!(scope(Feature::Attr("checked".to_string())), (true, false, false));
+        assert_eq!(scope(Feature::Attr("disabled".to_string())), (true, true, false));
+        assert_eq!(scope(Feature::Attr("lang".to_string())), (true, true, false));
+        // Parts are in their hosts' shadow trees
+        assert_eq!(scope(Feature::Attr("part".to_string())), (true, false, false));
+        assert_eq!(scope(Feature::Class("h".to_string())), (false, true, false));
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let map = invalidation_map(".a { color: red } .b p { color: red } .c + p { color: red }");
+        let root = parse_html("<div><p></p><p><span></span></p><p></p></div>").unwrap();
+        let p = root.borrow().children[1].clone();
+
+        clear_dirty(&root);
+        map.add_class(&p, "unused");
+        assert_eq!(p.borrow().attr("class"), Some(&Some("unused".to_string())));
+        assert!(!root.borrow().descendants_dirty);
+        map.add_class(&p, "a");
+        let clean = (false, false);
+        assert_eq!(dirty_bits(&root), vec![clean, clean, (false, true), clean, clean]);
+        assert!(root.borrow().descendants_dirty);
+
+        clear_dirty(&root);
+        map.set_attr(&p, "class", Some("unused b".to_string()));
+        assert_eq!(dirty_bits(&root), vec![clean, clean, (true, false), clean, clean]);
+
+        clear_dirty(&root);
+        map.remove_class(&p, "b");
+        map.remove_attr(&p, "title");
+        map.state_changed(&p, "hover");
+        assert_eq!(dirty_bits(&root), vec![clean, clean, (true, false), clean, clean]);
+
+        clear_dirty(&root);
+        map.remove_attr(&p, "class");
+        map.add_class(&p, "c");
+        assert_eq!(dirty_bits(&root), vec![clean, clean, clean, clean, (true, false)]);
+    }
+
+    #[test]
+    fn test_invalidation_hook() {
+        use magicparser::set_invalidation_hook;
+        use std::rc::Rc;
+
+        let map = invalidation_map(".a { color: red } .b p { color: red }");
+        let root = parse_html("<div><p></p><p><span></span></p><p></p></div>").unwrap();
+        let p = root.borrow().children[1].clone();
+        let clean = (false, false);
+        assert!(set_invalidation_hook(Some(Rc::new(map))).is_none());
+
+        clear_dirty(&root);
+        p.class_list().add("unused");
+        p.set_attribute("title", Some("t".to_string()));
+        assert_eq!(dirty_bits(&root), vec![clean, clean, clean, clean, clean]);
+        assert!(!p.class_list().toggle("unused"));
+        p.class_list().toggle("a");
+        assert_eq!(dirty_bits(&root), vec![clean, clean, (false, true), clean, clean]);
+
+        clear_dirty(&root);
+        p.remove_attribute("class");
+        p.add_class("b");
+        assert_eq!(dirty_bits(&root), vec![clean, clean, (true, true), clean, clean]);
+
+        // Without the hook, later siblings are marked too
+        assert!(set_invalidation_hook(None).is_some());
+        clear_dirty(&root);
+        p.set_attribute("title", None);
+        assert_eq!(dirty_bits(&root), vec![clean, clean, (true, false), clean, (true, false)]);
+    }
+
+    #[test]
+    fn test_invalidate_quirks() {
+        let mut config = MatcherConfig::default();
+        config.quirks_mode = QuirksMode::Quirks;
+        let mut map = InvalidationMap::new(&config);
+        map.add_stylesheet(&parse_stylesheet(".foo, #Bar { color: red }").unwrap());
+        let root = parse_html("<div><p></p></div>").unwrap();
+        let p = root.borrow().children[0].clone();
+        let clean = (false, false);
+
+        clear_dirty(&root);
+        map.add_class(&p, "FOO");
+        assert_eq!(dirty_bits(&root), vec![clean, (false, true)]);
+        clear_dirty(&root);
+        map.set_attr(&p, "id", Some("bar".to_string()));
+        assert_eq!(dirty_bits(&root), vec![clean, (false, true)]);
+
+        // Only in quirks mode
+        let map = invalidation_map(".foo { color: red }");
+        clear_dirty(&root);
+        map.add_class(&p, "Foo");
+        assert_eq!(dirty_bits(&root), vec![clean, clean]);
+    }
+
+    #[test]
+    fn test_invalidate_state() {
+        let map = invalidation_map("p:hover > span { color: red }");
+        let root = parse_html("<div><p></p><p><span></span></p><p></p></div>").unwrap();
+        let p = root.borrow().children[1].clone();
+        let clean = (false, false);
+
+        clear_dirty(&root);
+        map.set_state(&p, ElementState::FOCUS, true);
+        assert_eq!(dirty_bits(&root), vec![clean, clean, clean, clean, clean]);
+        map.set_state(&p, ElementState::HOVER | ElementState::FOCUS, true);
+        assert_eq!(dirty_bits(&root), vec![clean, clean, (true, false), clean, clean]);
+        assert_eq!(p.borrow().state, ElementState::HOVER | ElementState::FOCUS);
+
+        // Nothing changes if the node already has the state
+        clear_dirty(&root);
+        map.set_state(&p, ElementState::HOVER, true);
+        assert_eq!(dirty_bits(&root), vec![clean, clean, clean, clean, clean]);
+    }
+}
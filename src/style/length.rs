@@ -0,0 +1,298 @@
+use std::fmt;
+use style::calc::{parse_calc, CalcNode, CalcValue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LengthUnit {
+    Px,
+    Cm,
+    Mm,
+    Q,
+    In,
+    Pt,
+    Pc,
+    Em,
+    Rem,
+    Ex,
+    Ch,
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+}
+
+impl LengthUnit {
+    /// Returns None for unknown units. Units are case-insensitive.
+    pub fn parse(unit: &str) -> Option<LengthUnit> {
+        Some(match unit.to_ascii_lowercase().as_str() {
+            "px" => LengthUnit::Px,
+            "cm" => LengthUnit::Cm,
+            "mm" => LengthUnit::Mm,
+            "q" => LengthUnit::Q,
+            "in" => LengthUnit::In,
+            "pt" => LengthUnit::Pt,
+            "pc" => LengthUnit::Pc,
+            "em" => LengthUnit::Em,
+            "rem" => LengthUnit::Rem,
+            "ex" => LengthUnit::Ex,
+            "ch" => LengthUnit::Ch,
+            "vw" => LengthUnit::Vw,
+            "vh" => LengthUnit::Vh,
+            "vmin" => LengthUnit::Vmin,
+            "vmax" => LengthUnit::Vmax,
+            _ => return None,
+        })
+    }
+}
+
+/// What's needed to convert relative units to px.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LengthContext {
+    // Of the element, in px. When computing font-size itself, this is the
+    // parent's font size.
+    pub font_size: f32,
+    // Of the root element, in px
+    pub root_font_size: f32,
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+}
+
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+impl Default for LengthContext {
+    fn default() -> LengthContext {
+        LengthContext {
+            font_size: DEFAULT_FONT_SIZE,
+            root_font_size: DEFAULT_FONT_SIZE,
+            viewport_width: 800.0,
+            viewport_height: 600.0,
+        }
+    }
+}
+
+/// A length with a unit, e.g. `1.5em`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Length {
+    pub value: f32,
+    pub unit: LengthUnit,
+}
+
+// Splits e.g. "-1.5em" into (-1.5, "em")
+fn split_number(value: &str) -> Option<(f32, &str)> {
+    let is_sign = |i: usize, ch: char| i == 0 && (ch == '-' || ch == '+');
+    let is_number_char = |i: usize, ch: char| ch.is_ascii_digit() || ch == '.' || is_sign(i, ch);
+    let number_len = value
+        .char_indices()
+        .find(|&(i, ch)| !is_number_char(i, ch))
+        .map_or(value.len(), |(i, _)| i);
+    let number = value[..number_len].parse::<f32>().ok()?;
+    Some((number, &value[number_len..]))
+}
+
+impl Length {
+    pub fn new(value: f32, unit: LengthUnit) -> Length {
+        Length { value, unit }
+    }
+
+    pub fn px(value: f32) -> Length {
+        Length::new(value, LengthUnit::Px)
+    }
+
+    /// Parses a number followed by a unit. The unit may be left out if the
+    /// number is 0.
+    pub fn parse(value: &str) -> Option<Length> {
+        let (number, unit) = split_number(value.trim())?;
+        if unit.is_empty() && number == 0.0 {
+            return Some(Length::px(0.0));
+        }
+        Some(Length::new(number, LengthUnit::parse(unit)?))
+    }
+
+    pub fn to_px(&self, context: &LengthContext) -> f32 {
+        let px_per_unit = match self.unit {
+            LengthUnit::Px => 1.0,
+            LengthUnit::In => 96.0,
+            LengthUnit::Cm => 96.0 / 2.54,
+            LengthUnit::Mm => 96.0 / 25.4,
+            LengthUnit::Q => 96.0 / 101.6,
+            LengthUnit::Pt => 96.0 / 72.0,
+            LengthUnit::Pc => 16.0,
+            LengthUnit::Em => context.font_size,
+            LengthUnit::Rem => context.root_font_size,
+            // Without font metrics, assume x-height and the width of "0" are
+            // half an em
+            LengthUnit::Ex | LengthUnit::Ch => context.font_size / 2.0,
+            LengthUnit::Vw => context.viewport_width / 100.0,
+            LengthUnit::Vh => context.viewport_height / 100.0,
+            LengthUnit::Vmin => context.viewport_width.min(context.viewport_height) / 100.0,
+            LengthUnit::Vmax => context.viewport_width.max(context.viewport_height) / 100.0,
+        };
+        self.value * px_per_unit
+    }
+}
+
+/// Returns true if value has a length in vw, vh, vmin or vmax units, e.g.
+/// `calc(50vw - 1em)`, so that it depends on the viewport size.
+pub fn has_viewport_units(value: &str) -> bool {
+    value
+        .split(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '.' || ch == '-' || ch == '+'))
+        .filter_map(Length::parse)
+        .any(|length| {
+            matches!(
+                length.unit,
+                LengthUnit::Vw | LengthUnit::Vh | LengthUnit::Vmin | LengthUnit::Vmax
+            )
+        })
+}
+
+/// A specified length-or-percentage value, e.g. of `width` or `margin-left`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LengthOrPercentage {
+    Length(Length),
+    Percentage(f32),
+    Calc(CalcNode),
+}
+
+impl LengthOrPercentage {
+    pub fn parse(value: &str) -> Option<LengthOrPercentage> {
+        let value = value.trim();
+        if let Some(node) = parse_calc(value) {
+            return Some(LengthOrPercentage::Calc(node));
+        }
+        if let Some(percentage) = value.strip_suffix('%') {
+            return match split_number(percentage)? {
+                (number, "") => Some(LengthOrPercentage::Percentage(number)),
+                _ => None,
+            };
+        }
+        Length::parse(value).map(LengthOrPercentage::Length)
+    }
+
+    /// Resolves everything but percentages. Returns None if a calc()
+    /// expression doesn't evaluate to a length.
+    pub fn compute(&self, context: &LengthContext) -> Option<ComputedLength> {
+        match *self {
+            LengthOrPercentage::Length(ref length) => Some(ComputedLength {
+                px: length.to_px(context),
+                percentage: 0.0,
+            }),
+            LengthOrPercentage::Percentage(percentage) => Some(ComputedLength {
+                px: 0.0,
+                percentage,
+            }),
+            LengthOrPercentage::Calc(ref node) => match node.evaluate(context)? {
+                CalcValue::Length { px, percentage } => Some(ComputedLength { px, percentage }),
+                CalcValue::Number(_) => None,
+            },
+        }
+    }
+}
+
+/// A length in px plus a percentage of a length only known at layout.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ComputedLength {
+    pub px: f32,
+    pub percentage: f32,
+}
+
+impl ComputedLength {
+    pub fn to_px(&self, percentage_basis: f32) -> f32 {
+        self.px + percentage_basis * self.percentage / 100.0
+    }
+
+    /// Like to_px(), for a percentage_basis that may not be known, e.g. the
+    /// height of a block that's as tall as its contents. Returns None if
+    /// there's a percentage of it then.
+    pub fn resolve(&self, percentage_basis: Option<f32>) -> Option<f32> {
+        match percentage_basis {
+            Some(basis) => Some(self.to_px(basis)),
+            None if self.percentage == 0.0 => Some(self.px),
+            None => None,
+        }
+    }
+}
+
+impl fmt::Display for ComputedLength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.percentage == 0.0 {
+            write!(f, "{}px", self.px)
+        } else if self.px == 0.0 {
+            write!(f, "{}%", self.percentage)
+        } else if self.px < 0.0 {
+            write!(f, "calc({}% - {}px)", self.percentage, -self.px)
+        } else {
+            write!(f, "calc({}% + {}px)", self.percentage, self.px)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_length() {
+        assert_eq!(Length::parse("12px"), Some(Length::px(12.0)));
+        assert_eq!(Length::parse(" -1.5EM "), Some(Length::new(-1.5, LengthUnit::Em)));
+        assert_eq!(Length::parse("0"), Some(Length::px(0.0)));
+        assert_eq!(Length::parse("1"), None);
+        assert_eq!(Length::parse("1furlong"), None);
+        assert_eq!(Length::parse("px"), None);
+        assert_eq!(Length::parse("auto"), None);
+    }
+
+    #[test]
+    fn test_length_to_px() {
+        let context = LengthContext {
+            font_size: 20.0,
+            root_font_size: 10.0,
+            viewport_width: 1000.0,
+            viewport_height: 500.0,
+        };
+        let to_px = |value: &str| Length::parse(value).unwrap().to_px(&context);
+        assert_eq!(to_px("3px"), 3.0);
+        assert_eq!(to_px("1in"), 96.0);
+        assert_eq!(to_px("12pt"), 16.0);
+        assert_eq!(to_px("2em"), 40.0);
+        assert_eq!(to_px("2rem"), 20.0);
+        assert_eq!(to_px("2ex"), 20.0);
+        assert_eq!(to_px("10vw"), 100.0);
+        assert_eq!(to_px("10vh"), 50.0);
+        assert_eq!(to_px("10vmin"), 50.0);
+        assert_eq!(to_px("10vmax"), 100.0);
+    }
+
+    #[test]
+    fn test_has_viewport_units() {
+        assert!(has_viewport_units("10vw"));
+        assert!(has_viewport_units("0 calc(100VMIN - 2em)"));
+        assert!(!has_viewport_units("1em 2px 50%"));
+        assert!(!has_viewport_units("vw"));
+    }
+
+    #[test]
+    fn test_compute_length_or_percentage() {
+        let compute = |value: &str| {
+            LengthOrPercentage::parse(value)
+                .and_then(|value| value.compute(&LengthContext::default()))
+                .map(|computed| computed.to_string())
+        };
+        assert_eq!(compute("2em"), Some("32px".to_string()));
+        assert_eq!(compute("50%"), Some("50%".to_string()));
+        assert_eq!(compute("calc(50% - 1em)"), Some("calc(50% - 16px)".to_string()));
+        assert_eq!(compute("calc(2 * 1px)"), Some("2px".to_string()));
+        assert_eq!(compute("calc(2 * 1)"), None);
+        assert_eq!(compute("5%%"), None);
+        assert_eq!(compute("auto"), None);
+    }
+
+    #[test]
+    fn test_resolve_computed_length() {
+        let length = ComputedLength {
+            px: 2.0,
+            percentage: 50.0,
+        };
+        assert_eq!(length.resolve(Some(10.0)), Some(7.0));
+        assert_eq!(length.resolve(None), None);
+        assert_eq!(ComputedLength { px: 2.0, percentage: 0.0 }.resolve(None), Some(2.0));
+    }
+}
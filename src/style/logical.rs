@@ -0,0 +1,200 @@
+use magicparser::Declaration;
+use style::properties::{longhands, CssWideKeyword};
+use style::resolver::ComputedStyle;
+
+// Logical shorthands and the start and end longhands their one or two values
+// go to
+const LOGICAL_SHORTHANDS: &[(&str, &str, &str)] = &[
+    ("inset-block", "inset-block-start", "inset-block-end"),
+    ("inset-inline", "inset-inline-start", "inset-inline-end"),
+    ("margin-block", "margin-block-start", "margin-block-end"),
+    ("margin-inline", "margin-inline-start", "margin-inline-end"),
+    ("padding-block", "padding-block-start", "padding-block-end"),
+    ("padding-inline", "padding-inline-start", "padding-inline-end"),
+];
+
+/// Returns true if property is a logical property or shorthand, e.g.
+/// `margin-inline-start` or `padding-block`.
+pub fn is_logical(property: &str) -> bool {
+    LOGICAL_SHORTHANDS.iter().any(|&(shorthand, start, end)| {
+        property == shorthand || property == start || property == end
+    })
+}
+
+/// Returns the physical property a logical longhand maps to in writing_mode
+/// and direction, e.g. `margin-right` for `margin-inline-start` in
+/// right-to-left horizontal text.
+pub fn physical_property(
+    property: &str,
+    writing_mode: &str,
+    direction: &str,
+) -> Option<&'static str> {
+    let (prefix, side) = if let Some(side) = property.strip_prefix("inset-") {
+        ("", side)
+    } else if let Some(side) = property.strip_prefix("margin-") {
+        ("margin-", side)
+    } else if let Some(side) = property.strip_prefix("padding-") {
+        ("padding-", side)
+    } else {
+        return None;
+    };
+    let vertical = writing_mode != "horizontal-tb";
+    let rtl = direction == "rtl";
+    // Which edge each end of each axis is on
+    let (block_start, block_end) = match writing_mode {
+        "vertical-rl" | "sideways-rl" => ("right", "left"),
+        "vertical-lr" | "sideways-lr" => ("left", "right"),
+        _ => ("top", "bottom"),
+    };
+    let (inline_start, inline_end) = match (vertical, rtl) {
+        (false, false) => ("left", "right"),
+        (false, true) => ("right", "left"),
+        (true, false) => ("top", "bottom"),
+        (true, true) => ("bottom", "top"),
+    };
+    let edge = match side {
+        "block-start" => block_start,
+        "block-end" => block_end,
+        "inline-start" => inline_start,
+        "inline-end" => inline_end,
+        _ => return None,
+    };
+    let physical = match (prefix, edge) {
+        ("", "top") => "top",
+        ("", "right") => "right",
+        ("", "bottom") => "bottom",
+        ("", "left") => "left",
+        ("margin-", "top") => "margin-top",
+        ("margin-", "right") => "margin-right",
+        ("margin-", "bottom") => "margin-bottom",
+        ("margin-", "left") => "margin-left",
+        ("padding-", "top") => "padding-top",
+        ("padding-", "right") => "padding-right",
+        ("padding-", "bottom") => "padding-bottom",
+        _ => "padding-left",
+    };
+    Some(physical)
+}
+
+/// Removes the logical properties from a cascaded style whose writing-mode
+/// and direction are computed, and returns the (physical property, value)
+/// pairs they map to. declarations are the ones the style was cascaded from,
+/// in cascade order. When a logical and a physical property map to the same
+/// property, whichever was declared later wins, so physical properties
+/// declared after a logical one, on their own or through a shorthand like
+/// `margin`, are left out.
+pub fn map_logical_properties(
+    style: &mut ComputedStyle,
+    declarations: &[&Declaration],
+) -> Vec<(&'static str, String)> {
+    let position = |property: &str| {
+        declarations.iter().rposition(|declaration| {
+            declaration.property.eq_ignore_ascii_case(property)
+                || longhands(&declaration.property)
+                    .is_some_and(|longhands| longhands.contains(&property))
+        })
+    };
+    // (longhand, value, position of its declaration)
+    let mut logical = vec![];
+    for &(shorthand, start, end) in LOGICAL_SHORTHANDS {
+        if let Some(value) = style.remove(shorthand) {
+            let values = value.split_whitespace().collect::<Vec<_>>();
+            let (start_value, end_value) = match values[..] {
+                [both] => (both, both),
+                [start_value, end_value] if CssWideKeyword::parse(&value).is_none() => {
+                    (start_value, end_value)
+                }
+                _ => continue,
+            };
+            let shorthand_position = position(shorthand);
+            logical.push((start, start_value.to_string(), shorthand_position));
+            logical.push((end, end_value.to_string(), shorthand_position));
+        }
+    }
+    for &(_, start, end) in LOGICAL_SHORTHANDS {
+        for longhand in &[start, end] {
+            if let Some(value) = style.remove(longhand) {
+                logical.push((longhand, value, position(longhand)));
+            }
+        }
+    }
+    let writing_mode = style.get("writing-mode").unwrap_or("horizontal-tb").to_string();
+    let direction = style.get("direction").unwrap_or("ltr").to_string();
+    let mut mapped: Vec<(&'static str, String, Option<usize>)> = vec![];
+    for (longhand, value, longhand_position) in logical {
+        let physical = match physical_property(longhand, &writing_mode, &direction) {
+            Some(physical) => physical,
+            None => continue,
+        };
+        if position(physical) > longhand_position {
+            continue;
+        }
+        match mapped.iter_mut().find(|(property, _, _)| *property == physical) {
+            Some(earlier) if earlier.2 > longhand_position => (),
+            Some(earlier) => *earlier = (physical, value, longhand_position),
+            None => mapped.push((physical, value, longhand_position)),
+        }
+    }
+    mapped.into_iter().map(|(physical, value, _)| (physical, value)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_physical_property() {
+        let physical = |property: &str, writing_mode: &str, direction: &str| {
+            physical_property(property, writing_mode, direction).unwrap()
+        };
+        assert_eq!(physical("margin-inline-start", "horizontal-tb", "ltr"), "margin-left");
+        assert_eq!(physical("margin-inline-start", "horizontal-tb", "rtl"), "margin-right");
+        assert_eq!(physical("padding-block-end", "horizontal-tb", "rtl"), "padding-bottom");
+        assert_eq!(physical("padding-block-start", "vertical-rl", "ltr"), "padding-right");
+        assert_eq!(physical("inset-block-start", "vertical-lr", "ltr"), "left");
+        assert_eq!(physical("inset-inline-end", "vertical-lr", "rtl"), "top");
+        assert_eq!(physical_property("margin-top", "horizontal-tb", "ltr"), None);
+        assert!(is_logical("padding-inline"));
+        assert!(!is_logical("padding-left"));
+    }
+
+    #[test]
+    fn test_map_logical_properties() {
+        let decl = |property: &str, value: &str| {
+            Declaration::new(property.to_string(), value.to_string())
+        };
+        let declarations = vec![
+            decl("margin-inline-start", "1px"),
+            decl("margin-left", "2px"),
+            decl("margin-inline-end", "3px"),
+            decl("padding-block", "4px 5px"),
+            decl("padding-block-end", "6px"),
+            decl("padding-top", "7px"),
+        ];
+        let mut style = ComputedStyle::default();
+        for declaration in &declarations {
+            style.set(&declaration.property, &declaration.value);
+        }
+        style.set("direction", "rtl");
+        let declarations = declarations.iter().collect::<Vec<_>>();
+        let mut mapped = map_logical_properties(&mut style, &declarations);
+        mapped.sort();
+        assert_eq!(
+            mapped,
+            vec![
+                ("margin-left", "3px".to_string()),
+                ("margin-right", "1px".to_string()),
+                ("padding-bottom", "6px".to_string()),
+            ]
+        );
+        assert_eq!(style.get("margin-inline-start"), None);
+        assert_eq!(style.get("padding-block"), None);
+
+        // A later shorthand sets the physical property too
+        let declarations = [decl("padding-inline-end", "1px"), decl("padding", "2px")];
+        let mut style = ComputedStyle::default();
+        style.set("padding-inline-end", "1px");
+        let declarations = declarations.iter().collect::<Vec<_>>();
+        assert!(map_logical_properties(&mut style, &declarations).is_empty());
+    }
+}
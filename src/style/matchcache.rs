@@ -0,0 +1,133 @@
+use magicparser::{dom_revision, DomNodeRef, NodeId, Selector, ToCss};
+use std::collections::HashMap;
+use style::docindex::DocumentIndex;
+use style::selectormatcher::{matches_with_config, query_selector_all, MatcherConfig};
+
+/// Remembers the results of matching and querying selectors, so that repeating
+/// them over a DOM that hasn't changed is a lookup. Everything is forgotten
+/// once the DOM revision (see dom_revision()) moves on.
+///
+/// Queries for just an id, a class or an element type are answered from a
+/// DocumentIndex of the root, built the first time one is made.
+///
+/// Results are only reused for the same config, so custom pseudo-class
+/// matchers must not depend on anything but the DOM.
+#[derive(Debug)]
+pub struct MatchCache {
+    config: MatcherConfig,
+    // DOM revision the results were computed at
+    revision: usize,
+    // Keyed by DomNode::id_num and serialized selector
+    matches: HashMap<(usize, String), bool>,
+    queries: HashMap<(usize, String), Vec<NodeId>>,
+    // Keyed by the id_num of the root
+    indexes: HashMap<usize, DocumentIndex>,
+}
+
+impl MatchCache {
+    pub fn new(config: MatcherConfig) -> MatchCache {
+        MatchCache {
+            config,
+            revision: dom_revision(),
+            matches: HashMap::new(),
+            queries: HashMap::new(),
+            indexes: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &MatcherConfig {
+        &self.config
+    }
+
+    /// Like matches_with_config().
+    pub fn matches(&mut self, dom_node: &DomNodeRef, selector: &Selector) -> bool {
+        self.forget_if_stale();
+        let key = (dom_node.borrow().id_num, selector.to_css_string());
+        if let Some(&matched) = self.matches.get(&key) {
+            return matched;
+        }
+        let matched = matches_with_config(dom_node, selector, &self.config);
+        self.matches.insert(key, matched);
+        matched
+    }
+
+    /// Like selectormatcher::query_selector_all().
+    pub fn query_selector_all(
+        &mut self,
+        root: &DomNodeRef,
+        selector: &Selector,
+    ) -> Vec<DomNodeRef> {
+        self.forget_if_stale();
+        let key = (root.borrow().id_num, selector.to_css_string());
+        if let Some(found) = self.queries.get(&key) {
+            // The DOM hasn't changed, so none of the nodes have been freed
+            return found.iter().filter_map(|&id| DomNodeRef::from_node_id(id)).collect();
+        }
+        let config = &self.config;
+        let index = self.indexes.entry(key.0).or_insert_with(|| DocumentIndex::new(root, config));
+        let found = match index.lookup(selector) {
+            Some(found) => found,
+            None => query_selector_all(root, selector, config),
+        };
+        self.queries.insert(key, found.iter().map(|node| node.node_id()).collect());
+        found
+    }
+
+    /// Like selectormatcher::query_selector().
+    pub fn query_selector(
+        &mut self,
+        root: &DomNodeRef,
+        selector: &Selector,
+    ) -> Option<DomNodeRef> {
+        self.query_selector_all(root, selector).into_iter().next()
+    }
+
+    fn forget_if_stale(&mut self) {
+        let revision = dom_revision();
+        if revision != self.revision {
+            self.matches.clear();
+            self.queries.clear();
+            self.indexes.clear();
+            self.revision = revision;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_css, parse_html, CssBlocks};
+
+    fn selector(css: &str) -> Selector {
+        let CssBlocks(mut blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+        blocks.remove(0).0
+    }
+
+    #[test]
+    fn test_match_cache() {
+        let root = parse_html("<div><p class=\"a\">One</p><p>Two</p></div>").unwrap();
+        let p2 = root.borrow().children[1].clone();
+        let mut cache = MatchCache::new(MatcherConfig::default());
+        let a = selector(".a");
+        let ids = |nodes: Vec<DomNodeRef>| {
+            nodes.iter().map(|node| node.borrow().id_num).collect::<Vec<_>>()
+        };
+        let p1_id = root.borrow().children[0].borrow().id_num;
+
+        assert_eq!(ids(cache.query_selector_all(&root, &a)), vec![p1_id]);
+        assert!(!cache.matches(&p2, &a));
+        assert_eq!(cache.queries.len(), 1);
+        assert_eq!(ids(cache.query_selector_all(&root, &a)), vec![p1_id]);
+        assert!(!cache.matches(&p2, &a));
+
+        // A mutation invalidates the results
+        p2.set_attribute("class", Some("a".to_string()));
+        assert!(cache.matches(&p2, &a));
+        assert_eq!(cache.queries.len(), 0);
+        assert_eq!(ids(cache.query_selector_all(&root, &a)), vec![p1_id, p2.borrow().id_num]);
+        assert!(cache.query_selector(&root, &selector("p + p")).unwrap().ptr_eq(&p2));
+        assert_eq!(cache.indexes.len(), 1);
+        assert_eq!(ids(cache.query_selector_all(&p2, &selector("p"))), Vec::<usize>::new());
+        assert_eq!(cache.indexes.len(), 2);
+    }
+}
@@ -0,0 +1,475 @@
+use magicparser::{ContainerQuery, MediaFeature, MediaFeatureOp, MediaQuery, MediaQueryList};
+use style::length::{Length, LengthContext, DEFAULT_FONT_SIZE};
+use style::resolver::ComputedStyle;
+
+/// The color theme the user prefers, for `prefers-color-scheme`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+/// How accurate the primary pointing device is, for `pointer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pointer {
+    // There's no pointing device
+    None,
+    // e.g. a touchscreen
+    Coarse,
+    // e.g. a mouse
+    Fine,
+}
+
+/// The window a document is shown in, which it's laid out in and @media
+/// rules are evaluated against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    // In px
+    pub width: f32,
+    pub height: f32,
+    // Device pixels per px, e.g. 2 on a high-density screen, or when zoomed
+    // in to 200%
+    pub device_pixel_ratio: f32,
+    // The user's preferred font size, that of `medium`, in px
+    pub font_size: f32,
+}
+
+impl Viewport {
+    /// Returns a viewport width by height px, with a device pixel per px and
+    /// a 16px font size.
+    pub fn new(width: f32, height: f32) -> Viewport {
+        Viewport {
+            width,
+            height,
+            device_pixel_ratio: 1.0,
+            font_size: DEFAULT_FONT_SIZE,
+        }
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Viewport {
+        Viewport::new(800.0, 600.0)
+    }
+}
+
+/// The device a document is styled for, which @media rules are evaluated
+/// against.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaContext {
+    // Lowercase, e.g. "screen" or "print"
+    pub media_type: String,
+    // In px
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+    // Device pixels per px
+    pub resolution: f32,
+    // Of `medium`, the root element's initial font size, which relative
+    // lengths in media queries are relative to, in px
+    pub font_size: f32,
+    pub color_scheme: ColorScheme,
+    // True if the user asked for less non-essential motion
+    pub reduced_motion: bool,
+    // True if the primary pointing device can hover over elements
+    pub hover: bool,
+    pub pointer: Pointer,
+}
+
+impl Default for MediaContext {
+    fn default() -> MediaContext {
+        MediaContext {
+            media_type: "screen".to_string(),
+            viewport_width: 800.0,
+            viewport_height: 600.0,
+            resolution: 1.0,
+            font_size: DEFAULT_FONT_SIZE,
+            color_scheme: ColorScheme::Light,
+            reduced_motion: false,
+            hover: true,
+            pointer: Pointer::Fine,
+        }
+    }
+}
+
+fn compare(actual: f32, op: MediaFeatureOp, expected: f32) -> bool {
+    match op {
+        MediaFeatureOp::Eq => actual == expected,
+        MediaFeatureOp::Lt => actual < expected,
+        MediaFeatureOp::Le => actual <= expected,
+        MediaFeatureOp::Gt => actual > expected,
+        MediaFeatureOp::Ge => actual >= expected,
+    }
+}
+
+// e.g. "16/9" or "2"
+fn parse_ratio(value: &str) -> Option<f32> {
+    let mut parts = value.splitn(2, '/');
+    let numerator = parts.next()?.trim().parse::<f32>().ok()?;
+    let denominator = match parts.next() {
+        Some(denominator) => denominator.trim().parse::<f32>().ok()?,
+        None => 1.0,
+    };
+    if denominator == 0.0 {
+        None
+    } else {
+        Some(numerator / denominator)
+    }
+}
+
+// In dppx
+fn parse_resolution(value: &str) -> Option<f32> {
+    let value = value.trim().to_ascii_lowercase();
+    let (number, dppx_per_unit) = if let Some(number) = value.strip_suffix("dppx") {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix("dpcm") {
+        (number, 2.54 / 96.0)
+    } else if let Some(number) = value.strip_suffix("dpi") {
+        (number, 1.0 / 96.0)
+    } else if let Some(number) = value.strip_suffix('x') {
+        (number, 1.0)
+    } else {
+        return None;
+    };
+    Some(number.parse::<f32>().ok()? * dppx_per_unit)
+}
+
+impl MediaContext {
+    pub fn viewport(&self) -> Viewport {
+        Viewport {
+            width: self.viewport_width,
+            height: self.viewport_height,
+            device_pixel_ratio: self.resolution,
+            font_size: self.font_size,
+        }
+    }
+
+    /// Sets the viewport size, resolution and font size to viewport's.
+    pub fn set_viewport(&mut self, viewport: &Viewport) {
+        self.viewport_width = viewport.width;
+        self.viewport_height = viewport.height;
+        self.resolution = viewport.device_pixel_ratio;
+        self.font_size = viewport.font_size;
+    }
+
+    /// Returns true if any query in the list matches, or the list is empty.
+    pub fn matches(&self, query_list: &MediaQueryList) -> bool {
+        let MediaQueryList(ref queries) = *query_list;
+        queries.is_empty() || queries.iter().any(|query| self.matches_query(query))
+    }
+
+    fn matches_query(&self, query: &MediaQuery) -> bool {
+        let type_matches = match query.media_type {
+            Some(ref media_type) => media_type == "all" || *media_type == self.media_type,
+            None => true,
+        };
+        let matches =
+            type_matches && query.features.iter().all(|feature| self.matches_feature(feature));
+        matches != query.negated
+    }
+
+    // Unknown features and invalid values never match
+    fn matches_feature(&self, feature: &MediaFeature) -> bool {
+        // Relative lengths in media queries are relative to the initial font
+        // size
+        let context = LengthContext {
+            font_size: self.font_size,
+            root_font_size: self.font_size,
+            viewport_width: self.viewport_width,
+            viewport_height: self.viewport_height,
+        };
+        let to_px = |value: &str| Length::parse(value).map(|length| length.to_px(&context));
+        let (width, height) = (self.viewport_width, self.viewport_height);
+        let op_value = feature.op_value.as_ref().map(|(op, value)| (*op, value.as_str()));
+        match (feature.name.as_str(), op_value) {
+            ("width", None) => width != 0.0,
+            ("width", Some((op, value))) => to_px(value).is_some_and(|px| compare(width, op, px)),
+            ("height", None) => height != 0.0,
+            ("height", Some((op, value))) => {
+                to_px(value).is_some_and(|px| compare(height, op, px))
+            }
+            ("aspect-ratio", None) => true,
+            ("aspect-ratio", Some((op, value))) => {
+                height != 0.0
+                    && parse_ratio(value).is_some_and(|ratio| compare(width / height, op, ratio))
+            }
+            ("orientation", None) => true,
+            ("orientation", Some((MediaFeatureOp::Eq, value))) => {
+                match value.to_ascii_lowercase().as_str() {
+                    "portrait" => height >= width,
+                    "landscape" => width > height,
+                    _ => false,
+                }
+            }
+            ("resolution", None) => self.resolution != 0.0,
+            ("resolution", Some((op, value))) => {
+                parse_resolution(value).is_some_and(|dppx| compare(self.resolution, op, dppx))
+            }
+            _ => self.matches_preference(feature),
+        }
+    }
+
+    // Features with keyword values, about the user's preferences and input
+    // devices. There's only one pointing device, so the any- features are the
+    // same as the others.
+    fn matches_preference(&self, feature: &MediaFeature) -> bool {
+        let value = match feature.op_value {
+            Some((MediaFeatureOp::Eq, ref value)) => Some(value.to_ascii_lowercase()),
+            Some(_) => return false,
+            None => None,
+        };
+        let pointer = match self.pointer {
+            Pointer::None => "none",
+            Pointer::Coarse => "coarse",
+            Pointer::Fine => "fine",
+        };
+        let (actual, false_value) = match feature.name.as_str() {
+            "prefers-color-scheme" => match self.color_scheme {
+                ColorScheme::Light => ("light", None),
+                ColorScheme::Dark => ("dark", None),
+            },
+            "prefers-reduced-motion" if self.reduced_motion => ("reduce", Some("no-preference")),
+            "prefers-reduced-motion" => ("no-preference", Some("no-preference")),
+            "hover" | "any-hover" if self.hover => ("hover", Some("none")),
+            "hover" | "any-hover" => ("none", Some("none")),
+            "pointer" | "any-pointer" => (pointer, Some("none")),
+            _ => return false,
+        };
+        // In a boolean context, e.g. (hover), features match unless they
+        // have the value that means false
+        match value {
+            Some(value) => value == actual,
+            None => false_value != Some(actual),
+        }
+    }
+}
+
+/// An element whose size @container rules in its subtree are evaluated
+/// against, as it was laid out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryContainer {
+    // Only the inline axis can be queried unless container-type is size
+    pub size_contained: bool,
+    pub names: Vec<String>,
+    // True if the inline axis is vertical
+    pub vertical: bool,
+    // Of the content box, in px
+    pub width: f32,
+    pub height: f32,
+    // What relative lengths in queries are resolved against
+    pub context: LengthContext,
+}
+
+impl QueryContainer {
+    /// Returns None if style's container-type is normal, in which case the
+    /// element isn't a container.
+    pub fn new(
+        style: &ComputedStyle,
+        context: LengthContext,
+        width: f32,
+        height: f32,
+    ) -> Option<QueryContainer> {
+        let size_contained = match style.get("container-type") {
+            Some("size") => true,
+            Some("inline-size") => false,
+            _ => return None,
+        };
+        let names = match style.get("container-name") {
+            Some(names) if !names.eq_ignore_ascii_case("none") => {
+                names.split_whitespace().map(|name| name.to_string()).collect()
+            }
+            _ => vec![],
+        };
+        let vertical = style.get("writing-mode").is_some_and(|mode| mode != "horizontal-tb");
+        Some(QueryContainer {
+            size_contained,
+            names,
+            vertical,
+            width,
+            height,
+            context,
+        })
+    }
+
+    /// Returns true if query can be evaluated against the container, which
+    /// it can if it names the container, or no container, and only queries
+    /// the axes the container's container-type allows.
+    pub fn is_eligible(&self, query: &ContainerQuery) -> bool {
+        let name_matches = query.name.as_ref().is_none_or(|name| self.names.contains(name));
+        name_matches
+            && (self.size_contained
+                || query.features.iter().all(|feature| self.inline_axis(&feature.name)))
+    }
+
+    // True if feature only depends on the size of the inline axis
+    fn inline_axis(&self, feature: &str) -> bool {
+        match feature {
+            "inline-size" => true,
+            "width" => !self.vertical,
+            "height" => self.vertical,
+            _ => false,
+        }
+    }
+
+    /// Returns true if every feature of query matches, or if it's negated,
+    /// if any doesn't.
+    pub fn matches(&self, query: &ContainerQuery) -> bool {
+        let matches = query.features.iter().all(|feature| self.matches_feature(feature));
+        matches != query.negated
+    }
+
+    // Unknown features and invalid values never match
+    fn matches_feature(&self, feature: &MediaFeature) -> bool {
+        let to_px = |value: &str| Length::parse(value).map(|length| length.to_px(&self.context));
+        let (width, height) = (self.width, self.height);
+        let (inline_size, block_size) = if self.vertical {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let size = match feature.name.as_str() {
+            "width" => Some(width),
+            "height" => Some(height),
+            "inline-size" => Some(inline_size),
+            "block-size" => Some(block_size),
+            _ => None,
+        };
+        let op_value = feature.op_value.as_ref().map(|(op, value)| (*op, value.as_str()));
+        if let Some(size) = size {
+            return match op_value {
+                None => size != 0.0,
+                Some((op, value)) => to_px(value).is_some_and(|px| compare(size, op, px)),
+            };
+        }
+        match (feature.name.as_str(), op_value) {
+            ("aspect-ratio", None) => true,
+            ("aspect-ratio", Some((op, value))) => {
+                height != 0.0
+                    && parse_ratio(value).is_some_and(|ratio| compare(width / height, op, ratio))
+            }
+            ("orientation", None) => true,
+            ("orientation", Some((MediaFeatureOp::Eq, value))) => {
+                match value.to_ascii_lowercase().as_str() {
+                    "portrait" => height >= width,
+                    "landscape" => width > height,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(query_list: &str) -> bool {
+        let context = MediaContext {
+            viewport_width: 1000.0,
+            viewport_height: 500.0,
+            resolution: 2.0,
+            ..MediaContext::default()
+        };
+        context.matches(&MediaQueryList::parse(query_list))
+    }
+
+    #[test]
+    fn test_media_type() {
+        assert!(matches(""));
+        assert!(matches("screen"));
+        assert!(matches("all"));
+        assert!(matches("print, screen"));
+        assert!(!matches("print"));
+        assert!(matches("not print"));
+        assert!(!matches("not all"));
+        assert!(!matches("screen and"));
+    }
+
+    #[test]
+    fn test_media_features() {
+        assert!(matches("(min-width: 1000px) and (max-width: 1200px)"));
+        assert!(!matches("screen and (min-width: 1001px)"));
+        assert!(matches("(width > 60em)"));
+        assert!(!matches("(width < 60em)"));
+        assert!(!matches("(height: 50vh)"));
+        assert!(matches("(height: 100vh)"));
+        assert!(matches("(orientation: landscape)"));
+        assert!(!matches("(orientation: portrait)"));
+        assert!(matches("(aspect-ratio: 2/1)"));
+        assert!(matches("(min-aspect-ratio: 16/9)"));
+        assert!(matches("(min-resolution: 192dpi)"));
+        assert!(!matches("(resolution: 1x)"));
+        assert!(matches("(width)"));
+        assert!(!matches("(scan: progressive)"));
+        assert!(matches("not screen and (max-width: 600px)"));
+    }
+
+    #[test]
+    fn test_preference_features() {
+        let context = MediaContext::default();
+        let matches = |context: &MediaContext, query_list: &str| {
+            context.matches(&MediaQueryList::parse(query_list))
+        };
+        assert!(matches(&context, "(prefers-color-scheme: light)"));
+        assert!(!matches(&context, "(prefers-color-scheme: dark)"));
+        assert!(matches(&context, "(prefers-color-scheme)"));
+        assert!(matches(&context, "(prefers-reduced-motion: no-preference)"));
+        assert!(!matches(&context, "(prefers-reduced-motion)"));
+        assert!(matches(&context, "(hover) and (pointer: fine)"));
+        assert!(matches(&context, "(any-pointer)"));
+        assert!(!matches(&context, "(min-hover: hover)"));
+
+        let context = MediaContext {
+            color_scheme: ColorScheme::Dark,
+            reduced_motion: true,
+            hover: false,
+            pointer: Pointer::Coarse,
+            ..MediaContext::default()
+        };
+        assert!(matches(&context, "(prefers-color-scheme: DARK)"));
+        assert!(matches(&context, "(prefers-reduced-motion)"));
+        assert!(matches(&context, "(hover: none) and (any-pointer: coarse)"));
+        assert!(!matches(&context, "(hover)"));
+        assert!(matches(&context, "(pointer)"));
+    }
+
+    #[test]
+    fn test_set_viewport() {
+        let viewport = Viewport {
+            device_pixel_ratio: 2.0,
+            font_size: 20.0,
+            ..Viewport::new(1000.0, 500.0)
+        };
+        let mut context = MediaContext::default();
+        context.set_viewport(&viewport);
+        assert_eq!(context.viewport(), viewport);
+        assert!(context.matches(&MediaQueryList::parse("(width: 50em) and (resolution: 2x)")));
+    }
+
+    #[test]
+    fn test_container_query() {
+        let mut style = ComputedStyle::default();
+        style.set("container-type", "inline-size");
+        style.set("container-name", "card main");
+        style.set("writing-mode", "vertical-rl");
+        let container = QueryContainer::new(&style, LengthContext::default(), 300.0, 500.0);
+        let container = container.unwrap();
+        let query = |query: &str| ContainerQuery::parse(query).unwrap();
+        assert!(container.is_eligible(&query("main (inline-size > 400px)")));
+        assert!(container.matches(&query("main (inline-size > 400px)")));
+        assert!(container.matches(&query("(height: 500px) and (max-inline-size: 40em)")));
+        assert!(!container.is_eligible(&query("sidebar (inline-size > 400px)")));
+        assert!(!container.is_eligible(&query("(width > 100px)")));
+        assert!(!container.is_eligible(&query("(orientation: portrait)")));
+
+        style.set("container-type", "size");
+        let container = QueryContainer::new(&style, LengthContext::default(), 300.0, 500.0);
+        let container = container.unwrap();
+        assert!(container.is_eligible(&query("(orientation: portrait)")));
+        assert!(container.matches(&query("(orientation: portrait) and (block-size: 300px)")));
+        assert!(container.matches(&query("not (aspect-ratio > 1)")));
+        assert!(!container.matches(&query("(hover: hover)")));
+        style.set("container-type", "normal");
+        assert_eq!(QueryContainer::new(&style, LengthContext::default(), 1.0, 1.0), None);
+    }
+}
@@ -1 +1,26 @@
+pub mod animation;
+pub mod bloom;
+pub mod calc;
+pub mod cascade;
+pub mod color;
+pub mod counters;
+pub mod declaration;
+pub mod docindex;
+pub mod font;
+pub mod interpolate;
+pub mod invalidation;
+pub mod length;
+pub mod logical;
+pub mod matchcache;
+pub mod media;
+pub mod prefixes;
+pub mod properties;
+pub mod report;
+pub mod resolver;
+pub mod rulemap;
+pub mod ruletree;
 pub mod selectormatcher;
+pub mod sharing;
+pub mod transition;
+pub mod typed;
+pub mod useragent;
@@ -0,0 +1,151 @@
+use magicparser::{Declaration, Stylesheet};
+use style::properties::lookup_property;
+
+const VENDOR_PREFIXES: &[&str] = &["-webkit-", "-moz-", "-ms-", "-o-"];
+
+// Prefixed properties whose standard name isn't just the unprefixed one
+const RENAMED_PROPERTIES: &[(&str, &str)] = &[
+    ("-moz-margin-end", "margin-inline-end"),
+    ("-moz-margin-start", "margin-inline-start"),
+    ("-moz-padding-end", "padding-inline-end"),
+    ("-moz-padding-start", "padding-inline-start"),
+    ("-webkit-margin-after", "margin-block-end"),
+    ("-webkit-margin-before", "margin-block-start"),
+    ("-webkit-margin-end", "margin-inline-end"),
+    ("-webkit-margin-start", "margin-inline-start"),
+    ("-webkit-padding-after", "padding-block-end"),
+    ("-webkit-padding-before", "padding-block-start"),
+    ("-webkit-padding-end", "padding-inline-end"),
+    ("-webkit-padding-start", "padding-inline-start"),
+];
+
+// Prefixed keywords and their standard ones, by property
+const RENAMED_KEYWORDS: &[(&str, &str, &str)] = &[
+    ("cursor", "-webkit-grab", "grab"),
+    ("cursor", "-webkit-grabbing", "grabbing"),
+    ("cursor", "-webkit-zoom-in", "zoom-in"),
+    ("cursor", "-webkit-zoom-out", "zoom-out"),
+    ("display", "-ms-flexbox", "flex"),
+    ("display", "-ms-inline-flexbox", "inline-flex"),
+    ("display", "-webkit-flex", "flex"),
+    ("display", "-webkit-inline-flex", "inline-flex"),
+];
+
+/// Returns the standard property a prefixed one is equivalent to, e.g.
+/// `transition-delay` for `-webkit-transition-delay`, or None if property
+/// isn't prefixed or isn't a well-known one.
+pub fn standard_property(property: &str) -> Option<&'static str> {
+    let property = property.to_ascii_lowercase();
+    if let Some(&(_, standard)) = RENAMED_PROPERTIES.iter().find(|(name, _)| *name == property) {
+        return Some(standard);
+    }
+    let unprefixed = VENDOR_PREFIXES
+        .iter()
+        .find_map(|prefix| property.strip_prefix(prefix))?;
+    lookup_property(unprefixed).map(|property| property.name)
+}
+
+// Replaces prefixed functions and keywords in a declaration's value
+fn standard_value(property: &str, value: &str) -> String {
+    let renamed = RENAMED_KEYWORDS
+        .iter()
+        .find(|(name, keyword, _)| *name == property && value.eq_ignore_ascii_case(keyword));
+    if let Some(&(_, _, standard)) = renamed {
+        return standard.to_string();
+    }
+    let mut value = value.to_string();
+    for prefix in VENDOR_PREFIXES {
+        let function = format!("{}calc(", prefix);
+        while let Some(start) = value.to_ascii_lowercase().find(&function) {
+            value.replace_range(start..start + prefix.len(), "");
+        }
+    }
+    value
+}
+
+/// Replaces well-known declarations with `-webkit-`, `-moz-`, `-ms-` or `-o-`
+/// prefixes, or with prefixed values like `-webkit-calc()`, with the standard
+/// ones they're equivalent to. A prefixed property is dropped instead if the
+/// standard one is declared too, so that it doesn't override it. Other
+/// prefixed declarations are left as they are.
+pub fn normalize_declarations(declarations: &mut Vec<Declaration>) {
+    let standard_properties = declarations
+        .iter()
+        .filter(|decl| standard_property(&decl.property).is_none())
+        .map(|decl| decl.property.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    declarations.retain(|decl| {
+        standard_property(&decl.property)
+            .is_none_or(|standard| !standard_properties.iter().any(|name| name == standard))
+    });
+    for decl in declarations.iter_mut() {
+        if let Some(standard) = standard_property(&decl.property) {
+            decl.property = standard.to_string();
+        }
+        decl.value = standard_value(&decl.property, &decl.value);
+    }
+}
+
+/// Normalizes the declarations of every style rule and keyframe of
+/// stylesheet; see normalize_declarations().
+pub fn normalize_stylesheet(stylesheet: &mut Stylesheet) {
+    for rule in &mut stylesheet.rules {
+        normalize_declarations(&mut rule.declarations);
+    }
+    for keyframes in &mut stylesheet.keyframes {
+        for keyframe in &mut keyframes.keyframes {
+            normalize_declarations(&mut keyframe.declarations);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_stylesheet;
+
+    #[test]
+    fn test_standard_property() {
+        assert_eq!(standard_property("-webkit-animation-name"), Some("animation-name"));
+        assert_eq!(standard_property("-MOZ-Transition-Delay"), Some("transition-delay"));
+        assert_eq!(standard_property("-webkit-margin-start"), Some("margin-inline-start"));
+        assert_eq!(standard_property("-webkit-tap-highlight-color"), None);
+        assert_eq!(standard_property("animation-name"), None);
+    }
+
+    #[test]
+    fn test_normalize_stylesheet() {
+        let mut stylesheet = parse_stylesheet(
+            "a { -webkit-animation-name: slide; display: -webkit-flex; \
+                 width: -moz-calc(100% - -webkit-calc(1px)) } \
+             b { transition-delay: 1s; -webkit-transition-delay: 2s !important; \
+                 -webkit-tap-highlight-color: red } \
+             @keyframes slide { to { -webkit-padding-start: 1px } }",
+        ).unwrap();
+        normalize_stylesheet(&mut stylesheet);
+        let declarations = |rule: &[Declaration]| {
+            rule.iter()
+                .map(|decl| (decl.property.clone(), decl.value.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            declarations(&stylesheet.rules[0].declarations),
+            vec![
+                ("animation-name".to_string(), "slide".to_string()),
+                ("display".to_string(), "flex".to_string()),
+                ("width".to_string(), "calc(100% - calc(1px))".to_string()),
+            ]
+        );
+        assert_eq!(
+            declarations(&stylesheet.rules[1].declarations),
+            vec![
+                ("transition-delay".to_string(), "1s".to_string()),
+                ("-webkit-tap-highlight-color".to_string(), "red".to_string()),
+            ]
+        );
+        assert_eq!(
+            declarations(&stylesheet.keyframes[0].keyframes[0].declarations),
+            vec![("padding-inline-start".to_string(), "1px".to_string())]
+        );
+    }
+}
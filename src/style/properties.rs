@@ -0,0 +1,200 @@
+/// What kind of values a property takes, and so how its computed value is
+/// found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    // Computed value is the specified value
+    Any,
+    // One or more space-separated lengths or percentages, which are computed
+    // to px
+    LengthOrPercentage,
+    // Computed to rgb() or rgba()
+    Color,
+    // One or more comma-separated times, which are computed to seconds
+    Time,
+}
+
+/// A CSS property the style resolver knows about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Property {
+    pub name: &'static str,
+    // If true, nodes take the parent's computed value when no declaration
+    // applies; otherwise they take the initial value
+    pub inherited: bool,
+    pub value_type: ValueType,
+    pub initial: &'static str,
+}
+
+impl Property {
+    const fn inherited(
+        name: &'static str,
+        value_type: ValueType,
+        initial: &'static str,
+    ) -> Property {
+        Property { name, inherited: true, value_type, initial }
+    }
+
+    const fn not_inherited(
+        name: &'static str,
+        value_type: ValueType,
+        initial: &'static str,
+    ) -> Property {
+        Property { name, inherited: false, value_type, initial }
+    }
+}
+
+use self::ValueType::{Any, Color, LengthOrPercentage, Time};
+
+/// Every supported property, sorted by name.
+pub static PROPERTIES: &[Property] = &[
+    Property::not_inherited("animation-delay", Time, "0s"),
+    Property::not_inherited("animation-direction", Any, "normal"),
+    Property::not_inherited("animation-duration", Time, "0s"),
+    Property::not_inherited("animation-fill-mode", Any, "none"),
+    Property::not_inherited("animation-iteration-count", Any, "1"),
+    Property::not_inherited("animation-name", Any, "none"),
+    Property::not_inherited("animation-play-state", Any, "running"),
+    Property::not_inherited("animation-timing-function", Any, "ease"),
+    Property::not_inherited("background-color", Color, "transparent"),
+    Property::not_inherited("bottom", LengthOrPercentage, "auto"),
+    Property::not_inherited("break-after", Any, "auto"),
+    Property::not_inherited("break-before", Any, "auto"),
+    Property::not_inherited("break-inside", Any, "auto"),
+    Property::not_inherited("clear", Any, "none"),
+    Property::inherited("color", Color, "black"),
+    Property::not_inherited("container-name", Any, "none"),
+    Property::not_inherited("container-type", Any, "normal"),
+    Property::not_inherited("content", Any, "normal"),
+    Property::not_inherited("counter-increment", Any, "none"),
+    Property::not_inherited("counter-reset", Any, "none"),
+    Property::not_inherited("counter-set", Any, "none"),
+    Property::inherited("cursor", Any, "auto"),
+    Property::inherited("direction", Any, "ltr"),
+    Property::not_inherited("display", Any, "inline"),
+    Property::not_inherited("float", Any, "none"),
+    Property::inherited("font-family", Any, "serif"),
+    Property::inherited("font-size", LengthOrPercentage, "medium"),
+    Property::inherited("font-stretch", Any, "normal"),
+    Property::inherited("font-style", Any, "normal"),
+    Property::inherited("font-weight", Any, "normal"),
+    Property::not_inherited("height", LengthOrPercentage, "auto"),
+    Property::not_inherited("left", LengthOrPercentage, "auto"),
+    Property::inherited("line-height", Any, "normal"),
+    Property::inherited("list-style-type", Any, "disc"),
+    Property::not_inherited("margin-bottom", LengthOrPercentage, "0"),
+    Property::not_inherited("margin-left", LengthOrPercentage, "0"),
+    Property::not_inherited("margin-right", LengthOrPercentage, "0"),
+    Property::not_inherited("margin-top", LengthOrPercentage, "0"),
+    Property::not_inherited("max-height", LengthOrPercentage, "none"),
+    Property::not_inherited("max-width", LengthOrPercentage, "none"),
+    Property::not_inherited("min-height", LengthOrPercentage, "auto"),
+    Property::not_inherited("min-width", LengthOrPercentage, "auto"),
+    Property::not_inherited("overflow-x", Any, "visible"),
+    Property::not_inherited("overflow-y", Any, "visible"),
+    Property::not_inherited("padding-bottom", LengthOrPercentage, "0"),
+    Property::not_inherited("padding-left", LengthOrPercentage, "0"),
+    Property::not_inherited("padding-right", LengthOrPercentage, "0"),
+    Property::not_inherited("padding-top", LengthOrPercentage, "0"),
+    Property::not_inherited("position", Any, "static"),
+    Property::not_inherited("right", LengthOrPercentage, "auto"),
+    Property::inherited("text-align", Any, "start"),
+    Property::not_inherited("text-decoration", Any, "none"),
+    Property::not_inherited("top", LengthOrPercentage, "auto"),
+    Property::not_inherited("transition-delay", Time, "0s"),
+    Property::not_inherited("transition-duration", Time, "0s"),
+    Property::not_inherited("transition-property", Any, "all"),
+    Property::not_inherited("transition-timing-function", Any, "ease"),
+    Property::inherited("visibility", Any, "visible"),
+    Property::inherited("white-space", Any, "normal"),
+    Property::not_inherited("width", LengthOrPercentage, "auto"),
+    Property::inherited("writing-mode", Any, "horizontal-tb"),
+    Property::not_inherited("z-index", Any, "auto"),
+];
+
+// Shorthands the cascade expands, and their longhands in the order the
+// shorthand's values are for
+static SHORTHANDS: &[(&str, &[&str])] = &[
+    ("margin", &["margin-top", "margin-right", "margin-bottom", "margin-left"]),
+    ("overflow", &["overflow-x", "overflow-y"]),
+    ("padding", &["padding-top", "padding-right", "padding-bottom", "padding-left"]),
+];
+
+/// Returns the longhands of shorthand, e.g. margin-top, margin-right,
+/// margin-bottom and margin-left for margin, or None if it isn't a shorthand
+/// the cascade expands. Shorthands aren't in PROPERTIES, since computed
+/// styles only have their longhands. Logical shorthands like margin-inline
+/// are mapped along with the logical longhands instead.
+pub fn longhands(shorthand: &str) -> Option<&'static [&'static str]> {
+    SHORTHANDS
+        .iter()
+        .find(|&&(name, _)| name == shorthand)
+        .map(|&(_, longhands)| longhands)
+}
+
+/// A value every property accepts, e.g. `color: inherit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CssWideKeyword {
+    Initial,
+    Inherit,
+    // Inherit if the property is inherited, otherwise initial
+    Unset,
+    // Roll back to the value from the previous origin
+    Revert,
+}
+
+impl CssWideKeyword {
+    pub fn parse(value: &str) -> Option<CssWideKeyword> {
+        match value.to_ascii_lowercase().as_str() {
+            "initial" => Some(CssWideKeyword::Initial),
+            "inherit" => Some(CssWideKeyword::Inherit),
+            "unset" => Some(CssWideKeyword::Unset),
+            "revert" => Some(CssWideKeyword::Revert),
+            _ => None,
+        }
+    }
+}
+
+/// Returns true if the `all` shorthand sets property, which it does for every
+/// property but custom properties and direction.
+pub fn is_reset_by_all(property: &str) -> bool {
+    !property.starts_with("--") && !matches!(property, "all" | "direction" | "unicode-bidi")
+}
+
+/// Returns None if the property isn't supported.
+pub fn lookup_property(name: &str) -> Option<&'static Property> {
+    PROPERTIES
+        .binary_search_by_key(&name, |property| property.name)
+        .ok()
+        .map(|i| &PROPERTIES[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_properties_sorted() {
+        for pair in PROPERTIES.windows(2) {
+            assert!(pair[0].name < pair[1].name, "{} >= {}", pair[0].name, pair[1].name);
+        }
+    }
+
+    #[test]
+    fn test_lookup_property() {
+        assert_eq!(lookup_property("color").map(|p| p.inherited), Some(true));
+        assert_eq!(lookup_property("margin-left").map(|p| p.inherited), Some(false));
+        assert_eq!(lookup_property("display").map(|p| p.initial), Some("inline"));
+        assert_eq!(lookup_property("not-a-property"), None);
+        assert_eq!(lookup_property("margin"), None);
+        assert_eq!(longhands("overflow"), Some(&["overflow-x", "overflow-y"][..]));
+        assert_eq!(longhands("margin-left"), None);
+    }
+
+    #[test]
+    fn test_parse_css_wide_keyword() {
+        assert_eq!(CssWideKeyword::parse("inherit"), Some(CssWideKeyword::Inherit));
+        assert_eq!(CssWideKeyword::parse("UNSET"), Some(CssWideKeyword::Unset));
+        assert_eq!(CssWideKeyword::parse("revert"), Some(CssWideKeyword::Revert));
+        assert_eq!(CssWideKeyword::parse("initial"), Some(CssWideKeyword::Initial));
+        assert_eq!(CssWideKeyword::parse("red"), None);
+    }
+}
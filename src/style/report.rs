@@ -0,0 +1,48 @@
+use magicparser::{Declaration, Specificity};
+use style::cascade::Origin;
+
+/// Whether a declaration of a matched rule is part of a node's style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclarationStatus {
+    // Set or reverted a property
+    Applied,
+    // Beaten by a declaration with higher precedence
+    Overridden,
+    // Ignored, since its value is invalid for its property
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedDeclaration {
+    pub declaration: Declaration,
+    pub status: DeclarationStatus,
+}
+
+/// A rule that applies to a node, like an entry in the "Styles" pane of a
+/// browser's devtools. See StyleResolver::matched_rules().
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedRule {
+    // Serialized, or None for the node's style attribute
+    pub selector: Option<String>,
+    pub origin: Origin,
+    // Of the selector in the list that matched the node
+    pub specificity: Specificity,
+    // Full name of the rule's cascade layer, if any
+    pub layer: Option<String>,
+    // In the order they're declared
+    pub declarations: Vec<MatchedDeclaration>,
+}
+
+impl MatchedRule {
+    /// Returns the declarations with status, e.g. the ones that were
+    /// overridden.
+    pub fn declarations_with_status(
+        &self,
+        status: DeclarationStatus,
+    ) -> impl Iterator<Item = &Declaration> {
+        self.declarations
+            .iter()
+            .filter(move |matched| matched.status == status)
+            .map(|matched| &matched.declaration)
+    }
+}
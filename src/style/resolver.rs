@@ -0,0 +1,2823 @@
+use magicparser::{
+    error, parse_html_with_sink, parse_style_attribute, shadow_roots_attached, Children,
+    ContainerQuery, Declaration, DomNodeRef, HtmlSink, NodeId, NodeSnapshot,
+    PseudoElementSelector, Selector, Specificity, StyleRule, Stylesheet, ToCss,
+};
+#[cfg(feature = "parallel")]
+use magicparser::{DomVisitor, SyncTree};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
+use std::{fmt, mem, ptr};
+use style::animation::{parse_time, KeyframesRegistry};
+use style::bloom::AncestorFilter;
+use style::cascade::{
+    cascade, cascade_with_winners, sort_declarations, CascadedDeclaration, LayerOrder, Origin,
+    TreeContext, UNLAYERED,
+};
+use style::color::{Color, Rgba};
+use style::declaration::is_valid;
+use style::font::{bolder, lighter, parse_font_weight, FontFace, FontRegistry};
+use style::invalidation::InvalidationMap;
+use style::length::{has_viewport_units, LengthContext, LengthOrPercentage};
+use style::media::{MediaContext, QueryContainer, Viewport};
+use style::prefixes::{normalize_declarations, normalize_stylesheet};
+use style::logical::map_logical_properties;
+use style::properties::{lookup_property, CssWideKeyword, Property, ValueType, PROPERTIES};
+use style::report::{DeclarationStatus, MatchedDeclaration, MatchedRule};
+use style::ruletree::{RuleNodeId, RuleTree};
+use style::rulemap::RuleMap;
+use style::selectormatcher::{
+    matches_with_config, matching_part_specificity, matching_specificity, MatcherConfig,
+    QuirksMode,
+};
+#[cfg(feature = "parallel")]
+use style::selectormatcher::SyncMatcherConfig;
+use style::sharing::{revalidation_selectors, StyleSharingCache};
+use style::typed::TypedStyle;
+use style::useragent::user_agent_stylesheet;
+
+// Groups of properties that are stored together, and shared between styles
+// until one of them changes a property of the group
+const FONT: usize = 0;
+// Inherited properties other than fonts
+const INHERITED: usize = 1;
+const ANIMATION: usize = 2;
+// Other supported properties, which aren't inherited
+const BOX: usize = 3;
+// Unsupported and custom properties
+const OTHER: usize = 4;
+const GROUPS: usize = 5;
+
+type PropertyGroup = Arc<BTreeMap<String, String>>;
+
+fn property_group(property: &str) -> usize {
+    if property.starts_with("font-") || property == "line-height" {
+        FONT
+    } else if property.starts_with("animation-") || property.starts_with("transition-") {
+        ANIMATION
+    } else {
+        match lookup_property(property) {
+            Some(property) if property.inherited => INHERITED,
+            Some(_) => BOX,
+            None => OTHER,
+        }
+    }
+}
+
+// Shared by every group with no properties, so that empty styles don't
+// allocate
+fn empty_group() -> PropertyGroup {
+    static EMPTY: OnceLock<PropertyGroup> = OnceLock::new();
+    EMPTY.get_or_init(PropertyGroup::default).clone()
+}
+
+/// Property values of a DOM node after applying a stylesheet to it.
+///
+/// Properties are stored in groups, like the font properties, behind Arcs.
+/// Cloning a style shares its groups, which are copied when they're changed,
+/// and computed styles share the groups that are the same as their parent's,
+/// so that e.g. children share their parent's fonts unless they set them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComputedStyle {
+    groups: [PropertyGroup; GROUPS],
+}
+
+impl ComputedStyle {
+    /// Returns the style of a node no declarations apply to, like a text
+    /// node or an anonymous box of layout, whose parent has style parent: the
+    /// inherited properties take parent's values and the others their
+    /// initial values.
+    pub fn new_inheriting(parent: &ComputedStyle) -> ComputedStyle {
+        let context = LengthContext::default();
+        let media = MediaContext::default();
+        compute_values(ComputedStyle::default(), &[], Some((parent, &context)), &media).0
+    }
+
+    pub fn get(&self, property: &str) -> Option<&str> {
+        self.groups[property_group(property)]
+            .get(property)
+            .map(|value| value.as_str())
+    }
+
+    pub fn set(&mut self, property: &str, value: &str) {
+        // Leaves a shared group shared if nothing changes
+        if self.get(property) == Some(value) {
+            return;
+        }
+        Arc::make_mut(&mut self.groups[property_group(property)])
+            .insert(property.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, property: &str) -> Option<String> {
+        let group = &mut self.groups[property_group(property)];
+        if !group.contains_key(property) {
+            return None;
+        }
+        Arc::make_mut(group).remove(property)
+    }
+
+    /// Returns (property, value) pairs, sorted by property name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        let mut properties = self
+            .groups
+            .iter()
+            .flat_map(|group| group.iter())
+            .map(|(property, value)| (property.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        properties.sort_unstable_by_key(|&(property, _)| property);
+        properties.into_iter()
+    }
+
+    /// Returns the values of the supported properties, parsed.
+    pub fn typed(&self) -> TypedStyle {
+        TypedStyle::from_style(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|group| group.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.iter().all(|group| group.is_empty())
+    }
+
+    // Replaces the groups that are the same as other's with other's, so that
+    // they share memory
+    fn share_groups(&mut self, other: &ComputedStyle) {
+        for (group, other_group) in self.groups.iter_mut().zip(other.groups.iter()) {
+            if !Arc::ptr_eq(group, other_group) && group == other_group {
+                *group = other_group.clone();
+            }
+        }
+    }
+}
+
+impl Default for ComputedStyle {
+    fn default() -> ComputedStyle {
+        ComputedStyle {
+            groups: [empty_group(), empty_group(), empty_group(), empty_group(), empty_group()],
+        }
+    }
+}
+
+/// Computed styles for every node of a DOM tree, apart from the descendants
+/// of nodes that StyleResolver::resolve_lazy() deferred.
+#[derive(Debug, Default)]
+pub struct StyleMap {
+    // Keyed by DomNode::id_num
+    styles: HashMap<usize, ComputedStyle>,
+    // What each node's lengths were resolved against, for restyling its
+    // children
+    contexts: HashMap<usize, LengthContext>,
+    // Cascade results shared between the nodes, kept across restyles
+    rule_tree: RuleTree,
+    // Nodes whose descendants haven't been styled yet. Their own styles and
+    // contexts are kept, for resuming with their children.
+    deferred: HashSet<usize>,
+    defer: Option<DeferRule>,
+}
+
+type DeferFn = dyn Fn(&DomNodeRef, &ComputedStyle) -> bool;
+
+// Decides which nodes' descendants are left unstyled until they're asked for
+#[derive(Clone)]
+struct DeferRule(Rc<DeferFn>);
+
+impl fmt::Debug for DeferRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DeferRule")
+    }
+}
+
+impl StyleMap {
+    /// Returns None for nodes whose styles were deferred; see
+    /// StyleResolver::ensure_style().
+    pub fn get(&self, dom_node: &DomNodeRef) -> Option<&ComputedStyle> {
+        self.styles.get(&dom_node.borrow().id_num)
+    }
+
+    /// Returns true if dom_node is styled, but its descendants' styles were
+    /// deferred.
+    pub fn is_deferred(&self, dom_node: &DomNodeRef) -> bool {
+        self.deferred.contains(&dom_node.borrow().id_num)
+    }
+
+    pub fn insert(&mut self, dom_node: &DomNodeRef, style: ComputedStyle) {
+        self.styles.insert(dom_node.borrow().id_num, style);
+    }
+
+    pub fn len(&self) -> usize {
+        self.styles.len()
+    }
+
+    /// Returns the cascade results the styles were computed from.
+    pub fn rule_tree(&self) -> &RuleTree {
+        &self.rule_tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.styles.is_empty()
+    }
+
+    /// Returns a snapshot of the tree rooted at root along with the computed
+    /// values of its nodes, e.g. for dumping a styled tree to JSON.
+    pub fn snapshot(&self, root: &DomNodeRef) -> NodeSnapshot {
+        NodeSnapshot::new_with(root, |dom_node| {
+            let style = self.get(dom_node)?;
+            Some(style.iter().map(|(property, value)| (property.into(), value.into())).collect())
+        })
+    }
+}
+
+// A node whose children are being styled by StyleResolver::resolve_children()
+struct StyledParent<'a> {
+    dom_node: DomNodeRef,
+    style: ComputedStyle,
+    context: LengthContext,
+    // The children left to style, last first
+    children: Children,
+    // Holds the styles of the children styled so far
+    sharing: StyleSharingCache<'a>,
+}
+
+// A node whose cascade is done, waiting for its values to be computed once
+// its parent's are. See StyleResolver::resolve_parallel().
+#[cfg(feature = "parallel")]
+struct CascadedNode {
+    id_num: usize,
+    rule_node: RuleNodeId,
+    // Indices of the children in the list of cascaded nodes
+    children: Vec<usize>,
+}
+
+// Collects the nodes of the tree it walks, in the order a SyncTree copies
+// them in. See StyleResolver::resolve_parallel().
+#[cfg(feature = "parallel")]
+#[derive(Default)]
+struct NodeCollector {
+    nodes: Vec<DomNodeRef>,
+}
+
+#[cfg(feature = "parallel")]
+impl DomVisitor for NodeCollector {
+    fn enter_element(&mut self, element: &DomNodeRef) -> bool {
+        self.nodes.push(element.clone());
+        true
+    }
+
+    fn visit_text(&mut self, text: &DomNodeRef) {
+        self.nodes.push(text.clone());
+    }
+
+    fn visit_comment(&mut self, comment: &DomNodeRef) {
+        self.nodes.push(comment.clone());
+    }
+}
+
+// The parts of a StyleResolver that can be sent to other threads, where
+// StyleResolver::resolve_parallel() builds a resolver from them for matching
+// against the copies of a SyncTree's nodes. Nodes are given by their indices
+// in the tree.
+#[cfg(feature = "parallel")]
+struct SyncResolver {
+    stylesheets: Vec<(Origin, Arc<Stylesheet>)>,
+    // None for shadow roots outside the tree
+    scopes: HashMap<usize, Option<usize>>,
+    config: SyncMatcherConfig,
+    media: MediaContext,
+    containers: HashMap<usize, QueryContainer>,
+    normalize_prefixes: bool,
+    rule_map: Arc<RuleMap>,
+}
+
+#[cfg(feature = "parallel")]
+impl SyncResolver {
+    // index_of maps the DomNode::id_num of tree's nodes to their indices
+    fn new(
+        resolver: &StyleResolver,
+        tree: &SyncTree,
+        index_of: &HashMap<usize, usize>,
+    ) -> SyncResolver {
+        let scopes = resolver
+            .scopes
+            .iter()
+            .map(|(&stylesheet, &shadow_root)| {
+                let index = DomNodeRef::from_node_id(shadow_root)
+                    .and_then(|node| index_of.get(&node.borrow().id_num).cloned());
+                (stylesheet, index)
+            })
+            .collect();
+        let containers = resolver
+            .containers
+            .iter()
+            .filter_map(|(id_num, container)| Some((*index_of.get(id_num)?, container.clone())))
+            .collect();
+        SyncResolver {
+            stylesheets: resolver.stylesheets.clone(),
+            scopes,
+            config: SyncMatcherConfig::new(&resolver.config, tree),
+            media: resolver.media.clone(),
+            containers,
+            normalize_prefixes: resolver.normalize_prefixes,
+            rule_map: resolver.rule_map().clone(),
+        }
+    }
+
+    // Cascades the nodes at indices, which are consecutive in tree order,
+    // on this thread, with their custom elements' default_declarations. Their
+    // cascade results are in a rule tree of the thread's own, which is
+    // returned along with them.
+    fn cascade(
+        &self,
+        tree: &SyncTree,
+        indices: &[usize],
+        default_declarations: &[Vec<Declaration>],
+    ) -> (RuleTree, Vec<RuleNodeId>) {
+        let dom_nodes = tree.to_dom_nodes();
+        // Which :defined looks at
+        for (dom_node, node) in dom_nodes.iter().zip(tree.nodes()) {
+            dom_node.borrow_mut().upgraded = node.upgraded;
+        }
+        let resolver = self.to_resolver(&dom_nodes);
+        let parent_or_host = |index: usize| {
+            let node = &tree.nodes()[index];
+            node.parent.or(node.host)
+        };
+        let mut filter = resolver.ancestor_filter(&dom_nodes[indices[0]]);
+        // The nodes in filter, outermost first
+        let mut ancestors = vec![];
+        let mut ancestor = parent_or_host(indices[0]);
+        while let Some(index) = ancestor {
+            ancestors.push(index);
+            ancestor = parent_or_host(index);
+        }
+        ancestors.reverse();
+        let mut rule_tree = RuleTree::new();
+        let mut rule_nodes = vec![];
+        for (&index, default_declarations) in indices.iter().zip(default_declarations) {
+            let parent = parent_or_host(index);
+            while ancestors.last() != parent.as_ref() {
+                filter.pop(&dom_nodes[ancestors.pop().unwrap()]);
+            }
+            let dom_node = &dom_nodes[index];
+            rule_nodes.push(resolver.rule_node_with_defaults(
+                dom_node,
+                default_declarations,
+                &mut rule_tree,
+                &filter,
+            ));
+            filter.push(dom_node);
+            ancestors.push(index);
+        }
+        (rule_tree, rule_nodes)
+    }
+
+    // The resolver for matching against dom_nodes, the copies of the tree's
+    // nodes on this thread
+    fn to_resolver(&self, dom_nodes: &[DomNodeRef]) -> StyleResolver {
+        // The root is never a shadow root, so stylesheets scoped to shadow
+        // roots outside the tree are scoped to it, to match nothing
+        let scopes = self
+            .scopes
+            .iter()
+            .map(|(&stylesheet, shadow_root)| {
+                (stylesheet, dom_nodes[shadow_root.unwrap_or(0)].node_id())
+            })
+            .collect();
+        let containers = self
+            .containers
+            .iter()
+            .map(|(&index, container)| (dom_nodes[index].borrow().id_num, container.clone()))
+            .collect();
+        StyleResolver {
+            stylesheets: self.stylesheets.clone(),
+            scopes,
+            config: self.config.to_config(dom_nodes),
+            media: self.media.clone(),
+            system_fonts: vec![],
+            containers,
+            normalize_prefixes: self.normalize_prefixes,
+            rule_map: OnceLock::from(self.rule_map.clone()),
+        }
+    }
+}
+
+/// Applies stylesheets to a DOM tree.
+pub struct StyleResolver {
+    // In the order they were added. Shared with the threads of
+    // resolve_parallel() while it runs.
+    stylesheets: Vec<(Origin, Arc<Stylesheet>)>,
+    // Shadow roots the stylesheets added with add_shadow_stylesheet() are
+    // scoped to, by the stylesheets' indices
+    scopes: HashMap<usize, NodeId>,
+    config: MatcherConfig,
+    media: MediaContext,
+    system_fonts: Vec<FontFace>,
+    // Keyed by DomNode::id_num
+    containers: HashMap<usize, QueryContainer>,
+    // See normalize_vendor_prefixes()
+    normalize_prefixes: bool,
+    // Index of the stylesheets' rules, built when it's first needed after the
+    // stylesheets change
+    rule_map: OnceLock<Arc<RuleMap>>,
+}
+
+impl StyleResolver {
+    /// Styles documents with the built-in user agent stylesheet and the author
+    /// stylesheet.
+    pub fn new(stylesheet: Stylesheet) -> StyleResolver {
+        Self::new_with_config(stylesheet, MatcherConfig::default())
+    }
+
+    pub fn new_with_config(stylesheet: Stylesheet, config: MatcherConfig) -> StyleResolver {
+        StyleResolver {
+            stylesheets: vec![
+                (Origin::UserAgent, Arc::new(user_agent_stylesheet())),
+                (Origin::Author, Arc::new(stylesheet)),
+            ],
+            scopes: HashMap::new(),
+            config,
+            media: MediaContext::default(),
+            system_fonts: vec![],
+            containers: HashMap::new(),
+            normalize_prefixes: false,
+            rule_map: OnceLock::new(),
+        }
+    }
+
+    /// Sets the size vw and vh units and @media rules are relative to. Styles
+    /// that have already been computed aren't updated; see
+    /// set_media_context().
+    pub fn set_viewport_size(&mut self, width: f32, height: f32) {
+        self.media.viewport_width = width;
+        self.media.viewport_height = height;
+    }
+
+    /// Sets the device @media rules are evaluated against, e.g. when the
+    /// window is resized or zoomed, and marks the nodes in root's tree whose
+    /// styles can change as needing to be restyled: those matched by rules
+    /// that start or stop applying, and if the viewport size changed, those
+    /// with lengths in viewport units. If the font size changed, every node
+    /// is.
+    pub fn set_media_context(&mut self, root: &DomNodeRef, media: MediaContext) {
+        let old_media = mem::replace(&mut self.media, media);
+        if old_media.font_size != self.media.font_size {
+            // Any length can be relative to it, through em or rem units
+            root.mark_subtree_style_dirty();
+            return;
+        }
+        let viewport_changed = old_media.viewport_width != self.media.viewport_width
+            || old_media.viewport_height != self.media.viewport_height;
+        for (_, stylesheet) in &self.stylesheets {
+            for rule in &stylesheet.rules {
+                let applied = rule.media.iter().all(|media| old_media.matches(media));
+                let applies = rule.media.iter().all(|media| self.media.matches(media));
+                let viewport_relative = viewport_changed
+                    && rule.declarations.iter().any(|decl| has_viewport_units(&decl.value));
+                if applied != applies || viewport_relative {
+                    self.invalidate_rule(root, rule);
+                }
+            }
+        }
+        if viewport_changed {
+            invalidate_viewport_relative_inline_styles(root);
+        }
+    }
+
+    /// Sets the viewport, like set_media_context() does the rest of the
+    /// device, e.g. when the window is resized.
+    pub fn set_viewport(&mut self, root: &DomNodeRef, viewport: &Viewport) {
+        let mut media = self.media.clone();
+        media.set_viewport(viewport);
+        self.set_media_context(root, media);
+    }
+
+    /// Sets the laid-out content-box size of container, which @container
+    /// rules are evaluated against, and if it changed, marks its descendants
+    /// as needing to be restyled. The node is only a container if its style
+    /// in styles has a container-type other than normal, so sizes should be
+    /// set again after restyles that change container-type.
+    pub fn set_container_size(
+        &mut self,
+        container: &DomNodeRef,
+        styles: &StyleMap,
+        width: f32,
+        height: f32,
+    ) {
+        let id_num = container.borrow().id_num;
+        let query_container = match (styles.styles.get(&id_num), styles.contexts.get(&id_num)) {
+            (Some(style), Some(context)) => QueryContainer::new(style, *context, width, height),
+            _ => None,
+        };
+        if self.containers.get(&id_num) == query_container.as_ref() {
+            return;
+        }
+        match query_container {
+            Some(query_container) => self.containers.insert(id_num, query_container),
+            None => self.containers.remove(&id_num),
+        };
+        for child in &styled_children(container) {
+            child.mark_subtree_style_dirty();
+        }
+    }
+
+    /// Adds a stylesheet after all others. Within an origin, stylesheets added
+    /// later take precedence. Author stylesheets don't apply inside shadow
+    /// trees; see add_shadow_stylesheet().
+    pub fn add_stylesheet(&mut self, mut stylesheet: Stylesheet, origin: Origin) {
+        if self.normalize_prefixes {
+            normalize_stylesheet(&mut stylesheet);
+        }
+        self.stylesheets.push((origin, Arc::new(stylesheet)));
+        self.rule_map = OnceLock::new();
+    }
+
+    /// Adds an author stylesheet scoped to shadow_root, e.g. from a `<style>`
+    /// in its shadow tree, after all others. It styles the shadow tree's
+    /// nodes, and its :host rules the host, but nothing else.
+    ///
+    /// For the host, the declarations of the host's own tree take precedence
+    /// over those of the shadow tree, unless they're important.
+    pub fn add_shadow_stylesheet(&mut self, shadow_root: &DomNodeRef, stylesheet: Stylesheet) {
+        self.scopes.insert(self.stylesheets.len(), shadow_root.node_id());
+        self.add_stylesheet(stylesheet, Origin::Author);
+    }
+
+    /// Opts in to replacing well-known vendor-prefixed declarations, e.g.
+    /// `-webkit-transition-delay`, with their standard equivalents, in the
+    /// stylesheets added so far and later, and in style attributes. See
+    /// normalize_declarations(). Rules added through stylesheet_mut() aren't
+    /// normalized.
+    pub fn normalize_vendor_prefixes(&mut self) {
+        self.normalize_prefixes = true;
+        for (_, stylesheet) in &mut self.stylesheets {
+            // Only shared while resolve_parallel() runs
+            normalize_stylesheet(Arc::get_mut(stylesheet).unwrap());
+        }
+        self.rule_map = OnceLock::new();
+    }
+
+    /// Returns the stylesheet at index, in the order they were added, with the
+    /// user agent stylesheet first and the author stylesheet passed to new()
+    /// second. Call invalidate_rule() for each rule that's changed, added or
+    /// removed, so that restyle() picks up the change.
+    pub fn stylesheet_mut(&mut self, index: usize) -> Option<&mut Stylesheet> {
+        self.rule_map = OnceLock::new();
+        self.stylesheets.get_mut(index).and_then(|(_, stylesheet)| Arc::get_mut(stylesheet))
+    }
+
+    /// Marks the nodes in root's tree that rule applies to, and their
+    /// descendants, as needing to be restyled. Shadow trees, whose rules
+    /// match differently, are marked whole, along with their hosts.
+    pub fn invalidate_rule(&self, root: &DomNodeRef, rule: &StyleRule) {
+        if let Some(shadow_root) = root.shadow_root() {
+            root.mark_self_style_dirty();
+            shadow_root.mark_subtree_style_dirty();
+        }
+        if matches_with_config(root, &rule.selector, &self.config) {
+            root.mark_subtree_style_dirty();
+            return;
+        }
+        // Marking a child dirty marks root as having dirty descendants
+        let children = root.borrow().children.clone();
+        for child in &children {
+            if !child.borrow().elem_type.is_element() {
+                continue;
+            }
+            self.invalidate_rule(child, rule);
+        }
+    }
+
+    /// Adds a font installed on the system, which font-family can name.
+    pub fn add_system_font(&mut self, face: FontFace) {
+        self.system_fonts.push(face);
+    }
+
+    /// Returns the font faces declared by @font-face rules in every
+    /// stylesheet, along with the system fonts. Pass computed styles to
+    /// FontRegistry::match_style() for the font to render them with.
+    pub fn font_registry(&self) -> FontRegistry {
+        let mut registry = FontRegistry::default();
+        for (_, stylesheet) in &self.stylesheets {
+            registry.add_stylesheet(stylesheet);
+        }
+        for face in &self.system_fonts {
+            registry.add_system_font(face.clone());
+        }
+        registry
+    }
+
+    /// Returns the @keyframes rules of every stylesheet, which animations
+    /// in computed styles refer to by name.
+    pub fn keyframes_registry(&self) -> KeyframesRegistry {
+        let mut registry = KeyframesRegistry::default();
+        for (_, stylesheet) in &self.stylesheets {
+            registry.add_stylesheet(stylesheet);
+        }
+        registry
+    }
+
+    /// Returns the features the selectors of every stylesheet depend on, for
+    /// marking only the nodes a change can restyle as dirty.
+    pub fn invalidation_map(&self) -> InvalidationMap {
+        let mut map = InvalidationMap::new(&self.config);
+        for (_, stylesheet) in &self.stylesheets {
+            map.add_stylesheet(stylesheet);
+        }
+        map
+    }
+
+    /// Computes the style of root and each of its descendants.
+    pub fn resolve(&self, root: &DomNodeRef) -> StyleMap {
+        let mut styles = StyleMap::default();
+        let selectors = self.revalidation_selectors();
+        let mut sharing = StyleSharingCache::new(&selectors, &self.config);
+        let mut filter = self.ancestor_filter(root);
+        self.resolve_subtree(root, None, &mut styles, &mut sharing, &mut filter);
+        styles
+    }
+
+    /// Like resolve(), but leaves the descendants of each node that defer
+    /// returns true for unstyled, e.g. those with `display: none` or outside
+    /// the viewport, until ensure_style() asks for one of them. restyle()
+    /// keeps deferring them, and defers newly styled nodes by the same rule.
+    pub fn resolve_lazy<F>(&self, root: &DomNodeRef, defer: F) -> StyleMap
+    where
+        F: Fn(&DomNodeRef, &ComputedStyle) -> bool + 'static,
+    {
+        let mut styles = StyleMap {
+            defer: Some(DeferRule(Rc::new(defer))),
+            ..StyleMap::default()
+        };
+        let selectors = self.revalidation_selectors();
+        let mut sharing = StyleSharingCache::new(&selectors, &self.config);
+        let mut filter = self.ancestor_filter(root);
+        self.resolve_subtree(root, None, &mut styles, &mut sharing, &mut filter);
+        styles
+    }
+
+    /// Returns dom_node's style, first computing the styles deferred by
+    /// resolve_lazy() that it depends on. Returns None if dom_node was never
+    /// styled at all.
+    ///
+    /// The deferred node's whole subtree is styled, apart from the descendants
+    /// of nodes in it that are deferred themselves.
+    pub fn ensure_style<'a>(
+        &self,
+        dom_node: &DomNodeRef,
+        styles: &'a mut StyleMap,
+    ) -> Option<&'a ComputedStyle> {
+        while !styles.styles.contains_key(&dom_node.borrow().id_num) {
+            // The nearest styled ancestor is the one holding back the rest
+            let styled = dom_node
+                .shadow_including_ancestors()
+                .find(|ancestor| styles.styles.contains_key(&ancestor.borrow().id_num))?;
+            if !styles.deferred.remove(&styled.borrow().id_num) {
+                return None;
+            }
+            self.resolve_deferred(&styled, styles);
+        }
+        styles.get(dom_node)
+    }
+
+    // Styles the children of dom_node, whose descendants were deferred, and
+    // their descendants
+    fn resolve_deferred(&self, dom_node: &DomNodeRef, styles: &mut StyleMap) {
+        let id_num = dom_node.borrow().id_num;
+        let style = styles.styles[&id_num].clone();
+        let mut context = styles.contexts[&id_num];
+        // The viewport may have changed since the node was styled
+        context.viewport_width = self.media.viewport_width;
+        context.viewport_height = self.media.viewport_height;
+        let selectors = self.revalidation_selectors();
+        let mut sharing = StyleSharingCache::new(&selectors, &self.config);
+        let mut filter = self.ancestor_filter(dom_node);
+        filter.push(dom_node);
+        for child in &styled_children(dom_node) {
+            let parent = Some((&style, &context));
+            self.resolve_subtree(child, parent, styles, &mut sharing, &mut filter);
+        }
+    }
+
+    /// Like resolve(), but styles the tree on rayon's thread pool.
+    ///
+    /// DOM nodes live in their thread's arena, so root's whole tree is copied
+    /// into a SyncTree first. Each thread builds a DOM from that, and matches
+    /// and cascades its share of root's nodes in it, in tree order, into a
+    /// rule tree of its own. Those are merged, and then values are computed
+    /// in parallel too, for sibling subtrees once their parent's are. Styles
+    /// aren't shared between siblings, but siblings that match the same
+    /// declarations still share their cascade results. Registered
+    /// pseudo-classes are called with the copies.
+    #[cfg(feature = "parallel")]
+    pub fn resolve_parallel(&self, root: &DomNodeRef) -> StyleMap {
+        // Selectors can look at root's ancestors, and at the hosts of the
+        // shadow trees it's in
+        let top = root.shadow_including_ancestors().last().unwrap_or_else(|| root.clone());
+        let tree = SyncTree::from(&top);
+        let index_of = tree
+            .nodes()
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node.id_num, index))
+            .collect::<HashMap<_, _>>();
+        // root's nodes come right after it in the tree
+        let start = index_of[&root.borrow().id_num];
+        let mut collector = NodeCollector::default();
+        root.walk(&mut collector);
+        let mut default_declarations = vec![];
+        for dom_node in &collector.nodes {
+            // Custom element definitions are only known on this thread
+            default_declarations.push(self.default_declarations(dom_node));
+            let mut node = dom_node.borrow_mut();
+            node.style_dirty = false;
+            node.self_style_dirty = false;
+            node.descendants_dirty = false;
+        }
+
+        let sync_resolver = SyncResolver::new(self, &tree, &index_of);
+        let indices = (start..start + collector.nodes.len()).collect::<Vec<_>>();
+        let chunk_size = indices.len().div_ceil(rayon::current_num_threads()).max(1);
+        let cascaded = indices
+            .par_chunks(chunk_size)
+            .zip(default_declarations.par_chunks(chunk_size))
+            .map(|(indices, default_declarations)| {
+                sync_resolver.cascade(&tree, indices, default_declarations)
+            })
+            .collect::<Vec<_>>();
+
+        let mut styles = StyleMap::default();
+        let mut rule_nodes = vec![];
+        for (rule_tree, nodes) in &cascaded {
+            rule_nodes.extend(styles.rule_tree.merge(rule_tree, nodes));
+        }
+        let nodes = rule_nodes
+            .into_iter()
+            .zip(&tree.nodes()[start..])
+            .map(|(rule_node, node)| {
+                let children = node.shadow_root.iter().chain(&node.children);
+                CascadedNode {
+                    id_num: node.id_num,
+                    rule_node,
+                    children: children.map(|&child| child - start).collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let computed = compute_subtree(&styles.rule_tree, &nodes, &self.media);
+        for (id_num, style, context) in computed {
+            styles.styles.insert(id_num, style);
+            styles.contexts.insert(id_num, context);
+        }
+        styles
+    }
+
+    /// Computes the style of dom_node alone, like `window.getComputedStyle()`.
+    /// Its ancestors' styles are computed too, for it to inherit from, but
+    /// not its descendants' or siblings'.
+    pub fn get_computed_style(&self, dom_node: &DomNodeRef) -> ComputedStyle {
+        let mut ancestors = vec![dom_node.clone()];
+        ancestors.extend(dom_node.shadow_including_ancestors());
+        let mut rule_tree = RuleTree::new();
+        let mut filter = AncestorFilter::new(self.is_quirks());
+        let mut parent: Option<(ComputedStyle, LengthContext)> = None;
+        for node in ancestors.iter().rev() {
+            let parent_style = parent.as_ref().map(|(style, context)| (style, context));
+            parent = Some(self.compute_style(node, parent_style, &mut rule_tree, &filter));
+            filter.push(node);
+        }
+        parent.unwrap().0
+    }
+
+    /// Parses input and computes the style of each node as soon as it's
+    /// parsed, passing it to on_styled along with the node, so that a document
+    /// can be styled in the same pass that parses it without keeping the
+    /// styles around. Nodes are passed in tree order, so a node's ancestors
+    /// have always been passed before it.
+    ///
+    /// A node is styled before its later siblings and its children are
+    /// parsed, so selectors that depend on them, like `:last-child` or
+    /// `:nth-last-child()`, match as if the document ended after the node.
+    pub fn resolve_streaming<F>(&self, input: &str, on_styled: F) -> error::Result<DomNodeRef>
+    where
+        F: FnMut(&DomNodeRef, &ComputedStyle),
+    {
+        let mut styler = StreamingStyler {
+            resolver: self,
+            rule_tree: RuleTree::new(),
+            filter: AncestorFilter::new(self.is_quirks()),
+            open: vec![],
+            on_styled,
+        };
+        parse_html_with_sink(input, &mut styler)
+    }
+
+    /// Returns the rules that apply to dom_node, and its style attribute if it
+    /// has one, from highest to lowest precedence, along with which of their
+    /// declarations are part of its style, like the "Styles" pane of a
+    /// browser's devtools.
+    pub fn matched_rules(&self, dom_node: &DomNodeRef) -> Vec<MatchedRule> {
+        if !dom_node.borrow().elem_type.is_element() {
+            return vec![];
+        }
+        let default_declarations = self.default_declarations(dom_node);
+        let inline_declarations = self.inline_declarations(dom_node);
+        let declarations = self.cascaded_declarations(
+            dom_node,
+            None,
+            &default_declarations,
+            &inline_declarations,
+            None,
+        );
+        let (_, winners) = cascade_with_winners(declarations);
+        let matched_declarations = |declarations: &[Declaration]| {
+            declarations
+                .iter()
+                .map(|declaration| {
+                    let status = if !is_valid(declaration) {
+                        DeclarationStatus::Invalid
+                    } else if winners.iter().any(|winner| ptr::eq(*winner, declaration)) {
+                        DeclarationStatus::Applied
+                    } else {
+                        DeclarationStatus::Overridden
+                    };
+                    MatchedDeclaration { declaration: declaration.clone(), status }
+                })
+                .collect()
+        };
+        // Sorted by (origin, context, inline, layer, specificity, source order)
+        let mut matched = vec![];
+        let rule_map = self.rule_map();
+        let layer_orders = self.layer_orders();
+        let scopes = NodeScopes::new(dom_node, &self.config);
+        for index in rule_map.candidates(dom_node) {
+            let (origin, ref stylesheet) = self.stylesheets[index.0];
+            let rule = &stylesheet.rules[index.1];
+            let scope = self.scopes.get(&index.0).cloned();
+            let (config, context) = match scopes.config(origin, scope) {
+                Some(found) => found,
+                None => continue,
+            };
+            let specificity = match self.rule_specificity(dom_node, None, rule, config, context) {
+                Some(specificity) => specificity,
+                None => continue,
+            };
+            let layer = layer_orders[&origin].rank(rule.layer.as_deref());
+            let matched_rule = MatchedRule {
+                selector: Some(rule.selector.to_css_string()),
+                origin,
+                specificity,
+                layer: rule.layer.clone(),
+                declarations: matched_declarations(&rule.declarations),
+            };
+            let source_order = rule_map.source_order(index);
+            let key = (origin, context, false, layer, specificity, source_order);
+            matched.push((key, matched_rule));
+        }
+        if !inline_declarations.is_empty() {
+            let matched_rule = MatchedRule {
+                selector: None,
+                origin: Origin::Author,
+                specificity: Specificity::default(),
+                layer: None,
+                declarations: matched_declarations(&inline_declarations),
+            };
+            let source_order = rule_map.declaration_count();
+            let key = (
+                Origin::Author,
+                TreeContext::Tree,
+                true,
+                UNLAYERED,
+                Specificity::default(),
+                source_order,
+            );
+            matched.push((key, matched_rule));
+        }
+        matched.sort_by_key(|(key, _)| Reverse(*key));
+        matched.into_iter().map(|(_, matched_rule)| matched_rule).collect()
+    }
+
+    /// Returns DomNodeRef::dump_tree() of root, with the author and user
+    /// rules each element matched listed below it, from highest to lowest
+    /// precedence. The user agent's rules are left out, since nearly every
+    /// element matches some.
+    pub fn dump_tree(&self, root: &DomNodeRef) -> String {
+        root.dump_tree_with(|dom_node| {
+            self.matched_rules(dom_node)
+                .into_iter()
+                .filter(|rule| rule.origin != Origin::UserAgent)
+                .map(|rule| rule.selector.unwrap_or_else(|| "style attribute".to_string()))
+                .collect()
+        })
+    }
+
+    /// Recomputes the styles of the nodes marked dirty by mutations since
+    /// styles were last computed, along with their descendants, and of nodes
+    /// that aren't in styles yet. Other styles are left as they are.
+    pub fn restyle(&self, root: &DomNodeRef, styles: &mut StyleMap) {
+        let selectors = self.revalidation_selectors();
+        let mut sharing = StyleSharingCache::new(&selectors, &self.config);
+        let mut filter = self.ancestor_filter(root);
+        self.restyle_subtree(root, None, styles, &mut sharing, &mut filter);
+    }
+
+    fn revalidation_selectors(&self) -> Vec<&Selector> {
+        revalidation_selectors(self.stylesheets.iter().map(|(_, stylesheet)| &**stylesheet))
+    }
+
+    // parent is the id_num of the parent, whose style is up to date. sharing
+    // holds the styles of dom_node's siblings, and filter its ancestors.
+    fn restyle_subtree(
+        &self,
+        dom_node: &DomNodeRef,
+        parent: Option<usize>,
+        styles: &mut StyleMap,
+        sharing: &mut StyleSharingCache,
+        filter: &mut AncestorFilter,
+    ) {
+        let id_num = dom_node.borrow().id_num;
+        let (style_dirty, self_style_dirty, descendants_dirty) = {
+            let node = dom_node.borrow();
+            (node.style_dirty, node.self_style_dirty, node.descendants_dirty)
+        };
+        let parent_style = parent.and_then(|parent| {
+            let mut context = *styles.contexts.get(&parent)?;
+            // The viewport may have changed since the parent was styled
+            context.viewport_width = self.media.viewport_width;
+            context.viewport_height = self.media.viewport_height;
+            Some((styles.styles.get(&parent)?.clone(), context))
+        });
+        let parent_style = parent_style.as_ref().map(|(style, context)| (style, context));
+        if style_dirty || !styles.styles.contains_key(&id_num) {
+            self.resolve_subtree(dom_node, parent_style, styles, sharing, filter);
+            return;
+        }
+        if self_style_dirty {
+            let (style, context) =
+                self.compute_style(dom_node, parent_style, &mut styles.rule_tree, filter);
+            dom_node.borrow_mut().self_style_dirty = false;
+            let unchanged = styles.styles.get(&id_num) == Some(&style)
+                && styles.contexts.get(&id_num) == Some(&context);
+            if !unchanged {
+                // Children can inherit from the node
+                self.resolve_children(dom_node, style, context, styles, sharing, filter);
+                return;
+            }
+        }
+        if descendants_dirty {
+            dom_node.borrow_mut().descendants_dirty = false;
+            if styles.deferred.contains(&id_num) {
+                return;
+            }
+            let mut child_sharing = sharing.new_for_children();
+            filter.push(dom_node);
+            for child in &styled_children(dom_node) {
+                self.restyle_subtree(child, Some(id_num), styles, &mut child_sharing, filter);
+            }
+            filter.pop(dom_node);
+        }
+    }
+
+    fn resolve_subtree(
+        &self,
+        dom_node: &DomNodeRef,
+        parent: Option<(&ComputedStyle, &LengthContext)>,
+        styles: &mut StyleMap,
+        sharing: &mut StyleSharingCache,
+        filter: &mut AncestorFilter,
+    ) {
+        let (style, context) =
+            self.share_or_compute_style(dom_node, parent, styles, sharing, filter);
+        self.resolve_children(dom_node, style, context, styles, sharing, filter);
+    }
+
+    fn share_or_compute_style(
+        &self,
+        dom_node: &DomNodeRef,
+        parent: Option<(&ComputedStyle, &LengthContext)>,
+        styles: &mut StyleMap,
+        sharing: &mut StyleSharingCache,
+        filter: &AncestorFilter,
+    ) -> (ComputedStyle, LengthContext) {
+        match sharing.get(dom_node) {
+            Some(shared) => shared,
+            None => {
+                let (style, context) =
+                    self.compute_style(dom_node, parent, &mut styles.rule_tree, filter);
+                sharing.insert(dom_node, &style, context);
+                (style, context)
+            }
+        }
+    }
+
+    // Stores the node's newly computed style and resolves its descendants'
+    // styles from it. The tree is walked with a stack of the nodes whose
+    // children are being styled rather than by recursing, since it can be
+    // deep.
+    fn resolve_children<'a>(
+        &self,
+        dom_node: &DomNodeRef,
+        style: ComputedStyle,
+        context: LengthContext,
+        styles: &mut StyleMap,
+        sharing: &StyleSharingCache<'a>,
+        filter: &mut AncestorFilter,
+    ) {
+        let mut stack = vec![];
+        stack.extend(self.start_children(dom_node, style, context, styles, sharing, filter));
+        while let Some(parent) = stack.last_mut() {
+            match parent.children.pop() {
+                Some(child) => {
+                    let parent_style = Some((&parent.style, &parent.context));
+                    let (style, context) = self.share_or_compute_style(
+                        &child,
+                        parent_style,
+                        styles,
+                        &mut parent.sharing,
+                        filter,
+                    );
+                    let child = self.start_children(
+                        &child,
+                        style,
+                        context,
+                        styles,
+                        &parent.sharing,
+                        filter,
+                    );
+                    stack.extend(child);
+                }
+                None => {
+                    let parent = stack.pop().unwrap();
+                    filter.pop(&parent.dom_node);
+                    let id_num = parent.dom_node.borrow().id_num;
+                    styles.contexts.insert(id_num, parent.context);
+                    styles.insert(&parent.dom_node, parent.style);
+                }
+            }
+        }
+    }
+
+    // Clears the node's dirty bits, and returns what its children are styled
+    // from, unless they're deferred, in which case its style is stored right
+    // away
+    fn start_children<'a>(
+        &self,
+        dom_node: &DomNodeRef,
+        style: ComputedStyle,
+        context: LengthContext,
+        styles: &mut StyleMap,
+        sharing: &StyleSharingCache<'a>,
+        filter: &mut AncestorFilter,
+    ) -> Option<StyledParent<'a>> {
+        {
+            let mut node = dom_node.borrow_mut();
+            node.style_dirty = false;
+            node.self_style_dirty = false;
+            node.descendants_dirty = false;
+        }
+        let id_num = dom_node.borrow().id_num;
+        let defer = styles.defer.clone().is_some_and(|DeferRule(defer)| defer(dom_node, &style));
+        if defer {
+            styles.deferred.insert(id_num);
+            styles.contexts.insert(id_num, context);
+            styles.insert(dom_node, style);
+            return None;
+        }
+        styles.deferred.remove(&id_num);
+        filter.push(dom_node);
+        let mut children = styled_children(dom_node);
+        children.reverse();
+        Some(StyledParent {
+            dom_node: dom_node.clone(),
+            style,
+            context,
+            children,
+            sharing: sharing.new_for_children(),
+        })
+    }
+
+    /// Computes the style of pseudo_element of dom_node, e.g. `p::before`,
+    /// which inherits from dom_node's style in styles. Returns None if no rule
+    /// applies to it, or dom_node isn't styled.
+    pub fn resolve_pseudo_element(
+        &self,
+        dom_node: &DomNodeRef,
+        pseudo_element: &PseudoElementSelector,
+        styles: &StyleMap,
+    ) -> Option<ComputedStyle> {
+        let id_num = dom_node.borrow().id_num;
+        let parent = (styles.styles.get(&id_num)?, styles.contexts.get(&id_num)?);
+        let mut declarations =
+            self.cascaded_declarations(dom_node, Some(pseudo_element), &[], &[], None);
+        if declarations.is_empty() {
+            return None;
+        }
+        sort_declarations(&mut declarations);
+        let order = declarations.iter().map(|cascaded| cascaded.declaration).collect::<Vec<_>>();
+        Some(compute_values(cascade(declarations), &order, Some(parent), &self.media).0)
+    }
+
+    // Also returns what the node's lengths were resolved against. filter holds
+    // the node's ancestors.
+    fn compute_style(
+        &self,
+        dom_node: &DomNodeRef,
+        parent: Option<(&ComputedStyle, &LengthContext)>,
+        rule_tree: &mut RuleTree,
+        filter: &AncestorFilter,
+    ) -> (ComputedStyle, LengthContext) {
+        let rule_node = self.rule_node(dom_node, rule_tree, filter);
+        compute_rule_node_values(rule_tree, rule_node, parent, &self.media)
+    }
+
+    // Text and comment nodes aren't matched by selectors, so they only get
+    // inherited and initial values. Elements' cascade results come from
+    // rule_tree, which adds the ones it doesn't have yet.
+    fn rule_node(
+        &self,
+        dom_node: &DomNodeRef,
+        rule_tree: &mut RuleTree,
+        filter: &AncestorFilter,
+    ) -> RuleNodeId {
+        let default_declarations = self.default_declarations(dom_node);
+        self.rule_node_with_defaults(dom_node, &default_declarations, rule_tree, filter)
+    }
+
+    // Like rule_node(), with the default declarations of dom_node's custom
+    // element definition
+    fn rule_node_with_defaults(
+        &self,
+        dom_node: &DomNodeRef,
+        default_declarations: &[Declaration],
+        rule_tree: &mut RuleTree,
+        filter: &AncestorFilter,
+    ) -> RuleNodeId {
+        if !dom_node.borrow().elem_type.is_element() {
+            return rule_tree.root();
+        }
+        let inline_declarations = self.inline_declarations(dom_node);
+        let declarations = self.cascaded_declarations(
+            dom_node,
+            None,
+            default_declarations,
+            &inline_declarations,
+            Some(filter),
+        );
+        rule_tree.insert_declarations(declarations)
+    }
+
+    // Declarations that apply to pseudo_element of dom_node, or dom_node itself
+    // if it's None. default_declarations are from the node's custom element
+    // definition, and inline_declarations from its style attribute. filter, if
+    // any, holds the node's ancestors.
+    fn cascaded_declarations<'a>(
+        &'a self,
+        dom_node: &DomNodeRef,
+        pseudo_element: Option<&PseudoElementSelector>,
+        default_declarations: &'a [Declaration],
+        inline_declarations: &'a [Declaration],
+        filter: Option<&AncestorFilter>,
+    ) -> Vec<CascadedDeclaration<'a>> {
+        let mut declarations = vec![];
+        let rule_map = self.rule_map();
+        let layer_orders = self.layer_orders();
+        let scopes = NodeScopes::new(dom_node, &self.config);
+        // Only rules in the node's buckets can match it
+        for index in rule_map.candidates(dom_node) {
+            // Rejects e.g. `.menu a` without walking the ancestors
+            if filter.is_some_and(|filter| !filter.might_match(rule_map.ancestor_hashes(index))) {
+                continue;
+            }
+            let (ref origin, ref stylesheet) = self.stylesheets[index.0];
+            let scope = self.scopes.get(&index.0).cloned();
+            let (config, context) = match scopes.config(*origin, scope) {
+                Some(found) => found,
+                None => continue,
+            };
+            let rule = &stylesheet.rules[index.1];
+            let specificity =
+                match self.rule_specificity(dom_node, pseudo_element, rule, config, context) {
+                    Some(specificity) => specificity,
+                    None => continue,
+                };
+            let layer = layer_orders[origin].rank(rule.layer.as_deref());
+            let source_order = rule_map.source_order(index);
+            for (i, decl) in rule.declarations.iter().enumerate() {
+                let allowed = pseudo_element.is_none_or(|pe| pe.allows_property(&decl.property));
+                if allowed && is_valid(decl) {
+                    declarations.push(CascadedDeclaration {
+                        context,
+                        ..CascadedDeclaration::new_with_layer(
+                            decl,
+                            *origin,
+                            layer,
+                            specificity,
+                            source_order + i,
+                        )
+                    });
+                }
+            }
+        }
+        // Like a user agent rule for the element's tag, after the others
+        let source_order = rule_map.declaration_count();
+        for (i, decl) in default_declarations.iter().filter(|decl| is_valid(decl)).enumerate() {
+            declarations.push(CascadedDeclaration::new(
+                decl,
+                Origin::UserAgent,
+                Specificity::default(),
+                source_order + i,
+            ));
+        }
+        let source_order = source_order + default_declarations.len();
+        for (i, decl) in inline_declarations.iter().filter(|decl| is_valid(decl)).enumerate() {
+            declarations.push(CascadedDeclaration::new_inline(decl, source_order + i));
+        }
+        declarations
+    }
+
+    fn rule_map(&self) -> &Arc<RuleMap> {
+        self.rule_map.get_or_init(|| {
+            let stylesheets = self.stylesheets.iter().map(|(_, stylesheet)| &**stylesheet);
+            Arc::new(RuleMap::new(stylesheets, &self.config))
+        })
+    }
+
+    fn is_quirks(&self) -> bool {
+        self.config.quirks_mode == QuirksMode::Quirks
+    }
+
+    // Filter holding the ancestors of dom_node, for a traversal starting at it
+    fn ancestor_filter(&self, dom_node: &DomNodeRef) -> AncestorFilter {
+        let ancestors = dom_node.shadow_including_ancestors().collect::<Vec<_>>();
+        let mut filter = AncestorFilter::new(self.is_quirks());
+        for node in ancestors.iter().rev() {
+            filter.push(node);
+        }
+        filter
+    }
+
+    fn layer_orders(&self) -> HashMap<Origin, LayerOrder> {
+        let mut layer_orders: HashMap<Origin, LayerOrder> = HashMap::new();
+        for (origin, stylesheet) in &self.stylesheets {
+            layer_orders.entry(*origin).or_default().add_stylesheet(stylesheet);
+        }
+        layer_orders
+    }
+
+    // Specificity of the selector of rule that matches pseudo_element of
+    // dom_node, or dom_node itself if it's None, when matched with config in
+    // context. None if the rule doesn't apply.
+    fn rule_specificity(
+        &self,
+        dom_node: &DomNodeRef,
+        pseudo_element: Option<&PseudoElementSelector>,
+        rule: &StyleRule,
+        config: &MatcherConfig,
+        context: TreeContext,
+    ) -> Option<Specificity> {
+        let applies = rule.media.iter().all(|media| self.media.matches(media))
+            && rule.containers.iter().all(|query| {
+                self.matches_container_query(dom_node, pseudo_element.is_some(), query)
+            });
+        if !applies {
+            return None;
+        }
+        match (context, pseudo_element) {
+            // The host's tree only styles dom_node as one of the host's parts
+            (TreeContext::HostTree, None) => {
+                matching_part_specificity(dom_node, &rule.selector, config)
+            }
+            (TreeContext::HostTree, Some(_)) => None,
+            _ => matching_specificity(dom_node, &rule.selector, pseudo_element, config),
+        }
+    }
+
+    // Default declarations of the custom element definition dom_node was
+    // upgraded with
+    fn default_declarations(&self, dom_node: &DomNodeRef) -> Vec<Declaration> {
+        let definition = match dom_node.custom_element_definition() {
+            Some(definition) => definition,
+            None => return vec![],
+        };
+        let mut declarations = definition.default_style.clone();
+        if self.normalize_prefixes {
+            normalize_declarations(&mut declarations);
+        }
+        declarations
+    }
+
+    // Declarations of dom_node's style attribute
+    fn inline_declarations(&self, dom_node: &DomNodeRef) -> Vec<Declaration> {
+        let mut declarations = inline_declarations(dom_node);
+        if self.normalize_prefixes {
+            normalize_declarations(&mut declarations);
+        }
+        declarations
+    }
+
+    // Evaluates query against the nearest container of dom_node that's
+    // eligible for it, which for pseudo-elements may be dom_node itself.
+    // Containers that haven't been laid out are skipped, and queries with no
+    // container never match.
+    fn matches_container_query(
+        &self,
+        dom_node: &DomNodeRef,
+        pseudo_element: bool,
+        query: &ContainerQuery,
+    ) -> bool {
+        let mut ancestor = if pseudo_element {
+            Some(dom_node.clone())
+        } else {
+            dom_node.parent_or_host()
+        };
+        while let Some(node) = ancestor {
+            let container = self.containers.get(&node.borrow().id_num);
+            if let Some(container) = container.filter(|container| container.is_eligible(query)) {
+                return container.matches(query);
+            }
+            ancestor = node.parent_or_host();
+        }
+        false
+    }
+}
+
+// The configs a node is matched against the rules of each stylesheet with.
+// Author stylesheets scoped to a shadow root only apply in its shadow tree
+// and, through :host, to its host, and the others only outside of shadow
+// trees. Through ::part(), those of the tree of a shadow tree's host apply to
+// the shadow tree too.
+struct NodeScopes<'a> {
+    config: &'a MatcherConfig,
+    // Root of the shadow tree the node is in, and the config for matching the
+    // tree's rules
+    tree: Option<(NodeId, MatcherConfig)>,
+    // The node's own shadow root, and the config for matching its rules
+    // against the node as its host
+    own: Option<(NodeId, MatcherConfig)>,
+    // If the node is in a shadow tree, the root of the shadow tree its host is
+    // in, or None for no shadow tree, and the config for matching that tree's
+    // rules against the node as a part of the host
+    host_tree: Option<(Option<NodeId>, MatcherConfig)>,
+}
+
+impl<'a> NodeScopes<'a> {
+    fn new(dom_node: &DomNodeRef, config: &'a MatcherConfig) -> NodeScopes<'a> {
+        let mut scopes = NodeScopes { config, tree: None, own: None, host_tree: None };
+        if !shadow_roots_attached() {
+            return scopes;
+        }
+        let with_host = |shadow_host| {
+            let mut config = config.clone();
+            config.shadow_host = shadow_host;
+            config
+        };
+        if let Some(shadow_root) = dom_node.containing_shadow_root() {
+            let host = shadow_root.host();
+            let host_root = host.as_ref().and_then(DomNodeRef::containing_shadow_root);
+            let host_tree_config = with_host(host_root.as_ref().and_then(DomNodeRef::host));
+            scopes.host_tree = Some((host_root.map(|root| root.node_id()), host_tree_config));
+            scopes.tree = Some((shadow_root.node_id(), with_host(host)));
+        }
+        if let Some(shadow_root) = dom_node.shadow_root() {
+            scopes.own = Some((shadow_root.node_id(), with_host(Some(dom_node.clone()))));
+        }
+        scopes
+    }
+
+    // The config for the rules of a stylesheet of origin scoped to scope, or
+    // to no shadow tree if it's None, and the tree context they're in. None if
+    // the rules don't apply to the node.
+    fn config(
+        &self,
+        origin: Origin,
+        scope: Option<NodeId>,
+    ) -> Option<(&MatcherConfig, TreeContext)> {
+        // The user agent's and the user's stylesheets apply to every tree
+        if scope.is_none() && (origin != Origin::Author || self.tree.is_none()) {
+            return Some((self.config, TreeContext::Tree));
+        }
+        match (&self.tree, &self.own, &self.host_tree) {
+            (Some((root, config)), _, _) if Some(*root) == scope => {
+                Some((config, TreeContext::Tree))
+            }
+            (_, Some((root, config)), _) if Some(*root) == scope => {
+                Some((config, TreeContext::ShadowTree))
+            }
+            (_, _, Some((root, config))) if *root == scope => {
+                Some((config, TreeContext::HostTree))
+            }
+            _ => None,
+        }
+    }
+}
+
+// The nodes that inherit dom_node's style: its children and, if it's a
+// shadow host, its shadow root
+fn styled_children(dom_node: &DomNodeRef) -> Children {
+    let node = dom_node.borrow();
+    let mut children = node.children.clone();
+    children.extend(node.shadow_root.clone());
+    children
+}
+
+// Computes the values of the cascaded style of rule_node
+fn compute_rule_node_values(
+    rule_tree: &RuleTree,
+    rule_node: RuleNodeId,
+    parent: Option<(&ComputedStyle, &LengthContext)>,
+    media: &MediaContext,
+) -> (ComputedStyle, LengthContext) {
+    let style = (*rule_tree.style(rule_node)).clone();
+    let order = rule_tree
+        .declarations(rule_node)
+        .into_iter()
+        .map(|(_, declaration)| declaration)
+        .collect::<Vec<_>>();
+    compute_values(style, &order, parent, media)
+}
+
+// Computes the values of a cascaded style, whose declarations are in
+// cascade order. CSS-wide keywords are only resolved for supported
+// properties, since the others have no known initial value.
+fn compute_values(
+    mut style: ComputedStyle,
+    declarations: &[&Declaration],
+    parent: Option<(&ComputedStyle, &LengthContext)>,
+    media: &MediaContext,
+) -> (ComputedStyle, LengthContext) {
+    let parent_style = parent.map(|(parent_style, _)| parent_style);
+    for property in PROPERTIES {
+        resolve_css_wide_keyword(&mut style, property, parent_style);
+    }
+
+    // Logical properties depend on the computed writing-mode and
+    // direction
+    for (physical, value) in map_logical_properties(&mut style, declarations) {
+        style.set(physical, &value);
+        if let Some(property) = lookup_property(physical) {
+            resolve_css_wide_keyword(&mut style, property, parent_style);
+        }
+    }
+
+    // Other lengths can be relative to the font size, so it's computed
+    // first. Its own em and % units are relative to the parent's font size.
+    let mut context = match parent {
+        Some((_, parent_context)) => *parent_context,
+        None => LengthContext {
+            font_size: media.font_size,
+            root_font_size: media.font_size,
+            viewport_width: media.viewport_width,
+            viewport_height: media.viewport_height,
+        },
+    };
+    let font_size = style
+        .get("font-size")
+        .and_then(|value| compute_font_size(value, &context, media.font_size))
+        .unwrap_or(context.font_size);
+    style.set("font-size", &format!("{}px", font_size));
+    context.font_size = font_size;
+    if parent.is_none() {
+        context.root_font_size = font_size;
+    }
+
+    // Same for currentColor
+    let parent_color = parent_style
+        .and_then(|parent_style| parent_style.get("color"))
+        .and_then(Color::parse)
+        .map_or(Rgba::opaque(0, 0, 0), |color| color.resolve(Rgba::opaque(0, 0, 0)));
+    let color = style
+        .get("color")
+        .and_then(Color::parse)
+        .map_or(parent_color, |color| color.resolve(parent_color));
+    style.set("color", &color.to_string());
+
+    // bolder and lighter are relative to the parent's weight
+    let parent_weight = parent_style
+        .and_then(|parent_style| parent_style.get("font-weight"))
+        .and_then(parse_font_weight)
+        .unwrap_or(400);
+    let weight = match style.get("font-weight").map(|weight| weight.to_ascii_lowercase()) {
+        Some(ref weight) if weight == "bolder" => Some(bolder(parent_weight)),
+        Some(ref weight) if weight == "lighter" => Some(lighter(parent_weight)),
+        _ => None,
+    };
+    if let Some(weight) = weight {
+        style.set("font-weight", &weight.to_string());
+    }
+
+    // A line-height length or percentage is computed to px, so that children
+    // inherit the length rather than the em or percentage of their own font
+    // size. Numbers are inherited as they are.
+    let line_height = style.get("line-height").and_then(|value| {
+        if value.parse::<f32>().is_ok() {
+            return None;
+        }
+        Some(LengthOrPercentage::parse(value)?.compute(&context)?.to_px(font_size))
+    });
+    if let Some(line_height) = line_height {
+        style.set("line-height", &format!("{}px", line_height));
+    }
+
+    for property in PROPERTIES {
+        if property.name == "font-size" || property.name == "color" {
+            continue;
+        }
+        let value = style.get(property.name).unwrap();
+        let computed = match property.value_type {
+            ValueType::Any => continue,
+            ValueType::LengthOrPercentage => compute_lengths(value, &context),
+            ValueType::Color => match Color::parse(value) {
+                Some(value) => value.resolve(color).to_string(),
+                None => continue,
+            },
+            ValueType::Time => match compute_times(value) {
+                Some(value) => value,
+                None => continue,
+            },
+        };
+        style.set(property.name, &computed);
+    }
+    if let Some(parent_style) = parent_style {
+        style.share_groups(parent_style);
+    }
+    (style, context)
+}
+
+// Styles nodes as the parser adds them, for StyleResolver::resolve_streaming()
+struct StreamingStyler<'a, F> {
+    resolver: &'a StyleResolver,
+    rule_tree: RuleTree,
+    filter: AncestorFilter,
+    // Styles of the nodes whose children are being parsed, outermost first
+    open: Vec<(ComputedStyle, LengthContext)>,
+    on_styled: F,
+}
+
+impl<'a, F: FnMut(&DomNodeRef, &ComputedStyle)> HtmlSink for StreamingStyler<'a, F> {
+    fn node_opened(&mut self, dom_node: &DomNodeRef) {
+        let parent = self.open.last().map(|(style, context)| (style, context));
+        let (style, context) =
+            self.resolver.compute_style(dom_node, parent, &mut self.rule_tree, &self.filter);
+        (self.on_styled)(dom_node, &style);
+        self.filter.push(dom_node);
+        self.open.push((style, context));
+    }
+
+    fn node_closed(&mut self, dom_node: &DomNodeRef) {
+        self.filter.pop(dom_node);
+        self.open.pop();
+        // Adding the node's children marked it dirty
+        let mut node = dom_node.borrow_mut();
+        node.style_dirty = false;
+        node.self_style_dirty = false;
+        node.descendants_dirty = false;
+    }
+}
+
+// Computes the values of the first node in nodes and its descendants, a
+// level of the tree at a time, each in parallel. Levels are used rather than
+// recursing into subtrees in parallel, since the tree can be too deep for
+// rayon's threads' stacks.
+#[cfg(feature = "parallel")]
+fn compute_subtree(
+    rule_tree: &RuleTree,
+    nodes: &[CascadedNode],
+    media: &MediaContext,
+) -> Vec<(usize, ComputedStyle, LengthContext)> {
+    let mut computed: Vec<Option<(ComputedStyle, LengthContext)>> =
+        nodes.iter().map(|_| None).collect();
+    // Indices of the nodes of a level, and of their parents
+    let mut level: Vec<(usize, Option<usize>)> = vec![(0, None)];
+    while !level.is_empty() {
+        let values = level
+            .par_iter()
+            .map(|&(index, parent)| {
+                let parent = parent.and_then(|parent| computed[parent].as_ref());
+                let parent = parent.map(|(style, context)| (style, context));
+                compute_rule_node_values(rule_tree, nodes[index].rule_node, parent, media)
+            })
+            .collect::<Vec<_>>();
+        let next_level = level
+            .iter()
+            .flat_map(|&(index, _)| {
+                nodes[index].children.iter().map(move |&child| (child, Some(index)))
+            })
+            .collect();
+        for (&(index, _), values) in level.iter().zip(values) {
+            computed[index] = Some(values);
+        }
+        level = next_level;
+    }
+    nodes
+        .iter()
+        .zip(computed)
+        .filter_map(|(node, computed)| {
+            computed.map(|(style, context)| (node.id_num, style, context))
+        })
+        .collect()
+}
+
+// Replaces a CSS-wide keyword in property, or its lack of a value, with the
+// inherited or initial value
+fn resolve_css_wide_keyword(
+    style: &mut ComputedStyle,
+    property: &Property,
+    parent_style: Option<&ComputedStyle>,
+) {
+    let inherit = match style.get(property.name).map(CssWideKeyword::parse) {
+        Some(None) => return,
+        Some(Some(CssWideKeyword::Initial)) => false,
+        Some(Some(CssWideKeyword::Inherit)) => true,
+        // The cascade already replaced revert
+        None | Some(Some(CssWideKeyword::Unset)) | Some(Some(CssWideKeyword::Revert)) => {
+            property.inherited
+        }
+    };
+    let value = match parent_style {
+        Some(parent_style) if inherit => {
+            parent_style.get(property.name).unwrap_or(property.initial)
+        }
+        _ => property.initial,
+    };
+    style.set(property.name, value);
+}
+
+fn inline_declarations(dom_node: &DomNodeRef) -> Vec<Declaration> {
+    match dom_node.borrow().attr("style") {
+        Some(Some(style)) => parse_style_attribute(style),
+        _ => vec![],
+    }
+}
+
+// Marks the elements in root's tree whose style attribute has lengths in
+// viewport units as needing to be restyled
+fn invalidate_viewport_relative_inline_styles(root: &DomNodeRef) {
+    if !root.borrow().elem_type.is_element() {
+        return;
+    }
+    let declarations = inline_declarations(root);
+    if declarations.iter().any(|decl| has_viewport_units(&decl.value)) {
+        root.mark_self_style_dirty();
+    }
+    // Not the shadow root, which has no style attribute, but its children
+    let shadow_root = root.shadow_root();
+    let mut children = root.borrow().children.to_vec();
+    children.extend(shadow_root.iter().flat_map(|root| root.borrow().children.to_vec()));
+    for child in &children {
+        invalidate_viewport_relative_inline_styles(child);
+    }
+}
+
+// context is the parent's, and medium is the size of `medium`. Returns None
+// for invalid values.
+fn compute_font_size(value: &str, context: &LengthContext, medium: f32) -> Option<f32> {
+    let parent_font_size = context.font_size;
+    let px = match value.to_ascii_lowercase().as_str() {
+        "xx-small" => medium * 3.0 / 5.0,
+        "x-small" => medium * 3.0 / 4.0,
+        "small" => medium * 8.0 / 9.0,
+        "medium" => medium,
+        "large" => medium * 6.0 / 5.0,
+        "x-large" => medium * 3.0 / 2.0,
+        "xx-large" => medium * 2.0,
+        "larger" => parent_font_size * 1.2,
+        "smaller" => parent_font_size / 1.2,
+        _ => LengthOrPercentage::parse(value)?
+            .compute(context)?
+            .to_px(parent_font_size),
+    };
+    Some(px)
+}
+
+// Computes each space-separated length or percentage in value, e.g. for
+// `margin: 1em auto`. Anything else is kept as is.
+fn compute_lengths(value: &str, context: &LengthContext) -> String {
+    let compute = |value: &str| {
+        LengthOrPercentage::parse(value)
+            .and_then(|length| length.compute(context))
+            .map(|computed| computed.to_string())
+    };
+    // calc() can contain spaces
+    if let Some(computed) = compute(value) {
+        return computed;
+    }
+    value
+        .split_whitespace()
+        .map(|component| compute(component).unwrap_or_else(|| component.to_string()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Computes a comma-separated list of times to seconds, e.g. `200ms, 1s` to
+// `0.2s, 1s`. Returns None if any of them is invalid.
+fn compute_times(value: &str) -> Option<String> {
+    let times = value
+        .split(',')
+        .map(|time| parse_time(time).map(|seconds| format!("{}s", seconds)))
+        .collect::<Option<Vec<_>>>()?;
+    Some(times.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{
+        define_custom_element, parse_html, parse_stylesheet, Atom, CustomElementDefinition,
+    };
+    use style::font::FontStyle;
+
+    #[test]
+    fn test_resolve() {
+        let root = parse_html(
+            "<html><body><div class=\"a\"><p>One</p><p id=\"two\">Two</p></div></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "p { color: red; margin: 0 } \
+             .a > p { color: blue } \
+             #two { color: green; } \
+             p::first-line { color: black }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p1 = div.borrow().children[0].clone();
+        let p2 = div.borrow().children[1].clone();
+        assert_eq!(styles.get(&root).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+        assert_eq!(styles.get(&p1).unwrap().get("margin-top"), Some("0px"));
+        assert_eq!(styles.get(&p2).unwrap().get("color"), Some("rgb(0, 128, 0)"));
+        assert_eq!(styles.get(&div).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+        // Every node is styled, including text nodes
+        assert_eq!(styles.len(), 7);
+    }
+
+    #[test]
+    fn test_resolve_cascade_order() {
+        let root =
+            parse_html("<html><body><p id=\"p\" class=\"a\">One</p></body></html>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "#p { color: red } \
+             p.a { color: blue; margin: 1px } \
+             .a { margin: 2px } \
+             p, .a[id] { margin: 3px } \
+             p { margin: 4px; padding: 0 !important } \
+             #p { padding: 1px }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        let style = styles.get(&p).unwrap();
+        // The id selector wins even though it comes first
+        assert_eq!(style.get("color"), Some("rgb(255, 0, 0)"));
+        // .a[id] is more specific than p.a
+        assert_eq!(style.get("margin-top"), Some("3px"));
+        assert_eq!(style.get("padding-top"), Some("0px"));
+
+        // Shorthands set their longhands where they are in the cascade
+        let root = parse_html("<html><body><div></div><p></p></body></html>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "div { margin-left: 10px; margin: 0 } p { margin: 10px; margin-left: 0 }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p = body.borrow().children[1].clone();
+        assert_eq!(styles.get(&div).unwrap().get("margin-left"), Some("0px"));
+        assert_eq!(styles.get(&p).unwrap().get("margin-left"), Some("0px"));
+        assert_eq!(styles.get(&p).unwrap().get("margin-top"), Some("10px"));
+    }
+
+    #[test]
+    fn test_resolve_origins() {
+        let root = parse_html(
+            "<html><head><title>Title</title></head>\
+             <body><h1>Heading</h1><p>Text</p></body></html>",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(
+            parse_stylesheet("h1 { margin: 0; color: red !important } p { color: blue }").unwrap(),
+        );
+        resolver.add_stylesheet(
+            parse_stylesheet("h1 { color: black !important; margin: 1px } p { color: green }")
+                .unwrap(),
+            Origin::User,
+        );
+        let styles = resolver.resolve(&root);
+        let head = root.borrow().children[0].clone();
+        let body = root.borrow().children[1].clone();
+        let h1 = body.borrow().children[0].clone();
+        let p = body.borrow().children[1].clone();
+
+        // From the user agent stylesheet
+        assert_eq!(styles.get(&root).unwrap().get("display"), Some("block"));
+        assert_eq!(styles.get(&head).unwrap().get("display"), Some("none"));
+        assert_eq!(styles.get(&body).unwrap().get("margin-top"), Some("8px"));
+        assert_eq!(styles.get(&h1).unwrap().get("font-size"), Some("32px"));
+        // Authors override users, unless the user's declaration is important
+        assert_eq!(styles.get(&h1).unwrap().get("margin-top"), Some("0px"));
+        assert_eq!(styles.get(&h1).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+    }
+
+    #[test]
+    fn test_resolve_inheritance() {
+        let root = parse_html(
+            "<html><body><div id=\"a\">One<p>Two</p></div><p>Three</p></body></html>",
+        ).unwrap();
+        let stylesheet =
+            parse_stylesheet("body { color: red } #a { padding: 1px; font-style: italic }")
+                .unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let text = div.borrow().children[0].clone();
+        let p1 = div.borrow().children[1].clone();
+        let p2 = body.borrow().children[1].clone();
+
+        // Inherited properties come from the parent
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        assert_eq!(styles.get(&p1).unwrap().get("font-style"), Some("italic"));
+        assert_eq!(styles.get(&p2).unwrap().get("font-style"), Some("normal"));
+        assert_eq!(styles.get(&text).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        // Other properties get their initial value
+        assert_eq!(styles.get(&div).unwrap().get("padding-top"), Some("1px"));
+        assert_eq!(styles.get(&p1).unwrap().get("padding-top"), Some("0px"));
+        assert_eq!(styles.get(&text).unwrap().get("display"), Some("inline"));
+        assert_eq!(styles.get(&text).unwrap().get("padding-top"), Some("0px"));
+        // Declarations on the node beat inherited values
+        assert_eq!(styles.get(&root).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+        assert_eq!(styles.get(&body).unwrap().get("display"), Some("block"));
+    }
+
+    #[test]
+    fn test_resolve_css_wide_keywords() {
+        let root = parse_html(
+            "<html><body><div><p>One</p><h1>Two</h1><a class=\"r\">Three</a><h2>Four</h2>\
+             </div></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "div { color: red; padding: 1px; display: inline } \
+             p { color: initial; padding: inherit } \
+             h1 { color: unset; display: unset; font-weight: revert; margin: revert } \
+             .r { display: revert } \
+             h2 { all: revert } div h2 { all: inherit; font-size: 10px }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p = div.borrow().children[0].clone();
+        let h1 = div.borrow().children[1].clone();
+        let a = div.borrow().children[2].clone();
+        let h2 = div.borrow().children[3].clone();
+
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+        assert_eq!(styles.get(&p).unwrap().get("padding-top"), Some("1px"));
+        assert_eq!(styles.get(&h1).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        assert_eq!(styles.get(&h1).unwrap().get("display"), Some("inline"));
+        // Back to the user agent stylesheet's values
+        assert_eq!(styles.get(&h1).unwrap().get("font-weight"), Some("bold"));
+        assert_eq!(styles.get(&h1).unwrap().get("margin-top"), Some("21.44px"));
+        assert_eq!(styles.get(&h1).unwrap().get("margin-left"), Some("0px"));
+        // The user agent stylesheet doesn't set display for a, so it's unset
+        assert_eq!(styles.get(&a).unwrap().get("display"), Some("inline"));
+        let style = styles.get(&h2).unwrap();
+        assert_eq!(style.get("display"), Some("inline"));
+        assert_eq!(style.get("padding-top"), Some("1px"));
+        assert_eq!(style.get("font-size"), Some("10px"));
+    }
+
+    #[test]
+    fn test_resolve_lengths() {
+        let root = parse_html(
+            "<html><body><div><p>One</p><h1>Two</h1></div></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "html { font-size: 10px } \
+             div { font-size: 150%; width: calc(50% - 1em); padding: 1rem 10vw } \
+             p { font-size: larger; margin: 1em auto; height: 50vh } \
+             h1 { font-size: 2rem; margin: inherit }",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        resolver.set_viewport_size(1000.0, 500.0);
+        let styles = resolver.resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p = div.borrow().children[0].clone();
+        let h1 = div.borrow().children[1].clone();
+
+        assert_eq!(styles.get(&body).unwrap().get("font-size"), Some("10px"));
+        assert_eq!(styles.get(&body).unwrap().get("margin-top"), Some("8px"));
+        assert_eq!(styles.get(&div).unwrap().get("font-size"), Some("15px"));
+        assert_eq!(styles.get(&div).unwrap().get("width"), Some("calc(50% - 15px)"));
+        assert_eq!(styles.get(&div).unwrap().get("padding-top"), Some("10px"));
+        assert_eq!(styles.get(&div).unwrap().get("padding-left"), Some("100px"));
+        assert_eq!(styles.get(&p).unwrap().get("font-size"), Some("18px"));
+        assert_eq!(styles.get(&p).unwrap().get("margin-top"), Some("18px"));
+        assert_eq!(styles.get(&p).unwrap().get("margin-left"), Some("auto"));
+        assert_eq!(styles.get(&p).unwrap().get("height"), Some("250px"));
+        assert_eq!(styles.get(&p).unwrap().get("width"), Some("auto"));
+        assert_eq!(styles.get(&h1).unwrap().get("font-size"), Some("20px"));
+        // Inherited lengths are already computed
+        assert_eq!(styles.get(&h1).unwrap().get("margin-top"), Some("0px"));
+    }
+
+    #[test]
+    fn test_resolve_colors() {
+        let root = parse_html("<html><body><div><p>One</p><a>Two</a></div></body></html>")
+            .unwrap();
+        let stylesheet = parse_stylesheet(
+            "div { color: #00f8; background-color: currentColor } \
+             p { color: hsl(120, 100%, 50%); background-color: currentcolor } \
+             a { color: currentColor; background-color: notacolor }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p = div.borrow().children[0].clone();
+        let a = div.borrow().children[1].clone();
+
+        let style = styles.get(&body).unwrap();
+        assert_eq!(style.get("background-color"), Some("rgba(0, 0, 0, 0)"));
+        let style = styles.get(&div).unwrap();
+        let blue = "rgba(0, 0, 255, 0.53333336)";
+        assert_eq!(style.get("color"), Some(blue));
+        assert_eq!(style.get("background-color"), Some(blue));
+        let style = styles.get(&p).unwrap();
+        assert_eq!(style.get("color"), Some("rgb(0, 255, 0)"));
+        assert_eq!(style.get("background-color"), Some("rgb(0, 255, 0)"));
+        // currentColor in color is the parent's color
+        let style = styles.get(&a).unwrap();
+        assert_eq!(style.get("color"), Some(blue));
+        // Invalid, so ignored
+        assert_eq!(style.get("background-color"), Some("rgba(0, 0, 0, 0)"));
+    }
+
+    #[test]
+    fn test_resolve_fonts() {
+        let root = parse_html("<html><body><h1>One <b>Two</b></h1></body></html>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "@font-face { font-family: Foo; src: url(foo-bold.woff); font-weight: bold } \
+             body { font-family: Foo, Bar, sans-serif; font-weight: lighter } \
+             b { font-weight: bolder; font-stretch: condensed }",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        resolver.add_system_font(FontFace {
+            family: "Bar".to_string(),
+            sources: vec![],
+            weight: (100, 900),
+            style: FontStyle::Normal,
+            stretch: (50.0, 100.0),
+        });
+        let styles = resolver.resolve(&root);
+        let registry = resolver.font_registry();
+        let body = root.borrow().children[0].clone();
+        let h1 = body.borrow().children[0].clone();
+        let b = h1.borrow().children[1].clone();
+
+        assert_eq!(styles.get(&body).unwrap().get("font-weight"), Some("100"));
+        // bold from the user agent stylesheet
+        assert_eq!(styles.get(&b).unwrap().get("font-weight"), Some("900"));
+        let font = registry.match_style(styles.get(&b).unwrap());
+        assert_eq!((font.family.as_str(), font.weight, font.stretch), ("Foo", 900, 75.0));
+        assert_eq!(registry.match_style(styles.get(&body).unwrap()).family, "Foo");
+    }
+
+    #[test]
+    fn test_resolve_line_height() {
+        let root = parse_html("<html><body><p>One <b>Two</b></p><div>Three</div></body></html>")
+            .unwrap();
+        let stylesheet = parse_stylesheet(
+            "body { font-size: 10px; line-height: 150% } p { line-height: 2em } \
+             b { font-size: 20px } div { line-height: 1.5 }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        let b = p.borrow().children[1].clone();
+        let div = body.borrow().children[1].clone();
+        let line_height = |node: &DomNodeRef| styles.get(node).unwrap().get("line-height");
+        assert_eq!(line_height(&body), Some("15px"));
+        // The b inherits its parent's line-height, not 2em of its own
+        assert_eq!(line_height(&p), Some("20px"));
+        assert_eq!(line_height(&b), Some("20px"));
+        assert_eq!(line_height(&div), Some("1.5"));
+    }
+
+    #[test]
+    fn test_resolve_logical_properties() {
+        let root = parse_html("<html><body><div><p>One</p></div></body></html>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "div { direction: rtl; margin-inline: 1px 2em; padding-left: 3px; \
+                   padding-inline-end: 4px; inset-block-start: 10% } \
+             div { padding-left: 5px } \
+             p { writing-mode: vertical-rl; margin-block-start: inherit; padding-block: 6px }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p = div.borrow().children[0].clone();
+
+        let style = styles.get(&div).unwrap();
+        assert_eq!(style.get("margin-right"), Some("1px"));
+        assert_eq!(style.get("margin-left"), Some("32px"));
+        // Declared after padding-inline-end
+        assert_eq!(style.get("padding-left"), Some("5px"));
+        assert_eq!(style.get("top"), Some("10%"));
+        assert_eq!(style.get("margin-inline"), None);
+        let style = styles.get(&p).unwrap();
+        assert_eq!(style.get("direction"), Some("rtl"));
+        // The parent's margin-right
+        assert_eq!(style.get("margin-right"), Some("1px"));
+        assert_eq!(style.get("padding-left"), Some("6px"));
+        assert_eq!(style.get("padding-right"), Some("6px"));
+        assert_eq!(style.get("padding-top"), Some("0px"));
+    }
+
+    #[test]
+    fn test_resolve_layers() {
+        let root = parse_html("<html><body><p id=\"a\" class=\"b\">One</p></body></html>")
+            .unwrap();
+        let stylesheet = parse_stylesheet(
+            "@layer base, components; \
+             p { padding: 1px } \
+             @layer components { #a.b { color: red; padding: 2px !important } } \
+             @layer base { #a { color: blue; padding: 3px !important } p { margin: 4px } }",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        let user = parse_stylesheet("@layer base { p { color: green } }").unwrap();
+        resolver.add_stylesheet(user, Origin::User);
+        let styles = resolver.resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+
+        let style = styles.get(&p).unwrap();
+        assert_eq!(style.get("color"), Some("rgb(255, 0, 0)"));
+        // Important declarations in earlier layers win
+        assert_eq!(style.get("padding-top"), Some("3px"));
+        assert_eq!(style.get("margin-top"), Some("4px"));
+    }
+
+    #[test]
+    fn test_resolve_media_rules() {
+        let root = parse_html("<html><body><p>One</p></body></html>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "p { color: red; margin: 0 } \
+             @media screen and (min-width: 600px) { \
+                 p { color: blue } \
+                 @media (orientation: portrait) { p { margin: 1px } } \
+             } \
+             @media print { p { color: green } }",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+
+        let styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+        assert_eq!(styles.get(&p).unwrap().get("margin-top"), Some("0px"));
+        resolver.set_viewport_size(700.0, 1000.0);
+        let styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&p).unwrap().get("margin-top"), Some("1px"));
+        resolver.set_viewport_size(500.0, 1000.0);
+        let styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        assert_eq!(styles.get(&p).unwrap().get("margin-top"), Some("0px"));
+        let print = MediaContext {
+            media_type: "print".to_string(),
+            ..MediaContext::default()
+        };
+        resolver.set_media_context(&root, print);
+        let styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(0, 128, 0)"));
+    }
+
+    #[test]
+    fn test_set_media_context() {
+        let root = parse_html(
+            "<html><body><p>One</p><div style=\"width: 50vw\"><i></i></div><span></span>\
+             </body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "@media (min-width: 600px) { p { color: blue } } \
+             i { height: 10vh } \
+             span { margin: 1em }",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        let div = body.borrow().children[1].clone();
+        let i = div.borrow().children[0].clone();
+        let span = body.borrow().children[2].clone();
+        let mut styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+
+        let narrow = MediaContext {
+            viewport_width: 500.0,
+            viewport_height: 400.0,
+            ..MediaContext::default()
+        };
+        resolver.set_media_context(&root, narrow.clone());
+        assert!(p.borrow().style_dirty && i.borrow().style_dirty);
+        assert!(div.borrow().self_style_dirty && !div.borrow().style_dirty);
+        assert!(!span.borrow().style_dirty && !span.borrow().self_style_dirty);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+        assert_eq!(styles.get(&div).unwrap().get("width"), Some("250px"));
+        assert_eq!(styles.get(&i).unwrap().get("height"), Some("40px"));
+
+        // Nothing depends on the media type
+        resolver.set_media_context(
+            &root,
+            MediaContext {
+                media_type: "print".to_string(),
+                ..narrow
+            },
+        );
+        assert!(!root.borrow().descendants_dirty);
+
+        let viewport = Viewport {
+            font_size: 20.0,
+            ..Viewport::new(500.0, 400.0)
+        };
+        resolver.set_viewport(&root, &viewport);
+        assert!(root.borrow().style_dirty);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&span).unwrap().get("margin-top"), Some("20px"));
+        assert_eq!(styles.get(&i).unwrap().get("height"), Some("40px"));
+    }
+
+    #[test]
+    fn test_resolve_container_queries() {
+        let root = parse_html(
+            "<html><body><div class=\"card\"><p>One</p></div><p>Two</p></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            ".card { container-type: inline-size; container-name: card } \
+             @container card (min-width: 400px) { p { color: red } } \
+             @container (height > 100px) { p { margin-top: 1px } } \
+             @container sidebar (width > 0px) { p { margin-left: 1px } }",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p1 = div.borrow().children[0].clone();
+        let p2 = body.borrow().children[1].clone();
+        let mut styles = resolver.resolve(&root);
+        // Not laid out yet
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+
+        resolver.set_container_size(&div, &styles, 500.0, 200.0);
+        assert!(p1.borrow().style_dirty && !p2.borrow().style_dirty);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        // Block-axis features can't be queried in inline-size containers, so
+        // it's the user agent stylesheet's margin
+        assert_eq!(styles.get(&p1).unwrap().get("margin-top"), Some("16px"));
+        assert_eq!(styles.get(&p1).unwrap().get("margin-left"), Some("0px"));
+        assert_eq!(styles.get(&p2).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+
+        resolver.set_container_size(&div, &styles, 300.0, 200.0);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+    }
+
+    #[test]
+    fn test_resolve_vendor_prefixes() {
+        let root = parse_html(
+            "<html><body><p style=\"-webkit-margin-start: 2px\">One</p></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "p { -webkit-animation-duration: 1s; -moz-padding-end: 1px; padding-left: 3px }",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        let styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&p).unwrap().get("animation-duration"), Some("0s"));
+        assert_eq!(styles.get(&p).unwrap().get("-webkit-animation-duration"), Some("1s"));
+
+        resolver.normalize_vendor_prefixes();
+        let styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&p).unwrap().get("animation-duration"), Some("1s"));
+        assert_eq!(styles.get(&p).unwrap().get("-webkit-animation-duration"), None);
+        assert_eq!(styles.get(&p).unwrap().get("margin-left"), Some("2px"));
+        assert_eq!(styles.get(&p).unwrap().get("padding-right"), Some("1px"));
+        assert_eq!(styles.get(&p).unwrap().get("padding-left"), Some("3px"));
+    }
+
+    #[test]
+    fn test_matched_rules() {
+        let root = parse_html(
+            "<html><body><p id=\"a\" class=\"b\" style=\"margin: 2px; color: blue\">One</p>\
+             </body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "@layer base { #a { color: red } } \
+             p.b { margin: 1px; color: green !important; width: wide } \
+             div { color: black } \
+             p { display: inline }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        let matched = resolver.matched_rules(&p);
+        let selectors = matched
+            .iter()
+            .map(|rule| rule.selector.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(&selectors[..4], &[None, Some("p.b"), Some("p"), Some("#a")]);
+        // Followed by the user agent's rules
+        assert!(matched[4..].iter().all(|rule| rule.origin == Origin::UserAgent));
+        assert_eq!(matched[3].layer.as_deref(), Some("base"));
+        assert_eq!(matched[3].specificity, Specificity(1, 0, 0));
+
+        fn properties(rule: &MatchedRule, status: DeclarationStatus) -> Vec<&str> {
+            rule.declarations_with_status(status)
+                .map(|declaration| declaration.property.as_str())
+                .collect()
+        }
+        assert_eq!(properties(&matched[0], DeclarationStatus::Applied), vec!["margin"]);
+        assert_eq!(properties(&matched[0], DeclarationStatus::Overridden), vec!["color"]);
+        assert_eq!(properties(&matched[1], DeclarationStatus::Applied), vec!["color"]);
+        assert_eq!(properties(&matched[1], DeclarationStatus::Overridden), vec!["margin"]);
+        assert_eq!(properties(&matched[1], DeclarationStatus::Invalid), vec!["width"]);
+        assert_eq!(properties(&matched[3], DeclarationStatus::Overridden), vec!["color"]);
+        assert!(resolver.matched_rules(&p.borrow().children[0]).is_empty());
+    }
+
+    #[test]
+    fn test_dump_tree() {
+        let root = parse_html(
+            "<div class=\"a\"><p style=\"color: red\">One</p><p class=\"a\"></p></div>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(".a { color: blue } p { margin-left: 1px }").unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        assert_eq!(
+            resolver.dump_tree(&root),
+            "div.a\n\
+             \x20 - .a\n\
+             \x20 p[style=\"color: red\"]\n\
+             \x20   - style attribute\n\
+             \x20   - p\n\
+             \x20   \"One\"\n\
+             \x20 p.a\n\
+             \x20   - .a\n\
+             \x20   - p\n"
+        );
+    }
+
+    #[test]
+    fn test_resolve_inline_style() {
+        let root = parse_html(
+            "<html><body style=\"color: red; margin: 0 !important\">\
+             <p id=\"p\" style=\"COLOR: green; padding: 1px; bogus; margin: 2px\">One</p>\
+             </body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "#p { color: blue; padding: 2px !important } body { margin: 3px !important }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        assert_eq!(styles.get(&body).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        assert_eq!(styles.get(&body).unwrap().get("margin-top"), Some("0px"));
+        // Inline styles beat author rules, but not important ones
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(0, 128, 0)"));
+        assert_eq!(styles.get(&p).unwrap().get("padding-top"), Some("2px"));
+        assert_eq!(styles.get(&p).unwrap().get("margin-top"), Some("2px"));
+    }
+
+    #[test]
+    fn test_restyle() {
+        let root = parse_html("<html><body><p>One</p><div><p>Two</p></div></body></html>")
+            .unwrap();
+        let stylesheet = parse_stylesheet(
+            "p { color: red } .a p { color: blue } .a { font-size: 20px } \
+             p[title] { padding: 1em }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let p1 = body.borrow().children[0].clone();
+        let div = body.borrow().children[1].clone();
+        let p2 = div.borrow().children[0].clone();
+        let mut styles = resolver.resolve(&root);
+        assert!(!root.borrow().style_dirty && !root.borrow().descendants_dirty);
+
+        div.add_class("a");
+        // Changed without marking p1 dirty, so its style isn't recomputed
+        p1.borrow_mut().attrs.insert(Atom::from("title"), None);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p2).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+        assert_eq!(styles.get(&p2).unwrap().get("font-size"), Some("20px"));
+        assert_eq!(styles.get(&p1).unwrap().get("padding-top"), Some("0px"));
+        assert!(!body.borrow().descendants_dirty && !div.borrow().style_dirty);
+
+        p1.set_attribute("title", None);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p1).unwrap().get("padding-top"), Some("16px"));
+
+        // New nodes inherit from their parent's existing style
+        let p3 = parse_html("<p>Three</p>").unwrap();
+        div.add_child(p3.clone());
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p3).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+        assert_eq!(styles.get(&p3).unwrap().get("padding-top"), Some("0px"));
+    }
+
+    #[test]
+    fn test_restyle_child_list_changes() {
+        let root = parse_html("<div><p>One</p><span><b></b></span></div>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "p:last-of-type { color: red } :nth-last-child(2) b { color: blue } \
+             :last-child { padding: 1px }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let mut styles = resolver.resolve(&root);
+        let assert_fresh = |styles: &StyleMap| {
+            let fresh = resolver.resolve(&root);
+            for node in Some(root.clone()).into_iter().chain(root.descendants()) {
+                assert_eq!(styles.get(&node), fresh.get(&node));
+            }
+        };
+
+        // Each changes how the earlier children match
+        let p = parse_html("<p>Two</p>").unwrap();
+        root.append_child(p.clone());
+        resolver.restyle(&root, &mut styles);
+        assert_fresh(&styles);
+        let first = root.borrow().children[0].clone();
+        root.insert_before(parse_html("<i></i>").unwrap(), Some(&first));
+        resolver.restyle(&root, &mut styles);
+        assert_fresh(&styles);
+        p.remove();
+        resolver.restyle(&root, &mut styles);
+        assert_fresh(&styles);
+    }
+
+    #[test]
+    fn test_restyle_invalidated() {
+        let root = parse_html("<html><body><div><p>One</p></div></body></html>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "div.b { font-size: 16px } div.c { font-size: 20px } p[title] { padding: 1em }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let map = resolver.invalidation_map();
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p = div.borrow().children[0].clone();
+        let mut styles = resolver.resolve(&root);
+
+        // Changed without marking p dirty, so its style is only recomputed if
+        // its parent's style changes
+        p.borrow_mut().attrs.insert(Atom::from("title"), None);
+        map.add_class(&div, "b");
+        assert!(div.borrow().self_style_dirty && !div.borrow().style_dirty);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p).unwrap().get("padding-top"), Some("0px"));
+        assert!(!div.borrow().self_style_dirty);
+
+        map.add_class(&div, "c");
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&div).unwrap().get("font-size"), Some("20px"));
+        assert_eq!(styles.get(&p).unwrap().get("padding-top"), Some("20px"));
+    }
+
+    #[test]
+    fn test_restyle_inline_style() {
+        use magicparser::set_invalidation_hook;
+        use std::rc::Rc;
+
+        let root = parse_html("<div><p style=\"color: red\">One</p></div>").unwrap();
+        let resolver = StyleResolver::new(parse_stylesheet("p { font-size: 20px }").unwrap());
+        let p = root.borrow().children[0].clone();
+        let mut styles = resolver.resolve(&root);
+        assert!(set_invalidation_hook(Some(Rc::new(resolver.invalidation_map()))).is_none());
+
+        // No selector depends on the style attribute, but its declarations do
+        p.set_attribute("style", Some("color: blue".to_string()));
+        resolver.restyle(&root, &mut styles);
+        assert!(set_invalidation_hook(None).is_some());
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+        assert_eq!(styles.get(&p), resolver.resolve(&root).get(&p));
+    }
+
+    #[test]
+    fn test_resolve_shared_styles() {
+        let root = parse_html(
+            "<ul><li class=\"a\">One</li><li class=\"a\">Two</li><li class=\"a\">Three</li>\
+             <li class=\"a\" style=\"color: green\">Four</li></ul>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            ".a { color: blue; font-size: 2em } li:nth-child(2) { color: red }",
+        ).unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let items = root.borrow().children.clone();
+        let color = |item: &DomNodeRef| styles.get(item).unwrap().get("color").unwrap().to_string();
+        assert_eq!(color(&items[0]), "rgb(0, 0, 255)");
+        assert_eq!(color(&items[1]), "rgb(255, 0, 0)");
+        assert_eq!(color(&items[2]), "rgb(0, 0, 255)");
+        assert_eq!(color(&items[3]), "rgb(0, 128, 0)");
+        assert_eq!(styles.get(&items[0]), styles.get(&items[2]));
+        let text = items[2].borrow().children[0].clone();
+        assert_eq!(styles.get(&text).unwrap().get("font-size"), Some("32px"));
+    }
+
+    #[test]
+    fn test_resolve_shared_property_groups() {
+        let root = parse_html("<html><body><div><p>One</p></div></body></html>").unwrap();
+        let stylesheet =
+            parse_stylesheet("div { font-size: 20px; width: 50% } p { color: red }").unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p = div.borrow().children[0].clone();
+        let shared = |a: &DomNodeRef, b: &DomNodeRef| {
+            let (a, b) = (styles.get(a).unwrap(), styles.get(b).unwrap());
+            a.groups.iter().zip(b.groups.iter()).map(|(a, b)| Arc::ptr_eq(a, b)).collect::<Vec<_>>()
+        };
+        // Groups: fonts, other inherited, animations, box, other
+        assert_eq!(shared(&body, &div), vec![false, true, true, false, true]);
+        assert_eq!(shared(&div, &p), vec![true, false, true, false, true]);
+
+        let mut style = styles.get(&p).unwrap().clone();
+        style.set("font-size", "10px");
+        assert_eq!(styles.get(&p).unwrap().get("font-size"), Some("20px"));
+        assert_eq!(style.get("font-size"), Some("10px"));
+    }
+
+    #[test]
+    fn test_restyle_edited_rules() {
+        let root = parse_html("<html><body><p>One</p><div><p>Two</p></div></body></html>")
+            .unwrap();
+        let stylesheet = parse_stylesheet("p { color: red } div p { color: blue }").unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let p1 = body.borrow().children[0].clone();
+        let div = body.borrow().children[1].clone();
+        let p2 = div.borrow().children[0].clone();
+        let mut styles = resolver.resolve(&root);
+
+        resolver.stylesheet_mut(1).unwrap().rules[1].set_property("color", "green", false);
+        resolver.invalidate_rule(&root, &resolver.stylesheets[1].1.rules[1]);
+        assert!(!p1.borrow().style_dirty && p2.borrow().style_dirty);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p2).unwrap().get("color"), Some("rgb(0, 128, 0)"));
+
+        let stylesheet = resolver.stylesheet_mut(1).unwrap();
+        stylesheet.insert_rule("body { color: purple; padding: 1px }", 0).unwrap();
+        let deleted = stylesheet.delete_rule(1).unwrap();
+        resolver.invalidate_rule(&root, &resolver.stylesheets[1].1.rules[0]);
+        resolver.invalidate_rule(&root, &deleted);
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&body).unwrap().get("padding-top"), Some("1px"));
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(128, 0, 128)"));
+        assert_eq!(styles.get(&p2).unwrap().get("color"), Some("rgb(0, 128, 0)"));
+    }
+
+    #[test]
+    fn test_resolve_ancestor_filter() {
+        let root = parse_html(
+            "<html><body class=\"menu\"><div id=\"nav\"><p>One</p></div><p>Two</p></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            ".menu #nav > p { color: red } .other p { color: blue } \
+             div + p, #nav p { margin: 1px }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p1 = div.borrow().children[0].clone();
+        let p2 = body.borrow().children[1].clone();
+        let styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        assert_eq!(styles.get(&p1).unwrap().get("margin-top"), Some("1px"));
+        assert_eq!(styles.get(&p2).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+        assert_eq!(styles.get(&p2).unwrap().get("margin-top"), Some("1px"));
+
+        // Resolving a subtree still sees the ancestors outside of it
+        let styles = resolver.resolve(&div);
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        assert_eq!(resolver.get_computed_style(&p1).get("color"), Some("rgb(255, 0, 0)"));
+
+        let mut styles = resolver.resolve(&root);
+        body.borrow_mut().classes.remove(&Atom::from("menu"));
+        p1.mark_self_style_dirty();
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&p1).unwrap().get("color"), Some("rgb(0, 0, 0)"));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_resolve_parallel() {
+        let root = parse_html(
+            "<html><body><div class=\"a\"><p>One</p><p id=\"two\">Two</p></div>\
+             <div><h1>Three</h1><p>Four</p></div></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "div { font-size: 20px; color: red } .a p { margin: 1em; color: inherit } \
+             #two { font-size: 2em; color: currentColor } h1 + p { width: 50% }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let expected = resolver.resolve(&root);
+        let styles = resolver.resolve_parallel(&root);
+        assert_eq!(styles.styles, expected.styles);
+        assert_eq!(styles.contexts, expected.contexts);
+        assert!(!root.borrow().descendants_dirty);
+
+        // Big enough to be split between threads, with shadow trees, custom
+        // elements and style attributes, from the root and from inside it
+        let section = "<section><div class=\"a\"><p>One</p><x-badge></x-badge></div>\
+                       <p style=\"color: green\">Two</p><x-badge class=\"b\"></x-badge>\
+                       </section>";
+        let root = parse_html(&format!("<html><body>{}</body></html>", section.repeat(50)))
+            .unwrap();
+        let body = root.borrow().children[0].clone();
+        let mut definition = CustomElementDefinition::new("x-badge");
+        definition.default_style = parse_style_attribute("display: block; padding: 2px");
+        define_custom_element(definition);
+        root.upgrade_custom_elements();
+        let mut resolver = StyleResolver::new(
+            parse_stylesheet(
+                "section:nth-child(odd) p { font-size: 2em } .a + p { width: 50% } \
+                 x-badge:defined { margin: 1px } .b { padding: 3px } div p { color: blue }",
+            ).unwrap(),
+        );
+        let sections = body.borrow().children.clone();
+        for section in sections.iter().step_by(7) {
+            let host = section.borrow().children[0].clone();
+            let shadow_root = host.attach_shadow().unwrap();
+            shadow_root.append_child(parse_html("<span><p>Shadow</p></span>").unwrap());
+            resolver.add_shadow_stylesheet(
+                &shadow_root,
+                parse_stylesheet(":host { opacity: 0.5 } :host(.a) p { color: red }").unwrap(),
+            );
+        }
+        for root in [&root, &body, &sections[7]] {
+            let expected = resolver.resolve(root);
+            let styles = resolver.resolve_parallel(root);
+            assert_eq!(styles.styles, expected.styles);
+            assert_eq!(styles.contexts, expected.contexts);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_resolve_parallel_deep() {
+        // Deep enough that styling it recursively would overflow the stack
+        let root = parse_html("<div></div>").unwrap();
+        let mut node = root.clone();
+        for _ in 0..5_000 {
+            let child = parse_html("<div></div>").unwrap();
+            node.append_child(child.clone());
+            node = child;
+        }
+        node.append_child(parse_html("<p>Deep</p>").unwrap());
+        let resolver = StyleResolver::new(
+            parse_stylesheet("div { font-size: 20px } p { color: red }").unwrap(),
+        );
+        let expected = resolver.resolve(&root);
+        let styles = resolver.resolve_parallel(&root);
+        assert_eq!(styles.styles.len(), 5_003);
+        assert_eq!(styles.styles, expected.styles);
+        assert_eq!(styles.contexts, expected.contexts);
+    }
+
+    #[test]
+    fn test_get_computed_style() {
+        let root = parse_html("<html><body><div><p>One</p></div></body></html>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "div { font-size: 20px; color: red; width: 50% } p { margin-left: 1em; opacity: 0.5 }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p = div.borrow().children[0].clone();
+        let style = resolver.get_computed_style(&p);
+        assert_eq!(Some(&style), resolver.resolve(&root).get(&p));
+
+        let properties = style.iter().map(|(property, _)| property).collect::<Vec<_>>();
+        assert_eq!(properties.len(), PROPERTIES.len() + 1);
+        assert!(properties.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(style.iter().any(|property| property == ("opacity", "0.5")));
+
+        let typed = style.typed();
+        assert_eq!(typed.color, Rgba::opaque(255, 0, 0));
+        assert_eq!(typed.background_color, Rgba::transparent());
+        assert_eq!(typed.font_size, 20.0);
+        assert_eq!(typed.display, "block");
+        assert_eq!(typed.margin_left.unwrap().px, 20.0);
+        assert_eq!(typed.width, None);
+        assert_eq!(resolver.get_computed_style(&div).typed().width.unwrap().percentage, 50.0);
+    }
+
+    #[test]
+    fn test_resolve_lazy() {
+        let root = parse_html(
+            "<html><body><div class=\"hidden\"><p>One<b>Two</b></p></div>\
+             <div class=\"far\"><p class=\"hidden\">Three</p></div><p>Four</p></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            ".hidden { display: none } div p { color: red } b { font-size: 20px }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let hidden = body.borrow().children[0].clone();
+        let b = hidden.borrow().children[0].borrow().children[1].clone();
+        let far = body.borrow().children[1].clone();
+        let p3 = far.borrow().children[0].clone();
+        let eager = resolver.resolve(&root);
+        let mut styles = resolver.resolve_lazy(&root, |node, style| {
+            style.get("display") == Some("none") || node.borrow().has_class("far")
+        });
+
+        assert_eq!(styles.len(), 6);
+        assert!(styles.is_deferred(&hidden) && styles.is_deferred(&far));
+        assert_eq!(styles.get(&hidden), eager.get(&hidden));
+        assert_eq!(styles.get(&b), None);
+
+        // Changes under deferred nodes don't style them
+        b.add_class("x");
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&b), None);
+
+        assert_eq!(resolver.ensure_style(&b, &mut styles), eager.get(&b));
+        assert!(!styles.is_deferred(&hidden));
+        assert_eq!(styles.len(), 10);
+        // p3 is styled, but its text isn't
+        assert_eq!(resolver.ensure_style(&p3, &mut styles), eager.get(&p3));
+        assert!(styles.is_deferred(&p3));
+        assert_eq!(styles.len(), 11);
+
+        // Undeferred nodes' descendants are styled by restyle()
+        far.remove_class("far");
+        p3.remove_class("hidden");
+        resolver.restyle(&root, &mut styles);
+        assert!(!styles.is_deferred(&p3));
+        assert_eq!(styles.len(), 12);
+        let unstyled = parse_html("<p>Five</p>").unwrap();
+        assert_eq!(resolver.ensure_style(&unstyled, &mut styles), None);
+    }
+
+    #[test]
+    fn test_resolve_streaming() {
+        let html = "<html><body><div class=\"a\"><p>One</p><p id=\"two\">Two<img>\
+                    <b>Three</b></p></div><svg><rect></rect></svg></body></html>";
+        let stylesheet = parse_stylesheet(
+            "div { font-size: 20px } .a > p { margin-left: 1em } p + p { color: blue } \
+             #two b { color: red } p:first-child { opacity: 0.5 }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let mut streamed = vec![];
+        let root = resolver
+            .resolve_streaming(html, |node, style| {
+                // Its parent has been styled already
+                if let Some(parent) = node.parent() {
+                    assert!(streamed.iter().any(|(other, _)| parent.ptr_eq(other)));
+                }
+                streamed.push((node.clone(), style.clone()));
+            })
+            .unwrap();
+
+        // Same tree as parse_html() builds, apart from the nodes' ids
+        fn shape(node: &DomNodeRef) -> String {
+            let node = node.borrow();
+            let children = node.children.iter().map(shape).collect::<Vec<_>>();
+            format!("{:?} {:?} {:?} {:?}", node.elem_type, node.attrs, node.namespace, children)
+        }
+        assert_eq!(shape(&root), shape(&parse_html(html).unwrap()));
+        let styles = resolver.resolve(&parse_html(html).unwrap());
+        assert_eq!(streamed.len(), styles.len());
+        let styles = resolver.resolve(&root);
+        for (node, style) in &streamed {
+            assert_eq!(styles.get(node), Some(style));
+        }
+        let p2 = root.borrow().children[0].borrow().children[0].borrow().children[1].clone();
+        assert_eq!(styles.get(&p2).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+        assert_eq!(styles.get(&p2).unwrap().get("margin-left"), Some("20px"));
+        // Nothing is left to restyle
+        assert!(!root.borrow().style_dirty && !root.borrow().descendants_dirty);
+    }
+
+    #[test]
+    fn test_resolve_animations() {
+        let root = parse_html("<html><body><p>One</p></body></html>").unwrap();
+        let stylesheet = parse_stylesheet(
+            "@keyframes grow { to { width: 100px } } \
+             p { animation-name: grow; animation-duration: 500ms, 2S; width: 0 } \
+             body { animation-delay: 1 }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let styles = resolver.resolve(&root);
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        let style = styles.get(&p).unwrap();
+        assert_eq!(style.get("animation-duration"), Some("0.5s, 2s"));
+        assert_eq!(style.get("animation-delay"), Some("0s"));
+        // Invalid, so ignored
+        assert_eq!(styles.get(&body).unwrap().get("animation-delay"), Some("0s"));
+
+        let context = LengthContext::default();
+        let declarations = resolver.keyframes_registry().sample(style, 0.25, &context);
+        assert_eq!(declarations.len(), 1);
+        assert_eq!(declarations[0].property, "width");
+        // ease is 80.24% of the way at half way
+        assert!(declarations[0].value.starts_with("80.2"));
+    }
+
+    #[test]
+    fn test_resolve_shadow_tree() {
+        let root = parse_html("<html><body><div class=\"x\"></div><p>Text</p></body></html>")
+            .unwrap();
+        let body = root.borrow().children[0].clone();
+        let host = body.borrow().children[0].clone();
+        let p = body.borrow().children[1].clone();
+        let shadow_root = host.attach_shadow().unwrap();
+        let span = parse_html("<span part=\"label\"><p>Shadow</p></span>").unwrap();
+        shadow_root.append_child(span.clone());
+        let shadow_p = span.borrow().children[0].clone();
+
+        let stylesheet = parse_stylesheet(
+            "p { color: red } body span { padding: 1px } div { margin: 1px; width: 10px } \
+             body > .x::part(label) { opacity: 0.75 } p::part(label) { opacity: 0 }",
+        ).unwrap();
+        let mut resolver = StyleResolver::new(stylesheet);
+        resolver.add_shadow_stylesheet(
+            &shadow_root,
+            parse_stylesheet(
+                "p { opacity: 0.5 } :host { color: blue; margin: 2px } \
+                 :host { width: 20px !important } :host(.x) span { padding: 3px } \
+                 div { opacity: 0.1 } span { opacity: 0.3 }",
+            ).unwrap(),
+        );
+        let mut styles = resolver.resolve(&root);
+        // Each tree's rules only match its own nodes, apart from :host and
+        // ::part()
+        assert_eq!(styles.get(&p).unwrap().get("color"), Some("rgb(255, 0, 0)"));
+        assert_eq!(styles.get(&p).unwrap().get("opacity"), None);
+        assert_eq!(styles.get(&shadow_p).unwrap().get("opacity"), Some("0.5"));
+        assert_eq!(styles.get(&host).unwrap().get("opacity"), None);
+        assert_eq!(styles.get(&span).unwrap().get("padding-top"), Some("3px"));
+        // And through ::part(), which wins over the shadow tree's own rules
+        assert_eq!(styles.get(&span).unwrap().get("opacity"), Some("0.75"));
+        // The host's own tree wins, unless the shadow tree's rule is important
+        assert_eq!(styles.get(&host).unwrap().get("margin-top"), Some("1px"));
+        assert_eq!(styles.get(&host).unwrap().get("width"), Some("20px"));
+        // Inherited from the host
+        assert_eq!(styles.get(&shadow_p).unwrap().get("color"), Some("rgb(0, 0, 255)"));
+        assert!(!shadow_root.borrow().descendants_dirty);
+
+        host.remove_class("x");
+        shadow_p.set_attribute("style", Some("opacity: 0.25".to_string()));
+        resolver.restyle(&root, &mut styles);
+        assert_eq!(styles.get(&span).unwrap().get("padding-top"), Some("0px"));
+        assert_eq!(styles.get(&span).unwrap().get("opacity"), Some("0.3"));
+        assert_eq!(styles.get(&shadow_p).unwrap().get("opacity"), Some("0.25"));
+    }
+
+    #[test]
+    fn test_style_map_snapshot() {
+        let root = parse_html("<div><p>Text</p></div>").unwrap();
+        let stylesheet = parse_stylesheet("p { color: red }").unwrap();
+        let styles = StyleResolver::new(stylesheet).resolve(&root);
+        let snapshot = styles.snapshot(&root);
+        let p = &snapshot.children[0];
+        let style = p.style.as_ref().unwrap();
+        assert_eq!(style.get("color").map(String::as_str), Some("rgb(255, 0, 0)"));
+        assert_eq!(style.len(), styles.get(&root.borrow().children[0]).unwrap().len());
+        assert!(p.children[0].style.is_some());
+        assert_eq!(StyleMap::default().snapshot(&root).style, None);
+    }
+
+    #[test]
+    fn test_resolve_custom_elements() {
+        let root = parse_html(
+            "<html><body><my-card></my-card><my-card class=\"a\"></my-card></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "my-card { padding: 1px } my-card:not(:defined) { opacity: 0 } \
+             .a { display: inline }",
+        ).unwrap();
+        let resolver = StyleResolver::new(stylesheet);
+        let body = root.borrow().children[0].clone();
+        let card1 = body.borrow().children[0].clone();
+        let card2 = body.borrow().children[1].clone();
+        let mut styles = resolver.resolve(&root);
+        assert_eq!(styles.get(&card1).unwrap().get("opacity"), Some("0"));
+        assert_eq!(styles.get(&card1).unwrap().get("display"), Some("inline"));
+
+        let mut definition = CustomElementDefinition::new("my-card");
+        definition.default_style = parse_style_attribute("display: block; padding: 2px");
+        define_custom_element(definition);
+        root.upgrade_custom_elements();
+        resolver.restyle(&root, &mut styles);
+        let style = styles.get(&card1).unwrap();
+        assert_eq!(style.get("opacity"), None);
+        assert_eq!(style.get("display"), Some("block"));
+        // Author rules still win
+        assert_eq!(style.get("padding-top"), Some("1px"));
+        assert_eq!(styles.get(&card2).unwrap().get("display"), Some("inline"));
+    }
+}
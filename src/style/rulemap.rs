@@ -0,0 +1,202 @@
+use magicparser::{
+    Atom, DomNodeRef, ElemType, PseudoElementSelector, Selector, SimpleSelector, Stylesheet,
+};
+use std::collections::HashMap;
+use style::bloom::AncestorHashes;
+use style::selectormatcher::{MatcherConfig, QuirksMode};
+
+/// Position of a style rule: the index of its stylesheet, and of the rule in
+/// the stylesheet. Sorts in source order.
+pub type RuleIndex = (usize, usize);
+
+// What a selector's rightmost compound selector requires of the nodes it
+// matches, in order of preference for bucketing
+enum Bucket<'a> {
+    Id(&'a Atom),
+    Class(&'a Atom),
+    Type(&'a ElemType),
+    Universal,
+}
+
+/// The style rules of a list of stylesheets, bucketed by the id, class or
+/// element type the rightmost compound selector of each requires, so that
+/// only rules that can match a node are tried against it.
+#[derive(Debug, Default)]
+pub struct RuleMap {
+    by_id: HashMap<Atom, Vec<RuleIndex>>,
+    by_class: HashMap<Atom, Vec<RuleIndex>>,
+    by_type: HashMap<ElemType, Vec<RuleIndex>>,
+    // Rules that require none of these, e.g. `*` or `[href]`
+    universal: Vec<RuleIndex>,
+    // Position of the first declaration of each rule among all declarations
+    source_orders: Vec<Vec<usize>>,
+    declaration_count: usize,
+    // What each rule requires of the ancestors of the nodes it matches
+    ancestor_hashes: Vec<Vec<AncestorHashes>>,
+    // Ids and classes are keyed in lowercase in quirks mode, where they match
+    // ASCII-case-insensitively
+    quirks: bool,
+}
+
+impl RuleMap {
+    pub fn new<'a, I>(stylesheets: I, config: &MatcherConfig) -> RuleMap
+    where
+        I: IntoIterator<Item = &'a Stylesheet>,
+    {
+        let mut map = RuleMap {
+            quirks: config.quirks_mode == QuirksMode::Quirks,
+            ..RuleMap::default()
+        };
+        for (i, stylesheet) in stylesheets.into_iter().enumerate() {
+            let (mut source_orders, mut ancestor_hashes) = (vec![], vec![]);
+            for (j, rule) in stylesheet.rules.iter().enumerate() {
+                source_orders.push(map.declaration_count);
+                map.declaration_count += rule.declarations.len();
+                ancestor_hashes.push(AncestorHashes::new(&rule.selector, map.quirks));
+                map.insert(&rule.selector, (i, j));
+            }
+            map.source_orders.push(source_orders);
+            map.ancestor_hashes.push(ancestor_hashes);
+        }
+        map
+    }
+
+    fn key(&self, name: &Atom) -> Atom {
+        if self.quirks {
+            name.to_ascii_lowercase()
+        } else {
+            name.clone()
+        }
+    }
+
+    // Like key(), but without interning a lowercased atom, since there are
+    // no rules in its bucket if it doesn't exist
+    fn lookup_key(&self, name: &Atom) -> Option<Atom> {
+        if self.quirks {
+            name.lookup_ascii_lowercase()
+        } else {
+            Some(name.clone())
+        }
+    }
+
+    fn insert(&mut self, selector: &Selector, index: RuleIndex) {
+        if let Selector::Group(ref sels) = *selector {
+            for sel in sels {
+                self.insert(sel, index);
+            }
+            return;
+        }
+        let bucket = match *selector {
+            Selector::Simple(ref simple) => bucket(simple),
+            // The rest of the compound is of the part's host
+            Selector::Seq(_)
+                if matches!(selector.pseudo_element(), Some(PseudoElementSelector::Part(_))) =>
+            {
+                Bucket::Universal
+            }
+            Selector::Seq(ref sels) => sels
+                .iter()
+                .filter_map(|sel| match *sel {
+                    Selector::Simple(ref simple) => Some(bucket(simple)),
+                    _ => None,
+                })
+                .next()
+                .unwrap_or(Bucket::Universal),
+            Selector::Combinator(_, _, ref rightmost) => return self.insert(rightmost, index),
+            _ => Bucket::Universal,
+        };
+        let rules = match bucket {
+            Bucket::Id(id) => self.by_id.entry(self.key(id)).or_default(),
+            Bucket::Class(class) => self.by_class.entry(self.key(class)).or_default(),
+            Bucket::Type(elem_type) => self.by_type.entry(elem_type.clone()).or_default(),
+            Bucket::Universal => &mut self.universal,
+        };
+        // A group's selectors can land in the same bucket
+        if rules.last() != Some(&index) {
+            rules.push(index);
+        }
+    }
+
+    /// Returns the rules whose selectors might match dom_node, or a
+    /// pseudo-element of it, in source order. The others can't.
+    pub fn candidates(&self, dom_node: &DomNodeRef) -> Vec<RuleIndex> {
+        let node = dom_node.borrow();
+        let mut candidates = self.universal.clone();
+        if let Some(id) = node.id.as_ref().and_then(|id| self.lookup_key(id)) {
+            candidates.extend(self.by_id.get(&id).into_iter().flatten());
+        }
+        for class in node.classes.iter().filter_map(|class| self.lookup_key(class)) {
+            candidates.extend(self.by_class.get(&class).into_iter().flatten());
+        }
+        candidates.extend(self.by_type.get(&node.elem_type).into_iter().flatten());
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    /// Returns the position of the first declaration of the rule at index
+    /// among the declarations of every rule, in source order.
+    pub fn source_order(&self, (stylesheet, rule): RuleIndex) -> usize {
+        self.source_orders[stylesheet][rule]
+    }
+
+    /// Returns what the selector of the rule at index requires of the
+    /// ancestors of the nodes it matches, for rejecting it with an
+    /// AncestorFilter.
+    pub fn ancestor_hashes(&self, (stylesheet, rule): RuleIndex) -> &AncestorHashes {
+        &self.ancestor_hashes[stylesheet][rule]
+    }
+
+    /// Returns how many declarations the rules have in total.
+    pub fn declaration_count(&self) -> usize {
+        self.declaration_count
+    }
+}
+
+fn bucket(simple: &SimpleSelector) -> Bucket<'_> {
+    if let Some(ref id) = simple.id {
+        return Bucket::Id(id);
+    }
+    // Any class will do, since the node needs all of them
+    if let Some(class) = simple.classes.iter().min() {
+        return Bucket::Class(class);
+    }
+    match simple.elem_type {
+        Some(ref elem_type) => Bucket::Type(elem_type),
+        None => Bucket::Universal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, parse_stylesheet};
+
+    #[test]
+    fn test_candidates() {
+        let root = parse_html(
+            "<html><body><p id=\"x\" class=\"a b\">One</p><div class=\"A\"></div></body></html>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet(
+            "p { color: red } #x { margin: 0; padding: 0 } div .a.c { } [title] { } \
+             span, .b { } div#y { } *:first-child { } body > div { } p::before { } \
+             div::part(label) { }",
+        ).unwrap();
+        let body = root.borrow().children[0].clone();
+        let p = body.borrow().children[0].clone();
+        let div = body.borrow().children[1].clone();
+        let map = RuleMap::new(vec![&stylesheet], &MatcherConfig::default());
+        let rules = |candidates: Vec<RuleIndex>| {
+            candidates.into_iter().map(|(_, rule)| rule).collect::<Vec<_>>()
+        };
+        assert_eq!(rules(map.candidates(&p)), vec![0, 1, 2, 3, 4, 6, 8, 9]);
+        assert_eq!(rules(map.candidates(&div)), vec![3, 6, 7, 9]);
+        assert_eq!(map.source_order((0, 3)), 3);
+        assert_eq!(map.declaration_count(), 3);
+
+        let mut quirks = MatcherConfig::default();
+        quirks.quirks_mode = QuirksMode::Quirks;
+        let map = RuleMap::new(vec![&stylesheet], &quirks);
+        assert_eq!(rules(map.candidates(&div)), vec![2, 3, 6, 7, 9]);
+    }
+}
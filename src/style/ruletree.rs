@@ -0,0 +1,341 @@
+use magicparser::Declaration;
+use std::collections::HashMap;
+use std::sync::Arc;
+use style::cascade::{sort_declarations, CascadedDeclaration, Origin};
+use style::declaration::expand_declaration;
+use style::properties::{is_reset_by_all, lookup_property, CssWideKeyword, PROPERTIES};
+use style::resolver::ComputedStyle;
+
+/// Identifies a node of a RuleTree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RuleNodeId(usize);
+
+// Declarations with the same origin, property and value cascade alike, no
+// matter which rule they're from
+type RuleNodeKey = (Origin, String, String);
+
+#[derive(Debug)]
+struct RuleNode {
+    parent: Option<RuleNodeId>,
+    // None for the root
+    declaration: Option<(Origin, Declaration)>,
+    // Result of cascading the declarations from the root to this node
+    style: Arc<ComputedStyle>,
+    children: HashMap<RuleNodeKey, RuleNodeId>,
+}
+
+/// Stores cascade results as a tree of declarations. The path from the root
+/// to a node is a chain of declarations in cascade order, and each node holds
+/// the cascaded style of its chain, which is its parent's with one more
+/// declaration applied.
+///
+/// Elements that match the same declarations end up at the same node and
+/// share its style, and a chain that shares a prefix with one seen before
+/// only cascades the declarations after it.
+///
+/// Nodes are never removed, so a tree should live as long as the styles of a
+/// document, not as long as the stylesheets.
+#[derive(Debug)]
+pub struct RuleTree {
+    nodes: Vec<RuleNode>,
+}
+
+impl Default for RuleTree {
+    fn default() -> RuleTree {
+        RuleTree::new()
+    }
+}
+
+impl RuleTree {
+    pub fn new() -> RuleTree {
+        RuleTree {
+            nodes: vec![RuleNode {
+                parent: None,
+                declaration: None,
+                style: Arc::new(ComputedStyle::default()),
+                children: HashMap::new(),
+            }],
+        }
+    }
+
+    /// Returns the node for no declarations.
+    pub fn root(&self) -> RuleNodeId {
+        RuleNodeId(0)
+    }
+
+    /// Returns the node for declarations, sorted in cascade order, adding the
+    /// nodes of the chain that aren't in the tree yet.
+    pub fn insert_declarations(
+        &mut self,
+        mut declarations: Vec<CascadedDeclaration>,
+    ) -> RuleNodeId {
+        sort_declarations(&mut declarations);
+        declarations.iter().fold(self.root(), |node, cascaded| {
+            self.insert_child(node, cascaded.origin, cascaded.declaration)
+        })
+    }
+
+    /// Returns the child of parent for declaration, adding it if needed.
+    pub fn insert_child(
+        &mut self,
+        parent: RuleNodeId,
+        origin: Origin,
+        declaration: &Declaration,
+    ) -> RuleNodeId {
+        let key = (origin, declaration.property.clone(), declaration.value.clone());
+        if let Some(&child) = self.nodes[parent.0].children.get(&key) {
+            return child;
+        }
+        let mut style = (*self.nodes[parent.0].style).clone();
+        let is_revert = CssWideKeyword::parse(&declaration.value) == Some(CssWideKeyword::Revert);
+        // With the values they're set to
+        let properties = if declaration.property == "all" {
+            // Unsupported properties are unset, as with cascade()
+            let unsupported = style
+                .iter()
+                .map(|(property, _)| property.to_string())
+                .filter(|property| {
+                    is_reset_by_all(property) && lookup_property(property).is_none()
+                })
+                .collect::<Vec<_>>();
+            for property in &unsupported {
+                style.remove(property);
+            }
+            let supported = PROPERTIES.iter().map(|property| property.name.to_string());
+            let mut properties =
+                supported.filter(|property| is_reset_by_all(property)).collect::<Vec<_>>();
+            if is_revert {
+                properties.extend(unsupported);
+            }
+            let value = &declaration.value;
+            properties.into_iter().map(|property| (property, value.clone())).collect()
+        } else {
+            expand_declaration(&declaration.property, &declaration.value)
+                .into_iter()
+                .map(|(property, value)| (property.to_string(), value))
+                .collect::<Vec<_>>()
+        };
+        for (property, value) in &properties {
+            if !is_revert {
+                style.set(property, value);
+                continue;
+            }
+            match self.reverted_value(parent, property, origin) {
+                Some(value) => style.set(property, &value),
+                None => {
+                    style.remove(property);
+                }
+            }
+        }
+        let child = RuleNodeId(self.nodes.len());
+        self.nodes.push(RuleNode {
+            parent: Some(parent),
+            declaration: Some((origin, declaration.clone())),
+            style: Arc::new(style),
+            children: HashMap::new(),
+        });
+        self.nodes[parent.0].children.insert(key, child);
+        child
+    }
+
+    /// Adds the nodes of other that the tree doesn't have yet, e.g. from a
+    /// tree built on another thread, and returns the ids in this tree of
+    /// other's nodes.
+    pub fn merge(&mut self, other: &RuleTree, nodes: &[RuleNodeId]) -> Vec<RuleNodeId> {
+        let mut ids: Vec<RuleNodeId> = Vec::with_capacity(other.nodes.len());
+        // Parents come before their children
+        for node in &other.nodes {
+            let id = match (node.parent, &node.declaration) {
+                (Some(parent), Some((origin, declaration))) => {
+                    self.insert_child(ids[parent.0], *origin, declaration)
+                }
+                _ => self.root(),
+            };
+            ids.push(id);
+        }
+        nodes.iter().map(|node| ids[node.0]).collect()
+    }
+
+    /// Returns the cascaded style of the declarations from the root to node.
+    /// CSS-wide keywords other than `revert` are kept, as with cascade().
+    pub fn style(&self, node: RuleNodeId) -> Arc<ComputedStyle> {
+        self.nodes[node.0].style.clone()
+    }
+
+    /// Returns the declarations from the root to node, in cascade order.
+    pub fn declarations(&self, node: RuleNodeId) -> Vec<(Origin, &Declaration)> {
+        let mut declarations = vec![];
+        let mut node = Some(node);
+        while let Some(RuleNodeId(index)) = node {
+            if let Some((origin, ref declaration)) = self.nodes[index].declaration {
+                declarations.push((origin, declaration));
+            }
+            node = self.nodes[index].parent;
+        }
+        declarations.reverse();
+        declarations
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        // There's always a root
+        false
+    }
+
+    // Value of property from the declarations from the root to node, ignoring
+    // those from origin or later, which a `revert` from origin rolls back
+    fn reverted_value(&self, node: RuleNodeId, property: &str, origin: Origin) -> Option<String> {
+        let mut bound = origin;
+        let mut node = Some(node);
+        while let Some(RuleNodeId(index)) = node {
+            if let Some((declaration_origin, ref declaration)) = self.nodes[index].declaration {
+                let all = declaration.property == "all" && is_reset_by_all(property);
+                let value = if all {
+                    Some(declaration.value.clone())
+                } else {
+                    expand_declaration(&declaration.property, &declaration.value)
+                        .into_iter()
+                        .find(|&(longhand, _)| longhand == property)
+                        .map(|(_, value)| value)
+                };
+                let value = value.filter(|_| declaration_origin < bound);
+                if let Some(value) = value {
+                    if CssWideKeyword::parse(&value) == Some(CssWideKeyword::Revert) {
+                        bound = declaration_origin;
+                    } else if all && lookup_property(property).is_none() {
+                        return None;
+                    } else {
+                        return Some(value);
+                    }
+                }
+            }
+            node = self.nodes[index].parent;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::Specificity;
+    use style::cascade::cascade;
+
+    #[test]
+    fn test_rule_tree() {
+        let decl = |property: &str, value: &str| {
+            Declaration::new(property.to_string(), value.to_string())
+        };
+        let (red, blue, wide) = (decl("color", "red"), decl("color", "blue"), decl("width", "1px"));
+        let mut tree = RuleTree::new();
+        let one = tree.insert_declarations(vec![
+            CascadedDeclaration::new(&blue, Origin::Author, Specificity(0, 1, 0), 1),
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 0, 1), 0),
+        ]);
+        assert_eq!(tree.style(one).get("color"), Some("blue"));
+        assert_eq!(tree.len(), 3);
+
+        // Shares the prefix
+        let two = tree.insert_declarations(vec![
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&wide, Origin::Author, Specificity(0, 0, 1), 2),
+        ]);
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.style(two).get("color"), Some("red"));
+        assert_eq!(tree.style(two).get("width"), Some("1px"));
+        let declarations = tree.declarations(two);
+        assert_eq!(declarations.len(), 2);
+        assert_eq!(declarations[1], (Origin::Author, &wide));
+
+        // Same declarations from another rule end at the same node
+        let red_again = decl("color", "red");
+        let three = tree.insert_declarations(vec![
+            CascadedDeclaration::new(&red_again, Origin::Author, Specificity(1, 0, 0), 7),
+            CascadedDeclaration::new(&blue, Origin::Author, Specificity(1, 0, 0), 8),
+        ]);
+        assert_eq!(three, one);
+        assert!(Arc::ptr_eq(&tree.style(one), &tree.style(three)));
+
+        // Merged into a tree that has some of the nodes already
+        let mut other = RuleTree::new();
+        let four = other.insert_declarations(vec![
+            CascadedDeclaration::new(&red, Origin::Author, Specificity(0, 0, 1), 0),
+            CascadedDeclaration::new(&blue, Origin::User, Specificity(0, 0, 1), 1),
+        ]);
+        let merged = tree.merge(&other, &[other.root(), four]);
+        assert_eq!(merged[0], tree.root());
+        assert_eq!(tree.len(), 6);
+        assert_eq!(tree.declarations(merged[1]), other.declarations(four));
+    }
+
+    #[test]
+    fn test_rule_tree_revert() {
+        let decl = |value: &str, important: bool| {
+            Declaration::new_with_importance("color".to_string(), value.to_string(), important)
+        };
+        let (red, green, blue) = (decl("red", false), decl("green", false), decl("blue", false));
+        let (revert, important_revert) = (decl("revert", false), decl("revert", true));
+        let important_revert =
+            CascadedDeclaration::new(&important_revert, Origin::Author, Specificity(0, 0, 1), 2);
+        for declarations in [
+            vec![
+                CascadedDeclaration::new(&red, Origin::UserAgent, Specificity(0, 0, 1), 0),
+                CascadedDeclaration::new(&green, Origin::User, Specificity(0, 0, 1), 1),
+                CascadedDeclaration::new(&blue, Origin::Author, Specificity(0, 0, 1), 2),
+                CascadedDeclaration::new(&revert, Origin::Author, Specificity(0, 1, 0), 3),
+            ],
+            vec![
+                CascadedDeclaration::new(&red, Origin::UserAgent, Specificity(0, 0, 1), 0),
+                CascadedDeclaration::new(&revert, Origin::User, Specificity(0, 0, 1), 1),
+                important_revert,
+            ],
+            vec![CascadedDeclaration::new(&revert, Origin::UserAgent, Specificity(0, 0, 1), 0)],
+        ] {
+            let mut tree = RuleTree::new();
+            let node = tree.insert_declarations(declarations.clone());
+            assert_eq!(*tree.style(node), cascade(declarations));
+        }
+    }
+
+    #[test]
+    fn test_rule_tree_all() {
+        let decl = |property: &str, value: &str| {
+            Declaration::new(property.to_string(), value.to_string())
+        };
+        let (red, opacity, direction) =
+            (decl("color", "red"), decl("opacity", "0.5"), decl("direction", "rtl"));
+        let (initial, revert, width) =
+            (decl("all", "initial"), decl("all", "revert"), decl("width", "1px"));
+        let declaration = |declaration, origin, source_order| {
+            CascadedDeclaration::new(declaration, origin, Specificity(0, 0, 1), source_order)
+        };
+        for declarations in [
+            vec![
+                declaration(&red, Origin::UserAgent, 0),
+                declaration(&opacity, Origin::Author, 1),
+                declaration(&direction, Origin::Author, 2),
+                declaration(&initial, Origin::Author, 3),
+                declaration(&width, Origin::Author, 4),
+            ],
+            vec![
+                declaration(&red, Origin::UserAgent, 0),
+                declaration(&opacity, Origin::UserAgent, 1),
+                declaration(&initial, Origin::User, 2),
+                declaration(&width, Origin::Author, 3),
+                declaration(&revert, Origin::Author, 4),
+            ],
+            vec![
+                declaration(&initial, Origin::UserAgent, 0),
+                declaration(&width, Origin::Author, 1),
+                declaration(&revert, Origin::Author, 2),
+            ],
+        ] {
+            let mut tree = RuleTree::new();
+            let node = tree.insert_declarations(declarations.clone());
+            assert_eq!(*tree.style(node), cascade(declarations));
+        }
+    }
+}
@@ -1,6 +1,147 @@
-use magicparser::{AttrSelector, AttrSelectorOp, Combinator, DomNodeRef, PseudoClassSelector,
-                  Selector, SimpleSelector};
+use magicparser::error::Result;
+use magicparser::{parse_selector, Atom, AttrCaseFlag, AttrSelector, AttrSelectorOp, Combinator,
+                  CssBlocks, Document, DomNode, DomNodeRef, ElemType, ElementState, FilterResult,
+                  NamespaceConstraint,
+                  PseudoClassSelector, PseudoElementSelector, Selector, SimpleSelector,
+                  Specificity, ToCss};
+#[cfg(feature = "parallel")]
+use magicparser::SyncTree;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::collections::HashMap;
+#[cfg(feature = "parallel")]
 use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+pub use magicparser::{DocumentKind, QuirksMode};
+
+// Attributes whose values quirks-mode documents compare ASCII-case-insensitively
+// when the selector has no i/s flag
+static QUIRKS_CASE_INSENSITIVE_ATTRS: &[&str] = &[
+    "accept", "accept-charset", "align", "alink", "axis", "bgcolor", "charset", "checked",
+    "clear", "codetype", "color", "compact", "declare", "defer", "dir", "direction",
+    "disabled", "enctype", "face", "frame", "hreflang", "http-equiv", "lang", "language",
+    "link", "media", "method", "multiple", "nohref", "noresize", "noshade", "nowrap",
+    "readonly", "rel", "rev", "rules", "scope", "scrolling", "selected", "shape", "target",
+    "text", "type", "valign", "valuetype", "vlink",
+];
+
+/// Decides whether a DOM node is in an application-defined state, e.g. whether
+/// it is hovered.
+pub type PseudoClassFn = fn(&DomNodeRef) -> bool;
+
+/// Context that selector matching depends on but that isn't part of the
+/// selector or the DOM node itself.
+#[derive(Debug, Clone, Default)]
+pub struct MatcherConfig {
+    /// Attribute names compare ASCII-case-insensitively in HTML documents and
+    /// case-sensitively in XML documents.
+    pub document_kind: DocumentKind,
+    /// Host element of the shadow tree the stylesheet being matched belongs
+    /// to, if any. Only then can :host match anything. The host is otherwise
+    /// featureless, so only :host matches it, and it's the parent of the
+    /// shadow root's children for combinators, e.g. for `:host(.dark) a`.
+    pub shadow_host: Option<DomNodeRef>,
+    /// In quirks mode, ids and classes match ASCII-case-insensitively.
+    pub quirks_mode: QuirksMode,
+    // Keyed by lowercased pseudo-class name. Shared, since configs are
+    // cloned for each shadow tree.
+    custom_pseudo_classes: Rc<HashMap<String, PseudoClassFn>>,
+}
+
+impl MatcherConfig {
+    pub fn new(document_kind: DocumentKind) -> MatcherConfig {
+        MatcherConfig {
+            document_kind,
+            shadow_host: None,
+            quirks_mode: QuirksMode::NoQuirks,
+            custom_pseudo_classes: Rc::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the config for matching in document, in its kind and quirks
+    /// mode.
+    pub fn for_document(document: &Document) -> MatcherConfig {
+        MatcherConfig {
+            quirks_mode: document.quirks_mode(),
+            ..MatcherConfig::new(document.document_kind())
+        }
+    }
+
+    /// Registers the matcher for the pseudo-class `:name`. Besides custom
+    /// pseudo-classes, i.e. ones that aren't standard, this is how :link is
+    /// matched, and how state pseudo-classes like :hover can match elements
+    /// whose DomNode::state doesn't have their state. A pseudo-class with no
+    /// registered matcher matches nothing.
+    pub fn register_pseudo(&mut self, name: &str, matcher: PseudoClassFn) {
+        Rc::make_mut(&mut self.custom_pseudo_classes)
+            .insert(name.to_ascii_lowercase(), matcher);
+    }
+
+    fn is_quirks(&self) -> bool {
+        self.quirks_mode == QuirksMode::Quirks
+    }
+
+    fn is_shadow_host(&self, dom_node: &DomNodeRef) -> bool {
+        self.shadow_host.as_ref().is_some_and(|host| host.ptr_eq(dom_node))
+    }
+
+    fn matches_pseudo(&self, name: &str, dom_node: &DomNodeRef) -> bool {
+        self.custom_pseudo_classes
+            .get(name)
+            .is_some_and(|matcher| matcher(dom_node))
+    }
+
+    // Matches a state pseudo-class like :hover, falling back to its registered
+    // matcher, if any
+    fn matches_state(&self, state: ElementState, dom_node: &DomNodeRef) -> bool {
+        dom_node.borrow().state.contains(state)
+            || state.pseudo_classes().any(|name| self.matches_pseudo(name, dom_node))
+    }
+}
+
+/// A MatcherConfig that can be sent to other threads, for matching against
+/// the copies of a SyncTree's nodes there. Its shadow host is given by its
+/// index in the tree.
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone)]
+pub struct SyncMatcherConfig {
+    document_kind: DocumentKind,
+    shadow_host: Option<usize>,
+    quirks_mode: QuirksMode,
+    custom_pseudo_classes: HashMap<String, PseudoClassFn>,
+}
+
+#[cfg(feature = "parallel")]
+impl SyncMatcherConfig {
+    /// Copies config for matching against tree's nodes. A shadow host that
+    /// isn't in the tree is dropped.
+    pub fn new(config: &MatcherConfig, tree: &SyncTree) -> SyncMatcherConfig {
+        let shadow_host = config.shadow_host.as_ref().and_then(|host| {
+            let id_num = host.borrow().id_num;
+            tree.nodes().iter().position(|node| node.id_num == id_num)
+        });
+        SyncMatcherConfig {
+            document_kind: config.document_kind,
+            shadow_host,
+            quirks_mode: config.quirks_mode,
+            custom_pseudo_classes: (*config.custom_pseudo_classes).clone(),
+        }
+    }
+
+    /// Returns the config for matching against dom_nodes, the copies of the
+    /// tree's nodes made by SyncTree::to_dom_nodes() on this thread.
+    /// Registered pseudo-classes are called with the copies.
+    pub fn to_config(&self, dom_nodes: &[DomNodeRef]) -> MatcherConfig {
+        MatcherConfig {
+            document_kind: self.document_kind,
+            shadow_host: self.shadow_host.map(|index| dom_nodes[index].clone()),
+            quirks_mode: self.quirks_mode,
+            custom_pseudo_classes: Rc::new(self.custom_pseudo_classes.clone()),
+        }
+    }
+}
 
 fn matches_simple_selector(
     node: &DomNodeRef,
@@ -8,196 +149,236 @@ fn matches_simple_selector(
         elem_type,
         id,
         classes,
+        namespace,
         ..
     }: &SimpleSelector,
+    config: &MatcherConfig,
 ) -> bool {
     let node = node.borrow();
+    if !namespace.matches(node.namespace.as_deref()) {
+        return false;
+    }
     if let Some(ref elem_type) = elem_type {
         if *elem_type != node.elem_type {
             return false;
         }
     }
-    if let (Some(ref id), Some(ref dom_node_id)) = (id, &node.id) {
-        if *id != *dom_node_id {
+    if let Some(ref id) = id {
+        if !node.id.as_ref().is_some_and(|node_id| id_matches(id, node_id, config)) {
             return false;
         }
     }
-    if !classes.is_empty() && !classes.is_subset(&node.classes) {
-        return false;
+    classes.iter().all(|class| has_class(&node, class, config))
+}
+
+fn id_matches(id: &Atom, dom_node_id: &Atom, config: &MatcherConfig) -> bool {
+    if config.is_quirks() {
+        id.eq_ignore_ascii_case(dom_node_id)
+    } else {
+        id == dom_node_id
+    }
+}
+
+fn has_class(node: &DomNode, class: &Atom, config: &MatcherConfig) -> bool {
+    if config.is_quirks() {
+        node.classes
+            .iter()
+            .any(|node_class| node_class.eq_ignore_ascii_case(class))
+    } else {
+        node.classes.contains(class)
+    }
+}
+
+// Finds the DOM node's attribute that the attribute selector tests
+fn selected_attr<'a>(
+    node: &'a DomNode,
+    AttrSelector { attr, namespace, .. }: &AttrSelector,
+    config: &MatcherConfig,
+) -> Option<&'a Option<String>> {
+    match config.document_kind {
+        DocumentKind::Html => node.attr_ns_ignore_ascii_case(attr, namespace),
+        DocumentKind::Xml => node.attr_ns(attr, namespace),
     }
-    true
 }
 
 fn matches_attr_selector(
     node: &DomNodeRef,
-    AttrSelector {
-        attr,
-        op_val,
-        case_insensitive,
-    }: &AttrSelector,
+    selector: &AttrSelector,
+    config: &MatcherConfig,
 ) -> bool {
+    let AttrSelector {
+        attr: attr_name,
+        op_val,
+        case_flag,
+        lowercase_val,
+        ..
+    } = selector;
     let node = node.borrow();
-    let attrs = &node.attrs;
-    match op_val {
-        Some((op, val)) => {
+    let attr = selected_attr(&node, selector, config);
+    let case_insensitive = match case_flag {
+        AttrCaseFlag::Insensitive => true,
+        AttrCaseFlag::Sensitive => false,
+        AttrCaseFlag::Unspecified => {
+            config.is_quirks()
+                && QUIRKS_CASE_INSENSITIVE_ATTRS
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(attr_name))
+        }
+    };
+    match (op_val, lowercase_val) {
+        (Some((op, val)), Some(lowercase_val)) => {
             // Value of attr in DOM node
-            let attr_value = if let Some(&Some(ref v)) = attrs.get(attr) {
+            let attr_value = if let Some(Some(v)) = attr {
                 v
             } else {
                 return false;
             };
-            match op {
-                AttrSelectorOp::Exactly => {
-                    if *case_insensitive {
-                        attr_value.to_lowercase() == val.to_lowercase()
-                    } else {
-                        attr_value == val
-                    }
-                }
-                AttrSelectorOp::ExactlyOne => {
-                    if *case_insensitive {
-                        let words = attr_value
-                            .split_whitespace()
-                            .map(|s| s.to_lowercase())
-                            .collect::<HashSet<_>>();
-                        words.contains(&val.to_lowercase())
-                    } else {
-                        let words = attr_value
-                            .split_whitespace()
-                            .map(|s| s.to_string())
-                            .collect::<HashSet<_>>();
-                        words.contains(val)
-                    }
-                }
-                AttrSelectorOp::ExactlyOrHyphen => {
-                    if *case_insensitive {
-                        attr_value
-                            .split_whitespace()
-                            .find(|&s| {
-                                s.to_lowercase() == val.to_lowercase()
-                                    || s.to_lowercase()
-                                        .starts_with(&format!("{}-", val.to_lowercase()))
-                            })
-                            .is_some()
-                    } else {
-                        attr_value
-                            .split_whitespace()
-                            .find(|&s| s == val || s.starts_with(&format!("{}-", val)))
-                            .is_some()
-                    }
-                }
-                AttrSelectorOp::Prefixed => {
-                    if *case_insensitive {
-                        attr_value.to_lowercase().starts_with(&val.to_lowercase())
-                    } else {
-                        attr_value.starts_with(val)
-                    }
-                }
-                AttrSelectorOp::Suffixed => {
-                    if *case_insensitive {
-                        attr_value.to_lowercase().ends_with(&val.to_lowercase())
-                    } else {
-                        attr_value.ends_with(val)
-                    }
-                }
-                AttrSelectorOp::ContainsAtLeastOne => {
-                    if *case_insensitive {
-                        attr_value.to_lowercase().contains(&val.to_lowercase())
-                    } else {
-                        attr_value.contains(val)
-                    }
-                }
+            if case_insensitive {
+                matches_attr_value(*op, attr_value.as_bytes(), lowercase_val.as_bytes(), |a, b| {
+                    a.to_ascii_lowercase() == b
+                })
+            } else {
+                matches_attr_value(*op, attr_value.as_bytes(), val.as_bytes(), |a, b| a == b)
             }
         }
-        None => match attrs.get(attr) {
-            Some(_) => true,
-            None => false,
-        },
+        _ => attr.is_some(),
     }
 }
 
-fn matches_pseudo_class_selector(dom_node: &DomNodeRef, selector: &PseudoClassSelector) -> bool {
-    match selector {
-        PseudoClassSelector::Matches(ref sel) => matches(dom_node, sel),
-        PseudoClassSelector::Not(ref sel) => !matches(dom_node, sel),
-        PseudoClassSelector::FirstChild => dom_node.child_index().unwrap_or(1) == 1,
-        PseudoClassSelector::LastChild => {
-            let parent = dom_node.parent();
-            dom_node.child_index().unwrap_or(1) == if let Some(ref parent) = parent {
-                let siblings = &parent.borrow().children;
-                siblings.len()
-            } else {
-                1
-            }
-        }
-        PseudoClassSelector::NthChild(ref expr) => {
-            let child_index = dom_node.child_index().unwrap_or(1);
-            expr.matches(child_index)
+// Tests an attribute's value against an attribute selector's, comparing
+// bytes with eq. For case-insensitive matching, val is ASCII-lowercased and
+// eq lowercases the attribute value's bytes, so nothing is allocated.
+fn matches_attr_value<F>(op: AttrSelectorOp, attr_value: &[u8], val: &[u8], eq: F) -> bool
+where
+    F: Fn(u8, u8) -> bool,
+{
+    let equals = |bytes: &[u8]| bytes.len() == val.len() && starts_with(bytes, val, &eq);
+    let mut words = attr_value
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|word| !word.is_empty());
+    match op {
+        AttrSelectorOp::Exactly => equals(attr_value),
+        AttrSelectorOp::ExactlyOne => words.any(equals),
+        AttrSelectorOp::ExactlyOrHyphen => words.any(|word| {
+            equals(word) || (starts_with(word, val, &eq) && word.get(val.len()) == Some(&b'-'))
+        }),
+        AttrSelectorOp::Prefixed => starts_with(attr_value, val, &eq),
+        AttrSelectorOp::Suffixed => {
+            attr_value.len() >= val.len()
+                && starts_with(&attr_value[attr_value.len() - val.len()..], val, &eq)
         }
-        PseudoClassSelector::FirstOfType => {
-            let parent = dom_node.parent().unwrap();
-            let parent = parent.borrow();
-            parent
-                .children
-                .iter()
-                .filter(|node| node.borrow().elem_type == dom_node.borrow().elem_type)
-                .nth(0)
-                .unwrap() == dom_node
+        AttrSelectorOp::ContainsAtLeastOne => {
+            val.is_empty() || attr_value.windows(val.len()).any(equals)
         }
-        PseudoClassSelector::LastOfType => {
-            let parent = dom_node.parent().unwrap();
-            let parent = parent.borrow();
-            parent
-                .children
-                .iter()
-                .filter(|node| node.borrow().elem_type == dom_node.borrow().elem_type)
-                .last()
-                .unwrap() == dom_node
+    }
+}
+
+fn starts_with<F>(bytes: &[u8], prefix: &[u8], eq: &F) -> bool
+where
+    F: Fn(u8, u8) -> bool,
+{
+    bytes.len() >= prefix.len() && bytes.iter().zip(prefix).all(|(&a, &b)| eq(a, b))
+}
+
+fn matches_pseudo_class_selector(
+    dom_node: &DomNodeRef,
+    selector: &PseudoClassSelector,
+    config: &MatcherConfig,
+) -> bool {
+    match selector {
+        PseudoClassSelector::Matches(ref sel) => matches_with_config(dom_node, sel, config),
+        PseudoClassSelector::Not(ref sel) => !matches_with_config(dom_node, sel, config),
+        PseudoClassSelector::FirstChild => dom_node.element_index().unwrap_or(1) == 1,
+        PseudoClassSelector::LastChild => dom_node.rev_element_index().unwrap_or(1) == 1,
+        PseudoClassSelector::NthChild(ref expr) => {
+            expr.matches(dom_node.element_index().unwrap_or(1))
         }
+        PseudoClassSelector::FirstOfType => dom_node.type_index().unwrap_or(1) == 1,
+        PseudoClassSelector::LastOfType => dom_node.rev_type_index().unwrap_or(1) == 1,
         PseudoClassSelector::NthOfType(ref expr) => {
-            let parent = dom_node.parent().unwrap();
-            let parent = parent.borrow();
-            let child_index = parent
-                .children
-                .iter()
-                .filter(|node| node.borrow().elem_type == dom_node.borrow().elem_type)
-                .position(|node| node == dom_node)
-                .unwrap() + 1;
-            expr.matches(child_index)
+            expr.matches(dom_node.type_index().unwrap_or(1))
         }
         PseudoClassSelector::NthLastChild(ref expr) => {
-            let rev_child_index = dom_node.rev_child_index().unwrap_or(1);
-            expr.matches(rev_child_index)
+            expr.matches(dom_node.rev_element_index().unwrap_or(1))
         }
+        PseudoClassSelector::Empty => dom_node.borrow().is_empty(),
         PseudoClassSelector::NthLastOfType(ref expr) => {
-            let parent = dom_node.parent().unwrap();
-            let parent = parent.borrow();
-            let rev_child_index = parent
-                .children
-                .iter()
-                .rev()
-                .filter(|node| node.borrow().elem_type == dom_node.borrow().elem_type)
-                .position(|node| node == dom_node)
-                .unwrap() + 1;
-            expr.matches(rev_child_index)
+            expr.matches(dom_node.rev_type_index().unwrap_or(1))
+        }
+        PseudoClassSelector::Host(ref sel) => {
+            // The argument matches the host as it is in its own tree, not
+            // featureless
+            let outer_config = MatcherConfig {
+                shadow_host: None,
+                ..config.clone()
+            };
+            config.is_shadow_host(dom_node)
+                && sel.as_ref()
+                    .is_none_or(|sel| matches_with_config(dom_node, sel, &outer_config))
+        }
+        PseudoClassSelector::Active => config.matches_state(ElementState::ACTIVE, dom_node),
+        PseudoClassSelector::Checked => {
+            dom_node.checked() || dom_node.selected()
+                || config.matches_state(ElementState::CHECKED, dom_node)
+        }
+        PseudoClassSelector::Default => dom_node.is_default(),
+        PseudoClassSelector::Defined => dom_node.is_defined(),
+        PseudoClassSelector::Disabled => {
+            dom_node.disabled() || config.matches_state(ElementState::DISABLED, dom_node)
         }
-        // TODO: Implement other pseudo-class selectors (see README)
-        _ => unimplemented!(),
+        PseudoClassSelector::Enabled => {
+            dom_node.enabled() && !config.matches_state(ElementState::DISABLED, dom_node)
+        }
+        PseudoClassSelector::Focus => config.matches_state(ElementState::FOCUS, dom_node),
+        PseudoClassSelector::Hover => config.matches_state(ElementState::HOVER, dom_node),
+        PseudoClassSelector::Indeterminate => dom_node.indeterminate(),
+        PseudoClassSelector::Lang(ref lang) => matches_lang(dom_node, lang),
+        PseudoClassSelector::Link => config.matches_pseudo("link", dom_node),
+        PseudoClassSelector::Target => config.matches_state(ElementState::TARGET, dom_node),
+        PseudoClassSelector::Visited => config.matches_state(ElementState::VISITED, dom_node),
+        PseudoClassSelector::Custom(ref name) => config.matches_pseudo(name, dom_node),
     }
 }
 
+// Whether the language of dom_node is lang, or a sublanguage of it, like
+// en-US of en, ignoring ASCII case. The language is given by the xml:lang or
+// lang attribute of the node, or else of its nearest ancestor that has one.
+fn matches_lang(dom_node: &DomNodeRef, lang: &str) -> bool {
+    let mut ancestor = Some(dom_node.clone());
+    while let Some(node) = ancestor {
+        let value = {
+            let node = node.borrow();
+            node.attr("xml:lang").or_else(|| node.attr("lang")).cloned()
+        };
+        if let Some(value) = value {
+            let (value, lang) = (value.unwrap_or_default(), lang.as_bytes());
+            let value = value.as_bytes();
+            return starts_with(value, lang, &|a: u8, b: u8| a.eq_ignore_ascii_case(&b))
+                && (value.len() == lang.len() || value[lang.len()] == b'-');
+        }
+        ancestor = node.parent_or_host();
+    }
+    false
+}
+
 /// Given that dom_node matches the first selector of the combinator, returns all
 /// children of dom_node that match the second selector.
-fn matching_child_combinator_nodes(dom_node: &DomNodeRef, selector: &Selector) -> Vec<DomNodeRef> {
+pub fn matching_child_combinator_nodes(
+    dom_node: &DomNodeRef,
+    selector: &Selector,
+    config: &MatcherConfig,
+) -> Vec<DomNodeRef> {
     match selector {
         &Selector::Combinator(ref first, Combinator::Child, ref second) => {
-            if !matches(dom_node, first) {
+            if !matches_with_config(dom_node, first, config) {
                 return vec![];
             }
-            (&dom_node.borrow().children)
+            dom_node
+                .borrow()
+                .children
                 .iter()
-                .filter(|child| matches(child, second))
-                .map(|x| x.clone())
+                .filter(|child| matches_with_config(child, second, config))
+                .cloned()
                 .collect()
         }
         _ => unreachable!(),
@@ -206,145 +387,781 @@ fn matching_child_combinator_nodes(dom_node: &DomNodeRef, selector: &Selector) -
 
 /// Given that dom_node matches the first selector of the combinator, returns all
 /// siblings of dom_node that match the second selector and come after dom_node.
-fn matching_gen_sib_combinator_nodes(
+pub fn matching_gen_sib_combinator_nodes(
     dom_node: &DomNodeRef,
     selector: &Selector,
+    config: &MatcherConfig,
 ) -> Vec<DomNodeRef> {
     match selector {
         &Selector::Combinator(ref first, Combinator::GeneralSibling, ref second) => {
-            if !matches(dom_node, first) {
+            if !matches_with_config(dom_node, first, config) {
                 return vec![];
             }
             let siblings = dom_node.siblings();
             let child_index = dom_node.child_index().unwrap_or(1) - 1;
-            (&siblings[child_index..])
+            siblings[child_index..]
                 .iter()
-                .filter(|x| matches(x, second))
-                .map(|x| x.clone())
+                .filter(|x| matches_with_config(x, second, config))
+                .cloned()
                 .collect()
         }
         _ => unreachable!(),
     }
 }
 
-fn matches(dom_node: &DomNodeRef, selector: &Selector) -> bool {
+/// Matches dom_node against selector in an HTML document. See
+/// `matches_with_config()`.
+pub fn matches(dom_node: &DomNodeRef, selector: &Selector) -> bool {
+    matches_with_config(dom_node, selector, &MatcherConfig::default())
+}
+
+/// Pseudo-element selectors match their originating element, so `p::selection`
+/// matches every `p`. Use `matches_pseudo_element()` to tell rules that style
+/// the element apart from rules that style one of its pseudo-elements.
+pub fn matches_with_config(
+    dom_node: &DomNodeRef,
+    selector: &Selector,
+    config: &MatcherConfig,
+) -> bool {
+    if config.is_shadow_host(dom_node) && is_feature(selector) {
+        return false;
+    }
+    match selector {
+        Selector::Simple(ref simple_sel) => matches_simple_selector(dom_node, simple_sel, config),
+        Selector::Attr(ref attr_sel) => matches_attr_selector(dom_node, attr_sel, config),
+        Selector::PseudoClass(ref pc_sel) => {
+            matches_pseudo_class_selector(dom_node, pc_sel, config)
+        }
+        // Slotting isn't implemented, so no element is slotted
+        Selector::PseudoElement(PseudoElementSelector::Slotted(_)) => false,
+        Selector::PseudoElement(PseudoElementSelector::Part(ref names)) => {
+            part_host(dom_node, config).is_some() && has_parts(dom_node, names)
+        }
+        Selector::PseudoElement(_) => true,
+        Selector::Seq(ref sels) => match sels.iter().position(is_part) {
+            // What comes before a ::part() is of its host
+            Some(part) => {
+                let (host_sels, sels) = sels.split_at(part);
+                part_host(dom_node, config).is_some_and(|host| {
+                    host_sels.iter().all(|sel| matches_with_config(&host, sel, config))
+                }) && sels.iter().all(|sel| matches_with_config(dom_node, sel, config))
+            }
+            None => sels.iter().all(|sel| matches_with_config(dom_node, sel, config)),
+        },
+        Selector::Group(ref sels) => sels.iter()
+            .any(|sel| matches_with_config(dom_node, sel, config)),
+        Selector::Combinator(..) => {
+            let mut compounds = vec![];
+            let mut combinators = vec![];
+            flatten_complex_selector(selector, &mut compounds, &mut combinators);
+            matches_complex_selector(dom_node, &compounds, &combinators, config)
+                == ComplexMatch::Matched
+        }
+    }
+}
+
+fn is_part(selector: &Selector) -> bool {
+    matches!(selector, Selector::PseudoElement(PseudoElementSelector::Part(_)))
+}
+
+// The host dom_node is a part of for config's tree: the host of the shadow
+// tree dom_node is in, if the host is in config's tree, or is its shadow
+// host. ::part() selectors match the parts of those hosts only.
+fn part_host(dom_node: &DomNodeRef, config: &MatcherConfig) -> Option<DomNodeRef> {
+    let host = dom_node.containing_shadow_root()?.host()?;
+    let outer_host = host.containing_shadow_root().and_then(|root| root.host());
+    let in_tree = match (&outer_host, &config.shadow_host) {
+        (Some(outer_host), Some(shadow_host)) => outer_host.ptr_eq(shadow_host),
+        (outer_host, shadow_host) => outer_host.is_none() && shadow_host.is_none(),
+    };
+    Some(host).filter(|host| in_tree || config.is_shadow_host(host))
+}
+
+// Whether the part attribute of dom_node has each of names
+fn has_parts(dom_node: &DomNodeRef, names: &[String]) -> bool {
+    let node = dom_node.borrow();
+    let parts = match node.attr("part") {
+        Some(Some(parts)) => parts.split_ascii_whitespace().collect::<Vec<_>>(),
+        _ => return false,
+    };
+    names.iter().all(|name| parts.contains(&name.as_str()))
+}
+
+/// Returns the descendants of root that match selector, in document order,
+/// like the DOM's `querySelectorAll()`. See query_selector_all_parallel() for
+/// large documents.
+pub fn query_selector_all(
+    root: &DomNodeRef,
+    selector: &Selector,
+    config: &MatcherConfig,
+) -> Vec<DomNodeRef> {
+    let mut found = vec![];
+    query_descendants(root, selector, config, &mut |node| {
+        found.push(node.clone());
+        true
+    });
+    found
+}
+
+/// Like query_selector_all(), but matches on rayon's thread pool.
+///
+/// Nodes live in their thread's arena, so root's whole tree is copied into a
+/// SyncTree first, and each thread builds a DOM from that to match its share
+/// of root's descendants in. That's only worth it for large documents or
+/// selectors that are slow to match. Registered pseudo-classes are called
+/// with the copies.
+#[cfg(feature = "parallel")]
+pub fn query_selector_all_parallel(
+    root: &DomNodeRef,
+    selector: &Selector,
+    config: &MatcherConfig,
+) -> Vec<DomNodeRef> {
+    // Selectors can look at root's ancestors, and at the hosts of the shadow
+    // trees it's in
+    let top = root.shadow_including_ancestors().last().unwrap_or_else(|| root.clone());
+    let tree = SyncTree::from(&top);
+    let root_id_num = root.borrow().id_num;
+    let root_index = tree.nodes().iter().position(|node| node.id_num == root_id_num).unwrap();
+    let config = SyncMatcherConfig::new(config, &tree);
+
+    // Parents come before their children
+    let mut in_root = vec![false; tree.len()];
+    let mut candidates = vec![];
+    for (index, node) in tree.nodes().iter().enumerate() {
+        in_root[index] = node.parent.is_some_and(|parent| parent == root_index || in_root[parent]);
+        if in_root[index] && node.elem_type.is_element() {
+            candidates.push(index);
+        }
+    }
+    let chunk_size = candidates.len().div_ceil(rayon::current_num_threads()).max(1);
+    let found = candidates
+        .par_chunks(chunk_size)
+        .flat_map_iter(|chunk| {
+            let dom_nodes = tree.to_dom_nodes();
+            // Which :defined looks at
+            for (dom_node, node) in dom_nodes.iter().zip(tree.nodes()) {
+                dom_node.borrow_mut().upgraded = node.upgraded;
+            }
+            let config = config.to_config(&dom_nodes);
+            chunk
+                .iter()
+                .filter(|&&index| matches_with_config(&dom_nodes[index], selector, &config))
+                .map(|&index| tree.nodes()[index].id_num)
+                .collect::<Vec<_>>()
+        })
+        .collect::<HashSet<_>>();
+    root.descendants().filter(|node| found.contains(&node.borrow().id_num)).collect()
+}
+
+/// Returns the first descendant of root that matches selector, in document
+/// order, like the DOM's `querySelector()`.
+pub fn query_selector(
+    root: &DomNodeRef,
+    selector: &Selector,
+    config: &MatcherConfig,
+) -> Option<DomNodeRef> {
+    let mut found = None;
+    query_descendants(root, selector, config, &mut |node| {
+        found = Some(node.clone());
+        false
+    });
+    found
+}
+
+/// Returns the elements of document that match selector, in document order,
+/// like the DOM's `document.querySelectorAll()`. Unlike query_selector_all(),
+/// the root is included. Selectors that are just an id are looked up in the
+/// document's id map, except in quirks mode, where ids match
+/// case-insensitively.
+pub fn query_document(
+    document: &Document,
+    selector: &Selector,
+    config: &MatcherConfig,
+) -> Vec<DomNodeRef> {
+    if let Selector::Simple(ref simple) = *selector {
+        let id_only = simple.elem_type.is_none() && simple.classes.is_empty()
+            && simple.namespace == NamespaceConstraint::Any;
+        if let (Some(id), true, false) = (&simple.id, id_only, config.is_quirks()) {
+            return document.get_elements_by_id(id);
+        }
+    }
+    let root = document.root();
+    let mut found = vec![];
+    if root.borrow().elem_type.is_element() && matches_with_config(root, selector, config) {
+        found.push(root.clone());
+    }
+    found.extend(query_selector_all(root, selector, config));
+    found
+}
+
+impl Document {
+    /// Returns the text of each element that matches selectors, a selector
+    /// list like `h1, .title`, in document order, e.g. for scraping. See
+    /// query_document() and DomNodeRef::text_content(). Returns an error if
+    /// selectors can't be parsed.
+    pub fn extract_text(&self, selectors: &str) -> Result<Vec<String>> {
+        let selector = parse_selector(selectors)?;
+        let found = query_document(self, &selector, &MatcherConfig::for_document(self));
+        Ok(found.iter().map(DomNodeRef::text_content).collect())
+    }
+}
+
+/// Returns a TreeWalker filter that accepts the elements that match selector
+/// and skips the other nodes, so that their descendants are still looked at,
+/// e.g. for `TreeWalker::new(root).filter(selector_filter(selector, config))`.
+pub fn selector_filter(
+    selector: Selector,
+    config: MatcherConfig,
+) -> impl Fn(&DomNodeRef) -> FilterResult {
+    move |dom_node| {
+        let is_element = dom_node.borrow().elem_type.is_element();
+        if is_element && matches_with_config(dom_node, &selector, &config) {
+            FilterResult::Accept
+        } else {
+            FilterResult::Skip
+        }
+    }
+}
+
+// Calls found with each matching descendant of dom_node in document order,
+// until it returns false. Returns false if the search was stopped.
+fn query_descendants<F>(
+    dom_node: &DomNodeRef,
+    selector: &Selector,
+    config: &MatcherConfig,
+    found: &mut F,
+) -> bool
+where
+    F: FnMut(&DomNodeRef) -> bool,
+{
+    for descendant in dom_node.descendants() {
+        // Text and comment nodes aren't matched by selectors
+        if !descendant.borrow().elem_type.is_element() {
+            continue;
+        }
+        if matches_with_config(&descendant, selector, config) && !found(&descendant) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Splits a complex selector like `a > b c` (which the parser nests to the
+/// right) into its compound selectors and the combinators between them, left to
+/// right.
+pub fn flatten_complex_selector<'a>(
+    selector: &'a Selector,
+    compounds: &mut Vec<&'a Selector>,
+    combinators: &mut Vec<&'a Combinator>,
+) {
+    match selector {
+        Selector::Combinator(ref first, ref combinator, ref second) => {
+            flatten_complex_selector(first, compounds, combinators);
+            combinators.push(combinator);
+            flatten_complex_selector(second, compounds, combinators);
+        }
+        _ => compounds.push(selector),
+    }
+}
+
+// True if selector is a simple selector that a featureless element, i.e. a
+// shadow host seen from its shadow tree, doesn't match
+fn is_feature(selector: &Selector) -> bool {
+    match selector {
+        Selector::Simple(_) | Selector::Attr(_) => true,
+        Selector::PseudoClass(ref pseudo_class) => !matches!(
+            pseudo_class,
+            PseudoClassSelector::Host(_)
+                | PseudoClassSelector::Matches(_)
+                | PseudoClassSelector::Not(_)
+        ),
+        _ => false,
+    }
+}
+
+// dom_node's parent as combinators see it. In the shadow tree of config's
+// shadow host, that's the host for the shadow root's children, and nothing
+// for the host itself, since a shadow tree's selectors can't see past it.
+fn combinator_parent(dom_node: &DomNodeRef, config: &MatcherConfig) -> Option<DomNodeRef> {
+    if config.is_shadow_host(dom_node) {
+        return None;
+    }
+    let parent = dom_node.parent()?;
+    match parent.host() {
+        Some(host) => Some(host).filter(|host| config.is_shadow_host(host)),
+        None => Some(parent),
+    }
+}
+
+// Element siblings before dom_node, nearest first. Text and comment nodes are
+// skipped since sibling combinators only relate elements.
+fn preceding_element_siblings(dom_node: &DomNodeRef) -> Vec<DomNodeRef> {
+    dom_node
+        .preceding_siblings()
+        .filter(|sibling| sibling.borrow().elem_type.is_element())
+        .collect()
+}
+
+// Result of matching the compounds of a complex selector up to some point
+// against a node. A failure says how far back (left to right) matching has to
+// restart from, so that it doesn't retry nodes that can't match, which on deep
+// trees takes exponential time for selectors like `a b c d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ComplexMatch {
+    Matched,
+    // The next node to the left, e.g. an earlier sibling for `~`, can be tried
+    RestartFromLaterSibling,
+    // Only another ancestor for the nearest descendant combinator to the right
+    // can match, since the others have the same ancestors as this one
+    RestartFromDescendant,
+    // Every ancestor has been tried, so no other node can match either
+    NotMatchedGlobally,
+}
+
+// Matches dom_node against the last compound, then walks to the ancestors or
+// siblings the last combinator allows, nearest first, and matches the rest
+// from there.
+fn matches_complex_selector(
+    dom_node: &DomNodeRef,
+    compounds: &[&Selector],
+    combinators: &[&Combinator],
+    config: &MatcherConfig,
+) -> ComplexMatch {
+    let (last, compounds) = compounds.split_last().unwrap();
+    if !matches_with_config(dom_node, last, config) {
+        return ComplexMatch::RestartFromLaterSibling;
+    }
+    // The combinators left of a ::part() relate its host
+    let host;
+    let dom_node = match last.pseudo_element() {
+        Some(PseudoElementSelector::Part(_)) => {
+            host = part_host(dom_node, config).unwrap();
+            &host
+        }
+        _ => dom_node,
+    };
+    let (combinator, combinators) = match combinators.split_last() {
+        Some(split) => split,
+        None => return ComplexMatch::Matched,
+    };
+    let candidates = match combinator {
+        Combinator::Child => combinator_parent(dom_node, config).into_iter().collect(),
+        Combinator::Descendant => {
+            let mut ancestors = vec![];
+            let mut node = dom_node.clone();
+            while let Some(parent) = combinator_parent(&node, config) {
+                ancestors.push(parent.clone());
+                node = parent;
+            }
+            ancestors
+        }
+        Combinator::AdjacentSibling => {
+            preceding_element_siblings(dom_node).into_iter().take(1).collect()
+        }
+        Combinator::GeneralSibling => preceding_element_siblings(dom_node),
+    };
+    for node in &candidates {
+        let result = matches_complex_selector(node, compounds, combinators, config);
+        match (result, combinator) {
+            (ComplexMatch::Matched, _)
+            | (ComplexMatch::NotMatchedGlobally, _)
+            | (_, Combinator::AdjacentSibling) => return result,
+            // Another parent isn't possible, so the nearest descendant
+            // combinator has to try its next ancestor
+            (_, Combinator::Child) => return ComplexMatch::RestartFromDescendant,
+            (ComplexMatch::RestartFromDescendant, Combinator::GeneralSibling) => return result,
+            _ => {}
+        }
+    }
+    // Siblings have the same ancestors, so only a descendant combinator to
+    // the right can still match
+    match combinator {
+        Combinator::AdjacentSibling | Combinator::GeneralSibling => {
+            ComplexMatch::RestartFromDescendant
+        }
+        Combinator::Child | Combinator::Descendant => ComplexMatch::NotMatchedGlobally,
+    }
+}
+
+/// Returns true if selector matches pseudo_element of dom_node, or dom_node
+/// itself if pseudo_element is None. The parts ::part() selects are elements,
+/// so its selectors style dom_node itself.
+pub fn matches_pseudo_element(
+    dom_node: &DomNodeRef,
+    selector: &Selector,
+    pseudo_element: Option<&PseudoElementSelector>,
+    config: &MatcherConfig,
+) -> bool {
+    match selector {
+        Selector::Group(ref sels) => sels.iter()
+            .any(|sel| matches_pseudo_element(dom_node, sel, pseudo_element, config)),
+        _ => {
+            styled_pseudo_element(selector) == pseudo_element
+                && matches_with_config(dom_node, selector, config)
+        }
+    }
+}
+
+/// Returns the specificity selector matches pseudo_element of dom_node with (see
+/// `matches_pseudo_element()`), or None if it doesn't match. For groups, this
+/// is the specificity of the most specific selector that matched.
+pub fn matching_specificity(
+    dom_node: &DomNodeRef,
+    selector: &Selector,
+    pseudo_element: Option<&PseudoElementSelector>,
+    config: &MatcherConfig,
+) -> Option<Specificity> {
     match selector {
-        Selector::Simple(ref simple_sel) => matches_simple_selector(dom_node, simple_sel),
-        Selector::Attr(ref attr_sel) => matches_attr_selector(dom_node, attr_sel),
-        _ => unimplemented!(),
+        Selector::Group(ref sels) => sels.iter()
+            .filter_map(|sel| matching_specificity(dom_node, sel, pseudo_element, config))
+            .max(),
+        _ => if matches_pseudo_element(dom_node, selector, pseudo_element, config) {
+            Some(selector.specificity())
+        } else {
+            None
+        },
+    }
+}
+
+/// Like matching_specificity() for dom_node itself, but only with the
+/// selectors that have a ::part(), for the rules of the tree of the host whose
+/// shadow tree dom_node is in, which can only style it as one of the host's
+/// parts. config is the one for the host's tree.
+pub fn matching_part_specificity(
+    dom_node: &DomNodeRef,
+    selector: &Selector,
+    config: &MatcherConfig,
+) -> Option<Specificity> {
+    match selector {
+        Selector::Group(ref sels) => sels.iter()
+            .filter_map(|sel| matching_part_specificity(dom_node, sel, config))
+            .max(),
+        _ => match selector.pseudo_element() {
+            Some(PseudoElementSelector::Part(_)) => {
+                matching_specificity(dom_node, selector, None, config)
+            }
+            _ => None,
+        },
+    }
+}
+
+// The pseudo-element selector styles, or None if it styles the elements it
+// matches
+fn styled_pseudo_element(selector: &Selector) -> Option<&PseudoElementSelector> {
+    selector.pseudo_element().filter(|pseudo_element| {
+        !matches!(pseudo_element, PseudoElementSelector::Part(_))
+    })
+}
+
+/// Collects the declarations in css_blocks that apply to pseudo_element of
+/// dom_node (or dom_node itself if pseudo_element is None). Later blocks
+/// override earlier ones. Properties the pseudo-element doesn't support (e.g.
+/// margin on ::selection) are dropped.
+pub fn matching_declarations(
+    dom_node: &DomNodeRef,
+    CssBlocks(blocks): &CssBlocks,
+    pseudo_element: Option<&PseudoElementSelector>,
+    config: &MatcherConfig,
+) -> HashMap<String, String> {
+    let mut decls = HashMap::new();
+    for (selector, decl_block) in blocks {
+        if !matches_pseudo_element(dom_node, selector, pseudo_element, config) {
+            continue;
+        }
+        for (property, value) in decl_block {
+            if pseudo_element.is_none_or(|pe| pe.allows_property(property)) {
+                decls.insert(property.clone(), value.clone());
+            }
+        }
+    }
+    decls
+}
+
+/// The first part of a selector that failed to match a DOM node, as found by
+/// `explain_mismatch()`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Mismatch {
+    Namespace {
+        expected: NamespaceConstraint,
+        actual: Option<String>,
+    },
+    ElemType {
+        expected: ElemType,
+        actual: ElemType,
+    },
+    Id {
+        expected: String,
+        actual: Option<String>,
+    },
+    MissingClass(String),
+    // The attribute selector (as CSS) and the attribute's value, or None if the
+    // node doesn't have the attribute
+    Attr(String, Option<Option<String>>),
+    // The pseudo-class selector (as CSS) and the node's index among its siblings
+    PseudoClass(String, Option<usize>),
+    PseudoElement(String),
+    // The complex selector (as CSS) whose rightmost compound selector matched,
+    // but whose ancestors or siblings didn't
+    Combinator(String),
+    // No selector of a group matched; holds the mismatch for each of them
+    NoneMatched(Vec<Mismatch>),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Namespace { expected, actual } => write!(
+                f,
+                "namespace {:?} doesn't satisfy {:?}",
+                actual, expected
+            ),
+            Mismatch::ElemType { expected, actual } => write!(
+                f,
+                "expected <{}>, got <{}>",
+                expected.tag_name(),
+                actual.tag_name()
+            ),
+            Mismatch::Id {
+                expected,
+                actual: Some(actual),
+            } => write!(f, "expected id \"{}\", got \"{}\"", expected, actual),
+            Mismatch::Id {
+                expected,
+                actual: None,
+            } => write!(f, "expected id \"{}\", got no id", expected),
+            Mismatch::MissingClass(class) => write!(f, "missing class \"{}\"", class),
+            Mismatch::Attr(selector, None) => write!(f, "{}: attribute missing", selector),
+            Mismatch::Attr(selector, Some(None)) => {
+                write!(f, "{}: attribute has no value", selector)
+            }
+            Mismatch::Attr(selector, Some(Some(value))) => {
+                write!(f, "{}: attribute value is \"{}\"", selector, value)
+            }
+            Mismatch::PseudoClass(selector, Some(child_index)) => {
+                write!(f, "{} doesn't match child {}", selector, child_index)
+            }
+            Mismatch::PseudoClass(selector, None) => write!(f, "{} doesn't match", selector),
+            Mismatch::PseudoElement(selector) => write!(f, "{} doesn't match", selector),
+            Mismatch::Combinator(selector) => {
+                write!(f, "{}: no matching ancestors or siblings", selector)
+            }
+            Mismatch::NoneMatched(mismatches) => {
+                write!(f, "no selector in group matched (")?;
+                for (i, mismatch) in mismatches.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", mismatch)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn explain_simple_selector_mismatch(
+    node: &DomNodeRef,
+    SimpleSelector {
+        elem_type,
+        id,
+        classes,
+        namespace,
+        ..
+    }: &SimpleSelector,
+    config: &MatcherConfig,
+) -> Option<Mismatch> {
+    let node = node.borrow();
+    if !namespace.matches(node.namespace.as_deref()) {
+        return Some(Mismatch::Namespace {
+            expected: namespace.clone(),
+            actual: node.namespace.clone(),
+        });
+    }
+    if let Some(ref elem_type) = elem_type {
+        if *elem_type != node.elem_type {
+            return Some(Mismatch::ElemType {
+                expected: elem_type.clone(),
+                actual: node.elem_type.clone(),
+            });
+        }
+    }
+    if let Some(ref id) = id {
+        if !node.id.as_ref().is_some_and(|node_id| id_matches(id, node_id, config)) {
+            return Some(Mismatch::Id {
+                expected: id.to_string(),
+                actual: node.id.as_ref().map(|id| id.to_string()),
+            });
+        }
+    }
+    let mut classes = classes.iter().collect::<Vec<_>>();
+    classes.sort();
+    classes
+        .into_iter()
+        .find(|class| !has_class(&node, class, config))
+        .map(|class| Mismatch::MissingClass(class.to_string()))
+}
+
+/// Returns why selector doesn't match dom_node, or None if it does. Meant for
+/// debugging stylesheets; use `matches_with_config()` when only the result
+/// matters.
+pub fn explain_mismatch(
+    dom_node: &DomNodeRef,
+    selector: &Selector,
+    config: &MatcherConfig,
+) -> Option<Mismatch> {
+    if matches_with_config(dom_node, selector, config) {
+        return None;
+    }
+    match selector {
+        Selector::Simple(ref simple_sel) => {
+            explain_simple_selector_mismatch(dom_node, simple_sel, config)
+        }
+        Selector::Attr(ref attr_sel) => {
+            let node = dom_node.borrow();
+            Some(Mismatch::Attr(
+                attr_sel.to_css_string(),
+                selected_attr(&node, attr_sel, config).cloned(),
+            ))
+        }
+        Selector::PseudoClass(ref pc_sel) => Some(Mismatch::PseudoClass(
+            pc_sel.to_css_string(),
+            dom_node.child_index(),
+        )),
+        Selector::PseudoElement(ref pe_sel) => {
+            Some(Mismatch::PseudoElement(pe_sel.to_css_string()))
+        }
+        Selector::Seq(ref sels) => sels.iter()
+            .filter_map(|sel| explain_mismatch(dom_node, sel, config))
+            .next(),
+        Selector::Group(ref sels) => Some(Mismatch::NoneMatched(
+            sels.iter()
+                .filter_map(|sel| explain_mismatch(dom_node, sel, config))
+                .collect(),
+        )),
+        Selector::Combinator(..) => {
+            let mut compounds = vec![];
+            let mut combinators = vec![];
+            flatten_complex_selector(selector, &mut compounds, &mut combinators);
+            let last = compounds.last().unwrap();
+            explain_mismatch(dom_node, last, config)
+                .or_else(|| Some(Mismatch::Combinator(selector.to_css_string())))
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use magicparser::{DomNode, ElemType, NthExpr, NthExprOp};
+    use magicparser::{DomNode, ElemType, NamespaceConstraint, NthExpr, NthExprOp,
+                      TreeWalker, SVG_NAMESPACE, XLINK_NAMESPACE};
 
     #[test]
     fn test_matches_simple_selector1() {
         let dom_node =
             DomNode::new(ElemType::A, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref();
         let selector = SimpleSelector::new(Some(ElemType::A), None, hashset!{}, false);
-        assert!(matches_simple_selector(&dom_node, &selector));
+        assert!(matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
     fn test_matches_simple_selector_universal() {
-        let dom_node = DomNode::new(
-            ElemType::A,
-            Some("id".to_string()),
-            hashset!{"cl1".to_string()},
-            hashmap!{
-                "id".to_string() => Some("id".to_string()),
-                "class".to_string() => Some("cl1".to_string()),
-            },
-            None,
-            vec![],
-        ).to_dnref();
+        let dom_node = DomNode::builder(ElemType::A).id("id").class("cl1").build();
         let selector = SimpleSelector::new(None, None, hashset!{}, true);
-        assert!(matches_simple_selector(&dom_node, &selector));
+        assert!(matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_matches_simple_selector_quirks_mode() {
+        let dom_node = DomNode::builder(ElemType::A).id("Main").class("Nav").build();
+        let quirks = MatcherConfig {
+            quirks_mode: QuirksMode::Quirks,
+            ..MatcherConfig::default()
+        };
+        let selector = SimpleSelector::new(None, Some("main".to_string()), hashset!{}, false);
+        assert!(!matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+        assert!(matches_simple_selector(&dom_node, &selector, &quirks));
+        let selector = SimpleSelector::new(None, None, hashset!{"NAV".to_string()}, false);
+        assert!(!matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+        assert!(matches_simple_selector(&dom_node, &selector, &quirks));
+        let selector = SimpleSelector::new(None, None, hashset!{"menu".to_string()}, false);
+        assert!(!matches_simple_selector(&dom_node, &selector, &quirks));
     }
 
     #[test]
     fn test_matches_simple_selector2() {
-        let dom_node = DomNode::new(
-            ElemType::A,
-            Some("id".to_string()),
-            hashset!{},
-            hashmap!{
-                "id".to_string() => Some("id".to_string())
-            },
-            None,
-            vec![],
-        ).to_dnref();
+        let dom_node = DomNode::builder(ElemType::A).id("id").build();
         let selector = SimpleSelector::new(None, Some("id".to_string()), hashset!{}, false);
-        assert!(matches_simple_selector(&dom_node, &selector));
+        assert!(matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
     fn test_matches_simple_selector3() {
-        let dom_node = DomNode::new(
-            ElemType::A,
-            None,
-            hashset!{"cl1".to_string(), "cl2".to_string()},
-            hashmap!{},
-            None,
-            vec![],
-        ).to_dnref();
+        let dom_node = DomNode::builder(ElemType::A).class("cl1").class("cl2").build();
         let selector = SimpleSelector::new(None, None, hashset!{"cl2".to_string()}, false);
-        assert!(matches_simple_selector(&dom_node, &selector));
+        assert!(matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
     fn test_matches_simple_selector_fail1() {
-        let dom_node = DomNode::new(
-            ElemType::A,
-            Some("id".to_string()),
-            hashset!{"cl1".to_string()},
-            hashmap!{},
-            None,
-            vec![],
-        ).to_dnref();
+        let dom_node = dom!(a#id.cl1);
         let selector = SimpleSelector::new(
             Some(ElemType::P),
             Some("id".to_string()),
             hashset!{"cl1".to_string(), "cl2".to_string()},
             true,
         );
-        assert!(!matches_simple_selector(&dom_node, &selector));
+        assert!(!matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
     fn test_matches_simple_selector_fail2() {
+        let dom_node = dom!(a#id);
+        let selector =
+            SimpleSelector::new(Some(ElemType::P), Some("id".to_string()), hashset!{}, false);
+        assert!(!matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_matches_simple_selector_fail3() {
+        let dom_node = dom!(a#id1);
+        let selector = SimpleSelector::new(None, Some("id2".to_string()), hashset!{}, false);
+        assert!(!matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_matches_attr_selector_no_op_val() {
         let dom_node = DomNode::new(
             ElemType::A,
-            Some("id".to_string()),
+            Some("id1".to_string()),
             hashset!{},
-            hashmap!{},
+            hashmap!{
+                "id".to_string() => Some("id1".to_string())
+            },
             None,
             vec![],
         ).to_dnref();
-        let selector =
-            SimpleSelector::new(Some(ElemType::P), Some("id".to_string()), hashset!{}, false);
-        assert!(!matches_simple_selector(&dom_node, &selector));
+        let selector = AttrSelector::new("id".to_string(), None, AttrCaseFlag::Unspecified);
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
-    fn test_matches_simple_selector_fail3() {
+    fn test_matches_attr_selector_no_op_val_fail() {
         let dom_node = DomNode::new(
             ElemType::A,
             Some("id1".to_string()),
             hashset!{},
-            hashmap!{},
+            hashmap!{
+                "id".to_string() => Some("id1".to_string())
+            },
             None,
             vec![],
         ).to_dnref();
-        let selector = SimpleSelector::new(None, Some("id2".to_string()), hashset!{}, false);
-        assert!(!matches_simple_selector(&dom_node, &selector));
+        let selector = AttrSelector::new("attr".to_string(), None, AttrCaseFlag::Unspecified);
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
-    fn test_matches_attr_selector_no_op_val() {
+    fn test_matches_attr_selector_exactly() {
         let dom_node = DomNode::new(
             ElemType::A,
             Some("id1".to_string()),
@@ -355,12 +1172,16 @@ mod tests {
             None,
             vec![],
         ).to_dnref();
-        let selector = AttrSelector::new("id".to_string(), None, false);
-        assert!(matches_attr_selector(&dom_node, &selector));
+        let selector = AttrSelector::new(
+            "id".to_string(),
+            Some((AttrSelectorOp::Exactly, "id1".to_string())),
+            AttrCaseFlag::Unspecified,
+        );
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
-    fn test_matches_attr_selector_no_op_val_fail() {
+    fn test_matches_attr_selector_exactly_fail() {
         let dom_node = DomNode::new(
             ElemType::A,
             Some("id1".to_string()),
@@ -371,68 +1192,137 @@ mod tests {
             None,
             vec![],
         ).to_dnref();
-        let selector = AttrSelector::new("attr".to_string(), None, false);
-        assert!(!matches_attr_selector(&dom_node, &selector));
+        let selector = AttrSelector::new(
+            "id".to_string(),
+            Some((AttrSelectorOp::Exactly, "Id1".to_string())),
+            AttrCaseFlag::Unspecified,
+        );
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
-    fn test_matches_attr_selector_exactly() {
+    fn test_matches_attr_selector_exactly_case_insensitive() {
         let dom_node = DomNode::new(
             ElemType::A,
             Some("id1".to_string()),
             hashset!{},
             hashmap!{
-                "id".to_string() => Some("id1".to_string())
+                "id".to_string() => Some("iD1".to_string())
             },
             None,
             vec![],
         ).to_dnref();
         let selector = AttrSelector::new(
             "id".to_string(),
-            Some((AttrSelectorOp::Exactly, "id1".to_string())),
-            false,
+            Some((AttrSelectorOp::Exactly, "Id1".to_string())),
+            AttrCaseFlag::Insensitive,
+        );
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_matches_attr_selector_exactly_case_sensitive() {
+        let dom_node = DomNode::new(
+            ElemType::A,
+            None,
+            hashset!{},
+            hashmap!{
+                "type".to_string() => Some("Text".to_string())
+            },
+            None,
+            vec![],
+        ).to_dnref();
+        let selector = AttrSelector::new(
+            "type".to_string(),
+            Some((AttrSelectorOp::Exactly, "text".to_string())),
+            AttrCaseFlag::Sensitive,
+        );
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
+        let selector = AttrSelector::new(
+            "type".to_string(),
+            Some((AttrSelectorOp::Exactly, "Text".to_string())),
+            AttrCaseFlag::Sensitive,
+        );
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_matches_attr_selector_quirks_mode_values() {
+        let dom_node = DomNode::new(
+            ElemType::A,
+            None,
+            hashset!{},
+            hashmap!{
+                "type".to_string() => Some("Text".to_string()),
+                "title".to_string() => Some("Text".to_string()),
+            },
+            None,
+            vec![],
+        ).to_dnref();
+        let quirks = MatcherConfig {
+            quirks_mode: QuirksMode::Quirks,
+            ..MatcherConfig::default()
+        };
+        let selector = AttrSelector::new(
+            "type".to_string(),
+            Some((AttrSelectorOp::Exactly, "text".to_string())),
+            AttrCaseFlag::Unspecified,
+        );
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
+        assert!(matches_attr_selector(&dom_node, &selector, &quirks));
+        // The s flag overrides quirks mode
+        let selector = AttrSelector::new(
+            "type".to_string(),
+            Some((AttrSelectorOp::Exactly, "text".to_string())),
+            AttrCaseFlag::Sensitive,
+        );
+        assert!(!matches_attr_selector(&dom_node, &selector, &quirks));
+        // title isn't one of the legacy case-insensitive attributes
+        let selector = AttrSelector::new(
+            "title".to_string(),
+            Some((AttrSelectorOp::Exactly, "text".to_string())),
+            AttrCaseFlag::Unspecified,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(!matches_attr_selector(&dom_node, &selector, &quirks));
     }
 
     #[test]
-    fn test_matches_attr_selector_exactly_fail() {
+    fn test_matches_attr_selector_name_case_html() {
         let dom_node = DomNode::new(
             ElemType::A,
-            Some("id1".to_string()),
+            None,
             hashset!{},
             hashmap!{
-                "id".to_string() => Some("id1".to_string())
+                "viewBox".to_string() => Some("0 0 10 10".to_string())
             },
             None,
             vec![],
         ).to_dnref();
-        let selector = AttrSelector::new(
-            "id".to_string(),
-            Some((AttrSelectorOp::Exactly, "Id1".to_string())),
-            false,
-        );
-        assert!(!matches_attr_selector(&dom_node, &selector));
+        let selector = AttrSelector::new("VIEWBOX".to_string(), None, AttrCaseFlag::Unspecified);
+        assert!(matches_attr_selector(
+            &dom_node,
+            &selector,
+            &MatcherConfig::new(DocumentKind::Html)
+        ));
     }
 
     #[test]
-    fn test_matches_attr_selector_exactly_case_insensitive() {
+    fn test_matches_attr_selector_name_case_xml() {
         let dom_node = DomNode::new(
             ElemType::A,
-            Some("id1".to_string()),
+            None,
             hashset!{},
             hashmap!{
-                "id".to_string() => Some("iD1".to_string())
+                "viewBox".to_string() => Some("0 0 10 10".to_string())
             },
             None,
             vec![],
         ).to_dnref();
-        let selector = AttrSelector::new(
-            "id".to_string(),
-            Some((AttrSelectorOp::Exactly, "Id1".to_string())),
-            true,
-        );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        let config = MatcherConfig::new(DocumentKind::Xml);
+        let selector = AttrSelector::new("viewbox".to_string(), None, AttrCaseFlag::Unspecified);
+        assert!(!matches_attr_selector(&dom_node, &selector, &config));
+        let selector = AttrSelector::new("viewBox".to_string(), None, AttrCaseFlag::Unspecified);
+        assert!(matches_attr_selector(&dom_node, &selector, &config));
     }
 
     #[test]
@@ -450,9 +1340,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::ExactlyOne, "val2".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -470,9 +1360,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::ExactlyOne, "val".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(!matches_attr_selector(&dom_node, &selector));
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -490,9 +1380,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::ExactlyOne, "VaL2".to_string())),
-            true,
+            AttrCaseFlag::Insensitive,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -510,9 +1400,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::ExactlyOrHyphen, "val".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -530,9 +1420,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::ExactlyOrHyphen, "val-1".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -550,9 +1440,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::ExactlyOrHyphen, "val1".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(!matches_attr_selector(&dom_node, &selector));
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -570,9 +1460,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::Prefixed, "va".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -590,9 +1480,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::Prefixed, "al".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(!matches_attr_selector(&dom_node, &selector));
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -610,9 +1500,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::Prefixed, "VaL".to_string())),
-            true,
+            AttrCaseFlag::Insensitive,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -630,9 +1520,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::Suffixed, "l1".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -650,9 +1540,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::Suffixed, "al".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(!matches_attr_selector(&dom_node, &selector));
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -670,9 +1560,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::Suffixed, "aL1".to_string())),
-            true,
+            AttrCaseFlag::Insensitive,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -690,9 +1580,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::ContainsAtLeastOne, "example".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -710,9 +1600,9 @@ mod tests {
         let selector = AttrSelector::new(
             "attr".to_string(),
             Some((AttrSelectorOp::ContainsAtLeastOne, "notexample".to_string())),
-            false,
+            AttrCaseFlag::Unspecified,
         );
-        assert!(!matches_attr_selector(&dom_node, &selector));
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -733,9 +1623,283 @@ mod tests {
                 AttrSelectorOp::ContainsAtLeastOne,
                 "exAMpLe.Com".to_string(),
             )),
-            true,
+            AttrCaseFlag::Insensitive,
         );
-        assert!(matches_attr_selector(&dom_node, &selector));
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_matches_attr_selector_ascii_case_insensitive() {
+        let dom_node = DomNode::new(
+            ElemType::A,
+            None,
+            hashset!{},
+            hashmap!{
+                "attr".to_string() => Some("Caf\u{c9} EN-us".to_string())
+            },
+            None,
+            vec![],
+        ).to_dnref();
+        let matches = |op: AttrSelectorOp, val: &str| {
+            let selector = AttrSelector::new(
+                "attr".to_string(),
+                Some((op, val.to_string())),
+                AttrCaseFlag::Insensitive,
+            );
+            matches_attr_selector(&dom_node, &selector, &MatcherConfig::default())
+        };
+        assert!(matches(AttrSelectorOp::ExactlyOne, "en-US"));
+        assert!(matches(AttrSelectorOp::ExactlyOne, "CAF\u{c9}"));
+        // Only ASCII letters are compared case-insensitively
+        assert!(!matches(AttrSelectorOp::ExactlyOne, "caf\u{e9}"));
+        assert!(matches(AttrSelectorOp::ExactlyOrHyphen, "en"));
+        assert!(!matches(AttrSelectorOp::ExactlyOrHyphen, "e"));
+        assert!(matches(AttrSelectorOp::Prefixed, "cAF"));
+        assert!(matches(AttrSelectorOp::Suffixed, "\u{c9} en-US"));
+        assert!(!matches(AttrSelectorOp::Exactly, "caf\u{c9} en"));
+    }
+
+    #[test]
+    fn test_matches_pcs_custom() {
+        fn is_open(dom_node: &DomNodeRef) -> bool {
+            dom_node.borrow().attr("open").is_some()
+        }
+
+        let open_node = DomNode::new(
+            ElemType::Div,
+            None,
+            hashset!{},
+            hashmap!{
+                "open".to_string() => None
+            },
+            None,
+            vec![],
+        ).to_dnref();
+        let closed_node =
+            DomNode::new(ElemType::Div, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref();
+        let selector = PseudoClassSelector::Custom("is-open".to_string());
+
+        // Unregistered pseudo-classes match nothing
+        let mut config = MatcherConfig::default();
+        assert!(!matches_pseudo_class_selector(&open_node, &selector, &config));
+
+        config.register_pseudo("Is-Open", is_open);
+        assert!(matches_pseudo_class_selector(&open_node, &selector, &config));
+        assert!(!matches_pseudo_class_selector(&closed_node, &selector, &config));
+    }
+
+    #[test]
+    fn test_matches_pcs_hover() {
+        let dom_node =
+            DomNode::new(ElemType::A, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref();
+        let selector = PseudoClassSelector::Hover;
+        let mut config = MatcherConfig::default();
+        assert!(!matches_pseudo_class_selector(&dom_node, &selector, &config));
+
+        config.register_pseudo("hover", |_| true);
+        assert!(matches_pseudo_class_selector(&dom_node, &selector, &config));
+    }
+
+    #[test]
+    fn test_matches_pcs_element_state() {
+        use magicparser::parse_css;
+
+        let input = ElemType::Custom(Atom::from("input"));
+        let dom_node = DomNode::new(input, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref();
+        dom_node.set_state(ElementState::FOCUS | ElementState::CHECKED, true);
+        let config = MatcherConfig::default();
+        let matches = |selector: PseudoClassSelector| {
+            matches_pseudo_class_selector(&dom_node, &selector, &config)
+        };
+        assert!(matches(PseudoClassSelector::Focus));
+        assert!(matches(PseudoClassSelector::Checked));
+        assert!(!matches(PseudoClassSelector::Disabled));
+        assert!(!matches(PseudoClassSelector::Hover));
+
+        dom_node.set_state(ElementState::CHECKED, false);
+        assert!(!matches(PseudoClassSelector::Checked));
+        let CssBlocks(mut blocks) = parse_css("input:focus:not(:target) {}").unwrap();
+        assert!(matches_with_config(&dom_node, &blocks.remove(0).0, &config));
+    }
+
+    #[test]
+    fn test_matches_pcs_form_state() {
+        use magicparser::{parse_css, parse_html};
+
+        let root = parse_html(
+            "<form><input type=\"checkbox\" checked></input><select><option>One</option>\
+             <option selected>Two</option></select><fieldset disabled><input></input>\
+             </fieldset></form>",
+        ).unwrap();
+        let config = MatcherConfig::default();
+        let matching = |css: &str| {
+            let CssBlocks(mut blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+            query_selector_all(&root, &blocks.remove(0).0, &config)
+                .iter()
+                .map(|node| node.borrow().elem_type.tag_name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matching(":checked"), vec!["input", "option"]);
+        assert_eq!(matching(":disabled"), vec!["fieldset", "input"]);
+        assert_eq!(matching(":enabled"), vec!["input", "select", "option", "option"]);
+        assert_eq!(matching(":default"), vec!["input", "option"]);
+        assert_eq!(matching(":indeterminate"), Vec::<String>::new());
+
+        let checkbox = root.borrow().children[0].clone();
+        checkbox.set_checked(false);
+        assert_eq!(matching(":checked"), vec!["option"]);
+        assert_eq!(matching(":default"), vec!["input", "option"]);
+        checkbox.set_indeterminate(true);
+        assert_eq!(matching(":indeterminate"), vec!["input"]);
+        let fieldset = root.borrow().children[2].clone();
+        fieldset.set_disabled(false);
+        assert_eq!(matching(":disabled"), Vec::<String>::new());
+        assert_eq!(matching(":enabled").len(), 6);
+    }
+
+    #[test]
+    fn test_matches_pcs_lang() {
+        use magicparser::{parse_css, parse_html};
+
+        let root = parse_html(
+            "<div lang=\"en-US\"><p>One</p><p lang=\"fr\">Two</p><p xml:lang=\"EN\" \
+             lang=\"de\">Three</p><p lang=\"\">Four</p></div>",
+        ).unwrap();
+        let config = MatcherConfig::default();
+        let matching = |css: &str| {
+            let CssBlocks(mut blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+            let selector = blocks.remove(0).0;
+            let children = root.borrow().children.to_vec();
+            children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| matches_with_config(child, &selector, &config))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        // Inherited, by prefix up to a hyphen, with xml:lang first, and an
+        // empty lang matching nothing
+        assert_eq!(matching("p:lang(en)"), vec![0, 2]);
+        assert_eq!(matching("p:lang(en-us)"), vec![0]);
+        assert_eq!(matching("p:lang(e)"), Vec::<usize>::new());
+        assert_eq!(matching("p:lang(fr)"), vec![1]);
+        assert_eq!(matching("p:lang(de)"), Vec::<usize>::new());
+        assert!(!matches_lang(&parse_html("<p>Text</p>").unwrap(), "en"));
+    }
+
+    #[test]
+    fn test_matches_pcs_text_and_comments() {
+        use magicparser::{parse_css, parse_html};
+
+        let root = parse_html(
+            "<div>Text<!-- a --><p><!-- b --></p> <p></p><!-- c -->More<p>Text</p></div>",
+        ).unwrap();
+        let config = MatcherConfig::default();
+        let matching = |css: &str| {
+            let CssBlocks(mut blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+            let selector = blocks.remove(0).0;
+            let children = root.borrow().children.to_vec();
+            children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| matches_with_config(child, &selector, &config))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        // Text, comment, p, p, comment, text, p
+        assert_eq!(matching("p:empty"), vec![2, 3]);
+        assert_eq!(matching("p:first-child"), vec![2]);
+        assert_eq!(matching("p:last-child"), vec![6]);
+        assert_eq!(matching("p:nth-child(2)"), vec![3]);
+        assert_eq!(matching("p:nth-last-child(3)"), vec![2]);
+        assert_eq!(matching("p + p"), vec![3, 6]);
+
+        let p = root.borrow().children[2].clone();
+        p.append_child(parse_html("<span></span>").unwrap());
+        assert!(p.borrow().self_style_dirty);
+        assert_eq!(matching("p:empty"), vec![3]);
+    }
+
+    #[test]
+    fn test_matches_pcs_host() {
+        let host = DomNode::new(
+            ElemType::Custom(Atom::from("my-elem")),
+            None,
+            hashset!{"open".to_string()},
+            hashmap!{
+                "class".to_string() => Some("open".to_string())
+            },
+            None,
+            vec![],
+        ).to_dnref();
+        let other =
+            DomNode::new(ElemType::Div, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref();
+        let selector = PseudoClassSelector::Host(None);
+
+        // Document stylesheets have no host
+        assert!(!matches_pseudo_class_selector(&host, &selector, &MatcherConfig::default()));
+
+        let config = MatcherConfig {
+            shadow_host: Some(host.clone()),
+            ..MatcherConfig::default()
+        };
+        assert!(matches_pseudo_class_selector(&host, &selector, &config));
+        assert!(!matches_pseudo_class_selector(&other, &selector, &config));
+
+        let selector = PseudoClassSelector::Host(Some(Box::new(Selector::Simple(
+            SimpleSelector::new(None, None, hashset!{"open".to_string()}, false),
+        ))));
+        assert!(matches_pseudo_class_selector(&host, &selector, &config));
+        let selector = PseudoClassSelector::Host(Some(Box::new(Selector::Simple(
+            SimpleSelector::new(None, None, hashset!{"closed".to_string()}, false),
+        ))));
+        assert!(!matches_pseudo_class_selector(&host, &selector, &config));
+    }
+
+    #[test]
+    fn test_matches_pes_slotted_and_part() {
+        use magicparser::parse_html;
+
+        let dom_node = DomNode::new(
+            ElemType::P,
+            None,
+            hashset!{},
+            hashmap!{
+                "part".to_string() => Some("label".to_string())
+            },
+            None,
+            vec![],
+        ).to_dnref();
+        let slotted = Selector::PseudoElement(PseudoElementSelector::Slotted(Box::new(
+            Selector::Simple(SimpleSelector::new(Some(ElemType::P), None, hashset!{}, false)),
+        )));
+        assert!(!matches(&dom_node, &slotted));
+        // Only elements in shadow trees are parts
+        let part = Selector::PseudoElement(PseudoElementSelector::Part(vec!["label".to_string()]));
+        assert!(!matches(&dom_node, &part));
+
+        let root = parse_html("<html><body><div class=\"x\"></div></body></html>").unwrap();
+        let body = root.borrow().children[0].clone();
+        let host = body.borrow().children[0].clone();
+        let span = parse_html("<span part=\"label big\"></span>").unwrap();
+        host.attach_shadow().unwrap().append_child(span.clone());
+        let matches_part = |selector: &str, config: &MatcherConfig| {
+            matches_with_config(&span, &parse_selector(selector).unwrap(), config)
+        };
+        let config = MatcherConfig::default();
+        assert!(matches_part("::part(label)", &config));
+        assert!(matches_part("div.x::part(big label)", &config));
+        assert!(matches_part("body > div::part(label)", &config));
+        assert!(!matches_part("span::part(label)", &config));
+        assert!(!matches_part("div::part(label small)", &config));
+        assert!(!matches_part("p div::part(label)", &config));
+        // From the shadow tree, only through :host
+        let config = MatcherConfig {
+            shadow_host: Some(host.clone()),
+            ..MatcherConfig::default()
+        };
+        assert!(matches_part(":host::part(label)", &config));
+        assert!(!matches_part("div::part(label)", &config));
     }
 
     #[test]
@@ -751,10 +1915,10 @@ mod tests {
             vec![],
         ).to_dnref();
         let selector = PseudoClassSelector::NthChild(NthExpr::A(1));
-        assert!(matches_pseudo_class_selector(&dom_node, &selector));
+        assert!(matches_pseudo_class_selector(&dom_node, &selector, &MatcherConfig::default()));
 
         let selector = PseudoClassSelector::NthChild(NthExpr::A(2));
-        assert!(!matches_pseudo_class_selector(&dom_node, &selector));
+        assert!(!matches_pseudo_class_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -779,11 +1943,13 @@ mod tests {
         let selector = PseudoClassSelector::NthChild(NthExpr::A(2));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -809,19 +1975,23 @@ mod tests {
         let selector = PseudoClassSelector::NthChild(NthExpr::AnOpB(2, Some(NthExprOp::Add), 1));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -847,19 +2017,23 @@ mod tests {
         let selector = PseudoClassSelector::FirstChild;
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -885,19 +2059,23 @@ mod tests {
         let selector = PseudoClassSelector::LastChild;
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -926,31 +2104,38 @@ mod tests {
         let selector = PseudoClassSelector::NthOfType(NthExpr::A(2));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[4],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[5],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[6],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -979,31 +2164,38 @@ mod tests {
         let selector = PseudoClassSelector::NthOfType(NthExpr::AnOpB(2, None, 0));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[4],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[5],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[6],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -1032,31 +2224,38 @@ mod tests {
         let selector = PseudoClassSelector::NthOfType(NthExpr::AnOpB(2, Some(NthExprOp::Add), 1));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[4],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[5],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[6],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -1085,31 +2284,38 @@ mod tests {
         let selector = PseudoClassSelector::FirstOfType;
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[4],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[5],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[6],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -1138,31 +2344,38 @@ mod tests {
         let selector = PseudoClassSelector::LastOfType;
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[4],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[5],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[6],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -1179,10 +2392,10 @@ mod tests {
             vec![],
         ).to_dnref();
         let selector = PseudoClassSelector::NthLastChild(NthExpr::A(1));
-        assert!(matches_pseudo_class_selector(&dom_node, &selector));
+        assert!(matches_pseudo_class_selector(&dom_node, &selector, &MatcherConfig::default()));
 
         let selector = PseudoClassSelector::NthLastChild(NthExpr::A(2));
-        assert!(!matches_pseudo_class_selector(&dom_node, &selector));
+        assert!(!matches_pseudo_class_selector(&dom_node, &selector, &MatcherConfig::default()));
     }
 
     #[test]
@@ -1207,19 +2420,23 @@ mod tests {
         let selector = PseudoClassSelector::NthLastChild(NthExpr::A(2));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -1246,19 +2463,23 @@ mod tests {
             PseudoClassSelector::NthLastChild(NthExpr::AnOpB(2, Some(NthExprOp::Add), 1));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -1288,31 +2509,38 @@ mod tests {
             PseudoClassSelector::NthLastOfType(NthExpr::AnOpB(2, Some(NthExprOp::Add), 1));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[0],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[1],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[2],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[3],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(!matches_pseudo_class_selector(
             &dom_node.borrow().children[4],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[5],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
         assert!(matches_pseudo_class_selector(
             &dom_node.borrow().children[6],
-            &selector
+            &selector,
+            &MatcherConfig::default(),
         ));
     }
 
@@ -1354,7 +2582,7 @@ mod tests {
         );
         let children = &dom_node.borrow().children;
         assert_eq!(
-            matching_child_combinator_nodes(&dom_node, &selector),
+            matching_child_combinator_nodes(&dom_node, &selector, &MatcherConfig::default()),
             vec![
                 children[0].clone(),
                 children[2].clone(),
@@ -1401,7 +2629,7 @@ mod tests {
         );
         let children = &dom_node.borrow().children;
         assert_eq!(
-            matching_child_combinator_nodes(&dom_node, &selector),
+            matching_child_combinator_nodes(&dom_node, &selector, &MatcherConfig::default()),
             vec![
                 children[1].clone(),
                 children[3].clone(),
@@ -1448,7 +2676,7 @@ mod tests {
             ))),
         );
         assert_eq!(
-            matching_child_combinator_nodes(&dom_node, &selector),
+            matching_child_combinator_nodes(&dom_node, &selector, &MatcherConfig::default()),
             vec![]
         );
     }
@@ -1491,7 +2719,7 @@ mod tests {
         );
         let children = &dom_node.borrow().children;
         assert_eq!(
-            matching_gen_sib_combinator_nodes(&children[3], &selector),
+            matching_gen_sib_combinator_nodes(&children[3], &selector, &MatcherConfig::default()),
             vec![children[5].clone()]
         );
     }
@@ -1534,7 +2762,7 @@ mod tests {
         );
         let children = &dom_node.borrow().children;
         assert_eq!(
-            matching_gen_sib_combinator_nodes(&children[3], &selector),
+            matching_gen_sib_combinator_nodes(&children[3], &selector, &MatcherConfig::default()),
             vec![children[4].clone(), children[6].clone()]
         );
     }
@@ -1577,7 +2805,7 @@ mod tests {
         );
         let children = &dom_node.borrow().children;
         assert_eq!(
-            matching_gen_sib_combinator_nodes(&children[2], &selector),
+            matching_gen_sib_combinator_nodes(&children[2], &selector, &MatcherConfig::default()),
             vec![children[4].clone(), children[6].clone()]
         );
     }
@@ -1620,8 +2848,460 @@ mod tests {
         );
         let children = &dom_node.borrow().children;
         assert_eq!(
-            matching_gen_sib_combinator_nodes(&children[0], &selector),
+            matching_gen_sib_combinator_nodes(&children[0], &selector, &MatcherConfig::default()),
             vec![]
         );
     }
+
+    #[test]
+    fn test_matches_pseudo_element_placeholder() {
+        let dom_node =
+            DomNode::new(ElemType::A, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref();
+        let selector = Selector::Seq(vec![
+            Selector::Simple(SimpleSelector::new(
+                Some(ElemType::A),
+                None,
+                hashset!{},
+                false,
+            )),
+            Selector::PseudoElement(PseudoElementSelector::Placeholder),
+        ]);
+        assert!(matches(&dom_node, &selector));
+        assert!(matches_pseudo_element(
+            &dom_node,
+            &selector,
+            Some(&PseudoElementSelector::Placeholder),
+            &MatcherConfig::default(),
+        ));
+        assert!(!matches_pseudo_element(&dom_node, &selector, None, &MatcherConfig::default()));
+        assert!(!matches_pseudo_element(
+            &dom_node,
+            &selector,
+            Some(&PseudoElementSelector::Selection),
+            &MatcherConfig::default(),
+        ));
+    }
+
+    #[test]
+    fn test_matching_declarations_selection() {
+        let dom_node =
+            DomNode::new(ElemType::P, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref();
+        let blocks = CssBlocks(vec![
+            (
+                Selector::Simple(SimpleSelector::new(
+                    Some(ElemType::P),
+                    None,
+                    hashset!{},
+                    false,
+                )),
+                hashmap! {
+                    "color".to_string() => "black".to_string(),
+                },
+            ),
+            (
+                Selector::Group(vec![
+                    Selector::PseudoElement(PseudoElementSelector::Selection),
+                    Selector::Seq(vec![
+                        Selector::Simple(SimpleSelector::new(
+                            Some(ElemType::P),
+                            None,
+                            hashset!{},
+                            false,
+                        )),
+                        Selector::PseudoElement(PseudoElementSelector::Placeholder),
+                    ]),
+                ]),
+                hashmap! {
+                    "color".to_string() => "white".to_string(),
+                    "background-color".to_string() => "blue".to_string(),
+                    "margin".to_string() => "10px".to_string(),
+                },
+            ),
+        ]);
+        assert_eq!(
+            matching_declarations(&dom_node, &blocks, None, &MatcherConfig::default()),
+            hashmap! {
+                "color".to_string() => "black".to_string(),
+            }
+        );
+        assert_eq!(
+            matching_declarations(
+                &dom_node,
+                &blocks,
+                Some(&PseudoElementSelector::Selection),
+                &MatcherConfig::default(),
+            ),
+            hashmap! {
+                "color".to_string() => "white".to_string(),
+                "background-color".to_string() => "blue".to_string(),
+            }
+        );
+        assert_eq!(
+            matching_declarations(
+                &dom_node,
+                &blocks,
+                Some(&PseudoElementSelector::Placeholder),
+                &MatcherConfig::default(),
+            ),
+            hashmap! {
+                "color".to_string() => "white".to_string(),
+                "background-color".to_string() => "blue".to_string(),
+                "margin".to_string() => "10px".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_matches_simple_selector_namespace() {
+        let dom_node = DomNode::new_with_namespace(
+            ElemType::from("rect"),
+            None,
+            hashset!{},
+            hashmap!{},
+            None,
+            vec![],
+            Some(SVG_NAMESPACE.to_string()),
+        ).to_dnref();
+        let selector = SimpleSelector::new_with_namespace(
+            Some(ElemType::from("rect")),
+            None,
+            hashset!{},
+            false,
+            NamespaceConstraint::Url(SVG_NAMESPACE.to_string()),
+        );
+        assert!(matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+        let selector = SimpleSelector::new_with_namespace(
+            Some(ElemType::from("rect")),
+            None,
+            hashset!{},
+            false,
+            NamespaceConstraint::Any,
+        );
+        assert!(matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+        let selector = SimpleSelector::new_with_namespace(
+            Some(ElemType::from("rect")),
+            None,
+            hashset!{},
+            false,
+            NamespaceConstraint::NoNamespace,
+        );
+        assert!(!matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_matches_simple_selector_namespace_fail() {
+        let dom_node = DomNode::new(
+            ElemType::from("rect"),
+            None,
+            hashset!{},
+            hashmap!{},
+            None,
+            vec![],
+        ).to_dnref();
+        let selector = SimpleSelector::new_with_namespace(
+            Some(ElemType::from("rect")),
+            None,
+            hashset!{},
+            false,
+            NamespaceConstraint::Url(SVG_NAMESPACE.to_string()),
+        );
+        assert!(!matches_simple_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_matches_attr_selector_namespace() {
+        let dom_node = DomNode::new_with_namespace(
+            ElemType::from("use"),
+            None,
+            hashset!{},
+            hashmap!{
+                "xlink:href".to_string() => Some("#a".to_string())
+            },
+            None,
+            vec![],
+            Some(SVG_NAMESPACE.to_string()),
+        ).to_dnref();
+        let selector = AttrSelector::new_with_namespace(
+            "href".to_string(),
+            Some((AttrSelectorOp::Exactly, "#a".to_string())),
+            AttrCaseFlag::Unspecified,
+            NamespaceConstraint::Url(XLINK_NAMESPACE.to_string()),
+        );
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
+        let selector = AttrSelector::new_with_namespace(
+            "href".to_string(),
+            None,
+            AttrCaseFlag::Unspecified,
+            NamespaceConstraint::Any,
+        );
+        assert!(matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
+        // [href] only matches href attributes in no namespace
+        let selector = AttrSelector::new("href".to_string(), None, AttrCaseFlag::Unspecified);
+        assert!(!matches_attr_selector(&dom_node, &selector, &MatcherConfig::default()));
+    }
+
+    #[test]
+    fn test_explain_mismatch() {
+        let dom_node = DomNode::new(
+            ElemType::Div,
+            Some("main".to_string()),
+            hashset!{"a".to_string()},
+            hashmap!{
+                "id".to_string() => Some("main".to_string()),
+                "class".to_string() => Some("a".to_string()),
+                "lang".to_string() => Some("fr".to_string()),
+            },
+            None,
+            vec![],
+        ).to_dnref();
+        dom_node.add_children(vec![
+            DomNode::new(ElemType::P, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref(),
+            DomNode::new(ElemType::P, None, hashset!{}, hashmap!{}, None, vec![]).to_dnref(),
+        ]);
+        let config = MatcherConfig::default();
+        let div = Selector::Simple(SimpleSelector::new(
+            Some(ElemType::Div),
+            None,
+            hashset!{},
+            false,
+        ));
+        assert_eq!(explain_mismatch(&dom_node, &div, &config), None);
+
+        let selector = Selector::Simple(SimpleSelector::new(
+            Some(ElemType::Div),
+            Some("main".to_string()),
+            hashset!{"a".to_string(), "b".to_string()},
+            false,
+        ));
+        let mismatch = explain_mismatch(&dom_node, &selector, &config);
+        assert_eq!(mismatch, Some(Mismatch::MissingClass("b".to_string())));
+        assert_eq!(mismatch.unwrap().to_string(), "missing class \"b\"");
+
+        let selector = Selector::Seq(vec![
+            div,
+            Selector::Attr(AttrSelector::new(
+                "lang".to_string(),
+                Some((AttrSelectorOp::Exactly, "en".to_string())),
+                AttrCaseFlag::Unspecified,
+            )),
+        ]);
+        assert_eq!(
+            explain_mismatch(&dom_node, &selector, &config),
+            Some(Mismatch::Attr(
+                "[lang=\"en\"]".to_string(),
+                Some(Some("fr".to_string()))
+            ))
+        );
+
+        let selector = Selector::PseudoClass(PseudoClassSelector::FirstChild);
+        let mismatch = explain_mismatch(&dom_node.borrow().children[1], &selector, &config);
+        assert_eq!(
+            mismatch,
+            Some(Mismatch::PseudoClass(":first-child".to_string(), Some(2)))
+        );
+        assert_eq!(mismatch.unwrap().to_string(), ":first-child doesn't match child 2");
+
+        let selector = Selector::Group(vec![
+            Selector::Simple(SimpleSelector::new(Some(ElemType::A), None, hashset!{}, false)),
+            Selector::Attr(AttrSelector::new("href".to_string(), None, AttrCaseFlag::Unspecified)),
+        ]);
+        assert_eq!(
+            explain_mismatch(&dom_node, &selector, &config),
+            Some(Mismatch::NoneMatched(vec![
+                Mismatch::ElemType {
+                    expected: ElemType::A,
+                    actual: ElemType::Div,
+                },
+                Mismatch::Attr("[href]".to_string(), None),
+            ]))
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_query_selector_all_parallel() {
+        use generator::{generate_dom, generate_stylesheet, GeneratorConfig};
+
+        let generator_config = GeneratorConfig {
+            elements: 300,
+            rules: 50,
+            ..GeneratorConfig::default()
+        };
+        let root = generate_dom(&generator_config);
+        let body = root.borrow().children[0].clone();
+        let mut config = MatcherConfig::default();
+        config.register_pseudo("x-first", |node| node.element_index() == Some(1));
+        let rules = generate_stylesheet(&generator_config).rules;
+        let mut selectors = rules.into_iter().map(|rule| rule.selector).collect::<Vec<_>>();
+        selectors.push(parse_selector("html div:x-first > *").unwrap());
+        let ids = |nodes: Vec<DomNodeRef>| {
+            nodes.iter().map(|node| node.borrow().id_num).collect::<Vec<_>>()
+        };
+        for selector in &selectors {
+            for root in &[&root, &body] {
+                assert_eq!(
+                    ids(query_selector_all_parallel(root, selector, &config)),
+                    ids(query_selector_all(root, selector, &config))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_selector_all() {
+        use magicparser::{parse_css, parse_html};
+
+        let root = parse_html(
+            "<html><body><div class=\"a\"><p id=\"one\">One</p><div class=\"a\">\
+             <p>Two</p></div></div><p class=\"a\">Three</p></body></html>",
+        ).unwrap();
+        let selector = |css: &str| {
+            let CssBlocks(mut blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+            blocks.remove(0).0
+        };
+        let config = MatcherConfig::default();
+        let ids = |nodes: Vec<DomNodeRef>| {
+            nodes.iter().map(|node| node.borrow().id_num).collect::<Vec<_>>()
+        };
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let p1 = div.borrow().children[0].clone();
+        let inner_div = div.borrow().children[1].clone();
+        let p2 = inner_div.borrow().children[0].clone();
+        let p3 = body.borrow().children[1].clone();
+
+        assert_eq!(
+            ids(query_selector_all(&root, &selector("p"), &config)),
+            ids(vec![p1.clone(), p2.clone(), p3.clone()])
+        );
+        assert_eq!(
+            ids(query_selector_all(&root, &selector(".a"), &config)),
+            ids(vec![div.clone(), inner_div.clone(), p3.clone()])
+        );
+        // Only descendants of the root are searched, but selectors match
+        // against the whole tree
+        assert_eq!(
+            ids(query_selector_all(&div, &selector("body .a, #one"), &config)),
+            ids(vec![p1.clone(), inner_div.clone()])
+        );
+        assert!(query_selector(&root, &selector("div > p"), &config).unwrap().ptr_eq(&p1));
+        assert!(query_selector(&inner_div, &selector("#one"), &config).is_none());
+
+        let mut document = Document::new(root.clone());
+        for css in &["#one", "html", ".a", "#two", "p#one"] {
+            let mut expected = query_selector_all(&root, &selector(css), &config);
+            if *css == "html" {
+                expected.insert(0, root.clone());
+            }
+            assert_eq!(ids(query_document(&document, &selector(css), &config)), ids(expected));
+        }
+        document.set_quirks_mode(QuirksMode::Quirks);
+        let quirks = MatcherConfig::for_document(&document);
+        assert_eq!(ids(query_document(&document, &selector("#ONE"), &quirks)), ids(vec![p1]));
+
+        assert_eq!(document.extract_text("p.a, #one").unwrap(), vec!["One", "Three"]);
+        assert_eq!(document.extract_text("body > .a").unwrap(), vec!["OneTwo", "Three"]);
+        assert!(document.extract_text("p >").is_err());
+
+        // Walking the matching elements finds the same ones
+        let walker = TreeWalker::new(root.clone()).filter(selector_filter(selector(".a"), config));
+        assert_eq!(ids(walker.collect()), ids(vec![div, inner_div, p3]));
+    }
+
+    #[test]
+    fn test_matches_combinators() {
+        use magicparser::{parse_css, parse_html};
+
+        let root = parse_html(
+            "<html><body><div class=\"a\">\
+             <p>One</p>and<h1>Two</h1><p>Three</p>\
+             </div></body></html>",
+        ).unwrap();
+        let body = root.borrow().children[0].clone();
+        let div = body.borrow().children[0].clone();
+        let children = div.borrow().children.clone();
+        let (p1, h1, p2) = (&children[0], &children[2], &children[3]);
+        let selector = |css: &str| {
+            let CssBlocks(mut blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+            blocks.remove(0).0
+        };
+
+        assert!(matches(p1, &selector("html p")));
+        assert!(matches(p1, &selector("body > .a > p")));
+        assert!(!matches(p1, &selector("body > p")));
+        assert!(matches(h1, &selector("p + h1")));
+        assert!(!matches(p2, &selector("p + p")));
+        assert!(matches(p2, &selector("p ~ p")));
+        assert!(!matches(p1, &selector("h1 ~ p")));
+        // The p ancestor must itself be a child of div
+        assert!(matches(p2, &selector("body div > h1 + p")));
+        assert!(!matches(p2, &selector("body > h1 + p")));
+        assert!(matches(p2, &selector(".a p ~ h1 + p")));
+        assert!(!matches(p2, &selector("html > div p")));
+        assert_eq!(
+            explain_mismatch(p1, &selector("h1 ~ p"), &MatcherConfig::default()),
+            Some(Mismatch::Combinator("h1 ~ p".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_matches_combinators_deep_tree() {
+        use magicparser::{parse_css, parse_html};
+
+        // Trying every way to match the divs against the ancestors would take
+        // C(60, 10) steps before failing on the missing section
+        let depth = 60;
+        let root = parse_html(&format!(
+            "<html><body>{}<p>One</p>{}</body></html>",
+            "<div>".repeat(depth),
+            "</div>".repeat(depth)
+        )).unwrap();
+        let mut p = root.borrow().children[0].clone();
+        while !p.borrow().children.is_empty() {
+            let child = p.borrow().children[0].clone();
+            p = child;
+        }
+        let p = p.parent().unwrap();
+        let selector = |css: &str| {
+            let CssBlocks(mut blocks) = parse_css(&format!("{} {{}}", css)).unwrap();
+            blocks.remove(0).0
+        };
+        let divs = "div ".repeat(10);
+        assert!(!matches(&p, &selector(&format!("section {}p", divs))));
+        assert!(!matches(&p, &selector(&format!("html > {}> p", divs))));
+        assert!(matches(&p, &selector(&format!("body {}p", divs))));
+    }
+
+    #[test]
+    fn test_matching_specificity() {
+        let dom_node = DomNode::new(
+            ElemType::A,
+            Some("id".to_string()),
+            hashset!{"cl".to_string()},
+            hashmap!{},
+            None,
+            vec![],
+        ).to_dnref();
+        let selector = Selector::Group(vec![
+            Selector::Simple(SimpleSelector::new(
+                Some(ElemType::P),
+                Some("id".to_string()),
+                hashset!{},
+                false,
+            )),
+            Selector::Simple(SimpleSelector::new(None, None, hashset!{"cl".to_string()}, false)),
+            Selector::Simple(SimpleSelector::new(Some(ElemType::A), None, hashset!{}, false)),
+        ]);
+        // p#id is the most specific, but doesn't match
+        assert_eq!(
+            matching_specificity(&dom_node, &selector, None, &MatcherConfig::default()),
+            Some(Specificity(0, 1, 0))
+        );
+        let selector =
+            Selector::Simple(SimpleSelector::new(Some(ElemType::P), None, hashset!{}, false));
+        assert_eq!(
+            matching_specificity(&dom_node, &selector, None, &MatcherConfig::default()),
+            None
+        );
+    }
 }
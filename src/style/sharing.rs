@@ -0,0 +1,192 @@
+use magicparser::{Combinator, DomNodeRef, PseudoClassSelector, Selector, Stylesheet};
+use style::length::LengthContext;
+use style::resolver::ComputedStyle;
+use style::selectormatcher::{matches_with_config, MatcherConfig};
+
+// How many recently styled siblings are kept as candidates
+const CACHE_SIZE: usize = 8;
+
+// True if selector can match one of two siblings with the same tag and
+// attributes but not the other, because it depends on their positions or on
+// state the pseudo-class matchers decide.
+fn needs_revalidation(selector: &Selector) -> bool {
+    match *selector {
+        Selector::Simple(_) | Selector::Attr(_) | Selector::PseudoElement(_) => false,
+        Selector::PseudoClass(ref pseudo_class) => match *pseudo_class {
+            PseudoClassSelector::Lang(_) => false,
+            PseudoClassSelector::Matches(ref selector)
+            | PseudoClassSelector::Not(ref selector) => needs_revalidation(selector),
+            _ => true,
+        },
+        Selector::Seq(ref selectors) | Selector::Group(ref selectors) => {
+            selectors.iter().any(needs_revalidation)
+        }
+        Selector::Combinator(ref left, ref combinator, ref right) => match *combinator {
+            Combinator::AdjacentSibling | Combinator::GeneralSibling => true,
+            Combinator::Descendant | Combinator::Child => {
+                needs_revalidation(left) || needs_revalidation(right)
+            }
+        },
+    }
+}
+
+/// Returns the selectors of stylesheets that siblings have to match alike to
+/// share a style.
+pub fn revalidation_selectors<'a, I>(stylesheets: I) -> Vec<&'a Selector>
+where
+    I: IntoIterator<Item = &'a Stylesheet>,
+{
+    stylesheets
+        .into_iter()
+        .flat_map(|stylesheet| &stylesheet.rules)
+        .map(|rule| &rule.selector)
+        .filter(|selector| needs_revalidation(selector))
+        .collect()
+}
+
+// A sibling whose style can be reused
+struct Candidate {
+    dom_node: DomNodeRef,
+    // Whether it matches each revalidation selector
+    revalidation: Vec<bool>,
+    style: ComputedStyle,
+    context: LengthContext,
+}
+
+/// Remembers the computed styles of recently styled siblings, so that siblings
+/// with the same tag, attributes and matching state, which get the same style,
+/// don't go through the cascade again.
+///
+/// Only siblings, which inherit from the same parent style, may share a cache.
+pub struct StyleSharingCache<'a> {
+    selectors: &'a [&'a Selector],
+    config: &'a MatcherConfig,
+    // Most recently used first
+    candidates: Vec<Candidate>,
+}
+
+impl<'a> StyleSharingCache<'a> {
+    /// selectors are the ones returned by revalidation_selectors() for the
+    /// stylesheets styles are computed from.
+    pub fn new(selectors: &'a [&'a Selector], config: &'a MatcherConfig) -> StyleSharingCache<'a> {
+        StyleSharingCache {
+            selectors,
+            config,
+            candidates: vec![],
+        }
+    }
+
+    /// Returns an empty cache for the children of the nodes styled with this
+    /// one.
+    pub fn new_for_children(&self) -> StyleSharingCache<'a> {
+        StyleSharingCache::new(self.selectors, self.config)
+    }
+
+    /// Returns the style and length context of a sibling that dom_node can
+    /// share.
+    pub fn get(&mut self, dom_node: &DomNodeRef) -> Option<(ComputedStyle, LengthContext)> {
+        let mut revalidation = None;
+        let position = self.candidates.iter().position(|candidate| {
+            if !same_element(&candidate.dom_node, dom_node) {
+                return false;
+            }
+            let revalidation = revalidation.get_or_insert_with(|| self.revalidate(dom_node));
+            candidate.revalidation == *revalidation
+        })?;
+        let candidate = self.candidates.remove(position);
+        let shared = (candidate.style.clone(), candidate.context);
+        self.candidates.insert(0, candidate);
+        Some(shared)
+    }
+
+    /// Adds the style computed for dom_node as a candidate for its later
+    /// siblings, evicting the least recently used candidate if the cache is
+    /// full.
+    pub fn insert(&mut self, dom_node: &DomNodeRef, style: &ComputedStyle, context: LengthContext) {
+        self.candidates.truncate(CACHE_SIZE - 1);
+        let revalidation = self.revalidate(dom_node);
+        self.candidates.insert(
+            0,
+            Candidate {
+                dom_node: dom_node.clone(),
+                revalidation,
+                style: style.clone(),
+                context,
+            },
+        );
+    }
+
+    fn revalidate(&self, dom_node: &DomNodeRef) -> Vec<bool> {
+        // Text and comment nodes aren't matched by selectors
+        if !dom_node.borrow().elem_type.is_element() {
+            return vec![];
+        }
+        self.selectors
+            .iter()
+            .map(|selector| matches_with_config(dom_node, selector, self.config))
+            .collect()
+    }
+}
+
+// True if a and b have the same tag, namespace and attributes, which include
+// their ids, classes and inline styles. Text and comment nodes are all styled
+// alike. Shadow hosts, which their shadow trees' :host rules can style, are
+// never alike, and custom elements are only alike if both are upgraded, and so
+// have their definition's default style, or neither is.
+fn same_element(a: &DomNodeRef, b: &DomNodeRef) -> bool {
+    let (a, b) = (a.borrow(), b.borrow());
+    if a.shadow_root.is_some() || b.shadow_root.is_some() || a.upgraded != b.upgraded {
+        return false;
+    }
+    match (&a.elem_type, &b.elem_type) {
+        (a_type, b_type) if !a_type.is_element() => !b_type.is_element(),
+        (a_type, b_type) => a_type == b_type && a.namespace == b.namespace && a.attrs == b.attrs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::{parse_html, parse_stylesheet};
+
+    #[test]
+    fn test_revalidation_selectors() {
+        let stylesheet = parse_stylesheet(
+            "p, .a > div[title] { color: red } li:first-child { color: red } \
+             :not(:hover) { color: red } h1 + p { color: red } div:not(.a) { color: red }",
+        ).unwrap();
+        let selectors = revalidation_selectors(vec![&stylesheet]);
+        assert_eq!(selectors.len(), 3);
+        assert_eq!(selectors[0], &stylesheet.rules[1].selector);
+    }
+
+    #[test]
+    fn test_style_sharing_cache() {
+        let root = parse_html(
+            "<ul><li class=\"a\">One</li><li class=\"a\">Two</li><li class=\"b\">Three</li>\
+             <li class=\"a\">Four</li></ul>",
+        ).unwrap();
+        let stylesheet = parse_stylesheet("li:first-child { color: red }").unwrap();
+        let selectors = revalidation_selectors(vec![&stylesheet]);
+        let config = MatcherConfig::default();
+        let mut cache = StyleSharingCache::new(&selectors, &config);
+        let items = root.borrow().children.clone();
+        let mut style = ComputedStyle::default();
+        style.set("color", "red");
+
+        assert_eq!(cache.get(&items[0]), None);
+        cache.insert(&items[0], &style, LengthContext::default());
+        // Not the first child
+        assert_eq!(cache.get(&items[1]), None);
+        style.set("color", "blue");
+        cache.insert(&items[1], &style, LengthContext::default());
+        cache.insert(&items[2], &ComputedStyle::default(), LengthContext::default());
+        let (shared, _) = cache.get(&items[3]).unwrap();
+        assert_eq!(shared.get("color"), Some("blue"));
+
+        let text = items[0].borrow().children[0].clone();
+        cache.insert(&text, &ComputedStyle::default(), LengthContext::default());
+        assert!(cache.get(&items[3].borrow().children[0]).is_some());
+        assert!(cache.new_for_children().get(&items[3]).is_none());
+    }
+}
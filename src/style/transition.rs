@@ -0,0 +1,271 @@
+use magicparser::{Declaration, DomNodeRef};
+use std::collections::HashMap;
+use style::animation::{parse_time, TimingFunction};
+use style::interpolate::interpolate_smooth;
+use style::length::LengthContext;
+use style::properties::{longhands, PROPERTIES};
+use style::resolver::{ComputedStyle, StyleMap};
+
+/// A transition from one computed value of a property to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunningTransition {
+    pub property: String,
+    pub from: String,
+    pub to: String,
+    // In seconds, after the delay
+    pub start_time: f32,
+    pub duration: f32,
+    pub timing_function: TimingFunction,
+}
+
+impl RunningTransition {
+    /// Returns the value at time now. Before the transition starts, this is
+    /// the value it starts from.
+    pub fn value_at(&self, now: f32) -> String {
+        let progress = ((now - self.start_time) / self.duration).clamp(0.0, 1.0);
+        interpolate_smooth(
+            &self.from,
+            &self.to,
+            self.timing_function.apply(progress),
+            &LengthContext::default(),
+        ).unwrap_or_else(|| self.to.clone())
+    }
+
+    pub fn is_finished(&self, now: f32) -> bool {
+        now >= self.start_time + self.duration
+    }
+}
+
+// (property, duration, delay, timing function) for each property style
+// transitions, with the longhands of shorthands like margin. Durations and
+// delays are in seconds.
+fn transitioned_properties(style: &ComputedStyle) -> Vec<(String, f32, f32, TimingFunction)> {
+    let list = |property: &str| {
+        style
+            .get(property)
+            .unwrap_or("")
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .collect::<Vec<_>>()
+    };
+    let (durations, delays, timing_functions) = (
+        list("transition-duration"),
+        list("transition-delay"),
+        list("transition-timing-function"),
+    );
+    let mut properties: Vec<(String, f32, f32, TimingFunction)> = vec![];
+    for (i, property) in list("transition-property").iter().enumerate() {
+        // Lists repeat if they're shorter than the list of properties
+        let duration = parse_time(&durations[i % durations.len()]).unwrap_or(0.0);
+        let delay = parse_time(&delays[i % delays.len()]).unwrap_or(0.0);
+        let timing_function = TimingFunction::parse(&timing_functions[i % timing_functions.len()])
+            .unwrap_or(TimingFunction::CubicBezier(0.25, 0.1, 0.25, 1.0));
+        let names = match property.to_ascii_lowercase().as_str() {
+            "none" => vec![],
+            "all" => PROPERTIES
+                .iter()
+                .map(|property| property.name.to_string())
+                .filter(|name| !name.starts_with("animation") && !name.starts_with("transition"))
+                .collect(),
+            name => match longhands(name) {
+                Some(longhands) => longhands.iter().map(|name| name.to_string()).collect(),
+                None => vec![name.to_string()],
+            },
+        };
+        for name in names {
+            // A later mention of the same property takes precedence
+            properties.retain(|(earlier, ..)| *earlier != name);
+            properties.push((name, duration, delay, timing_function));
+        }
+    }
+    properties
+}
+
+/// Runs the transitions started by style changes, which are found by diffing
+/// each element's old and new computed styles.
+#[derive(Debug, Default)]
+pub struct TransitionEngine {
+    // Keyed by DomNode::id_num
+    transitions: HashMap<usize, Vec<RunningTransition>>,
+}
+
+impl TransitionEngine {
+    pub fn new() -> TransitionEngine {
+        TransitionEngine::default()
+    }
+
+    /// Starts transitions for the properties that changed from old to new
+    /// and that new transitions, at time now (in seconds). A transition
+    /// that's interrupted starts the new one from its current value.
+    /// Transitions of properties that new no longer transitions are
+    /// cancelled.
+    pub fn style_changed(
+        &mut self,
+        dom_node: &DomNodeRef,
+        old: &ComputedStyle,
+        new: &ComputedStyle,
+        now: f32,
+    ) {
+        let id_num = dom_node.borrow().id_num;
+        let mut running = self.transitions.remove(&id_num).unwrap_or_default();
+        let properties = transitioned_properties(new);
+        running.retain(|transition| {
+            properties.iter().any(|(property, ..)| *property == transition.property)
+        });
+        for (property, duration, delay, timing_function) in properties {
+            let to = match new.get(&property) {
+                Some(to) => to,
+                None => continue,
+            };
+            let current = running
+                .iter()
+                .find(|transition| transition.property == property)
+                .map(|transition| transition.value_at(now));
+            let from = match current {
+                Some(ref current) => current.as_str(),
+                None => match old.get(&property) {
+                    Some(from) => from,
+                    None => continue,
+                },
+            };
+            let retarget = running
+                .iter()
+                .any(|transition| transition.property == property && transition.to != to);
+            if from == to && !retarget {
+                continue;
+            }
+            running.retain(|transition| transition.property != property);
+            let can_interpolate =
+                interpolate_smooth(from, to, 0.0, &LengthContext::default()).is_some();
+            if duration <= 0.0 || !can_interpolate {
+                continue;
+            }
+            running.push(RunningTransition {
+                property: property.to_string(),
+                from: from.to_string(),
+                to: to.to_string(),
+                start_time: now + delay,
+                duration,
+                timing_function,
+            });
+        }
+        if !running.is_empty() {
+            self.transitions.insert(id_num, running);
+        }
+    }
+
+    /// Calls style_changed() for root and each of its descendants that's
+    /// styled in both old and new.
+    pub fn update(&mut self, root: &DomNodeRef, old: &StyleMap, new: &StyleMap, now: f32) {
+        if let (Some(old_style), Some(new_style)) = (old.get(root), new.get(root)) {
+            self.style_changed(root, old_style, new_style, now);
+        }
+        for child in &root.borrow().children {
+            self.update(child, old, new, now);
+        }
+    }
+
+    /// Returns the declarations running transitions override styles with at
+    /// time now, keyed by DomNode::id_num, and drops finished transitions.
+    pub fn tick(&mut self, now: f32) -> HashMap<usize, Vec<Declaration>> {
+        let mut values = HashMap::new();
+        for (id_num, running) in &mut self.transitions {
+            running.retain(|transition| !transition.is_finished(now));
+            let declarations = running
+                .iter()
+                .map(|transition| {
+                    Declaration::new(transition.property.clone(), transition.value_at(now))
+                })
+                .collect::<Vec<_>>();
+            if !declarations.is_empty() {
+                values.insert(*id_num, declarations);
+            }
+        }
+        self.transitions.retain(|_, running| !running.is_empty());
+        values
+    }
+
+    /// Returns true if any transitions haven't finished.
+    pub fn is_running(&self) -> bool {
+        !self.transitions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use magicparser::parse_html;
+
+    fn style(declarations: &[(&str, &str)]) -> ComputedStyle {
+        let mut style = ComputedStyle::default();
+        for &(property, value) in declarations {
+            style.set(property, value);
+        }
+        style
+    }
+
+    fn tick(engine: &mut TransitionEngine, dom_node: &DomNodeRef, now: f32) -> Vec<String> {
+        engine
+            .tick(now)
+            .get(&dom_node.borrow().id_num)
+            .map_or(vec![], |declarations| {
+                declarations
+                    .iter()
+                    .map(|decl| format!("{}: {}", decl.property, decl.value))
+                    .collect()
+            })
+    }
+
+    #[test]
+    fn test_transition() {
+        let root = parse_html("<div></div>").unwrap();
+        let transition = [
+            ("transition-property", "width, color, display"),
+            ("transition-duration", "2s"),
+            ("transition-delay", "0s, 1s"),
+            ("transition-timing-function", "linear"),
+        ];
+        let mut old = style(&transition);
+        let mut new = style(&transition);
+        for &(property, old_value, new_value) in &[
+            ("width", "0px", "100px"),
+            ("height", "0px", "10px"),
+            ("color", "rgb(0, 0, 0)", "rgb(0, 0, 255)"),
+            ("display", "block", "none"),
+        ] {
+            old.set(property, old_value);
+            new.set(property, new_value);
+        }
+        let mut engine = TransitionEngine::new();
+        engine.style_changed(&root, &old, &new, 10.0);
+        assert!(engine.is_running());
+        assert_eq!(tick(&mut engine, &root, 10.5), vec!["width: 25px", "color: rgb(0, 0, 0)"]);
+        assert_eq!(tick(&mut engine, &root, 12.0), vec!["color: rgb(0, 0, 128)"]);
+        assert!(tick(&mut engine, &root, 13.0).is_empty());
+        assert!(!engine.is_running());
+    }
+
+    #[test]
+    fn test_interrupted_transition() {
+        let root = parse_html("<div></div>").unwrap();
+        let style_with_width = |width: &str| {
+            style(&[
+                ("transition-property", "all"),
+                ("transition-duration", "1s"),
+                ("transition-timing-function", "linear"),
+                ("width", width),
+            ])
+        };
+        let mut engine = TransitionEngine::new();
+        engine.style_changed(&root, &style_with_width("0px"), &style_with_width("100px"), 0.0);
+        assert_eq!(tick(&mut engine, &root, 0.5), vec!["width: 50px"]);
+        // Going back starts from where the transition got to
+        engine.style_changed(&root, &style_with_width("100px"), &style_with_width("0px"), 0.5);
+        assert_eq!(tick(&mut engine, &root, 1.0), vec!["width: 25px"]);
+
+        // Styles that don't transition cancel running transitions
+        let no_transition = style(&[("width", "50px"), ("transition-property", "none")]);
+        engine.style_changed(&root, &style_with_width("0px"), &no_transition, 1.0);
+        assert!(!engine.is_running());
+    }
+}
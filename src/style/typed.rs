@@ -0,0 +1,168 @@
+use style::color::{Color, Rgba};
+use style::length::{ComputedLength, LengthContext, LengthOrPercentage};
+use style::properties::lookup_property;
+use style::resolver::ComputedStyle;
+
+/// The computed values of the supported properties, parsed. Keyword values are
+/// kept as strings. See Animation::from_style() for the animation-*
+/// properties.
+///
+/// Logical properties are mapped to these physical ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedStyle {
+    pub background_color: Rgba,
+    // None for auto
+    pub bottom: Option<ComputedLength>,
+    pub break_after: String,
+    pub break_before: String,
+    pub break_inside: String,
+    pub clear: String,
+    pub color: Rgba,
+    pub cursor: String,
+    pub direction: String,
+    pub display: String,
+    pub float: String,
+    pub font_family: String,
+    // In px
+    pub font_size: f32,
+    pub font_stretch: String,
+    pub font_style: String,
+    pub font_weight: String,
+    // None for auto
+    pub height: Option<ComputedLength>,
+    // None for auto
+    pub left: Option<ComputedLength>,
+    pub line_height: String,
+    pub list_style_type: String,
+    // None for auto
+    pub margin_bottom: Option<ComputedLength>,
+    pub margin_left: Option<ComputedLength>,
+    pub margin_right: Option<ComputedLength>,
+    pub margin_top: Option<ComputedLength>,
+    // None for none
+    pub max_height: Option<ComputedLength>,
+    pub max_width: Option<ComputedLength>,
+    // None for auto
+    pub min_height: Option<ComputedLength>,
+    pub min_width: Option<ComputedLength>,
+    pub overflow_x: String,
+    pub overflow_y: String,
+    pub padding_bottom: ComputedLength,
+    pub padding_left: ComputedLength,
+    pub padding_right: ComputedLength,
+    pub padding_top: ComputedLength,
+    pub position: String,
+    // None for auto
+    pub right: Option<ComputedLength>,
+    pub text_align: String,
+    pub text_decoration: String,
+    // None for auto
+    pub top: Option<ComputedLength>,
+    pub visibility: String,
+    pub white_space: String,
+    // None for auto
+    pub width: Option<ComputedLength>,
+    pub writing_mode: String,
+    // None for auto
+    pub z_index: Option<i32>,
+}
+
+impl TypedStyle {
+    /// Values that are missing or don't parse, which the resolver never
+    /// computes, are replaced by the property's initial value.
+    pub fn from_style(style: &ComputedStyle) -> TypedStyle {
+        let value = |property: &str| {
+            let initial = lookup_property(property).map_or("", |property| property.initial);
+            style.get(property).unwrap_or(initial).to_string()
+        };
+        let color = |property: &str| {
+            let black = Rgba::opaque(0, 0, 0);
+            Color::parse(&value(property)).map_or(black, |color| color.resolve(black))
+        };
+        let length = |property: &str| {
+            LengthOrPercentage::parse(&value(property))?.compute(&LengthContext::default())
+        };
+        let (overflow_x, overflow_y) = overflow(value("overflow-x"), value("overflow-y"));
+        TypedStyle {
+            background_color: color("background-color"),
+            bottom: length("bottom"),
+            break_after: value("break-after"),
+            break_before: value("break-before"),
+            break_inside: value("break-inside"),
+            clear: value("clear"),
+            color: color("color"),
+            cursor: value("cursor"),
+            direction: value("direction"),
+            display: value("display"),
+            float: value("float"),
+            font_family: value("font-family"),
+            font_size: length("font-size").map_or(LengthContext::default().font_size, |size| {
+                size.to_px(0.0)
+            }),
+            font_stretch: value("font-stretch"),
+            font_style: value("font-style"),
+            font_weight: value("font-weight"),
+            height: length("height"),
+            left: length("left"),
+            line_height: value("line-height"),
+            list_style_type: value("list-style-type"),
+            margin_bottom: length("margin-bottom"),
+            margin_left: length("margin-left"),
+            margin_right: length("margin-right"),
+            margin_top: length("margin-top"),
+            max_height: length("max-height"),
+            max_width: length("max-width"),
+            min_height: length("min-height"),
+            min_width: length("min-width"),
+            overflow_x,
+            overflow_y,
+            padding_bottom: length("padding-bottom").unwrap_or_default(),
+            padding_left: length("padding-left").unwrap_or_default(),
+            padding_right: length("padding-right").unwrap_or_default(),
+            padding_top: length("padding-top").unwrap_or_default(),
+            position: value("position"),
+            right: length("right"),
+            text_align: value("text-align"),
+            text_decoration: value("text-decoration"),
+            top: length("top"),
+            visibility: value("visibility"),
+            white_space: value("white-space"),
+            width: length("width"),
+            writing_mode: value("writing-mode"),
+            z_index: value("z-index").parse().ok(),
+        }
+    }
+}
+
+// Returns the used overflow-x and overflow-y. visible and clip become auto
+// and hidden if the other axis is a scrolling one.
+fn overflow(x: String, y: String) -> (String, String) {
+    let scrolls = |value: &str| matches!(value, "hidden" | "scroll" | "auto");
+    let adjust = |value: &str, other: &str| match value {
+        "visible" if scrolls(other) => "auto".to_string(),
+        "clip" if scrolls(other) => "hidden".to_string(),
+        _ => value.to_string(),
+    };
+    (adjust(&x, &y), adjust(&y, &x))
+}
+
+#[cfg(test)]
+mod tests {
+    use style::resolver::ComputedStyle;
+
+    #[test]
+    fn test_overflow() {
+        let mut style = ComputedStyle::default();
+        style.set("overflow-x", "hidden");
+        style.set("overflow-y", "clip");
+        let typed = style.typed();
+        assert_eq!((typed.overflow_x.as_str(), typed.overflow_y.as_str()), ("hidden", "hidden"));
+        style.set("overflow-x", "scroll");
+        style.set("overflow-y", "visible");
+        let typed = style.typed();
+        assert_eq!((typed.overflow_x.as_str(), typed.overflow_y.as_str()), ("scroll", "auto"));
+        style.set("overflow-x", "visible");
+        let typed = style.typed();
+        assert_eq!((typed.overflow_x.as_str(), typed.overflow_y.as_str()), ("visible", "visible"));
+    }
+}
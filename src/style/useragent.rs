@@ -0,0 +1,21 @@
+use magicparser::{parse_stylesheet, Stylesheet};
+
+static USER_AGENT_CSS: &str = include_str!("useragent.css");
+
+/// The stylesheet every document is styled with before user and author
+/// stylesheets.
+pub fn user_agent_stylesheet() -> Stylesheet {
+    parse_stylesheet(USER_AGENT_CSS).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_agent_stylesheet_parses() {
+        // Rules that fail to parse are dropped
+        let rule_count = USER_AGENT_CSS.matches('{').count();
+        assert_eq!(user_agent_stylesheet().rules.len(), rule_count);
+    }
+}